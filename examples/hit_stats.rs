@@ -0,0 +1,36 @@
+//! Цена счётчиков попаданий на чтение: наносекунды на `get_or_load` (им
+//! сервер исполняет `Get`) без префиксов и со счётом по префиксам:
+//! `cargo run --release --example hit_stats --no-default-features -- 2000000`.
+
+use std::hint::black_box;
+use std::time::Instant;
+use tiny_mp_cache::{PersistentCore, DEFAULT_STATS_PREFIXES};
+
+fn main() {
+    let n: usize = std::env::args()
+        .nth(1)
+        .map_or(2_000_000, |s| s.parse().expect("reads"));
+    let keys: Vec<String> = (0..1024)
+        .map(|i| format!("{}:{}", ["sessions", "users", "orders", "jobs"][i % 4], i))
+        .collect();
+    for depth in [0, 1] {
+        let core = PersistentCore::ephemeral();
+        core.set_hit_stats_prefix(depth, ":", DEFAULT_STATS_PREFIXES)
+            .expect("stats prefix");
+        // половина ключей есть — половина чтений промахивается
+        for key in keys.iter().step_by(2) {
+            core.set(key.clone(), b"v".to_vec()).expect("set");
+        }
+        let t = Instant::now();
+        for i in 0..n {
+            black_box(core.get_or_load(&keys[i % keys.len()]).expect("get"));
+        }
+        let elapsed = t.elapsed();
+        println!(
+            "stats_prefix_depth={} reads={} ns/read={:.1}",
+            depth,
+            n,
+            elapsed.as_nanos() as f64 / n as f64
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Конвейерная запись по одному мультиплексированному соединению: сколько
+//! записей WAL (write, при `always` — и fsync) уходит на Set, когда клиент
+//! ждёт каждого ответа и когда шлёт все команды сразу:
+//! `cargo run --release --example pipelined_writes --no-default-features -- 5000 always`.
+
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tiny_mp_cache::wal::FsyncPolicy;
+use tiny_mp_cache::{
+    serve_listener, CacheCommand, Client, MuxConn, PersistOptions, PersistentCore, ResponseValue,
+};
+
+fn wal_batches(c: &Client) -> i64 {
+    match c
+        .info()
+        .expect("info")
+        .into_iter()
+        .find(|(k, _)| k == "wal_batches")
+    {
+        Some((_, ResponseValue::Int(n))) => n,
+        other => panic!("wal_batches is {:?}", other),
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let n: u64 = args.next().map_or(5000, |s| s.parse().expect("sets"));
+    let fsync: FsyncPolicy = args
+        .next()
+        .map_or(Ok(FsyncPolicy::Always), |s| s.parse())
+        .expect("fsync policy");
+    let dir = std::env::temp_dir().join(format!("tmc-pipelined-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create WAL dir");
+    let opts = PersistOptions {
+        fsync,
+        ..PersistOptions::default()
+    };
+    let core = PersistentCore::new(dir.join("cache.wal"), dir.join("cache.snapshot"), opts)
+        .expect("open WAL");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    thread::spawn(move || serve_listener(listener, Arc::new(core)));
+
+    let c = Client::connect(&addr).expect("connect");
+    let mux = MuxConn::connect(&addr).expect("connect mux");
+    let value = vec![b'v'; 64];
+    let report = |mode: &str, elapsed: std::time::Duration, writes: i64| {
+        println!(
+            "fsync={:<8} {:<10} sets={} elapsed={:.3}s ops/s={:.0} WAL writes={} sets/write={:.1}",
+            fsync.as_str(),
+            mode,
+            n,
+            elapsed.as_secs_f64(),
+            n as f64 / elapsed.as_secs_f64(),
+            writes,
+            n as f64 / writes.max(1) as f64,
+        );
+    };
+
+    let before = wal_batches(&c);
+    let t = Instant::now();
+    for i in 0..n {
+        let set = CacheCommand::Set(format!("seq:{}", i), value.clone());
+        mux.call(&set).expect("set");
+    }
+    report("sequential", t.elapsed(), wal_batches(&c) - before);
+
+    let before = wal_batches(&c);
+    let t = Instant::now();
+    let pending: Vec<_> = (0..n)
+        .map(|i| {
+            let set = CacheCommand::Set(format!("pipe:{}", i), value.clone());
+            mux.send(&set).expect("send")
+        })
+        .collect();
+    for p in pending {
+        p.wait().expect("set");
+    }
+    report("pipelined", t.elapsed(), wal_batches(&c) - before);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,113 @@
+//! Сервер без персистентности для тестов и экспериментов из других языков:
+//! `cargo run --example serve --no-default-features -- 127.0.0.1:5022`.
+//! С `--print-config` печатает настройки, с которыми поднялся бы (тот же
+//! ответ, что `ConfigDump`), и выходит. С `--verify` проверяет журнал по
+//! пути вместо адреса, ничего не загружая (`wal::verify`, ключ — из
+//! `TINY_MP_CACHE_WAL_KEY`), печатает отчёт и выходит: 0 — журнал цел,
+//! 1 — только оборванный хвост, 3 — испорчен, 4 — не прочитался; 2 — неверные
+//! флаги.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tiny_mp_cache::crypto::WalKey;
+use tiny_mp_cache::wal::{self, VerifyStatus};
+use tiny_mp_cache::{serve_tcp, PersistentCore, ResponseValue};
+
+fn verify(path: &str) -> i32 {
+    let key = match WalKey::from_env() {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("WAL key: {}", e);
+            return 4;
+        }
+    };
+    let mut last = Duration::ZERO;
+    let report = wal::verify(Path::new(path), key.as_ref(), &mut |p| {
+        if !p.done && p.elapsed >= last + Duration::from_secs(5) {
+            last = p.elapsed;
+            eprintln!(
+                "verify: {} records, {} of {} bytes",
+                p.records, p.bytes_read, p.bytes_total
+            );
+        }
+    });
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("verify {}: {}", path, e);
+            return 4;
+        }
+    };
+    println!("status = {}", report.status().as_str());
+    println!("segments = {}", report.segments);
+    println!("bytes = {}", report.bytes);
+    println!("records = {}", report.records);
+    for (op, n) in &report.ops {
+        println!("records.{} = {}", op, n);
+    }
+    println!("first_seq = {}", report.first_seq);
+    println!("last_seq = {}", report.last_seq);
+    for (file, at) in &report.torn {
+        println!("torn = {} @ {}", file.display(), at);
+    }
+    for r in &report.corrupt {
+        let lost = r.records_lost.map_or("?".into(), |n| n.to_string());
+        println!(
+            "corrupt = {} [{}, {}), {} records lost",
+            r.file.display(),
+            r.start,
+            r.end,
+            lost
+        );
+    }
+    for b in &report.seq_breaks {
+        println!(
+            "seq_break = {}: expected {}, found {}",
+            b.file.display(),
+            b.expected,
+            b.found
+        );
+    }
+    match report.status() {
+        VerifyStatus::Clean => 0,
+        VerifyStatus::Recoverable => 1,
+        VerifyStatus::Corrupt => 3,
+    }
+}
+
+fn main() {
+    let (flags, args): (Vec<String>, Vec<String>) =
+        std::env::args().skip(1).partition(|a| a.starts_with("--"));
+    let addr = args
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| "127.0.0.1:5002".into());
+    let core = PersistentCore::ephemeral();
+    match flags.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => {}
+        ["--print-config"] => {
+            println!("addr = {}", addr);
+            for (name, value) in core.config_dump().expect("config dump") {
+                match value {
+                    ResponseValue::Str(s) => println!("{} = {:?}", name, s),
+                    ResponseValue::Int(n) => println!("{} = {}", name, n),
+                    other => println!("{} = {:?}", name, other),
+                }
+            }
+            return;
+        }
+        ["--verify"] => std::process::exit(verify(&addr)),
+        ref other => {
+            eprintln!(
+                "unknown flags {:?}, expected --print-config or --verify",
+                other
+            );
+            std::process::exit(2);
+        }
+    }
+    if let Err(e) = serve_tcp(&addr, Arc::new(core)) {
+        eprintln!("serve error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,109 @@
+//! Маленькие значения в `CacheCore`: сколько живых аллокаций и байт кучи
+//! держат ключи и сколько стоят set/get/peek на ключ, в одном потоке:
+//! `cargo run --release --example small_values --no-default-features -- 1000000 16`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Instant;
+use tiny_mp_cache::core::CacheCore;
+
+struct Counting;
+
+static ALLOCS: AtomicU64 = AtomicU64::new(0);
+static LIVE: AtomicI64 = AtomicI64::new(0);
+static LIVE_BYTES: AtomicI64 = AtomicI64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        LIVE.fetch_add(1, Ordering::Relaxed);
+        LIVE_BYTES.fetch_add(layout.size() as i64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(1, Ordering::Relaxed);
+        LIVE_BYTES.fetch_sub(layout.size() as i64, Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn snapshot() -> (u64, i64, i64) {
+    (
+        ALLOCS.load(Ordering::Relaxed),
+        LIVE.load(Ordering::Relaxed),
+        LIVE_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+// резидентная память процесса в байтах; вне Linux — 0
+fn rss() -> i64 {
+    std::fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|s| s.split_whitespace().nth(1)?.parse::<i64>().ok())
+        .map_or(0, |pages| pages * 4096)
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let n: usize = args.next().map_or(1_000_000, |s| s.parse().expect("keys"));
+    let size: usize = args.next().map_or(16, |s| s.parse().expect("value size"));
+    let keys: Vec<String> = (0..n).map(|i| format!("k:{:08}", i)).collect();
+    let values: Vec<Vec<u8>> = (0..n).map(|i| vec![i as u8; size]).collect();
+
+    let core = CacheCore::new();
+    let (a0, l0, b0) = snapshot();
+    let r0 = rss();
+    let t = Instant::now();
+    for (k, v) in keys.iter().zip(&values) {
+        core.set(k.clone(), v.clone());
+    }
+    let load = t.elapsed();
+    let (a1, l1, b1) = snapshot();
+    println!(
+        "load {} keys x {} B: {:.0} ns/set, {:.2} allocs/set, {:.2} live allocs/key, {:.1} live B/key, {:.1} RSS B/key",
+        n,
+        size,
+        load.as_nanos() as f64 / n as f64,
+        (a1 - a0) as f64 / n as f64,
+        (l1 - l0) as f64 / n as f64,
+        (b1 - b0) as f64 / n as f64,
+        (rss() - r0) as f64 / n as f64,
+    );
+
+    let t = Instant::now();
+    for (k, v) in keys.iter().zip(&values) {
+        core.set(k.clone(), v.clone());
+    }
+    let overwrite = t.elapsed();
+    let (a2, ..) = snapshot();
+    println!(
+        "overwrite: {:.0} ns/set, {:.2} allocs/set",
+        overwrite.as_nanos() as f64 / n as f64,
+        (a2 - a1) as f64 / n as f64,
+    );
+
+    let t = Instant::now();
+    let mut total = 0;
+    for k in &keys {
+        total += core.get(k).map_or(0, |v| v.len());
+    }
+    let get = t.elapsed();
+    let (a3, ..) = snapshot();
+    println!(
+        "get: {:.0} ns/op, {:.2} allocs/op",
+        get.as_nanos() as f64 / n as f64,
+        (a3 - a2) as f64 / n as f64,
+    );
+
+    let t = Instant::now();
+    for k in &keys {
+        total += core.peek(k, |v| v.len()).unwrap_or(0);
+    }
+    let peek = t.elapsed();
+    println!("peek: {:.0} ns/op", peek.as_nanos() as f64 / n as f64);
+    assert_eq!(total, 2 * n * size);
+}
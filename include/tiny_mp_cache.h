@@ -0,0 +1,82 @@
+#ifndef TINY_MP_CACHE_H
+#define TINY_MP_CACHE_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Непрозрачный клиент: одно постоянное соединение с сервером. Вызовы из
+// разных потоков безопасны и идут по очереди.
+typedef struct TmcClient TmcClient;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Подключается к серверу по адресу `"host:port"`, `"tcp://host:port"` или
+// `"unix:///path/to.sock"`.
+//
+// Возвращает клиента, которым владеет вызывающий (освобождать через
+// `tmc_client_free`), или NULL при ошибке — причина в `tmc_last_error()`.
+//
+// # Safety
+// `addr` — NUL-терминированная строка UTF-8.
+TmcClient *tmc_client_new(const char *addr);
+
+// Закрывает соединение и освобождает клиента. NULL допустим.
+//
+// # Safety
+// `client` получен из `tmc_client_new` и ещё не освобождён.
+void tmc_client_free(TmcClient *client);
+
+// Записывает `value_len` байт из `value` под ключ `key`.
+//
+// Возвращает 0 при успехе и -1 при ошибке. Буфер `value` только читается
+// и остаётся за вызывающим.
+//
+// # Safety
+// `client` — живой клиент, `key` — NUL-терминированная строка UTF-8,
+// `value` указывает на `value_len` байт (может быть NULL при длине 0).
+int32_t tmc_set(const TmcClient *client, const char *key, const uint8_t *value, size_t value_len);
+
+// Читает значение `key`.
+//
+// Возвращает 1, если ключ найден: тогда в `*out` лежит буфер длиной
+// `*out_len`, которым теперь владеет вызывающий и который освобождается
+// только через `tmc_free(*out, *out_len)`. Возвращает 0, если ключа нет
+// (`*out` = NULL, `*out_len` = 0), и -1 при ошибке.
+//
+// # Safety
+// `client` — живой клиент, `key` — NUL-терминированная строка UTF-8,
+// `out` и `out_len` — валидные указатели для записи.
+int32_t tmc_get(const TmcClient *client, const char *key, uint8_t **out, size_t *out_len);
+
+// Освобождает буфер из `tmc_get`; `len` — ровно тот `*out_len`, что
+// вернул `tmc_get`. NULL допустим.
+//
+// # Safety
+// `buf` получен из `tmc_get` и ещё не освобождён.
+void tmc_free(uint8_t *buf, size_t len);
+
+// Удаляет `key`. Возвращает число удалённых ключей (0 или 1) или -1 при
+// ошибке.
+//
+// # Safety
+// `client` — живой клиент, `key` — NUL-терминированная строка UTF-8.
+int64_t tmc_delete(const TmcClient *client, const char *key);
+
+// Текст последней ошибки в этом потоке или NULL, если последний вызов
+// `tmc_*` завершился успешно.
+//
+// Строка принадлежит библиотеке и живёт до следующего вызова `tmc_*` из
+// того же потока; освобождать её не нужно.
+const char *tmc_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TINY_MP_CACHE_H */
@@ -0,0 +1,620 @@
+//! Пользователи с правами по префиксам ключей, см.
+//! `PersistentCore::set_acl_file`. Соединение входит командой `Auth`, и
+//! дальше каждая его команда проходит `Acl::check` по имени вошедшего:
+//! ключи и префиксы команд записи — по `allow_write`, остальных — по
+//! `allow_read`; команды над всем кэшем (Save, Export, ReplSync, Config*, ...)
+//! — только у `admin`. Правило — префикс ключа, `"events:"` и `"events:*"`
+//! — одно и то же, `""` и `"*"` — все ключи. Правила сверяются с ключом уже
+//! в форме политики ключей сервера. `Admin` с верным токеном проходит, как и
+//! без ACL.
+//!
+//! Таблица задаётся файлом в подмножестве TOML: таблицы `[[users]]` с
+//! `name`, `password_hash` (см. `hash_password`), `allow_read`,
+//! `allow_write` и `admin`. `AclSetUser`/`AclDelUser` переписывают этот файл
+//! целиком, поэтому комментарии в нём не сохраняются.
+
+use crate::error::CacheError;
+use crate::keys::for_each_key;
+use crate::{encode_frame, request_frame, CacheCommand, CacheResponse, Conn};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{RwLock, RwLockReadGuard};
+
+// контекст blake3 для хешей паролей; поменять — значит обнулить все хеши
+const HASH_CONTEXT: &str = "tiny-mp-cache 2026 acl password";
+
+/// Пользователь ACL; он же — таблица `[[users]]` файла.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AclUser {
+    pub name: String,
+    // `blake3$<соль hex>$<хеш hex>`, см. `hash_password`; в ответе AclList
+    // пустой
+    pub password_hash: String,
+    // префиксы ключей
+    pub allow_read: Vec<String>,
+    pub allow_write: Vec<String>,
+    // все команды, в том числе админские, без токена
+    pub admin: bool,
+}
+
+/// Хеш пароля для `password_hash`: blake3 с солью из 16 случайных байт.
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    format!(
+        "blake3${}${}",
+        to_hex(&salt),
+        password_digest(&salt, password).to_hex()
+    )
+}
+
+fn password_digest(salt: &[u8], password: &str) -> blake3::Hash {
+    let mut h = blake3::Hasher::new_derive_key(HASH_CONTEXT);
+    h.update(salt).update(password.as_bytes());
+    h.finalize()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+// (соль, хеш) из `password_hash`
+fn parse_hash(hash: &str) -> Option<(Vec<u8>, blake3::Hash)> {
+    let (salt, digest) = hash.strip_prefix("blake3$")?.split_once('$')?;
+    if salt.is_empty() || salt.len() % 2 != 0 {
+        return None;
+    }
+    let salt = (0..salt.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(salt.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some((salt, blake3::Hash::from_hex(digest).ok()?))
+}
+
+impl AclUser {
+    fn check_password(&self, password: &str) -> bool {
+        // сравнение blake3::Hash — без раннего выхода
+        parse_hash(&self.password_hash)
+            .is_some_and(|(salt, digest)| password_digest(&salt, password) == digest)
+    }
+
+    fn validate(&self) -> Result<(), CacheError> {
+        if self.name.is_empty() {
+            return Err(CacheError::Unsupported(
+                "ACL user name must not be empty".into(),
+            ));
+        }
+        if parse_hash(&self.password_hash).is_none() {
+            return Err(CacheError::Unsupported(format!(
+                "password_hash of ACL user {:?} is not a hash_password() result",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Логин и пароль клиента: `Auth` первой командой после `Hello` на каждом
+/// новом соединении.
+#[derive(Clone, Debug)]
+pub(crate) struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub(crate) fn command(&self) -> CacheCommand {
+        CacheCommand::Auth(self.user.clone(), self.password.clone())
+    }
+
+    /// Вход на соединении без `Hello` (подписка, watch).
+    pub(crate) fn login(&self, conn: &mut Conn) -> Result<(), CacheError> {
+        match request_frame(conn, &encode_frame(&self.command())?)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(CacheError::Internal(format!(
+                "Unexpected response from auth: {:?}",
+                resp
+            ))),
+        }
+    }
+}
+
+/// Чем кончилась проверка.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    Granted,
+    // пользователь `admin`: команда выполняется, как внутри `Admin`
+    Admin,
+}
+
+struct Table {
+    // по имени
+    users: Vec<AclUser>,
+    // куда сохранять изменения; None — таблица задана без файла
+    path: Option<PathBuf>,
+}
+
+#[derive(Default)]
+pub(crate) struct Acl {
+    // без таблицы команды не проверяются (по умолчанию)
+    enabled: AtomicBool,
+    table: RwLock<Option<Table>>,
+}
+
+// правило вида "events:*" — то же, что "events:"
+fn rule_covers(rule: &str, key: &str) -> bool {
+    key.starts_with(rule.strip_suffix('*').unwrap_or(rule))
+}
+
+fn denied(user: &str, what: &str, rule: &str, rules: &[String]) -> CacheError {
+    CacheError::PermissionDenied(format!(
+        "user {:?} may not {}: {} = {:?}",
+        user, what, rule, rules
+    ))
+}
+
+impl Acl {
+    fn read(&self) -> RwLockReadGuard<'_, Option<Table>> {
+        self.table.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Включает проверку с пользователями `users`; изменения сохраняются в
+    /// `path`, если он есть.
+    pub(crate) fn set_users(
+        &self,
+        mut users: Vec<AclUser>,
+        path: Option<PathBuf>,
+    ) -> Result<(), CacheError> {
+        users.iter().try_for_each(AclUser::validate)?;
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(dup) = users.windows(2).find(|w| w[0].name == w[1].name) {
+            return Err(CacheError::Unsupported(format!(
+                "ACL user {:?} is defined twice",
+                dup[0].name
+            )));
+        }
+        *self.table.write().unwrap_or_else(|e| e.into_inner()) = Some(Table { users, path });
+        self.enabled.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Файл таблицы и число пользователей для `ConfigDump`.
+    pub(crate) fn describe(&self) -> (String, usize) {
+        match &*self.read() {
+            Some(t) => (
+                t.path
+                    .as_deref()
+                    .map_or(String::new(), |p| p.display().to_string()),
+                t.users.len(),
+            ),
+            None => (String::new(), 0),
+        }
+    }
+
+    /// Пароль `password` пользователя `user`; неверное имя и неверный
+    /// пароль отвечают одинаково.
+    pub(crate) fn authenticate(&self, user: &str, password: &str) -> Result<(), CacheError> {
+        let table = self.read();
+        let Some(table) = &*table else {
+            return Err(CacheError::Unsupported(
+                "Auth: no ACL users are configured on this server".into(),
+            ));
+        };
+        match table.users.iter().find(|u| u.name == user) {
+            Some(u) if u.check_password(password) => Ok(()),
+            _ => Err(CacheError::PermissionDenied(
+                "invalid user or password".into(),
+            )),
+        }
+    }
+
+    /// Может ли `user` (None — соединение не вошло) выполнить `cmd`. Ключи
+    /// `cmd` уже в форме политики ключей.
+    pub(crate) fn check(
+        &self,
+        user: Option<&str>,
+        cmd: &mut CacheCommand,
+    ) -> Result<Access, CacheError> {
+        // токен Admin проверяет сам execute
+        if matches!(
+            cmd,
+            CacheCommand::Hello(_)
+                | CacheCommand::Ping
+                | CacheCommand::Time
+                | CacheCommand::Auth(..)
+                | CacheCommand::Admin(..)
+        ) {
+            return Ok(Access::Granted);
+        }
+        let Some(name) = user else {
+            return Err(CacheError::PermissionDenied(format!(
+                "{} requires Auth(user, password) first",
+                cmd.name()
+            )));
+        };
+        let table = self.read();
+        let Some(u) = table
+            .as_ref()
+            .and_then(|t| t.users.iter().find(|u| u.name == name))
+        else {
+            return Err(CacheError::PermissionDenied(format!(
+                "user {:?} no longer exists",
+                name
+            )));
+        };
+        if u.admin {
+            return Ok(Access::Admin);
+        }
+        // SetHistoryDepth данных не меняет, но это настройка: по правилу
+        // записи, а пустой префикс — глубина всего кэша, как в ConfigSet
+        let write = match &*cmd {
+            CacheCommand::SetHistoryDepth(prefix, _) if prefix.is_empty() => {
+                return Err(CacheError::PermissionDenied(format!(
+                    "user {:?} may not run SetHistoryDepth for all keys: admin = false",
+                    name
+                )))
+            }
+            CacheCommand::SetHistoryDepth(..) => true,
+            _ => cmd.is_write(),
+        };
+        let (verb, rule, rules) = match write {
+            true => ("write", "allow_write", &u.allow_write),
+            false => ("read", "allow_read", &u.allow_read),
+        };
+        let mut keyed = false;
+        for_each_key(cmd, &mut |key| {
+            keyed = true;
+            match rules.iter().any(|r| rule_covers(r, key)) {
+                true => Ok(()),
+                false => Err(denied(name, &format!("{} {:?}", verb, key), rule, rules)),
+            }
+        })?;
+        if keyed {
+            return Ok(Access::Granted);
+        }
+        match cmd {
+            CacheCommand::Len
+            | CacheCommand::Info
+            | CacheCommand::Stats
+            | CacheCommand::WalStats
+            | CacheCommand::Subscribe(_)
+            | CacheCommand::Publish(..) => Ok(Access::Granted),
+            // ключи всего кэша: нужно правило на все ключи
+            CacheCommand::Sample(_) | CacheCommand::PrefixStats(..) => {
+                match u.allow_read.iter().any(|r| rule_covers(r, "")) {
+                    true => Ok(Access::Granted),
+                    false => Err(denied(name, "read all keys", "allow_read", &u.allow_read)),
+                }
+            }
+            // остальное без ключей — сервер или кэш целиком
+            _ => Err(CacheError::PermissionDenied(format!(
+                "user {:?} may not run {}: admin = false",
+                name,
+                cmd.name()
+            ))),
+        }
+    }
+
+    /// Пользователи по имени, без хешей паролей (ответ `AclList`).
+    pub(crate) fn list(&self) -> Result<Vec<AclUser>, CacheError> {
+        let table = self.read();
+        let Some(table) = &*table else {
+            return Err(no_acl());
+        };
+        Ok(table
+            .users
+            .iter()
+            .map(|u| AclUser {
+                password_hash: String::new(),
+                ..u.clone()
+            })
+            .collect())
+    }
+
+    /// Заводит или заменяет `user` (`AclSetUser`) либо удаляет пользователя
+    /// `name` (`AclDelUser`, `user` — None; ответ — был ли он). Файл
+    /// таблицы переписывается до того, как изменение вступит в силу.
+    pub(crate) fn update(&self, name: &str, user: Option<AclUser>) -> Result<bool, CacheError> {
+        if let Some(u) = &user {
+            u.validate()?;
+        }
+        let mut guard = self.table.write().unwrap_or_else(|e| e.into_inner());
+        let Some(table) = &mut *guard else {
+            return Err(no_acl());
+        };
+        let mut users = table.users.clone();
+        let found = users.iter().position(|u| u.name == name);
+        match (found, user) {
+            (Some(at), Some(u)) => users[at] = u,
+            (None, Some(u)) => {
+                users.push(u);
+                users.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+            (Some(at), None) => {
+                users.remove(at);
+            }
+            (None, None) => return Ok(false),
+        }
+        if let Some(path) = &table.path {
+            save_users(path, &users)?;
+        }
+        table.users = users;
+        Ok(true)
+    }
+}
+
+fn no_acl() -> CacheError {
+    CacheError::Unsupported("no ACL users are configured on this server".into())
+}
+
+/// Пользователи из файла таблицы.
+pub(crate) fn load_users(path: &Path) -> Result<Vec<AclUser>, CacheError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| CacheError::Internal(format!("read {}: {}", path.display(), e)))?;
+    parse_users(&text).map_err(|e| CacheError::Unsupported(format!("{}: {}", path.display(), e)))
+}
+
+fn save_users(path: &Path, users: &[AclUser]) -> Result<(), CacheError> {
+    use std::io::Write;
+    let text = users_to_toml(users);
+    crate::wal::replace_file(path, |f| f.write_all(text.as_bytes()))
+        .map_err(|e| CacheError::Internal(format!("write {}: {}", path.display(), e)))
+}
+
+/// Таблица в том же подмножестве TOML, что читает `parse_users`.
+pub(crate) fn users_to_toml(users: &[AclUser]) -> String {
+    let list = |items: &[String]| {
+        let items: Vec<String> = items.iter().map(|s| toml_string(s)).collect();
+        format!("[{}]", items.join(", "))
+    };
+    let mut out = String::new();
+    for (i, u) in users.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "[[users]]");
+        let _ = writeln!(out, "name = {}", toml_string(&u.name));
+        let _ = writeln!(out, "password_hash = {}", toml_string(&u.password_hash));
+        let _ = writeln!(out, "allow_read = {}", list(&u.allow_read));
+        let _ = writeln!(out, "allow_write = {}", list(&u.allow_write));
+        let _ = writeln!(out, "admin = {}", u.admin);
+    }
+    out
+}
+
+fn toml_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04X}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Str(String),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+/// Разбор файла таблицы: `[[users]]`, базовые строки в двойных кавычках,
+/// `true`/`false`, массивы строк (в том числе на несколько строк) и
+/// комментарии `#`. Остальной TOML — ошибка с номером строки.
+pub(crate) fn parse_users(text: &str) -> Result<Vec<AclUser>, CacheError> {
+    let mut p = Parser {
+        chars: text.chars().collect(),
+        at: 0,
+        line: 1,
+    };
+    let mut users: Vec<AclUser> = Vec::new();
+    // поля текущей таблицы, чтобы поймать повтор
+    let mut seen: Vec<String> = Vec::new();
+    loop {
+        p.skip_blank(true);
+        let Some(c) = p.peek() else {
+            break;
+        };
+        if c == '[' {
+            let line = p.line;
+            let header = p.take_while(|c| c != '\n' && c != '#');
+            if header.trim() != "[[users]]" {
+                return Err(bad(
+                    line,
+                    format!("expected [[users]], got {:?}", header.trim()),
+                ));
+            }
+            users.push(AclUser::default());
+            seen.clear();
+        } else {
+            let line = p.line;
+            let key = p.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if key.is_empty() {
+                return Err(bad(line, format!("unexpected {:?}", c)));
+            }
+            p.skip_blank(false);
+            if p.next() != Some('=') {
+                return Err(bad(line, format!("expected = after {}", key)));
+            }
+            p.skip_blank(false);
+            let value = p.value()?;
+            let Some(user) = users.last_mut() else {
+                return Err(bad(line, format!("{} outside of [[users]]", key)));
+            };
+            if seen.contains(&key) {
+                return Err(bad(line, format!("{} is set twice", key)));
+            }
+            seen.push(key.clone());
+            match (key.as_str(), value) {
+                ("name", Value::Str(s)) => user.name = s,
+                ("password_hash", Value::Str(s)) => user.password_hash = s,
+                ("allow_read", Value::List(l)) => user.allow_read = l,
+                ("allow_write", Value::List(l)) => user.allow_write = l,
+                ("admin", Value::Bool(b)) => user.admin = b,
+                ("name" | "password_hash", _) => return Err(bad(line, format!("{} must be a string", key))),
+                ("allow_read" | "allow_write", _) => {
+                    return Err(bad(line, format!("{} must be an array of strings", key)))
+                }
+                ("admin", _) => return Err(bad(line, "admin must be true or false".into())),
+                _ => {
+                    return Err(bad(
+                        line,
+                        format!(
+                            "unknown key {:?}, expected name, password_hash, allow_read, allow_write or admin",
+                            key
+                        ),
+                    ))
+                }
+            }
+        }
+        p.skip_blank(false);
+        match p.next() {
+            None | Some('\n') => {}
+            Some(c) => return Err(bad(p.line, format!("unexpected {:?} at end of line", c))),
+        }
+    }
+    if let Some(u) = users.iter().find(|u| u.name.is_empty()) {
+        return Err(CacheError::Unsupported(format!(
+            "[[users]] without a name (password_hash {:?})",
+            u.password_hash
+        )));
+    }
+    Ok(users)
+}
+
+fn bad(line: usize, msg: String) -> CacheError {
+    CacheError::Unsupported(format!("line {}: {}", line, msg))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.at += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let mut out = String::new();
+        while let Some(c) = self.peek().filter(|&c| f(c)) {
+            out.push(c);
+            self.next();
+        }
+        out
+    }
+
+    // пробелы и комментарий до конца строки; с `newlines` — и переводы строк
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {}
+                '\n' if newlines => {}
+                '#' => {
+                    self.take_while(|c| c != '\n');
+                    continue;
+                }
+                _ => break,
+            }
+            self.next();
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, CacheError> {
+        let line = self.line;
+        match self.peek() {
+            Some('"') => self.string().map(Value::Str),
+            Some('[') => {
+                self.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_blank(true);
+                    match self.peek() {
+                        Some(']') => {
+                            self.next();
+                            return Ok(Value::List(items));
+                        }
+                        Some('"') => items.push(self.string()?),
+                        _ => return Err(bad(self.line, "arrays may hold only strings".into())),
+                    }
+                    self.skip_blank(true);
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Value::List(items)),
+                        _ => return Err(bad(self.line, "expected , or ] in array".into())),
+                    }
+                }
+            }
+            _ => match self.take_while(|c| c.is_ascii_alphanumeric()).as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                other => Err(bad(line, format!("unsupported value {:?}", other))),
+            },
+        }
+    }
+
+    fn string(&mut self) -> Result<String, CacheError> {
+        let line = self.line;
+        self.next();
+        let mut out = String::new();
+        loop {
+            match self.next() {
+                None | Some('\n') => return Err(bad(line, "unterminated string".into())),
+                Some('"') => return Ok(out),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(u @ ('u' | 'U')) => {
+                            let n = if u == 'u' { 4 } else { 8 };
+                            let hex: String = (0..n).filter_map(|_| self.next()).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| bad(line, format!("bad escape \\{}{}", u, hex)))?
+                        }
+                        other => return Err(bad(line, format!("bad escape {:?}", other))),
+                    };
+                    out.push(c);
+                }
+                Some(c) => out.push(c),
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::encoding::raw_bytes;
+use crate::error::CacheError;
+use crate::update::parse_i64;
+use serde::{Deserialize, Serialize};
+
+/// Свёртка команды `Aggregate` по значениям ключей на префиксе. Значения —
+/// целые десятичным текстом, как у `UpdateOp::AddI64`. Сумма, вышедшая за
+/// i64, — ошибка, а не насыщение: неверный итог на дашборде хуже отказа.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggOp {
+    Sum,
+    Min,
+    Max,
+    // число ключей с целым значением
+    Count,
+}
+
+impl AggOp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AggOp::Sum => "sum",
+            AggOp::Min => "min",
+            AggOp::Max => "max",
+            AggOp::Count => "count",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, CacheError> {
+        match s {
+            "sum" => Ok(AggOp::Sum),
+            "min" => Ok(AggOp::Min),
+            "max" => Ok(AggOp::Max),
+            "count" => Ok(AggOp::Count),
+            other => Err(CacheError::Unsupported(format!(
+                "unknown aggregate op {:?}, expected sum, min, max or count",
+                other
+            ))),
+        }
+    }
+}
+
+/// Накопитель одного обхода.
+pub(crate) struct Aggregate {
+    op: AggOp,
+    // нецелые значения пропускаются, иначе — ошибка
+    skip_invalid: bool,
+    acc: Option<i64>,
+}
+
+impl Aggregate {
+    pub(crate) fn new(op: AggOp, skip_invalid: bool) -> Self {
+        Self {
+            op,
+            skip_invalid,
+            acc: None,
+        }
+    }
+
+    pub(crate) fn add(&mut self, key: &str, value: &[u8]) -> Result<(), CacheError> {
+        let n = match parse_i64(key, Some(&raw_bytes(value))) {
+            Ok(n) => n,
+            Err(_) if self.skip_invalid => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        self.acc = Some(match (self.op, self.acc) {
+            (AggOp::Count, acc) => acc.unwrap_or(0) + 1,
+            (_, None) => n,
+            (AggOp::Sum, Some(acc)) => acc.checked_add(n).ok_or_else(|| {
+                CacheError::Unsupported(format!("sum overflows i64 at key {:?}", key))
+            })?,
+            (AggOp::Min, Some(acc)) => acc.min(n),
+            (AggOp::Max, Some(acc)) => acc.max(n),
+        });
+        Ok(())
+    }
+
+    /// Итог; `None` — min/max без единого целого значения.
+    pub(crate) fn finish(self) -> Option<i64> {
+        match self.op {
+            AggOp::Sum | AggOp::Count => Some(self.acc.unwrap_or(0)),
+            AggOp::Min | AggOp::Max => self.acc,
+        }
+    }
+}
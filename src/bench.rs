@@ -0,0 +1,255 @@
+//! Нагрузочный прогон против работающего сервера: `benchmark()` в Python и
+//! [`benchmark`] в Rust.
+
+use crate::core::Rng;
+use crate::error::CacheError;
+use crate::{Client, MAX_COMMAND_SIZE};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Параметры прогона. Каждый поток работает со своими `keys_per_worker`
+/// ключами `<prefix><поток>:<n>`, поэтому последнюю запись в ключ знает
+/// только он, и любое чтение можно сверить с ней.
+#[derive(Clone, Debug)]
+pub struct BenchOptions {
+    pub ops: u64,
+    pub value_size: usize,
+    pub workers: usize,
+    // доля get в процентах, остальное — set; см. `parse_mix`
+    pub get_percent: u8,
+    pub keys_per_worker: u64,
+    // ключи с этим префиксом удаляются после прогона
+    pub prefix: String,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            ops: 100_000,
+            value_size: 256,
+            workers: 8,
+            get_percent: 80,
+            keys_per_worker: 1000,
+            prefix: "bench:".into(),
+        }
+    }
+}
+
+/// Итог прогона; задержки — в микросекундах на одну команду.
+#[derive(Clone, Debug, Default)]
+pub struct BenchReport {
+    pub ops: u64,
+    pub gets: u64,
+    pub sets: u64,
+    pub elapsed: Duration,
+    pub ops_per_sec: f64,
+    pub p50_us: f64,
+    pub p90_us: f64,
+    pub p99_us: f64,
+    pub p999_us: f64,
+    pub max_us: f64,
+    // чтения, сверенные с последней записью: все get прогона и выборка после
+    pub verified: u64,
+}
+
+/// Смесь вида `"80get/20set"`; порядок частей любой, сумма — 100.
+pub fn parse_mix(mix: &str) -> Result<u8, CacheError> {
+    let bad = || {
+        CacheError::Unsupported(format!(
+            "bad benchmark mix {:?}, expected e.g. \"80get/20set\"",
+            mix
+        ))
+    };
+    let (mut get, mut set) = (0u32, 0u32);
+    for part in mix.split('/') {
+        let part = part.trim();
+        let (num, slot) = if let Some(n) = part.strip_suffix("get") {
+            (n, &mut get)
+        } else if let Some(n) = part.strip_suffix("set") {
+            (n, &mut set)
+        } else {
+            return Err(bad());
+        };
+        *slot += num.trim().parse::<u32>().map_err(|_| bad())?;
+    }
+    if get + set != 100 {
+        return Err(bad());
+    }
+    Ok(get as u8)
+}
+
+// значение с номером записи в начале: чтение старой версии видно при сверке
+fn value(template: &[u8], version: u64) -> Vec<u8> {
+    let mut v = template.to_vec();
+    let n = v.len().min(8);
+    v[..n].copy_from_slice(&version.to_le_bytes()[..n]);
+    v
+}
+
+struct WorkerResult {
+    started: Instant,
+    finished: Instant,
+    gets: u64,
+    latencies: Vec<u64>,
+    verified: u64,
+}
+
+fn run_worker(
+    client: Client,
+    opts: &BenchOptions,
+    worker: usize,
+    ops: u64,
+    start: &Barrier,
+) -> Result<WorkerResult, CacheError> {
+    let key = |n: u64| format!("{}{}:{}", opts.prefix, worker, n);
+    let template = vec![b'x'; opts.value_size];
+    let mut versions = vec![0u64; opts.keys_per_worker as usize];
+    // прогрев: у каждого ключа есть значение, get не промахивается
+    let warm_up =
+        (0..opts.keys_per_worker).try_for_each(|n| client.set(&key(n), &value(&template, 0)));
+    // до барьера доходят все потоки, иначе остальные ждали бы упавший вечно
+    start.wait();
+    warm_up?;
+    let mismatch = |k: &str, got: Option<Vec<u8>>| {
+        CacheError::Internal(format!(
+            "benchmark sanity check failed: {} read {} instead of the last written value",
+            k,
+            match got {
+                Some(v) => format!("{} bytes", v.len()),
+                None => "nothing".into(),
+            }
+        ))
+    };
+
+    let mut rng = Rng(worker as u64 + 1);
+    let mut latencies = Vec::with_capacity(ops as usize);
+    let (mut gets, mut verified, mut version) = (0, 0, 0);
+    let started = Instant::now();
+    for _ in 0..ops {
+        let r = rng.next();
+        let n = (r >> 8) % opts.keys_per_worker;
+        let k = key(n);
+        let t = Instant::now();
+        if (r % 100) < opts.get_percent as u64 {
+            let got = client.get(&k)?;
+            latencies.push(t.elapsed().as_nanos() as u64);
+            gets += 1;
+            if got.as_deref() != Some(value(&template, versions[n as usize]).as_slice()) {
+                return Err(mismatch(&k, got));
+            }
+            verified += 1;
+        } else {
+            version += 1;
+            let v = value(&template, version);
+            client.set(&k, &v)?;
+            latencies.push(t.elapsed().as_nanos() as u64);
+            versions[n as usize] = version;
+        }
+    }
+    let finished = Instant::now();
+
+    // выборка после прогона: сервер не потерял подтверждённые записи
+    let step = (opts.keys_per_worker / 100).max(1) as usize;
+    for n in (0..opts.keys_per_worker).step_by(step) {
+        let k = key(n);
+        let got = client.get(&k)?;
+        if got.as_deref() != Some(value(&template, versions[n as usize]).as_slice()) {
+            return Err(mismatch(&k, got));
+        }
+        verified += 1;
+    }
+    Ok(WorkerResult {
+        started,
+        finished,
+        gets,
+        latencies,
+        verified,
+    })
+}
+
+fn percentile(sorted: &[u64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let i = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[i] as f64 / 1000.0
+}
+
+/// Прогоняет `opts.ops` команд в `opts.workers` потоках, у каждого потока — свой
+/// `Client`. Любое чтение, не совпавшее с последней записью потока, — ошибка:
+/// цифры сломанного сервера не возвращаются. Ключи прогона потом удаляются.
+pub fn benchmark(addr: &str, opts: &BenchOptions) -> Result<BenchReport, CacheError> {
+    if opts.workers == 0 || opts.keys_per_worker == 0 {
+        return Err(CacheError::Unsupported(
+            "benchmark needs at least one worker and one key".into(),
+        ));
+    }
+    if opts.value_size > MAX_COMMAND_SIZE - 1024 {
+        return Err(CacheError::Unsupported(format!(
+            "benchmark value_size {} exceeds the command size limit",
+            opts.value_size
+        )));
+    }
+    let clients = (0..opts.workers)
+        .map(|_| Client::connect(addr))
+        .collect::<Result<Vec<_>, _>>()?;
+    let cleanup = clients[0].clone();
+    let start = Arc::new(Barrier::new(opts.workers));
+    let per_worker = opts.ops / opts.workers as u64;
+    let extra = opts.ops % opts.workers as u64;
+
+    let handles: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(w, client)| {
+            let opts = opts.clone();
+            let start = Arc::clone(&start);
+            let ops = per_worker + ((w as u64) < extra) as u64;
+            thread::Builder::new()
+                .name(format!("tiny-mp-cache-bench-{}", w))
+                .spawn(move || run_worker(client, &opts, w, ops, &start))
+                .map_err(|e| CacheError::Internal(format!("spawn bench thread: {}", e)))
+        })
+        .collect::<Result<_, _>>()?;
+    let results: Vec<Result<WorkerResult, CacheError>> = handles
+        .into_iter()
+        .map(|h| {
+            h.join()
+                .unwrap_or_else(|_| Err(CacheError::Internal("bench thread panicked".into())))
+        })
+        .collect();
+    cleanup.delete_prefix(&opts.prefix)?;
+    let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+    let started = results
+        .iter()
+        .map(|r| r.started)
+        .min()
+        .expect("workers > 0");
+    let finished = results
+        .iter()
+        .map(|r| r.finished)
+        .max()
+        .expect("workers > 0");
+    let elapsed = finished - started;
+    let mut latencies: Vec<u64> = results
+        .iter()
+        .flat_map(|r| r.latencies.iter().copied())
+        .collect();
+    latencies.sort_unstable();
+    let gets = results.iter().map(|r| r.gets).sum();
+    Ok(BenchReport {
+        ops: opts.ops,
+        gets,
+        sets: opts.ops - gets,
+        elapsed,
+        ops_per_sec: opts.ops as f64 / elapsed.as_secs_f64().max(1e-9),
+        p50_us: percentile(&latencies, 0.50),
+        p90_us: percentile(&latencies, 0.90),
+        p99_us: percentile(&latencies, 0.99),
+        p999_us: percentile(&latencies, 0.999),
+        max_us: latencies.last().map_or(0.0, |&ns| ns as f64 / 1000.0),
+        verified: results.iter().map(|r| r.verified).sum(),
+    })
+}
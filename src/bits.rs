@@ -0,0 +1,54 @@
+//! Битовые операции над значением ключа для SetBit/GetBit/BitCount. Бит 0 —
+//! старший бит первого байта, как в Redis.
+
+/// Наибольшее смещение бита по умолчанию: битовая карта до 8 МБ, см.
+/// `PersistentCore::set_max_bit_offset`.
+pub(crate) const DEFAULT_MAX_BIT_OFFSET: u64 = (8 << 20) * 8 - 1;
+
+fn split(offset: u64) -> (usize, u8) {
+    ((offset / 8) as usize, 0x80 >> (offset % 8))
+}
+
+/// За концом значения все биты — нули.
+pub(crate) fn get_bit(v: &[u8], offset: u64) -> bool {
+    let (byte, mask) = split(offset);
+    v.get(byte).is_some_and(|b| b & mask != 0)
+}
+
+/// Ставит бит, при нехватке длины дописывая нулевые байты.
+pub(crate) fn set_bit(v: &mut Vec<u8>, offset: u64, bit: bool) {
+    let (byte, mask) = split(offset);
+    if v.len() <= byte {
+        v.resize(byte + 1, 0);
+    }
+    if bit {
+        v[byte] |= mask;
+    } else {
+        v[byte] &= !mask;
+    }
+}
+
+/// Поменяет ли `set_bit` значение (другой бит или нужно дописать байты).
+pub(crate) fn changes(v: &[u8], offset: u64, bit: bool) -> bool {
+    (offset / 8) as usize >= v.len() || get_bit(v, offset) != bit
+}
+
+/// Число единичных битов в байтах `[start, end]` включительно; отрицательный
+/// индекс отсчитывается от конца (-1 — последний байт), как в Redis.
+pub(crate) fn bit_count(v: &[u8], range: Option<(i64, i64)>) -> i64 {
+    let len = v.len() as i64;
+    let (start, end) = match range {
+        None => (0, len - 1),
+        Some((start, end)) => {
+            let norm = |i: i64| if i < 0 { (len + i).max(0) } else { i };
+            (norm(start), norm(end).min(len - 1))
+        }
+    };
+    if start > end {
+        return 0;
+    }
+    v[start as usize..=end as usize]
+        .iter()
+        .map(|b| b.count_ones() as i64)
+        .sum()
+}
@@ -0,0 +1,106 @@
+use crate::error::CacheError;
+use crate::ResponseValue;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Блокирующий `BGet`: запрос ждёт появления ключа в списке ожидающих этого
+/// ключа, `Set` будит всех ожидающих. Сервер держит по потоку на соединение,
+/// так что каждый ждущий клиент занимает поток; поэтому время ожидания
+/// ограничено `MAX_BLOCK`, а число одновременно ждущих — `MAX_BLOCKED`.
+pub const MAX_BLOCK: Duration = Duration::from_secs(60);
+const MAX_BLOCKED: usize = 1024;
+
+// (id ожидающего, куда отдать значение)
+type Waiter = (u64, SyncSender<Vec<u8>>);
+
+#[derive(Default)]
+pub struct Waiters {
+    map: Mutex<HashMap<String, Vec<Waiter>>>,
+    // ждущих всего; без них Set не трогает блокировку
+    count: AtomicUsize,
+    next_id: AtomicU64,
+}
+
+/// Убирает ожидающего, если Set его ещё не разбудил.
+struct WaiterGuard<'a> {
+    waiters: &'a Waiters,
+    key: &'a str,
+    id: u64,
+}
+
+impl Drop for WaiterGuard<'_> {
+    fn drop(&mut self) {
+        let mut map = self.waiters.map.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(list) = map.get_mut(self.key) {
+            let before = list.len();
+            list.retain(|(id, _)| *id != self.id);
+            self.waiters
+                .count
+                .fetch_sub(before - list.len(), Ordering::Release);
+            if list.is_empty() {
+                map.remove(self.key);
+            }
+        }
+    }
+}
+
+impl Waiters {
+    pub fn active(&self) -> bool {
+        self.count.load(Ordering::Acquire) > 0
+    }
+
+    /// Будит всех, кто ждёт `key`; вызывается после применения Set.
+    pub fn wake(&self, key: &str, value: &[u8]) {
+        if !self.active() {
+            return;
+        }
+        let mut map = self.map.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(list) = map.remove(key) else {
+            return;
+        };
+        self.count.fetch_sub(list.len(), Ordering::Release);
+        for (_, tx) in list {
+            // ожидающий мог уже уйти по таймауту
+            let _ = tx.try_send(value.to_vec());
+        }
+    }
+
+    /// Ждёт значение `key` до `timeout`. `current` читает ключ уже после
+    /// регистрации, чтобы Set между проверкой и ожиданием не потерялся.
+    pub fn wait(
+        &self,
+        key: &str,
+        timeout: Duration,
+        current: impl FnOnce() -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, CacheError> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut map = self.map.lock().unwrap_or_else(|e| e.into_inner());
+            if self.count.load(Ordering::Acquire) >= MAX_BLOCKED {
+                return Err(CacheError::Busy(format!(
+                    "too many blocked clients (max {})",
+                    MAX_BLOCKED
+                )));
+            }
+            map.entry(key.to_string()).or_default().push((id, tx));
+            self.count.fetch_add(1, Ordering::Release);
+        }
+        let _guard = WaiterGuard {
+            waiters: self,
+            key,
+            id,
+        };
+        if let Some(v) = current() {
+            return Ok(Some(v));
+        }
+        Ok(rx.recv_timeout(timeout.min(MAX_BLOCK)).ok())
+    }
+
+    pub fn info_fields(&self) -> Vec<(&'static str, ResponseValue)> {
+        vec![("blocked_clients", self.count.load(Ordering::Acquire).into())]
+    }
+}
@@ -0,0 +1,75 @@
+//! Фильтр Блума по ключам на префикс (`CacheCommand::ExportBloom`): клиент
+//! проверяет «ключ, наверное, есть» у себя, без Exists на каждый ключ. Хэш
+//! свой, а не `DefaultHasher`: фильтр, собранный сервером, должен читаться
+//! клиентом другой сборки.
+
+use serde::{Deserialize, Serialize};
+
+/// Наибольшее число бит на ключ в `ExportBloom`.
+pub(crate) const MAX_BITS_PER_KEY: u32 = 64;
+
+/// Битовый массив и число хэш-функций. Ложных «нет» не бывает, ложное «да»
+/// — с вероятностью около `(1 - e^(-k/b))^k` при `b` битах на ключ и `k`
+/// хэшах.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BloomFilter {
+    hashes: u32,
+    bits: Vec<u8>,
+}
+
+// FNV-1a и перемешивание splitmix64: два независимых хэша для двойного
+// хэширования (Кирш — Митценмахер)
+fn hash_pair(key: &str) -> (u64, u64) {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in key.as_bytes() {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    let mix = |mut z: u64| {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    (mix(h), mix(h ^ 0x9e37_79b9_7f4a_7c15) | 1)
+}
+
+impl BloomFilter {
+    /// Пустой фильтр под `keys` ключей по `bits_per_key` бит на ключ; число
+    /// хэшей — оптимальное для этой плотности.
+    pub fn new(keys: usize, bits_per_key: u32) -> Self {
+        let bits_per_key = bits_per_key.clamp(1, MAX_BITS_PER_KEY);
+        let bits = keys.saturating_mul(bits_per_key as usize).max(64);
+        let hashes = (bits_per_key as f64 * std::f64::consts::LN_2).round() as u32;
+        Self {
+            hashes: hashes.clamp(1, 30),
+            bits: vec![0; bits.div_ceil(8)],
+        }
+    }
+
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let (h1, h2) = hash_pair(key);
+        let m = self.bits.len() as u64 * 8;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        for i in self.positions(key) {
+            self.bits[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    /// `false` — ключа точно не было при сборке фильтра.
+    pub fn maybe_contains(&self, key: &str) -> bool {
+        self.positions(key)
+            .all(|i| self.bits[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    pub fn hashes(&self) -> u32 {
+        self.hashes
+    }
+
+    /// Размер битового массива в битах.
+    pub fn bit_len(&self) -> u64 {
+        self.bits.len() as u64 * 8
+    }
+}
@@ -0,0 +1,154 @@
+//! Режим сбоев для проверки клиентов, см. `PersistentCore::set_chaos`: перед
+//! каждой командой соединения сервер ждёт случайное время из `latency_ms`, а
+//! с вероятностью `error_rate` отвечает ошибкой `INJECTED_ERROR`, с
+//! вероятностью `disconnect_rate` закрывает соединение, не ответив. Команда
+//! с ошибкой или обрывом не выполняется, так что повтор клиента её не
+//! задвоит. `Hello`, `Auth` и `ReplSync` не трогаются: без них клиент и
+//! реплика не начнут работать. С `seed` решения идут одной и той же
+//! последовательностью в порядке прихода команд на сервер.
+
+use crate::core::Rng;
+use crate::error::CacheError;
+use crate::{CacheCommand, ResponseValue};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Текст ответа на команду, которой выпала ошибка.
+pub const INJECTED_ERROR: &str = "chaos: injected error";
+
+/// Настройки режима сбоев; по умолчанию сбоев нет.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChaosOptions {
+    // задержка перед командой, мс, равномерно от min до max; None — без неё
+    pub latency_ms: Option<(u64, u64)>,
+    pub error_rate: f64,
+    pub disconnect_rate: f64,
+    // None — новая последовательность при каждом запуске
+    pub seed: Option<u64>,
+}
+
+/// Что выпало команде.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Fault {
+    Error,
+    Disconnect,
+}
+
+pub(crate) struct Chaos {
+    options: ChaosOptions,
+    rng: Mutex<Rng>,
+    delays: AtomicU64,
+    errors: AtomicU64,
+    disconnects: AtomicU64,
+}
+
+impl Chaos {
+    pub(crate) fn new(options: ChaosOptions) -> Result<Self, CacheError> {
+        for (name, rate) in [
+            ("error_rate", options.error_rate),
+            ("disconnect_rate", options.disconnect_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(CacheError::Unsupported(format!(
+                    "chaos: {} must be between 0 and 1, got {}",
+                    name, rate
+                )));
+            }
+        }
+        if options.error_rate + options.disconnect_rate > 1.0 {
+            return Err(CacheError::Unsupported(
+                "chaos: error_rate + disconnect_rate must not exceed 1".into(),
+            ));
+        }
+        if let Some((min, max)) = options.latency_ms {
+            if min > max {
+                return Err(CacheError::Unsupported(format!(
+                    "chaos: latency_ms ({}, {}) has min above max",
+                    min, max
+                )));
+            }
+        }
+        let seed = options
+            .seed
+            .unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        Ok(Self {
+            options,
+            // xorshift не принимает 0, а seed=0 — обычное значение
+            rng: Mutex::new(Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)),
+            delays: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            disconnects: AtomicU64::new(0),
+        })
+    }
+
+    pub(crate) fn options(&self) -> &ChaosOptions {
+        &self.options
+    }
+
+    /// Перед командой `cmd`: выдерживает задержку и решает, что с ней
+    /// сделать; None — выполнить как обычно.
+    pub(crate) fn before(&self, cmd: &CacheCommand) -> Option<Fault> {
+        if matches!(
+            cmd,
+            CacheCommand::Hello(_) | CacheCommand::Auth(..) | CacheCommand::ReplSync(_)
+        ) {
+            return None;
+        }
+        let (delay, roll) = {
+            let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+            let delay = self
+                .options
+                .latency_ms
+                .map(|(min, max)| min + rng.below(max - min + 1));
+            // 53 бита — столько помещается в мантиссу f64
+            (delay, (rng.next() >> 11) as f64 / (1u64 << 53) as f64)
+        };
+        if let Some(ms) = delay.filter(|&ms| ms > 0) {
+            self.delays.fetch_add(1, Ordering::Relaxed);
+            std::thread::sleep(Duration::from_millis(ms));
+        }
+        if roll < self.options.disconnect_rate {
+            self.disconnects.fetch_add(1, Ordering::Relaxed);
+            Some(Fault::Disconnect)
+        } else if roll < self.options.disconnect_rate + self.options.error_rate {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            Some(Fault::Error)
+        } else {
+            None
+        }
+    }
+
+    /// Счётчики для `info()`; без режима сбоев их там нет.
+    pub(crate) fn info_fields(&self) -> Vec<(&'static str, ResponseValue)> {
+        vec![
+            ("chaos_delays", self.delays.load(Ordering::Relaxed).into()),
+            ("chaos_errors", self.errors.load(Ordering::Relaxed).into()),
+            (
+                "chaos_disconnects",
+                self.disconnects.load(Ordering::Relaxed).into(),
+            ),
+        ]
+    }
+}
+
+/// Настройки для `ConfigDump`: выключенное — 0, `chaos_seed` без seed —
+/// `"unset"`.
+pub(crate) fn config_fields(chaos: Option<&Chaos>) -> Vec<(String, ResponseValue)> {
+    let default = ChaosOptions::default();
+    let opts = chaos.map_or(&default, Chaos::options);
+    let (min, max) = opts.latency_ms.unwrap_or((0, 0));
+    vec![
+        ("chaos".into(), chaos.is_some().into()),
+        ("chaos_latency_min_ms".into(), min.into()),
+        ("chaos_latency_max_ms".into(), max.into()),
+        ("chaos_error_rate".into(), opts.error_rate.into()),
+        ("chaos_disconnect_rate".into(), opts.disconnect_rate.into()),
+        (
+            "chaos_seed".into(),
+            opts.seed.map_or("unset".into(), ResponseValue::from),
+        ),
+    ]
+}
@@ -0,0 +1,869 @@
+use crate::acl::Credentials;
+use crate::blocking::MAX_BLOCK;
+use crate::encoding::{Compress, DEFAULT_COMPRESS_MIN};
+use crate::error::CacheError;
+use crate::pool::{Pool, Timeouts};
+use crate::{
+    encode_frame, set_ex_frame, set_frame, set_tagged_frame, AclUser, AggOp, BloomFilter,
+    CacheCommand, CacheResponse, ClientInfo, KeySample, MuxConn, ResponseValue, TransportAddr,
+    UpdateOp, ValueEncoding, ValueMeta, VerifyReport,
+};
+use std::borrow::Cow;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Страница `scan_items`: следующий курсор и пары ключ-значение.
+pub type ScanPage = (u64, Vec<(String, Vec<u8>)>);
+
+/// Настройки `Client`; `None` в таймауте — ждать без ограничения.
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    // для админ-команд (set_read_only)
+    pub admin_token: Option<String>,
+    // пользователь ACL и его пароль, см. `PersistentCore::set_acl_file`:
+    // каждое новое соединение входит командой Auth
+    pub user: Option<String>,
+    pub password: Option<String>,
+    // одно соединение протокола 2 на все потоки вместо пула, см. `MuxConn`;
+    // без повторов и без subscribe/watch; оборванное соединение заменяется
+    // новым при следующем вызове
+    pub multiplex: bool,
+    // сжатие значений set* от compress_min байт (None — DEFAULT_COMPRESS_MIN);
+    // сервер протокола младше 5 получает их как есть
+    pub compress: ValueEncoding,
+    pub compress_min: Option<usize>,
+}
+
+/// Rust-клиент, зеркало Python-класса `TinyCache`, но с пулом постоянных
+/// соединений вместо соединения на команду (переподключение и повторы — см.
+/// `Pool`). Адрес — `"host:port"`, `"tcp://host:port"` или
+/// `"unix:///path/to.sock"`. Клон открывает свои соединения, а клон клиента с
+/// `multiplex` делит с ним одно.
+pub struct Client {
+    pool: Transport,
+    // для админ-команд (set_read_only)
+    admin_token: Option<String>,
+    compress: Compress,
+}
+
+enum Transport {
+    Pool(Pool),
+    Mux(Arc<MuxSlot>),
+}
+
+// соединение `multiplex`, общее для клонов клиента. Оборванное (например,
+// сервером по idle_timeout_secs) переоткрывается перед следующим запросом;
+// запросы, которые оно унесло, не повторяются.
+struct MuxSlot {
+    addr: TransportAddr,
+    timeouts: Timeouts,
+    auth: Option<Credentials>,
+    conn: Mutex<Arc<MuxConn>>,
+}
+
+impl MuxSlot {
+    fn get(&self) -> Result<Arc<MuxConn>, CacheError> {
+        let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        if conn.is_broken() {
+            *conn = Arc::new(MuxConn::open(
+                self.addr.clone(),
+                self.timeouts,
+                self.auth.as_ref(),
+            )?);
+        }
+        Ok(Arc::clone(&conn))
+    }
+}
+
+impl Transport {
+    fn call(&self, cmd: &CacheCommand) -> Result<CacheResponse, CacheError> {
+        match self {
+            Transport::Pool(pool) => pool.call(cmd),
+            Transport::Mux(mux) => mux.get()?.call(cmd),
+        }
+    }
+
+    fn call_waiting(
+        &self,
+        cmd: &CacheCommand,
+        wait: Duration,
+    ) -> Result<CacheResponse, CacheError> {
+        match self {
+            Transport::Pool(pool) => pool.call_waiting(cmd, wait),
+            Transport::Mux(mux) => mux.get()?.send_frame(&encode_frame(cmd)?, wait)?.wait(),
+        }
+    }
+
+    fn call_frame(&self, frame: &[u8]) -> Result<CacheResponse, CacheError> {
+        match self {
+            Transport::Pool(pool) => pool.call_frame(frame),
+            Transport::Mux(mux) => mux.get()?.send_frame(frame, Duration::ZERO)?.wait(),
+        }
+    }
+
+    fn protocol(&self) -> Result<u32, CacheError> {
+        match self {
+            Transport::Pool(pool) => pool.protocol(),
+            Transport::Mux(mux) => Ok(mux.get()?.protocol()),
+        }
+    }
+
+    fn close(&self) {
+        match self {
+            Transport::Pool(pool) => pool.close(),
+            Transport::Mux(mux) => mux.conn.lock().unwrap_or_else(|e| e.into_inner()).close(),
+        }
+    }
+}
+
+fn unexpected(op: &str, resp: CacheResponse) -> CacheError {
+    CacheError::Internal(format!("Unexpected response from {}: {:?}", op, resp))
+}
+
+impl Clone for Client {
+    fn clone(&self) -> Self {
+        let pool = match &self.pool {
+            Transport::Pool(pool) => Transport::Pool(
+                Pool::new(pool.addr().clone(), pool.timeouts()).with_auth(pool.auth().cloned()),
+            ),
+            Transport::Mux(mux) => Transport::Mux(Arc::clone(mux)),
+        };
+        Self {
+            pool,
+            admin_token: self.admin_token.clone(),
+            compress: self.compress,
+        }
+    }
+}
+
+impl Client {
+    pub fn connect(addr: &str) -> Result<Self, CacheError> {
+        Self::connect_with(addr, ClientOptions::default())
+    }
+
+    pub fn connect_with(addr: &str, options: ClientOptions) -> Result<Self, CacheError> {
+        let timeouts = Timeouts {
+            connect: options.connect_timeout,
+            read: options.read_timeout,
+            write: options.write_timeout,
+        };
+        let addr = TransportAddr::parse(addr);
+        let auth = match (options.user, options.password) {
+            (Some(user), Some(password)) => Some(Credentials { user, password }),
+            (None, None) => None,
+            _ => {
+                return Err(CacheError::Unsupported(
+                    "ClientOptions: user and password go together".into(),
+                ))
+            }
+        };
+        let pool = if options.multiplex {
+            let conn = Mutex::new(Arc::new(MuxConn::open(
+                addr.clone(),
+                timeouts,
+                auth.as_ref(),
+            )?));
+            Transport::Mux(Arc::new(MuxSlot {
+                addr,
+                timeouts,
+                auth,
+                conn,
+            }))
+        } else {
+            let pool = Pool::new(addr, timeouts).with_auth(auth);
+            pool.warm_up()?;
+            Transport::Pool(pool)
+        };
+        Ok(Self {
+            pool,
+            admin_token: options.admin_token,
+            compress: Compress {
+                encoding: options.compress,
+                min_size: options.compress_min.unwrap_or(DEFAULT_COMPRESS_MIN),
+            },
+        })
+    }
+
+    /// Закрывает соединения; дальнейшие вызовы возвращают `CacheError::Closed`.
+    pub fn close(&self) {
+        self.pool.close();
+    }
+
+    fn call(&self, cmd: CacheCommand) -> Result<CacheResponse, CacheError> {
+        self.pool.call(&cmd)
+    }
+
+    // значение для записи в кодировке `ClientOptions::compress`
+    fn encoded<'a>(&self, value: &'a [u8]) -> Result<Cow<'a, [u8]>, CacheError> {
+        let encoded = self.compress.apply(value, || self.pool.protocol())?;
+        Ok(encoded.map_or(Cow::Borrowed(value), Cow::Owned))
+    }
+
+    pub fn ping(&self) -> Result<(), CacheError> {
+        match self.call(CacheCommand::Ping)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("ping", resp)),
+        }
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) -> Result<(), CacheError> {
+        let value = self.encoded(value)?;
+        let (mut frame, offset) = set_frame(key, value.len())?;
+        frame[offset..].copy_from_slice(&value);
+        match self.pool.call_frame(&frame)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("set", resp)),
+        }
+    }
+
+    /// Set со сроком жизни; срок точен до миллисекунды и должен быть не меньше её.
+    pub fn set_ex(&self, key: &str, value: &[u8], ttl: Duration) -> Result<(), CacheError> {
+        let value = self.encoded(value)?;
+        let (mut frame, offset) = set_ex_frame(key, ttl.as_millis() as u64, value.len())?;
+        frame[offset..].copy_from_slice(&value);
+        match self.pool.call_frame(&frame)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("set_ex", resp)),
+        }
+    }
+
+    /// Set с тегами: ключ потом удаляется вместе с остальными ключами тега
+    /// через `delete_by_tag`. Обычный `set` теги ключа сбрасывает.
+    pub fn set_with_tags(&self, key: &str, value: &[u8], tags: &[&str]) -> Result<(), CacheError> {
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        let value = self.encoded(value)?;
+        let (mut frame, offset) = set_tagged_frame(key, &tags, value.len())?;
+        frame[offset..].copy_from_slice(&value);
+        match self.pool.call_frame(&frame)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("set_with_tags", resp)),
+        }
+    }
+
+    /// Ставит срок жизни существующему ключу; `false`, если ключа нет.
+    pub fn expire(&self, key: &str, ttl: Duration) -> Result<bool, CacheError> {
+        let ttl_ms = ttl.as_millis().min(i64::MAX as u128) as i64;
+        match self.call(CacheCommand::PExpire(key.to_string(), ttl_ms))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("expire", resp)),
+        }
+    }
+
+    /// Ключ истечёт в момент `at`; прошедший момент удаляет ключ сразу.
+    pub fn expire_at(&self, key: &str, at: SystemTime) -> Result<bool, CacheError> {
+        let at_ms = at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        match self.call(CacheCommand::ExpireAt(key.to_string(), at_ms))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("expire_at", resp)),
+        }
+    }
+
+    /// Оставшийся срок в мс: -1 — ключ бессрочный, -2 — ключа нет.
+    pub fn pttl(&self, key: &str) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::PTtl(key.to_string()))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("pttl", resp)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        match self.call(CacheCommand::Get(key.to_string()))? {
+            CacheResponse::Value(v) => Ok(Some(v)),
+            CacheResponse::Nil => Ok(None),
+            resp => Err(unexpected("get", resp)),
+        }
+    }
+
+    /// Ждёт появления ключа до `timeout` (не больше минуты).
+    pub fn get_blocking(
+        &self,
+        key: &str,
+        timeout: Duration,
+    ) -> Result<Option<Vec<u8>>, CacheError> {
+        if timeout > MAX_BLOCK {
+            return Err(CacheError::Unsupported(format!(
+                "timeout must be at most {} seconds",
+                MAX_BLOCK.as_secs()
+            )));
+        }
+        let cmd = CacheCommand::BGet(key.to_string(), timeout.as_millis() as u64);
+        match self.pool.call_waiting(&cmd, timeout)? {
+            CacheResponse::Value(v) => Ok(Some(v)),
+            CacheResponse::Nil => Ok(None),
+            resp => Err(unexpected("get_blocking", resp)),
+        }
+    }
+
+    pub fn exists(&self, key: &str) -> Result<bool, CacheError> {
+        match self.call(CacheCommand::Exists(key.to_string()))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("exists", resp)),
+        }
+    }
+
+    /// Значение `key`, а если ключа нет — атомарно записывает `value`.
+    /// Второй элемент — `true`, если значение записал этот вызов.
+    pub fn get_or_set(&self, key: &str, value: &[u8]) -> Result<(Vec<u8>, bool), CacheError> {
+        match self.call(CacheCommand::GetOrSet(
+            key.to_string(),
+            self.encoded(value)?.into_owned(),
+        ))? {
+            CacheResponse::Entry(v, inserted) => Ok((v, inserted)),
+            resp => Err(unexpected("get_or_set", resp)),
+        }
+    }
+
+    /// Записывает `value`, только если ключа нет, и возвращает, записал ли
+    /// этот вызов; `ttl` — срок жизни, `None` — бессрочно. Вместе с
+    /// `delete_if` — блокировка между процессами: `set_nx(lock, token, ttl)`
+    /// для захвата и `delete_if(lock, token)` для освобождения.
+    pub fn set_nx(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> Result<bool, CacheError> {
+        // срок короче миллисекунды не должен стать «бессрочно»
+        let ttl_ms = ttl.map_or(0, |ttl| (ttl.as_millis() as u64).max(1));
+        match self.call(CacheCommand::SetNx(
+            key.to_string(),
+            ttl_ms,
+            self.encoded(value)?.into_owned(),
+        ))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("set_nx", resp)),
+        }
+    }
+
+    /// Атомарное преобразование значения на сервере; возвращает новое
+    /// значение (`None`, если ключа нет и после операции).
+    pub fn update(&self, key: &str, op: UpdateOp) -> Result<Option<Vec<u8>>, CacheError> {
+        match self.call(CacheCommand::Update(key.to_string(), op))? {
+            CacheResponse::Value(v) => Ok(Some(v)),
+            CacheResponse::Nil => Ok(None),
+            resp => Err(unexpected("update", resp)),
+        }
+    }
+
+    /// Сдвигает счётчик `key` на `n` и возвращает прежнее значение: номера
+    /// `prev + 1..=prev + n` достались этому вызову и не выдадутся повторно,
+    /// в том числе после перезапуска сервера.
+    pub fn incr_range(&self, key: &str, n: u64) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::IncrRange(key.to_string(), n))? {
+            CacheResponse::Int(prev) => Ok(prev),
+            resp => Err(unexpected("incr_range", resp)),
+        }
+    }
+
+    /// Ставит бит и возвращает прежний, см. `TinyCache.setbit`.
+    pub fn setbit(&self, key: &str, offset: u64, value: bool) -> Result<bool, CacheError> {
+        match self.call(CacheCommand::SetBit(key.to_string(), offset, value))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("setbit", resp)),
+        }
+    }
+
+    pub fn getbit(&self, key: &str, offset: u64) -> Result<bool, CacheError> {
+        match self.call(CacheCommand::GetBit(key.to_string(), offset))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("getbit", resp)),
+        }
+    }
+
+    /// `range` — диапазон байт `[start, end]`, отрицательные от конца.
+    pub fn bitcount(&self, key: &str, range: Option<(i64, i64)>) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::BitCount(key.to_string(), range))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("bitcount", resp)),
+        }
+    }
+
+    /// Обновляет время обращения к ключам; возвращает, сколько из них есть.
+    pub fn touch(&self, keys: &[&str]) -> Result<i64, CacheError> {
+        let keys = keys.iter().map(|k| k.to_string()).collect();
+        match self.call(CacheCommand::Touch(keys))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("touch", resp)),
+        }
+    }
+
+    /// Секунды с последнего обращения к ключу; `None`, если ключа нет.
+    pub fn idle_time(&self, key: &str) -> Result<Option<u64>, CacheError> {
+        match self.call(CacheCommand::IdleTime(key.to_string()))? {
+            CacheResponse::Int(n) => Ok(Some(n as u64)),
+            CacheResponse::Nil => Ok(None),
+            resp => Err(unexpected("idle_time", resp)),
+        }
+    }
+
+    /// Фильтр Блума по ключам на `prefix`, см. `PersistentCore::export_bloom`.
+    pub fn export_bloom(&self, prefix: &str, bits_per_key: u32) -> Result<BloomFilter, CacheError> {
+        match self.call(CacheCommand::ExportBloom(prefix.to_string(), bits_per_key))? {
+            CacheResponse::Bloom(filter) => Ok(filter),
+            resp => Err(unexpected("export_bloom", resp)),
+        }
+    }
+
+    /// Ключи и байты значений по группам из первых `depth` сегментов ключа,
+    /// см. `PersistentCore::prefix_stats`.
+    pub fn prefix_stats(
+        &self,
+        sep: &str,
+        depth: u32,
+    ) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        self.call(CacheCommand::PrefixStats(sep.to_string(), depth))?
+            .into_map()
+            .map_err(|resp| unexpected("prefix_stats", resp))
+    }
+
+    /// До `n` случайных ключей с размером значения, простоем и сроком, см.
+    /// `PersistentCore::sample`.
+    pub fn sample(&self, n: u32) -> Result<Vec<KeySample>, CacheError> {
+        match self.call(CacheCommand::Sample(n))? {
+            CacheResponse::Sample(keys) => Ok(keys),
+            resp => Err(unexpected("sample", resp)),
+        }
+    }
+
+    /// Сколько прежних значений сервер помнит у ключей на `prefix`, см.
+    /// `PersistentCore::set_history_depth`.
+    pub fn set_history_depth(&self, prefix: &str, depth: u32) -> Result<(), CacheError> {
+        match self.call(CacheCommand::SetHistoryDepth(prefix.to_string(), depth))? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("set_history_depth", resp)),
+        }
+    }
+
+    /// Прежние значения ключа от старых к новым: unix-время в мс, когда
+    /// значение заменили или удалили, и само значение.
+    pub fn history(&self, key: &str) -> Result<Vec<(u64, Vec<u8>)>, CacheError> {
+        match self.call(CacheCommand::History(key.to_string()))? {
+            CacheResponse::History(versions) => Ok(versions),
+            resp => Err(unexpected("history", resp)),
+        }
+    }
+
+    /// Вид значения ключа: пары "type", "encoding", "length", см.
+    /// `PersistentCore::key_type`.
+    pub fn key_type(&self, key: &str) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        self.call(CacheCommand::Type(key.to_string()))?
+            .into_map()
+            .map_err(|resp| unexpected("type", resp))
+    }
+
+    pub fn pop(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        match self.call(CacheCommand::Pop(key.to_string()))? {
+            CacheResponse::Value(v) => Ok(Some(v)),
+            CacheResponse::Nil => Ok(None),
+            resp => Err(unexpected("pop", resp)),
+        }
+    }
+
+    pub fn delete(&self, key: &str) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::Del(key.to_string()))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("delete", resp)),
+        }
+    }
+
+    /// Удаляет ключ, только если его значение равно `expected`; `false`, если
+    /// ключа нет или значение другое.
+    pub fn delete_if(&self, key: &str, expected: &[u8]) -> Result<bool, CacheError> {
+        match self.call(CacheCommand::DelIfEquals(
+            key.to_string(),
+            expected.to_vec(),
+        ))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("delete_if", resp)),
+        }
+    }
+
+    /// Продлевает срок ключа на `add`, только если его значение равно
+    /// `expected` (бессрочному ставит срок `add`); `false`, если ключа нет или
+    /// значение другое. Продление блокировки из `set_nx`, пока она наша.
+    pub fn extend_if(&self, key: &str, expected: &[u8], add: Duration) -> Result<bool, CacheError> {
+        let cmd = CacheCommand::PExtendIfEquals(
+            key.to_string(),
+            expected.to_vec(),
+            add.as_millis() as u64,
+        );
+        match self.call(cmd)? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("extend_if", resp)),
+        }
+    }
+
+    /// Возвращает ключ из надгробия (сервер с `set_tombstone_ttl`); `false`,
+    /// если надгробия нет или ключ уже записан заново.
+    pub fn undelete(&self, key: &str) -> Result<bool, CacheError> {
+        match self.call(CacheCommand::Undelete(key.to_string()))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("undelete", resp)),
+        }
+    }
+
+    /// Часы сервера, unix-время в мс.
+    pub fn time(&self) -> Result<u64, CacheError> {
+        match self.call(CacheCommand::Time)? {
+            CacheResponse::Int(n) => Ok(n as u64),
+            resp => Err(unexpected("time", resp)),
+        }
+    }
+
+    /// Значение со временем последней записи, сроком и версией; `None` —
+    /// ключа нет.
+    pub fn get_with_meta(&self, key: &str) -> Result<Option<ValueMeta>, CacheError> {
+        match self.call(CacheCommand::GetWithMeta(key.to_string()))? {
+            CacheResponse::Meta(meta) => Ok(Some(meta)),
+            CacheResponse::Nil => Ok(None),
+            resp => Err(unexpected("get_with_meta", resp)),
+        }
+    }
+
+    /// Атомарно меняет местами значения двух ключей; `false` — не было ни
+    /// одного.
+    /// Значения ключей на один момент времени, по порядку `keys`: обмен
+    /// `swap` виден целиком или не виден вовсе. Сквозное чтение (loader) не
+    /// применяется.
+    pub fn get_consistent(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, CacheError> {
+        let keys = keys.iter().map(|k| k.to_string()).collect();
+        match self.call(CacheCommand::MGetConsistent(keys))? {
+            CacheResponse::Values(values) => Ok(values),
+            resp => Err(unexpected("get_consistent", resp)),
+        }
+    }
+
+    pub fn swap(&self, a: &str, b: &str) -> Result<bool, CacheError> {
+        match self.call(CacheCommand::Swap(a.to_string(), b.to_string()))? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("swap", resp)),
+        }
+    }
+
+    /// Удаляет все ключи с `prefix` и возвращает их число.
+    pub fn delete_prefix(&self, prefix: &str) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::DelPrefix(prefix.to_string()))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("delete_prefix", resp)),
+        }
+    }
+
+    /// Удаляет все ключи с тегом `tag` и возвращает их число.
+    pub fn delete_by_tag(&self, tag: &str) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::DelByTag(tag.to_string()))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("delete_by_tag", resp)),
+        }
+    }
+
+    pub fn keys(&self, pattern: &str) -> Result<Vec<String>, CacheError> {
+        match self.call(CacheCommand::Keys(pattern.to_string()))? {
+            CacheResponse::Keys(keys) => Ok(keys),
+            resp => Err(unexpected("keys", resp)),
+        }
+    }
+
+    /// Ключи на `prefix` по возрастанию, страница `offset..offset + limit`.
+    pub fn keys_sorted(
+        &self,
+        prefix: &str,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<String>, CacheError> {
+        match self.call(CacheCommand::KeysSorted(prefix.to_string(), offset, limit))? {
+            CacheResponse::Keys(keys) => Ok(keys),
+            resp => Err(unexpected("keys_sorted", resp)),
+        }
+    }
+
+    pub fn len(&self) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::Len)? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("len", resp)),
+        }
+    }
+
+    pub fn is_empty(&self) -> Result<bool, CacheError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Одна страница пар с ключами на `prefix`; курсор 0 в ответе — конец.
+    pub fn scan_items(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: u32,
+    ) -> Result<ScanPage, CacheError> {
+        match self.call(CacheCommand::ScanItems(cursor, prefix.to_string(), count))? {
+            CacheResponse::Items(next, items) => Ok((next, items)),
+            resp => Err(unexpected("scan_items", resp)),
+        }
+    }
+
+    /// Как `scan_items`, но только ключи.
+    pub fn scan_keys(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: u32,
+    ) -> Result<(u64, Vec<String>), CacheError> {
+        match self.call(CacheCommand::Scan(cursor, prefix.to_string(), count))? {
+            CacheResponse::ScanKeys(next, keys) => Ok((next, keys)),
+            resp => Err(unexpected("scan_keys", resp)),
+        }
+    }
+
+    /// Как `scan_keys`, но только ключи с длиной значения в `sizes` байт
+    /// (`1_000_000..` — от мегабайта), вместе с этой длиной; фильтр
+    /// применяет сервер, значения не передаются.
+    pub fn scan_sizes(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: u32,
+        sizes: impl RangeBounds<u64>,
+    ) -> Result<(u64, Vec<(String, u64)>), CacheError> {
+        let min = match sizes.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let max = match sizes.end_bound() {
+            Bound::Included(&n) => n,
+            // пустой диапазон ..0 не пропускает ничего
+            Bound::Excluded(&0) => return Ok((0, Vec::new())),
+            Bound::Excluded(&n) => n - 1,
+            Bound::Unbounded => u64::MAX,
+        };
+        let cmd = CacheCommand::ScanSizes(cursor, prefix.to_string(), count, min, max);
+        match self.call(cmd)? {
+            CacheResponse::Sizes(next, keys) => Ok((next, keys)),
+            resp => Err(unexpected("scan_sizes", resp)),
+        }
+    }
+
+    /// Как `scan_keys`, но вместе с оставшимся сроком ключа в мс, как у
+    /// `pttl` (-1 — бессрочный).
+    pub fn scan_ttl(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: u32,
+    ) -> Result<(u64, Vec<(String, i64)>), CacheError> {
+        match self.call(CacheCommand::ScanTtl(cursor, prefix.to_string(), count))? {
+            CacheResponse::Ttls(next, keys) => Ok((next, keys)),
+            resp => Err(unexpected("scan_ttl", resp)),
+        }
+    }
+
+    /// Свёртка целых значений ключей на `prefix` на сервере, см. `AggOp`;
+    /// `None` — у min/max не нашлось ни одного целого значения.
+    pub fn aggregate(
+        &self,
+        prefix: &str,
+        op: AggOp,
+        skip_invalid: bool,
+    ) -> Result<Option<i64>, CacheError> {
+        match self.call(CacheCommand::Aggregate(
+            prefix.to_string(),
+            op,
+            skip_invalid,
+        ))? {
+            CacheResponse::Int(n) => Ok(Some(n)),
+            CacheResponse::Nil => Ok(None),
+            resp => Err(unexpected("aggregate", resp)),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), CacheError> {
+        match self.call(CacheCommand::Save)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("save", resp)),
+        }
+    }
+
+    pub fn bgsave(&self) -> Result<(), CacheError> {
+        match self.call(CacheCommand::BgSave)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("bgsave", resp)),
+        }
+    }
+
+    /// Дамп в файл на стороне сервера; `format` — `"binary"` или `"json"`.
+    pub fn export(&self, path: &str, format: &str) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::Export(format.to_string(), path.to_string()))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("export", resp)),
+        }
+    }
+
+    pub fn import_dump(&self, path: &str, replace: bool) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::Import(path.to_string(), replace))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("import_dump", resp)),
+        }
+    }
+
+    pub fn set_read_only(&self, read_only: bool) -> Result<(), CacheError> {
+        let mut cmd = CacheCommand::SetReadOnly(read_only);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match self.call(cmd)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("set_read_only", resp)),
+        }
+    }
+
+    /// Сверка памяти сервера со снапшотом и WAL, см. `PersistentCore::verify`;
+    /// нужен `admin_token`.
+    pub fn verify(&self, values: bool, max_examples: u32) -> Result<VerifyReport, CacheError> {
+        let mut cmd = CacheCommand::Verify(values, max_examples);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        let map = self
+            .call(cmd)?
+            .into_map()
+            .map_err(|resp| unexpected("verify", resp))?;
+        VerifyReport::from_map(map)
+    }
+
+    /// Соединения сервера по возрастанию id; нужен `admin_token`.
+    pub fn client_list(&self) -> Result<Vec<ClientInfo>, CacheError> {
+        let mut cmd = CacheCommand::ClientList;
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        let map = self
+            .call(cmd)?
+            .into_map()
+            .map_err(|resp| unexpected("client_list", resp))?;
+        ClientInfo::list_from_map(map)
+    }
+
+    /// Закрывает соединение сервера по id из `client_list`; false — такого
+    /// нет. Нужен `admin_token`.
+    pub fn client_kill(&self, id: u64) -> Result<bool, CacheError> {
+        let mut cmd = CacheCommand::ClientKill(id);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match self.call(cmd)? {
+            CacheResponse::Int(n) => Ok(n > 0),
+            resp => Err(unexpected("client_kill", resp)),
+        }
+    }
+
+    /// Меняет настройку работающего сервера, см. `PersistentCore::config_set`;
+    /// нужен `admin_token`.
+    pub fn config_set(&self, name: &str, value: &str) -> Result<(), CacheError> {
+        let mut cmd = CacheCommand::ConfigSet(name.to_string(), value.to_string());
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match self.call(cmd)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("config_set", resp)),
+        }
+    }
+
+    /// Значения настроек сервера (`"*"` — всех); нужен `admin_token`.
+    pub fn config_get(&self, name: &str) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        let mut cmd = CacheCommand::ConfigGet(name.to_string());
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        self.call(cmd)?
+            .into_map()
+            .map_err(|resp| unexpected("config_get", resp))
+    }
+
+    /// Все действующие настройки сервера, см. `PersistentCore::config_dump`;
+    /// нужен `admin_token`.
+    pub fn config_dump(&self) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        let mut cmd = CacheCommand::ConfigDump;
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        self.call(cmd)?
+            .into_map()
+            .map_err(|resp| unexpected("config_dump", resp))
+    }
+
+    /// Пользователи ACL сервера по имени, без хешей паролей; нужен
+    /// `admin_token` или пользователь с `admin`.
+    pub fn acl_list(&self) -> Result<Vec<AclUser>, CacheError> {
+        let mut cmd = CacheCommand::AclList;
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match self.call(cmd)? {
+            CacheResponse::AclUsers(users) => Ok(users),
+            resp => Err(unexpected("acl_list", resp)),
+        }
+    }
+
+    /// Заводит или заменяет пользователя ACL (`password_hash` — из
+    /// `hash_password`); сервер сохраняет таблицу в свой файл ACL. Нужен
+    /// `admin_token` или пользователь с `admin`.
+    pub fn acl_set_user(&self, user: &AclUser) -> Result<(), CacheError> {
+        let mut cmd = CacheCommand::AclSetUser(user.clone());
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match self.call(cmd)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("acl_set_user", resp)),
+        }
+    }
+
+    /// Удаляет пользователя ACL; false — такого не было. Его соединения
+    /// получают отказ со следующей команды.
+    pub fn acl_del_user(&self, name: &str) -> Result<bool, CacheError> {
+        let mut cmd = CacheCommand::AclDelUser(name.to_string());
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match self.call(cmd)? {
+            CacheResponse::Int(n) => Ok(n != 0),
+            resp => Err(unexpected("acl_del_user", resp)),
+        }
+    }
+
+    /// Возвращает число подписчиков, получивших сообщение.
+    pub fn publish(&self, channel: &str, data: &[u8]) -> Result<i64, CacheError> {
+        match self.call(CacheCommand::Publish(channel.to_string(), data.to_vec()))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("publish", resp)),
+        }
+    }
+
+    /// Значения от сервера версии 1 приходят строками (`ResponseValue::Str`).
+    pub fn wal_stats(&self) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        self.call(CacheCommand::WalStats)?
+            .into_map()
+            .map_err(|resp| unexpected("wal_stats", resp))
+    }
+
+    /// Значения от сервера версии 1 приходят строками (`ResponseValue::Str`).
+    pub fn info(&self) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        self.call(CacheCommand::Info)?
+            .into_map()
+            .map_err(|resp| unexpected("info", resp))
+    }
+
+    /// Попадания и промахи чтений на сервере, всего и по префиксам, см.
+    /// `PersistentCore::hit_stats`.
+    pub fn stats(&self) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        self.call(CacheCommand::Stats)?
+            .into_map()
+            .map_err(|resp| unexpected("stats", resp))
+    }
+}
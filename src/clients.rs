@@ -0,0 +1,666 @@
+//! Реестр соединений сервера: `ClientList` показывает, кто подключён и что
+//! делает, `ClientKill` закрывает соединение по id.
+//!
+//! С `PersistentCore::set_idle_timeout` поток-уборщик закрывает соединения,
+//! по которым дольше таймаута не прошло ни байта и на которых не выполняется
+//! ни одна команда. Команда считается выполняемой, пока не отправлен ответ,
+//! так что ждущий `BGet` соединение не теряет, а подписка, Watch и поток
+//! реплики — никогда: у них свои heartbeat'ы.
+//!
+//! С `PersistentCore::set_rate_limits` команды соединения (и всех соединений
+//! с одного IP по TCP) проходят через token bucket: сверх лимита команда не
+//! выполняется и получает ошибку "rate limited, retry after Nms", а
+//! соединение остаётся открытым.
+//!
+//! Соединение с ролью `PeerRole::ReadOnly` (Unix-сокет, см.
+//! `PersistentCore::set_peer_access`) не проходит `admit` с командами
+//! записи, Save/BgSave и Admin.
+//!
+//! Пользователь ACL, под которым соединение вошло командой `Auth` (см.
+//! acl.rs), хранится здесь же, в `Session::user`.
+//!
+//! Таймаут и лимиты меняются на ходу (`CacheCommand::ConfigSet`): уборщик
+//! берёт таймаут на каждом обходе, а лимиты читаются на каждой команде, в
+//! том числе у уже открытых соединений.
+
+use crate::core::{now_ms, now_unix_ms};
+use crate::error::CacheError;
+use crate::mux::Split;
+use crate::{CacheCommand, PeerRole, ResponseValue};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{IoSlice, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Сколько IP помнит лимит по адресу; сверх этого забываются давно не
+/// присылавшие команд и без открытых соединений.
+const MAX_IP_BUCKETS: usize = 10_000;
+
+/// Token bucket на `rate` команд в секунду с запасом на секунду вперёд.
+struct Bucket {
+    // 0 — без лимита
+    rate: f64,
+    tokens: f64,
+    at: Instant,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Self {
+            rate: 0.0,
+            tokens: 0.0,
+            at: Instant::now(),
+        }
+    }
+
+    // мс до следующего жетона при лимите `rate`, если сейчас его нет. Лимит,
+    // включённый только что, начинает с полного запаса, а уменьшенный
+    // урезает запас
+    fn wait(&mut self, now: Instant, rate: u32) -> Option<u64> {
+        let rate = rate as f64;
+        if rate != self.rate {
+            self.tokens = if self.rate == 0.0 {
+                rate
+            } else {
+                self.tokens.min(rate)
+            };
+            self.rate = rate;
+        }
+        if self.rate == 0.0 {
+            return None;
+        }
+        let elapsed = now.saturating_duration_since(self.at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.at = now;
+        (self.tokens < 1.0)
+            .then(|| ((1.0 - self.tokens) / self.rate * 1000.0).ceil().max(1.0) as u64)
+    }
+}
+
+fn lock_bucket(bucket: &Mutex<Bucket>) -> std::sync::MutexGuard<'_, Bucket> {
+    bucket.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Соединение в реестре; счётчики обновляет обслуживающий его поток.
+pub(crate) struct Session {
+    id: u64,
+    peer: String,
+    connected_at: u64,
+    commands: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    // unix-время в мс последней команды, 0 — команд ещё не было
+    last_at: AtomicU64,
+    last_command: Mutex<&'static str>,
+    // now_ms() последнего прочитанного или записанного байта
+    last_active: AtomicU64,
+    // команды без отправленного ответа
+    busy: AtomicUsize,
+    // закрыто уборщиком; считается в reaped_connections один раз
+    reaped: AtomicBool,
+    // закрывает сокет из чужого потока; поток соединения увидит обрыв
+    kill: Box<dyn Fn() + Send + Sync>,
+    // лимиты соединения и его IP, см. `Clients::admit`; у Unix-сокета IP нет
+    conn_bucket: Mutex<Bucket>,
+    ip_bucket: Option<Arc<Mutex<Bucket>>>,
+    // команды, отклонённые лимитом
+    rate_limited: AtomicU64,
+    // роль собеседника Unix-сокета; TCP — всегда Full
+    role: PeerRole,
+    // trace id последней команды, см. `with_trace_id`
+    last_trace: Mutex<Option<String>>,
+    // пользователь ACL после удачного Auth
+    user: Mutex<Option<String>>,
+}
+
+impl Session {
+    /// Запоминает trace id принятой команды для `ClientList`.
+    pub(crate) fn traced(&self, trace: Option<&str>) {
+        let mut last = self.last_trace.lock().unwrap_or_else(|e| e.into_inner());
+        if last.as_deref() != trace {
+            *last = trace.map(str::to_string);
+        }
+    }
+
+    /// Пользователь ACL, под которым вошло соединение.
+    pub(crate) fn user(&self) -> Option<String> {
+        self.user.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn set_user(&self, user: String) {
+        *self.user.lock().unwrap_or_else(|e| e.into_inner()) = Some(user);
+    }
+
+    /// Учитывает принятую команду; пара к ней — `done` после ответа.
+    pub(crate) fn command(&self, cmd: &CacheCommand) {
+        self.busy.fetch_add(1, Ordering::Relaxed);
+        self.commands.fetch_add(1, Ordering::Relaxed);
+        self.last_at.store(now_unix_ms(), Ordering::Relaxed);
+        *self.last_command.lock().unwrap_or_else(|e| e.into_inner()) = cmd.name();
+    }
+
+    /// Ответ на команду отправлен (или отправить его не удалось).
+    pub(crate) fn done(&self) {
+        self.busy.fetch_sub(1, Ordering::Relaxed);
+        self.last_active.store(now_ms(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    fn active(&self) {
+        self.last_active.store(now_ms(), Ordering::Relaxed);
+    }
+
+    fn info(&self) -> ClientInfo {
+        let now = now_unix_ms();
+        let last_at = match self.last_at.load(Ordering::Relaxed) {
+            0 => self.connected_at,
+            at => at,
+        };
+        ClientInfo {
+            id: self.id,
+            peer: self.peer.clone(),
+            connected_at_ms: self.connected_at,
+            commands: self.commands.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            last_command: self
+                .last_command
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .to_string(),
+            idle_ms: now.saturating_sub(last_at),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            trace_id: self
+                .last_trace
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone()
+                .unwrap_or_default(),
+            user: self.user().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Clients {
+    // общий с уборщиком, который держит только Weak
+    registry: Arc<Registry>,
+    next_id: AtomicU64,
+    // соединений за всё время работы сервера
+    total: AtomicU64,
+    sweeper_started: AtomicBool,
+    // команд в секунду на соединение и на IP; 0 — без лимита
+    conn_rate: AtomicU32,
+    ip_rate: AtomicU32,
+    ip_buckets: Mutex<HashMap<IpAddr, Arc<Mutex<Bucket>>>>,
+    // команды, отклонённые лимитами, за всё время
+    rate_limited: AtomicU64,
+}
+
+#[derive(Default)]
+struct Registry {
+    by_id: Mutex<HashMap<u64, Arc<Session>>>,
+    // закрытых уборщиком
+    reaped: AtomicU64,
+    // в мс; 0 — не закрывать
+    idle_timeout: AtomicU64,
+}
+
+impl Registry {
+    fn idle_timeout(&self) -> Option<Duration> {
+        Some(self.idle_timeout.load(Ordering::Relaxed))
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis)
+    }
+
+    fn sessions(&self) -> Vec<Arc<Session>> {
+        self.by_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    // закрывает простаивающие дольше `timeout`
+    fn sweep(&self, timeout: Duration) {
+        let now = now_ms();
+        let timeout = timeout.as_millis() as u64;
+        for s in self.sessions() {
+            let idle = now.saturating_sub(s.last_active.load(Ordering::Relaxed));
+            if idle >= timeout
+                && s.busy.load(Ordering::Relaxed) == 0
+                && !s.reaped.swap(true, Ordering::Relaxed)
+            {
+                (s.kill)();
+                self.reaped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// как часто уборщик обходит реестр: точность срабатывания — половина таймаута
+fn sweep_interval(timeout: Duration) -> Duration {
+    (timeout / 2).clamp(Duration::from_millis(10), Duration::from_secs(1))
+}
+
+/// Пока жива, соединение числится в реестре; Drop убирает его, в том числе
+/// когда поток соединения паникует.
+pub(crate) struct Registration<'a> {
+    clients: &'a Clients,
+    pub(crate) session: Arc<Session>,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.clients
+            .registry
+            .by_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.session.id);
+    }
+}
+
+impl Clients {
+    /// Простаивающие дольше `timeout` соединения закрываются; None — никогда.
+    pub(crate) fn set_idle_timeout(&self, timeout: Option<Duration>) {
+        let ms = timeout.map_or(0, |t| t.as_millis().clamp(1, u64::MAX as u128) as u64);
+        self.registry.idle_timeout.store(ms, Ordering::Relaxed);
+        self.start_sweeper();
+    }
+
+    pub(crate) fn idle_timeout(&self) -> Option<Duration> {
+        self.registry.idle_timeout()
+    }
+
+    /// Команд в секунду на соединение и на IP клиента; None или 0 — без
+    /// лимита. Действует и на уже открытые соединения.
+    pub(crate) fn set_rate_limits(&self, per_conn: Option<u32>, per_ip: Option<u32>) {
+        self.conn_rate
+            .store(per_conn.unwrap_or(0), Ordering::Relaxed);
+        self.ip_rate.store(per_ip.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Лимиты на соединение и на IP; None — без лимита.
+    pub(crate) fn rate_limits(&self) -> (Option<u32>, Option<u32>) {
+        let get = |rate: &AtomicU32| Some(rate.load(Ordering::Relaxed)).filter(|&n| n > 0);
+        (get(&self.conn_rate), get(&self.ip_rate))
+    }
+
+    // общий bucket всех соединений с IP; у Unix-сокета IP нет. Заводится и
+    // без лимита: его могут включить, пока соединение открыто
+    fn ip_bucket(&self, peer: &str) -> Option<Arc<Mutex<Bucket>>> {
+        let ip = peer.parse::<SocketAddr>().ok()?.ip();
+        let mut buckets = self.ip_buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if !buckets.contains_key(&ip) && buckets.len() >= MAX_IP_BUCKETS {
+            // забытый IP начнёт с полного запаса, поэтому только без соединений
+            let stale = buckets
+                .iter()
+                .filter(|(_, b)| Arc::strong_count(b) == 1)
+                .min_by_key(|(_, b)| lock_bucket(b).at)
+                .map(|(ip, _)| *ip);
+            if let Some(stale) = stale {
+                buckets.remove(&stale);
+            }
+        }
+        Some(Arc::clone(
+            buckets
+                .entry(ip)
+                .or_insert_with(|| Arc::new(Mutex::new(Bucket::new()))),
+        ))
+    }
+
+    /// Берёт жетон у соединения и у его IP. `Hello` лимитом не считается:
+    /// без него соединение не договорится о протоколе.
+    pub(crate) fn admit(&self, session: &Session, cmd: &CacheCommand) -> Result<(), CacheError> {
+        if matches!(cmd, CacheCommand::Hello(_)) {
+            return Ok(());
+        }
+        if session.role == PeerRole::ReadOnly
+            && (cmd.is_write()
+                || matches!(
+                    cmd,
+                    CacheCommand::Save | CacheCommand::BgSave | CacheCommand::Admin(..)
+                ))
+        {
+            return Err(CacheError::ReadOnly("connection".into()));
+        }
+        let conn_rate = self.conn_rate.load(Ordering::Relaxed);
+        let ip_rate = self.ip_rate.load(Ordering::Relaxed);
+        let now = Instant::now();
+        let mut conn = lock_bucket(&session.conn_bucket);
+        let mut ip = session.ip_bucket.as_deref().map(lock_bucket);
+        let wait = [
+            Some((&mut *conn, conn_rate)),
+            ip.as_deref_mut().map(|b| (b, ip_rate)),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|(b, rate)| b.wait(now, rate))
+        .max();
+        if let Some(ms) = wait {
+            session.rate_limited.fetch_add(1, Ordering::Relaxed);
+            self.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return Err(CacheError::RateLimited(ms));
+        }
+        for b in [Some(&mut *conn), ip.as_deref_mut()].into_iter().flatten() {
+            if b.rate > 0.0 {
+                b.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    // уборщик запускается с первым соединением и не держит реестр: после
+    // удаления ядра он завершается
+    fn start_sweeper(&self) {
+        let Some(timeout) = self.registry.idle_timeout() else {
+            return;
+        };
+        if self.sweeper_started.load(Ordering::Relaxed)
+            || self.sweeper_started.swap(true, Ordering::SeqCst)
+        {
+            return;
+        }
+        let weak: Weak<Registry> = Arc::downgrade(&self.registry);
+        let res = thread::Builder::new()
+            .name("tiny-mp-cache-reaper".into())
+            .spawn(move || {
+                let mut interval = sweep_interval(timeout);
+                loop {
+                    thread::sleep(interval);
+                    let Some(registry) = weak.upgrade() else {
+                        return;
+                    };
+                    // таймаут могли поменять или выключить на ходу
+                    interval = match registry.idle_timeout() {
+                        Some(timeout) => {
+                            registry.sweep(timeout);
+                            sweep_interval(timeout)
+                        }
+                        None => Duration::from_secs(1),
+                    };
+                }
+            });
+        if let Err(e) = res {
+            eprintln!("spawn reaper thread: {}", e);
+            self.sweeper_started.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Заносит соединение в реестр. Дальше его нужно читать и писать через
+    /// возвращённый `Tracked`, иначе байты не будут учтены.
+    pub(crate) fn register<S: Split>(
+        &self,
+        stream: &S,
+        role: PeerRole,
+    ) -> Result<(Registration<'_>, Tracked<S>), CacheError> {
+        let dup = || {
+            stream
+                .try_clone()
+                .map_err(|e| CacheError::Network(e.to_string()))
+        };
+        let killer = dup()?;
+        let session = Arc::new(Session {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            peer: stream.peer(),
+            connected_at: now_unix_ms(),
+            commands: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            last_at: AtomicU64::new(0),
+            last_command: Mutex::new("none"),
+            last_active: AtomicU64::new(now_ms()),
+            busy: AtomicUsize::new(0),
+            reaped: AtomicBool::new(false),
+            kill: Box::new(move || killer.shutdown()),
+            conn_bucket: Mutex::new(Bucket::new()),
+            ip_bucket: self.ip_bucket(&stream.peer()),
+            rate_limited: AtomicU64::new(0),
+            role,
+            last_trace: Mutex::new(None),
+            user: Mutex::new(None),
+        });
+        let tracked = Tracked {
+            inner: dup()?,
+            session: Arc::clone(&session),
+        };
+        self.total.fetch_add(1, Ordering::Relaxed);
+        self.start_sweeper();
+        self.registry
+            .by_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(session.id, Arc::clone(&session));
+        Ok((
+            Registration {
+                clients: self,
+                session,
+            },
+            tracked,
+        ))
+    }
+
+    /// Соединения по возрастанию id.
+    pub fn list(&self) -> Vec<ClientInfo> {
+        let sessions = self.registry.sessions();
+        let mut list: Vec<ClientInfo> = sessions.iter().map(|s| s.info()).collect();
+        list.sort_unstable_by_key(|c| c.id);
+        list
+    }
+
+    /// Закрывает соединение; false — такого нет. Из реестра оно пропадает,
+    /// когда его поток заметит обрыв и завершится.
+    pub fn kill(&self, id: u64) -> bool {
+        let session = self
+            .registry
+            .by_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&id)
+            .cloned();
+        match session {
+            Some(s) => {
+                (s.kill)();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn info_fields(&self) -> Vec<(&'static str, ResponseValue)> {
+        let connected = self
+            .registry
+            .by_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len();
+        vec![
+            ("connected_clients", connected.into()),
+            (
+                "total_connections",
+                self.total.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "reaped_connections",
+                self.registry.reaped.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "rate_limited_commands",
+                self.rate_limited.load(Ordering::Relaxed).into(),
+            ),
+        ]
+    }
+}
+
+/// Сокет соединения, считающий байты в своей `Session`. Клон (`try_clone`)
+/// считает в ту же.
+pub(crate) struct Tracked<S> {
+    inner: S,
+    session: Arc<Session>,
+}
+
+impl<S: Read> Read for Tracked<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.session.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+        self.session.active();
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Tracked<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.session
+            .bytes_out
+            .fetch_add(n as u64, Ordering::Relaxed);
+        self.session.active();
+        Ok(n)
+    }
+
+    // без него writev кадров протокола 2 распался бы на отдельные write
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        self.session
+            .bytes_out
+            .fetch_add(n as u64, Ordering::Relaxed);
+        self.session.active();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Split> Split for Tracked<S> {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Tracked {
+            inner: self.inner.try_clone()?,
+            session: Arc::clone(&self.session),
+        })
+    }
+
+    fn peer(&self) -> String {
+        self.inner.peer()
+    }
+
+    fn shutdown(&self) {
+        self.inner.shutdown()
+    }
+}
+
+/// Соединение из `ClientList`. Время — unix-время в миллисекундах.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientInfo {
+    pub id: u64,
+    // адрес клиента; у Unix-сокета — "unix"
+    pub peer: String,
+    pub connected_at_ms: u64,
+    // принятые команды; потоковые режимы (Subscribe, Watch, ReplSync) — одна
+    pub commands: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    // имя последней команды без аргументов, "none" — команд не было
+    pub last_command: String,
+    // мс с последней команды (или с подключения)
+    pub idle_ms: u64,
+    // команды, отклонённые лимитом частоты
+    pub rate_limited: u64,
+    // trace id последней команды, "" — без него
+    pub trace_id: String,
+    // пользователь ACL, "" — соединение не входило
+    pub user: String,
+}
+
+impl ClientInfo {
+    fn into_fields(self) -> Vec<(String, ResponseValue)> {
+        vec![
+            ("peer".into(), self.peer.into()),
+            ("connected_at_ms".into(), self.connected_at_ms.into()),
+            ("commands".into(), self.commands.into()),
+            ("bytes_in".into(), self.bytes_in.into()),
+            ("bytes_out".into(), self.bytes_out.into()),
+            ("last_command".into(), self.last_command.into()),
+            ("idle_ms".into(), self.idle_ms.into()),
+            ("rate_limited".into(), self.rate_limited.into()),
+            ("trace_id".into(), self.trace_id.into()),
+            ("user".into(), self.user.into()),
+        ]
+    }
+
+    /// Ответ `ClientList`: по записи `Nested` на соединение, ключ — id.
+    pub(crate) fn list_into_map(list: Vec<ClientInfo>) -> Vec<(String, ResponseValue)> {
+        list.into_iter()
+            .map(|c| (c.id.to_string(), ResponseValue::Nested(c.into_fields())))
+            .collect()
+    }
+
+    /// Список из `Map`, а от соединения версии 1 — из Info с полями
+    /// "<id>:<поле>" и числами строками.
+    pub(crate) fn list_from_map(
+        map: Vec<(String, ResponseValue)>,
+    ) -> Result<Vec<ClientInfo>, CacheError> {
+        let mut by_id: BTreeMap<u64, ClientInfo> = BTreeMap::new();
+        for (name, value) in map {
+            let bad =
+                || CacheError::Internal(format!("bad client list field {}: {:?}", name, value));
+            let (id, fields) = match (&value, name.split_once(':')) {
+                (ResponseValue::Nested(fields), None) => (name.as_str(), fields.clone()),
+                (_, Some((id, field))) => (id, vec![(field.to_string(), value.clone())]),
+                _ => return Err(bad()),
+            };
+            let id: u64 = id.parse().map_err(|_| bad())?;
+            let entry = by_id.entry(id).or_insert_with(|| ClientInfo {
+                id,
+                ..Default::default()
+            });
+            for (field, value) in fields {
+                let slot = match field.as_str() {
+                    "peer" => {
+                        entry.peer = value.as_str().ok_or_else(bad)?.to_string();
+                        continue;
+                    }
+                    "last_command" => {
+                        entry.last_command = value.as_str().ok_or_else(bad)?.to_string();
+                        continue;
+                    }
+                    "trace_id" => {
+                        entry.trace_id = value.as_str().ok_or_else(bad)?.to_string();
+                        continue;
+                    }
+                    "user" => {
+                        entry.user = value.as_str().ok_or_else(bad)?.to_string();
+                        continue;
+                    }
+                    "connected_at_ms" => &mut entry.connected_at_ms,
+                    "commands" => &mut entry.commands,
+                    "bytes_in" => &mut entry.bytes_in,
+                    "bytes_out" => &mut entry.bytes_out,
+                    "idle_ms" => &mut entry.idle_ms,
+                    "rate_limited" => &mut entry.rate_limited,
+                    _ => continue,
+                };
+                *slot = match &value {
+                    ResponseValue::Int(n) => u64::try_from(*n).ok(),
+                    ResponseValue::Str(s) => s.parse().ok(),
+                    _ => None,
+                }
+                .ok_or_else(bad)?;
+            }
+        }
+        Ok(by_id.into_values().collect())
+    }
+}
@@ -0,0 +1,146 @@
+//! Настройки, которые меняются без перезапуска: `CacheCommand::ConfigSet` и
+//! `ConfigGet`, только внутри `Admin`. Имена — как у аргументов `serve()`,
+//! значения приходят строкой. Аргументы, которые действуют только при
+//! запуске (адрес, WAL, политика ключей), отклоняются с объяснением: молча
+//! принятая и не применённая настройка хуже ошибки. Файла конфигурации у
+//! сервера нет, так что перечитывать по SIGHUP нечего.
+
+use crate::error::CacheError;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting {
+    MaxBitOffset,
+    IdleTimeoutSecs,
+    TombstoneTtlSecs,
+    DedupMinSize,
+    RateLimitPerConn,
+    RateLimitPerIp,
+    VerifyValues,
+    MaxResponseSize,
+    SlowlogMs,
+}
+
+impl Setting {
+    /// В порядке ответа `ConfigGet("*")`.
+    pub const ALL: [Setting; 9] = [
+        Setting::MaxBitOffset,
+        Setting::IdleTimeoutSecs,
+        Setting::TombstoneTtlSecs,
+        Setting::DedupMinSize,
+        Setting::RateLimitPerConn,
+        Setting::RateLimitPerIp,
+        Setting::VerifyValues,
+        Setting::MaxResponseSize,
+        Setting::SlowlogMs,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Setting::MaxBitOffset => "max_bit_offset",
+            Setting::IdleTimeoutSecs => "idle_timeout_secs",
+            Setting::TombstoneTtlSecs => "tombstone_ttl_secs",
+            Setting::DedupMinSize => "dedup_min_size",
+            Setting::RateLimitPerConn => "rate_limit_per_conn",
+            Setting::RateLimitPerIp => "rate_limit_per_ip",
+            Setting::VerifyValues => "verify_values",
+            Setting::MaxResponseSize => "max_response_size",
+            Setting::SlowlogMs => "slowlog_ms",
+        }
+    }
+
+    /// Разбирает значение для `ConfigSet`: число, у выключаемых — ещё "none"
+    /// (то же, что 0), у `verify_values` — true/false (1/0).
+    pub(crate) fn parse(self, value: &str) -> Result<u64, CacheError> {
+        let value = value.trim();
+        let bad = |expected: &str| {
+            CacheError::Unsupported(format!(
+                "{} expects {}, got {:?}",
+                self.as_str(),
+                expected,
+                value
+            ))
+        };
+        match self {
+            Setting::VerifyValues => match value {
+                "true" | "1" | "on" => Ok(1),
+                "false" | "0" | "off" => Ok(0),
+                _ => Err(bad("true or false")),
+            },
+            Setting::MaxBitOffset => value.parse().map_err(|_| bad("a non-negative integer")),
+            Setting::MaxResponseSize => match value.parse() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(bad("a positive number of bytes")),
+            },
+            Setting::RateLimitPerConn | Setting::RateLimitPerIp => match value {
+                "none" => Ok(0),
+                v => v
+                    .parse::<u32>()
+                    .map(u64::from)
+                    .map_err(|_| bad("commands per second up to 4294967295 or \"none\"")),
+            },
+            Setting::IdleTimeoutSecs
+            | Setting::TombstoneTtlSecs
+            | Setting::DedupMinSize
+            | Setting::SlowlogMs => match value {
+                "none" => Ok(0),
+                v => v
+                    .parse()
+                    .map_err(|_| bad("a non-negative integer or \"none\"")),
+            },
+        }
+    }
+}
+
+// почему аргумент serve() не меняется на ходу; None — такого аргумента нет
+fn fixed_reason(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "port" | "path" => "the listener is bound at start",
+        "allow_uids" | "allow_gids" | "readonly_uids" | "readonly_gids" => {
+            "the socket access list is fixed at start"
+        }
+        "wal_dir" | "name" | "persistence" | "wal_key" | "fsync" | "wal_segment_size"
+        | "compact_after" | "replay_threads" => "the WAL is opened with it at start",
+        "replicate_from" => "replication is set up at start",
+        "admin_token" => "it guards ConfigSet itself",
+        "acl_file" => "ACL users change through AclSetUser and AclDelUser",
+        "key_normalization" | "case_insensitive_keys" => {
+            "keys already stored were checked against it at start"
+        }
+        "preload" | "preload_file" => "it runs once before the listener opens",
+        "on_ready" | "on_error" | "on_connection" | "on_write" | "on_write_prefix"
+        | "on_write_queue" | "on_write_overflow" => "callbacks are registered at start",
+        "loader" | "loader_ttl_secs" | "loader_threads" => "the loader pool starts with the server",
+        _ => return None,
+    })
+}
+
+impl FromStr for Setting {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(setting) = Setting::ALL.into_iter().find(|v| v.as_str() == s) {
+            return Ok(setting);
+        }
+        let msg = match s {
+            // меняются на ходу, но своими командами
+            "read_only" => "read_only changes through SetReadOnly, not ConfigSet".to_string(),
+            "history_depth" => {
+                "history_depth changes through SetHistoryDepth with an empty prefix, not ConfigSet"
+                    .to_string()
+            }
+            _ => match fixed_reason(s) {
+                Some(reason) => format!(
+                    "{} cannot change at runtime ({}); restart the server with the new value",
+                    s, reason
+                ),
+                None => format!(
+                    "unknown setting {:?}, expected one of {}",
+                    s,
+                    Setting::ALL.map(|v| v.as_str()).join(", ")
+                ),
+            },
+        };
+        Err(CacheError::Unsupported(msg))
+    }
+}
@@ -1,9 +1,289 @@
-use dashmap::DashMap;
-use std::sync::Arc;
+use crate::aggregate::{AggOp, Aggregate};
+use crate::bits;
+use crate::bloom::BloomFilter;
+use crate::dedup::{Dedup, DedupStats, Value};
+use crate::encoding::{check_raw, raw_bytes, same_value};
+use crate::error::CacheError;
+use crate::history::{History, HistoryStats};
+use crate::{KeySample, ScanPage};
+use dashmap::mapref::entry::Entry;
+use dashmap::mapref::one::Ref;
+use dashmap::{DashMap, SharedValue};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::ops::{DerefMut, RangeInclusive};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-#[derive(Clone, Default)]
+// миллисекунды от первого обращения к часам в процессе; монотонны, в отличие
+// от системного времени
+pub(crate) fn now_ms() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Unix-время в миллисекундах: сроки ключей абсолютные, чтобы пережить
+/// рестарт и совпадать у всех процессов на машине.
+pub(crate) fn now_unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// xorshift64*: прогону benchmark и Sample хватает, зависимостей не нужно;
+// seed не должен быть 0
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // случайное в 0..n, n > 0; сдвиг у n много меньше 2^64 незаметен
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Крайний срок команды, пришедший в кадре протокола 2 (см. `mux`). Долгие
+/// обходы карты сверяются с ним раз в `Deadline::EVERY` ключей и бросают
+/// работу с `CacheError::DeadlineExceeded`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Без срока: команда выполняется до конца.
+    pub const NONE: Deadline = Deadline(None);
+    // ключей между двумя взглядами на часы
+    const EVERY: usize = 1024;
+
+    pub fn after(budget: Duration) -> Self {
+        Deadline(Instant::now().checked_add(budget))
+    }
+
+    pub fn check(&self) -> Result<(), CacheError> {
+        match self.0 {
+            Some(at) if Instant::now() >= at => Err(CacheError::DeadlineExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    /// Точка отмены на `step`-м шаге обхода.
+    pub(crate) fn checkpoint(&self, step: usize) -> Result<(), CacheError> {
+        if step.is_multiple_of(Self::EVERY) {
+            self.check()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Сколько групп возвращает `CacheCore::prefix_stats`.
+pub const MAX_PREFIX_GROUPS: usize = 10_000;
+
+/// Больше ключей `CacheCore::sample` не возвращает.
+pub const MAX_SAMPLE: usize = 10_000;
+
+// промахов по пустым бакетам шарда, после которых sample берёт ближайший
+// занятый
+const MAX_PROBES: usize = 32;
+
+/// Ответ `CacheCore::prefix_stats`: (число ключей, байт значений) по группам
+/// и в сумме по группам сверх предела, если такие были.
+#[derive(Debug, Default)]
+pub struct PrefixStats {
+    pub groups: HashMap<String, (u64, u64)>,
+    pub other: Option<(u64, u64)>,
+}
+
+/// Условие удаления для `CacheCore::remove_if` и `bury`; проверяется под
+/// блокировкой шарда.
+#[derive(Clone, Copy, Debug)]
+pub enum RemoveIf<'a> {
+    Always,
+    /// у ключа есть этот тег
+    Tagged(&'a str),
+    /// значение ключа ровно такое (DelIfEquals)
+    Equals(&'a [u8]),
+}
+
+impl RemoveIf<'_> {
+    fn holds(self, slot: &Slot) -> bool {
+        match self {
+            RemoveIf::Always => true,
+            RemoveIf::Tagged(tag) => slot.tags.iter().any(|t| t == tag),
+            RemoveIf::Equals(value) => same_value(&slot.value, value),
+        }
+    }
+}
+
+/// Значение ключа (в самом слоте, своё или общее, см. `dedup::Value`) и
+/// его метаданные: время последнего обращения (для Touch/IdleTime), срок
+/// жизни, теги, время и номер последней записи.
+struct Slot {
+    value: Value,
+    accessed: AtomicU64,
+    // unix-время в мс, с которого ключа нет; None — бессрочный
+    expires_at: Option<u64>,
+    // без повторов; у большинства ключей пусто
+    tags: Box<[String]>,
+    // unix-время в мс последней записи значения; 0 — неизвестно
+    written_at: u64,
+    // сколько раз значение записывали с создания ключа
+    version: u64,
+    // crc32 значения, см. CacheCore::set_verify_values; None — не считается
+    crc: Option<u32>,
+}
+
+impl Slot {
+    fn new(value: Value) -> Self {
+        Self {
+            value,
+            accessed: AtomicU64::new(now_ms()),
+            expires_at: None,
+            tags: Box::default(),
+            written_at: now_unix_ms(),
+            version: 1,
+            crc: None,
+        }
+    }
+
+    // значение на месте только что изменили
+    fn rewritten(&mut self) {
+        self.written_at = now_unix_ms();
+        self.version += 1;
+        if self.crc.is_some() {
+            self.crc = Some(crc32fast::hash(&self.value));
+        }
+    }
+
+    fn touch(&self) {
+        self.accessed.store(now_ms(), Ordering::Relaxed);
+    }
+
+    fn touched(&self) -> &[u8] {
+        self.touch();
+        &self.value
+    }
+
+    fn expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= now_unix_ms())
+    }
+
+    // (now_ms, now_unix_ms) — часы на момент выборки
+    fn sample(&self, key: &str, (now, now_unix): (u64, u64)) -> KeySample {
+        KeySample {
+            key: key.to_string(),
+            size: self.value.len() as u64,
+            idle_ms: now.saturating_sub(self.accessed.load(Ordering::Relaxed)),
+            ttl_ms: self.expires_at.map(|at| at.saturating_sub(now_unix).max(1)),
+            age_ms: Some(self.written_at)
+                .filter(|&at| at > 0)
+                .map(|at| now_unix.saturating_sub(at)),
+        }
+    }
+}
+
+// занятый бакет шарда наугад: до MAX_PROBES случайных бакетов, потом
+// ближайший занятый за последним; None — шард пуст
+fn random_entry<'a, S>(
+    shard: &'a hashbrown::HashMap<String, SharedValue<Slot>, S>,
+    rng: &mut Rng,
+) -> Option<(&'a String, &'a Slot)> {
+    if shard.is_empty() {
+        return None;
+    }
+    let table = shard.raw_table();
+    let buckets = table.buckets();
+    let mut at = rng.below(buckets as u64) as usize;
+    let mut probes = 0;
+    loop {
+        // SAFETY: at < buckets, а таблицу под read-локом шарда никто не
+        // меняет; занятый бакет хранит инициализированную пару, ссылка на
+        // неё живёт не дольше заимствования шарда
+        unsafe {
+            if table.is_bucket_full(at) {
+                let (key, slot) = table.bucket(at).as_ref();
+                return Some((key, slot.get()));
+            }
+        }
+        probes += 1;
+        at = if probes < MAX_PROBES {
+            rng.below(buckets as u64) as usize
+        } else {
+            (at + 1) % buckets
+        };
+    }
+}
+
+/// Удалённое значение, которое до `purge_at` можно вернуть `undelete`, см.
+/// `PersistentCore::set_tombstone_ttl`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Tombstone {
+    pub value: Vec<u8>,
+    // срок ключа на момент удаления, unix-время в мс
+    pub expires_at: Option<u64>,
+    pub tags: Vec<String>,
+    // unix-время в мс, после которого надгробие убирается
+    pub purge_at: u64,
+}
+
+impl Tombstone {
+    fn bytes(&self, key: &str) -> usize {
+        key.len() + self.value.len() + self.tags.iter().map(String::len).sum::<usize>()
+    }
+}
+
+/// Истёкший ключ сразу перестаёт быть виден всем операциям, а из карты его
+/// убирает `take_expired` (см. `PersistentCore::expire_due`).
+#[derive(Clone)]
 pub struct CacheCore {
-    inner: Arc<DashMap<String, Vec<u8>>>,
+    inner: Arc<DashMap<String, Slot>>,
+    // (срок, ключ) ключей со сроком. Перезапись ключа отсюда не удаляет:
+    // перед удалением из карты срок сверяется со слотом
+    expiries: Arc<Mutex<BTreeSet<(u64, String)>>>,
+    // был ли хоть один ключ со сроком или надгробие (у него тоже срок);
+    // дальше не сбрасывается
+    any_expiry: Arc<AtomicBool>,
+    // тег -> ключи с ним. Меняется под блокировкой шарда ключа вместе со
+    // слотом; сам индекс блокируется после шарда, не наоборот
+    tags: Arc<Mutex<HashMap<String, HashSet<String>>>>,
+    // удалённые ключи, которые ещё можно вернуть. Не видны ни Get, ни Keys,
+    // ни Len; блокируется после шарда ключа, как и индекс тегов
+    tombstones: Arc<Mutex<HashMap<String, Tombstone>>>,
+    // ближайший purge_at надгробий; u64::MAX — надгробий нет
+    next_purge: Arc<AtomicU64>,
+    // общие значения; слоты отпускают их сами при удалении
+    dedup: Arc<Dedup>,
+    // прежние значения ключей; пишется под блокировкой шарда, после неё
+    history: Arc<History>,
+    // считать ли crc32 новых значений, см. set_verify_values
+    verify_values: Arc<AtomicBool>,
+    // чтения, на которых значение не сошлось со своим crc32
+    checksum_failures: Arc<AtomicU64>,
+}
+
+impl Default for CacheCore {
+    fn default() -> Self {
+        Self {
+            inner: Arc::default(),
+            expiries: Arc::default(),
+            any_expiry: Arc::default(),
+            tags: Arc::default(),
+            tombstones: Arc::default(),
+            next_purge: Arc::new(AtomicU64::new(u64::MAX)),
+            dedup: Arc::default(),
+            history: Arc::default(),
+            verify_values: Arc::default(),
+            checksum_failures: Arc::default(),
+        }
+    }
 }
 
 impl CacheCore {
@@ -11,31 +291,1149 @@ impl CacheCore {
         Self::default()
     }
 
+    fn slot(&self, value: Vec<u8>) -> Slot {
+        let mut slot = Slot::new(self.dedup.intern(value));
+        if self.verify_values.load(Ordering::Relaxed) {
+            slot.crc = Some(crc32fast::hash(&slot.value));
+        }
+        slot
+    }
+
+    /// Хранить ли рядом со значениями их crc32 и сверять его при `get_checked`
+    /// и `verify_value`; суммы уже записанных значений считаются сразу.
+    /// Изменения на месте (SetBit, Update) пересчитывают сумму. Выключение
+    /// убирает суммы.
+    pub fn set_verify_values(&self, on: bool) {
+        self.verify_values.store(on, Ordering::Relaxed);
+        for mut e in self.inner.iter_mut() {
+            let slot = e.value_mut();
+            slot.crc = on.then(|| crc32fast::hash(&slot.value));
+        }
+    }
+
+    pub fn verify_values(&self) -> bool {
+        self.verify_values.load(Ordering::Relaxed)
+    }
+
+    /// Сколько раз чтение нашло значение, не совпавшее со своим crc32.
+    pub fn checksum_failures(&self) -> u64 {
+        self.checksum_failures.load(Ordering::Relaxed)
+    }
+
+    /// Меняет байт значения на месте в обход crc32 — порча, которую ловит
+    /// `set_verify_values`; только для тестов. `false` — ключа нет или
+    /// значение пустое.
+    #[doc(hidden)]
+    pub fn corrupt_value(&self, key: &str) -> bool {
+        self.inner.get_mut(key).is_some_and(|mut s| {
+            let flipped = s.value.make_mut().first_mut().map(|b| *b ^= 1).is_some();
+            s.value.shrink();
+            flipped
+        })
+    }
+
+    // значение слота совпадает со своей суммой (или суммы нет)
+    fn check(&self, key: &str, slot: &Slot) -> Result<(), CacheError> {
+        match slot.crc {
+            Some(crc) if crc32fast::hash(&slot.value) != crc => {
+                self.checksum_failures.fetch_add(1, Ordering::Relaxed);
+                Err(CacheError::Checksum(key.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Значения не короче `min_size` байт хранятся по одному экземпляру на
+    /// одинаковое содержимое; уже записанные значения тоже объединяются.
+    /// `None` выключает дедупликацию для новых записей.
+    pub fn set_dedup_min_size(&self, min_size: Option<usize>) {
+        self.dedup.set_min_size(min_size);
+        if min_size.is_none() {
+            return;
+        }
+        for mut e in self.inner.iter_mut() {
+            self.dedup.reintern(&mut e.value_mut().value);
+        }
+    }
+
+    pub fn dedup_min_size(&self) -> Option<usize> {
+        self.dedup.threshold()
+    }
+
+    pub(crate) fn dedup_stats(&self) -> DedupStats {
+        self.dedup.stats()
+    }
+
+    /// Сколько прежних значений помнить у ключей на `prefix`; из нескольких
+    /// подходящих префиксов действует самый длинный.
+    pub fn set_history_depth(&self, prefix: String, depth: usize) {
+        self.history.set_depth(prefix, depth);
+    }
+
+    /// Глубина истории по умолчанию — правило пустого префикса.
+    pub(crate) fn default_history_depth(&self) -> usize {
+        self.history.depth("")
+    }
+
+    /// Прежние значения ключа от старых к новым с unix-временем в мс, когда
+    /// их заменили или удалили.
+    pub fn history(&self, key: &str) -> Vec<(u64, Vec<u8>)> {
+        self.history.get(key)
+    }
+
+    pub(crate) fn history_stats(&self) -> HistoryStats {
+        self.history.stats()
+    }
+
+    // живой слот ключа
+    fn live(&self, key: &str) -> Option<Ref<'_, String, Slot>> {
+        self.inner.get(key).filter(|s| !s.expired())
+    }
+
+    // entry, где истёкший ключ уже удалён и выглядит отсутствующим; второй
+    // элемент — был ли удалён истёкший ключ
+    fn entry(&self, key: String) -> (Entry<'_, String, Slot>, bool) {
+        match self.inner.entry(key) {
+            Entry::Occupied(e) if e.get().expired() => {
+                let (key, slot) = e.remove_entry();
+                self.unindex_tags(&key, &slot.tags);
+                (self.inner.entry(key), true)
+            }
+            e => (e, false),
+        }
+    }
+
+    fn index_expiry(&self, key: &str, at: u64) {
+        self.expiries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((at, key.to_string()));
+        self.any_expiry.store(true, Ordering::Relaxed);
+    }
+
+    fn tag_index(&self) -> MutexGuard<'_, HashMap<String, HashSet<String>>> {
+        self.tags.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn index_tags(&self, key: &str, tags: &[String]) {
+        if tags.is_empty() {
+            return;
+        }
+        let mut index = self.tag_index();
+        for tag in tags {
+            index
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.to_string());
+        }
+    }
+
+    fn unindex_tags(&self, key: &str, tags: &[String]) {
+        if tags.is_empty() {
+            return;
+        }
+        let mut index = self.tag_index();
+        for tag in tags {
+            if let Some(keys) = index.get_mut(tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    index.remove(tag);
+                }
+            }
+        }
+    }
+
+    // вставка с заменой прежнего слота: теги переиндексируются под
+    // блокировкой шарда, а прежнее значение освобождается уже без неё —
+    // большое значение не держит шард, пока уходит память
+    fn put(&self, key: String, slot: Slot) {
+        let _old = match self.inner.entry(key) {
+            Entry::Occupied(mut e) => {
+                let mut slot = slot;
+                if !e.get().expired() {
+                    self.history.record(e.key(), &e.get().value);
+                    slot.version = e.get().version + 1;
+                }
+                self.unindex_tags(e.key(), &e.get().tags);
+                self.index_tags(e.key(), &slot.tags);
+                Some(e.insert(slot))
+            }
+            Entry::Vacant(e) => {
+                self.index_tags(e.key(), &slot.tags);
+                e.insert(slot);
+                None
+            }
+        };
+    }
+
+    /// Записывает значение; прежние срок и теги ключа сбрасываются.
     pub fn set(&self, key: String, value: Vec<u8>) {
-        self.inner.insert(key, value);
+        self.put(key, self.slot(value));
+    }
+
+    /// Записывает значение, которое истечёт в `expires_at` (unix-время в мс).
+    pub fn set_expiring(&self, key: String, value: Vec<u8>, expires_at: u64) {
+        self.set_tagged(key, value, Vec::new(), Some(expires_at));
+    }
+
+    /// Записывает значение с тегами (повторы отбрасываются) и сроком.
+    pub fn set_tagged(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        mut tags: Vec<String>,
+        expires_at: Option<u64>,
+    ) {
+        if let Some(at) = expires_at {
+            self.index_expiry(&key, at);
+        }
+        tags.sort_unstable();
+        tags.dedup();
+        let mut slot = self.slot(value);
+        slot.expires_at = expires_at;
+        slot.tags = tags.into_boxed_slice();
+        self.put(key, slot);
     }
 
     pub fn get(&self, key: &str) -> Option<Vec<u8>> {
-        self.inner.get(key).map(|v| v.value().clone())
+        self.live(key).map(|s| s.touched().to_vec())
+    }
+
+    /// `get` со сверкой crc32, если значения его хранят: несовпадение —
+    /// `CacheError::Checksum`.
+    pub fn get_checked(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        self.live(key)
+            .map(|s| self.check(key, &s).map(|()| s.touched().to_vec()))
+            .transpose()
+    }
+
+    /// Сверяет значение ключа с его crc32, не читая его наружу; ключа нет или
+    /// суммы нет — `Ok`. Для Pop: проверка до записи в WAL.
+    pub fn verify_value(&self, key: &str) -> Result<(), CacheError> {
+        self.live(key).map_or(Ok(()), |s| self.check(key, &s))
+    }
+
+    /// Значение, срок, время последней записи и номер версии ключа, см.
+    /// `Slot::written_at`.
+    pub fn get_with_meta(&self, key: &str) -> Option<(Vec<u8>, Option<u64>, u64, u64)> {
+        self.live(key)
+            .map(|s| (s.touched().to_vec(), s.expires_at, s.written_at, s.version))
+    }
+
+    /// Время последней записи и номер версии ключа без отметки об обращении.
+    pub fn write_meta(&self, key: &str) -> Option<(u64, u64)> {
+        self.live(key).map(|s| (s.written_at, s.version))
+    }
+
+    /// Ставит время последней записи и версию, восстановленные из снапшота
+    /// или WAL.
+    pub fn set_write_meta(&self, key: &str, written_at: u64, version: u64) {
+        if let Some(mut s) = self.inner.get_mut(key) {
+            s.written_at = written_at;
+            s.version = version;
+        }
+    }
+
+    /// Значение, срок и теги ключа без отметки об обращении, для снимков.
+    pub fn get_entry(&self, key: &str) -> Option<(Vec<u8>, Option<u64>, Vec<String>)> {
+        self.live(key)
+            .map(|s| (s.value.to_vec(), s.expires_at, s.tags.to_vec()))
+    }
+
+    /// Заменяет теги существующего ключа, не трогая значение и срок;
+    /// возвращает, нашёлся ли ключ.
+    pub fn retag(&self, key: String, mut tags: Vec<String>) -> bool {
+        match self.entry(key).0 {
+            Entry::Occupied(mut e) => {
+                tags.sort_unstable();
+                tags.dedup();
+                self.unindex_tags(e.key(), &e.get().tags);
+                self.index_tags(e.key(), &tags);
+                e.get_mut().tags = tags.into_boxed_slice();
+                true
+            }
+            Entry::Vacant(_) => false,
+        }
+    }
+
+    /// Живые ключи с тегом `tag`, в произвольном порядке.
+    pub fn keys_tagged(&self, tag: &str) -> Vec<String> {
+        let keys: Vec<String> = match self.tag_index().get(tag) {
+            Some(keys) => keys.iter().cloned().collect(),
+            None => return Vec::new(),
+        };
+        // индекс отпущен: шарды блокируются только без него
+        keys.into_iter()
+            .filter(|k| self.live(k).is_some())
+            .collect()
+    }
+
+    /// Удаляет ключ, только если он жив и подходит под `cond`.
+    /// `before_remove` вызывается под блокировкой шарда, его ошибка отменяет
+    /// удаление.
+    pub fn remove_if<E>(
+        &self,
+        key: &str,
+        cond: RemoveIf<'_>,
+        before_remove: impl FnOnce(&str) -> Result<(), E>,
+    ) -> Result<bool, E> {
+        self.take_if(key, cond, before_remove).map(|v| v.is_some())
+    }
+
+    /// `remove_if`, возвращающий удалённое значение. Пока `before_remove`
+    /// не вернулся, ключ на месте и шард заблокирован: другие соединения не
+    /// увидят удаления раньше, чем оно записано в WAL.
+    pub fn take_if<E>(
+        &self,
+        key: &str,
+        cond: RemoveIf<'_>,
+        before_remove: impl FnOnce(&str) -> Result<(), E>,
+    ) -> Result<Option<Vec<u8>>, E> {
+        let mut res = Ok(());
+        let removed = self.inner.remove_if(key, |k, s| {
+            if s.expired() || !cond.holds(s) {
+                return false;
+            }
+            res = before_remove(k);
+            if res.is_err() {
+                return false;
+            }
+            self.history.record(k, &s.value);
+            self.unindex_tags(k, &s.tags);
+            true
+        });
+        res.map(|()| removed.map(|(_, s)| s.value.into_vec()))
+    }
+
+    fn tomb_lock(&self) -> MutexGuard<'_, HashMap<String, Tombstone>> {
+        self.tombstones.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Переносит живой ключ в надгробие до `purge_at` (unix-время в мс) и
+    /// возвращает, был ли ключ; только ключ, подходящий под `cond`. Прежнее
+    /// надгробие ключа заменяется. `before_remove` получает значение, срок и
+    /// теги под блокировкой шарда, его ошибка отменяет удаление.
+    pub fn bury<E>(
+        &self,
+        key: &str,
+        purge_at: u64,
+        cond: RemoveIf<'_>,
+        before_remove: impl FnOnce(&str, &[u8], Option<u64>, &[String]) -> Result<(), E>,
+    ) -> Result<bool, E> {
+        let Entry::Occupied(e) = self.inner.entry(key.to_string()) else {
+            return Ok(false);
+        };
+        let slot = e.get();
+        if slot.expired() || !cond.holds(slot) {
+            return Ok(false);
+        }
+        before_remove(e.key(), &slot.value, slot.expires_at, &slot.tags)?;
+        self.history.record(e.key(), &slot.value);
+        self.unindex_tags(e.key(), &slot.tags);
+        let mut tombs = self.tomb_lock();
+        let (key, slot) = e.remove_entry();
+        self.next_purge.fetch_min(purge_at, Ordering::Relaxed);
+        self.any_expiry.store(true, Ordering::Relaxed);
+        tombs.insert(
+            key,
+            Tombstone {
+                value: slot.value.into_vec(),
+                expires_at: slot.expires_at,
+                tags: slot.tags.into_vec(),
+                purge_at,
+            },
+        );
+        Ok(true)
+    }
+
+    /// Кладёт надгробие как есть (replay, снапшот, реплика). Прошедшее тоже
+    /// кладётся — его уберёт `purge_tombstones`: Undelete дальше в журнале мог
+    /// успеть до срока.
+    pub fn put_tombstone(&self, key: String, tomb: Tombstone) {
+        self.next_purge.fetch_min(tomb.purge_at, Ordering::Relaxed);
+        self.any_expiry.store(true, Ordering::Relaxed);
+        self.tomb_lock().insert(key, tomb);
+    }
+
+    /// Убирает надгробие ключа и возвращает его.
+    pub fn take_tombstone(&self, key: &str) -> Option<Tombstone> {
+        self.tomb_lock().remove(key)
+    }
+
+    /// Возвращает значение из надгробия, если ключа сейчас нет, а срок
+    /// надгробия не прошёл; срок и теги — те, что были при удалении.
+    /// `before_write` вызывается под блокировкой шарда, его ошибка отменяет
+    /// восстановление.
+    pub fn undelete<E>(
+        &self,
+        key: String,
+        before_write: impl FnOnce(&str) -> Result<(), E>,
+    ) -> Result<bool, E> {
+        self.undelete_within(key, Some(now_unix_ms()), before_write)
+    }
+
+    /// `undelete` уже состоявшегося в журнале Undelete: надгробие, ещё не
+    /// убранное из памяти, возвращается и после своего срока.
+    pub fn restore(&self, key: String) -> bool {
+        self.undelete_within(key, None, |_| Ok::<_, CacheError>(()))
+            .expect("restore without a WAL write cannot fail")
+    }
+
+    // `now` — надгробия не позже него уже не возвращаются
+    fn undelete_within<E>(
+        &self,
+        key: String,
+        now: Option<u64>,
+        before_write: impl FnOnce(&str) -> Result<(), E>,
+    ) -> Result<bool, E> {
+        let Entry::Vacant(e) = self.entry(key).0 else {
+            return Ok(false);
+        };
+        let mut tombs = self.tomb_lock();
+        let live = |t: &Tombstone| now.is_none_or(|now| t.purge_at > now);
+        if !tombs.get(e.key()).is_some_and(live) {
+            return Ok(false);
+        }
+        before_write(e.key())?;
+        let tomb = tombs.remove(e.key()).expect("tombstone checked above");
+        drop(tombs);
+        if let Some(at) = tomb.expires_at {
+            self.index_expiry(e.key(), at);
+        }
+        self.index_tags(e.key(), &tomb.tags);
+        let mut slot = self.slot(tomb.value);
+        slot.expires_at = tomb.expires_at;
+        slot.tags = tomb.tags.into_boxed_slice();
+        e.insert(slot);
+        Ok(true)
+    }
+
+    /// Убирает надгробия с прошедшим `purge_at` и возвращает их число.
+    pub fn purge_tombstones(&self) -> usize {
+        let now = now_unix_ms();
+        if self.next_purge.load(Ordering::Relaxed) > now {
+            return 0;
+        }
+        let mut tombs = self.tomb_lock();
+        let before = tombs.len();
+        tombs.retain(|_, t| t.purge_at > now);
+        let next = tombs.values().map(|t| t.purge_at).min();
+        self.next_purge
+            .store(next.unwrap_or(u64::MAX), Ordering::Relaxed);
+        before - tombs.len()
+    }
+
+    /// Число надгробий и занятые ими байты ключей, значений и тегов.
+    pub fn tombstone_stats(&self) -> (u64, u64) {
+        let tombs = self.tomb_lock();
+        let bytes = tombs.iter().map(|(k, t)| t.bytes(k) as u64).sum();
+        (tombs.len() as u64, bytes)
+    }
+
+    /// Обход надгробий; останавливается на первой ошибке колбэка.
+    pub fn try_for_each_tombstone<E>(
+        &self,
+        mut f: impl FnMut(&str, &Tombstone) -> Result<(), E>,
+    ) -> Result<(), E> {
+        // копия, чтобы колбэк (запись снапшота) не держал блокировку
+        let tombs: Vec<(String, Tombstone)> = self
+            .tomb_lock()
+            .iter()
+            .map(|(k, t)| (k.clone(), t.clone()))
+            .collect();
+        for (k, t) in &tombs {
+            f(k, t)?;
+        }
+        Ok(())
+    }
+
+    /// Значение ключа, а если его нет — записывает `value` со сроком
+    /// `expires_at` (unix-время в мс). Второй элемент —
+    /// записала ли значение эта команда. `before_insert` вызывается под
+    /// блокировкой шарда, поэтому из двух конкурентных вызовов пишет ровно один;
+    /// ошибка из него отменяет запись.
+    pub fn get_or_insert<E>(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+        before_insert: impl FnOnce(&str, &[u8]) -> Result<(), E>,
+    ) -> Result<(Vec<u8>, bool), E> {
+        match self.entry(key).0 {
+            Entry::Occupied(e) => Ok((e.get().touched().to_vec(), false)),
+            Entry::Vacant(e) => {
+                before_insert(e.key(), &value)?;
+                if let Some(at) = expires_at {
+                    self.index_expiry(e.key(), at);
+                }
+                let mut slot = self.slot(value.clone());
+                slot.expires_at = expires_at;
+                e.insert(slot);
+                Ok((value, true))
+            }
+        }
+    }
+
+    /// Новое значение ключа из текущего (`None` — ключа нет) под блокировкой
+    /// шарда. `f` возвращает `None`, если значение не меняется; иначе перед
+    /// записью вызывается `before_write`, и его ошибка отменяет запись.
+    /// Возвращает значение после вызова и изменилось ли оно. Срок и теги
+    /// ключа сохраняются, `before_write` получает и их.
+    pub fn update<E>(
+        &self,
+        key: String,
+        f: impl FnOnce(Option<&[u8]>) -> Result<Option<Vec<u8>>, E>,
+        before_write: impl FnOnce(&str, &[u8], Option<u64>, &[String]) -> Result<(), E>,
+    ) -> Result<(Option<Vec<u8>>, bool), E> {
+        match self.entry(key).0 {
+            Entry::Occupied(mut e) => match f(Some(e.get().touched()))? {
+                Some(new) => {
+                    let slot = e.get();
+                    before_write(e.key(), &new, slot.expires_at, &slot.tags)?;
+                    self.history.record(e.key(), &slot.value);
+                    let slot = e.get_mut();
+                    slot.value = self.dedup.intern(new.clone());
+                    slot.rewritten();
+                    Ok((Some(new), true))
+                }
+                None => Ok((Some(e.get().value.to_vec()), false)),
+            },
+            Entry::Vacant(e) => match f(None)? {
+                Some(new) => {
+                    before_write(e.key(), &new, None, &[])?;
+                    e.insert(self.slot(new.clone()));
+                    Ok((Some(new), true))
+                }
+                None => Ok((None, false)),
+            },
+        }
+    }
+
+    /// Ставит бит значения на месте, без копии, и возвращает прежний бит и
+    /// изменилось ли значение. `before_write` вызывается под блокировкой шарда
+    /// только перед изменением; его ошибка отменяет запись. Если ключ только
+    /// что истёк, `before_write` получает новое значение целиком: replay не
+    /// знает, что срок прошёл раньше SetBit. Сжатое значение — ошибка, см.
+    /// `encoding::check_raw`.
+    pub fn set_bit<E: From<CacheError>>(
+        &self,
+        key: String,
+        offset: u64,
+        bit: bool,
+        before_write: impl FnOnce(&str, Option<&[u8]>) -> Result<(), E>,
+    ) -> Result<(bool, bool), E> {
+        match self.entry(key) {
+            (Entry::Occupied(mut e), _) => {
+                let cur = e.get().touched();
+                check_raw(e.key(), cur)?;
+                let prev = bits::get_bit(cur, offset);
+                if !bits::changes(cur, offset, bit) {
+                    return Ok((prev, false));
+                }
+                before_write(e.key(), None)?;
+                self.history.record(e.key(), &e.get().value);
+                let slot = e.get_mut();
+                bits::set_bit(slot.value.make_mut(), offset, bit);
+                slot.value.shrink();
+                slot.rewritten();
+                Ok((prev, true))
+            }
+            (Entry::Vacant(e), expired) => {
+                let mut v = Vec::new();
+                bits::set_bit(&mut v, offset, bit);
+                before_write(e.key(), Some(v.as_slice()).filter(|_| expired))?;
+                e.insert(self.slot(v));
+                Ok((false, true))
+            }
+        }
+    }
+
+    // сжатое значение читается распакованным, см. encoding::raw_bytes
+    pub fn get_bit(&self, key: &str, offset: u64) -> bool {
+        self.live(key)
+            .is_some_and(|s| bits::get_bit(&raw_bytes(s.touched()), offset))
+    }
+
+    pub fn bit_count(&self, key: &str, range: Option<(i64, i64)>) -> i64 {
+        self.live(key)
+            .map_or(0, |s| bits::bit_count(&raw_bytes(s.touched()), range))
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.live(key).inspect(|s| s.touch()).is_some()
+    }
+
+    /// Ставит срок существующему ключу и возвращает, нашёлся ли он.
+    /// `before_write` вызывается под блокировкой шарда, его ошибка отменяет
+    /// изменение.
+    pub fn set_expiry<E>(
+        &self,
+        key: String,
+        expires_at: u64,
+        before_write: impl FnOnce(&str) -> Result<(), E>,
+    ) -> Result<bool, E> {
+        match self.entry(key).0 {
+            Entry::Occupied(mut e) => {
+                before_write(e.key())?;
+                self.index_expiry(e.key(), expires_at);
+                let slot = e.get_mut();
+                slot.touch();
+                slot.expires_at = Some(expires_at);
+                Ok(true)
+            }
+            Entry::Vacant(_) => Ok(false),
+        }
+    }
+
+    /// Продлевает срок живого ключа на `add_ms` (бессрочному ставит срок
+    /// `add_ms` от текущего момента), только если его значение равно
+    /// `expected`, и возвращает новый срок. `before_write` получает его под
+    /// блокировкой шарда, его ошибка отменяет продление.
+    pub fn extend_if_equals<E>(
+        &self,
+        key: String,
+        expected: &[u8],
+        add_ms: u64,
+        before_write: impl FnOnce(&str, u64) -> Result<(), E>,
+    ) -> Result<Option<u64>, E> {
+        match self.entry(key).0 {
+            Entry::Occupied(mut e) if same_value(&e.get().value, expected) => {
+                let from = e.get().expires_at.unwrap_or_else(now_unix_ms);
+                let at = from.saturating_add(add_ms);
+                before_write(e.key(), at)?;
+                self.index_expiry(e.key(), at);
+                let slot = e.get_mut();
+                slot.touch();
+                slot.expires_at = Some(at);
+                Ok(Some(at))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Срок ключа: `None` — ключа нет, `Some(None)` — ключ бессрочный.
+    pub fn expires_at(&self, key: &str) -> Option<Option<u64>> {
+        self.live(key).map(|s| s.expires_at)
+    }
+
+    /// Удаляет из карты истёкшие ключи и возвращает их.
+    pub fn take_expired(&self) -> Vec<String> {
+        if !self.any_expiry.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        let now = now_unix_ms();
+        let due = {
+            let mut expiries = self.expiries.lock().unwrap_or_else(|e| e.into_inner());
+            let later = expiries.split_off(&(now + 1, String::new()));
+            std::mem::replace(&mut *expiries, later)
+        };
+        due.into_iter()
+            .filter(|(at, key)| {
+                self.inner
+                    .remove_if(key, |k, s| {
+                        let due = s.expires_at == Some(*at);
+                        if due {
+                            self.unindex_tags(k, &s.tags);
+                        }
+                        due
+                    })
+                    .is_some()
+            })
+            .map(|(_, key)| key)
+            .collect()
+    }
+
+    /// Был ли у какого-нибудь ключа срок или надгробие с момента создания карты.
+    pub fn may_expire(&self) -> bool {
+        self.any_expiry.load(Ordering::Relaxed)
+    }
+
+    /// Обновляет время обращения к существующим ключам, не читая значений;
+    /// возвращает, сколько из них нашлось (повторы считаются каждый раз).
+    pub fn touch(&self, keys: &[String]) -> i64 {
+        keys.iter()
+            .filter(|k| self.live(k).inspect(|s| s.touch()).is_some())
+            .count() as i64
+    }
+
+    /// Секунды с последнего обращения к ключу; `None`, если ключа нет.
+    /// Обход (scan, снапшот) временем обращения не считается.
+    pub fn idle_time(&self, key: &str) -> Option<u64> {
+        self.live(key).map(|s| {
+            let accessed = s.accessed.load(Ordering::Relaxed);
+            now_ms().saturating_sub(accessed) / 1000
+        })
+    }
+
+    /// До `n` (не больше `MAX_SAMPLE`) разных живых ключей, выбранных наугад
+    /// с равной вероятностью, с размером значения и возрастом; значения не
+    /// копируются, обращения не отмечаются. Шард выбирается с весом по числу
+    /// его ключей, бакет в шарде — наугад, пустой бакет — повтор, так что
+    /// разные размеры шардов выборку не сдвигают, а обход карты не нужен.
+    /// Смещение остаётся у почти пустой таблицы (после массовых удалений
+    /// хэш-таблицы не сжимаются): после `MAX_PROBES` промахов берётся
+    /// ближайший занятый бакет, и ключи после длинной пустой полосы выпадают
+    /// чаще. Когда `n` не меньше половины ключей, выборку даёт один обход
+    /// (reservoir sampling).
+    pub fn sample(&self, n: usize) -> Vec<KeySample> {
+        let n = n.min(MAX_SAMPLE);
+        let shards = self.inner.shards();
+        let sizes: Vec<u64> = shards.iter().map(|s| s.read().len() as u64).collect();
+        let total: u64 = sizes.iter().sum();
+        let mut rng = Rng(RandomState::new().build_hasher().finish() | 1);
+        let now = (now_ms(), now_unix_ms());
+        if n == 0 || total == 0 {
+            return Vec::new();
+        }
+        if n as u64 * 2 >= total {
+            let mut out = Vec::with_capacity(n);
+            let live = self.inner.iter().filter(|e| !e.expired());
+            for (i, e) in live.enumerate() {
+                if out.len() < n {
+                    out.push(e.sample(e.key(), now));
+                } else {
+                    let j = rng.below(i as u64 + 1) as usize;
+                    if j < n {
+                        out[j] = e.sample(e.key(), now);
+                    }
+                }
+            }
+            return out;
+        }
+        let mut seen = HashSet::with_capacity(n);
+        let mut out = Vec::with_capacity(n);
+        // истёкшие ключи и повторы пропускаются; повтор меньше чем в половине
+        // попыток, так что запаса хватает
+        for _ in 0..n * 8 {
+            if out.len() == n {
+                break;
+            }
+            let (mut r, mut i) = (rng.below(total), 0);
+            while r >= sizes[i] {
+                r -= sizes[i];
+                i += 1;
+            }
+            let shard = shards[i].read();
+            if let Some((key, slot)) = random_entry(&shard, &mut rng) {
+                if !slot.expired() && !seen.contains(key) {
+                    seen.insert(key.clone());
+                    out.push(slot.sample(key, now));
+                }
+            }
+        }
+        out
+    }
+
+    /// Результат `f` над значением ключа без копии и без отметки об
+    /// обращении; `None`, если ключа нет.
+    pub fn peek<R>(&self, key: &str, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        self.live(key).map(|s| f(&s.value))
     }
 
     pub fn pop(&self, key: &str) -> Option<Vec<u8>> {
-        self.inner.remove(key).map(|(_, v)| v)
+        self.inner
+            .remove_if(key, |k, s| {
+                if !s.expired() {
+                    self.history.record(k, &s.value);
+                }
+                self.unindex_tags(k, &s.tags);
+                true
+            })
+            .filter(|(_, s)| !s.expired())
+            .map(|(_, s)| s.value.into_vec())
+    }
+
+    /// Значения `keys` на один момент времени (`None` — ключа нет). Шарды
+    /// всех ключей блокируются на чтение сразу и по возрастанию номера, как
+    /// в `swap`: команда, которой нужно несколько шардов, берёт их только в
+    /// этом порядке, иначе две такие команды заблокируют друг друга. Пока
+    /// значения копируются, запись в эти шарды ждёт.
+    pub fn get_consistent(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>, CacheError> {
+        let shard_of: Vec<usize> = keys.iter().map(|k| self.inner.determine_map(k)).collect();
+        let mut order = shard_of.clone();
+        order.sort_unstable();
+        order.dedup();
+        let shards = self.inner.shards();
+        let guards: Vec<_> = order.iter().map(|&i| shards[i].read()).collect();
+        keys.iter()
+            .zip(&shard_of)
+            .map(|(key, i)| {
+                let at = order.binary_search(i).expect("shard of the key is locked");
+                match guards[at].get(key.as_str()).map(SharedValue::get) {
+                    Some(slot) if !slot.expired() => {
+                        self.check(key, slot)?;
+                        Ok(Some(slot.touched().to_vec()))
+                    }
+                    _ => Ok(None),
+                }
+            })
+            .collect()
     }
 
     pub fn delete(&self, key: &str) -> i64 {
-        self.inner.remove(key).is_some() as i64
+        self.pop(key).is_some() as i64
+    }
+
+    /// Меняет местами слоты двух ключей вместе со сроками и тегами; если был
+    /// только один, он переезжает под другое имя. Возвращает, были ли ключи
+    /// `a` и `b` до обмена; если не было ни одного, ничего не меняется. Шарды
+    /// обоих ключей блокируются сразу, по возрастанию номера (общий порядок
+    /// с `get_consistent`), так что никто не увидит промежуточного состояния. `before_write` вызывается под
+    /// блокировками, его ошибка отменяет обмен.
+    pub fn swap<E>(
+        &self,
+        a: &str,
+        b: &str,
+        before_write: impl FnOnce(&str, &str) -> Result<(), E>,
+    ) -> Result<(bool, bool), E> {
+        fn shard<'g, G: DerefMut>(
+            low: &'g mut G,
+            high: &'g mut Option<G>,
+            upper: bool,
+        ) -> &'g mut G::Target {
+            match high {
+                Some(high) if upper => high,
+                _ => low,
+            }
+        }
+        let (ia, ib) = (self.inner.determine_map(a), self.inner.determine_map(b));
+        let (a_up, b_up) = (ia > ib, ib > ia);
+        let shards = self.inner.shards();
+        let mut low = shards[ia.min(ib)].write();
+        let mut high = (ia != ib).then(|| shards[ia.max(ib)].write());
+        let live = |s: Option<&SharedValue<Slot>>| s.is_some_and(|s| !s.get().expired());
+        let had_a = live(shard(&mut low, &mut high, a_up).get(a));
+        let had_b = live(shard(&mut low, &mut high, b_up).get(b));
+        if a == b || !(had_a || had_b) {
+            return Ok((had_a, had_b));
+        }
+        before_write(a, b)?;
+        let slot_a = shard(&mut low, &mut high, a_up)
+            .remove(a)
+            .map(SharedValue::into_inner);
+        let slot_b = shard(&mut low, &mut high, b_up)
+            .remove(b)
+            .map(SharedValue::into_inner);
+        for (k, slot) in [(a, &slot_a), (b, &slot_b)] {
+            if let Some(slot) = slot {
+                if !slot.expired() {
+                    self.history.record(k, &slot.value);
+                }
+                self.unindex_tags(k, &slot.tags);
+            }
+        }
+        // истёкший слот не переезжает: он и так выглядел отсутствующим
+        let moved = |slot: Option<Slot>| slot.filter(|s| !s.expired());
+        for (k, upper, slot) in [(b, b_up, moved(slot_a)), (a, a_up, moved(slot_b))] {
+            let Some(slot) = slot else {
+                continue;
+            };
+            if let Some(at) = slot.expires_at {
+                self.index_expiry(k, at);
+            }
+            self.index_tags(k, &slot.tags);
+            shard(&mut low, &mut high, upper).insert(k.to_string(), SharedValue::new(slot));
+        }
+        Ok((had_a, had_b))
+    }
+
+    pub fn clear(&self) {
+        self.inner.clear();
+        self.expiries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        self.tag_index().clear();
+        self.tomb_lock().clear();
+        self.next_purge.store(u64::MAX, Ordering::Relaxed);
+    }
+
+    // обход шард за шардом: `f` получает записи шарда под его блокировкой
+    // чтения и на `false` обход прекращает. Блокировка отпускается до
+    // следующего шарда, так что писатель, надолго занявший шард, задерживает
+    // обход только на нём, а обход не держит шард дольше его просмотра.
+    // Под блокировкой `f` только смотрит и копирует ключи: значения и ответ
+    // собираются после
+    fn each_slot(
+        &self,
+        deadline: Deadline,
+        mut f: impl FnMut(usize, &str, &Slot) -> bool,
+    ) -> Result<(), CacheError> {
+        let mut i = 0;
+        for shard in self.inner.shards() {
+            deadline.check()?;
+            let shard = shard.read();
+            for (key, slot) in shard.iter() {
+                deadline.checkpoint(i)?;
+                if !f(i, key, slot.get()) {
+                    return Ok(());
+                }
+                i += 1;
+            }
+        }
+        Ok(())
     }
 
     pub fn keys_prefix(&self, prefix: &str) -> Vec<String> {
-        self.inner
-            .iter()
-            .filter(|e| e.key().starts_with(prefix))
-            .map(|e| e.key().clone())
-            .collect()
+        self.keys_within(prefix, Deadline::NONE)
+            .expect("no deadline to miss")
     }
 
+    /// `keys_prefix`, бросающий обход по истечении `deadline`.
+    pub fn keys_within(&self, prefix: &str, deadline: Deadline) -> Result<Vec<String>, CacheError> {
+        let mut keys = Vec::new();
+        self.each_slot(deadline, |_, key, slot| {
+            if key.starts_with(prefix) && !slot.expired() {
+                keys.push(key.to_string());
+            }
+            true
+        })?;
+        Ok(keys)
+    }
+
+    /// Страница ключей на `prefix` в лексикографическом порядке. Упорядоченного
+    /// индекса нет: ключи собираются целиком, но сортируется только нужная
+    /// голова из `offset + limit` ключей.
+    pub fn keys_sorted(
+        &self,
+        prefix: &str,
+        offset: usize,
+        limit: usize,
+        deadline: Deadline,
+    ) -> Result<Vec<String>, CacheError> {
+        let mut keys = self.keys_within(prefix, deadline)?;
+        let end = offset.saturating_add(limit);
+        if end < keys.len() {
+            keys.select_nth_unstable(end);
+            keys.truncate(end);
+        }
+        keys.sort_unstable();
+        Ok(keys.into_iter().skip(offset).collect())
+    }
+
+    /// Фильтр Блума по живым ключам на `prefix`. Ключи не собираются: первый
+    /// обход считает их для размера фильтра, второй заполняет его, так что
+    /// ключ, записанный между обходами, тоже может попасть в фильтр.
+    pub fn bloom(
+        &self,
+        prefix: &str,
+        bits_per_key: u32,
+        deadline: Deadline,
+    ) -> Result<BloomFilter, CacheError> {
+        let matching = |key: &str, slot: &Slot| key.starts_with(prefix) && !slot.expired();
+        let mut keys = 0;
+        self.each_slot(deadline, |_, key, slot| {
+            keys += matching(key, slot) as usize;
+            true
+        })?;
+        let mut filter = BloomFilter::new(keys, bits_per_key);
+        self.each_slot(deadline, |_, key, slot| {
+            if matching(key, slot) {
+                filter.insert(key);
+            }
+            true
+        })?;
+        Ok(filter)
+    }
+
+    /// Число живых ключей и сумма длин их значений по группам: группа ключа
+    /// — его начало до `depth`-го разделителя `sep` включительно, у ключа с
+    /// меньшим числом разделителей — до последнего из них (без разделителя —
+    /// группа `""`), так что имя группы — префикс её ключей. Групп не больше
+    /// `MAX_PREFIX_GROUPS`: ключи групп, не попавших в предел (первыми в
+    /// обходе встретились другие), достаются `other`. Один обход, без списков
+    /// ключей.
+    pub fn prefix_stats(
+        &self,
+        sep: &str,
+        depth: u32,
+        deadline: Deadline,
+    ) -> Result<PrefixStats, CacheError> {
+        let mut stats = PrefixStats::default();
+        self.each_slot(deadline, |_, key, slot| {
+            if slot.expired() {
+                return true;
+            }
+            let end = key
+                .match_indices(sep)
+                .nth(depth as usize - 1)
+                .map(|(at, _)| at)
+                .or_else(|| key.rfind(sep))
+                .map_or(0, |at| at + sep.len());
+            let bytes = slot.value.len() as u64;
+            let group = &key[..end];
+            if stats.groups.len() < MAX_PREFIX_GROUPS && !stats.groups.contains_key(group) {
+                stats.groups.insert(group.to_string(), (0, 0));
+            }
+            let counts = match stats.groups.get_mut(group) {
+                Some(counts) => counts,
+                None => stats.other.get_or_insert((0, 0)),
+            };
+            counts.0 += 1;
+            counts.1 += bytes;
+            true
+        })?;
+        Ok(stats)
+    }
+
+    /// Свёртка `op` по целым значениям живых ключей на `prefix`, один обход
+    /// без копий значений; см. `AggOp`. Первое нецелое значение без
+    /// `skip_invalid` — ошибка.
+    pub fn aggregate(
+        &self,
+        prefix: &str,
+        op: AggOp,
+        skip_invalid: bool,
+        deadline: Deadline,
+    ) -> Result<Option<i64>, CacheError> {
+        let mut agg = Aggregate::new(op, skip_invalid);
+        let mut err = None;
+        self.each_slot(deadline, |_, key, slot| {
+            if !key.starts_with(prefix) || slot.expired() {
+                return true;
+            }
+            match agg.add(key, &slot.value) {
+                Ok(()) => true,
+                Err(e) => {
+                    err = Some(e);
+                    false
+                }
+            }
+        })?;
+        match err {
+            Some(e) => Err(e),
+            None => Ok(agg.finish()),
+        }
+    }
+
+    /// Вместе с истёкшими, но ещё не удалёнными ключами.
     pub fn len(&self) -> i64 {
         self.inner.len() as i64
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Страница постраничного обхода: пропускает `cursor` записей в порядке
+    /// обхода DashMap и возвращает до `count` пар с ключом на `prefix` и курсор
+    /// следующей страницы (0 — обход закончен). Параллельные записи могут
+    /// сдвинуть порядок, тогда отдельные ключи повторятся или пропадут.
+    /// Значения копируются после обхода, по ключу: ключ, удалённый за это
+    /// время, в страницу не попадает.
+    pub fn scan_items(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        deadline: Deadline,
+    ) -> Result<ScanPage, CacheError> {
+        let (next, keys) = self.scan_keys(cursor, prefix, count, deadline)?;
+        let items = keys
+            .into_iter()
+            .filter_map(|k| {
+                let value = self.live(&k)?.value.to_vec();
+                Some((k, value))
+            })
+            .collect();
+        Ok((next, items))
+    }
+
+    /// То же, что `scan_items`, но без значений.
+    pub fn scan_keys(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        deadline: Deadline,
+    ) -> Result<(u64, Vec<String>), CacheError> {
+        self.scan(cursor, prefix, count, deadline, |k, _| Some(k.to_string()))
+    }
+
+    /// То же, что `scan_keys`, но только ключи с длиной значения в `sizes`, с
+    /// этой длиной; значения не копируются. `count` считает подошедшие ключи,
+    /// так что на редком размере одна страница обходит почти всю карту.
+    pub fn scan_sizes(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        sizes: RangeInclusive<u64>,
+        deadline: Deadline,
+    ) -> Result<(u64, Vec<(String, u64)>), CacheError> {
+        self.scan(cursor, prefix, count, deadline, |k, slot| {
+            let len = slot.value.len() as u64;
+            sizes.contains(&len).then(|| (k.to_string(), len))
+        })
+    }
+
+    /// То же, что `scan_keys`, но с оставшимся сроком ключа в мс, как у
+    /// `PTtl` (-1 — бессрочный); срок читается из той же записи, что и ключ.
+    pub fn scan_ttls(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        deadline: Deadline,
+    ) -> Result<(u64, Vec<(String, i64)>), CacheError> {
+        let now = now_unix_ms();
+        self.scan(cursor, prefix, count, deadline, |k, slot| {
+            // живой ключ истекает не раньше следующей миллисекунды
+            let ttl = slot
+                .expires_at
+                .map_or(-1, |at| at.saturating_sub(now).max(1) as i64);
+            Some((k.to_string(), ttl))
+        })
+    }
+
+    // курсор — позиция в обходе карты, поэтому вставки и удаления между
+    // страницами сдвигают её: ключ может пропасть из обхода или повториться
+    fn scan<T>(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        deadline: Deadline,
+        f: impl Fn(&str, &Slot) -> Option<T>,
+    ) -> Result<(u64, Vec<T>), CacheError> {
+        let mut items = Vec::new();
+        let mut next = 0;
+        // пропуск до курсора тоже обход: на редком префиксе он и есть вся работа
+        self.each_slot(deadline, |i, key, slot| {
+            if i < cursor as usize || !key.starts_with(prefix) || slot.expired() {
+                return true;
+            }
+            let Some(item) = f(key, slot) else {
+                return true;
+            };
+            items.push(item);
+            if items.len() >= count {
+                next = i as u64 + 1;
+                return false;
+            }
+            true
+        })?;
+        Ok((next, items))
+    }
+
+    /// Обход живых ключей со значениями, сроками и тегами; останавливается
+    /// на первой ошибке колбэка.
+    pub fn try_for_each<E>(
+        &self,
+        mut f: impl FnMut(&str, &[u8], Option<u64>, &[String]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.try_for_each_with_meta(|k, v, expires_at, tags, _| f(k, v, expires_at, tags))
+    }
+
+    /// Как `try_for_each`, но ещё и со временем последней записи и версией.
+    pub fn try_for_each_with_meta<E>(
+        &self,
+        mut f: impl FnMut(&str, &[u8], Option<u64>, &[String], (u64, u64)) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for e in self.inner.iter().filter(|e| !e.expired()) {
+            let meta = (e.written_at, e.version);
+            f(e.key(), &e.value().value, e.expires_at, &e.tags, meta)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,138 @@
+use crate::error::CacheError;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+/// Переменная окружения с ключом в hex, если `wal_key` не передан в `serve()`.
+pub const WAL_KEY_ENV: &str = "TINY_MP_CACHE_WAL_KEY";
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// Проверочное значение ключа в заголовках WAL и снапшота: nonce + tag
+/// шифрования пустой строки. Позволяет отличить чужой ключ от порчи данных.
+pub const KEY_CHECK_LEN: usize = NONCE_LEN + TAG_LEN;
+const KEY_CHECK_AAD: &[u8] = b"tiny-mp-cache key check";
+
+/// Ключ шифрования WAL и снапшотов (XChaCha20-Poly1305). Каждая запись
+/// шифруется со своим случайным nonce, который лежит перед шифротекстом.
+pub struct WalKey {
+    cipher: XChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for WalKey {
+    // сам ключ в логи не попадает
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WalKey(..)")
+    }
+}
+
+impl WalKey {
+    pub fn from_bytes(key: &[u8]) -> Result<Self, CacheError> {
+        if key.len() != KEY_LEN {
+            return Err(CacheError::Internal(format!(
+                "WAL key must be {} bytes, got {}",
+                KEY_LEN,
+                key.len()
+            )));
+        }
+        let cipher = XChaCha20Poly1305::new_from_slice(key)
+            .map_err(|e| CacheError::Internal(format!("WAL key: {}", e)))?;
+        Ok(Self { cipher })
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, CacheError> {
+        let hex = hex.trim();
+        let bad =
+            || CacheError::Internal(format!("WAL key must be {} hex characters", KEY_LEN * 2));
+        if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+            return Err(bad());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| bad())?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Ключ из `TINY_MP_CACHE_WAL_KEY`, `None`, если переменная не задана.
+    pub fn from_env() -> Result<Option<Self>, CacheError> {
+        match std::env::var(WAL_KEY_ENV) {
+            Ok(hex) if !hex.is_empty() => Self::from_hex(&hex).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// nonce | ciphertext | tag
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, CacheError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ct = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| CacheError::Internal("encrypt record".into()))?;
+        let mut out = Vec::with_capacity(NONCE_LEN + ct.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ct);
+        Ok(out)
+    }
+
+    /// Обратное к `seal`; `None`, если запись не проходит проверку тега.
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return None;
+        }
+        let (nonce, ct) = sealed.split_at(NONCE_LEN);
+        self.cipher.decrypt(XNonce::from_slice(nonce), ct).ok()
+    }
+
+    pub fn check_value(&self) -> Result<[u8; KEY_CHECK_LEN], CacheError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let tag = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &[],
+                    aad: KEY_CHECK_AAD,
+                },
+            )
+            .map_err(|_| CacheError::Internal("encrypt key check".into()))?;
+        let mut out = [0u8; KEY_CHECK_LEN];
+        out[..NONCE_LEN].copy_from_slice(&nonce);
+        out[NONCE_LEN..].copy_from_slice(&tag);
+        Ok(out)
+    }
+
+    pub fn matches(&self, check: &[u8; KEY_CHECK_LEN]) -> bool {
+        let (nonce, tag) = check.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: tag,
+                    aad: KEY_CHECK_AAD,
+                },
+            )
+            .is_ok()
+    }
+}
+
+/// Проверяет проверочное значение из заголовка файла `what` против ключа.
+pub fn verify_key(
+    key: Option<&WalKey>,
+    check: &[u8; KEY_CHECK_LEN],
+    what: &std::path::Path,
+) -> Result<(), CacheError> {
+    match key {
+        None => Err(CacheError::Internal(format!(
+            "{} is encrypted, pass wal_key or set {}",
+            what.display(),
+            WAL_KEY_ENV
+        ))),
+        Some(key) if !key.matches(check) => Err(CacheError::Internal(format!(
+            "{} is encrypted with a different key",
+            what.display()
+        ))),
+        Some(_) => Ok(()),
+    }
+}
@@ -0,0 +1,224 @@
+//! Дедупликация одинаковых значений в памяти, см.
+//! `PersistentCore::set_dedup_min_size`. Значение не короче порога хранится
+//! один раз в таблице по хэшу blake3, а слоты держат на него ссылку со
+//! счётчиком. WAL и снапшоты по-прежнему пишут значения целиком. Здесь же
+//! `Value` — то, что лежит в слоте: маленькие значения хранятся прямо в нём,
+//! без своей аллокации.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type Hash = [u8; 32];
+
+/// Значения не длиннее хранятся в самом слоте: вместе с длиной и
+/// дискриминантом это 32 байта, как у `Vec` с дискриминантом. Общие значения
+/// поэтому лежат за `Box` — они не короче `dedup_min_size`, и лишняя
+/// аллокация на ссылку им не заметна.
+pub(crate) const INLINE_MAX: usize = 30;
+
+struct Stored {
+    bytes: Arc<[u8]>,
+    // сколько слотов ссылается на значение
+    refs: u64,
+}
+
+#[derive(Default)]
+struct Table {
+    values: HashMap<Hash, Stored>,
+    refs: u64,
+    // байты всех ссылок, как если бы каждая была копией
+    logical: u64,
+    physical: u64,
+}
+
+/// Таблица общих значений. Блокируется после шарда ключа и сама шардов не
+/// берёт: `Shared` отпускает ссылку из-под блокировки шарда.
+#[derive(Default)]
+pub(crate) struct Dedup {
+    // 0 — выключено
+    min_size: AtomicUsize,
+    table: Mutex<Table>,
+}
+
+/// Числа для Info: общие значения, ссылки на них и байты до и после
+/// дедупликации (только значения, попавшие в таблицу).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct DedupStats {
+    pub(crate) values: u64,
+    pub(crate) refs: u64,
+    pub(crate) logical_bytes: u64,
+    pub(crate) physical_bytes: u64,
+}
+
+impl Dedup {
+    fn lock(&self) -> MutexGuard<'_, Table> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn threshold(&self) -> Option<usize> {
+        Some(self.min_size.load(Ordering::Relaxed)).filter(|&n| n > 0)
+    }
+
+    /// `None` выключает дедупликацию новых значений; уже общие остаются
+    /// общими, пока их не перезапишут.
+    pub(crate) fn set_min_size(&self, min_size: Option<usize>) {
+        self.min_size
+            .store(min_size.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Значение для слота: не короче порога — ссылка из таблицы. Маленькие
+    /// значения не делятся при любом пороге: своя копия в слоте дешевле ссылки.
+    pub(crate) fn intern(self: &Arc<Self>, value: Vec<u8>) -> Value {
+        match self.threshold() {
+            Some(min) if value.len() >= min && value.len() > INLINE_MAX => self.share(value),
+            _ => Value::new(value),
+        }
+    }
+
+    /// Как `intern`, но только для ещё не общего значения.
+    pub(crate) fn reintern(self: &Arc<Self>, value: &mut Value) {
+        if let Value::Owned(v) = value {
+            if self
+                .threshold()
+                .is_some_and(|min| v.len() >= min && v.len() > INLINE_MAX)
+            {
+                *value = self.share(std::mem::take(v));
+            }
+        }
+    }
+
+    fn share(self: &Arc<Self>, value: Vec<u8>) -> Value {
+        // хэш считается без блокировки таблицы
+        let hash = *blake3::hash(&value).as_bytes();
+        let mut table = self.lock();
+        let bytes = match table.values.get_mut(&hash) {
+            Some(stored) if *stored.bytes == *value => {
+                stored.refs += 1;
+                Arc::clone(&stored.bytes)
+            }
+            // коллизия blake3 практически невозможна, но значение не должно
+            // подмениться чужим
+            Some(_) => return Value::Owned(value),
+            None => {
+                let bytes: Arc<[u8]> = value.into();
+                table.physical += bytes.len() as u64;
+                table.values.insert(
+                    hash,
+                    Stored {
+                        bytes: Arc::clone(&bytes),
+                        refs: 1,
+                    },
+                );
+                bytes
+            }
+        };
+        table.refs += 1;
+        table.logical += bytes.len() as u64;
+        drop(table);
+        Value::Shared(Box::new(Shared {
+            bytes,
+            hash,
+            table: Arc::clone(self),
+        }))
+    }
+
+    pub(crate) fn stats(&self) -> DedupStats {
+        let table = self.lock();
+        DedupStats {
+            values: table.values.len() as u64,
+            refs: table.refs,
+            logical_bytes: table.logical,
+            physical_bytes: table.physical,
+        }
+    }
+}
+
+/// Ссылка слота на общее значение; при сбросе последней значение уходит из
+/// таблицы.
+pub(crate) struct Shared {
+    bytes: Arc<[u8]>,
+    hash: Hash,
+    table: Arc<Dedup>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let len = self.bytes.len() as u64;
+        let mut table = self.table.lock();
+        table.refs -= 1;
+        table.logical -= len;
+        if let Some(stored) = table.values.get_mut(&self.hash) {
+            stored.refs -= 1;
+            if stored.refs == 0 {
+                table.values.remove(&self.hash);
+                table.physical -= len;
+            }
+        }
+    }
+}
+
+/// Значение в слоте: маленькое в самом слоте, своё в куче или общее.
+pub(crate) enum Value {
+    // длина и байты
+    Inline(u8, [u8; INLINE_MAX]),
+    Owned(Vec<u8>),
+    Shared(Box<Shared>),
+}
+
+impl Value {
+    /// Своё значение; не длиннее `INLINE_MAX` копируется в слот, а `value`
+    /// освобождается.
+    pub(crate) fn new(value: Vec<u8>) -> Self {
+        if value.len() <= INLINE_MAX {
+            let mut bytes = [0; INLINE_MAX];
+            bytes[..value.len()].copy_from_slice(&value);
+            Value::Inline(value.len() as u8, bytes)
+        } else {
+            Value::Owned(value)
+        }
+    }
+
+    /// Значение для изменения на месте; маленькое и общее сначала
+    /// копируются в свой `Vec`. После изменения — `shrink`.
+    pub(crate) fn make_mut(&mut self) -> &mut Vec<u8> {
+        match self {
+            Value::Inline(..) | Value::Shared(_) => *self = Value::Owned(self.to_vec()),
+            Value::Owned(_) => {}
+        }
+        match self {
+            Value::Owned(v) => v,
+            _ => unreachable!("value copied above"),
+        }
+    }
+
+    /// Своё значение, ставшее маленьким (или оставшееся им после
+    /// `make_mut`), возвращается в слот.
+    pub(crate) fn shrink(&mut self) {
+        if let Value::Owned(v) = self {
+            if v.len() <= INLINE_MAX {
+                *self = Value::new(std::mem::take(v));
+            }
+        }
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<u8> {
+        match self {
+            Value::Owned(v) => v,
+            Value::Inline(..) | Value::Shared(_) => self.to_vec(),
+        }
+    }
+}
+
+impl Deref for Value {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Value::Inline(len, bytes) => &bytes[..*len as usize],
+            Value::Owned(v) => v,
+            Value::Shared(s) => &s.bytes,
+        }
+    }
+}
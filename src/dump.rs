@@ -0,0 +1,247 @@
+use crate::core::{CacheCore, Deadline};
+use crate::error::CacheError;
+use crate::snapshot::Crc;
+use crate::wal::replace_file;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Логический дамп кэша: только пары ключ/значение, без истории WAL.
+///
+/// binary: magic "TMCD" | version u32
+///         records: (key len u32 | key | value len u32 | value) * count
+///         footer: count u64 | crc32 u32 (по всему, что до crc)
+/// json:   JSON Lines, по объекту {"key": ..., "value": <base64>} на строку
+const DUMP_MAGIC: &[u8; 4] = b"TMCD";
+const DUMP_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum DumpFormat {
+    #[default]
+    Binary,
+    Json,
+}
+
+impl FromStr for DumpFormat {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(DumpFormat::Binary),
+            "json" => Ok(DumpFormat::Json),
+            other => Err(CacheError::Internal(format!(
+                "unknown dump format {:?}, expected binary/json",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    key: &'a str,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct JsonEntryOwned {
+    key: String,
+    value: String,
+}
+
+/// Пара из прочитанного дампа; `end` — смещение в файле сразу после неё.
+pub struct DumpEntry {
+    pub key: String,
+    pub value: Vec<u8>,
+    pub end: u64,
+}
+
+/// Пишет дамп по одной записи; `finish` дописывает footer.
+pub struct DumpWriter<W: Write> {
+    w: Crc<BufWriter<W>>,
+    format: DumpFormat,
+    count: u64,
+}
+
+impl<W: Write> DumpWriter<W> {
+    pub fn new(inner: W, format: DumpFormat) -> std::io::Result<Self> {
+        let mut w = Crc::new(BufWriter::new(inner));
+        if format == DumpFormat::Binary {
+            w.write_all(DUMP_MAGIC)?;
+            w.write_all(&DUMP_VERSION.to_le_bytes())?;
+        }
+        Ok(Self {
+            w,
+            format,
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, key: &str, value: &[u8]) -> std::io::Result<()> {
+        match self.format {
+            DumpFormat::Binary => {
+                self.w.write_all(&(key.len() as u32).to_le_bytes())?;
+                self.w.write_all(key.as_bytes())?;
+                self.w.write_all(&(value.len() as u32).to_le_bytes())?;
+                self.w.write_all(value)?;
+            }
+            DumpFormat::Json => {
+                let entry = JsonEntry {
+                    key,
+                    value: BASE64.encode(value),
+                };
+                serde_json::to_writer(&mut self.w, &entry)?;
+                self.w.write_all(b"\n")?;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Возвращает число записанных пар.
+    pub fn finish(mut self) -> std::io::Result<u64> {
+        if self.format == DumpFormat::Binary {
+            self.w.write_all(&self.count.to_le_bytes())?;
+            let crc = self.w.hasher.clone().finalize();
+            self.w.inner.write_all(&crc.to_le_bytes())?;
+        }
+        self.w.inner.flush()?;
+        Ok(self.count)
+    }
+}
+
+/// Атомарно пишет все пары `core` в `path`, возвращает их число.
+/// Сначала собираются ключи, потом значения читаются по одному, так что
+/// шарды DashMap не блокируются на время записи файла, а дамп «размыт»
+/// по времени так же, как снапшот bgsave. Ключи идут по порядку, чтобы два
+/// дампа можно было сравнивать diff'ом. По истечении `deadline` недописанный
+/// файл удаляется, а `path` остаётся прежним.
+pub fn export(
+    core: &CacheCore,
+    path: &Path,
+    format: DumpFormat,
+    deadline: Deadline,
+) -> Result<u64, CacheError> {
+    let mut keys = core.keys_within("", deadline)?;
+    keys.sort_unstable();
+    let mut count = 0;
+    let mut expired = false;
+    replace_file(path, |f| {
+        let mut w = DumpWriter::new(f, format)?;
+        for (i, k) in keys.iter().enumerate() {
+            if deadline.checkpoint(i).is_err() {
+                expired = true;
+                return Err(std::io::Error::other("deadline exceeded"));
+            }
+            // ключ могли удалить, пока шёл дамп
+            if let Some(v) = core.get(k) {
+                w.write(k, &v)?;
+            }
+        }
+        count = w.finish()?;
+        Ok(())
+    })
+    .map_err(|e| {
+        if expired {
+            CacheError::DeadlineExceeded
+        } else {
+            CacheError::Internal(format!("write dump {}: {}", path.display(), e))
+        }
+    })?;
+    Ok(count)
+}
+
+/// Читает и целиком проверяет дамп (формат определяется по magic), ничего
+/// не применяя: битый файл отвергается до того, как тронут хоть один ключ.
+pub fn read(path: &Path) -> Result<Vec<DumpEntry>, CacheError> {
+    let data = fs::read(path)
+        .map_err(|e| CacheError::Internal(format!("read dump {}: {}", path.display(), e)))?;
+    let res = if data.starts_with(DUMP_MAGIC) {
+        read_binary(&data)
+    } else {
+        read_json(&data)
+    };
+    res.map_err(|e| CacheError::Internal(format!("dump {}: {}", path.display(), e)))
+}
+
+fn read_binary(data: &[u8]) -> Result<Vec<DumpEntry>, String> {
+    const HEADER_LEN: usize = 8;
+    const FOOTER_LEN: usize = 12;
+    if data.len() < HEADER_LEN + FOOTER_LEN {
+        return Err(format!("truncated: {} bytes", data.len()));
+    }
+    let version = u32::from_le_bytes(data[4..8].try_into().expect("4 bytes"));
+    if version != DUMP_VERSION {
+        return Err(format!("unsupported version {}", version));
+    }
+    let (body, crc) = data.split_at(data.len() - 4);
+    let crc = u32::from_le_bytes(crc.try_into().expect("4 bytes"));
+    if crc32fast::hash(body) != crc {
+        return Err("checksum mismatch".into());
+    }
+    let end = body.len() - 8;
+    let count = u64::from_le_bytes(body[end..].try_into().expect("8 bytes"));
+
+    let mut entries = Vec::new();
+    let mut pos = HEADER_LEN;
+    // границы поля «длина u32 | байты», начинающегося в `pos`
+    let field = |pos: usize| -> Result<(usize, usize), String> {
+        let truncated = || format!("truncated record at offset {}", pos);
+        if end - pos < 4 {
+            return Err(truncated());
+        }
+        let len = u32::from_le_bytes(data[pos..pos + 4].try_into().expect("4 bytes")) as usize;
+        if end - pos - 4 < len {
+            return Err(truncated());
+        }
+        Ok((pos + 4, pos + 4 + len))
+    };
+    while pos < end {
+        let start = pos;
+        let (ks, ke) = field(pos)?;
+        let key = std::str::from_utf8(&data[ks..ke])
+            .map_err(|_| format!("key at offset {} is not valid UTF-8", start))?
+            .to_string();
+        let (vs, ve) = field(ke)?;
+        entries.push(DumpEntry {
+            key,
+            value: data[vs..ve].to_vec(),
+            end: ve as u64,
+        });
+        pos = ve;
+    }
+    if entries.len() as u64 != count {
+        return Err(format!(
+            "footer says {} records, found {}",
+            count,
+            entries.len()
+        ));
+    }
+    Ok(entries)
+}
+
+fn read_json(data: &[u8]) -> Result<Vec<DumpEntry>, String> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    for (line_no, line) in data.split_inclusive(|&b| b == b'\n').enumerate() {
+        offset += line.len();
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let entry: JsonEntryOwned =
+            serde_json::from_slice(line).map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+        let value = BASE64
+            .decode(entry.value.as_bytes())
+            .map_err(|e| format!("line {}: bad base64 value: {}", line_no + 1, e))?;
+        entries.push(DumpEntry {
+            key: entry.key,
+            value,
+            end: offset as u64,
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,268 @@
+//! Кодировка значений, см. `ClientOptions::compress`: клиент сжимает большое
+//! значение сам, перед отправкой, и сервер хранит его как есть — в памяти, в
+//! WAL и снапшоте, у реплик. Сжатое значение начинается с `ENC_MAGIC` и байта
+//! `ValueEncoding`; что без заголовка — сырые байты, как и прежде.
+//!
+//! Распаковывает тот, кто читает: клиенты протокола 5 — у себя (ответ
+//! проходит через `decode_response`), а соединениям старше 5 (в том числе
+//! без `Hello`, подпискам и watch) сервер отдаёт значения уже распакованными,
+//! см. `CacheResponse::for_protocol`. Их сырые значения, похожие на заголовок,
+//! сервер при записи сам помечает как `Raw`, см. `escape_legacy`.
+
+use crate::error::CacheError;
+use crate::{CacheCommand, CacheResponse, MAX_RESPONSE_SIZE};
+use std::borrow::Cow;
+use std::io::Read;
+
+/// Заголовок закодированного значения, за ним — байт кодировки. NUL в начале,
+/// как у `OBJ_MAGIC`, не даёт спутать его с текстом.
+pub(crate) const ENC_MAGIC: &[u8] = b"\x00TMCENC";
+
+/// Порог `ClientOptions::compress_min` по умолчанию: значения короче не
+/// сжимаются.
+pub const DEFAULT_COMPRESS_MIN: usize = 16 * 1024;
+
+/// Кодировка значения; `Raw` — сырые байты.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ValueEncoding {
+    #[default]
+    Raw,
+    Zstd,
+    Lz4,
+}
+
+impl ValueEncoding {
+    fn tag(self) -> u8 {
+        match self {
+            ValueEncoding::Raw => 0,
+            ValueEncoding::Zstd => 1,
+            ValueEncoding::Lz4 => 2,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ValueEncoding::Raw => "raw",
+            ValueEncoding::Zstd => "zstd",
+            ValueEncoding::Lz4 => "lz4",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, CacheError> {
+        match s {
+            "raw" => Ok(ValueEncoding::Raw),
+            "zstd" => Ok(ValueEncoding::Zstd),
+            "lz4" => Ok(ValueEncoding::Lz4),
+            other => Err(CacheError::Unsupported(format!(
+                "unknown value encoding {:?}, expected raw, zstd or lz4",
+                other
+            ))),
+        }
+    }
+
+    /// Кодировка хранимого значения по его заголовку.
+    pub fn of(value: &[u8]) -> Self {
+        match value.strip_prefix(ENC_MAGIC).and_then(<[u8]>::first) {
+            Some(1) => ValueEncoding::Zstd,
+            Some(2) => ValueEncoding::Lz4,
+            _ => ValueEncoding::Raw,
+        }
+    }
+}
+
+/// `value` в кодировке `encoding` с заголовком; `None` — отправить как есть:
+/// `Raw` или сжатие не сделало значение короче. Сырое значение, которое само
+/// начинается с `ENC_MAGIC`, получает заголовок `Raw`, чтобы читатель его не
+/// распаковывал.
+pub fn encode_value(value: &[u8], encoding: ValueEncoding) -> Option<Vec<u8>> {
+    let header = |encoding: ValueEncoding| {
+        let mut out = Vec::with_capacity(ENC_MAGIC.len() + 1 + value.len() / 2);
+        out.extend_from_slice(ENC_MAGIC);
+        out.push(encoding.tag());
+        out
+    };
+    let escaped = || {
+        value.starts_with(ENC_MAGIC).then(|| {
+            let mut out = header(ValueEncoding::Raw);
+            out.extend_from_slice(value);
+            out
+        })
+    };
+    let mut out = header(encoding);
+    match encoding {
+        ValueEncoding::Raw => return escaped(),
+        ValueEncoding::Zstd => {
+            ruzstd::encoding::compress(value, &mut out, ruzstd::encoding::CompressionLevel::Fastest)
+        }
+        ValueEncoding::Lz4 => out.extend(lz4_flex::block::compress_prepend_size(value)),
+    }
+    if out.len() < value.len() {
+        Some(out)
+    } else {
+        escaped()
+    }
+}
+
+/// Сжатие значений клиента: `encoding` для значений от `min_size` байт.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Compress {
+    pub encoding: ValueEncoding,
+    pub min_size: usize,
+}
+
+impl Compress {
+    /// Значение для записи: `None` — отправить как есть. `proto` — версия
+    /// сервера; она нужна, только если значение что-то получит: сервер старше
+    /// 5 отдал бы сжатое старым клиентам как есть.
+    pub(crate) fn apply(
+        self,
+        value: &[u8],
+        proto: impl FnOnce() -> Result<u32, CacheError>,
+    ) -> Result<Option<Vec<u8>>, CacheError> {
+        let encoding = match self.encoding {
+            encoding if self.may_encode(value.len()) => encoding,
+            _ if value.starts_with(ENC_MAGIC) => ValueEncoding::Raw,
+            _ => return Ok(None),
+        };
+        if proto()? < 5 {
+            return Ok(None);
+        }
+        Ok(encode_value(value, encoding))
+    }
+
+    /// Сожмётся ли значение длины `len` (если сжатие его укоротит).
+    pub(crate) fn may_encode(self, len: usize) -> bool {
+        self.encoding != ValueEncoding::Raw && len >= self.min_size
+    }
+}
+
+/// Сырые байты значения: без заголовка отдаётся как есть, без копии.
+/// Распакованное больше `MAX_RESPONSE_SIZE` — ошибка, как и битые данные.
+pub fn decode_value(value: Vec<u8>) -> Result<Vec<u8>, CacheError> {
+    let Some(rest) = value.strip_prefix(ENC_MAGIC) else {
+        return Ok(value);
+    };
+    let bad = |what: &str| CacheError::Decode(what.to_string());
+    let (&tag, data) = rest.split_first().ok_or_else(|| bad("no encoding byte"))?;
+    match tag {
+        0 => Ok(data.to_vec()),
+        1 => {
+            let decoder = ruzstd::decoding::StreamingDecoder::new(data)
+                .map_err(|e| CacheError::Decode(format!("zstd: {}", e)))?;
+            let mut out = Vec::new();
+            decoder
+                .take(MAX_RESPONSE_SIZE as u64 + 1)
+                .read_to_end(&mut out)
+                .map_err(|e| CacheError::Decode(format!("zstd: {}", e)))?;
+            if out.len() > MAX_RESPONSE_SIZE {
+                return Err(too_large());
+            }
+            Ok(out)
+        }
+        2 => {
+            let (size, body) = lz4_flex::block::uncompressed_size(data)
+                .map_err(|e| CacheError::Decode(format!("lz4: {}", e)))?;
+            if size > MAX_RESPONSE_SIZE {
+                return Err(too_large());
+            }
+            lz4_flex::block::decompress(body, size)
+                .map_err(|e| CacheError::Decode(format!("lz4: {}", e)))
+        }
+        t => Err(CacheError::Decode(format!("unknown encoding byte {}", t))),
+    }
+}
+
+fn too_large() -> CacheError {
+    CacheError::Decode(format!("decoded value is over {} bytes", MAX_RESPONSE_SIZE))
+}
+
+/// Хранимое значение `stored` — то же, что сырое `expected`: для DelIfEquals
+/// и PExtendIfEquals, которым клиент шлёт несжатое значение.
+pub(crate) fn same_value(stored: &[u8], expected: &[u8]) -> bool {
+    stored == expected
+        || (stored.starts_with(ENC_MAGIC)
+            && decode_value(stored.to_vec()).is_ok_and(|v| v == expected))
+}
+
+/// Байты значения для чтения на сервере (GetBit, BitCount); битое сжатое
+/// значение читается как хранится.
+pub(crate) fn raw_bytes(value: &[u8]) -> Cow<'_, [u8]> {
+    match value.starts_with(ENC_MAGIC) {
+        true => decode_value(value.to_vec()).map_or(Cow::Borrowed(value), Cow::Owned),
+        false => Cow::Borrowed(value),
+    }
+}
+
+/// Ошибка для команд, которым нужны сырые байты значения (SetBit, Update):
+/// сжатое значение сервер на месте не распаковывает.
+pub(crate) fn check_raw(key: &str, value: &[u8]) -> Result<(), CacheError> {
+    match ValueEncoding::of(value) {
+        ValueEncoding::Raw => Ok(()),
+        encoding => Err(CacheError::Unsupported(format!(
+            "value of key {:?} is {}-compressed",
+            key,
+            encoding.as_str()
+        ))),
+    }
+}
+
+/// Записываемые значения команды соединения старше протокола 5: такой клиент
+/// о заголовках не знает, так что сырое значение, само начинающееся с
+/// `ENC_MAGIC`, получает заголовок `Raw` — иначе `for_protocol` при чтении
+/// отдал бы ему другие байты или ошибку. `Update` не трогается: его байты
+/// складываются с текущим значением на месте.
+pub(crate) fn escape_legacy(cmd: &mut CacheCommand) {
+    let escape = |v: &mut Vec<u8>| {
+        if let Some(escaped) = encode_value(v, ValueEncoding::Raw) {
+            *v = escaped;
+        }
+    };
+    match cmd {
+        CacheCommand::Set(_, v)
+        | CacheCommand::GetOrSet(_, v)
+        | CacheCommand::PSetEx(_, _, v)
+        | CacheCommand::SetWithTags(_, _, v)
+        | CacheCommand::SetNx(_, _, v) => escape(v),
+        CacheCommand::MSet(items) => items.iter_mut().for_each(|(_, v)| escape(v)),
+        CacheCommand::Admin(_, cmd) => escape_legacy(cmd),
+        _ => {}
+    }
+}
+
+/// Ответ с распакованными значениями; без сжатых значений — тот же ответ.
+pub(crate) fn decode_response(resp: CacheResponse) -> Result<CacheResponse, CacheError> {
+    let items = |items: Vec<(String, Vec<u8>)>| {
+        items
+            .into_iter()
+            .map(|(k, v)| Ok((k, decode_value(v)?)))
+            .collect::<Result<Vec<_>, CacheError>>()
+    };
+    Ok(match resp {
+        CacheResponse::Value(v) => CacheResponse::Value(decode_value(v)?),
+        CacheResponse::Entry(v, inserted) => CacheResponse::Entry(decode_value(v)?, inserted),
+        CacheResponse::Items(next, page) => CacheResponse::Items(next, items(page)?),
+        CacheResponse::ItemsChunk(next, part, more) => {
+            CacheResponse::ItemsChunk(next, items(part)?, more)
+        }
+        CacheResponse::History(list) => CacheResponse::History(
+            list.into_iter()
+                .map(|(at, v)| Ok((at, decode_value(v)?)))
+                .collect::<Result<_, CacheError>>()?,
+        ),
+        CacheResponse::Values(values) => CacheResponse::Values(
+            values
+                .into_iter()
+                .map(|v| v.map(decode_value).transpose())
+                .collect::<Result<_, CacheError>>()?,
+        ),
+        CacheResponse::Meta(mut meta) => {
+            meta.value = decode_value(meta.value)?;
+            CacheResponse::Meta(meta)
+        }
+        CacheResponse::Event(mut event) => {
+            event.value = event.value.map(decode_value).transpose()?;
+            CacheResponse::Event(event)
+        }
+        resp => resp,
+    })
+}
@@ -1,6 +1,8 @@
+use std::fmt;
+use std::io;
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum CacheError {
     #[error("network error: {0}")]
     Network(String),
@@ -10,4 +12,152 @@ pub enum CacheError {
 
     #[error("internal error: {0}")]
     Internal(String),
+
+    #[error("busy: {0}")]
+    Busy(String),
+
+    #[error("not supported: {0}")]
+    Unsupported(String),
+
+    // запись в реплику или в сервер в режиме только чтения
+    #[error("read-only {0}")]
+    ReadOnly(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    // ошибка, которую сервер вернул клиенту в CacheResponse::Error
+    #[error("server error: {0}")]
+    Server(String),
+
+    // крайний срок команды истёк до или во время её выполнения (протокол 2)
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+
+    // вызов после close() клиента
+    #[error("client is closed")]
+    Closed,
+
+    // сервер не смог открыть listener
+    #[error("{0}")]
+    Bind(BindFailure),
+
+    // команда сверх лимита частоты соединения; через сколько мс повторить
+    #[error("rate limited, retry after {0}ms")]
+    RateLimited(u64),
+
+    // ключ отклонён политикой ключей сервера, см. keys.rs
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+
+    // значение ключа не сошлось со своим crc32, см.
+    // PersistentCore::set_verify_values
+    #[error("value checksum mismatch for key {0:?}")]
+    Checksum(String),
+
+    // сервер отверг кадр команды: больше предела или не разобрался;
+    // соединение при этом живо
+    #[error("protocol error: {0}")]
+    Protocol(String),
+
+    // ответ больше предела кадра (размер и предел в байтах): сузьте запрос,
+    // см. PersistentCore::set_max_response_size
+    #[error("response too large: {0} bytes, limit {1}")]
+    ResponseTooLarge(u64, u64),
+
+    // сжатое значение не распаковалось, см. ClientOptions::compress
+    #[error("cannot decode value: {0}")]
+    Decode(String),
+}
+
+impl CacheError {
+    /// Ошибка из ответа сервера. Ответ несёт только текст, поэтому
+    /// `InvalidKey`, `Protocol`, `ResponseTooLarge`, `Decode` и `Checksum` узнаются по
+    /// нему, а остальное — `Server`.
+    pub(crate) fn from_server(msg: String) -> Self {
+        if let Some(detail) = msg.strip_prefix("invalid key: ") {
+            return CacheError::InvalidKey(detail.to_string());
+        }
+        if let Some(detail) = msg.strip_prefix("protocol error: ") {
+            return CacheError::Protocol(detail.to_string());
+        }
+        if let Some(detail) = msg.strip_prefix("cannot decode value: ") {
+            return CacheError::Decode(detail.to_string());
+        }
+        if let Some(sizes) = msg
+            .strip_prefix("response too large: ")
+            .and_then(|s| s.split_once(" bytes, limit "))
+            .and_then(|(size, limit)| Some((size.parse().ok()?, limit.parse().ok()?)))
+        {
+            return CacheError::ResponseTooLarge(sizes.0, sizes.1);
+        }
+        // ключ в сообщении — в кавычках Debug; экранирование почти всегда
+        // совпадает с JSON
+        match msg.strip_prefix("value checksum mismatch for key ") {
+            Some(key) => CacheError::Checksum(
+                serde_json::from_str(key).unwrap_or_else(|_| key.trim_matches('"').to_string()),
+            ),
+            None => CacheError::Server(msg),
+        }
+    }
+}
+
+/// Почему не открылся listener, для тех, кто разбирает ошибку программно.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindReason {
+    AddressInUse,
+    PermissionDenied,
+    // адрес не разобрался или не резолвится, каталога сокета нет
+    InvalidAddress,
+    Other,
+}
+
+impl BindReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BindReason::AddressInUse => "address_in_use",
+            BindReason::PermissionDenied => "permission_denied",
+            BindReason::InvalidAddress => "invalid_address",
+            BindReason::Other => "other",
+        }
+    }
+}
+
+/// Ошибка `serve_tcp`/`serve_unix_socket`: bind или удаление старого файла
+/// сокета.
+#[derive(Clone, Debug)]
+pub struct BindFailure {
+    // "127.0.0.1:5002" или путь сокета
+    pub addr: String,
+    pub reason: BindReason,
+    pub errno: Option<i32>,
+    message: String,
+}
+
+impl BindFailure {
+    pub(crate) fn new(addr: impl Into<String>, action: &str, e: &io::Error) -> Self {
+        let addr = addr.into();
+        let reason = match e.kind() {
+            io::ErrorKind::AddrInUse => BindReason::AddressInUse,
+            io::ErrorKind::PermissionDenied => BindReason::PermissionDenied,
+            io::ErrorKind::InvalidInput
+            | io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::NotFound => BindReason::InvalidAddress,
+            // ошибки разбора и резолвинга адреса приходят без errno
+            _ if e.raw_os_error().is_none() => BindReason::InvalidAddress,
+            _ => BindReason::Other,
+        };
+        Self {
+            message: format!("cannot {} {}: {}", action, addr, e),
+            addr,
+            reason,
+            errno: e.raw_os_error(),
+        }
+    }
+}
+
+impl fmt::Display for BindFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
 }
@@ -0,0 +1,162 @@
+//! Поток записей для внешнего потребителя, см. `PersistentCore::set_write_feed`:
+//! мутации ключей с префиксом кладутся событием `WatchEvent` в ограниченную
+//! очередь, которую разбирает поток потребителя (например, зеркалирование в
+//! базу). Путь записи только кладёт событие; что делать с полной очередью,
+//! задаёт `Overflow`.
+
+use crate::error::CacheError;
+use crate::watch::{WatchEvent, WatchOp};
+use crate::ResponseValue;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Как часто потребитель проверяет, не закрыт ли поток, пока очередь пуста.
+const CLOSE_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// запись ждёт места в очереди: события не теряются, но медленный
+    /// потребитель тормозит запись
+    Block,
+    /// событие выбрасывается и считается в `write_feed_dropped`
+    Drop,
+}
+
+impl FromStr for Overflow {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Overflow::Block),
+            "drop" => Ok(Overflow::Drop),
+            other => Err(CacheError::Unsupported(format!(
+                "unknown overflow policy {:?}, expected block/drop",
+                other
+            ))),
+        }
+    }
+}
+
+pub struct WriteFeed {
+    prefix: String,
+    overflow: Overflow,
+    tx: SyncSender<WatchEvent>,
+    closed: AtomicBool,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Сторона потребителя: события до закрытия потока и дочитывание остатка.
+pub struct FeedReceiver {
+    feed: Arc<WriteFeed>,
+    rx: Receiver<WatchEvent>,
+    // отпускается, когда потребитель дочитал очередь, см. Drain::wait
+    done: Option<SyncSender<()>>,
+}
+
+/// Ждёт, пока потребитель дочитает очередь после `WriteFeed::close`.
+pub struct Drain {
+    done: Receiver<()>,
+}
+
+impl WriteFeed {
+    /// Поток для ключей с `prefix` на `capacity` событий; `FeedReceiver`
+    /// отдаётся потоку потребителя.
+    pub fn new(
+        prefix: String,
+        capacity: usize,
+        overflow: Overflow,
+    ) -> (Arc<WriteFeed>, FeedReceiver, Drain) {
+        let (tx, rx) = mpsc::sync_channel(capacity.max(1));
+        let (done_tx, done) = mpsc::sync_channel(0);
+        let feed = Arc::new(WriteFeed {
+            prefix,
+            overflow,
+            tx,
+            closed: AtomicBool::new(false),
+            sent: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        let receiver = FeedReceiver {
+            feed: Arc::clone(&feed),
+            rx,
+            done: Some(done_tx),
+        };
+        (feed, receiver, Drain { done })
+    }
+
+    pub(crate) fn push(&self, key: &str, op: WatchOp, value: Option<&[u8]>) {
+        if !key.starts_with(&self.prefix) || self.closed.load(Ordering::Acquire) {
+            return;
+        }
+        let event = WatchEvent {
+            key: key.to_string(),
+            op,
+            value_size: value.map_or(0, |v| v.len() as u64),
+            value: value.map(<[u8]>::to_vec),
+        };
+        let sent = match self.overflow {
+            Overflow::Block => self.tx.send(event).is_ok(),
+            Overflow::Drop => match self.tx.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        };
+        if sent {
+            self.sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Новые события больше не принимаются; уже поставленные потребитель
+    /// дочитывает.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+
+    pub fn info_fields(&self) -> Vec<(&'static str, ResponseValue)> {
+        vec![
+            ("write_feed_sent", self.sent.load(Ordering::Relaxed).into()),
+            (
+                "write_feed_dropped",
+                self.dropped.load(Ordering::Relaxed).into(),
+            ),
+        ]
+    }
+}
+
+/// События до закрытия потока: `None` — поток закрыт и очередь пуста.
+impl Iterator for FeedReceiver {
+    type Item = WatchEvent;
+
+    fn next(&mut self) -> Option<WatchEvent> {
+        loop {
+            match self.rx.recv_timeout(CLOSE_POLL) {
+                Ok(event) => return Some(event),
+                Err(RecvTimeoutError::Timeout) if !self.feed.closed.load(Ordering::Acquire) => {}
+                Err(_) => {
+                    self.done = None;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+impl Drain {
+    /// Ждёт до `timeout`, пока потребитель дочитает очередь; false — не
+    /// успел. Вызывать после `WriteFeed::close`.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        // по done ничего не шлют: конец очереди — закрытие канала
+        matches!(
+            self.done.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        )
+    }
+}
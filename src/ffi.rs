@@ -0,0 +1,202 @@
+//! Минимальный C ABI поверх [`Client`] для встраивания в C/C++.
+//! Заголовок `include/tiny_mp_cache.h` генерируется cbindgen из этого файла,
+//! так что соглашения о владении описаны в doc-комментариях ниже.
+
+use crate::client::Client;
+use crate::error::CacheError;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+use std::slice;
+
+/// Непрозрачный клиент: одно постоянное соединение с сервером. Вызовы из
+/// разных потоков безопасны и идут по очереди.
+pub struct TmcClient {
+    inner: Client,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    // NUL внутри сообщения обрезал бы строку на стороне C
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|e| *e.borrow_mut() = None);
+}
+
+fn fail(e: CacheError) -> i32 {
+    set_last_error(e.to_string());
+    -1
+}
+
+unsafe fn str_arg<'a>(p: *const c_char, name: &str) -> Option<&'a str> {
+    if p.is_null() {
+        set_last_error(format!("{} is NULL", name));
+        return None;
+    }
+    match CStr::from_ptr(p).to_str() {
+        Ok(s) => Some(s),
+        Err(_) => {
+            set_last_error(format!("{} is not valid UTF-8", name));
+            None
+        }
+    }
+}
+
+unsafe fn client_arg<'a>(client: *const TmcClient) -> Option<&'a Client> {
+    if client.is_null() {
+        set_last_error("client is NULL".into());
+        return None;
+    }
+    Some(&(*client).inner)
+}
+
+/// Подключается к серверу по адресу `"host:port"`, `"tcp://host:port"` или
+/// `"unix:///path/to.sock"`.
+///
+/// Возвращает клиента, которым владеет вызывающий (освобождать через
+/// `tmc_client_free`), или NULL при ошибке — причина в `tmc_last_error()`.
+///
+/// # Safety
+/// `addr` — NUL-терминированная строка UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn tmc_client_new(addr: *const c_char) -> *mut TmcClient {
+    clear_last_error();
+    let Some(addr) = str_arg(addr, "addr") else {
+        return ptr::null_mut();
+    };
+    match Client::connect(addr) {
+        Ok(inner) => Box::into_raw(Box::new(TmcClient { inner })),
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Закрывает соединение и освобождает клиента. NULL допустим.
+///
+/// # Safety
+/// `client` получен из `tmc_client_new` и ещё не освобождён.
+#[no_mangle]
+pub unsafe extern "C" fn tmc_client_free(client: *mut TmcClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Записывает `value_len` байт из `value` под ключ `key`.
+///
+/// Возвращает 0 при успехе и -1 при ошибке. Буфер `value` только читается
+/// и остаётся за вызывающим.
+///
+/// # Safety
+/// `client` — живой клиент, `key` — NUL-терминированная строка UTF-8,
+/// `value` указывает на `value_len` байт (может быть NULL при длине 0).
+#[no_mangle]
+pub unsafe extern "C" fn tmc_set(
+    client: *const TmcClient,
+    key: *const c_char,
+    value: *const u8,
+    value_len: usize,
+) -> i32 {
+    clear_last_error();
+    let (Some(client), Some(key)) = (client_arg(client), str_arg(key, "key")) else {
+        return -1;
+    };
+    let value = if value_len == 0 {
+        &[][..]
+    } else if value.is_null() {
+        set_last_error("value is NULL".into());
+        return -1;
+    } else {
+        slice::from_raw_parts(value, value_len)
+    };
+    match client.set(key, value) {
+        Ok(()) => 0,
+        Err(e) => fail(e),
+    }
+}
+
+/// Читает значение `key`.
+///
+/// Возвращает 1, если ключ найден: тогда в `*out` лежит буфер длиной
+/// `*out_len`, которым теперь владеет вызывающий и который освобождается
+/// только через `tmc_free(*out, *out_len)`. Возвращает 0, если ключа нет
+/// (`*out` = NULL, `*out_len` = 0), и -1 при ошибке.
+///
+/// # Safety
+/// `client` — живой клиент, `key` — NUL-терминированная строка UTF-8,
+/// `out` и `out_len` — валидные указатели для записи.
+#[no_mangle]
+pub unsafe extern "C" fn tmc_get(
+    client: *const TmcClient,
+    key: *const c_char,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    clear_last_error();
+    if out.is_null() || out_len.is_null() {
+        set_last_error("out is NULL".into());
+        return -1;
+    }
+    *out = ptr::null_mut();
+    *out_len = 0;
+    let (Some(client), Some(key)) = (client_arg(client), str_arg(key, "key")) else {
+        return -1;
+    };
+    match client.get(key) {
+        Ok(Some(v)) => {
+            let buf = v.into_boxed_slice();
+            *out_len = buf.len();
+            *out = Box::into_raw(buf) as *mut u8;
+            1
+        }
+        Ok(None) => 0,
+        Err(e) => fail(e),
+    }
+}
+
+/// Освобождает буфер из `tmc_get`; `len` — ровно тот `*out_len`, что
+/// вернул `tmc_get`. NULL допустим.
+///
+/// # Safety
+/// `buf` получен из `tmc_get` и ещё не освобождён.
+#[no_mangle]
+pub unsafe extern "C" fn tmc_free(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)));
+    }
+}
+
+/// Удаляет `key`. Возвращает число удалённых ключей (0 или 1) или -1 при
+/// ошибке.
+///
+/// # Safety
+/// `client` — живой клиент, `key` — NUL-терминированная строка UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn tmc_delete(client: *const TmcClient, key: *const c_char) -> i64 {
+    clear_last_error();
+    let (Some(client), Some(key)) = (client_arg(client), str_arg(key, "key")) else {
+        return -1;
+    };
+    match client.delete(key) {
+        Ok(n) => n,
+        Err(e) => fail(e).into(),
+    }
+}
+
+/// Текст последней ошибки в этом потоке или NULL, если последний вызов
+/// `tmc_*` завершился успешно.
+///
+/// Строка принадлежит библиотеке и живёт до следующего вызова `tmc_*` из
+/// того же потока; освобождать её не нужно.
+#[no_mangle]
+pub extern "C" fn tmc_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
@@ -0,0 +1,195 @@
+//! Прежние значения ключей для отладки (`CacheCommand::History`), см.
+//! `PersistentCore::set_history_depth`. Хранятся только в памяти: в WAL,
+//! снапшоты и на реплики не попадают.
+
+use crate::core::now_unix_ms;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, RwLock};
+
+/// Наибольшая глубина истории одного ключа.
+pub(crate) const MAX_HISTORY_DEPTH: u32 = 1000;
+
+/// Сколько байт (ключи и значения) занимает вся история. Сверх этого первыми
+/// выбрасываются самые старые версии, а не живые ключи.
+pub(crate) const HISTORY_MAX_BYTES: usize = 64 << 20;
+
+struct Version {
+    // порядковый номер записи в истории, общий для всех ключей
+    seq: u64,
+    // unix-время в мс, когда значение заменили или удалили
+    at: u64,
+    value: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Store {
+    keys: HashMap<String, VecDeque<Version>>,
+    // (seq, ключ) в порядке записи, для вытеснения старых версий. Версии,
+    // уже убранные по глубине, остаются здесь, пока их не вычистит `tidy`
+    order: VecDeque<(u64, String)>,
+    next_seq: u64,
+    versions: usize,
+    bytes: usize,
+    dropped: u64,
+}
+
+impl Store {
+    fn cost(key: &str, v: &Version) -> usize {
+        key.len() + v.value.len()
+    }
+
+    // версия с этим seq ещё хранится: версии ключа идут по возрастанию seq
+    // и убираются с головы
+    fn holds(&self, seq: u64, key: &str) -> bool {
+        self.keys
+            .get(key)
+            .and_then(|d| d.front())
+            .is_some_and(|v| v.seq <= seq)
+    }
+
+    fn trim(&mut self, key: &str, depth: usize) {
+        let Some(versions) = self.keys.get_mut(key) else {
+            return;
+        };
+        while versions.len() > depth {
+            let v = versions.pop_front().expect("longer than depth");
+            self.bytes -= Self::cost(key, &v);
+            self.versions -= 1;
+        }
+        if versions.is_empty() {
+            self.keys.remove(key);
+        }
+    }
+
+    // самая старая версия среди всех ключей
+    fn drop_oldest(&mut self) {
+        while let Some((seq, key)) = self.order.pop_front() {
+            let Some(versions) = self.keys.get_mut(&key) else {
+                continue;
+            };
+            if versions.front().map(|v| v.seq) != Some(seq) {
+                continue;
+            }
+            let v = versions.pop_front().expect("front checked above");
+            if versions.is_empty() {
+                self.keys.remove(&key);
+            }
+            self.bytes -= Self::cost(&key, &v);
+            self.versions -= 1;
+            self.dropped += 1;
+            return;
+        }
+    }
+
+    // `order` не растёт без предела, пока версии уходят по глубине
+    fn tidy(&mut self) {
+        if self.order.len() > 2 * self.versions + 1024 {
+            let order = std::mem::take(&mut self.order);
+            self.order = order
+                .into_iter()
+                .filter(|(seq, key)| self.holds(*seq, key))
+                .collect();
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct History {
+    // (префикс, глубина); действует самый длинный подходящий префикс, так
+    // что глубина 0 выключает историю под префиксом с правилом короче
+    rules: RwLock<Vec<(String, usize)>>,
+    // есть ли правило с ненулевой глубиной; без него запись ничего не стоит
+    enabled: AtomicBool,
+    store: Mutex<Store>,
+}
+
+/// Числа для Info.
+pub(crate) struct HistoryStats {
+    pub(crate) keys: u64,
+    pub(crate) versions: u64,
+    pub(crate) bytes: u64,
+    pub(crate) dropped: u64,
+}
+
+impl History {
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn depth(&self, key: &str) -> usize {
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner());
+        rules
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(0, |&(_, depth)| depth)
+    }
+
+    /// Глубина истории ключей на `prefix`. Уже накопленная история
+    /// укорачивается под новые правила.
+    pub(crate) fn set_depth(&self, prefix: String, depth: usize) {
+        {
+            let mut rules = self.rules.write().unwrap_or_else(|e| e.into_inner());
+            rules.retain(|(p, _)| *p != prefix);
+            rules.push((prefix, depth));
+            let enabled = rules.iter().any(|&(_, depth)| depth > 0);
+            self.enabled.store(enabled, Ordering::Relaxed);
+        }
+        let mut store = self.lock();
+        let keys: Vec<String> = store.keys.keys().cloned().collect();
+        for key in keys {
+            store.trim(&key, self.depth(&key));
+        }
+        store.tidy();
+    }
+
+    /// Запоминает значение, которое у ключа сейчас заменят или удалят.
+    /// Вызывается под блокировкой шарда ключа; сама история шардов не берёт.
+    pub(crate) fn record(&self, key: &str, value: &[u8]) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let depth = self.depth(key);
+        if depth == 0 {
+            return;
+        }
+        let mut store = self.lock();
+        let seq = store.next_seq;
+        store.next_seq += 1;
+        let v = Version {
+            seq,
+            at: now_unix_ms(),
+            value: value.to_vec(),
+        };
+        store.bytes += Store::cost(key, &v);
+        store.versions += 1;
+        store.order.push_back((seq, key.to_string()));
+        store.keys.entry(key.to_string()).or_default().push_back(v);
+        store.trim(key, depth);
+        while store.bytes > HISTORY_MAX_BYTES && !store.order.is_empty() {
+            store.drop_oldest();
+        }
+        store.tidy();
+    }
+
+    /// Прежние значения ключа от старых к новым: (unix-время в мс замены,
+    /// значение).
+    pub(crate) fn get(&self, key: &str) -> Vec<(u64, Vec<u8>)> {
+        self.lock()
+            .keys
+            .get(key)
+            .map(|d| d.iter().map(|v| (v.at, v.value.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn stats(&self) -> HistoryStats {
+        let store = self.lock();
+        HistoryStats {
+            keys: store.keys.len() as u64,
+            versions: store.versions as u64,
+            bytes: store.bytes as u64,
+            dropped: store.dropped,
+        }
+    }
+}
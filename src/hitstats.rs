@@ -0,0 +1,180 @@
+//! Попадания и промахи чтений (`Get`, `GetWithMeta`, `GetOrSet`,
+//! `MGetConsistent`), всего и по префиксам ключей, см.
+//! `PersistentCore::set_hit_stats_prefix`. Префикс — первые `depth`
+//! сегментов ключа без последнего разделителя (`"sessions"` у
+//! `"sessions:42"`); ключ без разделителя считается в префиксе `""`. Число
+//! префиксов ограничено: чтения новых префиксов сверх предела идут в общий
+//! счётчик `other`. На чтение — одна атомарная операция, а с префиксами ещё
+//! поиск короткой строки в хеш-таблице под блокировкой на чтение.
+
+use crate::error::CacheError;
+use crate::ResponseValue;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{RwLock, RwLockReadGuard};
+
+/// Предел числа префиксов по умолчанию.
+pub const DEFAULT_STATS_PREFIXES: usize = 256;
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Counters {
+    fn add(&self, hit: bool) {
+        let n = if hit { &self.hits } else { &self.misses };
+        n.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn fields(&self) -> Vec<(String, ResponseValue)> {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        // без чтений — 0, а не NaN
+        let rate = match hits + misses {
+            0 => 0.0,
+            total => hits as f64 / total as f64,
+        };
+        vec![
+            ("hits".into(), hits.into()),
+            ("misses".into(), misses.into()),
+            ("hit_rate".into(), rate.into()),
+        ]
+    }
+}
+
+struct ByPrefix {
+    depth: usize,
+    sep: String,
+    max_prefixes: usize,
+    prefixes: HashMap<String, Counters>,
+    other: Counters,
+}
+
+impl ByPrefix {
+    fn prefix_of<'k>(&self, key: &'k str) -> &'k str {
+        let end = key
+            .match_indices(self.sep.as_str())
+            .nth(self.depth - 1)
+            .map(|(at, _)| at)
+            .or_else(|| key.rfind(self.sep.as_str()))
+            .unwrap_or(0);
+        &key[..end]
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct HitStats {
+    total: Counters,
+    // None — по префиксам не считается (по умолчанию)
+    by_prefix: RwLock<Option<ByPrefix>>,
+}
+
+impl HitStats {
+    fn read(&self) -> RwLockReadGuard<'_, Option<ByPrefix>> {
+        self.by_prefix.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Счёт по префиксам из `depth` сегментов через `sep`, не больше
+    /// `max_prefixes` префиксов; `depth == 0` выключает его. Прежние счётчики
+    /// префиксов сбрасываются.
+    pub(crate) fn set_prefix(
+        &self,
+        depth: u32,
+        sep: &str,
+        max_prefixes: usize,
+    ) -> Result<(), CacheError> {
+        let by_prefix = match depth {
+            0 => None,
+            _ if sep.is_empty() => {
+                return Err(CacheError::Unsupported(
+                    "stats_prefix_sep must not be empty".into(),
+                ))
+            }
+            depth => Some(ByPrefix {
+                depth: depth as usize,
+                sep: sep.to_string(),
+                max_prefixes,
+                prefixes: HashMap::new(),
+                other: Counters::default(),
+            }),
+        };
+        *self.by_prefix.write().unwrap_or_else(|e| e.into_inner()) = by_prefix;
+        Ok(())
+    }
+
+    /// Настройки для `ConfigDump`: выключенный счёт по префиксам — глубина 0,
+    /// пустой разделитель и предел 0.
+    pub(crate) fn config_fields(&self) -> Vec<(String, ResponseValue)> {
+        let by_prefix = self.read();
+        let (depth, sep, max) = match &*by_prefix {
+            Some(by) => (by.depth, by.sep.as_str(), by.max_prefixes),
+            None => (0, "", 0),
+        };
+        vec![
+            ("stats_prefix_depth".into(), depth.into()),
+            ("stats_prefix_sep".into(), sep.into()),
+            ("stats_max_prefixes".into(), max.into()),
+        ]
+    }
+
+    /// Учитывает чтение ключа `key`: `hit` — значение нашлось.
+    pub(crate) fn record(&self, key: &str, hit: bool) {
+        self.total.add(hit);
+        let by_prefix = self.read();
+        let Some(by) = &*by_prefix else {
+            return;
+        };
+        let prefix = by.prefix_of(key);
+        if let Some(counters) = by.prefixes.get(prefix) {
+            counters.add(hit);
+            return;
+        }
+        if by.prefixes.len() >= by.max_prefixes {
+            by.other.add(hit);
+            return;
+        }
+        drop(by_prefix);
+        // новый префикс: пока ждали записи, его мог завести другой поток
+        let mut by_prefix = self.by_prefix.write().unwrap_or_else(|e| e.into_inner());
+        let Some(by) = &mut *by_prefix else {
+            return;
+        };
+        let prefix = by.prefix_of(key);
+        match by.prefixes.get(prefix) {
+            Some(counters) => counters.add(hit),
+            None if by.prefixes.len() >= by.max_prefixes => by.other.add(hit),
+            None => {
+                let counters = Counters::default();
+                counters.add(hit);
+                by.prefixes.insert(prefix.to_string(), counters);
+            }
+        }
+    }
+
+    /// Ответ `Stats`: `hits`, `misses`, `hit_rate` всего и `by_prefix` —
+    /// префикс -> те же поля, от самых частых чтений; `other` — префиксы
+    /// сверх предела, только если такие были.
+    pub(crate) fn fields(&self) -> Vec<(String, ResponseValue)> {
+        let mut fields = self.total.fields();
+        let by_prefix = self.read();
+        let Some(by) = &*by_prefix else {
+            fields.push(("by_prefix".into(), ResponseValue::Nested(Vec::new())));
+            return fields;
+        };
+        let reads =
+            |c: &Counters| c.hits.load(Ordering::Relaxed) + c.misses.load(Ordering::Relaxed);
+        let mut prefixes: Vec<_> = by.prefixes.iter().collect();
+        prefixes.sort_unstable_by(|a, b| reads(b.1).cmp(&reads(a.1)).then_with(|| a.0.cmp(b.0)));
+        let prefixes = prefixes
+            .into_iter()
+            .map(|(p, c)| (p.clone(), ResponseValue::Nested(c.fields())))
+            .collect();
+        fields.push(("by_prefix".into(), ResponseValue::Nested(prefixes)));
+        if reads(&by.other) > 0 {
+            fields.push(("other".into(), ResponseValue::Nested(by.other.fields())));
+        }
+        fields
+    }
+}
@@ -0,0 +1,145 @@
+//! Политика ключей сервера, см. `PersistentCore::set_key_normalization`:
+//! ключи с NUL и управляющими символами отклоняются, а при `Nfc` ключи и
+//! префиксы всех команд приводятся к NFC до выполнения, так что "résumé" в
+//! NFC и NFD — один ключ. Там же приведение к нижнему регистру для
+//! `PersistOptions::case_insensitive_keys`.
+
+use crate::error::CacheError;
+use crate::CacheCommand;
+use std::str::FromStr;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyNormalization {
+    /// форма ключа не меняется, проверяются только символы
+    AsIs,
+    /// ключ приводится к NFC
+    Nfc,
+}
+
+impl FromStr for KeyNormalization {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(KeyNormalization::AsIs),
+            "nfc" => Ok(KeyNormalization::Nfc),
+            other => Err(CacheError::Unsupported(format!(
+                "unknown key normalization {:?}, expected none/nfc",
+                other
+            ))),
+        }
+    }
+}
+
+impl KeyNormalization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyNormalization::AsIs => "none",
+            KeyNormalization::Nfc => "nfc",
+        }
+    }
+
+    /// Проверяет ключ (или префикс) и приводит его к форме политики.
+    pub fn apply(self, key: &mut String) -> Result<(), CacheError> {
+        if let Some(c) = key.chars().find(|c| c.is_control()) {
+            return Err(CacheError::InvalidKey(format!(
+                "{:?} contains control character {:?}",
+                key, c
+            )));
+        }
+        if self == KeyNormalization::Nfc && is_nfc_quick(key.chars()) != IsNormalized::Yes {
+            *key = key.nfc().collect();
+        }
+        Ok(())
+    }
+}
+
+/// Ключ в нижнем регистре.
+pub(crate) fn fold_case(key: &mut String) {
+    if key.is_ascii() {
+        key.make_ascii_lowercase();
+    } else {
+        *key = key.to_lowercase();
+    }
+}
+
+/// Вызывает `f` для ключей и префиксов команды; каналы, теги и пути не
+/// трогаются.
+pub(crate) fn for_each_key(
+    cmd: &mut CacheCommand,
+    f: &mut impl FnMut(&mut String) -> Result<(), CacheError>,
+) -> Result<(), CacheError> {
+    match cmd {
+        CacheCommand::Set(key, _)
+        | CacheCommand::Get(key)
+        | CacheCommand::Pop(key)
+        | CacheCommand::Del(key)
+        | CacheCommand::Keys(key)
+        | CacheCommand::ScanItems(_, key, _)
+        | CacheCommand::Watch(key, _)
+        | CacheCommand::BGet(key, _)
+        | CacheCommand::Exists(key)
+        | CacheCommand::Scan(_, key, _)
+        | CacheCommand::ScanSizes(_, key, ..)
+        | CacheCommand::ScanTtl(_, key, _)
+        | CacheCommand::Aggregate(key, ..)
+        | CacheCommand::GetOrSet(key, _)
+        | CacheCommand::DelPrefix(key)
+        | CacheCommand::Update(key, _)
+        | CacheCommand::SetBit(key, ..)
+        | CacheCommand::GetBit(key, _)
+        | CacheCommand::BitCount(key, _)
+        | CacheCommand::IdleTime(key)
+        | CacheCommand::PSetEx(key, ..)
+        | CacheCommand::PExpire(key, _)
+        | CacheCommand::ExpireAt(key, _)
+        | CacheCommand::PTtl(key)
+        | CacheCommand::Type(key)
+        | CacheCommand::KeysSorted(key, ..)
+        | CacheCommand::SetWithTags(key, ..)
+        | CacheCommand::Undelete(key)
+        | CacheCommand::SetHistoryDepth(key, _)
+        | CacheCommand::History(key)
+        | CacheCommand::ExportBloom(key, _)
+        | CacheCommand::GetWithMeta(key)
+        | CacheCommand::DelIfEquals(key, _)
+        | CacheCommand::SetNx(key, ..)
+        | CacheCommand::PExtendIfEquals(key, ..)
+        | CacheCommand::IncrRange(key, _) => f(key),
+        CacheCommand::MSet(items) => items.iter_mut().try_for_each(|(key, _)| f(key)),
+        CacheCommand::Touch(keys) | CacheCommand::MGetConsistent(keys) => {
+            keys.iter_mut().try_for_each(f)
+        }
+        CacheCommand::Swap(a, b) => f(a).and_then(|()| f(b)),
+        CacheCommand::Admin(_, cmd) => for_each_key(cmd, f),
+        CacheCommand::Len
+        | CacheCommand::Save
+        | CacheCommand::BgSave
+        | CacheCommand::Info
+        | CacheCommand::Stats
+        | CacheCommand::WalStats
+        | CacheCommand::Export(..)
+        | CacheCommand::Import(..)
+        | CacheCommand::ReplSync(_)
+        | CacheCommand::SetReadOnly(_)
+        | CacheCommand::Subscribe(_)
+        | CacheCommand::Publish(..)
+        | CacheCommand::Ping
+        | CacheCommand::Verify(..)
+        | CacheCommand::Hello(_)
+        | CacheCommand::ClientList
+        | CacheCommand::ClientKill(_)
+        | CacheCommand::DelByTag(_)
+        | CacheCommand::PrefixStats(..)
+        | CacheCommand::ConfigSet(..)
+        | CacheCommand::ConfigGet(_)
+        | CacheCommand::ConfigDump
+        | CacheCommand::Auth(..)
+        | CacheCommand::AclList
+        | CacheCommand::AclSetUser(_)
+        | CacheCommand::AclDelUser(_)
+        | CacheCommand::Sample(_)
+        | CacheCommand::Time => Ok(()),
+    }
+}
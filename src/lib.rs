@@ -1,26 +1,100 @@
 #![allow(rust_2024_compatibility)]
 #![allow(unsafe_op_in_unsafe_fn)]
+// pyo3 0.22 генерирует `?` над PyResult, на который ругается свежий clippy
+#![cfg_attr(feature = "python", allow(clippy::useless_conversion))]
 
-mod core;
-mod error;
-mod wal;
+//! Ядро кэша, WAL и протокол. Python-биндинги — за фичей `python` (включена
+//! по умолчанию); без неё крейт даёт чистый Rust API: [`Client`] для работы
+//! с сервером по сети и [`PersistentCore`] для встроенного режима.
 
-use crate::core::CacheCore;
-use crate::error::CacheError;
-use crate::wal::{Wal, WalRecord};
+mod acl;
+mod aggregate;
+mod bench;
+mod bits;
+mod blocking;
+mod bloom;
+mod chaos;
+mod client;
+mod clients;
+mod config;
+pub mod core;
+pub mod crypto;
+mod dedup;
+mod dump;
+mod encoding;
+pub mod error;
+mod feed;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod history;
+mod hitstats;
+mod keys;
+mod loader;
+mod mux;
+mod peer_access;
+mod pool;
+mod pubsub;
+#[cfg(feature = "python")]
+mod python;
+mod repl;
+mod slowlog;
+mod snapshot;
+mod trace;
+mod update;
+mod verify;
+pub mod wal;
+mod watch;
+
+pub use crate::acl::{hash_password, AclUser};
+pub use crate::aggregate::AggOp;
+pub use crate::bench::{benchmark, parse_mix, BenchOptions, BenchReport};
+pub use crate::bloom::BloomFilter;
+pub use crate::chaos::{ChaosOptions, INJECTED_ERROR};
+pub use crate::client::{Client, ClientOptions, ScanPage};
+pub use crate::clients::ClientInfo;
+pub use crate::config::Setting;
+pub use crate::dump::DumpFormat;
+pub use crate::encoding::{decode_value, encode_value, ValueEncoding, DEFAULT_COMPRESS_MIN};
+pub use crate::feed::{Drain, FeedReceiver, Overflow, WriteFeed};
+pub use crate::hitstats::DEFAULT_STATS_PREFIXES;
+pub use crate::keys::KeyNormalization;
+pub use crate::loader::{Loaded, Loader, LOAD_TIMEOUT};
+pub use crate::mux::{MuxConn, Pending};
+#[cfg(unix)]
+pub use crate::peer_access::peer_cred;
+pub use crate::peer_access::{PeerAccess, PeerCred, PeerRole};
+pub use crate::repl::{spawn_replica, ReplFrame};
+pub use crate::slowlog::{SlowEntry, SLOWLOG_LEN};
+pub use crate::trace::{current_trace_id, with_trace_id, MAX_TRACE_ID};
+pub use crate::update::UpdateOp;
+pub use crate::verify::VerifyReport;
+pub use crate::watch::{WatchEvent, WatchOp};
+
+use crate::acl::{Access, Acl};
+use crate::blocking::Waiters;
+use crate::chaos::{Chaos, Fault};
+use crate::clients::{Clients, Session};
+use crate::core::{CacheCore, Deadline, RemoveIf, Tombstone};
+use crate::crypto::WalKey;
+use crate::encoding::{decode_response, escape_legacy};
+use crate::error::{BindFailure, CacheError};
+use crate::hitstats::HitStats;
+use crate::loader::ReadThrough;
+use crate::pubsub::PubSub;
+use crate::slowlog::SlowLog;
+use crate::wal::{FsyncPolicy, ReplayHook, ReplayProgress, Subscription, Wal, WalRecord};
+use crate::watch::Watchers;
 
-use pyo3::exceptions::PyRuntimeError;
-use pyo3::prelude::*;
-use pyo3::types::PyBytes;
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::io::{ErrorKind, IoSlice, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use bincode;
 #[cfg(unix)]
 use std::fs;
 #[cfg(unix)]
@@ -38,6 +112,258 @@ pub enum CacheCommand {
     Del(String),
     Keys(String),
     Len,
+    Save,
+    BgSave,
+    Info,
+    WalStats,
+    // формат ("binary" | "json"), путь на стороне сервера
+    Export(String, String),
+    // курсор (0 — начало), префикс ключа, размер страницы
+    ScanItems(u64, String, u32),
+    // путь на стороне сервера, перезаписывать ли существующие ключи
+    Import(String, bool),
+    // подписка реплики на записи после seq, см. repl.rs
+    ReplSync(u64),
+    // включить/выключить режим только чтения; только внутри Admin
+    SetReadOnly(bool),
+    // админ-токен сервера и команда, которая выполняется с правами администратора
+    Admin(String, Box<CacheCommand>),
+    // перевести соединение в режим подписки на каналы, см. pubsub.rs
+    Subscribe(Vec<String>),
+    // канал, сообщение; ответ — число получателей
+    Publish(String, Vec<u8>),
+    // поток событий по ключам с префиксом, со значениями или без, см. watch.rs
+    Watch(String, bool),
+    // ждать появления ключа не дольше timeout_ms, см. blocking.rs
+    BGet(String, u64),
+    // есть ли ключ; ответ Int(0 | 1), само значение не передаётся
+    Exists(String),
+    // как ScanItems, но только ключи; ответ ScanKeys
+    Scan(u64, String, u32),
+    // проверка живости; ответ Ok
+    Ping,
+    // записать значение, только если ключа нет; ответ Entry
+    GetOrSet(String, Vec<u8>),
+    // несколько Set одной записью в WAL; ответ Ok
+    MSet(Vec<(String, Vec<u8>)>),
+    // удалить все ключи с префиксом; ответ Int(сколько удалено)
+    DelPrefix(String),
+    // атомарное преобразование значения, см. update.rs; ответ Value | Nil
+    Update(String, UpdateOp),
+    // поставить бит по смещению; ответ Int(прежний бит), см. bits.rs
+    SetBit(String, u64, bool),
+    // ответ Int(0 | 1); за концом значения и для отсутствующего ключа — 0
+    GetBit(String, u64),
+    // число единичных битов, в диапазоне байт [start, end], если задан; ответ Int
+    BitCount(String, Option<(i64, i64)>),
+    // обновить время обращения к ключам; ответ Int(сколько из них есть)
+    Touch(Vec<String>),
+    // секунды с последнего обращения к ключу; ответ Int | Nil
+    IdleTime(String),
+    // Set со сроком жизни в мс (> 0); ответ Ok
+    PSetEx(String, u64, Vec<u8>),
+    // срок жизни ключа в мс от текущего момента, <= 0 удаляет ключ;
+    // ответ Int(1 — ключ есть, 0 — нет)
+    PExpire(String, i64),
+    // срок как unix-время в мс, прошедшее удаляет ключ; ответ как у PExpire
+    ExpireAt(String, u64),
+    // оставшийся срок в мс; ответ Int, -1 — ключ бессрочный, -2 — ключа нет
+    PTtl(String),
+    // вид значения ключа для отладки; ответ Map: "type" и, если ключ есть,
+    // "encoding" и "length"
+    Type(String),
+    // ключи на префикс (без '*') по возрастанию: offset и limit страницы;
+    // ответ Keys
+    KeysSorted(String, u64, u64),
+    // сверка памяти со снапшотом и WAL: сравнивать ли значения и сколько
+    // примеров вернуть; только внутри Admin, ответ Map
+    Verify(bool, u32),
+    // версия протокола клиента; ответ Int — версия, на которой дальше
+    // работает соединение (меньшая из двух)
+    Hello(u32),
+    // соединения сервера, см. clients.rs; только внутри Admin, ответ Map
+    ClientList,
+    // закрыть соединение по id из ClientList; только внутри Admin,
+    // ответ Int(1 — закрыто, 0 — такого нет)
+    ClientKill(u64),
+    // Set с тегами (ключ, теги, значение): значение последним, как в PSetEx,
+    // чтобы кадр собирался без копии; ответ Ok
+    SetWithTags(String, Vec<String>, Vec<u8>),
+    // удалить все ключи с тегом; ответ Int(сколько удалено)
+    DelByTag(String),
+    // вернуть ключ из надгробия, см. PersistentCore::set_tombstone_ttl;
+    // ответ Int(1 — возвращён, 0 — надгробия нет или ключ уже есть)
+    Undelete(String),
+    // сколько прежних значений помнить у ключей с префиксом (0 — не помнить),
+    // см. PersistentCore::set_history_depth; ответ Ok
+    SetHistoryDepth(String, u32),
+    // прежние значения ключа от старых к новым; ответ History
+    History(String),
+    // фильтр Блума по ключам с префиксом, бит на ключ (1..=64); ответ Bloom
+    ExportBloom(String, u32),
+    // атомарно поменять местами значения двух ключей (вместе со сроками и
+    // тегами); ответ Int(0 — не было ни одного, 1 — иначе)
+    Swap(String, String),
+    // часы сервера; ответ Int — unix-время в мс
+    Time,
+    // значение с временем последней записи, сроком и версией; ответ Meta
+    // или Nil
+    GetWithMeta(String),
+    // ключи и байты значений по группам из первых depth сегментов ключа
+    // (разделитель, depth >= 1), см. PersistentCore::prefix_stats; ответ Map
+    PrefixStats(String, u32),
+    // поменять настройку сервера на ходу (имя, значение строкой), см.
+    // config.rs; только внутри Admin, ответ Ok
+    ConfigSet(String, String),
+    // текущие значения настроек ("*" — всех), см. config.rs; только внутри
+    // Admin, ответ Map
+    ConfigGet(String),
+    // до N ключей наугад с размером значения, простоем и сроком, без
+    // значений, см. CacheCore::sample; ответ Sample
+    Sample(u32),
+    // как Scan, но только ключи с длиной значения в min..=max байт (курсор,
+    // префикс, размер страницы, min, max); ответ Sizes
+    ScanSizes(u64, String, u32, u64, u64),
+    // удалить ключ, только если значение равно данному; ответ Int(1), если
+    // удалён, иначе Int(0)
+    DelIfEquals(String, Vec<u8>),
+    // записать значение со сроком в мс (0 — бессрочно), только если ключа
+    // нет (ключ, срок, значение); ответ Int(1), если записано, иначе Int(0)
+    SetNx(String, u64, Vec<u8>),
+    // продлить срок ключа на N мс, только если значение равно данному (ключ,
+    // значение, мс); ответ Int(1), если продлён, иначе Int(0)
+    PExtendIfEquals(String, Vec<u8>, u64),
+    // сдвинуть счётчик на N (ключ, N >= 1), см. PersistentCore::incr_range;
+    // ответ Int с прежним значением
+    IncrRange(String, u64),
+    // как Scan, но ключи с оставшимся сроком в мс (-1 — бессрочный), оба из
+    // одного обращения к записи; ответ Ttls
+    ScanTtl(u64, String, u32),
+    // свёртка целых значений ключей на префиксе за один обход (префикс,
+    // операция, пропускать ли нецелые); ответ Int, у Min/Max без значений —
+    // Nil
+    Aggregate(String, AggOp, bool),
+    // значения ключей на один момент, см. CacheCore::get_consistent; без
+    // сквозного чтения (loader); ответ Values
+    MGetConsistent(Vec<String>),
+    // попадания и промахи чтений, см. PersistentCore::hit_stats; ответ Map
+    Stats,
+    // все действующие настройки сервера, см. PersistentCore::config_dump;
+    // только внутри Admin, ответ Map
+    ConfigDump,
+    // вход соединения пользователем ACL (имя, пароль), см. acl.rs; ответ Ok
+    Auth(String, String),
+    // пользователи ACL без хешей паролей; только внутри Admin или от
+    // пользователя admin, ответ AclUsers
+    AclList,
+    // завести или заменить пользователя ACL; там же, ответ Ok
+    AclSetUser(AclUser),
+    // удалить пользователя ACL (имя); там же, ответ Int(1), если он был
+    AclDelUser(String),
+}
+
+impl CacheCommand {
+    /// Имя команды без аргументов, для `ClientList`; у `Admin` — вложенной.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            CacheCommand::Admin(_, cmd) => cmd.name(),
+            CacheCommand::Set(..) => "Set",
+            CacheCommand::Get(..) => "Get",
+            CacheCommand::Pop(..) => "Pop",
+            CacheCommand::Del(..) => "Del",
+            CacheCommand::Keys(..) => "Keys",
+            CacheCommand::Len => "Len",
+            CacheCommand::Save => "Save",
+            CacheCommand::BgSave => "BgSave",
+            CacheCommand::Info => "Info",
+            CacheCommand::WalStats => "WalStats",
+            CacheCommand::Export(..) => "Export",
+            CacheCommand::ScanItems(..) => "ScanItems",
+            CacheCommand::Import(..) => "Import",
+            CacheCommand::ReplSync(..) => "ReplSync",
+            CacheCommand::SetReadOnly(..) => "SetReadOnly",
+            CacheCommand::Subscribe(..) => "Subscribe",
+            CacheCommand::Publish(..) => "Publish",
+            CacheCommand::Watch(..) => "Watch",
+            CacheCommand::BGet(..) => "BGet",
+            CacheCommand::Exists(..) => "Exists",
+            CacheCommand::Scan(..) => "Scan",
+            CacheCommand::Ping => "Ping",
+            CacheCommand::GetOrSet(..) => "GetOrSet",
+            CacheCommand::MSet(..) => "MSet",
+            CacheCommand::DelPrefix(..) => "DelPrefix",
+            CacheCommand::Update(..) => "Update",
+            CacheCommand::SetBit(..) => "SetBit",
+            CacheCommand::GetBit(..) => "GetBit",
+            CacheCommand::BitCount(..) => "BitCount",
+            CacheCommand::Touch(..) => "Touch",
+            CacheCommand::IdleTime(..) => "IdleTime",
+            CacheCommand::PSetEx(..) => "PSetEx",
+            CacheCommand::PExpire(..) => "PExpire",
+            CacheCommand::ExpireAt(..) => "ExpireAt",
+            CacheCommand::PTtl(..) => "PTtl",
+            CacheCommand::Type(..) => "Type",
+            CacheCommand::KeysSorted(..) => "KeysSorted",
+            CacheCommand::Verify(..) => "Verify",
+            CacheCommand::Hello(..) => "Hello",
+            CacheCommand::ClientList => "ClientList",
+            CacheCommand::ClientKill(..) => "ClientKill",
+            CacheCommand::SetWithTags(..) => "SetWithTags",
+            CacheCommand::DelByTag(..) => "DelByTag",
+            CacheCommand::Undelete(..) => "Undelete",
+            CacheCommand::SetHistoryDepth(..) => "SetHistoryDepth",
+            CacheCommand::History(..) => "History",
+            CacheCommand::ExportBloom(..) => "ExportBloom",
+            CacheCommand::Swap(..) => "Swap",
+            CacheCommand::Time => "Time",
+            CacheCommand::GetWithMeta(..) => "GetWithMeta",
+            CacheCommand::PrefixStats(..) => "PrefixStats",
+            CacheCommand::ConfigSet(..) => "ConfigSet",
+            CacheCommand::ConfigGet(..) => "ConfigGet",
+            CacheCommand::Sample(..) => "Sample",
+            CacheCommand::ScanSizes(..) => "ScanSizes",
+            CacheCommand::DelIfEquals(..) => "DelIfEquals",
+            CacheCommand::SetNx(..) => "SetNx",
+            CacheCommand::PExtendIfEquals(..) => "PExtendIfEquals",
+            CacheCommand::IncrRange(..) => "IncrRange",
+            CacheCommand::ScanTtl(..) => "ScanTtl",
+            CacheCommand::Aggregate(..) => "Aggregate",
+            CacheCommand::MGetConsistent(..) => "MGetConsistent",
+            CacheCommand::Stats => "Stats",
+            CacheCommand::ConfigDump => "ConfigDump",
+            CacheCommand::Auth(..) => "Auth",
+            CacheCommand::AclList => "AclList",
+            CacheCommand::AclSetUser(..) => "AclSetUser",
+            CacheCommand::AclDelUser(..) => "AclDelUser",
+        }
+    }
+
+    /// Команды, меняющие данные: реплика их не принимает.
+    fn is_write(&self) -> bool {
+        matches!(
+            self,
+            CacheCommand::Set(..)
+                | CacheCommand::Pop(_)
+                | CacheCommand::Del(_)
+                | CacheCommand::Import(..)
+                | CacheCommand::GetOrSet(..)
+                | CacheCommand::MSet(_)
+                | CacheCommand::DelPrefix(_)
+                | CacheCommand::Update(..)
+                | CacheCommand::SetBit(..)
+                | CacheCommand::PSetEx(..)
+                | CacheCommand::PExpire(..)
+                | CacheCommand::ExpireAt(..)
+                | CacheCommand::SetWithTags(..)
+                | CacheCommand::DelByTag(_)
+                | CacheCommand::Undelete(_)
+                | CacheCommand::Swap(..)
+                | CacheCommand::DelIfEquals(..)
+                | CacheCommand::SetNx(..)
+                | CacheCommand::PExtendIfEquals(..)
+                | CacheCommand::IncrRange(..)
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -47,6 +373,324 @@ pub enum CacheResponse {
     Nil,
     Int(i64),
     Keys(Vec<String>),
+    Error(String),
+    Info(Vec<(String, String)>),
+    // курсор следующей страницы (0 — конец) и пары страницы
+    Items(u64, Vec<(String, Vec<u8>)>),
+    // кадр потока репликации в ответ на ReplSync
+    Repl(ReplFrame),
+    // сообщение канала для соединения в режиме подписки
+    Message(String, Vec<u8>),
+    // изменение ключа для соединения в режиме Watch
+    Event(WatchEvent),
+    // курсор следующей страницы (0 — конец) и ключи страницы
+    ScanKeys(u64, Vec<String>),
+    // значение ключа и записала ли его эта команда (GetOrSet)
+    Entry(Vec<u8>, bool),
+    // словарь с типизированными значениями; только для протокола 2 и выше,
+    // клиентам версии 1 уходит Info
+    Map(Vec<(String, ResponseValue)>),
+    // (unix-время в мс, когда значение заменили или удалили, значение)
+    History(Vec<(u64, Vec<u8>)>),
+    Bloom(BloomFilter),
+    Meta(ValueMeta),
+    // кусок ответа Keys для протокола 3 и выше; false — последний
+    KeysChunk(Vec<String>, bool),
+    // кусок ответа ScanItems для протокола 3 и выше: курсор следующей
+    // страницы (во всех кусках один), пары куска и есть ли ещё куски
+    ItemsChunk(u64, Vec<(String, Vec<u8>)>, bool),
+    // ответ Sample
+    Sample(Vec<KeySample>),
+    // курсор следующей страницы (0 — конец), ключи страницы с длинами
+    // значений (ScanSizes)
+    Sizes(u64, Vec<(String, u64)>),
+    // курсор следующей страницы (0 — конец), ключи страницы с оставшимися
+    // сроками в мс (ScanTtl)
+    Ttls(u64, Vec<(String, i64)>),
+    // значения по порядку ключей запроса, None — ключа нет (MGetConsistent)
+    Values(Vec<Option<Vec<u8>>>),
+    // пользователи ACL по имени, с пустыми password_hash (AclList)
+    AclUsers(Vec<AclUser>),
+}
+
+/// Ответ `GetWithMeta`: значение и то, насколько оно свежее по часам сервера.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ValueMeta {
+    pub value: Vec<u8>,
+    // unix-время в мс последней записи значения; 0 — неизвестно (ключ из
+    // снапшота или WAL прежних версий)
+    pub written_at: u64,
+    // оставшийся срок в мс; None — ключ бессрочный
+    pub ttl_ms: Option<u64>,
+    // сколько раз значение записывали с создания ключа
+    pub version: u64,
+    // часы сервера в момент ответа, unix-время в мс
+    pub now: u64,
+}
+
+/// Ключ из ответа `Sample`: размер значения и возраст, без самого значения.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeySample {
+    pub key: String,
+    // длина значения в байтах
+    pub size: u64,
+    // мс с последнего обращения, как у IdleTime
+    pub idle_ms: u64,
+    // оставшийся срок в мс; None — ключ бессрочный
+    pub ttl_ms: Option<u64>,
+    // мс с последней записи значения; None — неизвестно (ключ из снапшота
+    // или WAL прежних версий)
+    pub age_ms: Option<u64>,
+}
+
+/// Версия протокола сервера. Клиент объявляет свою командой `Hello`, без неё
+/// соединение считается версии 1.
+/// 1 — исходная;
+/// 2 — ответ `Map` вместо `Info` у Info, WalStats, Type и Verify; после
+/// `Hello` кадры несут id запроса, а команды — ещё и бюджет времени; сервер
+/// выполняет команды соединения параллельно (см. mux.rs);
+/// 3 — ответы `Keys` и `Items` приходят кусками `KeysChunk`/`ItemsChunk` не
+/// больше `CHUNK_BYTES` с одним id запроса, так что миллион ключей — не один
+/// стомегабайтный кадр;
+/// 4 — команда может нести trace id (флаг `TRACE_FLAG` в бюджете, затем u8
+/// длины и сам id), см. `with_trace_id`;
+/// 5 — клиент распаковывает сжатые значения сам (см. encoding.rs); прежним
+/// версиям сервер отдаёт их распакованными.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// Старший бит бюджета в кадре команды протокола 4: после заголовка идёт
+/// trace id. Бюджет поэтому не больше `TRACE_FLAG - 1` мс (~24 дня).
+const TRACE_FLAG: u32 = 1 << 31;
+
+/// Предел тела кадра с куском ответа по оценке `chunk_in_frames`. Кусок
+/// из одной пары бывает и больше: значение может доходить до
+/// `MAX_COMMAND_SIZE`.
+const CHUNK_BYTES: usize = 1 << 20;
+
+/// Предел тела кадра ответа: больше клиент не принимает, а сервер вместо
+/// такого ответа отправляет ошибку `CacheError::ResponseTooLarge`, см.
+/// `PersistentCore::set_max_response_size`.
+pub const MAX_RESPONSE_SIZE: usize = 64 << 20;
+
+// заголовок куска сверх оценки его элементов: тег, длина списка, курсор, more
+const CHUNK_OVERHEAD: usize = 32;
+
+/// Значение в ответе `Map`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ResponseValue {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    Nested(Vec<(String, ResponseValue)>),
+}
+
+impl ResponseValue {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            ResponseValue::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ResponseValue::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // строка для Info клиентам версии 1; дробные — с тремя знаками, как
+    // было у live_ratio
+    fn into_legacy(self) -> String {
+        match self {
+            ResponseValue::Int(n) => n.to_string(),
+            ResponseValue::Float(f) => format!("{:.3}", f),
+            ResponseValue::Str(s) => s,
+            ResponseValue::Bytes(b) => String::from_utf8_lossy(&b).into_owned(),
+            ResponseValue::Nested(_) => unreachable!("nested maps are flattened"),
+        }
+    }
+}
+
+impl From<i64> for ResponseValue {
+    fn from(n: i64) -> Self {
+        ResponseValue::Int(n)
+    }
+}
+
+impl From<u64> for ResponseValue {
+    fn from(n: u64) -> Self {
+        ResponseValue::Int(n as i64)
+    }
+}
+
+impl From<usize> for ResponseValue {
+    fn from(n: usize) -> Self {
+        ResponseValue::Int(n as i64)
+    }
+}
+
+impl From<bool> for ResponseValue {
+    fn from(b: bool) -> Self {
+        ResponseValue::Int(b as i64)
+    }
+}
+
+impl From<f64> for ResponseValue {
+    fn from(f: f64) -> Self {
+        ResponseValue::Float(f)
+    }
+}
+
+impl From<String> for ResponseValue {
+    fn from(s: String) -> Self {
+        ResponseValue::Str(s)
+    }
+}
+
+impl From<&str> for ResponseValue {
+    fn from(s: &str) -> Self {
+        ResponseValue::Str(s.to_string())
+    }
+}
+
+// Map -> Info: вложенные словари разворачиваются в ключи "внешний:внутренний"
+fn flatten_legacy(
+    prefix: &str,
+    map: Vec<(String, ResponseValue)>,
+    out: &mut Vec<(String, String)>,
+) {
+    for (k, v) in map {
+        let k = if prefix.is_empty() {
+            k
+        } else {
+            format!("{}:{}", prefix, k)
+        };
+        match v {
+            ResponseValue::Nested(inner) => flatten_legacy(&k, inner, out),
+            v => out.push((k, v.into_legacy())),
+        }
+    }
+}
+
+impl CacheResponse {
+    /// Ответ для соединения версии `proto`.
+    fn for_protocol(self, proto: u32) -> Self {
+        match self {
+            CacheResponse::Map(map) if proto < 2 => {
+                let mut fields = Vec::with_capacity(map.len());
+                flatten_legacy("", map, &mut fields);
+                CacheResponse::Info(fields)
+            }
+            resp if proto < 5 => {
+                decode_response(resp).unwrap_or_else(|e| CacheResponse::Error(e.to_string()))
+            }
+            resp => resp,
+        }
+    }
+
+    /// Отправляет ответ соединению версии `proto` через `send`, по кадру на
+    /// вызов: с версии 3 `Keys` и `Items` — кусками, последний с
+    /// `more == false` (пустой список — один пустой кусок), остальное и
+    /// прежним версиям — одним кадром, как раньше. Куски не больше `limit`,
+    /// как и кадры `encode_response`.
+    fn send_in_frames(
+        self,
+        proto: u32,
+        limit: usize,
+        mut send: impl FnMut(&CacheResponse) -> Result<(), CacheError>,
+    ) -> Result<(), CacheError> {
+        let budget = CHUNK_BYTES.min(limit.saturating_sub(CHUNK_OVERHEAD));
+        match self.for_protocol(proto) {
+            CacheResponse::Keys(keys) if proto >= 3 => chunk_in_frames(
+                keys,
+                budget,
+                |k| 8 + k.len(),
+                |part, more| send(&CacheResponse::KeysChunk(part, more)),
+            ),
+            CacheResponse::Items(next, items) if proto >= 3 => chunk_in_frames(
+                items,
+                budget,
+                |(k, v)| 16 + k.len() + v.len(),
+                |part, more| send(&CacheResponse::ItemsChunk(next, part, more)),
+            ),
+            resp => send(&resp),
+        }
+    }
+
+    /// Словарь из `Map`, а от сервера версии 1 — из `Info` со строковыми
+    /// значениями; иной ответ возвращается как есть.
+    pub fn into_map(self) -> Result<Vec<(String, ResponseValue)>, CacheResponse> {
+        match self {
+            CacheResponse::Map(map) => Ok(map),
+            CacheResponse::Info(fields) => Ok(fields
+                .into_iter()
+                .map(|(k, v)| (k, ResponseValue::Str(v)))
+                .collect()),
+            resp => Err(resp),
+        }
+    }
+}
+
+// режет `all` на куски не больше `budget` байт по оценке `size` (размер в
+// bincode) и отдаёт их `send` по порядку; в куске хотя бы один элемент
+fn chunk_in_frames<T>(
+    all: Vec<T>,
+    budget: usize,
+    size: impl Fn(&T) -> usize,
+    mut send: impl FnMut(Vec<T>, bool) -> Result<(), CacheError>,
+) -> Result<(), CacheError> {
+    let mut rest = all.into_iter().peekable();
+    loop {
+        let (mut part, mut bytes) = (Vec::new(), 0);
+        while let Some(x) = rest.next_if(|x| part.is_empty() || bytes + size(x) <= budget) {
+            bytes += size(&x);
+            part.push(x);
+        }
+        let more = rest.peek().is_some();
+        send(part, more)?;
+        if !more {
+            return Ok(());
+        }
+    }
+}
+
+/// Кадр ответа протокола 3, который мог прийти кусками: кусок дописывается к
+/// собранному в `acc`, и на последнем возвращается весь ответ как `Keys` или
+/// `Items`; `None` — ждём следующих кусков. Прочие ответы — как есть.
+fn join_chunk(
+    acc: &mut Option<CacheResponse>,
+    frame: CacheResponse,
+) -> Result<Option<CacheResponse>, CacheError> {
+    let (resp, more) = match (acc.take(), frame) {
+        (None, CacheResponse::KeysChunk(keys, more)) => (CacheResponse::Keys(keys), more),
+        (Some(CacheResponse::Keys(mut all)), CacheResponse::KeysChunk(keys, more)) => {
+            all.extend(keys);
+            (CacheResponse::Keys(all), more)
+        }
+        (None, CacheResponse::ItemsChunk(next, items, more)) => {
+            (CacheResponse::Items(next, items), more)
+        }
+        (Some(CacheResponse::Items(_, mut all)), CacheResponse::ItemsChunk(next, items, more)) => {
+            all.extend(items);
+            (CacheResponse::Items(next, all), more)
+        }
+        (None, resp) => return Ok(Some(resp)),
+        // кусок из одного элемента не поместился в предел ответа
+        (Some(_), CacheResponse::Error(msg)) => return Ok(Some(CacheResponse::Error(msg))),
+        (Some(_), _) => {
+            return Err(CacheError::Serialization(
+                "chunked response interrupted by another response".into(),
+            ))
+        }
+    };
+    if more {
+        *acc = Some(resp);
+        Ok(None)
+    } else {
+        Ok(Some(resp))
+    }
 }
 
 /// =======================
@@ -56,7 +700,7 @@ pub enum CacheResponse {
 #[derive(Clone, Debug)]
 enum TransportAddr {
     Tcp(String),     // "127.0.0.1:5002"
-    #[cfg(unix)]
+    // вне Unix подключение к нему — ошибка Unsupported, см. `Conn::connect`
     Unix(PathBuf),   // "/tmp/tiny-mp-cache.sock"
 }
 
@@ -65,172 +709,2829 @@ impl TransportAddr {
         if let Some(rest) = s.strip_prefix("tcp://") {
             TransportAddr::Tcp(rest.to_string())
         } else if let Some(rest) = s.strip_prefix("unix://") {
-            #[cfg(unix)]
-            {
-                TransportAddr::Unix(PathBuf::from(rest))
-            }
-            #[cfg(not(unix))]
-            {
-                TransportAddr::Tcp(rest.to_string())
-            }
+            TransportAddr::Unix(PathBuf::from(rest))
         } else {
             TransportAddr::Tcp(s.to_string())
         }
     }
 }
 
-/// =======================
-/// PersistentCore: CacheCore + WAL
-/// =======================
+/// =======================
+/// PersistentCore: CacheCore + WAL
+/// =======================
+pub struct PersistentCore {
+    core: CacheCore,
+    persistence: Persistence,
+    // мутации держат read на время append + apply, снапшот — write,
+    // чтобы seq снапшота точно соответствовал его содержимому
+    gate: RwLock<()>,
+    compact_after: Option<u64>,
+    save: SaveState,
+    import: ImportState,
+    // seq последней записи WAL, уже применённой к памяти
+    last_seq: AtomicU64,
+    // Some — это реплика и данные приходят только от основного сервера
+    replica: Option<ReplicaState>,
+    // сколько реплик сейчас подписано на этот сервер
+    replicas: AtomicU64,
+    // мутирующие команды клиентов отклоняются
+    read_only: AtomicBool,
+    admin_token: Option<String>,
+    // пользователи и их права, см. set_acl_file
+    acl: Acl,
+    // SetBit с большим смещением отклоняется, см. set_max_bit_offset
+    max_bit_offset: AtomicU64,
+    // больший ответ заменяется ошибкой, см. set_max_response_size
+    max_response_size: AtomicUsize,
+    // медленные команды соединений, см. set_slowlog
+    slowlog: SlowLog,
+    // попадания и промахи чтений, см. set_hit_stats_prefix
+    hit_stats: HitStats,
+    // задержки, ошибки и обрывы для проверки клиентов, см. set_chaos
+    chaos: Option<Chaos>,
+    pubsub: PubSub,
+    watchers: Watchers,
+    // соединения сервера для ClientList/ClientKill
+    clients: Clients,
+    // см. set_event_hook
+    events: Option<EventHook>,
+    // BGet, ждущие своих ключей
+    waiters: Waiters,
+    // запущен ли поток, удаляющий истёкшие ключи, см. start_expiry
+    expiry_started: AtomicBool,
+    // в мс; не 0 — удаления уходят в надгробия на столько, см. set_tombstone_ttl
+    tombstone_ttl: AtomicU64,
+    // None — ключи принимаются как есть, см. set_key_normalization
+    key_normalization: Option<KeyNormalization>,
+    // ключи приводятся к нижнему регистру, см. set_case_insensitive_keys
+    fold_case: bool,
+    // загрузка ключей при промахе Get, см. set_loader
+    loader: Option<ReadThrough>,
+    // кого пускает Unix-сокет, см. set_peer_access
+    peer_access: PeerAccess,
+}
+
+/// Событие жизненного цикла сервера, см. `PersistentCore::set_event_hook`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerEvent {
+    // listener открыт: "127.0.0.1:5002" или "unix:///path/to.sock"
+    Ready {
+        addr: String,
+    },
+    // принято соединение; id — как в ClientList
+    Connection {
+        id: u64,
+        peer: String,
+    },
+    // соединение закрылось с ошибкой (обрыв, битый кадр)
+    ConnectionError {
+        id: u64,
+        peer: String,
+        error: String,
+    },
+}
+
+pub type EventHook = Box<dyn Fn(ServerEvent) + Send + Sync>;
+
+/// Как часто поток истечения убирает истёкшие ключи из памяти. Читатели не
+/// видят их и раньше, от интервала зависит только задержка события `expired`.
+const EXPIRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Куда уходят мутации: в WAL со снапшотами или никуда (ephemeral-режим).
+enum Persistence {
+    Wal {
+        wal: Wal,
+        snapshot_path: PathBuf,
+    },
+    // WAL и снапшот прочитаны при старте, на диск ничего не пишется
+    ReadOnly {
+        wal_path: PathBuf,
+        snapshot_path: PathBuf,
+    },
+    None,
+}
+
+fn wal_read_only() -> CacheError {
+    CacheError::Unsupported("the WAL is opened read-only".into())
+}
+
+/// Настройки персистентности, которые задаются при старте сервера.
+#[derive(Clone, Debug, Default)]
+pub struct PersistOptions {
+    // автокомпакция после стольких записей в WAL
+    pub compact_after: Option<u64>,
+    pub fsync: FsyncPolicy,
+    // размер сегмента WAL в байтах, после которого начинается новый
+    pub segment_size: Option<u64>,
+    // ключ шифрования WAL и снапшотов
+    pub key: Option<Arc<WalKey>>,
+    // ключи всех команд приводятся к нижнему регистру; записывается в
+    // заголовок WAL, и журнал с другим значением не открывается
+    pub case_insensitive_keys: bool,
+    // ход replay при старте; в stderr он пишется и без callback'а
+    pub on_replay: Option<ReplayHook>,
+    // потоков replay при старте; None — по числу ядер, если журнал не меньше
+    // `wal::PARALLEL_REPLAY_MIN_BYTES`, 1 — в одном потоке
+    pub replay_threads: Option<usize>,
+}
+
+/// Ход replay при старте: в stderr и в `PersistOptions::on_replay`.
+fn replay_reporter(hook: Option<&ReplayHook>) -> impl FnMut(&ReplayProgress) + '_ {
+    move |progress| {
+        eprintln!("{}", progress);
+        if let Some(hook) = hook {
+            (hook.0)(progress);
+        }
+    }
+}
+
+/// Состояние сохранения снапшота (обычного и фонового), отдаётся через Info.
+#[derive(Default)]
+struct SaveState {
+    in_progress: AtomicBool,
+    keys_total: AtomicU64,
+    keys_saved: AtomicU64,
+    last_seq: AtomicU64,
+    last_status: Mutex<String>,
+}
+
+impl SaveState {
+    fn try_start(&self, keys_total: u64) -> Result<(), CacheError> {
+        self.in_progress
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .map_err(|_| CacheError::Busy("background save already in progress".into()))?;
+        self.keys_total.store(keys_total, Ordering::Relaxed);
+        self.keys_saved.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn finish<T>(&self, seq: u64, res: &Result<T, CacheError>) {
+        let status = match res {
+            Ok(_) => {
+                self.last_seq.store(seq, Ordering::Relaxed);
+                "ok".to_string()
+            }
+            Err(e) => format!("err: {}", e),
+        };
+        if let Ok(mut s) = self.last_status.lock() {
+            *s = status;
+        }
+        self.in_progress.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Состояние реплики, отдаётся через Info.
+struct ReplicaState {
+    primary: String,
+    connected: AtomicBool,
+    last_error: Mutex<String>,
+}
+
+/// Пока жив, подписанная реплика учитывается в `repl_replicas`.
+struct ReplicaGuard<'a>(&'a AtomicU64);
+
+impl Drop for ReplicaGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Сколько пар дампа применяется за раз (и пишется одним батчем в WAL).
+const IMPORT_BATCH: usize = 1000;
+
+/// Прогресс загрузки дампа, отдаётся через Info.
+#[derive(Default)]
+struct ImportState {
+    in_progress: AtomicBool,
+    keys_total: AtomicU64,
+    keys_loaded: AtomicU64,
+    // уже существовавшие ключи, оставленные при replace=false
+    keys_skipped: AtomicU64,
+    bytes_total: AtomicU64,
+    bytes_loaded: AtomicU64,
+    last_status: Mutex<String>,
+}
+
+impl ImportState {
+    fn try_start(&self) -> Result<(), CacheError> {
+        self.in_progress
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .map_err(|_| CacheError::Busy("import already in progress".into()))?;
+        for n in [
+            &self.keys_total,
+            &self.keys_loaded,
+            &self.keys_skipped,
+            &self.bytes_total,
+            &self.bytes_loaded,
+        ] {
+            n.store(0, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn finish(&self, res: &Result<u64, CacheError>) {
+        let status = match res {
+            Ok(_) => "ok".to_string(),
+            Err(e) => format!(
+                "err after {} keys: {}",
+                self.keys_loaded.load(Ordering::Relaxed),
+                e
+            ),
+        };
+        if let Ok(mut s) = self.last_status.lock() {
+            *s = status;
+        }
+        self.in_progress.store(false, Ordering::SeqCst);
+    }
+}
+
+impl PersistentCore {
+    pub fn new(
+        wal_path: PathBuf,
+        snapshot_path: PathBuf,
+        opts: PersistOptions,
+    ) -> Result<Self, CacheError> {
+        let core = CacheCore::new();
+        let flags = if opts.case_insensitive_keys {
+            wal::WAL_FLAG_FOLD_CASE
+        } else {
+            0
+        };
+        let wal = Wal::open(wal_path, opts.fsync, opts.segment_size, opts.key, flags)?;
+        // при старте сначала грузим снапшот, потом доигрываем хвост WAL
+        let snapshot_seq = snapshot::load(&snapshot_path, &core, wal.key())?.unwrap_or(0);
+        let last_seq = wal.replay(
+            &core,
+            snapshot_seq,
+            opts.replay_threads,
+            &mut replay_reporter(opts.on_replay.as_ref()),
+        )?;
+        // истёкшие, пока сервер был остановлен
+        core.take_expired();
+        let persistence = Persistence::Wal { wal, snapshot_path };
+        let mut me = Self::from_parts(core, persistence, opts.compact_after, last_seq);
+        if opts.case_insensitive_keys {
+            // снапшот мог остаться от журнала без флага
+            me.check_stored_keys("case_insensitive_keys=True", |key| {
+                keys::fold_case(key);
+                Ok(())
+            })?;
+            me.fold_case = true;
+        }
+        if let Some(schema) = me.wal()?.0.legacy_schema()? {
+            // старые записи переходят в снапшот, журнал дальше пишется в
+            // нынешней схеме, и прежние определения записей больше не нужны
+            eprintln!(
+                "WAL has records of schema {}, upgrading to schema {} by compaction",
+                schema,
+                wal::WAL_SCHEMA
+            );
+            me.compact()?;
+        }
+        Ok(me)
+    }
+
+    /// Кэш без WAL и снапшотов: на диск ничего не пишется.
+    pub fn ephemeral() -> Self {
+        Self::from_parts(CacheCore::new(), Persistence::None, None, 0)
+    }
+
+    /// Снапшот и WAL только читаются, ни один файл не открывается на запись.
+    /// Сервер стартует в режиме только чтения, и снять его без рестарта нельзя.
+    pub fn open_read_only(
+        wal_path: &Path,
+        snapshot_path: PathBuf,
+        key: Option<&WalKey>,
+    ) -> Result<Self, CacheError> {
+        let core = CacheCore::new();
+        let snapshot_seq = snapshot::load(&snapshot_path, &core, key)?.unwrap_or(0);
+        let last_seq = wal::replay_read_only(
+            wal_path,
+            &core,
+            snapshot_seq,
+            key,
+            None,
+            &mut replay_reporter(None),
+        )?;
+        core.take_expired();
+        let fold_case = wal::journal_flags(wal_path, key)? & wal::WAL_FLAG_FOLD_CASE != 0;
+        let persistence = Persistence::ReadOnly {
+            wal_path: wal_path.to_path_buf(),
+            snapshot_path,
+        };
+        let mut me = Self::from_parts(core, persistence, None, last_seq);
+        me.read_only.store(true, Ordering::SeqCst);
+        me.fold_case = fold_case;
+        Ok(me)
+    }
+
+    fn from_parts(
+        core: CacheCore,
+        persistence: Persistence,
+        compact_after: Option<u64>,
+        last_seq: u64,
+    ) -> Self {
+        Self {
+            core,
+            persistence,
+            gate: RwLock::new(()),
+            compact_after,
+            save: SaveState::default(),
+            import: ImportState::default(),
+            last_seq: AtomicU64::new(last_seq),
+            replica: None,
+            replicas: AtomicU64::new(0),
+            read_only: AtomicBool::new(false),
+            admin_token: None,
+            acl: Acl::default(),
+            max_bit_offset: AtomicU64::new(bits::DEFAULT_MAX_BIT_OFFSET),
+            max_response_size: AtomicUsize::new(MAX_RESPONSE_SIZE),
+            slowlog: SlowLog::default(),
+            hit_stats: HitStats::default(),
+            chaos: None,
+            tombstone_ttl: AtomicU64::new(0),
+            key_normalization: None,
+            fold_case: false,
+            loader: None,
+            peer_access: PeerAccess::default(),
+            pubsub: PubSub::default(),
+            watchers: Watchers::default(),
+            clients: Clients::default(),
+            events: None,
+            waiters: Waiters::default(),
+            expiry_started: AtomicBool::new(false),
+        }
+    }
+
+    /// Токен для команд, обёрнутых в `CacheCommand::Admin`; без него они отключены.
+    pub fn set_admin_token(&mut self, token: String) {
+        self.admin_token = Some(token);
+    }
+
+    /// Пользователи ACL из файла `path`, см. acl.rs: дальше каждое соединение
+    /// сначала входит командой `Auth`, а `AclSetUser`/`AclDelUser`
+    /// переписывают этот файл.
+    pub fn set_acl_file(&mut self, path: impl Into<PathBuf>) -> Result<(), CacheError> {
+        let path = path.into();
+        let users = acl::load_users(&path)?;
+        self.acl.set_users(users, Some(path))
+    }
+
+    /// То же без файла: изменения `AclSetUser`/`AclDelUser` живут до
+    /// перезапуска.
+    pub fn set_acl_users(&mut self, users: Vec<AclUser>) -> Result<(), CacheError> {
+        self.acl.set_users(users, None)
+    }
+
+    /// Режим сбоев для проверки клиентов, см. chaos.rs: задержки, ошибки и
+    /// обрывы соединений перед командами; None выключает его. Только для
+    /// тестов — на рабочем сервере он ломает клиентов нарочно.
+    pub fn set_chaos(&mut self, options: Option<ChaosOptions>) -> Result<(), CacheError> {
+        self.chaos = options.map(Chaos::new).transpose()?;
+        Ok(())
+    }
+
+    /// Наибольшее смещение для SetBit (по умолчанию — битовая карта до 8 МБ):
+    /// один бит с огромным смещением иначе выделил бы сотни мегабайт.
+    pub fn set_max_bit_offset(&self, max: u64) {
+        self.max_bit_offset.store(max, Ordering::Relaxed);
+    }
+
+    /// Предел тела кадра ответа, не больше `MAX_RESPONSE_SIZE` (и по
+    /// умолчанию он): ответ, который в него не помещается, заменяется
+    /// ошибкой `CacheError::ResponseTooLarge` с его размером. Keys и Items с
+    /// протокола 3 идут кусками в пределах и под него не попадают, пока
+    /// помещается каждый ключ или пара.
+    pub fn set_max_response_size(&self, limit: usize) {
+        self.max_response_size
+            .store(limit.min(MAX_RESPONSE_SIZE), Ordering::Relaxed);
+    }
+
+    fn max_response_size(&self) -> usize {
+        self.max_response_size.load(Ordering::Relaxed)
+    }
+
+    /// Команды соединений дольше `threshold` пишутся в stderr и в
+    /// `info()["slowlog"]` (последние `SLOWLOG_LEN`) с trace id клиента, см.
+    /// `with_trace_id`; None — журнал выключен (по умолчанию).
+    pub fn set_slowlog(&self, threshold: Option<Duration>) {
+        self.slowlog.set_threshold(threshold);
+    }
+
+    /// Записи журнала медленных команд от старых к новым.
+    pub fn slowlog(&self) -> Vec<SlowEntry> {
+        self.slowlog.entries()
+    }
+
+    /// Считать попадания и промахи чтений ещё и по префиксам ключей: первые
+    /// `depth` сегментов через `sep`, не больше `max_prefixes` префиксов (см.
+    /// hitstats.rs). `depth == 0` — только общие счётчики (по умолчанию).
+    pub fn set_hit_stats_prefix(
+        &self,
+        depth: u32,
+        sep: &str,
+        max_prefixes: usize,
+    ) -> Result<(), CacheError> {
+        self.hit_stats.set_prefix(depth, sep, max_prefixes)
+    }
+
+    /// Ответ `Stats`: попадания и промахи чтений всего и по префиксам.
+    pub fn hit_stats(&self) -> Vec<(String, ResponseValue)> {
+        self.hit_stats.fields()
+    }
+
+    /// Соединения сервера, по которым дольше `timeout` не пришло и не ушло ни
+    /// байта и не выполняется ни одна команда, закрываются (см. clients.rs);
+    /// клиенты при следующей команде открывают новые. None — не закрывать.
+    pub fn set_idle_timeout(&self, timeout: Option<Duration>) {
+        self.clients.set_idle_timeout(timeout);
+    }
+
+    /// Лимиты команд в секунду: на соединение и на все TCP-соединения с
+    /// одного IP (у Unix-сокета — только на соединение). Команда сверх лимита
+    /// получает ошибку "rate limited, retry after Nms", соединение не
+    /// закрывается; отклонённые считаются в `rate_limited_commands` info и
+    /// в `rate_limited` у `ClientList`. None или 0 — без лимита.
+    pub fn set_rate_limits(&self, per_conn: Option<u32>, per_ip: Option<u32>) {
+        self.clients.set_rate_limits(per_conn, per_ip);
+    }
+
+    /// Del, Pop, DelPrefix и DelByTag переносят значения в надгробия на `ttl`:
+    /// до его конца `Undelete` возвращает ключ, потом надгробие убирает поток
+    /// истечения. Надгробия не видны ни Get, ни Keys, ни Len, но занимают
+    /// память (`tombstone_bytes` в info) и попадают в WAL и снапшот. None —
+    /// удалять сразу; надгробия, что уже есть, всё равно доживают свой срок.
+    pub fn set_tombstone_ttl(&self, ttl: Option<Duration>) {
+        let ms = ttl.map_or(0, |t| t.as_millis().min(u64::MAX as u128) as u64);
+        self.tombstone_ttl.store(ms, Ordering::Relaxed);
+    }
+
+    /// Значения не короче `min_size` байт хранятся в памяти по одному
+    /// экземпляру на одинаковое содержимое (сверка по blake3), уже
+    /// загруженные тоже. Get по-прежнему отдаёт копию, а WAL и снапшоты пишут
+    /// значения целиком. None или 0 — выключить для новых записей.
+    pub fn set_dedup_min_size(&self, min_size: Option<usize>) {
+        self.core.set_dedup_min_size(min_size.filter(|&n| n > 0));
+    }
+
+    /// Хранить рядом с каждым значением crc32 и сверять его на Get и Pop:
+    /// несовпадение — ошибка `value checksum mismatch for key ...` вместо
+    /// значения и счётчик `checksum_failures` в Info. Pop с такой ошибкой
+    /// ключ не удаляет. Стоит crc32 на каждую запись и чтение и 8 байт на
+    /// ключ; по умолчанию выключено.
+    pub fn set_verify_values(&self, on: bool) {
+        self.core.set_verify_values(on);
+    }
+
+    #[doc(hidden)]
+    pub fn corrupt_value(&self, key: &str) -> bool {
+        self.core.corrupt_value(key)
+    }
+
+    /// Меняет настройку по имени аргумента `serve()` на работающем сервере,
+    /// см. `Setting`: лимиты действуют со следующей команды, в том числе на
+    /// уже открытых соединениях. Настройки, которые задаются только при
+    /// запуске, отклоняются с `CacheError::Unsupported`.
+    pub fn config_set(&self, name: &str, value: &str) -> Result<(), CacheError> {
+        let setting: Setting = name.parse()?;
+        let n = setting.parse(value)?;
+        let (per_conn, per_ip) = self.clients.rate_limits();
+        match setting {
+            Setting::MaxBitOffset => self.set_max_bit_offset(n),
+            Setting::MaxResponseSize => self.set_max_response_size(n as usize),
+            Setting::SlowlogMs => {
+                self.set_slowlog(Some(Duration::from_millis(n)).filter(|_| n > 0))
+            }
+            Setting::IdleTimeoutSecs => {
+                self.set_idle_timeout(Some(Duration::from_secs(n)).filter(|_| n > 0))
+            }
+            Setting::TombstoneTtlSecs => self.set_tombstone_ttl(Some(Duration::from_secs(n))),
+            Setting::DedupMinSize => self.set_dedup_min_size(Some(n as usize)),
+            // parse не пропускает больше u32::MAX
+            Setting::RateLimitPerConn => self.set_rate_limits(Some(n as u32), per_ip),
+            Setting::RateLimitPerIp => self.set_rate_limits(per_conn, Some(n as u32)),
+            Setting::VerifyValues => self.set_verify_values(n == 1),
+        }
+        Ok(())
+    }
+
+    /// Текущее значение настройки или всех (`"*"`) в порядке `Setting::ALL`;
+    /// выключенное — 0, флаг — 0 или 1.
+    pub fn config_get(&self, name: &str) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        let settings = match name {
+            "*" => Setting::ALL.to_vec(),
+            name => vec![name.parse()?],
+        };
+        Ok(settings
+            .into_iter()
+            .map(|s| (s.as_str().to_string(), ResponseValue::Int(self.setting(s))))
+            .collect())
+    }
+
+    /// Ответ `ConfigDump`: версия, пределы кадров, все настройки `config_get("*")`
+    /// и то, что задано при запуске, — режим хранения, пути и политика WAL,
+    /// роль, политика ключей, файл ACL. Секреты не выдаются: у `admin_token` и
+    /// `wal_key` только "set" или "unset". Выключенное — 0, флаг — 0 или 1.
+    pub fn config_dump(&self) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        let set = |on: bool| ResponseValue::from(if on { "set" } else { "unset" });
+        let mut fields: Vec<(String, ResponseValue)> = vec![
+            ("version".into(), env!("CARGO_PKG_VERSION").into()),
+            (
+                "protocol_version".into(),
+                ResponseValue::Int(PROTOCOL_VERSION as i64),
+            ),
+            ("max_command_size".into(), MAX_COMMAND_SIZE.into()),
+        ];
+        fields.extend(self.config_get("*")?);
+        fields.extend([
+            (
+                "history_depth".into(),
+                self.core.default_history_depth().into(),
+            ),
+            ("read_only".into(), self.is_read_only().into()),
+            ("admin_token".into(), set(self.admin_token.is_some())),
+        ]);
+        let (acl_file, acl_users) = self.acl.describe();
+        fields.extend([
+            ("acl".into(), self.acl.is_enabled().into()),
+            ("acl_file".into(), acl_file.into()),
+            ("acl_users".into(), acl_users.into()),
+            (
+                "key_normalization".into(),
+                self.key_normalization.map_or("off", |p| p.as_str()).into(),
+            ),
+            ("case_insensitive_keys".into(), self.fold_case.into()),
+        ]);
+        fields.extend(self.hit_stats.config_fields());
+        fields.extend(chaos::config_fields(self.chaos.as_ref()));
+        match &self.replica {
+            Some(r) => fields.extend([
+                ("role".into(), "replica".into()),
+                ("replicate_from".into(), r.primary.clone().into()),
+            ]),
+            None => fields.push(("role".into(), "primary".into())),
+        }
+        match &self.persistence {
+            Persistence::Wal { wal, snapshot_path } => fields.extend([
+                ("persistence".into(), "wal".into()),
+                ("wal_path".into(), wal.path().display().to_string().into()),
+                (
+                    "snapshot_path".into(),
+                    snapshot_path.display().to_string().into(),
+                ),
+                ("fsync".into(), wal.fsync_policy().as_str().into()),
+                (
+                    "wal_segment_size".into(),
+                    wal.segment_size().unwrap_or(0).into(),
+                ),
+                (
+                    "compact_after".into(),
+                    self.compact_after.unwrap_or(0).into(),
+                ),
+                ("wal_key".into(), set(wal.key().is_some())),
+            ]),
+            Persistence::ReadOnly {
+                wal_path,
+                snapshot_path,
+            } => fields.extend([
+                ("persistence".into(), "read-only".into()),
+                ("wal_path".into(), wal_path.display().to_string().into()),
+                (
+                    "snapshot_path".into(),
+                    snapshot_path.display().to_string().into(),
+                ),
+            ]),
+            Persistence::None => fields.push(("persistence".into(), "none".into())),
+        }
+        Ok(fields)
+    }
+
+    fn setting(&self, setting: Setting) -> i64 {
+        let (per_conn, per_ip) = self.clients.rate_limits();
+        let n = match setting {
+            Setting::MaxBitOffset => self.max_bit_offset.load(Ordering::Relaxed),
+            Setting::MaxResponseSize => self.max_response_size() as u64,
+            Setting::SlowlogMs => self.slowlog.threshold().map_or(0, |t| t.as_millis() as u64),
+            Setting::IdleTimeoutSecs => self.clients.idle_timeout().map_or(0, |t| t.as_secs()),
+            Setting::TombstoneTtlSecs => self.tombstone_ttl.load(Ordering::Relaxed) / 1000,
+            Setting::DedupMinSize => self.core.dedup_min_size().unwrap_or(0) as u64,
+            Setting::RateLimitPerConn => per_conn.unwrap_or(0) as u64,
+            Setting::RateLimitPerIp => per_ip.unwrap_or(0) as u64,
+            Setting::VerifyValues => self.core.verify_values() as u64,
+        };
+        n.min(i64::MAX as u64) as i64
+    }
+
+    /// Политика ключей: с ней команды с NUL или управляющими символами в
+    /// ключе или префиксе получают `CacheError::InvalidKey`, а при `Nfc`
+    /// ключи и префиксы всех команд, включая Keys и Scan, и ключи Import
+    /// приводятся к NFC. Вызывается после открытия: если ключи, уже
+    /// восстановленные из снапшота и WAL, политике не подходят (записаны без
+    /// неё), открытие отказывает, а не переименовывает их молча. Реплика
+    /// применяет записи основного сервера как есть.
+    pub fn set_key_normalization(
+        &mut self,
+        policy: Option<KeyNormalization>,
+    ) -> Result<(), CacheError> {
+        if let Some(policy) = policy {
+            let setting = format!("key_normalization={}", policy.as_str());
+            self.check_stored_keys(&setting, |key| policy.apply(key))?;
+        }
+        self.key_normalization = policy;
+        Ok(())
+    }
+
+    /// Приводить ли ключи к нижнему регистру. У сервера с WAL это задаёт
+    /// `PersistOptions::case_insensitive_keys` при открытии, а у read-only —
+    /// сам журнал; здесь значение только сверяется. Без персистентности
+    /// включается, если уже загруженные ключи в нижнем регистре.
+    pub fn set_case_insensitive_keys(&mut self, on: bool) -> Result<(), CacheError> {
+        if !matches!(self.persistence, Persistence::None) {
+            if on != self.fold_case {
+                return Err(CacheError::Unsupported(format!(
+                    "case_insensitive_keys={} does not match the WAL, written with \
+                     case_insensitive_keys={}",
+                    on, self.fold_case
+                )));
+            }
+            return Ok(());
+        }
+        if on {
+            self.check_stored_keys("case_insensitive_keys=True", |key| {
+                keys::fold_case(key);
+                Ok(())
+            })?;
+        }
+        self.fold_case = on;
+        Ok(())
+    }
+
+    // ключи в памяти, которые `fix` отклоняет или меняет, не дают включить настройку
+    fn check_stored_keys(
+        &self,
+        setting: &str,
+        fix: impl Fn(&mut String) -> Result<(), CacheError>,
+    ) -> Result<(), CacheError> {
+        let mut bad = 0u64;
+        let mut example = None;
+        self.core.try_for_each(|k, _, _, _| {
+            let mut key = k.to_string();
+            if fix(&mut key).is_err() || key != k {
+                bad += 1;
+                example.get_or_insert_with(|| k.to_string());
+            }
+            Ok::<_, CacheError>(())
+        })?;
+        match example {
+            Some(example) => Err(CacheError::InvalidKey(format!(
+                "{} stored keys do not fit {}, e.g. {:?}; export them and \
+                 import into an empty wal_dir started with this setting",
+                bad, setting, example
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // регистр, затем политика: NFC строчных букв — и есть сравниваемая форма
+    fn normalize_key(&self, key: &mut String) -> Result<(), CacheError> {
+        if self.fold_case {
+            keys::fold_case(key);
+        }
+        match self.key_normalization {
+            Some(policy) => policy.apply(key),
+            None => Ok(()),
+        }
+    }
+
+    /// Команда с ключами в форме сервера, см. `set_key_normalization` и
+    /// `set_case_insensitive_keys`.
+    pub(crate) fn normalize_keys(&self, mut cmd: CacheCommand) -> Result<CacheCommand, CacheError> {
+        if self.key_normalization.is_some() || self.fold_case {
+            keys::for_each_key(&mut cmd, &mut |key| self.normalize_key(key))?;
+        }
+        Ok(cmd)
+    }
+
+    /// Сколько прежних значений помнить у ключей на `prefix` (0 — не
+    /// помнить), см. `CacheCommand::History`; из нескольких подходящих
+    /// префиксов действует самый длинный. Прежним значением считается и удалённое.
+    /// История живёт только в памяти и в сумме не больше
+    /// `HISTORY_MAX_BYTES`: сверх этого первыми уходят самые старые версии.
+    pub fn set_history_depth(&self, prefix: String, depth: u32) -> Result<(), CacheError> {
+        if depth > history::MAX_HISTORY_DEPTH {
+            return Err(CacheError::Unsupported(format!(
+                "history depth {} is over the limit of {}",
+                depth,
+                history::MAX_HISTORY_DEPTH
+            )));
+        }
+        self.core.set_history_depth(prefix, depth as usize);
+        Ok(())
+    }
+
+    // срок надгробия для удаления сейчас; None — режим надгробий выключен
+    fn tombstone_purge_at(&self) -> Option<u64> {
+        Some(self.tombstone_ttl.load(Ordering::Relaxed))
+            .filter(|&ttl| ttl > 0)
+            .map(|ttl| core::now_unix_ms().saturating_add(ttl))
+    }
+
+    // удаление в режиме надгробий под read_gate: Tombstone пишется в WAL под
+    // блокировкой шарда. Возвращает, был ли ключ, и с `keep_value` — его значение
+    fn bury(
+        &self,
+        key: &str,
+        purge_at: u64,
+        cond: RemoveIf<'_>,
+        keep_value: bool,
+    ) -> Result<(bool, Option<Vec<u8>>), CacheError> {
+        let mut seq = 0;
+        let mut kept = None;
+        let buried = self
+            .core
+            .bury(key, purge_at, cond, |k, v, expires_at, tags| {
+                let tomb = Tombstone {
+                    value: v.to_vec(),
+                    expires_at,
+                    tags: tags.to_vec(),
+                    purge_at,
+                };
+                seq = self.log(&WalRecord::Tombstone(k.to_string(), tomb))?;
+                if keep_value {
+                    kept = Some(v.to_vec());
+                }
+                Ok::<_, CacheError>(())
+            })?;
+        if buried {
+            self.watchers.notify(key, WatchOp::Del, None);
+            self.applied(seq);
+        }
+        Ok((buried, kept))
+    }
+
+    /// Возвращает ключ из надгробия; `false`, если надгробия нет, его срок
+    /// прошёл или ключ уже записан заново.
+    pub fn undelete(&self, key: &str) -> Result<bool, CacheError> {
+        let found = {
+            let _g = self.read_gate()?;
+            let mut seq = 0;
+            let found = self.core.undelete(key.to_string(), |k| {
+                seq = self.log(&WalRecord::Undelete(k.to_string()))?;
+                Ok::<_, CacheError>(())
+            })?;
+            if found {
+                self.notify_changed(key);
+                self.applied(seq);
+            }
+            found
+        };
+        self.maybe_compact()?;
+        Ok(found)
+    }
+
+    /// Атомарно меняет местами значения `a` и `b` вместе со сроками и тегами,
+    /// одной записью WAL; если есть только один ключ, он переименовывается.
+    /// `false` — не было ни одного, и ничего не записано.
+    pub fn swap(&self, a: &str, b: &str) -> Result<bool, CacheError> {
+        let any = {
+            let _g = self.read_gate()?;
+            let mut seq = None;
+            let had = self.core.swap(a, b, |a, b| {
+                seq = Some(self.log(&WalRecord::Swap(a.to_string(), b.to_string()))?);
+                Ok::<_, CacheError>(())
+            })?;
+            if let Some(seq) = seq {
+                self.notify_swapped(a, b, had);
+                self.applied(seq);
+            }
+            had.0 || had.1
+        };
+        self.maybe_compact()?;
+        Ok(any)
+    }
+
+    // `had` — были ли ключи до обмена
+    fn notify_swapped(&self, a: &str, b: &str, had: (bool, bool)) {
+        for (k, had_before, gets_value) in [(a, had.0, had.1), (b, had.1, had.0)] {
+            if gets_value {
+                self.notify_changed(k);
+            } else if had_before {
+                self.watchers.notify(k, WatchOp::Del, None);
+            }
+        }
+    }
+
+    /// `hook` получает события сервера (`ServerEvent`) прямо в потоках
+    /// приёма и соединений, поэтому должен быстро возвращаться.
+    pub fn set_event_hook(&mut self, hook: EventHook) {
+        self.events = Some(hook);
+    }
+
+    /// Мутации ключей, включая истечение срока, уходят в `feed` после
+    /// успешной записи в WAL, как события наблюдателям `Watch`; у реплики —
+    /// записи основного сервера. Записи, восстановленные из WAL при открытии,
+    /// в поток не попадают.
+    pub fn set_write_feed(&mut self, feed: Arc<WriteFeed>) {
+        self.watchers.feed = Some(feed);
+    }
+
+    /// Кого `serve_unix_socket` пускает по uid и gid собеседника, см.
+    /// `PeerAccess`; отказ пишется в stderr с uid и gid. Непустую политику
+    /// на платформе без учёток собеседника отклоняет с
+    /// `CacheError::Unsupported`, а не пускает всех.
+    pub fn set_peer_access(&mut self, access: PeerAccess) -> Result<(), CacheError> {
+        if !access.is_open() && !peer_access::SUPPORTED {
+            return Err(peer_access::unsupported());
+        }
+        self.peer_access = access;
+        Ok(())
+    }
+
+    // событие строится, только если его кто-то ждёт
+    fn emit(&self, event: impl FnOnce() -> ServerEvent) {
+        if let Some(hook) = &self.events {
+            hook(event());
+        }
+    }
+
+    fn check_admin(&self, token: &str) -> Result<(), CacheError> {
+        let Some(expected) = &self.admin_token else {
+            return Err(CacheError::PermissionDenied(
+                "admin commands are disabled on this server".into(),
+            ));
+        };
+        // сравнение без раннего выхода, чтобы время ответа не выдавало префикс токена
+        let same = expected.len() == token.len()
+            && expected
+                .bytes()
+                .zip(token.bytes())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0;
+        if same {
+            Ok(())
+        } else {
+            Err(CacheError::PermissionDenied("invalid admin token".into()))
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+
+    pub fn set_read_only(&self, read_only: bool) -> Result<(), CacheError> {
+        if !read_only && matches!(self.persistence, Persistence::ReadOnly { .. }) {
+            return Err(CacheError::Unsupported(
+                "the server was started with read_only=True and its WAL is not open for writing, \
+                 restart it without read_only"
+                    .into(),
+            ));
+        }
+        self.read_only.store(read_only, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Делает сервер репликой `primary`; поток репликации запускает `repl::spawn_replica`.
+    pub fn set_replica_of(&mut self, primary: String) {
+        self.replica = Some(ReplicaState {
+            primary,
+            connected: AtomicBool::new(false),
+            last_error: Mutex::new(String::new()),
+        });
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.replica.is_some()
+    }
+
+    /// Пишет мутацию в WAL и возвращает её seq (0 без персистентности).
+    fn log(&self, rec: &WalRecord) -> Result<u64, CacheError> {
+        match &self.persistence {
+            Persistence::Wal { wal, .. } => wal.append(rec),
+            Persistence::ReadOnly { .. } => Err(wal_read_only()),
+            Persistence::None => Ok(0),
+        }
+    }
+
+    /// Дописывает на диск записи, отложенные `wal::deferring`.
+    pub(crate) fn flush_wal(&self) -> Result<(), CacheError> {
+        match &self.persistence {
+            Persistence::Wal { wal, .. } => wal.flush_deferred(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Пишет мутации одним батчем и возвращает seq последней.
+    fn log_batch(&self, recs: &[WalRecord]) -> Result<u64, CacheError> {
+        match &self.persistence {
+            Persistence::Wal { wal, .. } if !recs.is_empty() => wal.append_batch(recs),
+            Persistence::ReadOnly { .. } => Err(wal_read_only()),
+            _ => Ok(0),
+        }
+    }
+
+    fn applied(&self, seq: u64) {
+        // записи одного батча применяются в произвольном порядке
+        self.last_seq.fetch_max(seq, Ordering::Release);
+    }
+
+    fn wal(&self) -> Result<(&Wal, &Path), CacheError> {
+        match &self.persistence {
+            Persistence::Wal { wal, snapshot_path } => Ok((wal, snapshot_path)),
+            Persistence::ReadOnly { .. } => Err(wal_read_only()),
+            Persistence::None => Err(CacheError::Unsupported(
+                "persistence is disabled on this server".into(),
+            )),
+        }
+    }
+
+    fn read_gate(&self) -> Result<RwLockReadGuard<'_, ()>, CacheError> {
+        self.gate
+            .read()
+            .map_err(|_| CacheError::Internal("persistence gate poisoned".into()))
+    }
+
+    fn write_gate(&self) -> Result<RwLockWriteGuard<'_, ()>, CacheError> {
+        self.gate
+            .write()
+            .map_err(|_| CacheError::Internal("persistence gate poisoned".into()))
+    }
+
+    pub fn set(&self, key: String, value: Vec<u8>) -> Result<(), CacheError> {
+        {
+            let _g = self.read_gate()?;
+            let rec = WalRecord::Set(key, value);
+            let seq = self.log(&rec)?;
+            self.apply_record(rec);
+            self.applied(seq);
+        }
+        self.maybe_compact()
+    }
+
+    /// Set с тегами; пустой список тегов — обычный Set.
+    pub fn set_tagged(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        tags: Vec<String>,
+    ) -> Result<(), CacheError> {
+        if tags.is_empty() {
+            return self.set(key, value);
+        }
+        {
+            let _g = self.read_gate()?;
+            let rec = WalRecord::SetTagged(key, value, tags, None);
+            let seq = self.log(&rec)?;
+            self.apply_record(rec);
+            self.applied(seq);
+        }
+        self.maybe_compact()
+    }
+
+    /// Пачка Set одним батчем WAL, как при импорте. Атомарности нет: при
+    /// обрыве хвоста журнала после сбоя может восстановиться только начало пачки.
+    pub fn set_many(&self, items: Vec<(String, Vec<u8>)>) -> Result<(), CacheError> {
+        {
+            let _g = self.read_gate()?;
+            let recs: Vec<WalRecord> = items
+                .into_iter()
+                .map(|(k, v)| WalRecord::Set(k, v))
+                .collect();
+            let seq = self.log_batch(&recs)?;
+            for rec in recs {
+                self.apply_record(rec);
+            }
+            self.applied(seq);
+        }
+        self.maybe_compact()
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.core.get(key)
+    }
+
+    pub fn get_consistent(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>, CacheError> {
+        let values = self.core.get_consistent(keys)?;
+        for (key, v) in keys.iter().zip(&values) {
+            self.hit_stats.record(key, v.is_some());
+        }
+        Ok(values)
+    }
+
+    /// `get` со сверкой crc32 значения, см. `set_verify_values`.
+    pub fn get_checked(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        self.core.get_checked(key)
+    }
+
+    fn get_entry(&self, key: &str) -> Option<(Vec<u8>, Option<u64>, Vec<String>)> {
+        self.core.get_entry(key)
+    }
+
+    fn for_each_tombstone(
+        &self,
+        f: impl FnMut(&str, &Tombstone) -> Result<(), CacheError>,
+    ) -> Result<(), CacheError> {
+        self.core.try_for_each_tombstone(f)
+    }
+
+    /// Значение ключа, а если его нет — атомарно записывает `value`; второй
+    /// элемент — записала ли значение эта команда. В WAL попадает обычный Set,
+    /// только когда запись состоялась.
+    pub fn get_or_set(&self, key: String, value: Vec<u8>) -> Result<(Vec<u8>, bool), CacheError> {
+        let res = {
+            let _g = self.read_gate()?;
+            let mut seq = 0;
+            // WAL пишется под блокировкой шарда: порядок в журнале совпадает с картой
+            let (v, inserted) = self.core.get_or_insert(key.clone(), value, None, |k, v| {
+                seq = self.log(&WalRecord::Set(k.to_string(), v.to_vec()))?;
+                Ok::<_, CacheError>(())
+            })?;
+            self.hit_stats.record(&key, !inserted);
+            if inserted {
+                self.waiters.wake(&key, &v);
+                self.watchers.notify(&key, WatchOp::Set, Some(&v));
+                self.applied(seq);
+            }
+            (v, inserted)
+        };
+        self.maybe_compact()?;
+        Ok(res)
+    }
+
+    /// Записывает `value` со сроком `ttl_ms` (0 — бессрочно), только если
+    /// ключа нет, и возвращает, записала ли. В WAL попадает SetEx (или Set) с
+    /// абсолютным сроком, только когда запись состоялась.
+    pub fn set_nx(&self, key: String, value: Vec<u8>, ttl_ms: u64) -> Result<bool, CacheError> {
+        let at = (ttl_ms > 0).then(|| core::now_unix_ms().saturating_add(ttl_ms));
+        Ok(self.insert_absent(key, value, at)?.1)
+    }
+
+    // записывает значение со сроком `at`, если ключа нет; возвращает значение
+    // ключа после команды и записала ли его она
+    fn insert_absent(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        at: Option<u64>,
+    ) -> Result<(Vec<u8>, bool), CacheError> {
+        let res = {
+            let _g = self.read_gate()?;
+            let mut seq = 0;
+            let (v, inserted) = self.core.get_or_insert(key.clone(), value, at, |k, v| {
+                let rec = match at {
+                    Some(at) => WalRecord::SetEx(k.to_string(), v.to_vec(), at),
+                    None => WalRecord::Set(k.to_string(), v.to_vec()),
+                };
+                seq = self.log(&rec)?;
+                Ok::<_, CacheError>(())
+            })?;
+            if inserted {
+                self.waiters.wake(&key, &v);
+                self.watchers.notify(&key, WatchOp::Set, Some(&v));
+                self.applied(seq);
+            }
+            (v, inserted)
+        };
+        self.maybe_compact()?;
+        Ok(res)
+    }
+
+    /// Загрузчик для промахов Get на пуле из `threads` потоков, см.
+    /// `loader.rs`. Загруженное значение записывается, только если ключ за
+    /// это время не появился, и попадает в WAL, как Set. Реплика ключи не
+    /// пишет, поэтому загрузчик ей не ставится.
+    pub fn set_loader(&mut self, loader: Loader, threads: usize) -> Result<(), CacheError> {
+        if self.replica.is_some() {
+            return Err(CacheError::Unsupported(
+                "a replica cannot have a loader: it only applies the primary's writes".into(),
+            ));
+        }
+        self.loader = Some(ReadThrough::start(loader, threads)?);
+        Ok(())
+    }
+
+    /// `get_checked`, а при промахе — значение от загрузчика, если он есть.
+    /// Одновременные промахи по ключу ждут один вызов загрузчика.
+    pub fn get_or_load(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        let v = self.get_checked(key)?;
+        // значение из loader — всё равно промах кэша
+        self.hit_stats.record(key, v.is_some());
+        if v.is_some() {
+            return Ok(v);
+        }
+        let Some(rt) = &self.loader else {
+            return Ok(None);
+        };
+        rt.load(key, |loaded| {
+            // в read-only значение отдаётся, но не записывается
+            if self.read_only.load(Ordering::SeqCst) {
+                return Ok(Some(loaded.value));
+            }
+            let at = loaded
+                .ttl
+                .map(|ttl| core::now_unix_ms().saturating_add((ttl.as_millis() as u64).max(1)));
+            Ok(Some(
+                self.insert_absent(key.to_string(), loaded.value, at)?.0,
+            ))
+        })
+    }
+
+    /// Атомарно применяет `op` к значению ключа и возвращает результат; в WAL
+    /// попадает Set с новым значением, только если оно изменилось.
+    pub fn update(&self, key: String, op: &UpdateOp) -> Result<Option<Vec<u8>>, CacheError> {
+        let res = {
+            let _g = self.read_gate()?;
+            let mut seq = 0;
+            let (v, changed) = self.core.update(
+                key.clone(),
+                |cur| op.apply(&key, cur),
+                |k, v, expires_at, tags| {
+                    // срок и теги ключа после Update остаются прежними
+                    let rec = match (expires_at, tags) {
+                        (_, [_, ..]) => WalRecord::SetTagged(
+                            k.to_string(),
+                            v.to_vec(),
+                            tags.to_vec(),
+                            expires_at,
+                        ),
+                        (Some(at), []) => WalRecord::SetEx(k.to_string(), v.to_vec(), at),
+                        (None, []) => WalRecord::Set(k.to_string(), v.to_vec()),
+                    };
+                    seq = self.log(&rec)?;
+                    Ok(())
+                },
+            )?;
+            if let (Some(v), true) = (&v, changed) {
+                self.waiters.wake(&key, v);
+                self.watchers.notify(&key, WatchOp::Set, Some(v));
+                self.applied(seq);
+            }
+            v
+        };
+        self.maybe_compact()?;
+        Ok(res)
+    }
+
+    /// Атомарно сдвигает счётчик `key` (десятичный текст, как у `AddI64`) на
+    /// `n` и возвращает прежнее значение: вызывающему достаются номера
+    /// `prev + 1..=prev + n`. Новое значение попадает в WAL до ответа, а при
+    /// fsync "everysec" ещё и сбрасывается на диск, так что выданные номера не
+    /// повторятся после перезапуска, даже если часть из них не использовали.
+    pub fn incr_range(&self, key: String, n: u64) -> Result<i64, CacheError> {
+        let add = i64::try_from(n).ok().filter(|&n| n > 0).ok_or_else(|| {
+            CacheError::Unsupported(format!(
+                "incr_range count must be between 1 and {}, got {}",
+                i64::MAX,
+                n
+            ))
+        })?;
+        // AddI64 всегда оставляет ключ с десятичным числом
+        let new = self
+            .update(key.clone(), &UpdateOp::AddI64(add))?
+            .and_then(|v| std::str::from_utf8(&v).ok()?.parse::<i64>().ok())
+            .ok_or_else(|| {
+                CacheError::Internal(format!("counter {:?} is not an i64 after incr_range", key))
+            })?;
+        if let Persistence::Wal { wal, .. } = &self.persistence {
+            if wal.fsync_policy() == FsyncPolicy::EverySec {
+                wal.sync()?;
+            }
+        }
+        Ok(new - add)
+    }
+
+    /// Ставит бит и возвращает прежний; значение дописывается нулями до
+    /// нужной длины. В WAL попадает SetBit, только если значение изменилось.
+    pub fn set_bit(&self, key: String, offset: u64, bit: bool) -> Result<bool, CacheError> {
+        let max = self.max_bit_offset.load(Ordering::Relaxed);
+        if offset > max {
+            return Err(CacheError::Unsupported(format!(
+                "bit offset {} is over the server limit of {}",
+                offset, max
+            )));
+        }
+        let prev = {
+            let _g = self.read_gate()?;
+            let mut seq = 0;
+            let (prev, changed) = self.core.set_bit(key.clone(), offset, bit, |k, new| {
+                let rec = match new {
+                    Some(v) => WalRecord::Set(k.to_string(), v.to_vec()),
+                    None => WalRecord::SetBit(k.to_string(), offset, bit),
+                };
+                seq = self.log(&rec)?;
+                Ok::<_, CacheError>(())
+            })?;
+            if changed {
+                self.notify_changed(&key);
+                self.applied(seq);
+            }
+            prev
+        };
+        self.maybe_compact()?;
+        Ok(prev)
+    }
+
+    pub fn get_bit(&self, key: &str, offset: u64) -> bool {
+        self.core.get_bit(key, offset)
+    }
+
+    pub fn bit_count(&self, key: &str, range: Option<(i64, i64)>) -> i64 {
+        self.core.bit_count(key, range)
+    }
+
+    // после изменения на месте: значение для событий читается заново
+    fn notify_changed(&self, key: &str) {
+        if self.watchers.active() || self.waiters.active() {
+            if let Some(v) = self.core.get(key) {
+                self.waiters.wake(key, &v);
+                self.watchers.notify(key, WatchOp::Set, Some(&v));
+            }
+        }
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.core.contains(key)
+    }
+
+    pub fn touch(&self, keys: &[String]) -> i64 {
+        self.core.touch(keys)
+    }
+
+    /// Set со сроком жизни `ttl_ms`; в WAL попадает абсолютный срок.
+    pub fn set_ex(&self, key: String, value: Vec<u8>, ttl_ms: u64) -> Result<(), CacheError> {
+        if ttl_ms == 0 {
+            return Err(CacheError::Unsupported(
+                "expire time must be positive".into(),
+            ));
+        }
+        let at = core::now_unix_ms().saturating_add(ttl_ms);
+        {
+            let _g = self.read_gate()?;
+            let rec = WalRecord::SetEx(key, value, at);
+            let seq = self.log(&rec)?;
+            self.apply_record(rec);
+            self.applied(seq);
+        }
+        self.maybe_compact()
+    }
+
+    /// Ставит ключу срок `at` (unix-время в мс) и возвращает, есть ли ключ.
+    /// Прошедший срок удаляет ключ сразу, в WAL тогда попадает Del.
+    pub fn expire_at(&self, key: &str, at: u64) -> Result<bool, CacheError> {
+        if at <= core::now_unix_ms() {
+            return Ok(self.delete(key)? > 0);
+        }
+        let found = {
+            let _g = self.read_gate()?;
+            let mut seq = 0;
+            let found = self.core.set_expiry(key.to_string(), at, |k| {
+                seq = self.log(&WalRecord::Expire(k.to_string(), at))?;
+                Ok::<_, CacheError>(())
+            })?;
+            if found {
+                self.applied(seq);
+            }
+            found
+        };
+        self.maybe_compact()?;
+        Ok(found)
+    }
+
+    /// Продлевает срок ключа на `add_ms`, только если его значение равно
+    /// `expected`, и возвращает, продлила ли; в WAL попадает Expire с новым
+    /// абсолютным сроком (см. `CacheCore::extend_if_equals`).
+    pub fn extend_if_equals(
+        &self,
+        key: &str,
+        expected: &[u8],
+        add_ms: u64,
+    ) -> Result<bool, CacheError> {
+        let extended = {
+            let _g = self.read_gate()?;
+            let mut seq = 0;
+            let at = self
+                .core
+                .extend_if_equals(key.to_string(), expected, add_ms, |k, at| {
+                    seq = self.log(&WalRecord::Expire(k.to_string(), at))?;
+                    Ok::<_, CacheError>(())
+                })?;
+            if at.is_some() {
+                self.applied(seq);
+            }
+            at.is_some()
+        };
+        self.maybe_compact()?;
+        Ok(extended)
+    }
+
+    /// Срок жизни в мс от текущего момента; `ttl_ms <= 0` удаляет ключ.
+    pub fn expire(&self, key: &str, ttl_ms: i64) -> Result<bool, CacheError> {
+        let at = (core::now_unix_ms() as i64).saturating_add(ttl_ms).max(0);
+        self.expire_at(key, at as u64)
+    }
+
+    /// Оставшийся срок в мс; -1 — ключ бессрочный, -2 — ключа нет.
+    pub fn get_with_meta(&self, key: &str) -> Option<ValueMeta> {
+        let meta = self.core.get_with_meta(key);
+        self.hit_stats.record(key, meta.is_some());
+        let (value, expires_at, written_at, version) = meta?;
+        let now = core::now_unix_ms();
+        Some(ValueMeta {
+            value,
+            written_at,
+            ttl_ms: expires_at.map(|at| at.saturating_sub(now).max(1)),
+            version,
+            now,
+        })
+    }
+
+    pub fn pttl(&self, key: &str) -> i64 {
+        match self.core.expires_at(key) {
+            None => -2,
+            Some(None) => -1,
+            // живой ключ истекает не раньше следующей миллисекунды
+            Some(Some(at)) => at.saturating_sub(core::now_unix_ms()).max(1) as i64,
+        }
+    }
+
+    /// Убирает из памяти истёкшие ключи и рассылает по ним `expired`. В WAL
+    /// ничего не пишется: срок там уже записан, и replay придёт к тому же.
+    pub fn expire_due(&self) {
+        for key in self.core.take_expired() {
+            self.watchers.notify(&key, WatchOp::Expired, None);
+        }
+        self.core.purge_tombstones();
+    }
+
+    /// Запускает поток истечения, когда у ключей впервые появились сроки.
+    fn start_expiry(self: &Arc<Self>) {
+        if self.expiry_started.load(Ordering::Relaxed)
+            || !self.core.may_expire()
+            || self.expiry_started.swap(true, Ordering::SeqCst)
+        {
+            return;
+        }
+        // поток не держит ядро: после его удаления поток завершается
+        let weak = Arc::downgrade(self);
+        let res = thread::Builder::new()
+            .name("tiny-mp-cache-expiry".into())
+            .spawn(move || loop {
+                thread::sleep(EXPIRY_INTERVAL);
+                match weak.upgrade() {
+                    Some(core) => core.expire_due(),
+                    None => return,
+                }
+            });
+        if let Err(e) = res {
+            eprintln!("spawn expiry thread: {}", e);
+            self.expiry_started.store(false, Ordering::SeqCst);
+        }
+    }
+
+    pub fn idle_time(&self, key: &str) -> Option<u64> {
+        self.core.idle_time(key)
+    }
+
+    /// Прежние значения ключа от старых к новым, см. `set_history_depth`.
+    pub fn history(&self, key: &str) -> Vec<(u64, Vec<u8>)> {
+        self.core.history(key)
+    }
+
+    /// Вид значения ключа: `type` — "bytes" или "none" для отсутствующего,
+    /// `encoding` — "pickle" для значений `set_obj()`, "zstd" или "lz4" для
+    /// сжатых клиентом (см. `ValueEncoding`), иначе "raw", `length` — длина в
+    /// байтах, у сжатых — сжатых. Обращением к ключу не считается.
+    pub fn key_type(&self, key: &str) -> Vec<(String, ResponseValue)> {
+        let Some((encoding, length)) = self.core.peek(key, |v| {
+            let encoding = match ValueEncoding::of(v) {
+                ValueEncoding::Raw if v.starts_with(OBJ_MAGIC) => "pickle",
+                encoding => encoding.as_str(),
+            };
+            (encoding, v.len())
+        }) else {
+            return vec![("type".into(), "none".into())];
+        };
+        vec![
+            ("type".into(), "bytes".into()),
+            ("encoding".into(), encoding.into()),
+            ("length".into(), length.into()),
+        ]
+    }
+
+    /// Значение ключа, а если его нет — первое записанное за `timeout`.
+    pub fn get_blocking(
+        &self,
+        key: &str,
+        timeout: Duration,
+    ) -> Result<Option<Vec<u8>>, CacheError> {
+        self.waiters.wait(key, timeout, || self.core.get(key))
+    }
+
+    /// Удаляет ключ и возвращает его значение. Промах (ключа нет или он
+    /// истёк) в WAL не пишется; истёкший ключ остаётся сборщику.
+    pub fn pop(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        let v = {
+            let _g = self.read_gate()?;
+            // испорченное значение остаётся на месте, в WAL ничего не пишется
+            self.core.verify_value(key)?;
+            match self.tombstone_purge_at() {
+                Some(purge_at) => self.bury(key, purge_at, RemoveIf::Always, true)?.1,
+                None => {
+                    // Pop пишется в WAL, только если ключ жив: под блокировкой
+                    // шарда, до удаления
+                    let mut seq = 0;
+                    let v = self.core.take_if(key, RemoveIf::Always, |k| {
+                        seq = self.log(&WalRecord::Pop(k.to_string()))?;
+                        Ok::<_, CacheError>(())
+                    })?;
+                    if v.is_some() {
+                        self.watchers.notify(key, WatchOp::Del, None);
+                        self.applied(seq);
+                    }
+                    v
+                }
+            }
+        };
+        self.maybe_compact()?;
+        Ok(v)
+    }
+
+    /// Удаляет ключ и возвращает 1, если он был; промах в WAL не пишется,
+    /// как и у `pop`.
+    pub fn delete(&self, key: &str) -> Result<i64, CacheError> {
+        let n = {
+            let _g = self.read_gate()?;
+            match self.tombstone_purge_at() {
+                Some(purge_at) => self.bury(key, purge_at, RemoveIf::Always, false)?.0 as i64,
+                None => {
+                    let mut seq = 0;
+                    let removed = self.core.remove_if(key, RemoveIf::Always, |k| {
+                        seq = self.log(&WalRecord::Del(k.to_string()))?;
+                        Ok::<_, CacheError>(())
+                    })?;
+                    if removed {
+                        self.watchers.notify(key, WatchOp::Del, None);
+                        self.applied(seq);
+                    }
+                    removed as i64
+                }
+            }
+        };
+        self.maybe_compact()?;
+        Ok(n)
+    }
+
+    /// Удаляет ключ, только если его значение равно `expected`, и возвращает,
+    /// удалила ли. Сравнение и удаление идут под блокировкой шарда, Del (или
+    /// Tombstone) пишется в WAL только при удалении.
+    pub fn delete_if_equals(&self, key: &str, expected: &[u8]) -> Result<bool, CacheError> {
+        let removed = {
+            let _g = self.read_gate()?;
+            match self.tombstone_purge_at() {
+                Some(purge_at) => {
+                    self.bury(key, purge_at, RemoveIf::Equals(expected), false)?
+                        .0
+                }
+                None => {
+                    let mut seq = 0;
+                    let removed = self.core.remove_if(key, RemoveIf::Equals(expected), |k| {
+                        seq = self.log(&WalRecord::Del(k.to_string()))?;
+                        Ok::<_, CacheError>(())
+                    })?;
+                    if removed {
+                        self.watchers.notify(key, WatchOp::Del, None);
+                        self.applied(seq);
+                    }
+                    removed
+                }
+            }
+        };
+        self.maybe_compact()?;
+        Ok(removed)
+    }
+
+    /// Удаляет все ключи с префиксом одним батчем WAL и возвращает их число.
+    /// Ключ, записанный параллельно с удалением, может и уцелеть. `deadline`
+    /// проверяется только пока собираются ключи: после записи батча в WAL
+    /// удаление доводится до конца. В режиме надгробий каждый ключ пишется в
+    /// WAL своим Tombstone.
+    pub fn delete_prefix(&self, prefix: &str, deadline: Deadline) -> Result<i64, CacheError> {
+        let n = {
+            let _g = self.read_gate()?;
+            let keys = self.core.keys_within(prefix, deadline)?;
+            match self.tombstone_purge_at() {
+                Some(purge_at) => {
+                    let mut n = 0;
+                    for key in keys {
+                        n += self.bury(&key, purge_at, RemoveIf::Always, false)?.0 as i64;
+                    }
+                    n
+                }
+                None => {
+                    let recs: Vec<WalRecord> = keys.iter().cloned().map(WalRecord::Del).collect();
+                    let seq = self.log_batch(&recs)?;
+                    let mut n = 0;
+                    for key in keys {
+                        if self.core.delete(&key) > 0 {
+                            self.watchers.notify(&key, WatchOp::Del, None);
+                            n += 1;
+                        }
+                    }
+                    self.applied(seq);
+                    n
+                }
+            }
+        };
+        self.maybe_compact()?;
+        Ok(n)
+    }
+
+    /// Удаляет все ключи с тегом и возвращает их число. Del каждого ключа
+    /// пишется в WAL под блокировкой его шарда, так что ключ, перезаписанный
+    /// параллельно без тега, не удаляется.
+    pub fn delete_by_tag(&self, tag: &str) -> Result<i64, CacheError> {
+        let n = {
+            let _g = self.read_gate()?;
+            let mut n = 0;
+            let mut seq = 0;
+            let purge_at = self.tombstone_purge_at();
+            for key in self.core.keys_tagged(tag) {
+                if let Some(purge_at) = purge_at {
+                    n += self.bury(&key, purge_at, RemoveIf::Tagged(tag), false)?.0 as i64;
+                    continue;
+                }
+                let removed = self.core.remove_if(&key, RemoveIf::Tagged(tag), |k| {
+                    seq = self.log(&WalRecord::Del(k.to_string()))?;
+                    Ok::<_, CacheError>(())
+                })?;
+                if removed {
+                    self.watchers.notify(&key, WatchOp::Del, None);
+                    self.applied(seq);
+                    n += 1;
+                }
+            }
+            n
+        };
+        self.maybe_compact()?;
+        Ok(n)
+    }
+
+    pub fn keys_prefix(&self, prefix: &str, deadline: Deadline) -> Result<Vec<String>, CacheError> {
+        self.core.keys_within(prefix, deadline)
+    }
+
+    pub fn keys_sorted(
+        &self,
+        prefix: &str,
+        offset: usize,
+        limit: usize,
+        deadline: Deadline,
+    ) -> Result<Vec<String>, CacheError> {
+        self.core.keys_sorted(prefix, offset, limit, deadline)
+    }
+
+    /// Фильтр Блума по ключам на `prefix`, `bits_per_key` бит на ключ: 10 бит
+    /// дают около 1% ложных «есть». Ключи не собираются в память, но карта
+    /// обходится дважды.
+    pub fn export_bloom(
+        &self,
+        prefix: &str,
+        bits_per_key: u32,
+        deadline: Deadline,
+    ) -> Result<BloomFilter, CacheError> {
+        if !(1..=bloom::MAX_BITS_PER_KEY).contains(&bits_per_key) {
+            return Err(CacheError::Unsupported(format!(
+                "bits_per_key must be between 1 and {}",
+                bloom::MAX_BITS_PER_KEY
+            )));
+        }
+        self.core.bloom(prefix, bits_per_key, deadline)
+    }
+
+    /// До `n` случайных живых ключей с размером значения, простоем и
+    /// сроком, каждый с равной вероятностью, см. `CacheCore::sample`: для
+    /// оценки, что вытеснять, без полного обхода.
+    pub fn sample(&self, n: usize) -> Vec<KeySample> {
+        self.core.sample(n)
+    }
+
+    /// Ключи и байты значений по группам ключей: `depth` первых сегментов,
+    /// разделённых `sep`, см. `CacheCore::prefix_stats`. Ответ `Map`: "groups"
+    /// — словарь группа -> {"keys", "bytes"} от самых больших по байтам,
+    /// "truncated" и, если групп больше `MAX_PREFIX_GROUPS`, "other" с
+    /// ключами и байтами остальных.
+    pub fn prefix_stats(
+        &self,
+        sep: &str,
+        depth: u32,
+        deadline: Deadline,
+    ) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        if sep.is_empty() || depth == 0 {
+            return Err(CacheError::Unsupported(
+                "prefix stats need a non-empty separator and depth of at least 1".into(),
+            ));
+        }
+        let stats = self.core.prefix_stats(sep, depth, deadline)?;
+        let counts = |(keys, bytes): (u64, u64)| {
+            ResponseValue::Nested(vec![
+                ("keys".into(), keys.into()),
+                ("bytes".into(), bytes.into()),
+            ])
+        };
+        let mut groups: Vec<_> = stats.groups.into_iter().collect();
+        groups.sort_unstable_by(|a, b| b.1 .1.cmp(&a.1 .1).then_with(|| a.0.cmp(&b.0)));
+        let mut map = vec![
+            (
+                "groups".into(),
+                ResponseValue::Nested(groups.into_iter().map(|(g, c)| (g, counts(c))).collect()),
+            ),
+            ("truncated".into(), stats.other.is_some().into()),
+        ];
+        if let Some(other) = stats.other {
+            map.push(("other".into(), counts(other)));
+        }
+        Ok(map)
+    }
+
+    pub fn len(&self) -> i64 {
+        self.core.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.core.is_empty()
+    }
+
+    pub fn scan_items(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        deadline: Deadline,
+    ) -> Result<ScanPage, CacheError> {
+        self.core.scan_items(cursor, prefix, count, deadline)
+    }
+
+    pub fn scan_keys(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        deadline: Deadline,
+    ) -> Result<(u64, Vec<String>), CacheError> {
+        self.core.scan_keys(cursor, prefix, count, deadline)
+    }
+
+    pub fn scan_sizes(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        sizes: RangeInclusive<u64>,
+        deadline: Deadline,
+    ) -> Result<(u64, Vec<(String, u64)>), CacheError> {
+        self.core.scan_sizes(cursor, prefix, count, sizes, deadline)
+    }
+
+    pub fn aggregate(
+        &self,
+        prefix: &str,
+        op: AggOp,
+        skip_invalid: bool,
+        deadline: Deadline,
+    ) -> Result<Option<i64>, CacheError> {
+        self.core.aggregate(prefix, op, skip_invalid, deadline)
+    }
+
+    pub fn scan_ttls(
+        &self,
+        cursor: u64,
+        prefix: &str,
+        count: usize,
+        deadline: Deadline,
+    ) -> Result<(u64, Vec<(String, i64)>), CacheError> {
+        self.core.scan_ttls(cursor, prefix, count, deadline)
+    }
+
+    /// Путь к файлу дампа: относительный считается от каталога с WAL
+    /// (без персистентности — от текущего каталога сервера).
+    fn dump_path(&self, path: &str) -> PathBuf {
+        let mut full = match &self.persistence {
+            Persistence::Wal { snapshot_path, .. }
+            | Persistence::ReadOnly { snapshot_path, .. } => snapshot_path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            Persistence::None => PathBuf::new(),
+        };
+        full.push(path);
+        full
+    }
+
+    pub fn export(
+        &self,
+        path: &str,
+        format: DumpFormat,
+        deadline: Deadline,
+    ) -> Result<u64, CacheError> {
+        dump::export(&self.core, &self.dump_path(path), format, deadline)
+    }
+
+    /// Загружает дамп и возвращает число записанных ключей. Файл сначала читается
+    /// и проверяется целиком, так что битый дамп не меняет ни одного ключа; затем
+    /// пары применяются батчами по IMPORT_BATCH — одна запись в WAL на батч.
+    pub fn import(&self, path: &str, replace: bool) -> Result<u64, CacheError> {
+        self.import.try_start()?;
+        let res = self.import_file(&self.dump_path(path), replace);
+        self.import.finish(&res);
+        res
+    }
+
+    fn import_file(&self, path: &Path, replace: bool) -> Result<u64, CacheError> {
+        let state = &self.import;
+        let mut entries = dump::read(path)?;
+        // как и сам дамп, ключи проверяются до первой записи
+        for e in &mut entries {
+            self.normalize_key(&mut e.key)?;
+        }
+        let bytes_total = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        state.bytes_total.store(bytes_total, Ordering::Relaxed);
+        state
+            .keys_total
+            .store(entries.len() as u64, Ordering::Relaxed);
+
+        let mut entries = entries.into_iter().peekable();
+        while entries.peek().is_some() {
+            let mut end = 0;
+            let mut skipped = 0;
+            let mut recs = Vec::with_capacity(IMPORT_BATCH);
+            for e in entries.by_ref().take(IMPORT_BATCH) {
+                end = e.end;
+                if !replace && self.core.contains(&e.key) {
+                    skipped += 1;
+                } else {
+                    recs.push(WalRecord::Set(e.key, e.value));
+                }
+            }
+            let loaded = recs.len() as u64;
+            {
+                let _g = self.read_gate()?;
+                let seq = self.log_batch(&recs)?;
+                for rec in recs {
+                    self.apply_record(rec);
+                }
+                self.applied(seq);
+            }
+            state.keys_loaded.fetch_add(loaded, Ordering::Relaxed);
+            state.keys_skipped.fetch_add(skipped, Ordering::Relaxed);
+            state.bytes_loaded.store(end, Ordering::Relaxed);
+            self.maybe_compact()?;
+        }
+        state.bytes_loaded.store(bytes_total, Ordering::Relaxed);
+        Ok(state.keys_loaded.load(Ordering::Relaxed))
+    }
+
+    /// Прогрев до старта сервера: `f` пишет в ядро, а WAL на это время в
+    /// режиме bulk (`Wal::begin_bulk`). Накопленное дописывается и проходит
+    /// fsync, даже если `f` вернул ошибку; её разбирает вызывающий.
+    pub fn preload<T>(&self, f: impl FnOnce() -> T) -> Result<T, CacheError> {
+        if self.is_read_only() || self.is_replica() {
+            return Err(CacheError::Unsupported(
+                "preload needs a writable server, not a replica or read_only".into(),
+            ));
+        }
+        let Persistence::Wal { wal, .. } = &self.persistence else {
+            return Ok(f());
+        };
+        wal.begin_bulk()?;
+        let out = f();
+        wal.end_bulk()?;
+        Ok(out)
+    }
+
+    /// Пишет снапшот текущего состояния в `path`, WAL не трогает. Возвращает seq снапшота.
+    pub fn snapshot(&self, path: &std::path::Path) -> Result<u64, CacheError> {
+        let (wal, _) = self.wal()?;
+        let _g = self.write_gate()?;
+        let seq = wal.last_seq()?;
+        snapshot::write(path, &self.core, seq, wal.key(), || {})?;
+        Ok(seq)
+    }
+
+    /// Снапшот в штатный файл + удаление покрытых им сегментов WAL. Порядок важен:
+    /// если упадём между шагами, старые сегменты просто перекрываются снапшотом по seq.
+    pub fn compact(&self) -> Result<u64, CacheError> {
+        self.wal()?;
+        self.save.try_start(self.core.len() as u64)?;
+        let res = self.write_gate().and_then(|_g| self.compact_locked());
+        self.save.finish(*res.as_ref().unwrap_or(&0), &res);
+        res
+    }
+
+    fn compact_locked(&self) -> Result<u64, CacheError> {
+        let (wal, snapshot_path) = self.wal()?;
+        let seq = wal.rotate()?;
+        snapshot::write(snapshot_path, &self.core, seq, wal.key(), || {
+            self.save.keys_saved.fetch_add(1, Ordering::Relaxed);
+        })?;
+        wal.remove_covered(seq)?;
+        Ok(seq)
+    }
+
+    fn maybe_compact(&self) -> Result<(), CacheError> {
+        let (Some(limit), Persistence::Wal { wal, .. }) = (self.compact_after, &self.persistence)
+        else {
+            return Ok(());
+        };
+        if wal.pending()? < limit {
+            return Ok(());
+        }
+        // уже идёт сохранение — компакция случится после него
+        if self.save.try_start(self.core.len() as u64).is_err() {
+            return Ok(());
+        }
+        let res = self.write_gate().and_then(|_g| {
+            // пока ждали блокировку, компакцию мог сделать соседний поток
+            if wal.pending()? >= limit {
+                self.compact_locked()
+            } else {
+                wal.last_seq()
+            }
+        });
+        self.save.finish(*res.as_ref().unwrap_or(&0), &res);
+        res.map(|_| ())
+    }
+
+    /// Фоновый снапшот: под короткой write-блокировкой начинает новый сегмент WAL,
+    /// затем обходит карту в отдельном потоке, не останавливая запись. После
+    /// снапшота удаляются сегменты до зафиксированного seq.
+    pub fn bgsave(self: &Arc<Self>) -> Result<(), CacheError> {
+        let (wal, _) = self.wal()?;
+        self.save.try_start(self.core.len() as u64)?;
+        // все записи до seq уже применены к памяти: мутации держат read-блокировку
+        let seq = match self.write_gate().and_then(|_g| wal.rotate()) {
+            Ok(seq) => seq,
+            Err(e) => {
+                self.save.finish(0, &Err::<(), _>(e.clone()));
+                return Err(e);
+            }
+        };
+
+        let me = Arc::clone(self);
+        thread::Builder::new()
+            .name("tiny-mp-cache-bgsave".into())
+            .spawn(move || {
+                let res = me.wal().and_then(|(wal, snapshot_path)| {
+                    snapshot::write(snapshot_path, &me.core, seq, wal.key(), || {
+                        me.save.keys_saved.fetch_add(1, Ordering::Relaxed);
+                    })?;
+                    wal.remove_covered(seq)
+                });
+                if let Err(e) = &res {
+                    eprintln!("bgsave error: {:?}", e);
+                }
+                me.save.finish(seq, &res);
+            })
+            .map_err(|e| {
+                let err = CacheError::Internal(format!("spawn bgsave thread: {}", e));
+                self.save.finish(0, &Err::<(), _>(err.clone()));
+                err
+            })?;
+        Ok(())
+    }
+
+    /// Сверяет память с состоянием, которое дают снапшот и WAL: они
+    /// доигрываются в отдельный `CacheCore` (на время проверки данные в памяти
+    /// занимают вдвое больше места) и сравниваются с живой картой, без
+    /// `values` — только по наличию ключей. Запись при этом не
+    /// останавливается, а расхождения по ключам, записанным после начала
+    /// проверки, не считаются. Пока идёт проверка, save и bgsave отвечают
+    /// Busy: они переписывают файлы, которые она читает.
+    pub fn verify(&self, values: bool, max_examples: usize) -> Result<VerifyReport, CacheError> {
+        let (wal, snapshot_path) = self.wal()?;
+        self.save
+            .in_progress
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .map_err(|_| CacheError::Busy("background save already in progress".into()))?;
+        let res = self.verify_files(wal, snapshot_path, values, max_examples);
+        self.save.in_progress.store(false, Ordering::SeqCst);
+        res
+    }
+
+    fn verify_files(
+        &self,
+        wal: &Wal,
+        snapshot_path: &Path,
+        values: bool,
+        max_examples: usize,
+    ) -> Result<VerifyReport, CacheError> {
+        // все записи до seq уже применены к памяти
+        let seq = {
+            let _g = self.write_gate()?;
+            wal.last_seq()?
+        };
+        let scratch = CacheCore::new();
+        let snapshot_seq = snapshot::load(snapshot_path, &scratch, wal.key())?.unwrap_or(0);
+        wal::replay_read_only(
+            wal.path(),
+            &scratch,
+            snapshot_seq,
+            wal.key(),
+            None,
+            &mut |_| {},
+        )?;
+        scratch.take_expired();
+        let (checked, found) = verify::diff(&self.core, &scratch, values);
+        // запись попадает в WAL раньше, чем в память: всё, что diff мог
+        // увидеть после seq, уже в журнале
+        let changed = wal::keys_after(wal.path(), wal.key(), seq)?;
+        Ok(verify::report(seq, checked, found, &changed, max_examples))
+    }
+
+    /// Подписка реплики: под write-блокировкой все записи до возвращённого seq
+    /// уже применены к памяти, а все следующие придут в канал.
+    fn repl_subscribe(&self) -> Result<(u64, Subscription), CacheError> {
+        let (wal, _) = self.wal()?;
+        let _g = self.write_gate()?;
+        wal.subscribe()
+    }
+
+    fn track_replica(&self) -> ReplicaGuard<'_> {
+        self.replicas.fetch_add(1, Ordering::Relaxed);
+        ReplicaGuard(&self.replicas)
+    }
+
+    /// seq основного сервера, до которого реплика применила записи.
+    fn repl_position(&self) -> u64 {
+        self.last_seq.load(Ordering::Acquire)
+    }
+
+    fn repl_connected(&self) {
+        if let Some(r) = &self.replica {
+            r.connected.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn repl_disconnected(&self, err: &CacheError) {
+        if let Some(r) = &self.replica {
+            r.connected.store(false, Ordering::Relaxed);
+            if let Ok(mut s) = r.last_error.lock() {
+                *s = err.to_string();
+            }
+        }
+    }
+
+    /// Применяет уже записанную в WAL мутацию и рассылает событие наблюдателям.
+    fn apply_record(&self, rec: WalRecord) {
+        match rec {
+            // копия значения нужна, только если кто-то наблюдает или ждёт
+            WalRecord::Set(k, v) if self.watchers.active() || self.waiters.active() => {
+                self.core.set(k.clone(), v.clone());
+                self.waiters.wake(&k, &v);
+                self.watchers.notify(&k, WatchOp::Set, Some(&v));
+            }
+            WalRecord::Set(k, v) => self.core.set(k, v),
+            WalRecord::Del(k) | WalRecord::Pop(k) => {
+                if self.core.delete(&k) > 0 {
+                    self.watchers.notify(&k, WatchOp::Del, None);
+                }
+            }
+            WalRecord::SetBit(k, offset, bit) => {
+                let (_, changed) = self
+                    .core
+                    .set_bit(k.clone(), offset, bit, |_, _| Ok::<_, CacheError>(()))
+                    .expect("set_bit without a WAL write cannot fail");
+                if changed {
+                    self.notify_changed(&k);
+                }
+            }
+            WalRecord::SetEx(k, v, at) if self.watchers.active() || self.waiters.active() => {
+                self.core.set_expiring(k.clone(), v.clone(), at);
+                self.waiters.wake(&k, &v);
+                self.watchers.notify(&k, WatchOp::Set, Some(&v));
+            }
+            WalRecord::SetEx(k, v, at) => self.core.set_expiring(k, v, at),
+            WalRecord::Expire(k, at) => {
+                self.core
+                    .set_expiry(k, at, |_| Ok::<_, CacheError>(()))
+                    .expect("set_expiry without a WAL write cannot fail");
+            }
+            WalRecord::SetTagged(k, v, tags, at)
+                if self.watchers.active() || self.waiters.active() =>
+            {
+                self.core.set_tagged(k.clone(), v.clone(), tags, at);
+                self.waiters.wake(&k, &v);
+                self.watchers.notify(&k, WatchOp::Set, Some(&v));
+            }
+            WalRecord::SetTagged(k, v, tags, at) => self.core.set_tagged(k, v, tags, at),
+            WalRecord::Tombstone(k, tomb) => {
+                if self.core.delete(&k) > 0 {
+                    self.watchers.notify(&k, WatchOp::Del, None);
+                }
+                self.core.put_tombstone(k, tomb);
+            }
+            WalRecord::Undelete(k) => {
+                if self.core.restore(k.clone()) {
+                    self.notify_changed(&k);
+                }
+            }
+            WalRecord::Swap(a, b) => {
+                let had = self
+                    .core
+                    .swap(&a, &b, |_, _| Ok::<_, CacheError>(()))
+                    .expect("swap without a WAL write cannot fail");
+                self.notify_swapped(&a, &b, had);
+            }
+        }
+    }
+
+    /// Записи основного сервера: seq должны продолжать позицию реплики, и в WAL
+    /// реплики они получают те же seq.
+    fn apply_replicated(&self, recs: Vec<(u64, WalRecord)>) -> Result<(), CacheError> {
+        let Some(&(last, _)) = recs.last() else {
+            return Ok(());
+        };
+        {
+            let _g = self.read_gate()?;
+            let mut expected = self.last_seq.load(Ordering::Acquire);
+            for (seq, _) in &recs {
+                expected += 1;
+                if *seq != expected {
+                    return Err(CacheError::Internal(format!(
+                        "replication stream has seq {}, expected {}",
+                        seq, expected
+                    )));
+                }
+            }
+            let recs: Vec<WalRecord> = recs.into_iter().map(|(_, rec)| rec).collect();
+            let seq = self.log_batch(&recs)?;
+            if seq != 0 && seq != last {
+                return Err(CacheError::Internal(format!(
+                    "replica WAL is at seq {} after applying primary seq {}",
+                    seq, last
+                )));
+            }
+            for rec in recs {
+                self.apply_record(rec);
+            }
+            self.last_seq.store(last, Ordering::Release);
+        }
+        self.maybe_compact()
+    }
+
+    /// Начало полной синхронизации: старые данные реплики больше не нужны.
+    /// До `finish_resync` читатели видят неполный набор ключей.
+    fn begin_resync(&self) -> Result<(), CacheError> {
+        let _g = self.write_gate()?;
+        self.core.clear();
+        Ok(())
+    }
+
+    fn load_resync(&self, items: Vec<(String, Vec<u8>)>) {
+        for (k, v) in items {
+            self.core.set(k, v);
+        }
+    }
+
+    // сроки ключей из уже пришедших SnapshotItems
+    fn load_resync_expiries(&self, expiries: Vec<(String, u64)>) {
+        for (k, at) in expiries {
+            let _ = self.core.set_expiry(k, at, |_| Ok::<_, CacheError>(()));
+        }
+    }
+
+    // теги ключей из уже пришедших SnapshotItems
+    fn load_resync_tags(&self, tags: Vec<(String, Vec<String>)>) {
+        for (k, tags) in tags {
+            self.core.retag(k, tags);
+        }
+    }
+
+    fn load_resync_tombstones(&self, tombs: Vec<(String, Tombstone)>) {
+        for (k, tomb) in tombs {
+            self.core.put_tombstone(k, tomb);
+        }
+    }
+
+    /// Конец полной синхронизации на seq основного сервера: снапшот на этот seq,
+    /// затем WAL заново с него. Если упадём между шагами, старый WAL перекрыт
+    /// снапшотом по seq.
+    fn finish_resync(&self, seq: u64) -> Result<(), CacheError> {
+        let _g = self.write_gate()?;
+        if let Persistence::Wal { wal, snapshot_path } = &self.persistence {
+            snapshot::write(snapshot_path, &self.core, seq, wal.key(), || {})?;
+            wal.reset(seq)?;
+        }
+        self.last_seq.store(seq, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn wal_stats(&self) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        let (wal, _) = self.wal()?;
+        let stats = wal.stats()?;
+        let records = stats.records();
+        let live_ratio = if records == 0 {
+            1.0
+        } else {
+            stats.live as f64 / records as f64
+        };
+        let fields: Vec<(&str, ResponseValue)> = vec![
+            ("segments", stats.segments.into()),
+            ("bytes", stats.bytes.into()),
+            ("records", records.into()),
+            ("sets", stats.sets.into()),
+            ("dels", stats.dels.into()),
+            ("pops", stats.pops.into()),
+            ("setbits", stats.setbits.into()),
+            ("expires", stats.expires.into()),
+            ("swaps", stats.swaps.into()),
+            ("first_seq", stats.first_seq.into()),
+            ("last_seq", stats.last_seq.into()),
+            ("live_records", stats.live.into()),
+            ("live_ratio", live_ratio.into()),
+        ];
+        Ok(fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect())
+    }
+
+    pub fn info(&self) -> Result<Vec<(String, ResponseValue)>, CacheError> {
+        let status = self
+            .save
+            .last_status
+            .lock()
+            .map(|s| {
+                if s.is_empty() {
+                    "none".to_string()
+                } else {
+                    s.clone()
+                }
+            })
+            .unwrap_or_default();
+        let import_status = self
+            .import
+            .last_status
+            .lock()
+            .map(|s| {
+                if s.is_empty() {
+                    "none".to_string()
+                } else {
+                    s.clone()
+                }
+            })
+            .unwrap_or_default();
+        let (tombstones, tombstone_bytes) = self.core.tombstone_stats();
+        let dedup = self.core.dedup_stats();
+        let history = self.core.history_stats();
+        let mut fields: Vec<(&str, ResponseValue)> = vec![
+            ("keys", self.core.len().into()),
+            ("tombstones", tombstones.into()),
+            ("tombstone_bytes", tombstone_bytes.into()),
+            ("dedup_values", dedup.values.into()),
+            ("dedup_refs", dedup.refs.into()),
+            ("dedup_logical_bytes", dedup.logical_bytes.into()),
+            ("dedup_physical_bytes", dedup.physical_bytes.into()),
+            ("history_keys", history.keys.into()),
+            ("history_versions", history.versions.into()),
+            ("history_bytes", history.bytes.into()),
+            ("history_dropped", history.dropped.into()),
+            (
+                "key_normalization",
+                self.key_normalization.map_or("off", |p| p.as_str()).into(),
+            ),
+            ("case_insensitive_keys", self.fold_case.into()),
+            ("verify_values", self.core.verify_values().into()),
+            ("checksum_failures", self.core.checksum_failures().into()),
+        ];
+        match &self.replica {
+            Some(r) => fields.extend([
+                ("role", "replica".into()),
+                ("repl_primary", r.primary.clone().into()),
+                ("repl_connected", r.connected.load(Ordering::Relaxed).into()),
+                (
+                    "repl_last_error",
+                    r.last_error
+                        .lock()
+                        .map(|s| {
+                            if s.is_empty() {
+                                "none".to_string()
+                            } else {
+                                s.clone()
+                            }
+                        })
+                        .unwrap_or_default()
+                        .into(),
+                ),
+            ]),
+            None => fields.push(("role", "primary".into())),
+        }
+        fields.push(("read_only", self.is_read_only().into()));
+        fields.push((
+            "repl_replicas",
+            self.replicas.load(Ordering::Relaxed).into(),
+        ));
+        fields.extend(self.clients.info_fields());
+        fields.extend(self.pubsub.info_fields());
+        fields.extend(self.watchers.info_fields());
+        fields.extend(self.waiters.info_fields());
+        fields.extend(self.slowlog.info_fields());
+        if let Some(chaos) = &self.chaos {
+            fields.extend(chaos.info_fields());
+        }
+        match &self.persistence {
+            Persistence::Wal { wal, snapshot_path } => {
+                let (batches, batched_records) = wal.batch_stats()?;
+                fields.extend([
+                    ("persistence", "wal".into()),
+                    ("wal_path", wal.path().display().to_string().into()),
+                    ("snapshot_path", snapshot_path.display().to_string().into()),
+                    (
+                        "lock_path",
+                        wal::lock_path(wal.path()).display().to_string().into(),
+                    ),
+                    ("last_seq", self.last_seq.load(Ordering::Acquire).into()),
+                    ("wal_last_seq", wal.last_seq()?.into()),
+                    ("wal_pending", wal.pending()?.into()),
+                    ("wal_segments", wal.segment_count()?.into()),
+                    ("wal_fsync", wal.fsync_policy().as_str().into()),
+                    ("wal_encrypted", wal.key().is_some().into()),
+                    ("wal_batches", batches.into()),
+                    ("wal_batched_records", batched_records.into()),
+                ]);
+            }
+            Persistence::ReadOnly {
+                wal_path,
+                snapshot_path,
+            } => fields.extend([
+                ("persistence", "read-only".into()),
+                ("wal_path", wal_path.display().to_string().into()),
+                ("snapshot_path", snapshot_path.display().to_string().into()),
+            ]),
+            Persistence::None => fields.push(("persistence", "none".into())),
+        }
+        fields.extend([
+            (
+                "save_in_progress",
+                self.save.in_progress.load(Ordering::SeqCst).into(),
+            ),
+            (
+                "save_keys_total",
+                self.save.keys_total.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "save_keys_saved",
+                self.save.keys_saved.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "last_save_seq",
+                self.save.last_seq.load(Ordering::Relaxed).into(),
+            ),
+            ("last_save_status", status.into()),
+            (
+                "import_in_progress",
+                self.import.in_progress.load(Ordering::SeqCst).into(),
+            ),
+            (
+                "import_keys_total",
+                self.import.keys_total.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "import_keys_loaded",
+                self.import.keys_loaded.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "import_keys_skipped",
+                self.import.keys_skipped.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "import_bytes_total",
+                self.import.bytes_total.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "import_bytes_loaded",
+                self.import.bytes_loaded.load(Ordering::Relaxed).into(),
+            ),
+            ("last_import_status", import_status.into()),
+        ]);
+        Ok(fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect())
+    }
+}
+
+/// =======================
+/// Клиентский транспорт (TCP/UDS)
+/// =======================
+fn write_all(w: &mut impl Write, buf: &[u8]) -> Result<(), CacheError> {
+    w.write_all(buf)
+        .and_then(|_| w.flush())
+        .map_err(|e| CacheError::Network(e.to_string()))
+}
+
+fn read_exact(r: &mut impl Read, buf: &mut [u8]) -> Result<(), CacheError> {
+    r.read_exact(buf)
+        .map_err(|e| CacheError::Network(e.to_string()))
+}
+
+/// Клиентское соединение с сервером.
+enum Conn {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Conn {
+    fn connect(addr: &TransportAddr) -> Result<Self, CacheError> {
+        match addr {
+            TransportAddr::Tcp(a) => {
+                let s = TcpStream::connect(a).map_err(|e| CacheError::Network(e.to_string()))?;
+                Ok(Conn::Tcp(s))
+            }
+            #[cfg(unix)]
+            TransportAddr::Unix(path) => {
+                let s =
+                    UnixStream::connect(path).map_err(|e| CacheError::Network(e.to_string()))?;
+                Ok(Conn::Unix(s))
+            }
+            #[cfg(not(unix))]
+            TransportAddr::Unix(path) => Err(CacheError::Unsupported(format!(
+                "unix://{} needs Unix domain sockets; on this platform use TCP loopback, \
+                 e.g. \"127.0.0.1:5002\"",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Как `connect`, но TCP-подключение ждёт не дольше `timeout`
+    /// (для UDS подключение и так мгновенное).
+    fn connect_timeout(
+        addr: &TransportAddr,
+        timeout: Option<Duration>,
+    ) -> Result<Self, CacheError> {
+        let (TransportAddr::Tcp(a), Some(timeout)) = (addr, timeout) else {
+            return Self::connect(addr);
+        };
+        let mut last_err = None;
+        for sa in a
+            .to_socket_addrs()
+            .map_err(|e| CacheError::Network(e.to_string()))?
+        {
+            match TcpStream::connect_timeout(&sa, timeout) {
+                Ok(s) => return Ok(Conn::Tcp(s)),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(CacheError::Network(match last_err {
+            Some(e) => e.to_string(),
+            None => format!("no addresses for {}", a),
+        }))
+    }
+
+    fn set_nodelay(&self) -> Result<(), CacheError> {
+        match self {
+            Conn::Tcp(s) => s
+                .set_nodelay(true)
+                .map_err(|e| CacheError::Network(e.to_string())),
+            #[cfg(unix)]
+            Conn::Unix(_) => Ok(()),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), CacheError> {
+        match self {
+            Conn::Tcp(s) => s.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.set_write_timeout(timeout),
+        }
+        .map_err(|e| CacheError::Network(e.to_string()))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), CacheError> {
+        match self {
+            Conn::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.set_read_timeout(timeout),
+        }
+        .map_err(|e| CacheError::Network(e.to_string()))
+    }
+
+    fn try_clone(&self) -> Result<Self, CacheError> {
+        match self {
+            Conn::Tcp(s) => s.try_clone().map(Conn::Tcp),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.try_clone().map(Conn::Unix),
+        }
+        .map_err(|e| CacheError::Network(e.to_string()))
+    }
+
+    /// Закрывает соединение для всех его копий; ошибки не интересны.
+    fn shutdown(&self) {
+        let _ = match self {
+            Conn::Tcp(s) => s.shutdown(std::net::Shutdown::Both),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.shutdown(std::net::Shutdown::Both),
+        };
+    }
+
+    /// Закрыл ли сервер простаивающее соединение (например, по
+    /// `idle_timeout_secs`): неблокирующее чтение видит EOF. Байты, пришедшие
+    /// без запроса, тоже делают соединение негодным.
+    fn peer_closed(&mut self) -> bool {
+        let set = |c: &Conn, on: bool| match c {
+            Conn::Tcp(s) => s.set_nonblocking(on),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.set_nonblocking(on),
+        };
+        if set(self, true).is_err() {
+            return true;
+        }
+        let alive = matches!(
+            self.read(&mut [0u8; 1]),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock
+        );
+        !alive || set(self, false).is_err()
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Conn::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Conn::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        match self {
+            Conn::Tcp(s) => s.write_vectored(bufs),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Conn::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Длина и тело кадра в одном буфере: на переиспользуемом соединении два
+/// мелких write подряд упираются в Nagle и delayed ACK.
+fn encode_frame<T: Serialize>(msg: &T) -> Result<Vec<u8>, CacheError> {
+    let ser = |e: bincode::Error| CacheError::Serialization(e.to_string());
+    let size = bincode::serialized_size(msg).map_err(ser)? as usize;
+    let mut frame = Vec::with_capacity(4 + size);
+    frame.extend_from_slice(&(size as u32).to_le_bytes());
+    bincode::serialize_into(&mut frame, msg).map_err(ser)?;
+    Ok(frame)
+}
 
-pub struct PersistentCore {
-    core: CacheCore,
-    wal: Wal,
+fn write_frame<T: Serialize>(w: &mut impl Write, msg: &T) -> Result<(), CacheError> {
+    write_all(w, &encode_frame(msg)?)
 }
 
-impl PersistentCore {
-    pub fn new(wal_path: PathBuf) -> Result<Self, CacheError> {
-        let core = CacheCore::new();
-        let wal = Wal::open(wal_path)?;
-        // при старте доигрываем WAL
-        wal.replay(&core)?;
-        Ok(Self { core, wal })
+/// Кадр ответа с телом не больше `limit`; больший ответ заменяется ошибкой
+/// `ResponseTooLarge`: клиент сужает запрос, а соединение остаётся в строю.
+fn encode_response(resp: &CacheResponse, limit: usize) -> Result<Vec<u8>, CacheError> {
+    let size =
+        bincode::serialized_size(resp).map_err(|e| CacheError::Serialization(e.to_string()))?;
+    if size > limit as u64 {
+        let e = CacheError::ResponseTooLarge(size, limit as u64);
+        return encode_frame(&CacheResponse::Error(e.to_string()));
     }
+    encode_frame(resp)
+}
 
-    pub fn set(&self, key: String, value: Vec<u8>) -> Result<(), CacheError> {
-        self.wal
-            .append(&WalRecord::Set(key.clone(), value.clone()))?;
-        self.core.set(key, value);
-        Ok(())
+// длина тела кадра ответа; больше MAX_RESPONSE_SIZE — ошибка без выделения буфера
+fn response_size(head: [u8; 4]) -> Result<usize, CacheError> {
+    let size = u32::from_le_bytes(head) as usize;
+    if size > MAX_RESPONSE_SIZE {
+        return Err(CacheError::Serialization(format!(
+            "response frame of {} bytes is over the {} byte limit",
+            size, MAX_RESPONSE_SIZE
+        )));
     }
+    Ok(size)
+}
 
-    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
-        self.core.get(key)
+/// Кадр команды `encode_frame`/`set_frame` в формате протокола 2: длина тела,
+/// u64 id запроса, u32 бюджет в миллисекундах (0 — без срока), тело. Бюджет
+/// относительный: сервер отсчитывает его от получения кадра, так что
+/// расхождение часов клиента и сервера не важно.
+fn write_tagged_command(
+    w: &mut impl Write,
+    id: u64,
+    budget: Option<Duration>,
+    trace: Option<&str>,
+    frame: &[u8],
+) -> Result<(), CacheError> {
+    // меньше миллисекунды — всё равно срок, а не его отсутствие
+    let mut budget_ms = budget.map_or(0, |b| {
+        b.as_millis().clamp(1, (TRACE_FLAG - 1) as u128) as u32
+    });
+    // with_trace_id уже обрезал id до MAX_TRACE_ID байт
+    let trace = trace.map(|t| t.as_bytes()).unwrap_or_default();
+    if !trace.is_empty() {
+        budget_ms |= TRACE_FLAG;
     }
+    let (id, budget_ms) = (id.to_le_bytes(), budget_ms.to_le_bytes());
+    let trace_len = [trace.len() as u8];
+    let trace_head: &[u8] = if trace.is_empty() { &[] } else { &trace_len };
+    write_slices(
+        w,
+        &mut [
+            IoSlice::new(&frame[..4]),
+            IoSlice::new(&id),
+            IoSlice::new(&budget_ms),
+            IoSlice::new(trace_head),
+            IoSlice::new(trace),
+            IoSlice::new(&frame[4..]),
+        ],
+    )
+}
 
-    pub fn pop(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
-        self.wal.append(&WalRecord::Pop(key.to_string()))?;
-        Ok(self.core.pop(key))
-    }
+/// Кадр ответа протокола 2: длина тела, u64 id запроса, тело.
+fn write_tagged_response(w: &mut impl Write, id: u64, frame: &[u8]) -> Result<(), CacheError> {
+    let id = id.to_le_bytes();
+    write_slices(
+        w,
+        &mut [
+            IoSlice::new(&frame[..4]),
+            IoSlice::new(&id),
+            IoSlice::new(&frame[4..]),
+        ],
+    )
+}
 
-    pub fn delete(&self, key: &str) -> Result<i64, CacheError> {
-        self.wal.append(&WalRecord::Del(key.to_string()))?;
-        Ok(self.core.delete(key))
+// одним writev, без копирования тела
+fn write_slices(w: &mut impl Write, bufs: &mut [IoSlice<'_>]) -> Result<(), CacheError> {
+    let mut bufs = bufs;
+    while !bufs.is_empty() {
+        match w.write_vectored(bufs) {
+            Ok(0) => {
+                return Err(CacheError::Network(
+                    "connection closed while writing".into(),
+                ))
+            }
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(CacheError::Network(e.to_string())),
+        }
     }
+    w.flush().map_err(|e| CacheError::Network(e.to_string()))
+}
 
-    pub fn keys_prefix(&self, prefix: &str) -> Vec<String> {
-        self.core.keys_prefix(prefix)
-    }
+/// Кадр `Set(key, value)` с местом под значение: вызывающий копирует его
+/// прямо в `frame[offset..]`, минуя промежуточный `Vec` и повторное
+/// кодирование. Возвращает кадр и `offset`.
+pub(crate) fn set_frame(key: &str, value_len: usize) -> Result<(Vec<u8>, usize), CacheError> {
+    value_frame(
+        &CacheCommand::Set(key.to_string(), Vec::new()),
+        key,
+        value_len,
+    )
+}
 
-    pub fn len(&self) -> i64 {
-        self.core.len()
+/// То же для `SetWithTags(key, tags, value)`.
+pub(crate) fn set_tagged_frame(
+    key: &str,
+    tags: &[String],
+    value_len: usize,
+) -> Result<(Vec<u8>, usize), CacheError> {
+    value_frame(
+        &CacheCommand::SetWithTags(key.to_string(), tags.to_vec(), Vec::new()),
+        key,
+        value_len,
+    )
+}
+
+/// То же для `PSetEx(key, ttl_ms, value)`.
+pub(crate) fn set_ex_frame(
+    key: &str,
+    ttl_ms: u64,
+    value_len: usize,
+) -> Result<(Vec<u8>, usize), CacheError> {
+    value_frame(
+        &CacheCommand::PSetEx(key.to_string(), ttl_ms, Vec::new()),
+        key,
+        value_len,
+    )
+}
+
+// `cmd` кончается пустым значением
+fn value_frame(
+    cmd: &CacheCommand,
+    key: &str,
+    value_len: usize,
+) -> Result<(Vec<u8>, usize), CacheError> {
+    // bincode кодирует Vec<u8> как u64 длины и сами байты, так что кадр с
+    // пустым значением отличается от нужного только этой длиной в конце
+    let mut frame = encode_frame(cmd)?;
+    let size = frame.len() - 4 + value_len;
+    if size > MAX_COMMAND_SIZE {
+        return Err(CacheError::Unsupported(format!(
+            "value of key {:?} is {} bytes, over the {} byte command limit",
+            key, value_len, MAX_COMMAND_SIZE
+        )));
     }
+    let offset = frame.len();
+    frame[offset - 8..].copy_from_slice(&(value_len as u64).to_le_bytes());
+    frame[..4].copy_from_slice(&(size as u32).to_le_bytes());
+    frame.resize(offset + value_len, 0);
+    Ok((frame, offset))
 }
 
-/// =======================
-/// Маппинг ошибок в Python
-/// =======================
+fn read_response(r: &mut impl Read) -> Result<CacheResponse, CacheError> {
+    let mut size_buf = [0u8; 4];
+    read_exact(r, &mut size_buf)?;
+    let resp_size = response_size(size_buf)?;
 
-fn map_error(e: CacheError, ctx: &str) -> PyErr {
-    PyRuntimeError::new_err(format!("{}: {}", ctx, e))
+    let mut buf = vec![0u8; resp_size];
+    read_exact(r, &mut buf)?;
+    bincode::deserialize(&buf).map_err(|e| CacheError::Serialization(e.to_string()))
 }
 
-/// =======================
-/// Клиентский транспорт (TCP/UDS)
-/// =======================
+/// Ответ протокола 2 и id запроса, на который он отвечает.
+fn read_tagged_response(r: &mut impl Read) -> Result<(u64, CacheResponse), CacheError> {
+    let mut head = [0u8; 12];
+    read_exact(r, &mut head)?;
+    let resp_size = response_size(head[..4].try_into().expect("4 bytes"))?;
+    let id = u64::from_le_bytes(head[4..].try_into().expect("8 bytes"));
 
-fn write_all(w: &mut impl Write, buf: &[u8]) -> Result<(), CacheError> {
-    w.write_all(buf)
-        .and_then(|_| w.flush())
-        .map_err(|e| CacheError::Network(e.to_string()))
+    let mut buf = vec![0u8; resp_size];
+    read_exact(r, &mut buf)?;
+    let resp = bincode::deserialize(&buf).map_err(|e| CacheError::Serialization(e.to_string()))?;
+    Ok((id, resp))
 }
 
-fn read_exact(r: &mut impl Read, buf: &mut [u8]) -> Result<(), CacheError> {
-    r.read_exact(buf)
-        .map_err(|e| CacheError::Network(e.to_string()))
+/// Одна команда по уже открытому соединению; ошибка сервера — `CacheError::Server`.
+#[cfg(feature = "python")]
+fn request(conn: &mut Conn, cmd: &CacheCommand) -> Result<CacheResponse, CacheError> {
+    request_frame(conn, &encode_frame(cmd)?)
 }
 
-fn send_cmd_sync(addr: &TransportAddr, cmd: CacheCommand) -> Result<CacheResponse, CacheError> {
-    enum Conn {
-        Tcp(TcpStream),
-        #[cfg(unix)]
-        Unix(UnixStream),
+/// Команда, уже закодированная `encode_frame` или `set_frame`; ошибка
+/// сервера — `CacheError::from_server`. Сжатые значения ответа приходят
+/// распакованными, см. encoding.rs.
+fn request_frame(conn: &mut Conn, frame: &[u8]) -> Result<CacheResponse, CacheError> {
+    write_all(conn, frame)?;
+    match read_response(conn)? {
+        CacheResponse::Error(msg) => Err(CacheError::from_server(msg)),
+        resp => decode_response(resp),
     }
+}
 
-    let mut conn = match addr {
-        TransportAddr::Tcp(a) => {
-            let s = TcpStream::connect(a).map_err(|e| CacheError::Network(e.to_string()))?;
-            Conn::Tcp(s)
-        }
-        #[cfg(unix)]
-        TransportAddr::Unix(path) => {
-            let s = UnixStream::connect(path)
-                .map_err(|e| CacheError::Network(e.to_string()))?;
-            Conn::Unix(s)
-        }
-    };
-
-    let encoded_cmd =
-        bincode::serialize(&cmd).map_err(|e| CacheError::Serialization(e.to_string()))?;
-    let size = (encoded_cmd.len() as u32).to_le_bytes();
-
-    match &mut conn {
-        Conn::Tcp(s) => {
-            write_all(s, &size)?;
-            write_all(s, &encoded_cmd)?;
-        }
-        #[cfg(unix)]
-        Conn::Unix(s) => {
-            write_all(s, &size)?;
-            write_all(s, &encoded_cmd)?;
+/// То же по соединению протокола 2, по одному запросу за раз: ответ должен
+/// прийти с тем же `id`, с протокола 3 — возможно, кусками (они собираются
+/// в один ответ). Через `budget` сервер бросает команду.
+fn request_tagged(
+    conn: &mut Conn,
+    id: u64,
+    budget: Option<Duration>,
+    trace: Option<&str>,
+    frame: &[u8],
+) -> Result<CacheResponse, CacheError> {
+    write_tagged_command(conn, id, budget, trace, frame)?;
+    let mut acc = None;
+    loop {
+        match read_tagged_response(conn)? {
+            (got, _) if got != id => {
+                return Err(CacheError::Serialization(format!(
+                    "response for request {} arrived while waiting for {}",
+                    got, id
+                )))
+            }
+            (_, CacheResponse::Error(msg)) => return Err(CacheError::from_server(msg)),
+            (_, frame) => {
+                if let Some(resp) = join_chunk(&mut acc, frame)? {
+                    return decode_response(resp);
+                }
+            }
         }
     }
+}
 
-    let mut size_buf = [0u8; 4];
-    match &mut conn {
-        Conn::Tcp(s) => read_exact(s, &mut size_buf)?,
-        #[cfg(unix)]
-        Conn::Unix(s) => read_exact(s, &mut size_buf)?,
-    }
-    let resp_size = u32::from_le_bytes(size_buf) as usize;
-
-    let mut buf = vec![0u8; resp_size];
-    match &mut conn {
-        Conn::Tcp(s) => read_exact(s, &mut buf)?,
-        #[cfg(unix)]
-        Conn::Unix(s) => read_exact(s, &mut buf)?,
-    }
-
-    bincode::deserialize(&buf).map_err(|e| CacheError::Serialization(e.to_string()))
+#[cfg(feature = "python")]
+fn send_cmd_sync(addr: &TransportAddr, cmd: CacheCommand) -> Result<CacheResponse, CacheError> {
+    let mut conn = Conn::connect(addr)?;
+    request(&mut conn, &cmd)
 }
 
 /// =======================
 /// Общая обработка соединения
 /// =======================
+#[cfg(feature = "python")]
+fn execute(cmd: CacheCommand, core: &Arc<PersistentCore>) -> Result<CacheResponse, CacheError> {
+    execute_within(cmd, core, Deadline::NONE)
+}
 
-fn handle_connection_impl<S: Read + Write>(
-    stream: &mut S,
-    core: Arc<PersistentCore>,
-) -> Result<(), CacheError> {
-    let mut size_buf = [0u8; 4];
-    read_exact(stream, &mut size_buf)?;
-    let cmd_size = u32::from_le_bytes(size_buf) as usize;
-    if cmd_size > 1_000_000 {
-        return Err(CacheError::Internal("command too large".into()));
+/// `execute_within` команды соединения `session`: ошибка — ответ `Error`, а
+/// медленная команда попадает в `slowlog` с trace id клиента.
+fn execute_traced(
+    cmd: CacheCommand,
+    core: &Arc<PersistentCore>,
+    deadline: Deadline,
+    session: &Session,
+    trace: Option<&str>,
+) -> CacheResponse {
+    let (started, name) = (Instant::now(), cmd.name());
+    let resp = execute_as(cmd, core, deadline, session)
+        .unwrap_or_else(|e| CacheResponse::Error(e.to_string()));
+    core.slowlog.record(started, name, session, trace);
+    resp
+}
+
+/// `execute_within` от имени пользователя ACL соединения `session`: `Auth`
+/// входит, остальное проходит `authorize`.
+fn execute_as(
+    cmd: CacheCommand,
+    core: &Arc<PersistentCore>,
+    deadline: Deadline,
+    session: &Session,
+) -> Result<CacheResponse, CacheError> {
+    if let CacheCommand::Auth(user, password) = &cmd {
+        core.acl.authenticate(user, password)?;
+        session.set_user(user.clone());
+        return Ok(CacheResponse::Ok);
     }
+    match authorize(core, session, cmd)? {
+        (cmd, Access::Admin) => execute_admin(cmd, core, deadline),
+        (cmd, Access::Granted) => execute_within(cmd, core, deadline),
+    }
+}
 
-    let mut buf = vec![0u8; cmd_size];
-    read_exact(stream, &mut buf)?;
-    let cmd: CacheCommand =
-        bincode::deserialize(&buf).map_err(|e| CacheError::Serialization(e.to_string()))?;
+/// Проверка ACL команды соединения `session`, см. acl.rs; без пользователей
+/// ACL пропускает всё как есть.
+fn authorize(
+    core: &PersistentCore,
+    session: &Session,
+    cmd: CacheCommand,
+) -> Result<(CacheCommand, Access), CacheError> {
+    if !core.acl.is_enabled() {
+        return Ok((cmd, Access::Granted));
+    }
+    // правила сверяются с ключами в форме политики
+    let mut cmd = core.normalize_keys(cmd)?;
+    let access = core.acl.check(session.user().as_deref(), &mut cmd)?;
+    Ok((cmd, access))
+}
 
+/// `execute` с крайним сроком: он проверяется перед началом и в точках
+/// отмены обходов `Keys`, `KeysSorted`, `Scan`, `ScanItems`, `ScanSizes`, `ScanTtl`, `PrefixStats`, `Aggregate`,
+/// `DelPrefix` и `Export` (см. `mux`).
+fn execute_within(
+    cmd: CacheCommand,
+    core: &Arc<PersistentCore>,
+    deadline: Deadline,
+) -> Result<CacheResponse, CacheError> {
+    deadline.check()?;
+    let cmd = core.normalize_keys(cmd)?;
+    if cmd.is_write() {
+        if core.is_replica() {
+            return Err(CacheError::ReadOnly("replica".into()));
+        }
+        if core.is_read_only() {
+            return Err(CacheError::ReadOnly("server".into()));
+        }
+    }
+    // снапшот усекает WAL, а замороженный сервер не должен менять ничего
+    if matches!(cmd, CacheCommand::Save | CacheCommand::BgSave) && core.is_read_only() {
+        return Err(CacheError::ReadOnly("server".into()));
+    }
     let resp = match cmd {
         CacheCommand::Set(key, value) => {
             core.set(key, value)?;
             CacheResponse::Ok
         }
         CacheCommand::Get(key) => core
-            .get(&key)
+            .get_or_load(&key)?
+            .map(CacheResponse::Value)
+            .unwrap_or(CacheResponse::Nil),
+        CacheCommand::Exists(key) => CacheResponse::Int(core.exists(&key) as i64),
+        CacheCommand::MSet(items) => {
+            core.set_many(items)?;
+            CacheResponse::Ok
+        }
+        CacheCommand::GetOrSet(key, value) => {
+            let (value, inserted) = core.get_or_set(key, value)?;
+            CacheResponse::Entry(value, inserted)
+        }
+        CacheCommand::BGet(key, timeout_ms) => core
+            .get_blocking(&key, Duration::from_millis(timeout_ms))?
             .map(CacheResponse::Value)
             .unwrap_or(CacheResponse::Nil),
         CacheCommand::Pop(key) => core
@@ -238,84 +3539,496 @@ fn handle_connection_impl<S: Read + Write>(
             .map(CacheResponse::Value)
             .unwrap_or(CacheResponse::Nil),
         CacheCommand::Del(key) => CacheResponse::Int(core.delete(&key)?),
+        CacheCommand::DelPrefix(prefix) => {
+            CacheResponse::Int(core.delete_prefix(&prefix, deadline)?)
+        }
+        CacheCommand::SetWithTags(key, tags, value) => {
+            core.set_tagged(key, value, tags)?;
+            CacheResponse::Ok
+        }
+        CacheCommand::DelByTag(tag) => CacheResponse::Int(core.delete_by_tag(&tag)?),
+        CacheCommand::Undelete(key) => CacheResponse::Int(core.undelete(&key)? as i64),
+        CacheCommand::Swap(a, b) => CacheResponse::Int(core.swap(&a, &b)? as i64),
+        CacheCommand::DelIfEquals(key, expected) => {
+            CacheResponse::Int(core.delete_if_equals(&key, &expected)? as i64)
+        }
+        CacheCommand::SetNx(key, ttl_ms, value) => {
+            CacheResponse::Int(core.set_nx(key, value, ttl_ms)? as i64)
+        }
+        CacheCommand::PExtendIfEquals(key, expected, add_ms) => {
+            CacheResponse::Int(core.extend_if_equals(&key, &expected, add_ms)? as i64)
+        }
+        CacheCommand::IncrRange(key, n) => CacheResponse::Int(core.incr_range(key, n)?),
+        CacheCommand::Time => CacheResponse::Int(core::now_unix_ms() as i64),
+        CacheCommand::Sample(n) => CacheResponse::Sample(core.sample(n as usize)),
+        CacheCommand::GetWithMeta(key) => core
+            .get_with_meta(&key)
+            .map(CacheResponse::Meta)
+            .unwrap_or(CacheResponse::Nil),
+        CacheCommand::SetHistoryDepth(prefix, depth) => {
+            core.set_history_depth(prefix, depth)?;
+            CacheResponse::Ok
+        }
+        CacheCommand::History(key) => CacheResponse::History(core.history(&key)),
+        CacheCommand::ExportBloom(prefix, bits_per_key) => {
+            CacheResponse::Bloom(core.export_bloom(&prefix, bits_per_key, deadline)?)
+        }
+        CacheCommand::PrefixStats(sep, depth) => {
+            CacheResponse::Map(core.prefix_stats(&sep, depth, deadline)?)
+        }
+        CacheCommand::SetBit(key, offset, bit) => {
+            CacheResponse::Int(core.set_bit(key, offset, bit)? as i64)
+        }
+        CacheCommand::GetBit(key, offset) => CacheResponse::Int(core.get_bit(&key, offset) as i64),
+        CacheCommand::BitCount(key, range) => CacheResponse::Int(core.bit_count(&key, range)),
+        CacheCommand::Touch(keys) => CacheResponse::Int(core.touch(&keys)),
+        CacheCommand::PSetEx(key, ttl_ms, value) => {
+            core.set_ex(key, value, ttl_ms)?;
+            CacheResponse::Ok
+        }
+        CacheCommand::PExpire(key, ttl_ms) => CacheResponse::Int(core.expire(&key, ttl_ms)? as i64),
+        CacheCommand::ExpireAt(key, at) => CacheResponse::Int(core.expire_at(&key, at)? as i64),
+        CacheCommand::PTtl(key) => CacheResponse::Int(core.pttl(&key)),
+        CacheCommand::Hello(version) => CacheResponse::Int(version.min(PROTOCOL_VERSION) as i64),
+        CacheCommand::Type(key) => CacheResponse::Map(core.key_type(&key)),
+        CacheCommand::IdleTime(key) => core
+            .idle_time(&key)
+            .map(|secs| CacheResponse::Int(secs as i64))
+            .unwrap_or(CacheResponse::Nil),
+        CacheCommand::Update(key, op) => core
+            .update(key, &op)?
+            .map(CacheResponse::Value)
+            .unwrap_or(CacheResponse::Nil),
         CacheCommand::Keys(pattern) => {
             if pattern.ends_with('*') {
                 let prefix = &pattern[..pattern.len() - 1];
-                CacheResponse::Keys(core.keys_prefix(prefix))
+                CacheResponse::Keys(core.keys_prefix(prefix, deadline)?)
             } else {
                 CacheResponse::Keys(Vec::new())
             }
         }
+        CacheCommand::KeysSorted(prefix, offset, limit) => CacheResponse::Keys(core.keys_sorted(
+            &prefix,
+            offset.try_into().unwrap_or(usize::MAX),
+            limit.try_into().unwrap_or(usize::MAX),
+            deadline,
+        )?),
         CacheCommand::Len => CacheResponse::Int(core.len()),
+        CacheCommand::Ping => CacheResponse::Ok,
+        CacheCommand::Save => {
+            core.compact()?;
+            CacheResponse::Ok
+        }
+        CacheCommand::BgSave => {
+            core.bgsave()?;
+            CacheResponse::Ok
+        }
+        CacheCommand::Info => CacheResponse::Map(core.info()?),
+        CacheCommand::WalStats => CacheResponse::Map(core.wal_stats()?),
+        CacheCommand::Export(format, path) => {
+            CacheResponse::Int(core.export(&path, format.parse()?, deadline)? as i64)
+        }
+        CacheCommand::Import(path, replace) => {
+            CacheResponse::Int(core.import(&path, replace)? as i64)
+        }
+        CacheCommand::Admin(token, cmd) => {
+            core.check_admin(&token)?;
+            return execute_admin(*cmd, core, deadline);
+        }
+        CacheCommand::SetReadOnly(_) => {
+            return Err(CacheError::PermissionDenied(
+                "SetReadOnly requires an admin token".into(),
+            ))
+        }
+        CacheCommand::Verify(..) => {
+            return Err(CacheError::PermissionDenied(
+                "Verify requires an admin token".into(),
+            ))
+        }
+        CacheCommand::ClientList | CacheCommand::ClientKill(_) => {
+            return Err(CacheError::PermissionDenied(
+                "ClientList and ClientKill require an admin token".into(),
+            ))
+        }
+        CacheCommand::ConfigSet(..) | CacheCommand::ConfigGet(_) => {
+            return Err(CacheError::PermissionDenied(
+                "ConfigSet and ConfigGet require an admin token".into(),
+            ))
+        }
+        CacheCommand::ConfigDump => {
+            return Err(CacheError::PermissionDenied(
+                "ConfigDump requires an admin token".into(),
+            ))
+        }
+        CacheCommand::AclList | CacheCommand::AclSetUser(_) | CacheCommand::AclDelUser(_) => {
+            return Err(CacheError::PermissionDenied(
+                "AclList, AclSetUser and AclDelUser require an admin token or an admin user".into(),
+            ))
+        }
+        // вход — дело соединения, см. execute_as
+        CacheCommand::Auth(..) => {
+            return Err(CacheError::Unsupported(
+                "Auth needs a server connection".into(),
+            ))
+        }
+        // поток репликации обслуживает handle_connection_impl
+        CacheCommand::ReplSync(_) => {
+            return Err(CacheError::Unsupported(
+                "ReplSync needs a dedicated connection".into(),
+            ))
+        }
+        CacheCommand::Subscribe(_) => {
+            return Err(CacheError::Unsupported(
+                "Subscribe needs a dedicated connection".into(),
+            ))
+        }
+        CacheCommand::Watch(..) => {
+            return Err(CacheError::Unsupported(
+                "Watch needs a dedicated connection".into(),
+            ))
+        }
+        CacheCommand::Publish(channel, payload) => {
+            CacheResponse::Int(core.pubsub.publish(channel, payload)?)
+        }
+        CacheCommand::ScanItems(cursor, prefix, count) => {
+            let (next, items) =
+                core.scan_items(cursor, &prefix, count.max(1) as usize, deadline)?;
+            CacheResponse::Items(next, items)
+        }
+        CacheCommand::Scan(cursor, prefix, count) => {
+            let (next, keys) = core.scan_keys(cursor, &prefix, count.max(1) as usize, deadline)?;
+            CacheResponse::ScanKeys(next, keys)
+        }
+        CacheCommand::ScanSizes(cursor, prefix, count, min, max) => {
+            let count = count.max(1) as usize;
+            let (next, keys) = core.scan_sizes(cursor, &prefix, count, min..=max, deadline)?;
+            CacheResponse::Sizes(next, keys)
+        }
+        CacheCommand::ScanTtl(cursor, prefix, count) => {
+            let (next, keys) = core.scan_ttls(cursor, &prefix, count.max(1) as usize, deadline)?;
+            CacheResponse::Ttls(next, keys)
+        }
+        CacheCommand::MGetConsistent(keys) => CacheResponse::Values(core.get_consistent(&keys)?),
+        CacheCommand::Stats => CacheResponse::Map(core.hit_stats()),
+        CacheCommand::Aggregate(prefix, op, skip_invalid) => {
+            match core.aggregate(&prefix, op, skip_invalid, deadline)? {
+                Some(n) => CacheResponse::Int(n),
+                None => CacheResponse::Nil,
+            }
+        }
     };
+    core.start_expiry();
+    Ok(resp)
+}
+
+/// Команда, пришедшая с верным админ-токеном или от пользователя ACL с
+/// `admin`.
+fn execute_admin(
+    cmd: CacheCommand,
+    core: &Arc<PersistentCore>,
+    deadline: Deadline,
+) -> Result<CacheResponse, CacheError> {
+    match cmd {
+        CacheCommand::SetReadOnly(read_only) => {
+            core.set_read_only(read_only)?;
+            Ok(CacheResponse::Ok)
+        }
+        CacheCommand::Verify(values, max_examples) => Ok(CacheResponse::Map(
+            core.verify(values, max_examples as usize)?.into_map(),
+        )),
+        CacheCommand::ClientList => Ok(CacheResponse::Map(ClientInfo::list_into_map(
+            core.clients.list(),
+        ))),
+        CacheCommand::ClientKill(id) => Ok(CacheResponse::Int(core.clients.kill(id) as i64)),
+        CacheCommand::ConfigSet(name, value) => {
+            core.config_set(&name, &value)?;
+            Ok(CacheResponse::Ok)
+        }
+        CacheCommand::ConfigGet(name) => Ok(CacheResponse::Map(core.config_get(&name)?)),
+        CacheCommand::ConfigDump => Ok(CacheResponse::Map(core.config_dump()?)),
+        CacheCommand::AclList => Ok(CacheResponse::AclUsers(core.acl.list()?)),
+        CacheCommand::AclSetUser(user) => {
+            let name = user.name.clone();
+            core.acl.update(&name, Some(user))?;
+            Ok(CacheResponse::Ok)
+        }
+        CacheCommand::AclDelUser(name) => {
+            Ok(CacheResponse::Int(core.acl.update(&name, None)? as i64))
+        }
+        other => execute_within(other, core, deadline),
+    }
+}
+
+/// Предел размера кадра команды; клиенты режут большие пакеты под него.
+pub(crate) const MAX_COMMAND_SIZE: usize = 1_000_000;
+
+// заголовок значений set_obj(): по нему get() и get_obj() отличают pickle от
+// сырых байтов, а Type сообщает encoding "pickle"; NUL в начале не даёт
+// спутать его с текстом
+pub(crate) const OBJ_MAGIC: &[u8] = b"\x00TMCPKL\x00";
+
+/// Команда из кадра или `CacheError::Protocol`, если кадр больше
+/// `MAX_COMMAND_SIZE` или не разобрался. Граница следующего кадра в обоих
+/// случаях на месте: на такой кадр отвечают ошибкой и читают дальше.
+type FrameCommand = Result<CacheCommand, CacheError>;
+
+/// Читает следующую команду; `None` — клиент закрыл соединение между
+/// командами. Ошибка — соединение больше читать нельзя.
+fn read_command(stream: &mut impl Read) -> Result<Option<FrameCommand>, CacheError> {
+    Ok(read_command_frame(stream, false, false)?.map(|(_, _, _, cmd)| cmd))
+}
+
+/// Кадр команды протокола 2: id запроса, крайний срок, trace id (с
+/// протокола 4) и команда.
+type TaggedCommand = (u64, Deadline, Option<String>, FrameCommand);
 
-    let encoded =
-        bincode::serialize(&resp).map_err(|e| CacheError::Serialization(e.to_string()))?;
-    let size = (encoded.len() as u32).to_le_bytes();
+/// То же для протокола 2 и выше, договорённого как `proto`.
+fn read_tagged_command(
+    stream: &mut impl Read,
+    proto: u32,
+) -> Result<Option<TaggedCommand>, CacheError> {
+    read_command_frame(stream, true, proto >= 4)
+}
 
-    write_all(stream, &size)?;
-    write_all(stream, &encoded)?;
+// пропускает `size` байт тела кадра кусками, без буфера под весь кадр
+fn skip_frame(stream: &mut impl Read, size: usize) -> Result<(), CacheError> {
+    let skipped = std::io::copy(&mut stream.take(size as u64), &mut std::io::sink())
+        .map_err(|e| CacheError::Network(e.to_string()))?;
+    if skipped < size as u64 {
+        return Err(CacheError::Network(format!(
+            "connection closed {} bytes into a {} byte frame",
+            skipped, size
+        )));
+    }
     Ok(())
 }
 
-fn handle_connection(
-    stream: &mut TcpStream,
+fn read_command_frame(
+    stream: &mut impl Read,
+    tagged: bool,
+    traced: bool,
+) -> Result<Option<TaggedCommand>, CacheError> {
+    let mut size_buf = [0u8; 4];
+    loop {
+        match stream.read(&mut size_buf[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            // одноразовые клиенты иногда закрываются через RST
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => return Ok(None),
+            Err(e) => return Err(CacheError::Network(e.to_string())),
+        }
+    }
+    read_exact(stream, &mut size_buf[1..])?;
+    let cmd_size = u32::from_le_bytes(size_buf) as usize;
+    let mut head = [0u8; 12];
+    let mut deadline = Deadline::NONE;
+    let mut trace = None;
+    if tagged {
+        read_exact(stream, &mut head)?;
+        let mut budget_ms = u32::from_le_bytes(head[8..].try_into().expect("4 bytes"));
+        if traced && budget_ms & TRACE_FLAG != 0 {
+            budget_ms &= !TRACE_FLAG;
+            let mut len = [0u8; 1];
+            read_exact(stream, &mut len)?;
+            let mut id = vec![0u8; len[0] as usize];
+            read_exact(stream, &mut id)?;
+            trace = Some(String::from_utf8_lossy(&id).into_owned()).filter(|t| !t.is_empty());
+        }
+        if budget_ms > 0 {
+            deadline = Deadline::after(Duration::from_millis(budget_ms as u64));
+        }
+    }
+    let id = u64::from_le_bytes(head[..8].try_into().expect("8 bytes"));
+
+    if cmd_size > MAX_COMMAND_SIZE {
+        skip_frame(stream, cmd_size)?;
+        let e = CacheError::Protocol(format!(
+            "command frame of {} bytes is over the {} byte limit",
+            cmd_size, MAX_COMMAND_SIZE
+        ));
+        return Ok(Some((id, deadline, trace, Err(e))));
+    }
+    let mut buf = vec![0u8; cmd_size];
+    read_exact(stream, &mut buf)?;
+    let cmd = bincode::deserialize(&buf)
+        .map_err(|e| CacheError::Protocol(format!("malformed command frame: {}", e)));
+    Ok(Some((id, deadline, trace, cmd)))
+}
+
+/// Команды по одной, пока клиент не закроет соединение: Python-клиент шлёт
+/// одну команду на соединение, Rust-`Client` держит его открытым. После
+/// `Hello` с версией 2 и выше соединение обслуживает `mux::serve_multiplexed`;
+/// до него ответы всегда одним кадром.
+fn handle_connection_impl<S: mux::Split>(
+    stream: &mut S,
     core: Arc<PersistentCore>,
+    role: PeerRole,
 ) -> Result<(), CacheError> {
-    handle_connection_impl(stream, core)
+    // запись реестра живёт до выхода из функции, в том числе по панике
+    let (registration, mut stream) = core.clients.register(stream, role)?;
+    let session = &*registration.session;
+    core.emit(|| ServerEvent::Connection {
+        id: session.id(),
+        peer: session.peer().to_string(),
+    });
+    let res = serve_commands(&mut stream, &core, session);
+    if let Err(e) = &res {
+        core.emit(|| ServerEvent::ConnectionError {
+            id: session.id(),
+            peer: session.peer().to_string(),
+            error: e.to_string(),
+        });
+    }
+    res
 }
 
-#[cfg(unix)]
-fn handle_connection_unix(
-    stream: &mut UnixStream,
+fn serve_commands<S: mux::Split>(
+    stream: &mut S,
+    core: &Arc<PersistentCore>,
+    session: &Session,
+) -> Result<(), CacheError> {
+    // до Hello соединение говорит на версии 1
+    let mut proto = 1;
+    while let Some(cmd) = read_command(stream)? {
+        let cmd = match cmd {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                write_frame(stream, &CacheResponse::Error(e.to_string()))?;
+                continue;
+            }
+        };
+        session.command(&cmd);
+        if let Err(e) = core.clients.admit(session, &cmd) {
+            let sent = write_frame(stream, &CacheResponse::Error(e.to_string()));
+            session.done();
+            sent?;
+            continue;
+        }
+        match core.chaos.as_ref().and_then(|chaos| chaos.before(&cmd)) {
+            Some(Fault::Error) => {
+                let sent = write_frame(stream, &CacheResponse::Error(INJECTED_ERROR.into()));
+                session.done();
+                sent?;
+                continue;
+            }
+            Some(Fault::Disconnect) => {
+                stream.shutdown();
+                return Ok(());
+            }
+            None => {}
+        }
+        // потоковые команды не доходят до execute_as: ACL проверяется здесь
+        let mut cmd = match cmd {
+            CacheCommand::ReplSync(_) | CacheCommand::Subscribe(_) | CacheCommand::Watch(..) => {
+                match authorize(core, session, cmd) {
+                    Ok((cmd, _)) => cmd,
+                    Err(e) => {
+                        let sent = write_frame(stream, &CacheResponse::Error(e.to_string()));
+                        session.done();
+                        sent?;
+                        continue;
+                    }
+                }
+            }
+            cmd => cmd,
+        };
+        if let CacheCommand::ReplSync(after) = cmd {
+            // соединение остаётся открытым, пока реплика подписана
+            return repl::serve_replica(stream, core, after);
+        }
+        if let CacheCommand::Subscribe(channels) = cmd {
+            return pubsub::serve_subscriber(stream, &core.pubsub, channels);
+        }
+        if let CacheCommand::Watch(prefix, with_values) = cmd {
+            return watch::serve_watcher(stream, &core.watchers, prefix, with_values);
+        }
+
+        match cmd {
+            CacheCommand::Hello(version) => proto = version.min(PROTOCOL_VERSION),
+            ref mut cmd if proto < 5 => escape_legacy(cmd),
+            _ => {}
+        }
+        let resp = execute_traced(cmd, core, Deadline::NONE, session, None);
+        let sent = encode_response(&resp.for_protocol(proto), core.max_response_size())
+            .and_then(|frame| write_all(stream, &frame));
+        session.done();
+        sent?;
+        if proto >= 2 {
+            return mux::serve_multiplexed(stream, core, session, proto);
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_connection(
+    stream: &mut TcpStream,
     core: Arc<PersistentCore>,
 ) -> Result<(), CacheError> {
-    handle_connection_impl(stream, core)
+    handle_connection_impl(stream, core, PeerRole::Full)
 }
 
-/// =======================
-/// Резолвинг директории журналирования
-/// =======================
-fn resolve_wal_path(wal_dir: Option<String>, file_name: &str) -> PyResult<PathBuf> {
-    let dir = if let Some(dir_str) = wal_dir {
-        PathBuf::from(dir_str)
-    } else {
-        // как раньше: просто кладём в текущую директорию
-        std::env::current_dir()
-            .map_err(|e| PyRuntimeError::new_err(format!("current_dir error: {}", e)))?
-    };
+/// Сколько отклонённое соединение ждёт, пока клиент прочтёт ошибку и закроется.
+#[cfg(unix)]
+const DENIED_LINGER: Duration = Duration::from_secs(1);
 
-    if !dir.exists() {
-        fs::create_dir_all(&dir)
-            .map_err(|e| PyRuntimeError::new_err(format!("create wal_dir error: {}", e)))?;
+/// Соединение Unix-сокета с проверкой `PersistentCore::set_peer_access`:
+/// чужой собеседник получает `Error("permission denied: ...")`, не прочитав
+/// ни одного кадра, и соединение закрывается.
+#[cfg(unix)]
+pub fn handle_connection_unix(
+    stream: &mut UnixStream,
+    core: Arc<PersistentCore>,
+) -> Result<(), CacheError> {
+    if core.peer_access.is_open() {
+        return handle_connection_impl(stream, core, PeerRole::Full);
+    }
+    let cred = peer_cred(stream)?;
+    match core.peer_access.role(cred, peer_access::own_uid()) {
+        Some(role) => handle_connection_impl(stream, core, role),
+        None => {
+            eprintln!(
+                "UDS connection from uid {} gid {} denied",
+                cred.uid, cred.gid
+            );
+            let e = CacheError::PermissionDenied(format!(
+                "uid {} gid {} may not use this socket",
+                cred.uid, cred.gid
+            ));
+            let sent = write_frame(stream, &CacheResponse::Error(e.to_string()));
+            let _ = stream.shutdown(std::net::Shutdown::Write);
+            // закрытие с непрочитанной командой оборвало бы ответ (ECONNRESET),
+            // поэтому присланное до закрытия клиентом выбрасывается не глядя
+            let _ = stream.set_read_timeout(Some(DENIED_LINGER));
+            let _ = std::io::copy(
+                &mut Read::take(&mut *stream, MAX_COMMAND_SIZE as u64),
+                &mut std::io::sink(),
+            );
+            sent
+        }
     }
-
-    Ok(dir.join(file_name))
 }
 
 /// =======================
-/// TCP-сервер
+/// Сервер
 /// =======================
-
-#[pyfunction(signature = (port, wal_dir=None))]
-fn serve(port: u16, wal_dir: Option<String>) -> PyResult<()> {
-    let addr = format!("127.0.0.1:{}", port);
-    println!("🚀 TinyCache TCP server: {}", addr);
-
-    // путь WAL можно потом вынести в конфиг/ENV
-    // let wal_path = PathBuf::from("tiny-mp-cache.wal");
-
-    let wal_path = resolve_wal_path(wal_dir, "tiny-mp-cache.wal")?;
-    let core = Arc::new(
-        PersistentCore::new(wal_path)
-            .map_err(|e| PyRuntimeError::new_err(format!("init persistent core: {}", e)))?,
-    );
-
-    let listener = TcpListener::bind(&addr)
-        .map_err(|e| PyRuntimeError::new_err(format!("Bind error: {}", e)))?;
+/// Принимает соединения на `addr` (`"127.0.0.1:5002"`), по потоку на клиента.
+/// Возвращается только при ошибке bind (`CacheError::Bind`) или listener.
+pub fn serve_tcp(addr: &str, core: Arc<PersistentCore>) -> Result<(), CacheError> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| CacheError::Bind(BindFailure::new(addr, "bind", &e)))?;
 
     println!("🚀 TinyCache TCP ready: {}", addr);
+    serve_listener(listener, core)
+}
 
+/// Цикл приёма на уже открытом listener, например на порту 0 в тестах.
+pub fn serve_listener(listener: TcpListener, core: Arc<PersistentCore>) -> Result<(), CacheError> {
+    if let Ok(addr) = listener.local_addr() {
+        core.emit(|| ServerEvent::Ready {
+            addr: addr.to_string(),
+        });
+    }
     for stream_res in listener.incoming() {
         match stream_res {
             Ok(mut stream) => {
@@ -336,32 +4049,58 @@ fn serve(port: u16, wal_dir: Option<String>) -> PyResult<()> {
     Ok(())
 }
 
-/// =======================
-/// UDS-сервер (только Unix)
-/// =======================
-
+/// Сколько `serve_unix_socket` ждёт ответа на Ping от того, кто уже слушает
+/// файл сокета.
 #[cfg(unix)]
-#[pyfunction(signature = (path, wal_dir=None))]
-fn serve_unix(path: String, wal_dir: Option<String>) -> PyResult<()> {
-    let sock_path = PathBuf::from(&path);
-    if sock_path.exists() {
-        fs::remove_file(&sock_path)
-            .map_err(|e| PyRuntimeError::new_err(format!("Remove old socket: {}", e)))?;
-    }
+const LIVE_SOCKET_PROBE: Duration = Duration::from_secs(1);
 
-    println!("🚀 TinyCache UDS server: {:?}", sock_path);
+/// Слушает ли кто-то файл сокета. Отказ в соединении — файл остался от
+/// упавшего процесса; если соединение приняли, это чужой живой сокет, даже
+/// если он не ответил на Ping.
+#[cfg(unix)]
+fn check_socket_free(path: &Path) -> Result<(), CacheError> {
+    let Ok(mut stream) = UnixStream::connect(path) else {
+        return Ok(());
+    };
+    let _ = stream.set_read_timeout(Some(LIVE_SOCKET_PROBE));
+    let _ = stream.set_write_timeout(Some(LIVE_SOCKET_PROBE));
+    let pong = encode_frame(&CacheCommand::Ping)
+        .and_then(|frame| write_all(&mut stream, &frame))
+        .and_then(|()| read_response(&mut stream));
+    let detail = match pong {
+        Ok(CacheResponse::Ok) => "another server is already listening on this path",
+        _ => "another process is listening on this path",
+    };
+    Err(CacheError::Bind(BindFailure::new(
+        path.display().to_string(),
+        "bind",
+        &std::io::Error::new(ErrorKind::AddrInUse, detail),
+    )))
+}
 
-    // let wal_path = PathBuf::from("tiny-mp-cache.wal");
-    let wal_path = resolve_wal_path(wal_dir, "tiny-mp-cache.wal")?;
-    let core = Arc::new(
-        PersistentCore::new(wal_path)
-            .map_err(|e| PyRuntimeError::new_err(format!("init persistent core: {}", e)))?,
-    );
+/// То же на Unix domain socket. Файл сокета, оставшийся от упавшего процесса,
+/// удаляется; если его ещё слушают, запуск прерывается с
+/// `BindReason::AddressInUse`, а не отнимает сокет у работающего сервера.
+#[cfg(unix)]
+pub fn serve_unix_socket(path: &Path, core: Arc<PersistentCore>) -> Result<(), CacheError> {
+    if path.exists() {
+        check_socket_free(path)?;
+        fs::remove_file(path).map_err(|e| {
+            CacheError::Bind(BindFailure::new(
+                path.display().to_string(),
+                "remove old socket",
+                &e,
+            ))
+        })?;
+    }
 
-    let listener = UnixListener::bind(&sock_path)
-        .map_err(|e| PyRuntimeError::new_err(format!("Bind UDS error: {}", e)))?;
+    let listener = UnixListener::bind(path)
+        .map_err(|e| CacheError::Bind(BindFailure::new(path.display().to_string(), "bind", &e)))?;
 
-    println!("🚀 TinyCache UDS ready: {:?}", sock_path);
+    println!("🚀 TinyCache UDS ready: {:?}", path);
+    core.emit(|| ServerEvent::Ready {
+        addr: format!("unix://{}", path.display()),
+    });
 
     for stream_res in listener.incoming() {
         match stream_res {
@@ -382,119 +4121,3 @@ fn serve_unix(path: String, wal_dir: Option<String>) -> PyResult<()> {
 
     Ok(())
 }
-
-/// =======================
-/// Python-клиент TinyCache
-/// =======================
-
-#[pyclass]
-#[derive(Clone)]
-pub struct TinyCache {
-    addr: TransportAddr,
-}
-
-#[pymethods]
-impl TinyCache {
-    #[new]
-    fn new(addr: String) -> Self {
-        thread::sleep(Duration::from_millis(10));
-        let addr = TransportAddr::parse(&addr);
-        Self { addr }
-    }
-
-    fn set(&self, key: String, value: &[u8]) -> PyResult<()> {
-        let v = value.to_vec();
-        match send_cmd_sync(&self.addr, CacheCommand::Set(key, v)) {
-            Ok(CacheResponse::Ok) => Ok(()),
-            Ok(resp) => Err(PyRuntimeError::new_err(format!(
-                "Unexpected response from set: {:?}",
-                resp
-            ))),
-            Err(e) => Err(map_error(e, "set")),
-        }
-    }
-
-    fn get<'py>(
-        &self,
-        py: Python<'py>,
-        key: String,
-    ) -> PyResult<Option<Bound<'py, PyBytes>>> {
-        match send_cmd_sync(&self.addr, CacheCommand::Get(key)) {
-            Ok(CacheResponse::Value(v)) => {
-                let b = PyBytes::new_bound(py, &v);
-                Ok(Some(b))
-            }
-            Ok(CacheResponse::Nil) => Ok(None),
-            Ok(resp) => Err(PyRuntimeError::new_err(format!(
-                "Unexpected response from get: {:?}",
-                resp
-            ))),
-            Err(e) => Err(map_error(e, "get")),
-        }
-    }
-
-    fn pop<'py>(
-        &self,
-        py: Python<'py>,
-        key: String,
-    ) -> PyResult<Option<Bound<'py, PyBytes>>> {
-        match send_cmd_sync(&self.addr, CacheCommand::Pop(key)) {
-            Ok(CacheResponse::Value(v)) => {
-                let b = PyBytes::new_bound(py, &v);
-                Ok(Some(b))
-            }
-            Ok(CacheResponse::Nil) => Ok(None),
-            Ok(resp) => Err(PyRuntimeError::new_err(format!(
-                "Unexpected response from pop: {:?}",
-                resp
-            ))),
-            Err(e) => Err(map_error(e, "pop")),
-        }
-    }
-
-    fn delete(&self, key: String) -> PyResult<i64> {
-        match send_cmd_sync(&self.addr, CacheCommand::Del(key)) {
-            Ok(CacheResponse::Int(n)) => Ok(n),
-            Ok(resp) => Err(PyRuntimeError::new_err(format!(
-                "Unexpected response from delete: {:?}",
-                resp
-            ))),
-            Err(e) => Err(map_error(e, "delete")),
-        }
-    }
-
-    fn keys(&self, pattern: String) -> PyResult<Vec<String>> {
-        match send_cmd_sync(&self.addr, CacheCommand::Keys(pattern)) {
-            Ok(CacheResponse::Keys(keys)) => Ok(keys),
-            Ok(resp) => Err(PyRuntimeError::new_err(format!(
-                "Unexpected response from keys: {:?}",
-                resp
-            ))),
-            Err(e) => Err(map_error(e, "keys")),
-        }
-    }
-
-    fn len(&self) -> PyResult<i64> {
-        match send_cmd_sync(&self.addr, CacheCommand::Len) {
-            Ok(CacheResponse::Int(n)) => Ok(n),
-            Ok(resp) => Err(PyRuntimeError::new_err(format!(
-                "Unexpected response from len: {:?}",
-                resp
-            ))),
-            Err(e) => Err(map_error(e, "len")),
-        }
-    }
-}
-
-/// =======================
-/// Python-модуль
-/// =======================
-
-#[pymodule]
-fn tiny_mp_cache(_py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_class::<TinyCache>()?;
-    m.add_function(wrap_pyfunction!(serve, m)?)?;
-    #[cfg(unix)]
-    m.add_function(wrap_pyfunction!(serve_unix, m)?)?;
-    Ok(())
-}
@@ -0,0 +1,153 @@
+//! Сквозное чтение (read-through), см. `PersistentCore::set_loader`: Get
+//! мимо кэша зовёт загрузчик, кладёт результат в кэш и отдаёт его.
+//! Загрузчик работает на своём пуле потоков, так что медленный источник
+//! занимает не больше `threads` вызовов сразу. Одновременные промахи по
+//! одному ключу ждут одного вызова (singleflight): первый становится ведущим
+//! и записывает результат, остальные получают его же.
+
+use crate::error::CacheError;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Сколько ждать загрузчик; дольше — ошибка Busy у всех ждущих ключа, а
+/// следующий промах зовёт загрузчик заново.
+pub const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Значение от загрузчика и его срок жизни в кэше (`None` — бессрочно).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loaded {
+    pub value: Vec<u8>,
+    pub ttl: Option<Duration>,
+}
+
+/// Загрузчик ключа: `Ok(None)` — и в источнике нет, `Err` — текст ошибки
+/// для ответа клиенту.
+pub type Loader = Box<dyn Fn(&str) -> Result<Option<Loaded>, String> + Send + Sync>;
+
+type Outcome = Result<Option<Vec<u8>>, CacheError>;
+
+type Job = (String, SyncSender<Result<Option<Loaded>, String>>);
+
+// вызов загрузчика по ключу, который ждут остальные промахи
+#[derive(Default)]
+struct Flight {
+    outcome: Mutex<Option<Outcome>>,
+    done: Condvar,
+}
+
+pub(crate) struct ReadThrough {
+    jobs: Sender<Job>,
+    inflight: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+impl ReadThrough {
+    pub(crate) fn start(loader: Loader, threads: usize) -> Result<Self, CacheError> {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        let loader = Arc::new(loader);
+        for n in 0..threads.max(1) {
+            let (rx, loader) = (Arc::clone(&rx), Arc::clone(&loader));
+            thread::Builder::new()
+                .name(format!("tiny-mp-cache-loader-{}", n))
+                .spawn(move || run(&rx, &loader))
+                .map_err(|e| CacheError::Internal(format!("spawn loader thread: {}", e)))?;
+        }
+        Ok(Self {
+            jobs: tx,
+            inflight: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn inflight(&self) -> MutexGuard<'_, HashMap<String, Arc<Flight>>> {
+        self.inflight.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Значение `key` от загрузчика. Ведущий промах вызывает загрузчик и
+    /// `store` с результатом (она возвращает, что в итоге лежит в кэше);
+    /// остальные промахи по ключу в это время ждут его ответа.
+    pub(crate) fn load(&self, key: &str, store: impl FnOnce(Loaded) -> Outcome) -> Outcome {
+        let (flight, leader) = {
+            let mut inflight = self.inflight();
+            match inflight.get(key) {
+                Some(flight) => (Arc::clone(flight), false),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    inflight.insert(key.to_string(), Arc::clone(&flight));
+                    (flight, true)
+                }
+            }
+        };
+        if !leader {
+            return wait(&flight, key);
+        }
+        let outcome = match self.call(key) {
+            Ok(Some(loaded)) => store(loaded),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        };
+        // ключ снимается до ответа ждущим: следующий промах уже зовёт загрузчик
+        self.inflight().remove(key);
+        *flight.outcome.lock().unwrap_or_else(|e| e.into_inner()) = Some(outcome.clone());
+        flight.done.notify_all();
+        outcome
+    }
+
+    fn call(&self, key: &str) -> Result<Option<Loaded>, CacheError> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.jobs
+            .send((key.to_string(), tx))
+            .map_err(|_| CacheError::Internal("loader threads are gone".into()))?;
+        match rx.recv_timeout(LOAD_TIMEOUT) {
+            Ok(Ok(loaded)) => Ok(loaded),
+            Ok(Err(msg)) => Err(CacheError::Internal(format!(
+                "loader failed for key {:?}: {}",
+                key, msg
+            ))),
+            Err(_) => Err(timed_out(key)),
+        }
+    }
+}
+
+fn timed_out(key: &str) -> CacheError {
+    CacheError::Busy(format!(
+        "loader for key {:?} did not finish within {}s",
+        key,
+        LOAD_TIMEOUT.as_secs()
+    ))
+}
+
+fn wait(flight: &Flight, key: &str) -> Outcome {
+    let until = Instant::now() + LOAD_TIMEOUT;
+    let mut outcome = flight.outcome.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        if let Some(outcome) = outcome.as_ref() {
+            return outcome.clone();
+        }
+        let Some(left) = until.checked_duration_since(Instant::now()) else {
+            return Err(timed_out(key));
+        };
+        outcome = flight
+            .done
+            .wait_timeout(outcome, left)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+}
+
+fn run(rx: &Mutex<Receiver<Job>>, loader: &Loader) {
+    loop {
+        let job = rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        let Ok((key, reply)) = job else {
+            return;
+        };
+        // паника загрузчика — ошибка этого ключа, поток живёт дальше
+        let res = panic::catch_unwind(AssertUnwindSafe(|| loader(&key)))
+            .unwrap_or_else(|_| Err("loader panicked".into()));
+        // ведущий мог уже не дождаться
+        let _ = reply.send(res);
+    }
+}
@@ -0,0 +1,649 @@
+//! Протокол 2: много запросов одновременно по одному соединению.
+//!
+//! После `Hello` с версией 2 кадр команды — длина тела (u32 LE), id запроса
+//! (u64 LE), бюджет в миллисекундах (u32 LE, 0 — без срока) и тело bincode.
+//! Ответ — длина, id запроса и тело; он может обогнать ответы на запросы,
+//! отправленные раньше. Порядок выполнения команд одного соединения:
+//! - команды с одним и тем же ключом выполняются в порядке отправки: их
+//!   обрабатывает один и тот же поток соединения;
+//! - команды не с одним ключом (`Keys`, `MSet`, `DelPrefix`, `Touch`, `Info`,
+//!   `Save`, админ-команды и т.п.) — барьер: выполняются после всех отправленных
+//!   раньше и до всех отправленных позже;
+//! - `BGet` ждёт ключ отдельно и ни с чем не упорядочен.
+//!
+//! Порядок между разными соединениями, как и раньше, не гарантируется.
+//!
+//! С протокола 3 ответы на `Keys`, `KeysSorted` и `ScanItems` приходят
+//! несколькими кадрами с одним id (`KeysChunk`, `ItemsChunk`, последний — с
+//! `more == false`); между ними могут быть кадры других ответов. Клиенты
+//! этого модуля и пула собирают куски в обычные `Keys` и `Items`.
+//!
+//! Бюджет отсчитывается от получения кадра. Когда он истёк, команда
+//! получает `Error("deadline exceeded")`; проверяется он только здесь:
+//! - перед началом выполнения, в том числе после ожидания в очереди потока
+//!   и барьера, — команда не выполнена совсем;
+//! - каждые 1024 ключа обхода в `Keys`, `KeysSorted`, `Scan`, `ScanItems`,
+//!   `ExportBloom`, `PrefixStats` и `Aggregate` — они ничего не меняют;
+//! - в `DelPrefix` — только пока собираются ключи; после записи батча в WAL
+//!   удаление доводится до конца и отвечает обычным числом;
+//! - в `Export` — пока собираются ключи и пишутся пары; недописанный файл
+//!   удаляется, прежний файл дампа остаётся.
+//!
+//! Остальные команды, начав, выполняются до конца: запись, уже попавшая в
+//! WAL, никогда не отменяется наполовину. `BGet` ждёт ключ по своему таймауту.
+//! `Subscribe`, `Watch` и `ReplSync` переводят соединение в потоковый режим,
+//! поэтому по такому соединению не принимаются.
+
+use crate::acl::Credentials;
+use crate::chaos::{Fault, INJECTED_ERROR};
+use crate::clients::Session;
+use crate::core::Deadline;
+use crate::encoding::{decode_response, escape_legacy};
+use crate::error::CacheError;
+use crate::pool::Timeouts;
+use crate::{
+    current_trace_id, encode_frame, encode_response, execute_traced, join_chunk,
+    read_tagged_command, read_tagged_response, request_frame, request_tagged, wal,
+    write_tagged_command, write_tagged_response, CacheCommand, CacheResponse, Conn, PersistentCore,
+    TransportAddr, PROTOCOL_VERSION, TRACE_FLAG,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+/// Потоки, выполняющие команды одного соединения.
+const WORKERS: usize = 8;
+/// Команды в очереди одного потока; при полной очереди сервер перестаёт
+/// читать соединение, пока она не освободится.
+const QUEUE: usize = 64;
+/// Сколько `BGet` одного соединения могут ждать одновременно.
+const MAX_BLOCKED: usize = 64;
+/// Сколько команд подряд может уйти в одну пачку записи WAL, см.
+/// `serve_multiplexed`.
+const MAX_PIPELINE: usize = 256;
+/// Буфер чтения соединения: в нём видно, пришёл ли уже следующий кадр.
+const READ_BUFFER: usize = 64 * 1024;
+
+/// Серверная сторона соединения, которую читают и пишут разные потоки.
+pub(crate) trait Split: Read + Write + Send + Sync + Sized + 'static {
+    fn try_clone(&self) -> std::io::Result<Self>;
+    /// Адрес клиента для `ClientList`.
+    fn peer(&self) -> String;
+    /// Закрывает соединение в обе стороны; поток, читающий его, получит EOF.
+    fn shutdown(&self);
+}
+
+impl Split for TcpStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn peer(&self) -> String {
+        self.peer_addr()
+            .map_or_else(|_| "unknown".into(), |a| a.to_string())
+    }
+
+    fn shutdown(&self) {
+        let _ = TcpStream::shutdown(self, Shutdown::Both);
+    }
+}
+
+#[cfg(unix)]
+impl Split for UnixStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    // у клиентов Unix-сокета адреса обычно нет
+    fn peer(&self) -> String {
+        "unix".into()
+    }
+
+    fn shutdown(&self) {
+        let _ = UnixStream::shutdown(self, Shutdown::Both);
+    }
+}
+
+// ключ, по которому команда упорядочивается; None — барьер
+fn order_key(cmd: &CacheCommand) -> Option<&str> {
+    match cmd {
+        CacheCommand::Set(k, _)
+        | CacheCommand::SetWithTags(k, ..)
+        | CacheCommand::Undelete(k)
+        | CacheCommand::History(k)
+        | CacheCommand::Get(k)
+        | CacheCommand::GetWithMeta(k)
+        | CacheCommand::Pop(k)
+        | CacheCommand::Del(k)
+        | CacheCommand::Exists(k)
+        | CacheCommand::GetOrSet(k, _)
+        | CacheCommand::Update(k, _)
+        | CacheCommand::SetBit(k, ..)
+        | CacheCommand::GetBit(k, _)
+        | CacheCommand::BitCount(k, _)
+        | CacheCommand::IdleTime(k)
+        | CacheCommand::PSetEx(k, ..)
+        | CacheCommand::PExpire(k, _)
+        | CacheCommand::ExpireAt(k, _)
+        | CacheCommand::PTtl(k)
+        | CacheCommand::Type(k)
+        | CacheCommand::DelIfEquals(k, _)
+        | CacheCommand::SetNx(k, ..)
+        | CacheCommand::PExtendIfEquals(k, ..)
+        | CacheCommand::IncrRange(k, _) => Some(k),
+        _ => None,
+    }
+}
+
+fn worker_for(key: &str) -> usize {
+    let mut h = DefaultHasher::new();
+    key.hash(&mut h);
+    h.finish() as usize % WORKERS
+}
+
+// команды, отданные потокам соединения: барьер ждёт, пока их не останется
+#[derive(Default)]
+struct InFlight {
+    count: Mutex<usize>,
+    idle: Condvar,
+}
+
+impl InFlight {
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.count.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn start(&self) {
+        *self.lock() += 1;
+    }
+
+    fn done(&self) {
+        let mut n = self.lock();
+        *n -= 1;
+        if *n == 0 {
+            self.idle.notify_all();
+        }
+    }
+
+    fn wait_idle(&self) {
+        let mut n = self.lock();
+        while *n > 0 {
+            n = self.idle.wait(n).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+// команда в очереди потока: id запроса, крайний срок, trace id и команда
+type Queued = (u64, Deadline, Option<String>, CacheCommand);
+
+enum Job {
+    Run(Queued),
+    // граница пачки: отложенные записи WAL на диск, затем придержанные ответы
+    Flush,
+}
+
+// в буфере чтения уже целиком лежит следующий кадр команды
+fn frame_buffered(buf: &[u8], proto: u32) -> bool {
+    let Some(head) = buf.get(..16) else {
+        return false;
+    };
+    let size = u32::from_le_bytes(head[..4].try_into().expect("4 bytes")) as usize;
+    let budget_ms = u32::from_le_bytes(head[12..].try_into().expect("4 bytes"));
+    let mut need = head.len() + size;
+    if proto >= 4 && budget_ms & TRACE_FLAG != 0 {
+        match buf.get(16) {
+            Some(&len) => need += 1 + len as usize,
+            None => return false,
+        }
+    }
+    buf.len() >= need
+}
+
+/// Обслуживает соединение, договорившееся о протоколе `proto` (2 и выше), до
+/// его закрытия.
+///
+/// Записи WAL команд, отданных потокам, идут пачками: пока в буфере чтения
+/// уже лежит следующий кадр (клиент шлёт команды, не дожидаясь ответов),
+/// команды пишут в WAL без записи на диск (`wal::deferring`), а ответы на них
+/// придерживаются. На границе пачки — буфер пуст, барьер или `MAX_PIPELINE`
+/// команд подряд — потоки пишут накопленное одним write (и одним fsync при
+/// `fsync="always"`) и только затем отвечают, так что подтверждённая команда
+/// на диске так же, как без пачек. Одиночная команда — пачка из одной.
+pub(crate) fn serve_multiplexed<S: Split>(
+    stream: &mut S,
+    core: &Arc<PersistentCore>,
+    session: &Session,
+    proto: u32,
+) -> Result<(), CacheError> {
+    let writer = Mutex::new(
+        stream
+            .try_clone()
+            .map_err(|e| CacheError::Network(e.to_string()))?,
+    );
+    // у каждой принятой команды ровно один ответ: на нём она и завершается.
+    // Куски одного ответа пишутся под замком по одному, между ними могут
+    // пройти ответы других запросов
+    let respond = |id: u64, resp: CacheResponse| -> Result<(), CacheError> {
+        let limit = core.max_response_size();
+        let sent = resp.send_in_frames(proto, limit, |part| {
+            let frame = encode_response(part, limit)?;
+            let mut w = writer.lock().unwrap_or_else(|e| e.into_inner());
+            write_tagged_response(&mut *w, id, &frame)
+        });
+        session.done();
+        sent
+    };
+    // ответ на кадр, не ставший командой: среди принятых он не считается
+    let reject = |id: u64, e: CacheError| -> Result<(), CacheError> {
+        let frame = encode_frame(&CacheResponse::Error(e.to_string()))?;
+        let mut w = writer.lock().unwrap_or_else(|e| e.into_inner());
+        write_tagged_response(&mut *w, id, &frame)
+    };
+    let in_flight = InFlight::default();
+    let blocked = AtomicUsize::new(0);
+    let mut reader = BufReader::with_capacity(READ_BUFFER, stream);
+
+    thread::scope(|s| {
+        let (respond, in_flight, blocked) = (&respond, &in_flight, &blocked);
+        let workers: Vec<SyncSender<Job>> = (0..WORKERS)
+            .map(|_| {
+                let (tx, rx) = mpsc::sync_channel::<Job>(QUEUE);
+                s.spawn(move || {
+                    // ответы, чьи записи WAL ещё не на диске
+                    let mut held = Vec::new();
+                    let release = |held: &mut Vec<(u64, CacheResponse)>| {
+                        let flushed = core.flush_wal();
+                        for (id, resp) in held.drain(..) {
+                            let resp = match &flushed {
+                                Ok(()) => resp,
+                                Err(e) => CacheResponse::Error(e.to_string()),
+                            };
+                            // ошибку записи увидит и поток чтения
+                            let _ = respond(id, resp);
+                            in_flight.done();
+                        }
+                    };
+                    for job in rx {
+                        match job {
+                            Job::Run((id, deadline, trace, cmd)) => {
+                                let resp = wal::deferring(|| {
+                                    execute_traced(cmd, core, deadline, session, trace.as_deref())
+                                });
+                                held.push((id, resp));
+                            }
+                            Job::Flush => release(&mut held),
+                        }
+                    }
+                    release(&mut held);
+                });
+                tx
+            })
+            .collect();
+        // потоки с придержанными ответами и число команд в пачке
+        let mut dirty = [false; WORKERS];
+        let mut batched = 0;
+        let flush = |dirty: &mut [bool; WORKERS], batched: &mut usize| {
+            for (worker, dirty) in workers.iter().zip(dirty.iter_mut()) {
+                if std::mem::take(dirty) {
+                    worker.send(Job::Flush).expect("mux worker exited");
+                }
+            }
+            *batched = 0;
+        };
+
+        let res = loop {
+            if batched > 0 && (batched >= MAX_PIPELINE || !frame_buffered(reader.buffer(), proto)) {
+                flush(&mut dirty, &mut batched);
+            }
+            let (id, deadline, trace, cmd) = match read_tagged_command(&mut reader, proto) {
+                Ok(Some((id, deadline, trace, Ok(cmd)))) => (id, deadline, trace, cmd),
+                // кадр цел, но не команда: ответ ошибкой, чтение дальше
+                Ok(Some((id, _, _, Err(e)))) => match reject(id, e) {
+                    Ok(()) => continue,
+                    Err(e) => break Err(e),
+                },
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            session.command(&cmd);
+            session.traced(trace.as_deref());
+            // очередь выбирается по ключу, поэтому он приводится к форме политики уже здесь
+            let mut cmd = match core
+                .clients
+                .admit(session, &cmd)
+                .and_then(|()| core.normalize_keys(cmd))
+            {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    if let Err(e) = respond(id, CacheResponse::Error(e.to_string())) {
+                        break Err(e);
+                    }
+                    continue;
+                }
+            };
+            // задержка здесь держит и следующие команды: как медленная сеть
+            match core.chaos.as_ref().and_then(|chaos| chaos.before(&cmd)) {
+                Some(Fault::Error) => {
+                    if let Err(e) = respond(id, CacheResponse::Error(INJECTED_ERROR.into())) {
+                        break Err(e);
+                    }
+                    continue;
+                }
+                Some(Fault::Disconnect) => {
+                    // начатые команды доделываются, но ответы их уже не дойдут
+                    writer.lock().unwrap_or_else(|e| e.into_inner()).shutdown();
+                    break Ok(());
+                }
+                None => {}
+            }
+            if proto < 5 {
+                escape_legacy(&mut cmd);
+            }
+            let sent = match cmd {
+                CacheCommand::Subscribe(_)
+                | CacheCommand::Watch(..)
+                | CacheCommand::ReplSync(_) => respond(
+                    id,
+                    CacheResponse::Error(
+                        "streaming commands need a dedicated connection without multiplexing"
+                            .into(),
+                    ),
+                ),
+                CacheCommand::Hello(_) => respond(
+                    id,
+                    CacheResponse::Error("protocol version is already negotiated".into()),
+                ),
+                CacheCommand::BGet(..) => {
+                    if blocked.fetch_add(1, Ordering::AcqRel) >= MAX_BLOCKED {
+                        blocked.fetch_sub(1, Ordering::AcqRel);
+                        respond(
+                            id,
+                            CacheResponse::Error(format!(
+                                "at most {} blocking gets may wait on one connection",
+                                MAX_BLOCKED
+                            )),
+                        )
+                    } else {
+                        s.spawn(move || {
+                            let resp =
+                                execute_traced(cmd, core, deadline, session, trace.as_deref());
+                            let _ = respond(id, resp);
+                            blocked.fetch_sub(1, Ordering::AcqRel);
+                        });
+                        Ok(())
+                    }
+                }
+                cmd => match order_key(&cmd) {
+                    Some(key) => {
+                        let worker = worker_for(key);
+                        in_flight.start();
+                        // потоки живут, пока живы их очереди
+                        workers[worker]
+                            .send(Job::Run((id, deadline, trace, cmd)))
+                            .expect("mux worker exited");
+                        dirty[worker] = true;
+                        batched += 1;
+                        Ok(())
+                    }
+                    None => {
+                        // придержанные ответы тоже «в полёте»: без границы пачки
+                        // барьер ждал бы их вечно
+                        flush(&mut dirty, &mut batched);
+                        in_flight.wait_idle();
+                        let resp = execute_traced(cmd, core, deadline, session, trace.as_deref());
+                        respond(id, resp)
+                    }
+                },
+            };
+            if let Err(e) = sent {
+                break Err(e);
+            }
+        };
+        // закрытые очереди завершают потоки, когда те доделают начатое
+        drop(workers);
+        res
+    })
+}
+
+/// =======================
+/// Клиентская сторона
+/// =======================
+type Reply = Result<CacheResponse, CacheError>;
+
+#[derive(Default)]
+struct Waiters {
+    by_id: HashMap<u64, SyncSender<Reply>>,
+    // почему соединение больше не работает; новые запросы получают эту ошибку
+    broken: Option<CacheError>,
+}
+
+fn lock(waiters: &Mutex<Waiters>) -> MutexGuard<'_, Waiters> {
+    waiters.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Одно соединение протокола 2, по которому запросы из разных потоков идут
+/// одновременно: каждый получает свой id, а фоновый поток раздаёт ответы по
+/// id ожидающим. Порядок выполнения — см. описание модуля. Запросы не
+/// повторяются: после обрыва все ожидающие и все следующие вызовы получают
+/// ошибку, нужно открыть новое соединение.
+pub struct MuxConn {
+    timeouts: Timeouts,
+    // версия, о которой договорились в Hello
+    proto: u32,
+    writer: Mutex<Conn>,
+    waiters: Arc<Mutex<Waiters>>,
+    next_id: AtomicU64,
+}
+
+/// Ответ на отправленный запрос; см. `MuxConn::send`.
+pub struct Pending {
+    id: u64,
+    rx: Receiver<Reply>,
+    timeout: Option<Duration>,
+    waiters: Arc<Mutex<Waiters>>,
+}
+
+impl Pending {
+    /// Ждёт ответа не дольше таймаута чтения соединения. Сжатые значения
+    /// распаковываются здесь, в потоке вызывающего, а не в потоке чтения.
+    pub fn wait(self) -> Result<CacheResponse, CacheError> {
+        let reply = match self.timeout {
+            None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Some(t) => self.rx.recv_timeout(t),
+        };
+        match reply {
+            Ok(reply) => reply.and_then(decode_response),
+            Err(RecvTimeoutError::Timeout) => Err(CacheError::Network(format!(
+                "no response to request {} within {:?}",
+                self.id, self.timeout
+            ))),
+            Err(RecvTimeoutError::Disconnected) => Err(CacheError::Closed),
+        }
+    }
+
+    /// Ответ, если он уже пришёл, без ожидания: для циклов событий.
+    pub fn try_take(&self) -> Option<Result<CacheResponse, CacheError>> {
+        self.rx.try_recv().ok().map(|r| r.and_then(decode_response))
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        // ответ, который уже никто не ждёт, поток чтения просто выбросит
+        lock(&self.waiters).by_id.remove(&self.id);
+    }
+}
+
+fn read_loop(mut conn: Conn, waiters: Arc<Mutex<Waiters>>) {
+    // ответы протокола 3, пришедшие ещё не целиком, по id запроса
+    let mut partial: HashMap<u64, Option<CacheResponse>> = HashMap::new();
+    let err = loop {
+        match read_tagged_response(&mut conn) {
+            Ok((id, resp)) => {
+                let acc = partial.entry(id).or_default();
+                let reply = match join_chunk(acc, resp) {
+                    Ok(None) => continue,
+                    Ok(Some(CacheResponse::Error(msg))) => Err(CacheError::from_server(msg)),
+                    Ok(Some(resp)) => Ok(resp),
+                    Err(e) => Err(e),
+                };
+                partial.remove(&id);
+                if let Some(tx) = lock(&waiters).by_id.remove(&id) {
+                    let _ = tx.send(reply);
+                }
+            }
+            Err(e) => break e,
+        }
+    };
+    let mut w = lock(&waiters);
+    for (_, tx) in w.by_id.drain() {
+        let _ = tx.send(Err(err.clone()));
+    }
+    w.broken.get_or_insert(err);
+}
+
+impl MuxConn {
+    /// Адрес — как у `Client::connect`. Сервер версии 1 на `Hello` закрывает
+    /// соединение, и здесь это сетевая ошибка.
+    pub fn connect(addr: &str) -> Result<Self, CacheError> {
+        Self::open(TransportAddr::parse(addr), Timeouts::default(), None)
+    }
+
+    /// С `auth` соединение сразу после `Hello` входит пользователем ACL.
+    pub(crate) fn open(
+        addr: TransportAddr,
+        timeouts: Timeouts,
+        auth: Option<&Credentials>,
+    ) -> Result<Self, CacheError> {
+        let mut conn = Conn::connect_timeout(&addr, timeouts.connect)?;
+        conn.set_nodelay()?;
+        conn.set_read_timeout(timeouts.read)?;
+        conn.set_write_timeout(timeouts.write)?;
+        let proto = match request_frame(
+            &mut conn,
+            &encode_frame(&CacheCommand::Hello(PROTOCOL_VERSION))?,
+        )? {
+            CacheResponse::Int(v) if v >= 2 => v.min(PROTOCOL_VERSION as i64) as u32,
+            resp => {
+                return Err(CacheError::Unsupported(format!(
+                    "server does not support multiplexing, Hello answered {:?}",
+                    resp
+                )))
+            }
+        };
+        if let Some(auth) = auth {
+            // id 0 запросам соединения не достаётся: next_id начинается с 1
+            match request_tagged(
+                &mut conn,
+                0,
+                timeouts.read,
+                None,
+                &encode_frame(&auth.command())?,
+            )? {
+                CacheResponse::Ok => {}
+                resp => {
+                    return Err(CacheError::Internal(format!(
+                        "Unexpected response from auth: {:?}",
+                        resp
+                    )))
+                }
+            }
+        }
+        // поток чтения ждёт ответов без таймаута: таймаут — у каждого запроса
+        conn.set_read_timeout(None)?;
+        let reader = conn.try_clone()?;
+        let waiters = Arc::new(Mutex::new(Waiters::default()));
+        let w = Arc::clone(&waiters);
+        thread::Builder::new()
+            .name("tiny-mp-cache-mux".into())
+            .spawn(move || read_loop(reader, w))
+            .map_err(|e| CacheError::Internal(format!("spawn mux reader: {}", e)))?;
+        Ok(Self {
+            timeouts,
+            proto,
+            writer: Mutex::new(conn),
+            waiters,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Отправляет команду, не дожидаясь ответа.
+    pub fn send(&self, cmd: &CacheCommand) -> Result<Pending, CacheError> {
+        self.send_frame(&encode_frame(cmd)?, Duration::ZERO)
+    }
+
+    /// Отправляет команду и ждёт ответа; ошибка сервера — `CacheError::Server`.
+    pub fn call(&self, cmd: &CacheCommand) -> Result<CacheResponse, CacheError> {
+        self.send(cmd)?.wait()
+    }
+
+    /// Кадр `encode_frame`/`set_frame`; `wait` — сколько сервер может законно
+    /// молчать сверх таймаута чтения (для блокирующих команд).
+    pub(crate) fn send_frame(&self, frame: &[u8], wait: Duration) -> Result<Pending, CacheError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::sync_channel(1);
+        {
+            let mut w = lock(&self.waiters);
+            if let Some(e) = &w.broken {
+                return Err(e.clone());
+            }
+            w.by_id.insert(id, tx);
+        }
+        let pending = Pending {
+            id,
+            rx,
+            timeout: self.timeouts.read.map(|t| t + wait),
+            waiters: Arc::clone(&self.waiters),
+        };
+        let trace = (self.proto >= 4).then(current_trace_id).flatten();
+        // кого уже не ждут, тот и на сервере не нужен
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let sent = write_tagged_command(&mut *writer, id, pending.timeout, trace.as_deref(), frame);
+        if let Err(e) = sent {
+            // недописанный кадр сбил поток: соединение больше не годится
+            writer.shutdown();
+            return Err(e);
+        }
+        Ok(pending)
+    }
+
+    /// Закрывает соединение; ожидающие и следующие вызовы получают
+    /// `CacheError::Closed`.
+    pub fn close(&self) {
+        lock(&self.waiters).broken = Some(CacheError::Closed);
+        self.writer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .shutdown();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(lock(&self.waiters).broken, Some(CacheError::Closed))
+    }
+
+    /// Версия протокола, о которой договорились с сервером.
+    pub fn protocol(&self) -> u32 {
+        self.proto
+    }
+
+    /// Оборвано ли соединение само (сервер закрыл его, сеть, битый кадр), а
+    /// не через `close`.
+    pub fn is_broken(&self) -> bool {
+        matches!(&lock(&self.waiters).broken, Some(e) if !matches!(e, CacheError::Closed))
+    }
+}
+
+impl Drop for MuxConn {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
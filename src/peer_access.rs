@@ -0,0 +1,162 @@
+//! Доступ к Unix-сокету по учётке собеседника, см.
+//! `PersistentCore::set_peer_access`: uid и gid процесса на том конце
+//! берутся у ядра (`SO_PEERCRED` в Linux, `getpeereid` в macOS и BSD) сразу
+//! после accept, и чужое соединение закрывается, не прочитав ни байта. Gid —
+//! основная группа процесса, дополнительные группы не проверяются. На TCP
+//! политика не действует.
+
+use crate::error::CacheError;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+/// Учётка процесса на том конце сокета.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Что разрешено соединению.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerRole {
+    Full,
+    /// команды записи, Save/BgSave и Admin отклоняются с
+    /// `CacheError::ReadOnly("connection")`
+    ReadOnly,
+}
+
+/// Списки uid и gid. Пустая политика (по умолчанию) пускает всех; иначе
+/// полный доступ — по `allow_*`, только чтение — по `readonly_*`, остальные
+/// отклоняются. Uid самого сервера пускается всегда: ему и так открыты
+/// файлы кэша, а `spawn_server` проверяет через сокет, что сервер поднялся.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerAccess {
+    pub allow_uids: Vec<u32>,
+    pub allow_gids: Vec<u32>,
+    pub readonly_uids: Vec<u32>,
+    pub readonly_gids: Vec<u32>,
+}
+
+impl PeerAccess {
+    /// Пускает всех: учётки не проверяются и не запрашиваются.
+    pub fn is_open(&self) -> bool {
+        self.allow_uids.is_empty()
+            && self.allow_gids.is_empty()
+            && self.readonly_uids.is_empty()
+            && self.readonly_gids.is_empty()
+    }
+
+    /// Роль собеседника `cred` у сервера с uid `own_uid`; None — отказ.
+    pub fn role(&self, cred: PeerCred, own_uid: u32) -> Option<PeerRole> {
+        if self.is_open()
+            || cred.uid == own_uid
+            || self.allow_uids.contains(&cred.uid)
+            || self.allow_gids.contains(&cred.gid)
+        {
+            return Some(PeerRole::Full);
+        }
+        if self.readonly_uids.contains(&cred.uid) || self.readonly_gids.contains(&cred.gid) {
+            return Some(PeerRole::ReadOnly);
+        }
+        None
+    }
+}
+
+/// Умеет ли платформа узнавать учётку собеседника.
+pub(crate) const SUPPORTED: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+));
+
+pub(crate) fn unsupported() -> CacheError {
+    CacheError::Unsupported(format!(
+        "peer credentials of Unix sockets are not available on {}",
+        std::env::consts::OS
+    ))
+}
+
+/// Uid, под которым работает сервер.
+#[cfg(unix)]
+pub(crate) fn own_uid() -> u32 {
+    // SAFETY: geteuid не трогает память и не может завершиться ошибкой
+    unsafe { libc::geteuid() }
+}
+
+/// Учётка процесса на том конце `stream`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn peer_cred(stream: &UnixStream) -> Result<PeerCred, CacheError> {
+    use std::os::unix::io::AsRawFd;
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred и len живут до конца вызова, len — размер cred
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut cred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(CacheError::Network(format!(
+            "SO_PEERCRED: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(PeerCred {
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+/// Учётка процесса на том конце `stream`.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+pub fn peer_cred(stream: &UnixStream) -> Result<PeerCred, CacheError> {
+    use std::os::unix::io::AsRawFd;
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: uid и gid живут до конца вызова
+    let rc = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+    if rc != 0 {
+        return Err(CacheError::Network(format!(
+            "getpeereid: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(PeerCred { uid, gid })
+}
+
+/// Учётка процесса на том конце `stream`: на этой платформе недоступна.
+#[cfg(all(
+    unix,
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))
+))]
+pub fn peer_cred(_stream: &UnixStream) -> Result<PeerCred, CacheError> {
+    Err(unsupported())
+}
@@ -0,0 +1,408 @@
+use crate::acl::Credentials;
+use crate::error::CacheError;
+use crate::{
+    current_trace_id, encode_frame, request_frame, request_tagged, CacheCommand, CacheResponse,
+    Conn, TransportAddr, PROTOCOL_VERSION,
+};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Сколько простаивающих соединений держать; лишние закрываются.
+const MAX_IDLE: usize = 8;
+
+/// Соединение, простоявшее в пуле дольше, перед выдачей проверяется: не
+/// закрыл ли его сервер (перезапуск, `idle_timeout_secs`).
+const CHECK_AFTER: Duration = Duration::from_secs(1);
+
+/// Таймауты соединений пула; `None` — ждать без ограничения.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Timeouts {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+    pub write: Option<Duration>,
+}
+
+// соединение и версия протокола, о которой на нём договорились
+struct PoolConn {
+    conn: Conn,
+    proto: u32,
+    idle_since: Instant,
+}
+
+impl PoolConn {
+    fn new(conn: Conn, proto: u32) -> Self {
+        PoolConn {
+            conn,
+            proto,
+            idle_since: Instant::now(),
+        }
+    }
+}
+
+struct Idle {
+    // процесс, которому принадлежат соединения: после fork копии сокетов
+    // в дочернем процессе использовать нельзя, ответы перемешаются
+    pid: u32,
+    conns: Vec<PoolConn>,
+}
+
+/// Пул постоянных соединений клиента с одним сервером. Команда берёт
+/// свободное соединение или открывает новое и возвращает его после ответа,
+/// так что параллельные вызовы из разных потоков не ждут друг друга.
+///
+/// Сетевая ошибка закрывает соединение. Простоявшее соединение перед выдачей
+/// проверяется (`CHECK_AFTER`), закрытое сервером заменяется новым. Если
+/// переиспользованное соединение упало уже во время команды, она
+/// повторяется один раз на свежем — кроме `Pop`, `Publish`, `Import`,
+/// `BgSave`, `GetOrSet`, `SetNx`, `DelIfEquals`, `PExtendIfEquals`, `Update`,
+/// `IncrRange` и `SetBit`, которые нельзя безопасно выполнить дважды (у
+/// `GetOrSet`, `SetNx`, `DelIfEquals` и `SetBit` повтор исказил бы ответ,
+/// `Update`, `IncrRange` и `PExtendIfEquals` применились бы второй раз).
+///
+/// Каждое новое соединение начинается с `Hello`, а с `with_auth` — ещё и с
+/// `Auth`, см. `handshake`. По
+/// соединению протокола 2 пул всё равно шлёт по одной команде за раз, с
+/// возрастающими id; параллельные запросы по одному соединению — `MuxConn`.
+pub(crate) struct Pool {
+    addr: TransportAddr,
+    timeouts: Timeouts,
+    idle: Mutex<Idle>,
+    closed: AtomicBool,
+    // версия протокола, о которой договорились с сервером; 0 — ещё не знаем
+    protocol: AtomicU32,
+    next_id: AtomicU64,
+    // соединения, открытые взамен оборванных сервером
+    reconnects: AtomicU64,
+    // соединения, выброшенные проверкой при выдаче из пула
+    validation_failures: AtomicU64,
+    // пользователь ACL, под которым входит каждое новое соединение
+    auth: Option<Credentials>,
+}
+
+/// Счётчики пула для `TinyCache.pool_stats()`.
+#[cfg(feature = "python")]
+pub(crate) struct PoolStats {
+    // простаивающие соединения сейчас
+    pub idle: usize,
+    pub reconnects: u64,
+    pub validation_failures: u64,
+}
+
+// повтор на свежем соединении не меняет результат
+fn retryable(cmd: &CacheCommand) -> bool {
+    !matches!(
+        cmd,
+        CacheCommand::Pop(_)
+            | CacheCommand::Publish(..)
+            | CacheCommand::Import(..)
+            | CacheCommand::BgSave
+            | CacheCommand::GetOrSet(..)
+            | CacheCommand::Update(..)
+            | CacheCommand::SetBit(..)
+            | CacheCommand::Undelete(_)
+            | CacheCommand::Swap(..)
+            | CacheCommand::DelIfEquals(..)
+            | CacheCommand::SetNx(..)
+            | CacheCommand::PExtendIfEquals(..)
+            | CacheCommand::IncrRange(..)
+    )
+}
+
+impl Pool {
+    pub fn new(addr: TransportAddr, timeouts: Timeouts) -> Self {
+        Self {
+            addr,
+            timeouts,
+            idle: Mutex::new(Idle {
+                pid: std::process::id(),
+                conns: Vec::new(),
+            }),
+            closed: AtomicBool::new(false),
+            protocol: AtomicU32::new(0),
+            next_id: AtomicU64::new(1),
+            reconnects: AtomicU64::new(0),
+            validation_failures: AtomicU64::new(0),
+            auth: None,
+        }
+    }
+
+    /// Соединения входят пользователем ACL `auth`, см. acl.rs.
+    pub(crate) fn with_auth(mut self, auth: Option<Credentials>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub(crate) fn auth(&self) -> Option<&Credentials> {
+        self.auth.as_ref()
+    }
+
+    pub fn addr(&self) -> &TransportAddr {
+        &self.addr
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Idle> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        let pid = std::process::id();
+        if idle.pid != pid {
+            // закрытие копии сокета в дочернем процессе соединение родителя не трогает
+            idle.conns.clear();
+            idle.pid = pid;
+        }
+        idle
+    }
+
+    fn open(&self) -> Result<PoolConn, CacheError> {
+        self.handshake(self.connect()?)
+    }
+
+    fn connect(&self) -> Result<Conn, CacheError> {
+        let conn = Conn::connect_timeout(&self.addr, self.timeouts.connect)?;
+        conn.set_nodelay()?;
+        conn.set_read_timeout(self.timeouts.read)?;
+        conn.set_write_timeout(self.timeouts.write)?;
+        Ok(conn)
+    }
+
+    fn handshake(&self, conn: Conn) -> Result<PoolConn, CacheError> {
+        let mut pc = self.hello(conn)?;
+        let Some(auth) = &self.auth else {
+            return Ok(pc);
+        };
+        if pc.proto == 1 {
+            auth.login(&mut pc.conn)?;
+            return Ok(pc);
+        }
+        let frame = encode_frame(&auth.command())?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        match request_tagged(&mut pc.conn, id, self.timeouts.read, None, &frame)? {
+            CacheResponse::Ok => Ok(pc),
+            resp => Err(CacheError::Internal(format!(
+                "Unexpected response from auth: {:?}",
+                resp
+            ))),
+        }
+    }
+
+    // Hello с версией клиента. Сервер версии 1 такой команды не знает и
+    // закрывает соединение: тогда открываем новое, и дальше пул работает без
+    // Hello. Ответ не Int (например, Error) — тоже версия 1.
+    fn hello(&self, mut conn: Conn) -> Result<PoolConn, CacheError> {
+        let v1 = |conn| {
+            self.protocol.store(1, Ordering::Relaxed);
+            Ok(PoolConn::new(conn, 1))
+        };
+        if self.protocol.load(Ordering::Relaxed) == 1 {
+            return v1(conn);
+        }
+        let hello = encode_frame(&CacheCommand::Hello(PROTOCOL_VERSION))?;
+        match request_frame(&mut conn, &hello) {
+            Ok(CacheResponse::Int(v)) if v >= 2 => {
+                let proto = v.min(PROTOCOL_VERSION as i64) as u32;
+                self.protocol.store(proto, Ordering::Relaxed);
+                Ok(PoolConn::new(conn, proto))
+            }
+            Ok(_) => v1(conn),
+            // версию 1 запоминаем, только если сервер вообще отвечает
+            Err(CacheError::Network(_)) | Err(CacheError::Serialization(_)) => v1(self.connect()?),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Открывает соединение заранее, чтобы ошибка адреса всплыла сразу.
+    pub fn warm_up(&self) -> Result<(), CacheError> {
+        let conn = self.open()?;
+        self.put(conn);
+        Ok(())
+    }
+
+    /// Версия протокола сервера; пока она не известна, открывает соединение.
+    pub fn protocol(&self) -> Result<u32, CacheError> {
+        match self.protocol.load(Ordering::Relaxed) {
+            0 => {
+                self.warm_up()?;
+                Ok(self.protocol.load(Ordering::Relaxed))
+            }
+            proto => Ok(proto),
+        }
+    }
+
+    /// Ждёт, пока сервер ответит на Ping, повторяя попытки с растущей паузой.
+    /// Удачное соединение остаётся в пуле; при таймауте — последняя ошибка.
+    #[cfg(feature = "python")]
+    pub fn wait_ready(&self, timeout: Duration) -> Result<(), CacheError> {
+        let deadline = Instant::now() + timeout;
+        let mut delay = Duration::from_millis(10);
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let err = match self.ping_within(left) {
+                Ok(()) => return Ok(()),
+                // сервер поднят, но не пускает (Auth): ждать нечего
+                Err(e @ CacheError::Server(_)) => return Err(e),
+                Err(e) => e,
+            };
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(err);
+            }
+            std::thread::sleep(delay.min(left));
+            delay = (delay * 2).min(Duration::from_millis(200));
+        }
+    }
+
+    // одна попытка, целиком уложенная в `limit`
+    #[cfg(feature = "python")]
+    fn ping_within(&self, limit: Duration) -> Result<(), CacheError> {
+        // нулевой таймаут сокеты не принимают
+        let limit = limit.max(Duration::from_millis(1));
+        let connect = self.timeouts.connect.map_or(limit, |t| t.min(limit));
+        let mut conn = Conn::connect_timeout(&self.addr, Some(connect))?;
+        conn.set_nodelay()?;
+        conn.set_read_timeout(Some(limit))?;
+        conn.set_write_timeout(Some(limit))?;
+        match request_frame(&mut conn, &encode_frame(&CacheCommand::Ping)?)? {
+            CacheResponse::Ok => {}
+            resp => {
+                return Err(CacheError::Internal(format!(
+                    "Unexpected response from ping: {:?}",
+                    resp
+                )))
+            }
+        }
+        conn.set_read_timeout(self.timeouts.read)?;
+        conn.set_write_timeout(self.timeouts.write)?;
+        let conn = self.handshake(conn)?;
+        self.put(conn);
+        Ok(())
+    }
+
+    // Закрытое сервером соединение выбрасывается здесь, а не после неудачной
+    // попытки: непереповторяемую команду (pop, update) иначе пришлось бы
+    // вернуть с ошибкой. Второе значение — выброшено ли хоть одно.
+    fn take(&self) -> (Option<PoolConn>, bool) {
+        let mut stale = false;
+        loop {
+            let Some(mut pc) = self.lock().conns.pop() else {
+                return (None, stale);
+            };
+            if pc.idle_since.elapsed() < CHECK_AFTER || !pc.conn.peer_closed() {
+                return (Some(pc), stale);
+            }
+            self.validation_failures.fetch_add(1, Ordering::Relaxed);
+            stale = true;
+        }
+    }
+
+    // новое соединение взамен оборванного
+    fn reopen(&self) -> Result<PoolConn, CacheError> {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+        self.open()
+    }
+
+    #[cfg(feature = "python")]
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            idle: self.lock().conns.len(),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            validation_failures: self.validation_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    fn put(&self, mut conn: PoolConn) {
+        if self.is_closed() {
+            return;
+        }
+        conn.idle_since = Instant::now();
+        let mut idle = self.lock();
+        if idle.conns.len() < MAX_IDLE {
+            idle.conns.push(conn);
+        }
+    }
+
+    /// Закрывает простаивающие соединения; дальше любой вызов — ошибка.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.lock().conns.clear();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn call(&self, cmd: &CacheCommand) -> Result<CacheResponse, CacheError> {
+        self.call_waiting(cmd, Duration::ZERO)
+    }
+
+    /// `wait` — сколько сервер может законно молчать сверх таймаута чтения
+    /// (для блокирующих команд).
+    pub fn call_waiting(
+        &self,
+        cmd: &CacheCommand,
+        wait: Duration,
+    ) -> Result<CacheResponse, CacheError> {
+        let frame = encode_frame(cmd)?;
+        self.send(&frame, retryable(cmd), wait)
+    }
+
+    /// Уже закодированная команда (см. `set_frame`). При обрыве
+    /// переиспользованного соединения повторяется, так что годится только
+    /// для команд, которые можно выполнить дважды.
+    pub fn call_frame(&self, frame: &[u8]) -> Result<CacheResponse, CacheError> {
+        self.send(frame, true, Duration::ZERO)
+    }
+
+    fn send(&self, frame: &[u8], retry: bool, wait: Duration) -> Result<CacheResponse, CacheError> {
+        if self.is_closed() {
+            return Err(CacheError::Closed);
+        }
+        match self.take() {
+            (Some(conn), _) => match self.attempt(conn, frame, wait) {
+                Err(CacheError::Network(_)) if retry => self.attempt(self.reopen()?, frame, wait),
+                res => res,
+            },
+            (None, true) => self.attempt(self.reopen()?, frame, wait),
+            (None, false) => self.attempt(self.open()?, frame, wait),
+        }
+    }
+
+    fn attempt(
+        &self,
+        mut pc: PoolConn,
+        frame: &[u8],
+        wait: Duration,
+    ) -> Result<CacheResponse, CacheError> {
+        let read = self.timeouts.read;
+        let extended = !wait.is_zero() && read.is_some();
+        if extended {
+            pc.conn.set_read_timeout(read.map(|t| t + wait))?;
+        }
+        let res = if pc.proto >= 2 {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            // сервер бросит работу, ответ на которую уже никто не прочтёт
+            let trace = (pc.proto >= 4).then(current_trace_id).flatten();
+            request_tagged(
+                &mut pc.conn,
+                id,
+                read.map(|t| t + wait),
+                trace.as_deref(),
+                frame,
+            )
+        } else {
+            request_frame(&mut pc.conn, frame)
+        };
+        match &res {
+            // после сетевой ошибки или битого кадра поток рассинхронизирован
+            Err(CacheError::Network(_)) | Err(CacheError::Serialization(_)) => {}
+            _ => {
+                if !extended || pc.conn.set_read_timeout(read).is_ok() {
+                    self.put(pc);
+                }
+            }
+        }
+        res
+    }
+}
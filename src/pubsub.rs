@@ -0,0 +1,157 @@
+use crate::error::CacheError;
+use crate::{write_all, write_frame, CacheResponse, ResponseValue};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Pub/sub: соединение с `Subscribe(channels)` переходит в режим подписки и
+/// дальше только получает кадры `CacheResponse::Message(channel, payload)`.
+/// Сообщения нигде не хранятся: кто не подписан в момент `Publish`, его не
+/// увидит. У каждого подписчика своя ограниченная очередь; если она полна,
+/// сообщение для него выбрасывается (и считается в `pubsub_dropped`), чтобы
+/// медленный подписчик не тормозил публикующих.
+const SUBSCRIBER_QUEUE: usize = 1024;
+// в тишине сервер шлёт `CacheResponse::Ok`, чтобы обе стороны замечали обрыв
+pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+// готовый кадр `CacheResponse::Message`: кодируется один раз на публикацию
+type Frame = Arc<Vec<u8>>;
+
+struct Subscriber {
+    id: u64,
+    tx: SyncSender<Frame>,
+}
+
+#[derive(Default)]
+pub struct PubSub {
+    channels: Mutex<HashMap<String, Vec<Subscriber>>>,
+    next_id: AtomicU64,
+    // соединений в режиме подписки
+    subscribers: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Пока жива, подписка числится в реестре; Drop убирает её из всех каналов.
+struct Registration<'a> {
+    pubsub: &'a PubSub,
+    id: u64,
+    channels: Vec<String>,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        let mut map = self
+            .pubsub
+            .channels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        for ch in &self.channels {
+            if let Some(subs) = map.get_mut(ch) {
+                subs.retain(|s| s.id != self.id);
+                if subs.is_empty() {
+                    map.remove(ch);
+                }
+            }
+        }
+        self.pubsub.subscribers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl PubSub {
+    /// Возвращает, скольким подписчикам сообщение поставлено в очередь.
+    pub fn publish(&self, channel: String, payload: Vec<u8>) -> Result<i64, CacheError> {
+        let map = self.channels.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(subs) = map.get(&channel) else {
+            return Ok(0);
+        };
+        let frame: Frame = Arc::new(
+            bincode::serialize(&CacheResponse::Message(channel.clone(), payload))
+                .map_err(|e| CacheError::Serialization(e.to_string()))?,
+        );
+        let mut received = 0;
+        for sub in subs {
+            match sub.tx.try_send(frame.clone()) {
+                Ok(()) => received += 1,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                // соединение уже закрывается, Registration вот-вот его уберёт
+                Err(TrySendError::Disconnected(_)) => {}
+            }
+        }
+        Ok(received)
+    }
+
+    fn subscribe(&self, channels: Vec<String>) -> (Registration<'_>, Receiver<Frame>) {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut map = self.channels.lock().unwrap_or_else(|e| e.into_inner());
+        for ch in &channels {
+            let subs = map.entry(ch.clone()).or_default();
+            // повтор канала в списке не должен удваивать доставку
+            if !subs.iter().any(|s| s.id == id) {
+                subs.push(Subscriber { id, tx: tx.clone() });
+            }
+        }
+        self.subscribers.fetch_add(1, Ordering::Relaxed);
+        let reg = Registration {
+            pubsub: self,
+            id,
+            channels,
+        };
+        (reg, rx)
+    }
+
+    pub fn info_fields(&self) -> Vec<(&'static str, ResponseValue)> {
+        let channels = self
+            .channels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len();
+        vec![
+            ("pubsub_channels", channels.into()),
+            (
+                "pubsub_subscribers",
+                self.subscribers.load(Ordering::Relaxed).into(),
+            ),
+            (
+                "pubsub_dropped",
+                self.dropped.load(Ordering::Relaxed).into(),
+            ),
+        ]
+    }
+}
+
+/// Сторона сервера: держит соединение подписчика до обрыва.
+pub fn serve_subscriber<S: Write>(
+    stream: &mut S,
+    pubsub: &PubSub,
+    channels: Vec<String>,
+) -> Result<(), CacheError> {
+    if channels.is_empty() {
+        return write_frame(
+            stream,
+            &CacheResponse::Error("subscribe needs at least one channel".into()),
+        );
+    }
+    let (_reg, rx) = pubsub.subscribe(channels);
+    // подтверждение: после него публикации уже доходят
+    write_frame(stream, &CacheResponse::Ok)?;
+    loop {
+        let sent = match rx.recv_timeout(HEARTBEAT_INTERVAL) {
+            Ok(frame) => write_all(stream, &(frame.len() as u32).to_le_bytes())
+                .and_then(|_| write_all(stream, &frame)),
+            Err(RecvTimeoutError::Timeout) => write_frame(stream, &CacheResponse::Ok),
+            // отправители живут в реестре, пока жива Registration
+            Err(RecvTimeoutError::Disconnected) => unreachable!("subscriber channel closed"),
+        };
+        match sent {
+            // подписчик закрыл соединение — обычное завершение подписки
+            Err(CacheError::Network(_)) => return Ok(()),
+            other => other?,
+        }
+    }
+}
@@ -0,0 +1,3208 @@
+//! Python-биндинги: классы и функции модуля `tiny_mp_cache`.
+
+mod bloom;
+mod cluster;
+mod hooks;
+mod ids;
+mod loader;
+mod local;
+mod lock;
+mod meta;
+mod near;
+mod on_write;
+mod pubsub;
+mod scan;
+mod spawn;
+mod trace;
+mod watch;
+
+use crate::acl::{AclUser, Credentials};
+use crate::chaos::ChaosOptions;
+use crate::crypto::WalKey;
+use crate::dump::DumpFormat;
+use crate::encoding::{Compress, ENC_MAGIC};
+use crate::error::CacheError;
+use crate::pool::{Pool, Timeouts};
+#[cfg(unix)]
+use crate::serve_unix_socket;
+use crate::wal::FsyncPolicy;
+use crate::{blocking, dump, wal};
+use crate::{
+    send_cmd_sync, serve_tcp, CacheCommand, CacheResponse, PersistOptions, PersistentCore,
+    TransportAddr, MAX_COMMAND_SIZE,
+};
+use crate::{
+    set_ex_frame, set_frame, set_tagged_frame, AggOp, ClientInfo, KeyNormalization, Loader,
+    PeerAccess, ResponseValue, UpdateOp, ValueEncoding, VerifyReport, WriteFeed,
+    DEFAULT_COMPRESS_MIN, DEFAULT_STATS_PREFIXES, OBJ_MAGIC,
+};
+
+use pyo3::create_exception;
+use pyo3::exceptions::{
+    PyBufferError, PyConnectionError, PyException, PyKeyError, PyRuntimeError, PyTypeError,
+    PyValueError,
+};
+use pyo3::prelude::*;
+use pyo3::types::{
+    IntoPyDict, PyByteArray, PyBytes, PyDict, PyList, PyMemoryView, PySlice, PyTuple,
+};
+use std::ffi::{c_char, c_int};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// =======================
+/// Маппинг ошибок в Python
+/// =======================
+fn map_error(e: CacheError, ctx: &str) -> PyErr {
+    let msg = format!("{}: {}", ctx, e);
+    match e {
+        CacheError::InvalidKey(_) => InvalidKeyError::new_err(msg),
+        CacheError::Checksum(_) => ChecksumError::new_err(msg),
+        CacheError::ResponseTooLarge(..) => ResponseTooLargeError::new_err(msg),
+        _ => TinyCacheError::new_err(msg),
+    }
+}
+
+// подкласс RuntimeError: старый код с `except RuntimeError` продолжает работать
+create_exception!(
+    tiny_mp_cache,
+    TinyCacheError,
+    PyRuntimeError,
+    "Ошибка клиента или сервера tiny_mp_cache."
+);
+create_exception!(
+    tiny_mp_cache,
+    BindError,
+    TinyCacheError,
+    "serve()/serve_unix() не открыли listener: атрибуты addr, errno и reason \
+     (\"address_in_use\", \"permission_denied\", \"invalid_address\", \"other\")."
+);
+create_exception!(
+    tiny_mp_cache,
+    InvalidKeyError,
+    TinyCacheError,
+    "Ключ отклонён политикой key_normalization сервера: NUL или управляющий символ."
+);
+create_exception!(
+    tiny_mp_cache,
+    ChecksumError,
+    TinyCacheError,
+    "Значение ключа не сошлось со своим crc32 на сервере с verify_values=True."
+);
+create_exception!(
+    tiny_mp_cache,
+    ResponseTooLargeError,
+    TinyCacheError,
+    "Ответ сервера больше его max_response_size: сузьте запрос (префикс, count)."
+);
+
+create_exception!(
+    tiny_mp_cache,
+    PartialWriteError,
+    TinyCacheError,
+    "update(items) записал не все пакеты: атрибуты batches_written, keys_written \
+     и remaining (dict пар, записанных не наверняка); исходная ошибка — в __cause__."
+);
+
+create_exception!(
+    tiny_mp_cache,
+    WalCorruptError,
+    TinyCacheError,
+    "verify_wal(raise_on_corrupt=True) нашёл журнал, с которым сервер не поднимется: \
+     отчёт — в атрибуте report."
+);
+
+create_exception!(
+    tiny_mp_cache,
+    LockNotOwnedError,
+    TinyCacheError,
+    "release()/extend() блокировки TinyCacheLock, которую этот объект не держит: \
+     не взята или аренда истекла."
+);
+
+// ошибка bind — BindError с разобранной причиной, остальное — как раньше
+fn serve_error(py: Python<'_>, e: CacheError) -> PyErr {
+    let CacheError::Bind(failure) = e else {
+        return PyRuntimeError::new_err(e.to_string());
+    };
+    let err = BindError::new_err(failure.to_string());
+    let value = err.value_bound(py);
+    let res = value
+        .setattr("addr", &failure.addr)
+        .and_then(|_| value.setattr("errno", failure.errno))
+        .and_then(|_| value.setattr("reason", failure.reason.as_str()));
+    match res {
+        Ok(()) => err,
+        Err(e) => e,
+    }
+}
+
+/// Имя файлов кэша по умолчанию: `tiny-mp-cache.wal`, `tiny-mp-cache.snapshot`
+/// и `tiny-mp-cache.lock`.
+pub(crate) const DEFAULT_NAME: &str = "tiny-mp-cache";
+
+/// =======================
+/// Резолвинг директории журналирования
+/// =======================
+/// Пути WAL и снапшота кэша `name` в `wal_dir` (без него — в текущем
+/// каталоге); файл блокировки — `wal::lock_path` от пути WAL. С `create`
+/// каталог создаётся, если его нет.
+pub(crate) fn resolve_cache_paths(
+    wal_dir: Option<&str>,
+    name: &str,
+    create: bool,
+) -> PyResult<(PathBuf, PathBuf)> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(PyValueError::new_err(format!(
+            "name must be a plain file name, got {:?}",
+            name
+        )));
+    }
+    let dir = match wal_dir {
+        Some(dir) => PathBuf::from(dir),
+        // как раньше: просто кладём в текущую директорию
+        None => std::env::current_dir()
+            .map_err(|e| PyRuntimeError::new_err(format!("current_dir error: {}", e)))?,
+    };
+    if create && !dir.exists() {
+        fs::create_dir_all(&dir)
+            .map_err(|e| PyRuntimeError::new_err(format!("create wal_dir error: {}", e)))?;
+    }
+    // в Info и ServerHandle пути видны полностью
+    let dir = std::path::absolute(&dir)
+        .map_err(|e| PyRuntimeError::new_err(format!("resolve wal_dir error: {}", e)))?;
+    Ok((
+        dir.join(format!("{}.wal", name)),
+        dir.join(format!("{}.snapshot", name)),
+    ))
+}
+
+/// Общая для serve/serve_unix инициализация ядра из аргументов Python.
+/// Ключ из аргумента, иначе из `TINY_MP_CACHE_WAL_KEY`.
+fn resolve_wal_key(wal_key: Option<&[u8]>) -> PyResult<Option<WalKey>> {
+    match wal_key {
+        Some(bytes) => Some(WalKey::from_bytes(bytes)),
+        None => WalKey::from_env().transpose(),
+    }
+    .transpose()
+    .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Аргументы serve/serve_unix, общие для обоих транспортов.
+struct ServeArgs<'a> {
+    wal_dir: Option<String>,
+    // имя файлов кэша в wal_dir, см. DEFAULT_NAME
+    name: &'a str,
+    compact_after: Option<u64>,
+    fsync: &'a str,
+    wal_segment_size: Option<u64>,
+    persistence: bool,
+    wal_key: Option<&'a [u8]>,
+    replicate_from: Option<String>,
+    read_only: bool,
+    admin_token: Option<String>,
+    max_bit_offset: Option<u64>,
+    // больший ответ — ошибка ResponseTooLarge, см. set_max_response_size
+    max_response_size: Option<usize>,
+    // None или 0 — журнал медленных команд выключен, см. set_slowlog
+    slowlog_ms: Option<u64>,
+    // None или 0 — простаивающие соединения не закрываются
+    idle_timeout_secs: Option<u64>,
+    // None или 0 — удалённые ключи не уходят в надгробия
+    tombstone_ttl_secs: Option<u64>,
+    // None или 0 — одинаковые значения хранятся копиями
+    dedup_min_size: Option<usize>,
+    // 0 — прежние значения не помнятся; правила по префиксам — SetHistoryDepth
+    history_depth: u32,
+    // 0 — попадания считаются только всего, см. set_hit_stats_prefix
+    stats_prefix_depth: u32,
+    stats_prefix_sep: &'a str,
+    stats_max_prefixes: usize,
+    // команд в секунду; None или 0 — без лимита, у UDS только на соединение
+    rate_limit_per_conn: Option<u32>,
+    rate_limit_per_ip: Option<u32>,
+    // None — ключи как есть, "none" — только проверка символов, "nfc" — и NFC
+    key_normalization: Option<&'a str>,
+    // записывается в заголовок WAL, см. PersistOptions::case_insensitive_keys
+    case_insensitive_keys: bool,
+    // None — по числу ядер на большом журнале, см. PersistOptions::replay_threads
+    replay_threads: Option<usize>,
+    // crc32 у каждого значения, см. PersistentCore::set_verify_values
+    verify_values: bool,
+    // промахи Get идут к нему, см. PersistentCore::set_loader
+    loader: Option<Loader>,
+    loader_threads: usize,
+    // мутации для serve(on_write=...), см. PersistentCore::set_write_feed
+    write_feed: Option<Arc<WriteFeed>>,
+    // uid и gid, которых пускает serve_unix, см. PersistentCore::set_peer_access
+    peer_access: PeerAccess,
+    hooks: hooks::Hooks,
+    // пользователи с правами по префиксам, см. PersistentCore::set_acl_file
+    acl_file: Option<String>,
+    // задержки, ошибки и обрывы для тестов клиентов, см. PersistentCore::set_chaos
+    chaos: Option<ChaosOptions>,
+}
+
+fn open_core(args: ServeArgs<'_>) -> PyResult<Arc<PersistentCore>> {
+    let mut core = match (args.persistence, args.read_only) {
+        (true, true) => open_read_only_core(args.wal_dir.as_deref(), args.name, args.wal_key)?,
+        (true, false) => open_persistent_core(&args)?,
+        (false, _) => PersistentCore::ephemeral(),
+    };
+    if args.read_only {
+        core.set_read_only(true)
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    }
+    // токен из аргумента, иначе из `TINY_MP_CACHE_ADMIN_TOKEN`
+    if let Some(token) = args
+        .admin_token
+        .or_else(|| std::env::var("TINY_MP_CACHE_ADMIN_TOKEN").ok())
+    {
+        if token.is_empty() {
+            return Err(PyValueError::new_err("admin_token must not be empty"));
+        }
+        core.set_admin_token(token);
+    }
+    if let Some(path) = args.acl_file {
+        core.set_acl_file(path)
+            .map_err(|e| PyValueError::new_err(format!("acl_file: {}", e)))?;
+    }
+    if let Some(max) = args.max_bit_offset {
+        core.set_max_bit_offset(max);
+    }
+    if let Some(chaos) = args.chaos {
+        eprintln!("tiny-mp-cache: chaos mode is on: {:?}", chaos);
+        core.set_chaos(Some(chaos))
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    }
+    core.set_peer_access(args.peer_access)
+        .map_err(|e| map_error(e, "serve_unix"))?;
+    match args.max_response_size {
+        Some(0) => return Err(PyValueError::new_err("max_response_size must be positive")),
+        Some(limit) => core.set_max_response_size(limit),
+        None => {}
+    }
+    core.set_slowlog(
+        args.slowlog_ms
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis),
+    );
+    core.set_idle_timeout(args.idle_timeout_secs.map(Duration::from_secs));
+    core.set_rate_limits(args.rate_limit_per_conn, args.rate_limit_per_ip);
+    core.set_tombstone_ttl(args.tombstone_ttl_secs.map(Duration::from_secs));
+    core.set_dedup_min_size(args.dedup_min_size);
+    core.set_verify_values(args.verify_values);
+    let policy = args
+        .key_normalization
+        .map(str::parse::<KeyNormalization>)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    core.set_key_normalization(policy)
+        .and_then(|()| core.set_case_insensitive_keys(args.case_insensitive_keys))
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    if args.history_depth > 0 {
+        core.set_history_depth(String::new(), args.history_depth)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    }
+    core.set_hit_stats_prefix(
+        args.stats_prefix_depth,
+        args.stats_prefix_sep,
+        args.stats_max_prefixes,
+    )
+    .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    if let Some(hook) = args.hooks.into_event_hook()? {
+        core.set_event_hook(hook);
+    }
+    if let Some(feed) = args.write_feed {
+        core.set_write_feed(feed);
+    }
+    if let Some(loader) = args.loader {
+        if args.replicate_from.is_some() {
+            return Err(PyValueError::new_err(
+                "loader cannot be combined with replicate_from: a replica does not write keys",
+            ));
+        }
+        core.set_loader(loader, args.loader_threads)
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    }
+    let Some(primary) = args.replicate_from else {
+        return Ok(Arc::new(core));
+    };
+    core.set_replica_of(primary.clone());
+    let core = Arc::new(core);
+    crate::spawn_replica(Arc::clone(&core), primary)
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    Ok(core)
+}
+
+// serve(chaos={...}): latency_ms — мс или (min, max), error_rate,
+// disconnect_rate и seed
+fn chaos_options(d: &Bound<'_, PyDict>) -> PyResult<ChaosOptions> {
+    let mut opts = ChaosOptions::default();
+    for (name, value) in d.iter() {
+        match name.extract::<String>()?.as_str() {
+            "latency_ms" => {
+                opts.latency_ms = match value.extract::<u64>() {
+                    Ok(ms) => Some((ms, ms)),
+                    Err(_) => Some(value.extract::<(u64, u64)>().map_err(|_| {
+                        PyValueError::new_err("chaos latency_ms expects ms or (min_ms, max_ms)")
+                    })?),
+                }
+            }
+            "error_rate" => opts.error_rate = value.extract()?,
+            "disconnect_rate" => opts.disconnect_rate = value.extract()?,
+            "seed" => opts.seed = value.extract()?,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown chaos setting {:?}, expected latency_ms, error_rate, disconnect_rate or seed",
+                    other
+                )))
+            }
+        }
+    }
+    Ok(opts)
+}
+
+fn open_persistent_core(args: &ServeArgs<'_>) -> PyResult<PersistentCore> {
+    let fsync: FsyncPolicy = args
+        .fsync
+        .parse()
+        .map_err(|e: CacheError| PyValueError::new_err(e.to_string()))?;
+    let (wal_path, snapshot_path) = resolve_cache_paths(args.wal_dir.as_deref(), args.name, true)?;
+    let key = resolve_wal_key(args.wal_key)?;
+    let opts = PersistOptions {
+        compact_after: args.compact_after,
+        fsync,
+        segment_size: args.wal_segment_size,
+        key: key.map(Arc::new),
+        case_insensitive_keys: args.case_insensitive_keys,
+        // ход replay и так идёт в stderr, где его читает spawn_server
+        on_replay: None,
+        replay_threads: args.replay_threads,
+    };
+    PersistentCore::new(wal_path, snapshot_path, opts)
+        .map_err(|e| PyRuntimeError::new_err(format!("init persistent core: {}", e)))
+}
+
+/// Без записи на диск: каталог не создаётся, WAL не открывается на запись.
+fn open_read_only_core(
+    wal_dir: Option<&str>,
+    name: &str,
+    wal_key: Option<&[u8]>,
+) -> PyResult<PersistentCore> {
+    let (wal_path, snapshot_path) = resolve_cache_paths(wal_dir, name, false)?;
+    let key = resolve_wal_key(wal_key)?;
+    PersistentCore::open_read_only(&wal_path, snapshot_path, key.as_ref())
+        .map_err(|e| PyRuntimeError::new_err(format!("init read-only core: {}", e)))
+}
+
+/// Прогрев перед bind: сначала дамп `preload_file` (путь относительно
+/// `wal_dir`, как у import_dump), затем `preload(TinyCacheLocal)` над тем же
+/// ядром. Исключение из callable прерывает запуск как есть, с traceback.
+fn run_preload(
+    py: Python<'_>,
+    core: &Arc<PersistentCore>,
+    preload: Option<PyObject>,
+    preload_file: Option<String>,
+) -> PyResult<()> {
+    if preload.is_none() && preload_file.is_none() {
+        return Ok(());
+    }
+    let res = core.preload(|| -> PyResult<()> {
+        if let Some(path) = preload_file {
+            py.allow_threads(|| core.import(&path, true))
+                .map_err(|e| map_error(e, "preload_file"))?;
+        }
+        if let Some(preload) = preload {
+            let local = Py::new(py, local::TinyCacheLocal::from_core(Arc::clone(core)))?;
+            preload.call1(py, (local,))?;
+        }
+        Ok(())
+    });
+    res.map_err(|e| map_error(e, "preload"))?
+}
+
+/// =======================
+/// TCP-сервер
+/// =======================
+
+#[pyfunction(signature = (
+    port,
+    wal_dir=None,
+    compact_after=None,
+    fsync="everysec",
+    wal_segment_size=None,
+    persistence=true,
+    wal_key=None,
+    replicate_from=None,
+    read_only=false,
+    admin_token=None,
+    max_bit_offset=None,
+    max_response_size=None,
+    slowlog_ms=None,
+    idle_timeout_secs=300,
+    tombstone_ttl_secs=None,
+    dedup_min_size=None,
+    history_depth=0,
+    stats_prefix_depth=0,
+    stats_prefix_sep=":",
+    stats_max_prefixes=DEFAULT_STATS_PREFIXES,
+    rate_limit_per_conn=None,
+    rate_limit_per_ip=None,
+    key_normalization=None,
+    case_insensitive_keys=false,
+    replay_threads=None,
+    name="tiny-mp-cache",
+    verify_values=false,
+    loader=None,
+    loader_ttl_secs=None,
+    loader_threads=4,
+    preload=None,
+    preload_file=None,
+    on_ready=None,
+    on_error=None,
+    on_connection=None,
+    on_write=None,
+    on_write_prefix=String::new(),
+    on_write_queue=10000,
+    on_write_overflow="drop",
+    acl_file=None,
+    chaos=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn serve(
+    py: Python<'_>,
+    port: u16,
+    wal_dir: Option<String>,
+    compact_after: Option<u64>,
+    fsync: &str,
+    wal_segment_size: Option<u64>,
+    persistence: bool,
+    wal_key: Option<&[u8]>,
+    replicate_from: Option<String>,
+    read_only: bool,
+    admin_token: Option<String>,
+    max_bit_offset: Option<u64>,
+    max_response_size: Option<usize>,
+    slowlog_ms: Option<u64>,
+    idle_timeout_secs: Option<u64>,
+    tombstone_ttl_secs: Option<u64>,
+    dedup_min_size: Option<usize>,
+    history_depth: u32,
+    stats_prefix_depth: u32,
+    stats_prefix_sep: &str,
+    stats_max_prefixes: usize,
+    rate_limit_per_conn: Option<u32>,
+    rate_limit_per_ip: Option<u32>,
+    key_normalization: Option<&str>,
+    case_insensitive_keys: bool,
+    replay_threads: Option<usize>,
+    name: &str,
+    verify_values: bool,
+    loader: Option<PyObject>,
+    loader_ttl_secs: Option<f64>,
+    loader_threads: usize,
+    preload: Option<PyObject>,
+    preload_file: Option<String>,
+    on_ready: Option<PyObject>,
+    on_error: Option<PyObject>,
+    on_connection: Option<PyObject>,
+    on_write: Option<PyObject>,
+    on_write_prefix: String,
+    on_write_queue: usize,
+    on_write_overflow: &str,
+    acl_file: Option<String>,
+    chaos: Option<&Bound<'_, PyDict>>,
+) -> PyResult<()> {
+    let addr = format!("127.0.0.1:{}", port);
+    println!("🚀 TinyCache TCP server: {}", addr);
+
+    let loader = loader::wrap(py, loader, loader_ttl_secs)?;
+    let hooks = hooks::Hooks::new(py, on_ready, on_error, on_connection)?;
+    let chaos = chaos.map(chaos_options).transpose()?;
+    let on_write = on_write::start(
+        py,
+        on_write,
+        on_write_prefix,
+        on_write_queue,
+        on_write_overflow,
+    )?;
+    let res = open_core(ServeArgs {
+        wal_dir,
+        name,
+        compact_after,
+        fsync,
+        wal_segment_size,
+        persistence,
+        wal_key,
+        replicate_from,
+        read_only,
+        admin_token,
+        max_bit_offset,
+        max_response_size,
+        slowlog_ms,
+        idle_timeout_secs,
+        tombstone_ttl_secs,
+        dedup_min_size,
+        history_depth,
+        stats_prefix_depth,
+        stats_prefix_sep,
+        stats_max_prefixes,
+        rate_limit_per_conn,
+        rate_limit_per_ip,
+        key_normalization,
+        case_insensitive_keys,
+        replay_threads,
+        verify_values,
+        loader,
+        loader_threads,
+        write_feed: on_write.as_ref().map(|h| h.feed()),
+        peer_access: PeerAccess::default(),
+        hooks,
+        acl_file,
+        chaos,
+    })
+    .and_then(|core| run_preload(py, &core, preload, preload_file).map(|()| core))
+    // без GIL: его берёт поток, вызывающий callback'и
+    .and_then(|core| {
+        py.allow_threads(|| serve_tcp(&addr, core))
+            .map_err(|e| serve_error(py, e))
+    });
+    on_write::drain(py, on_write.as_deref());
+    res
+}
+
+/// =======================
+/// UDS-сервер (только Unix)
+/// =======================
+
+#[cfg(unix)]
+#[pyfunction(signature = (
+    path,
+    wal_dir=None,
+    compact_after=None,
+    fsync="everysec",
+    wal_segment_size=None,
+    persistence=true,
+    wal_key=None,
+    replicate_from=None,
+    read_only=false,
+    admin_token=None,
+    max_bit_offset=None,
+    max_response_size=None,
+    slowlog_ms=None,
+    idle_timeout_secs=300,
+    tombstone_ttl_secs=None,
+    dedup_min_size=None,
+    history_depth=0,
+    stats_prefix_depth=0,
+    stats_prefix_sep=":",
+    stats_max_prefixes=DEFAULT_STATS_PREFIXES,
+    rate_limit_per_conn=None,
+    key_normalization=None,
+    case_insensitive_keys=false,
+    replay_threads=None,
+    name="tiny-mp-cache",
+    verify_values=false,
+    loader=None,
+    loader_ttl_secs=None,
+    loader_threads=4,
+    preload=None,
+    preload_file=None,
+    on_ready=None,
+    on_error=None,
+    on_connection=None,
+    on_write=None,
+    on_write_prefix=String::new(),
+    on_write_queue=10000,
+    on_write_overflow="drop",
+    allow_uids=None,
+    allow_gids=None,
+    readonly_uids=None,
+    readonly_gids=None,
+    acl_file=None,
+    chaos=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn serve_unix(
+    py: Python<'_>,
+    path: String,
+    wal_dir: Option<String>,
+    compact_after: Option<u64>,
+    fsync: &str,
+    wal_segment_size: Option<u64>,
+    persistence: bool,
+    wal_key: Option<&[u8]>,
+    replicate_from: Option<String>,
+    read_only: bool,
+    admin_token: Option<String>,
+    max_bit_offset: Option<u64>,
+    max_response_size: Option<usize>,
+    slowlog_ms: Option<u64>,
+    idle_timeout_secs: Option<u64>,
+    tombstone_ttl_secs: Option<u64>,
+    dedup_min_size: Option<usize>,
+    history_depth: u32,
+    stats_prefix_depth: u32,
+    stats_prefix_sep: &str,
+    stats_max_prefixes: usize,
+    rate_limit_per_conn: Option<u32>,
+    key_normalization: Option<&str>,
+    case_insensitive_keys: bool,
+    replay_threads: Option<usize>,
+    name: &str,
+    verify_values: bool,
+    loader: Option<PyObject>,
+    loader_ttl_secs: Option<f64>,
+    loader_threads: usize,
+    preload: Option<PyObject>,
+    preload_file: Option<String>,
+    on_ready: Option<PyObject>,
+    on_error: Option<PyObject>,
+    on_connection: Option<PyObject>,
+    on_write: Option<PyObject>,
+    on_write_prefix: String,
+    on_write_queue: usize,
+    on_write_overflow: &str,
+    allow_uids: Option<Vec<u32>>,
+    allow_gids: Option<Vec<u32>>,
+    readonly_uids: Option<Vec<u32>>,
+    readonly_gids: Option<Vec<u32>>,
+    acl_file: Option<String>,
+    chaos: Option<&Bound<'_, PyDict>>,
+) -> PyResult<()> {
+    let sock_path = PathBuf::from(&path);
+    println!("🚀 TinyCache UDS server: {:?}", sock_path);
+
+    let loader = loader::wrap(py, loader, loader_ttl_secs)?;
+    let hooks = hooks::Hooks::new(py, on_ready, on_error, on_connection)?;
+    let chaos = chaos.map(chaos_options).transpose()?;
+    let on_write = on_write::start(
+        py,
+        on_write,
+        on_write_prefix,
+        on_write_queue,
+        on_write_overflow,
+    )?;
+    let res = open_core(ServeArgs {
+        wal_dir,
+        name,
+        compact_after,
+        fsync,
+        wal_segment_size,
+        persistence,
+        wal_key,
+        replicate_from,
+        read_only,
+        admin_token,
+        max_bit_offset,
+        max_response_size,
+        slowlog_ms,
+        idle_timeout_secs,
+        tombstone_ttl_secs,
+        dedup_min_size,
+        history_depth,
+        stats_prefix_depth,
+        stats_prefix_sep,
+        stats_max_prefixes,
+        rate_limit_per_conn,
+        rate_limit_per_ip: None,
+        key_normalization,
+        case_insensitive_keys,
+        replay_threads,
+        verify_values,
+        loader,
+        loader_threads,
+        write_feed: on_write.as_ref().map(|h| h.feed()),
+        peer_access: PeerAccess {
+            allow_uids: allow_uids.unwrap_or_default(),
+            allow_gids: allow_gids.unwrap_or_default(),
+            readonly_uids: readonly_uids.unwrap_or_default(),
+            readonly_gids: readonly_gids.unwrap_or_default(),
+        },
+        hooks,
+        acl_file,
+        chaos,
+    })
+    .and_then(|core| run_preload(py, &core, preload, preload_file).map(|()| core))
+    .and_then(|core| {
+        py.allow_threads(|| serve_unix_socket(&sock_path, core))
+            .map_err(|e| serve_error(py, e))
+    });
+    on_write::drain(py, on_write.as_deref());
+    res
+}
+
+/// =======================
+/// Python-клиент TinyCache
+/// =======================
+// запас под заголовок MSet, чтобы кадр точно не превысил предел сервера
+const MSET_BATCH_BYTES: usize = MAX_COMMAND_SIZE - 1024;
+
+// команда MSet из update(): пары для сервера и те же пары, как их передали,
+// для PartialWriteError.remaining
+#[derive(Default)]
+struct MSetBatch<'py> {
+    items: Vec<(String, Vec<u8>)>,
+    origin: Vec<(String, Bound<'py, PyBytes>)>,
+    bytes: usize,
+}
+
+/// `PartialWriteError` update(): `done` из `total` пакетов записаны
+/// (`keys_written` ключей), в `remaining` — dict пар из `rest`, записанных не
+/// наверняка, так что `update(e.remaining)` дописывает только их. Исходная
+/// ошибка — в `__cause__`.
+fn partial_write_error<'py>(
+    py: Python<'py>,
+    cause: PyErr,
+    (done, total): (usize, usize),
+    keys_written: usize,
+    rest: impl Iterator<Item = Vec<(String, Bound<'py, PyBytes>)>>,
+) -> PyErr {
+    let err = PartialWriteError::new_err(format!(
+        "update(): {} of {} batches written ({} keys) before: {}",
+        done, total, keys_written, cause
+    ));
+    let value = err.value_bound(py);
+    let remaining = PyDict::new_bound(py);
+    let res = rest
+        .flatten()
+        .try_for_each(|(key, value)| remaining.set_item(key, value))
+        .and_then(|_| value.setattr("batches_written", done))
+        .and_then(|_| value.setattr("keys_written", keys_written))
+        .and_then(|_| value.setattr("remaining", remaining));
+    err.set_cause(py, Some(cause));
+    match res {
+        Ok(()) => err,
+        Err(e) => e,
+    }
+}
+
+// страница keys(sort=True) по умолчанию
+pub(crate) const KEYS_PAGE: u64 = 1000;
+
+fn is_obj(v: &[u8]) -> bool {
+    v.starts_with(OBJ_MAGIC)
+}
+
+// значение больше предела кадра — ошибка аргумента, а не сервера
+fn frame_error(e: CacheError, op: &str) -> PyErr {
+    match e {
+        CacheError::Unsupported(msg) => PyValueError::new_err(format!("{}(): {}", op, msg)),
+        e => map_error(e, op),
+    }
+}
+
+// из pybuffer.h: в abi3 ниже 3.11 pyo3-ffi его не экспортирует
+const PYBUF_WRITE: c_int = 0x200;
+
+/// Кадр команды записи со значением из любого объекта с буферным протоколом;
+/// `frame` собирает кадр с местом под значение нужной длины (`set_frame` и
+/// подобные). Байты копируются один раз, сразу в кадр. `PyBuffer` в abi3
+/// доступен только с Python 3.11, поэтому экспортёры, кроме bytes и
+/// bytearray, копируются присваиванием среза memoryview поверх самого кадра.
+/// Значение, которое сожмётся по `compress` (`proto` — версия сервера),
+/// уходит в кадр сжатым; memoryview для этого сначала копируется в bytes.
+fn value_frame(
+    py: Python<'_>,
+    op: &str,
+    key: &str,
+    value: &Bound<'_, PyAny>,
+    compress: Compress,
+    proto: &dyn Fn() -> Result<u32, CacheError>,
+    frame: impl Fn(usize) -> Result<(Vec<u8>, usize), CacheError>,
+) -> PyResult<Vec<u8>> {
+    let frame = |len| frame(len).map_err(|e| frame_error(e, op));
+    let put = |b: &[u8]| {
+        let encoded = compress.apply(b, proto).map_err(|e| map_error(e, op))?;
+        let b = encoded.as_deref().unwrap_or(b);
+        let (mut out, at) = frame(b.len())?;
+        out[at..].copy_from_slice(b);
+        Ok(out)
+    };
+    if let Ok(b) = value.downcast::<PyBytes>() {
+        return put(b.as_bytes());
+    }
+    if let Ok(b) = value.downcast::<PyByteArray>() {
+        // под GIL bytearray не изменится, пока копируем
+        return put(unsafe { b.as_bytes() });
+    }
+    let src = PyMemoryView::from_bound(value).map_err(|_| {
+        let type_name = value
+            .get_type()
+            .name()
+            .map_or_else(|_| "?".to_string(), |n| n.to_string());
+        PyTypeError::new_err(format!(
+            "{}(): value for key {:?} must be a bytes-like object, not {}",
+            op, key, type_name
+        ))
+    })?;
+    if !src.getattr("c_contiguous")?.extract::<bool>()? {
+        return Err(PyBufferError::new_err(format!(
+            "{}(): value for key {:?} is not a C-contiguous buffer; \
+             copy it first, e.g. with bytes() or numpy.ascontiguousarray()",
+            op, key
+        )));
+    }
+    // cast('B') берёт любой C-непрерывный формат, в том числе с явным порядком
+    // байтов ('<d', '>i4'), кроме пустого многомерного буфера: тот — просто b""
+    if src.getattr("nbytes")?.extract::<usize>()? == 0 {
+        return put(b"");
+    }
+    let flat = src.call_method1("cast", ("B",))?;
+    let len = flat.len()?;
+    let head = PySlice::new_bound(py, 0, ENC_MAGIC.len() as isize, 1);
+    if compress.may_encode(len)
+        || flat
+            .get_item(head)?
+            .call_method0("tobytes")?
+            .extract::<&[u8]>()?
+            == ENC_MAGIC
+    {
+        let b = flat.call_method0("tobytes")?;
+        return put(b.downcast::<PyBytes>()?.as_bytes());
+    }
+    let (mut out, at) = frame(len)?;
+    if len > 0 {
+        let dst = unsafe {
+            let ptr = out[at..].as_mut_ptr() as *mut c_char;
+            let view =
+                pyo3::ffi::PyMemoryView_FromMemory(ptr, len as pyo3::ffi::Py_ssize_t, PYBUF_WRITE);
+            Bound::from_owned_ptr_or_err(py, view)?
+        };
+        let res = dst.set_item(PySlice::full_bound(py), &flat);
+        // view смотрит в память кадра и не должен её пережить
+        dst.call_method0("release")?;
+        res?;
+    }
+    Ok(out)
+}
+
+// get() по ключу из set_obj() вернул бы байты pickle с заголовком — это ошибка
+fn raw_value<'py>(py: Python<'py>, op: &str, key: &str, v: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    if is_obj(v) {
+        return Err(PyTypeError::new_err(format!(
+            "{}(): key {:?} holds a set_obj() value, use get_obj()",
+            op, key
+        )));
+    }
+    Ok(PyBytes::new_bound(py, v))
+}
+
+// ответ Map (или Info сервера версии 1) -> dict, вложенные словари — тоже dict
+fn map_to_dict<'py>(
+    py: Python<'py>,
+    map: Vec<(String, ResponseValue)>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let d = PyDict::new_bound(py);
+    for (k, v) in map {
+        match v {
+            ResponseValue::Int(n) => d.set_item(k, n)?,
+            ResponseValue::Float(f) => d.set_item(k, f)?,
+            ResponseValue::Str(s) => d.set_item(k, s)?,
+            ResponseValue::Bytes(b) => d.set_item(k, PyBytes::new_bound(py, &b))?,
+            ResponseValue::Nested(inner) => d.set_item(k, map_to_dict(py, inner)?)?,
+        }
+    }
+    Ok(d)
+}
+
+// значение для config_set(): None — "none", bool — "true"/"false", иначе str()
+fn config_value(value: &Bound<'_, PyAny>) -> PyResult<String> {
+    if value.is_none() {
+        Ok("none".into())
+    } else if let Ok(on) = value.downcast::<pyo3::types::PyBool>() {
+        Ok(if on.is_true() { "true" } else { "false" }.into())
+    } else {
+        Ok(value.str()?.to_string())
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct TinyCache {
+    // постоянные соединения, см. pool.rs; после fork дочерний процесс
+    // открывает свои
+    pool: Arc<Pool>,
+    // для админ-команд (set_read_only)
+    admin_token: Option<String>,
+    // разрешает get_obj(): unpickle данных с сервера исполняет произвольный код
+    allow_pickle: bool,
+    // префикс пространства имён, см. namespace(); пустой у корневого клиента
+    ns: String,
+    // ближний кэш get(), см. near.rs; общий у видов namespace()
+    near: Option<Arc<near::NearCache>>,
+    // наблюдение, сбрасывающее ближний кэш при чужих записях (local_cache_watch)
+    near_watch: Option<Arc<watch::Watch>>,
+    // сжатие значений при записи, см. TinyCache(compress=...)
+    compress: Compress,
+}
+
+impl TinyCache {
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.ns, key)
+    }
+
+    // запись сбрасывает ключ и при ошибке: команда могла дойти до сервера
+    fn invalidate(&self, key: &str) {
+        if let Some(near) = &self.near {
+            near.invalidate(key);
+        }
+    }
+
+    fn invalidate_prefix(&self, prefix: &str) {
+        if let Some(near) = &self.near {
+            near.invalidate_prefix(prefix);
+        }
+    }
+
+    // значение для записи в кодировке compress; None — как есть
+    fn encoded(&self, op: &str, value: &[u8]) -> PyResult<Option<Vec<u8>>> {
+        self.compress
+            .apply(value, || self.pool.protocol())
+            .map_err(|e| map_error(e, op))
+    }
+
+    // кадр записи значения, см. value_frame
+    fn value_frame(
+        &self,
+        py: Python<'_>,
+        op: &str,
+        key: &str,
+        value: &Bound<'_, PyAny>,
+        frame: impl Fn(usize) -> Result<(Vec<u8>, usize), CacheError>,
+    ) -> PyResult<Vec<u8>> {
+        let proto = || self.pool.protocol();
+        value_frame(py, op, key, value, self.compress, &proto, frame)
+    }
+
+    fn send_set(&self, op: &str, key: &str, frame: &[u8]) -> PyResult<()> {
+        let res = self.pool.call_frame(frame);
+        self.invalidate(key);
+        match res {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from {}: {:?}",
+                op, resp
+            ))),
+            Err(e) => Err(map_error(e, op)),
+        }
+    }
+
+    // ответ Int(0 | 1) команд PExpire/ExpireAt; ключ мог и удалиться
+    fn send_expire(&self, op: &str, key: &str, cmd: CacheCommand) -> PyResult<bool> {
+        let res = self.pool.call(&cmd);
+        self.invalidate(key);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n != 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from {}: {:?}",
+                op, resp
+            ))),
+            Err(e) => Err(map_error(e, op)),
+        }
+    }
+
+    /// `update(items)` как у `dict`: отображение или итерируемое пар
+    /// `(key, value)`. Все пары проверяются до отправки, затем уходят по
+    /// очереди командами MSet до `batch_bytes` байт; между командами update
+    /// не атомарен. После каждой команды зовётся `progress(bytes, keys)` с
+    /// записанным к этому моменту. Ошибка команды или `progress` —
+    /// `PartialWriteError` с ещё не подтверждёнными парами, см.
+    /// `partial_write_error`.
+    fn update_items<'py>(
+        &self,
+        py: Python<'py>,
+        items: &Bound<'py, PyAny>,
+        batch_bytes: Option<usize>,
+        progress: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<()> {
+        let budget = match batch_bytes {
+            None => MSET_BATCH_BYTES,
+            Some(n) if (1..=MSET_BATCH_BYTES).contains(&n) => n,
+            Some(n) => {
+                return Err(PyValueError::new_err(format!(
+                    "update(): batch_bytes must be between 1 and {}, got {}",
+                    MSET_BATCH_BYTES, n
+                )))
+            }
+        };
+        let pairs = if items.hasattr("keys")? {
+            items.call_method0("items")?
+        } else {
+            items.clone()
+        };
+        let mut batches = vec![MSetBatch::default()];
+        for pair in pairs.iter()? {
+            let (key, value): (Bound<'py, PyAny>, Bound<'py, PyAny>) =
+                pair?.extract().map_err(|_| {
+                    PyTypeError::new_err(
+                        "update() expects a mapping or an iterable of (key, value) pairs",
+                    )
+                })?;
+            let key: String = key
+                .extract()
+                .map_err(|_| PyTypeError::new_err(format!("update(): key {} is not a str", key)))?;
+            let value = value.downcast_into::<PyBytes>().map_err(|e| {
+                let type_name = e
+                    .into_inner()
+                    .get_type()
+                    .name()
+                    .map_or_else(|_| "?".to_string(), |n| n.to_string());
+                PyTypeError::new_err(format!(
+                    "update(): value for key {:?} must be bytes, not {}",
+                    key, type_name
+                ))
+            })?;
+            let encoded = match self.encoded("update", value.as_bytes())? {
+                Some(encoded) => encoded,
+                None => value.as_bytes().to_vec(),
+            };
+            // длины строки и вектора в bincode — по 8 байт
+            let item_size = self.ns.len() + key.len() + encoded.len() + 16;
+            if item_size > MSET_BATCH_BYTES {
+                return Err(PyValueError::new_err(format!(
+                    "update(): value for key {:?} is too large for one command ({} bytes)",
+                    key,
+                    encoded.len()
+                )));
+            }
+            // пара больше batch_bytes, но в пределах кадра уходит одна
+            let last = batches.last().expect("at least one batch");
+            if !last.items.is_empty() && last.bytes + item_size > budget {
+                batches.push(MSetBatch::default());
+            }
+            let batch = batches.last_mut().expect("at least one batch");
+            batch.bytes += item_size;
+            batch.items.push((self.key(&key), encoded));
+            batch.origin.push((key, value));
+        }
+        batches.retain(|b| !b.items.is_empty());
+        let total = batches.len();
+        let (mut bytes_done, mut keys_done) = (0, 0);
+        let mut pending = batches.into_iter().enumerate();
+        while let Some((i, batch)) = pending.next() {
+            let MSetBatch {
+                items,
+                origin,
+                bytes,
+            } = batch;
+            let keys: Vec<String> = match &self.near {
+                Some(_) => items.iter().map(|(k, _)| k.clone()).collect(),
+                None => Vec::new(),
+            };
+            let res = py.allow_threads(|| self.pool.call(&CacheCommand::MSet(items)));
+            for key in &keys {
+                self.invalidate(key);
+            }
+            let failed = match res {
+                Ok(CacheResponse::Ok) => None,
+                Ok(resp) => Some(PyRuntimeError::new_err(format!(
+                    "Unexpected response from update: {:?}",
+                    resp
+                ))),
+                Err(e) => Some(map_error(e, "update")),
+            };
+            if let Some(cause) = failed {
+                // ответа нет — пакет мог и записаться; повтор Set безвреден
+                let rest = std::iter::once(origin).chain(pending.map(|(_, b)| b.origin));
+                return Err(partial_write_error(py, cause, (i, total), keys_done, rest));
+            }
+            bytes_done += bytes;
+            keys_done += origin.len();
+            if let Some(progress) = progress {
+                if let Err(cause) = progress.call1((bytes_done, keys_done)) {
+                    // KeyboardInterrupt и SystemExit не прячутся в TinyCacheError
+                    if !cause.is_instance_of::<PyException>(py) {
+                        return Err(cause);
+                    }
+                    let rest = pending.map(|(_, b)| b.origin);
+                    return Err(partial_write_error(
+                        py,
+                        cause,
+                        (i + 1, total),
+                        keys_done,
+                        rest,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl TinyCache {
+    #[new]
+    #[pyo3(signature = (
+        addr,
+        admin_token=None,
+        wait_ready=false,
+        ready_timeout=5.0,
+        allow_pickle=false,
+        local_cache_size=0,
+        local_cache_ttl=0.5,
+        local_cache_watch=false,
+        compress=None,
+        compress_min=DEFAULT_COMPRESS_MIN,
+        user=None,
+        password=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python<'_>,
+        addr: String,
+        admin_token: Option<String>,
+        wait_ready: bool,
+        ready_timeout: f64,
+        allow_pickle: bool,
+        local_cache_size: usize,
+        local_cache_ttl: f64,
+        local_cache_watch: bool,
+        compress: Option<&str>,
+        compress_min: usize,
+        user: Option<String>,
+        password: Option<String>,
+    ) -> PyResult<Self> {
+        let auth = match (user, password) {
+            (Some(user), Some(password)) => Some(Credentials { user, password }),
+            (None, None) => None,
+            _ => return Err(PyValueError::new_err("user and password go together")),
+        };
+        let compress = Compress {
+            encoding: compress
+                .map(ValueEncoding::parse)
+                .transpose()
+                .map_err(|e| frame_error(e, "TinyCache"))?
+                .unwrap_or_default(),
+            min_size: compress_min,
+        };
+        if !(local_cache_ttl.is_finite() && local_cache_ttl > 0.0) {
+            return Err(PyValueError::new_err("local_cache_ttl must be positive"));
+        }
+        if local_cache_watch && local_cache_size == 0 {
+            return Err(PyValueError::new_err(
+                "local_cache_watch needs local_cache_size > 0",
+            ));
+        }
+        let pool =
+            Arc::new(Pool::new(TransportAddr::parse(&addr), Timeouts::default()).with_auth(auth));
+        // без wait_ready конструктор не делает I/O: соединение откроет первый вызов
+        if wait_ready {
+            if !(ready_timeout.is_finite() && ready_timeout > 0.0) {
+                return Err(PyValueError::new_err("ready_timeout must be positive"));
+            }
+            let timeout = Duration::from_secs_f64(ready_timeout);
+            py.allow_threads(|| pool.wait_ready(timeout)).map_err(|e| {
+                PyConnectionError::new_err(format!(
+                    "server {} not ready after {}s: {}",
+                    addr, ready_timeout, e
+                ))
+            })?;
+        }
+        let near = (local_cache_size > 0).then(|| {
+            Arc::new(near::NearCache::new(
+                local_cache_size,
+                Duration::from_secs_f64(local_cache_ttl),
+            ))
+        });
+        let near_watch = match &near {
+            Some(near) if local_cache_watch => {
+                let callback = Py::new(py, near::Invalidator(Arc::clone(near)))?.into_any();
+                let w =
+                    watch::Watch::open(pool.addr(), String::new(), 0, false, callback, pool.auth())
+                        .map_err(|e| map_error(e, "local_cache_watch"))?;
+                Some(Arc::new(w))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            pool,
+            admin_token,
+            allow_pickle,
+            ns: String::new(),
+            near,
+            near_watch,
+            compress,
+        })
+    }
+
+    /// Вид на тот же сервер и тот же пул соединений, где ко всем ключам
+    /// добавляется `prefix + sep`, а из keys(), итераторов, to_dict() и
+    /// событий watch() он срезается. Вложенные виды складываются:
+    /// `c.namespace("a").namespace("b")` — префикс `"a:b:"`.
+    ///
+    /// Команды сервера в целом (len() считает только ключи вида, но save(),
+    /// info(), prefix_stats(), publish(), subscribe() и т.п.) работают как у
+    /// корневого клиента; close() закрывает общий пул.
+    #[pyo3(signature = (prefix, sep=":"))]
+    fn namespace(&self, prefix: &str, sep: &str) -> PyResult<Self> {
+        if prefix.is_empty() {
+            return Err(PyValueError::new_err(
+                "namespace(): prefix must not be empty",
+            ));
+        }
+        Ok(Self {
+            ns: format!("{}{}{}", self.ns, prefix, sep),
+            ..self.clone()
+        })
+    }
+
+    /// Полный префикс ключей вида, `""` у корневого клиента.
+    #[getter]
+    fn prefix(&self) -> String {
+        self.ns.clone()
+    }
+
+    fn close(&self) {
+        self.pool.close();
+        if let Some(w) = &self.near_watch {
+            w.shutdown();
+        }
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.pool.is_closed()
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) -> bool {
+        self.close();
+        false
+    }
+
+    /// `value` — bytes или любой объект с буферным протоколом (bytearray,
+    /// memoryview, массив numpy); без промежуточного `bytes`. `tags` — теги
+    /// ключа для `delete_by_tag`; set без тегов сбрасывает прежние. Теги, как и
+    /// ключи, живут в пространстве имён клиента.
+    #[pyo3(signature = (key, value, tags=None))]
+    fn set(
+        &self,
+        py: Python<'_>,
+        key: String,
+        value: &Bound<'_, PyAny>,
+        tags: Option<Vec<String>>,
+    ) -> PyResult<()> {
+        let key = self.key(&key);
+        let frame = match tags.filter(|t| !t.is_empty()) {
+            Some(tags) => {
+                let tags: Vec<String> = tags.iter().map(|t| self.key(t)).collect();
+                self.value_frame(py, "set", &key, value, |len| {
+                    set_tagged_frame(&key, &tags, len)
+                })?
+            }
+            None => self.value_frame(py, "set", &key, value, |len| set_frame(&key, len))?,
+        };
+        self.send_set("set", &key, &frame)
+    }
+
+    /// `set` со сроком жизни в секундах (дробные — с точностью до мс).
+    fn setex(
+        &self,
+        py: Python<'_>,
+        key: String,
+        seconds: f64,
+        value: &Bound<'_, PyAny>,
+    ) -> PyResult<()> {
+        let ttl_ms = seconds * 1000.0;
+        if !ttl_ms.is_finite() || ttl_ms.round() < 1.0 {
+            return Err(PyValueError::new_err(format!(
+                "setex(): ttl must be at least 1 ms, got {} s",
+                seconds
+            )));
+        }
+        self.psetex(py, key, ttl_ms.round() as u64, value)
+    }
+
+    /// `set` со сроком жизни в миллисекундах.
+    fn psetex(
+        &self,
+        py: Python<'_>,
+        key: String,
+        millis: u64,
+        value: &Bound<'_, PyAny>,
+    ) -> PyResult<()> {
+        if millis == 0 {
+            return Err(PyValueError::new_err("psetex(): ttl must be at least 1 ms"));
+        }
+        let key = self.key(&key);
+        let frame = self.value_frame(py, "psetex", &key, value, |len| {
+            set_ex_frame(&key, millis, len)
+        })?;
+        self.send_set("psetex", &key, &frame)
+    }
+
+    /// Срок жизни существующего ключа в секундах; `seconds <= 0` удаляет
+    /// ключ. Возвращает, был ли ключ.
+    fn expire(&self, key: String, seconds: f64) -> PyResult<bool> {
+        if seconds.is_nan() {
+            return Err(PyValueError::new_err("expire(): seconds must be a number"));
+        }
+        // as насыщает бесконечности до пределов i64
+        self.pexpire(key, (seconds * 1000.0).round() as i64)
+    }
+
+    fn pexpire(&self, key: String, millis: i64) -> PyResult<bool> {
+        let key = self.key(&key);
+        self.send_expire("pexpire", &key, CacheCommand::PExpire(key.clone(), millis))
+    }
+
+    /// Ключ истечёт в момент `when`: `datetime` (наивный считается местным
+    /// временем, как в `datetime.timestamp()`) или unix-время в секундах.
+    /// Прошедший момент удаляет ключ сразу.
+    fn expire_at(&self, key: String, when: &Bound<'_, PyAny>) -> PyResult<bool> {
+        let ts: f64 = match when.extract() {
+            Ok(ts) => ts,
+            Err(_) if when.hasattr("timestamp")? => when.call_method0("timestamp")?.extract()?,
+            Err(_) => {
+                return Err(PyTypeError::new_err(
+                    "expire_at(): when must be a datetime or a unix timestamp in seconds",
+                ))
+            }
+        };
+        if ts.is_nan() {
+            return Err(PyValueError::new_err(
+                "expire_at(): timestamp must be a number",
+            ));
+        }
+        let at_ms = (ts * 1000.0).round().max(0.0) as u64;
+        let key = self.key(&key);
+        self.send_expire(
+            "expire_at",
+            &key,
+            CacheCommand::ExpireAt(key.clone(), at_ms),
+        )
+    }
+
+    /// Оставшийся срок в секундах с округлением; -1 — ключ бессрочный,
+    /// -2 — ключа нет.
+    fn ttl(&self, key: String) -> PyResult<i64> {
+        let ms = self.pttl(key)?;
+        Ok(if ms < 0 { ms } else { (ms + 500) / 1000 })
+    }
+
+    /// То же в миллисекундах.
+    fn pttl(&self, key: String) -> PyResult<i64> {
+        match self.pool.call(&CacheCommand::PTtl(self.key(&key))) {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from pttl: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "pttl")),
+        }
+    }
+
+    /// С ближним кэшем (`local_cache_size`) сначала смотрит в него;
+    /// `bypass_local=True` идёт на сервер и обновляет кэш ответом.
+    #[pyo3(signature = (key, default=None, bypass_local=false))]
+    fn get(
+        &self,
+        py: Python<'_>,
+        key: String,
+        default: Option<PyObject>,
+        bypass_local: bool,
+    ) -> PyResult<PyObject> {
+        let full = self.key(&key);
+        if let Some(near) = self.near.as_ref().filter(|_| !bypass_local) {
+            if let Some(v) = near.get(py, &full) {
+                return Ok(v.into_py(py));
+            }
+        }
+        match self.pool.call(&CacheCommand::Get(full.clone())) {
+            Ok(CacheResponse::Value(v)) => {
+                let v = raw_value(py, "get", &key, &v)?;
+                if let Some(near) = &self.near {
+                    near.insert(full, v.clone().unbind());
+                }
+                Ok(v.into_py(py))
+            }
+            // default отдаётся как есть, без проверки типа
+            Ok(CacheResponse::Nil) => Ok(default.unwrap_or_else(|| py.None())),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from get: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "get")),
+        }
+    }
+
+    #[pyo3(signature = (key, timeout=5.0))]
+    fn get_blocking<'py>(
+        &self,
+        py: Python<'py>,
+        key: String,
+        timeout: f64,
+    ) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        if !(0.0..=blocking::MAX_BLOCK.as_secs_f64()).contains(&timeout) {
+            return Err(PyValueError::new_err(format!(
+                "timeout must be between 0 and {} seconds",
+                blocking::MAX_BLOCK.as_secs()
+            )));
+        }
+        let cmd = CacheCommand::BGet(self.key(&key), (timeout * 1000.0) as u64);
+        // ожидание может длиться секундами: остальные потоки Python не должны стоять
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Value(v)) => raw_value(py, "get_blocking", &key, &v).map(Some),
+            Ok(CacheResponse::Nil) => Ok(None),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from get_blocking: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "get_blocking")),
+        }
+    }
+
+    // default позиционный, как у dict.pop: вызов без него и с None различаются
+    #[pyo3(signature = (key, *default))]
+    fn pop(&self, py: Python<'_>, key: String, default: &Bound<'_, PyTuple>) -> PyResult<PyObject> {
+        if default.len() > 1 {
+            return Err(PyTypeError::new_err(format!(
+                "pop expected at most 2 arguments, got {}",
+                default.len() + 1
+            )));
+        }
+        let default = default.get_item(0).ok();
+        let full = self.key(&key);
+        let res = self.pool.call(&CacheCommand::Pop(full.clone()));
+        self.invalidate(&full);
+        match res {
+            Ok(CacheResponse::Value(v)) => Ok(PyBytes::new_bound(py, &v).into_py(py)),
+            Ok(CacheResponse::Nil) => match default {
+                Some(default) => Ok(default.unbind()),
+                None => Err(PyKeyError::new_err(key)),
+            },
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from pop: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "pop")),
+        }
+    }
+
+    /// Значения `keys` на один момент времени (`None` — ключа нет):
+    /// `swap()` виден целиком или не виден вовсе, в отличие от `get()` по
+    /// очереди. Ближний кэш и `loader` сервера не участвуют.
+    fn get_consistent<'py>(
+        &self,
+        py: Python<'py>,
+        keys: Vec<String>,
+    ) -> PyResult<Vec<Option<Bound<'py, PyBytes>>>> {
+        let cmd = CacheCommand::MGetConsistent(keys.iter().map(|k| self.key(k)).collect());
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Values(values)) => Ok(values
+                .into_iter()
+                .map(|v| v.map(|v| PyBytes::new_bound(py, &v)))
+                .collect()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from get_consistent: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "get_consistent")),
+        }
+    }
+
+    /// `set(key, json.dumps(obj).encode())`: те же байты, что записал бы
+    /// клиент, сериализующий вручную.
+    fn set_json(&self, py: Python<'_>, key: String, obj: &Bound<'_, PyAny>) -> PyResult<()> {
+        let text: String = py
+            .import_bound("json")?
+            .call_method1("dumps", (obj,))?
+            .extract()?;
+        let key = self.key(&key);
+        let encoded = self.encoded("set_json", text.as_bytes())?;
+        let value = encoded.as_deref().unwrap_or(text.as_bytes());
+        let (mut frame, at) =
+            set_frame(&key, value.len()).map_err(|e| frame_error(e, "set_json"))?;
+        frame[at..].copy_from_slice(value);
+        self.send_set("set", &key, &frame)
+    }
+
+    /// `json.loads(get(key))`, а если ключа нет — `default`. Сохранённый JSON
+    /// `null` — это `None`, а не отсутствие ключа.
+    #[pyo3(signature = (key, default=None))]
+    fn get_json(
+        &self,
+        py: Python<'_>,
+        key: String,
+        default: Option<PyObject>,
+    ) -> PyResult<PyObject> {
+        let v = match self.pool.call(&CacheCommand::Get(self.key(&key))) {
+            Ok(CacheResponse::Value(v)) => v,
+            Ok(CacheResponse::Nil) => return Ok(default.unwrap_or_else(|| py.None())),
+            Ok(resp) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from get_json: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "get_json")),
+        };
+        let loads = py.import_bound("json")?.getattr("loads")?;
+        // JSONDecodeError и UnicodeDecodeError — подклассы ValueError
+        loads
+            .call1((PyBytes::new_bound(py, &v),))
+            .map(|o| o.unbind())
+            .map_err(|e| {
+                if e.is_instance_of::<PyValueError>(py) {
+                    let err = PyValueError::new_err(format!(
+                        "get_json(): value of key {:?} is not valid JSON: {}",
+                        key,
+                        e.value_bound(py)
+                    ));
+                    err.set_cause(py, Some(e));
+                    err
+                } else {
+                    e
+                }
+            })
+    }
+
+    /// Pickle объекта с заголовком OBJ_MAGIC; `protocol=None` — протокол
+    /// по умолчанию модуля pickle.
+    #[pyo3(signature = (key, obj, protocol=None))]
+    fn set_obj(
+        &self,
+        py: Python<'_>,
+        key: String,
+        obj: &Bound<'_, PyAny>,
+        protocol: Option<i32>,
+    ) -> PyResult<()> {
+        let data = py
+            .import_bound("pickle")?
+            .call_method1("dumps", (obj, protocol))?;
+        let data = data.downcast::<PyBytes>()?.as_bytes();
+        let key = self.key(&key);
+        if self.compress.may_encode(OBJ_MAGIC.len() + data.len()) {
+            let value = [OBJ_MAGIC, data].concat();
+            let encoded = self.encoded("set_obj", &value)?;
+            let value = encoded.as_deref().unwrap_or(&value);
+            let (mut frame, at) =
+                set_frame(&key, value.len()).map_err(|e| frame_error(e, "set_obj"))?;
+            frame[at..].copy_from_slice(value);
+            return self.send_set("set", &key, &frame);
+        }
+        let (mut frame, at) =
+            set_frame(&key, OBJ_MAGIC.len() + data.len()).map_err(|e| frame_error(e, "set_obj"))?;
+        frame[at..at + OBJ_MAGIC.len()].copy_from_slice(OBJ_MAGIC);
+        frame[at + OBJ_MAGIC.len()..].copy_from_slice(data);
+        self.send_set("set", &key, &frame)
+    }
+
+    /// Объект из `set_obj()`, а если ключа нет — `default`. Требует
+    /// `allow_pickle=True` у клиента; сырые байты без заголовка — ошибка.
+    #[pyo3(signature = (key, default=None))]
+    fn get_obj(
+        &self,
+        py: Python<'_>,
+        key: String,
+        default: Option<PyObject>,
+    ) -> PyResult<PyObject> {
+        if !self.allow_pickle {
+            return Err(PyRuntimeError::new_err(
+                "get_obj() needs TinyCache(..., allow_pickle=True): \
+                 unpickling data from the server can run arbitrary code",
+            ));
+        }
+        let v = match self.pool.call(&CacheCommand::Get(self.key(&key))) {
+            Ok(CacheResponse::Value(v)) => v,
+            Ok(CacheResponse::Nil) => return Ok(default.unwrap_or_else(|| py.None())),
+            Ok(resp) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from get_obj: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "get_obj")),
+        };
+        if !is_obj(&v) {
+            return Err(PyTypeError::new_err(format!(
+                "get_obj(): key {:?} holds raw bytes, not a set_obj() value",
+                key
+            )));
+        }
+        let data = PyBytes::new_bound(py, &v[OBJ_MAGIC.len()..]);
+        Ok(py
+            .import_bound("pickle")?
+            .call_method1("loads", (data,))?
+            .unbind())
+    }
+
+    /// Как `dict.setdefault`, но атомарно на сервере: из нескольких
+    /// конкурентных вызовов значение записывает ровно один.
+    fn setdefault<'py>(
+        &self,
+        py: Python<'py>,
+        key: String,
+        value: &[u8],
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let (v, _) = self.get_or_set(py, key, value)?;
+        Ok(v)
+    }
+
+    /// То же, что `setdefault`, плюс `True`, если значение записал этот вызов.
+    fn get_or_set<'py>(
+        &self,
+        py: Python<'py>,
+        key: String,
+        value: &[u8],
+    ) -> PyResult<(Bound<'py, PyBytes>, bool)> {
+        let key = self.key(&key);
+        let res = self
+            .pool
+            .call(&CacheCommand::GetOrSet(key.clone(), value.to_vec()));
+        self.invalidate(&key);
+        match res {
+            Ok(CacheResponse::Entry(v, inserted)) => Ok((PyBytes::new_bound(py, &v), inserted)),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from get_or_set: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "get_or_set")),
+        }
+    }
+
+    /// Записывает значение, только если ключа нет, и возвращает, записал ли
+    /// этот вызов; `ttl` — срок жизни в секундах (None — бессрочно). Проверка
+    /// и запись со сроком атомарны, см. `lock()`.
+    #[pyo3(signature = (key, value, ttl=None))]
+    fn set_nx(
+        &self,
+        py: Python<'_>,
+        key: String,
+        value: &[u8],
+        ttl: Option<f64>,
+    ) -> PyResult<bool> {
+        let ttl_ms = match ttl {
+            None => 0,
+            Some(seconds) => {
+                let ttl_ms = (seconds * 1000.0).round();
+                if !ttl_ms.is_finite() || ttl_ms < 1.0 {
+                    return Err(PyValueError::new_err(format!(
+                        "set_nx(): ttl must be at least 1 ms, got {} s",
+                        seconds
+                    )));
+                }
+                ttl_ms as u64
+            }
+        };
+        let key = self.key(&key);
+        let cmd = CacheCommand::SetNx(key.clone(), ttl_ms, value.to_vec());
+        let res = py.allow_threads(|| self.pool.call(&cmd));
+        self.invalidate(&key);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n != 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from set_nx: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "set_nx")),
+        }
+    }
+
+    /// `TinyCacheLock(self, name, ttl)`: блокировка между процессами на ключе
+    /// `name` с арендой `ttl` секунд. Сам вызов на сервер не ходит.
+    #[pyo3(signature = (name, ttl=30.0))]
+    fn lock(&self, name: String, ttl: f64) -> PyResult<lock::TinyCacheLock> {
+        lock::TinyCacheLock::create(self.clone(), name, ttl)
+    }
+
+    /// Атомарно сдвигает счётчик на `n` и возвращает прежнее значение: номера
+    /// `prev + 1 .. prev + n` достались этому вызову и не повторятся и после
+    /// перезапуска сервера. Раздача номеров блоками — `IdAllocator`.
+    fn incr_range(&self, py: Python<'_>, key: String, n: u64) -> PyResult<i64> {
+        if n == 0 {
+            return Err(PyValueError::new_err("incr_range(): n must be positive"));
+        }
+        let key = self.key(&key);
+        let cmd = CacheCommand::IncrRange(key.clone(), n);
+        let res = py.allow_threads(|| self.pool.call(&cmd));
+        self.invalidate(&key);
+        match res {
+            Ok(CacheResponse::Int(prev)) => Ok(prev),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from incr_range: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "incr_range")),
+        }
+    }
+
+    /// Ставит бит `offset` (бит 0 — старший бит первого байта) и возвращает
+    /// прежний; значение дописывается нулевыми байтами до нужной длины.
+    fn setbit(&self, key: String, offset: u64, value: bool) -> PyResult<bool> {
+        let key = self.key(&key);
+        let res = self
+            .pool
+            .call(&CacheCommand::SetBit(key.clone(), offset, value));
+        self.invalidate(&key);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n != 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from setbit: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "setbit")),
+        }
+    }
+
+    fn getbit(&self, key: String, offset: u64) -> PyResult<bool> {
+        match self
+            .pool
+            .call(&CacheCommand::GetBit(self.key(&key), offset))
+        {
+            Ok(CacheResponse::Int(n)) => Ok(n != 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from getbit: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "getbit")),
+        }
+    }
+
+    /// Число единичных битов; `start`/`end` — диапазон байт включительно,
+    /// отрицательные отсчитываются от конца.
+    #[pyo3(signature = (key, start=None, end=None))]
+    fn bitcount(&self, key: String, start: Option<i64>, end: Option<i64>) -> PyResult<i64> {
+        let range = match (start, end) {
+            (None, None) => None,
+            (start, end) => Some((start.unwrap_or(0), end.unwrap_or(-1))),
+        };
+        match self
+            .pool
+            .call(&CacheCommand::BitCount(self.key(&key), range))
+        {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from bitcount: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "bitcount")),
+        }
+    }
+
+    /// Обновляет время последнего обращения к ключам на сервере, не читая
+    /// значений; возвращает, сколько из них существует.
+    #[pyo3(signature = (*keys))]
+    fn touch(&self, keys: Vec<String>) -> PyResult<i64> {
+        let keys = keys.iter().map(|k| self.key(k)).collect();
+        match self.pool.call(&CacheCommand::Touch(keys)) {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from touch: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "touch")),
+        }
+    }
+
+    /// Целые секунды с последнего обращения к ключу или None, если ключа нет.
+    /// Попадание в ближний кэш до сервера не доходит и обращением не считается.
+    fn idle_time(&self, key: String) -> PyResult<Option<i64>> {
+        match self.pool.call(&CacheCommand::IdleTime(self.key(&key))) {
+            Ok(CacheResponse::Int(n)) => Ok(Some(n)),
+            Ok(CacheResponse::Nil) => Ok(None),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from idle_time: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "idle_time")),
+        }
+    }
+
+    /// Сколько прежних значений сервер помнит у ключей на `prefix` (в
+    /// пространстве имён клиента); 0 — не помнить.
+    fn set_history_depth(&self, prefix: String, depth: u32) -> PyResult<()> {
+        let cmd = CacheCommand::SetHistoryDepth(self.key(&prefix), depth);
+        match self.pool.call(&cmd) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from set_history_depth: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "set_history_depth")),
+        }
+    }
+
+    /// Прежние значения ключа от старых к новым: (unix-время в секундах,
+    /// когда значение заменили или удалили, значение).
+    fn history(&self, py: Python<'_>, key: String) -> PyResult<Vec<(f64, PyObject)>> {
+        match self.pool.call(&CacheCommand::History(self.key(&key))) {
+            Ok(CacheResponse::History(versions)) => Ok(versions
+                .into_iter()
+                .map(|(at, v)| (at as f64 / 1000.0, PyBytes::new_bound(py, &v).into_py(py)))
+                .collect()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from history: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "history")),
+        }
+    }
+
+    /// Вид значения: "bytes" или "none", если ключа нет. С `detail=True` —
+    /// dict с `type`, `encoding` ("raw", "pickle" для `set_obj()`, "zstd" или
+    /// "lz4" для сжатых, см. `compress`) и `length` в байтах, как хранится.
+    #[pyo3(signature = (key, detail=false))]
+    fn r#type(&self, py: Python<'_>, key: String, detail: bool) -> PyResult<PyObject> {
+        match self
+            .pool
+            .call(&CacheCommand::Type(self.key(&key)))
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) if detail => Ok(map_to_dict(py, map)?.into()),
+            Ok(Ok(map)) => {
+                let ty = map
+                    .iter()
+                    .find(|(k, _)| k == "type")
+                    .and_then(|(_, v)| v.as_str());
+                Ok(ty.unwrap_or("none").into_py(py))
+            }
+            Ok(Err(resp)) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from type: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "type")),
+        }
+    }
+
+    /// Удаляет все ключи с тегом и возвращает их число. Какие ключи удалены,
+    /// клиент не знает, поэтому ближний кэш пространства имён сбрасывается
+    /// целиком.
+    fn delete_by_tag(&self, py: Python<'_>, tag: String) -> PyResult<i64> {
+        let tag = self.key(&tag);
+        let res = py.allow_threads(|| self.pool.call(&CacheCommand::DelByTag(tag)));
+        self.invalidate_prefix(&self.ns);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from delete_by_tag: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "delete_by_tag")),
+        }
+    }
+
+    /// Возвращает ключ, удалённый на сервере с `tombstone_ttl_secs`, если
+    /// срок надгробия не прошёл и ключ не записан заново; иначе `False`.
+    fn undelete(&self, key: String) -> PyResult<bool> {
+        let key = self.key(&key);
+        let res = self.pool.call(&CacheCommand::Undelete(key.clone()));
+        self.invalidate(&key);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n != 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from undelete: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "undelete")),
+        }
+    }
+
+    fn swap(&self, a: String, b: String) -> PyResult<bool> {
+        let (a, b) = (self.key(&a), self.key(&b));
+        let res = self.pool.call(&CacheCommand::Swap(a.clone(), b.clone()));
+        self.invalidate(&a);
+        self.invalidate(&b);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n != 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from swap: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "swap")),
+        }
+    }
+
+    fn delete(&self, key: String) -> PyResult<i64> {
+        let key = self.key(&key);
+        let res = self.pool.call(&CacheCommand::Del(key.clone()));
+        self.invalidate(&key);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from delete: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "delete")),
+        }
+    }
+
+    /// Удаляет ключ, только если его значение равно `expected`, и возвращает,
+    /// удалил ли. Сравнение и удаление атомарны на сервере.
+    fn delete_if(&self, py: Python<'_>, key: String, expected: &[u8]) -> PyResult<bool> {
+        let key = self.key(&key);
+        let cmd = CacheCommand::DelIfEquals(key.clone(), expected.to_vec());
+        let res = py.allow_threads(|| self.pool.call(&cmd));
+        self.invalidate(&key);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n != 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from delete_if: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "delete_if")),
+        }
+    }
+
+    /// Часы сервера, unix-время в мс.
+    fn time_ms(&self, py: Python<'_>) -> PyResult<u64> {
+        match py.allow_threads(|| self.pool.call(&CacheCommand::Time)) {
+            Ok(CacheResponse::Int(n)) => Ok(n as u64),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from time_ms: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "time_ms")),
+        }
+    }
+
+    /// Значение вместе с временем записи, сроком и версией; мимо near-кэша.
+    fn get_with_meta(&self, py: Python<'_>, key: &str) -> PyResult<Option<meta::ValueMeta>> {
+        let cmd = CacheCommand::GetWithMeta(self.key(key));
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Meta(m)) => Ok(Some(meta::ValueMeta::new(m))),
+            Ok(CacheResponse::Nil) => Ok(None),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from get_with_meta: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "get_with_meta")),
+        }
+    }
+
+    /// До `n` (не больше 10000) разных ключей наугад, каждый с равной
+    /// вероятностью, без значений: список dict с `key`, `size` (байт),
+    /// `idle_ms`, `ttl_ms` (None — бессрочный) и `age_ms` (мс с последней
+    /// записи, None — неизвестно), который можно отдать в
+    /// `pandas.DataFrame`. Обращения к ключам не отмечаются.
+    #[pyo3(signature = (n=100))]
+    fn sample<'py>(&self, py: Python<'py>, n: u32) -> PyResult<Bound<'py, PyList>> {
+        let keys = match py.allow_threads(|| self.pool.call(&CacheCommand::Sample(n))) {
+            Ok(CacheResponse::Sample(keys)) => keys,
+            Ok(resp) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from sample: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "sample")),
+        };
+        let out = PyList::empty_bound(py);
+        for k in keys {
+            let d = PyDict::new_bound(py);
+            d.set_item("key", k.key)?;
+            d.set_item("size", k.size)?;
+            d.set_item("idle_ms", k.idle_ms)?;
+            d.set_item("ttl_ms", k.ttl_ms)?;
+            d.set_item("age_ms", k.age_ms)?;
+            out.append(d)?;
+        }
+        Ok(out)
+    }
+
+    /// Сколько ключей и байт значений у каждой группы ключей: группа — первые
+    /// `depth` сегментов ключа вместе с разделителями (`"img:"`), словарь
+    /// группа -> {"keys", "bytes"} от самых больших по байтам. Групп не больше
+    /// 10000; если их больше, ключи остальных посчитаны в записи `"*"`.
+    #[pyo3(signature = (sep=":", depth=1))]
+    fn prefix_stats<'py>(
+        &self,
+        py: Python<'py>,
+        sep: &str,
+        depth: u32,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        if sep.is_empty() {
+            return Err(PyValueError::new_err(
+                "prefix_stats(): sep must not be empty",
+            ));
+        }
+        if depth == 0 {
+            return Err(PyValueError::new_err(
+                "prefix_stats(): depth must be at least 1",
+            ));
+        }
+        let cmd = CacheCommand::PrefixStats(sep.to_string(), depth);
+        let map = match py
+            .allow_threads(|| self.pool.call(&cmd))
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => map,
+            Ok(Err(resp)) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from prefix_stats: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "prefix_stats")),
+        };
+        let d = PyDict::new_bound(py);
+        for (k, v) in map {
+            match (k.as_str(), v) {
+                ("groups", ResponseValue::Nested(groups)) => {
+                    for (group, counts) in groups {
+                        if let ResponseValue::Nested(counts) = counts {
+                            d.set_item(group, map_to_dict(py, counts)?)?;
+                        }
+                    }
+                }
+                ("other", ResponseValue::Nested(counts)) => {
+                    d.set_item("*", map_to_dict(py, counts)?)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(d)
+    }
+
+    /// Фильтр Блума по ключам на `prefix`, собранный сервером: проверка
+    /// `maybe_contains(key)` идёт локально. `bits_per_key=10` — около 1%
+    /// ложных срабатываний, каждые ещё 5 бит уменьшают их примерно вдесятеро.
+    #[pyo3(signature = (prefix="", bits_per_key=10))]
+    fn export_bloom(
+        &self,
+        py: Python<'_>,
+        prefix: &str,
+        bits_per_key: u32,
+    ) -> PyResult<bloom::KeyFilter> {
+        let cmd = CacheCommand::ExportBloom(self.key(prefix), bits_per_key);
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Bloom(filter)) => Ok(bloom::KeyFilter::new(filter, self.ns.clone())),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from export_bloom: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "export_bloom")),
+        }
+    }
+
+    /// С `sort=True` — ключи по возрастанию, страница `offset..offset + limit`;
+    /// без сортировки `offset` и `limit` не задаются.
+    #[pyo3(signature = (pattern, sort=false, offset=0, limit=None))]
+    fn keys(
+        &self,
+        pattern: String,
+        sort: bool,
+        offset: u64,
+        limit: Option<u64>,
+    ) -> PyResult<Vec<String>> {
+        let cmd = if sort {
+            let Some(prefix) = pattern.strip_suffix('*') else {
+                return Ok(Vec::new());
+            };
+            CacheCommand::KeysSorted(self.key(prefix), offset, limit.unwrap_or(KEYS_PAGE))
+        } else if offset != 0 || limit.is_some() {
+            return Err(PyValueError::new_err("offset and limit require sort=True"));
+        } else {
+            CacheCommand::Keys(self.key(&pattern))
+        };
+        match self.pool.call(&cmd) {
+            Ok(CacheResponse::Keys(mut keys)) => {
+                for k in &mut keys {
+                    k.drain(..self.ns.len());
+                }
+                Ok(keys)
+            }
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from keys: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "keys")),
+        }
+    }
+
+    /// Число ключей; в пространстве имён — только его ключей (сервер
+    /// перебирает все ключи, как keys()).
+    fn len(&self) -> PyResult<i64> {
+        if !self.ns.is_empty() {
+            return Ok(self.keys("*".into(), false, 0, None)?.len() as i64);
+        }
+        match self.pool.call(&CacheCommand::Len) {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from len: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "len")),
+        }
+    }
+
+    fn save(&self) -> PyResult<()> {
+        match self.pool.call(&CacheCommand::Save) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from save: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "save")),
+        }
+    }
+
+    fn bgsave(&self) -> PyResult<()> {
+        match self.pool.call(&CacheCommand::BgSave) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from bgsave: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "bgsave")),
+        }
+    }
+
+    #[pyo3(signature = (path, format="binary"))]
+    fn export(&self, path: String, format: &str) -> PyResult<i64> {
+        match self
+            .pool
+            .call(&CacheCommand::Export(format.to_string(), path))
+        {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from export: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "export")),
+        }
+    }
+
+    #[pyo3(signature = (path, replace=false))]
+    fn import_dump(&self, path: String, replace: bool) -> PyResult<i64> {
+        let res = self.pool.call(&CacheCommand::Import(path, replace));
+        self.invalidate_prefix("");
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from import_dump: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "import_dump")),
+        }
+    }
+
+    fn set_read_only(&self, read_only: bool) -> PyResult<()> {
+        let mut cmd = CacheCommand::SetReadOnly(read_only);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match self.pool.call(&cmd) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from set_read_only: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "set_read_only")),
+        }
+    }
+
+    /// Сверка памяти сервера со снапшотом и WAL; нужен `admin_token`. dict с
+    /// `seq`, `keys_checked`, `skipped` (расхождения по ключам, записанным во
+    /// время проверки), `mismatches` и `examples` — {ключ: причина}.
+    #[pyo3(signature = (values=true, max_examples=10))]
+    fn verify<'py>(
+        &self,
+        py: Python<'py>,
+        values: bool,
+        max_examples: u32,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let mut cmd = CacheCommand::Verify(values, max_examples);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        let report = match py
+            .allow_threads(|| self.pool.call(&cmd))
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => VerifyReport::from_map(map).map_err(|e| map_error(e, "verify"))?,
+            Ok(Err(resp)) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from verify: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "verify")),
+        };
+        let d = PyDict::new_bound(py);
+        d.set_item("seq", report.seq)?;
+        d.set_item("keys_checked", report.keys_checked)?;
+        d.set_item("skipped", report.skipped)?;
+        d.set_item("mismatches", report.mismatches)?;
+        d.set_item("examples", report.examples.into_py_dict_bound(py))?;
+        Ok(d)
+    }
+
+    /// Соединения сервера по возрастанию id; нужен `admin_token`. Список dict
+    /// с `id`, `peer`, `connected_at_ms`, `commands`, `bytes_in`, `bytes_out`,
+    /// `last_command`, `idle_ms`, `rate_limited`, `trace_id` и `user`.
+    fn client_list<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let mut cmd = CacheCommand::ClientList;
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        let list = match py
+            .allow_threads(|| self.pool.call(&cmd))
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => {
+                ClientInfo::list_from_map(map).map_err(|e| map_error(e, "client_list"))?
+            }
+            Ok(Err(resp)) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from client_list: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "client_list")),
+        };
+        let out = PyList::empty_bound(py);
+        for c in list {
+            let d = PyDict::new_bound(py);
+            d.set_item("id", c.id)?;
+            d.set_item("peer", c.peer)?;
+            d.set_item("connected_at_ms", c.connected_at_ms)?;
+            d.set_item("commands", c.commands)?;
+            d.set_item("bytes_in", c.bytes_in)?;
+            d.set_item("bytes_out", c.bytes_out)?;
+            d.set_item("last_command", c.last_command)?;
+            d.set_item("idle_ms", c.idle_ms)?;
+            d.set_item("rate_limited", c.rate_limited)?;
+            d.set_item("trace_id", c.trace_id)?;
+            d.set_item("user", c.user)?;
+            out.append(d)?;
+        }
+        Ok(out)
+    }
+
+    /// Закрывает соединение сервера по id из `client_list()`; False — такого
+    /// нет. Нужен `admin_token`.
+    fn client_kill(&self, py: Python<'_>, id: u64) -> PyResult<bool> {
+        let mut cmd = CacheCommand::ClientKill(id);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Int(n)) => Ok(n > 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from client_kill: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "client_kill")),
+        }
+    }
+
+    /// Меняет настройку работающего сервера по имени аргумента `serve()`:
+    /// max_bit_offset, idle_timeout_secs, tombstone_ttl_secs, dedup_min_size,
+    /// rate_limit_per_conn, rate_limit_per_ip, verify_values,
+    /// max_response_size, slowlog_ms. None или 0 — выключить. Нужен `admin_token`.
+    fn config_set(&self, py: Python<'_>, name: String, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let mut cmd = CacheCommand::ConfigSet(name, config_value(value)?);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from config_set: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "config_set")),
+        }
+    }
+
+    /// dict {имя: значение} настроек, что меняет `config_set()` ("*" — всех);
+    /// нужен `admin_token`.
+    #[pyo3(signature = (name="*".to_string()))]
+    fn config_get<'py>(&self, py: Python<'py>, name: String) -> PyResult<Bound<'py, PyDict>> {
+        let mut cmd = CacheCommand::ConfigGet(name);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match py
+            .allow_threads(|| self.pool.call(&cmd))
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => map_to_dict(py, map),
+            Ok(Err(resp)) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from config_get: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "config_get")),
+        }
+    }
+
+    /// dict всех действующих настроек сервера: версия, пределы, настройки
+    /// `config_get()`, режим хранения и пути WAL; у `admin_token` и `wal_key`
+    /// только "set"/"unset". Нужен `admin_token`.
+    fn config<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let mut cmd = CacheCommand::ConfigDump;
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match py
+            .allow_threads(|| self.pool.call(&cmd))
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => map_to_dict(py, map),
+            Ok(Err(resp)) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from config: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "config")),
+        }
+    }
+
+    /// Пользователи ACL по имени: список dict с `name`, `allow_read`,
+    /// `allow_write` и `admin`, без хешей паролей. Нужен `admin_token` или
+    /// пользователь с `admin`.
+    fn acl_list<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let mut cmd = CacheCommand::AclList;
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        let users = match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::AclUsers(users)) => users,
+            Ok(resp) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from acl_list: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "acl_list")),
+        };
+        let out = PyList::empty_bound(py);
+        for u in users {
+            let d = PyDict::new_bound(py);
+            d.set_item("name", u.name)?;
+            d.set_item("allow_read", u.allow_read)?;
+            d.set_item("allow_write", u.allow_write)?;
+            d.set_item("admin", u.admin)?;
+            out.append(d)?;
+        }
+        Ok(out)
+    }
+
+    /// Заводит или заменяет пользователя ACL; сервер переписывает свой
+    /// `acl_file`. Пароль хешируется здесь же, на сервер уходит только хеш;
+    /// готовый хеш из `hash_password()` — `password_hash`. Нужен
+    /// `admin_token` или пользователь с `admin`.
+    #[pyo3(signature = (
+        name,
+        password=None,
+        *,
+        password_hash=None,
+        allow_read=Vec::new(),
+        allow_write=Vec::new(),
+        admin=false
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn acl_set_user(
+        &self,
+        py: Python<'_>,
+        name: String,
+        password: Option<&str>,
+        password_hash: Option<String>,
+        allow_read: Vec<String>,
+        allow_write: Vec<String>,
+        admin: bool,
+    ) -> PyResult<()> {
+        let password_hash = match (password, password_hash) {
+            (Some(password), None) => crate::hash_password(password),
+            (None, Some(hash)) => hash,
+            _ => {
+                return Err(PyValueError::new_err(
+                    "acl_set_user needs exactly one of password and password_hash",
+                ))
+            }
+        };
+        let mut cmd = CacheCommand::AclSetUser(AclUser {
+            name,
+            password_hash,
+            allow_read,
+            allow_write,
+            admin,
+        });
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from acl_set_user: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "acl_set_user")),
+        }
+    }
+
+    /// Удаляет пользователя ACL; False — такого не было. Его соединения
+    /// получают отказ со следующей команды.
+    fn acl_del_user(&self, py: Python<'_>, name: String) -> PyResult<bool> {
+        let mut cmd = CacheCommand::AclDelUser(name);
+        if let Some(token) = &self.admin_token {
+            cmd = CacheCommand::Admin(token.clone(), Box::new(cmd));
+        }
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Int(n)) => Ok(n > 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from acl_del_user: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "acl_del_user")),
+        }
+    }
+
+    fn publish(&self, channel: String, data: &[u8]) -> PyResult<i64> {
+        match self
+            .pool
+            .call(&CacheCommand::Publish(channel, data.to_vec()))
+        {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from publish: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "publish")),
+        }
+    }
+
+    #[pyo3(signature = (channels, callback=None))]
+    fn subscribe(
+        &self,
+        channels: Vec<String>,
+        callback: Option<PyObject>,
+    ) -> PyResult<pubsub::Subscription> {
+        if self.pool.is_closed() {
+            return Err(map_error(CacheError::Closed, "subscribe"));
+        }
+        pubsub::Subscription::open(self.pool.addr(), channels, callback, self.pool.auth())
+            .map_err(|e| map_error(e, "subscribe"))
+    }
+
+    #[pyo3(signature = (prefix, callback, with_values=false))]
+    fn watch(
+        &self,
+        prefix: String,
+        callback: PyObject,
+        with_values: bool,
+    ) -> PyResult<watch::Watch> {
+        if self.pool.is_closed() {
+            return Err(map_error(CacheError::Closed, "watch"));
+        }
+        watch::Watch::open(
+            self.pool.addr(),
+            self.key(&prefix),
+            self.ns.len(),
+            with_values,
+            callback,
+            self.pool.auth(),
+        )
+        .map_err(|e| map_error(e, "watch"))
+    }
+
+    fn __setitem__(&self, py: Python<'_>, key: String, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.set(py, key, value, None)
+    }
+
+    fn __getitem__<'py>(&self, py: Python<'py>, key: String) -> PyResult<Bound<'py, PyBytes>> {
+        match self.pool.call(&CacheCommand::Get(self.key(&key))) {
+            Ok(CacheResponse::Value(v)) => raw_value(py, "__getitem__", &key, &v),
+            Ok(CacheResponse::Nil) => Err(PyKeyError::new_err(key)),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from __getitem__: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "__getitem__")),
+        }
+    }
+
+    fn __delitem__(&self, key: String) -> PyResult<()> {
+        match self.delete(key.clone())? {
+            0 => Err(PyKeyError::new_err(key)),
+            _ => Ok(()),
+        }
+    }
+
+    fn __contains__(&self, key: String) -> PyResult<bool> {
+        match self.pool.call(&CacheCommand::Exists(self.key(&key))) {
+            Ok(CacheResponse::Int(n)) => Ok(n != 0),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from __contains__: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "__contains__")),
+        }
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        Ok(self.len()? as usize)
+    }
+
+    fn __iter__(&self) -> scan::ScanIter {
+        self.keys_iter(String::new())
+    }
+
+    #[pyo3(signature = (prefix=String::new()))]
+    fn keys_iter(&self, prefix: String) -> scan::ScanIter {
+        scan::ScanIter::new(
+            &self.pool,
+            self.key(&prefix),
+            self.ns.len(),
+            scan::ScanMode::Keys,
+        )
+    }
+
+    #[pyo3(signature = (prefix=String::new()))]
+    fn values_iter(&self, prefix: String) -> scan::ScanIter {
+        scan::ScanIter::new(
+            &self.pool,
+            self.key(&prefix),
+            self.ns.len(),
+            scan::ScanMode::Values,
+        )
+    }
+
+    #[pyo3(signature = (prefix=String::new()))]
+    fn items_iter(&self, prefix: String) -> scan::ScanIter {
+        scan::ScanIter::new(
+            &self.pool,
+            self.key(&prefix),
+            self.ns.len(),
+            scan::ScanMode::Items,
+        )
+    }
+
+    /// Ленивый обход пар (ключ, длина значения) для ключей на `prefix` с
+    /// длиной значения от `min_size` до `max_size` байт включительно (None —
+    /// без верхней границы): фильтр применяет сервер, значения не
+    /// передаются.
+    #[pyo3(signature = (prefix=String::new(), min_size=0, max_size=None))]
+    fn scan(
+        &self,
+        prefix: String,
+        min_size: u64,
+        max_size: Option<u64>,
+    ) -> PyResult<scan::ScanIter> {
+        let max_size = max_size.unwrap_or(u64::MAX);
+        if min_size > max_size {
+            return Err(PyValueError::new_err(format!(
+                "scan(): min_size {} is over max_size {}",
+                min_size, max_size
+            )));
+        }
+        let mode = scan::ScanMode::Sizes(min_size, max_size);
+        Ok(scan::ScanIter::new(
+            &self.pool,
+            self.key(&prefix),
+            self.ns.len(),
+            mode,
+        ))
+    }
+
+    /// Ленивый обход пар (ключ, срок в секундах, как у `ttl()`; -1 —
+    /// бессрочный) для ключей на `prefix`. Срок читается вместе с ключом, без
+    /// отдельного запроса на ключ.
+    #[pyo3(signature = (prefix=String::new()))]
+    fn scan_ttl(&self, prefix: String) -> scan::ScanIter {
+        scan::ScanIter::new(
+            &self.pool,
+            self.key(&prefix),
+            self.ns.len(),
+            scan::ScanMode::Ttls,
+        )
+    }
+
+    /// Ленивый обход ключей на `prefix` без срока жизни: `scan_ttl()` с
+    /// фильтром на клиенте.
+    #[pyo3(signature = (prefix=String::new()))]
+    fn keys_without_ttl(&self, prefix: String) -> scan::ScanIter {
+        scan::ScanIter::new(
+            &self.pool,
+            self.key(&prefix),
+            self.ns.len(),
+            scan::ScanMode::NoTtl,
+        )
+    }
+
+    fn items(&self) -> scan::ScanIter {
+        self.items_iter(String::new())
+    }
+
+    /// `update(items)` — запись многих пар, как `dict.update`, командами до
+    /// `batch_bytes` байт с `progress(bytes, keys)` после каждой;
+    /// `update(key, op=..., arg=...)` — атомарное преобразование значения на
+    /// сервере, возвращает новое значение (для `*_i64` — `int`).
+    #[pyo3(signature = (items, op=None, arg=None, *, batch_bytes=None, progress=None))]
+    fn update<'py>(
+        &self,
+        py: Python<'py>,
+        items: &Bound<'py, PyAny>,
+        op: Option<&str>,
+        arg: Option<&Bound<'py, PyAny>>,
+        batch_bytes: Option<usize>,
+        progress: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<PyObject> {
+        let Some(op) = op else {
+            if arg.is_some() {
+                return Err(PyTypeError::new_err("update(): arg needs op"));
+            }
+            return self
+                .update_items(py, items, batch_bytes, progress)
+                .map(|()| py.None());
+        };
+        if batch_bytes.is_some() || progress.is_some() {
+            return Err(PyTypeError::new_err(
+                "update(): batch_bytes and progress are for update(items), not op",
+            ));
+        }
+        let key: String = items.extract().map_err(|_| {
+            PyTypeError::new_err("update(): with op, the first argument must be a str key")
+        })?;
+        let arg =
+            arg.ok_or_else(|| PyTypeError::new_err(format!("update(): op {:?} needs arg", op)))?;
+        let bytes_arg = || -> PyResult<Vec<u8>> {
+            arg.downcast::<PyBytes>()
+                .map(|b| b.as_bytes().to_vec())
+                .map_err(|_| {
+                    PyTypeError::new_err(format!("update(): op {:?} needs a bytes arg", op))
+                })
+        };
+        let int_arg = || -> PyResult<i64> {
+            arg.extract::<i64>().map_err(|_| {
+                PyTypeError::new_err(format!("update(): op {:?} needs an int arg", op))
+            })
+        };
+        let (update_op, numeric) = match op {
+            "append_bytes" => (UpdateOp::AppendBytes(bytes_arg()?), false),
+            "prepend_bytes" => (UpdateOp::PrependBytes(bytes_arg()?), false),
+            "set_if_longer" => (UpdateOp::SetIfLonger(bytes_arg()?), false),
+            "truncate_to" => {
+                let len = u64::try_from(int_arg()?).map_err(|_| {
+                    PyValueError::new_err("update(): truncate_to needs a non-negative arg")
+                })?;
+                (UpdateOp::TruncateTo(len), false)
+            }
+            "add_i64" => (UpdateOp::AddI64(int_arg()?), true),
+            "min_i64" => (UpdateOp::MinI64(int_arg()?), true),
+            "max_i64" => (UpdateOp::MaxI64(int_arg()?), true),
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "update(): unknown op {:?}, expected one of append_bytes, prepend_bytes, \
+                     truncate_to, set_if_longer, add_i64, min_i64, max_i64",
+                    op
+                )))
+            }
+        };
+        let key = self.key(&key);
+        let cmd = CacheCommand::Update(key.clone(), update_op);
+        let res = self.pool.call(&cmd);
+        self.invalidate(&key);
+        match res {
+            // целое сервер хранит десятичным текстом и только что его записал
+            Ok(CacheResponse::Value(v)) if numeric => Ok(std::str::from_utf8(&v)
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+                .ok_or_else(|| PyRuntimeError::new_err("update(): server returned a non-integer"))?
+                .into_py(py)),
+            Ok(CacheResponse::Value(v)) => Ok(PyBytes::new_bound(py, &v).into_py(py)),
+            Ok(CacheResponse::Nil) => Ok(py.None()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from update: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "update")),
+        }
+    }
+
+    /// `op` ("sum", "min", "max" или "count") по целым значениям ключей на
+    /// `prefix` за один обход на сервере, без передачи значений. Нецелое
+    /// значение — ошибка, с `skip_invalid=True` оно пропускается. Сумма за
+    /// пределами i64 — ошибка; min/max без целых значений — `None`.
+    #[pyo3(signature = (prefix, op, skip_invalid=false))]
+    fn aggregate(
+        &self,
+        py: Python<'_>,
+        prefix: String,
+        op: &str,
+        skip_invalid: bool,
+    ) -> PyResult<Option<i64>> {
+        let op = AggOp::parse(op).map_err(|e| frame_error(e, "aggregate"))?;
+        let cmd = CacheCommand::Aggregate(self.key(&prefix), op, skip_invalid);
+        match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::Int(n)) => Ok(Some(n)),
+            Ok(CacheResponse::Nil) => Ok(None),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from aggregate: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "aggregate")),
+        }
+    }
+
+    /// Все пары с ключами на `prefix` одним словарём, страницами ScanItems.
+    /// `max_items` — предохранитель: если пар больше, ValueError вместо
+    /// словаря на всю память.
+    #[pyo3(signature = (prefix=String::new(), max_items=None))]
+    fn to_dict<'py>(
+        &self,
+        py: Python<'py>,
+        prefix: String,
+        max_items: Option<usize>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let out = PyDict::new_bound(py);
+        let mut cursor = 0;
+        loop {
+            let cmd = CacheCommand::ScanItems(cursor, self.key(&prefix), scan::SCAN_PAGE_SIZE);
+            let (next, items) = match py.allow_threads(|| self.pool.call(&cmd)) {
+                Ok(CacheResponse::Items(next, items)) => (next, items),
+                Ok(resp) => {
+                    return Err(PyRuntimeError::new_err(format!(
+                        "Unexpected response from to_dict: {:?}",
+                        resp
+                    )))
+                }
+                Err(e) => return Err(map_error(e, "to_dict")),
+            };
+            for (k, v) in items {
+                out.set_item(&k[self.ns.len()..], PyBytes::new_bound(py, &v))?;
+            }
+            if let Some(max) = max_items {
+                if out.len() > max {
+                    return Err(PyValueError::new_err(format!(
+                        "to_dict(): more than {} items match prefix {:?}",
+                        max, prefix
+                    )));
+                }
+            }
+            if next == 0 {
+                return Ok(out);
+            }
+            cursor = next;
+        }
+    }
+
+    /// Удаляет все ключи с `prefix` (внутри пространства имён) и возвращает
+    /// их число.
+    fn delete_prefix(&self, py: Python<'_>, prefix: String) -> PyResult<i64> {
+        let prefix = self.key(&prefix);
+        let res = py.allow_threads(|| self.pool.call(&CacheCommand::DelPrefix(prefix.clone())));
+        self.invalidate_prefix(&prefix);
+        match res {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from delete_prefix: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "delete_prefix")),
+        }
+    }
+
+    /// Удаляет все ключи пространства имён, а у корневого клиента — все
+    /// ключи сервера. Возвращает их число.
+    fn clear(&self, py: Python<'_>) -> PyResult<i64> {
+        self.delete_prefix(py, String::new())
+    }
+
+    /// Счётчики ближнего кэша: `hits`, `misses`, `evictions` (вытеснения LRU),
+    /// `size` и `capacity`; без `local_cache_size` — все нули.
+    fn local_cache_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        match &self.near {
+            Some(near) => near.stats(py),
+            None => {
+                let d = PyDict::new_bound(py);
+                for field in ["hits", "misses", "evictions", "size", "capacity"] {
+                    d.set_item(field, 0)?;
+                }
+                Ok(d)
+            }
+        }
+    }
+
+    /// Счётчики пула соединений: `idle` (свободные соединения сейчас),
+    /// `reconnects` (открыты взамен оборванных сервером) и
+    /// `validation_failures` (выброшены проверкой при выдаче из пула).
+    fn pool_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = self.pool.stats();
+        let d = PyDict::new_bound(py);
+        d.set_item("idle", stats.idle)?;
+        d.set_item("reconnects", stats.reconnects)?;
+        d.set_item("validation_failures", stats.validation_failures)?;
+        Ok(d)
+    }
+
+    /// Сбрасывает `key` из ближнего кэша, а без аргумента — все ключи
+    /// пространства имён (у корневого клиента — весь кэш). Сервер не трогает.
+    #[pyo3(signature = (key=None))]
+    fn invalidate_local(&self, key: Option<String>) {
+        match key {
+            Some(key) => self.invalidate(&self.key(&key)),
+            None => self.invalidate_prefix(&self.ns),
+        }
+    }
+
+    fn wal_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        match self
+            .pool
+            .call(&CacheCommand::WalStats)
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => map_to_dict(py, map),
+            Ok(Err(resp)) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from wal_stats: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "wal_stats")),
+        }
+    }
+
+    fn info<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        match self
+            .pool
+            .call(&CacheCommand::Info)
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => map_to_dict(py, map),
+            Ok(Err(resp)) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from info: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "info")),
+        }
+    }
+
+    /// Попадания и промахи чтений на сервере: `hits`, `misses`, `hit_rate` и
+    /// `by_prefix` — префикс -> те же поля (с `serve(stats_prefix_depth=1)`).
+    /// Префиксы сверх `stats_max_prefixes` посчитаны в записи `"*"`. Ключи —
+    /// как на сервере, с пространством имён.
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let resp = py.allow_threads(|| self.pool.call(&CacheCommand::Stats));
+        let map = match resp.map(CacheResponse::into_map) {
+            Ok(Ok(map)) => map,
+            Ok(Err(resp)) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from stats: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "stats")),
+        };
+        let (prefixes, totals): (Vec<_>, Vec<_>) = map
+            .into_iter()
+            .partition(|(k, _)| k == "by_prefix" || k == "other");
+        let d = map_to_dict(py, totals)?;
+        let by_prefix = PyDict::new_bound(py);
+        for (k, v) in prefixes {
+            match (k.as_str(), v) {
+                ("by_prefix", ResponseValue::Nested(prefixes)) => {
+                    for (prefix, counts) in prefixes {
+                        if let ResponseValue::Nested(counts) = counts {
+                            by_prefix.set_item(prefix, map_to_dict(py, counts)?)?;
+                        }
+                    }
+                }
+                ("other", ResponseValue::Nested(counts)) => {
+                    by_prefix.set_item("*", map_to_dict(py, counts)?)?;
+                }
+                _ => {}
+            }
+        }
+        d.set_item("by_prefix", by_prefix)?;
+        Ok(d)
+    }
+}
+
+/// =======================
+/// Дамп на стороне клиента
+/// =======================
+const EXPORT_PAGE_SIZE: u32 = 1000;
+
+/// Дамп в локальный файл клиента: пары забираются страницами через ScanItems,
+/// сервер на диск при этом ничего не пишет.
+#[pyfunction(signature = (addr, path, format="binary"))]
+fn export_to_file(py: Python<'_>, addr: String, path: String, format: &str) -> PyResult<u64> {
+    let format: DumpFormat = format
+        .parse()
+        .map_err(|e: CacheError| PyValueError::new_err(e.to_string()))?;
+    let addr = TransportAddr::parse(&addr);
+    py.allow_threads(|| {
+        let mut count = 0;
+        let mut failure = None;
+        let res = wal::replace_file(Path::new(&path), |f| {
+            let mut w = dump::DumpWriter::new(f, format)?;
+            let mut cursor = 0;
+            loop {
+                let cmd = CacheCommand::ScanItems(cursor, String::new(), EXPORT_PAGE_SIZE);
+                let (next, items) = match send_cmd_sync(&addr, cmd) {
+                    Ok(CacheResponse::Items(next, items)) => (next, items),
+                    Ok(resp) => {
+                        failure = Some(PyRuntimeError::new_err(format!(
+                            "Unexpected response from scan: {:?}",
+                            resp
+                        )));
+                        return Err(std::io::Error::other("scan failed"));
+                    }
+                    Err(e) => {
+                        failure = Some(map_error(e, "export_to_file"));
+                        return Err(std::io::Error::other("scan failed"));
+                    }
+                };
+                for (k, v) in &items {
+                    w.write(k, v)?;
+                }
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+            count = w.finish()?;
+            Ok(())
+        });
+        match (res, failure) {
+            (_, Some(err)) => Err(err),
+            (Err(e), None) => Err(PyRuntimeError::new_err(format!(
+                "export_to_file: write {}: {}",
+                path, e
+            ))),
+            (Ok(()), None) => Ok(count),
+        }
+    })
+}
+
+/// =======================
+/// Отладка WAL без сервера
+/// =======================
+#[pyfunction(signature = (path, limit=100, wal_key=None))]
+fn inspect_wal<'py>(
+    py: Python<'py>,
+    path: String,
+    limit: usize,
+    wal_key: Option<&[u8]>,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let key = resolve_wal_key(wal_key)?;
+    let records = wal::inspect(Path::new(&path), key.as_ref(), limit)
+        .map_err(|e| map_error(e, "inspect_wal"))?;
+    records
+        .into_iter()
+        .map(|r| {
+            let d = PyDict::new_bound(py);
+            d.set_item("seq", r.seq)?;
+            d.set_item("op", r.op)?;
+            d.set_item("key", r.key)?;
+            if let Some(other) = r.other_key {
+                d.set_item("other_key", other)?;
+            }
+            d.set_item("value_size", r.value_size)?;
+            d.set_item("written_at_ms", r.written_at)?;
+            d.set_item("offset", r.offset)?;
+            d.set_item("file", r.file.to_string_lossy().into_owned())?;
+            Ok(d)
+        })
+        .collect()
+}
+
+#[pyfunction(signature = (path, output_path, wal_key=None))]
+fn repair_wal<'py>(
+    py: Python<'py>,
+    path: String,
+    output_path: String,
+    wal_key: Option<&[u8]>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let key = resolve_wal_key(wal_key)?;
+    let report = wal::repair(Path::new(&path), Path::new(&output_path), key.as_ref())
+        .map_err(|e| map_error(e, "repair_wal"))?;
+    let d = PyDict::new_bound(py);
+    d.set_item("records_kept", report.kept)?;
+    d.set_item("records_dropped", report.dropped)?;
+    d.set_item(
+        "bytes_skipped",
+        report.skipped.iter().map(|(a, b)| b - a).sum::<u64>(),
+    )?;
+    d.set_item("skipped_ranges", report.skipped)?;
+    Ok(d)
+}
+
+fn verify_report_dict<'py>(
+    py: Python<'py>,
+    report: &wal::VerifyReport,
+) -> PyResult<Bound<'py, PyDict>> {
+    let file = |path: &Path| path.to_string_lossy().into_owned();
+    let d = PyDict::new_bound(py);
+    d.set_item("status", report.status().as_str())?;
+    d.set_item("segments", report.segments)?;
+    d.set_item("bytes", report.bytes)?;
+    d.set_item("records", report.records)?;
+    let ops = PyDict::new_bound(py);
+    for (op, n) in &report.ops {
+        ops.set_item(op, n)?;
+    }
+    d.set_item("ops", ops)?;
+    d.set_item("first_seq", report.first_seq)?;
+    d.set_item("last_seq", report.last_seq)?;
+    let torn: Vec<_> = report.torn.iter().map(|(f, at)| (file(f), *at)).collect();
+    d.set_item("torn", torn)?;
+    let corrupt = PyList::empty_bound(py);
+    for region in &report.corrupt {
+        let r = PyDict::new_bound(py);
+        r.set_item("file", file(&region.file))?;
+        r.set_item("start", region.start)?;
+        r.set_item("end", region.end)?;
+        r.set_item("records_lost", region.records_lost)?;
+        corrupt.append(r)?;
+    }
+    d.set_item("corrupt", corrupt)?;
+    let breaks = PyList::empty_bound(py);
+    for b in &report.seq_breaks {
+        let r = PyDict::new_bound(py);
+        r.set_item("file", file(&b.file))?;
+        r.set_item("expected", b.expected)?;
+        r.set_item("found", b.found)?;
+        breaks.append(r)?;
+    }
+    d.set_item("seq_breaks", breaks)?;
+    Ok(d)
+}
+
+/// Хеш пароля для `password_hash` в файле ACL (`serve(acl_file=...)`).
+#[pyfunction]
+fn hash_password(password: &str) -> String {
+    crate::hash_password(password)
+}
+
+/// Проверка журнала без загрузки, GIL отпущен. `progress(dict)` зовётся не
+/// чаще раза в секунду и в конце; его исключение поднимается после проверки.
+#[pyfunction(signature = (path, wal_key=None, progress=None, *, raise_on_corrupt=false))]
+fn verify_wal<'py>(
+    py: Python<'py>,
+    path: String,
+    wal_key: Option<&[u8]>,
+    progress: Option<&Bound<'py, PyAny>>,
+    raise_on_corrupt: bool,
+) -> PyResult<Bound<'py, PyDict>> {
+    let key = resolve_wal_key(wal_key)?;
+    let progress = progress.map(|p| p.clone().unbind());
+    let mut failure: Option<PyErr> = None;
+    let res = py.allow_threads(|| {
+        wal::verify(Path::new(&path), key.as_ref(), &mut |p| {
+            let Some(progress) = progress.as_ref().filter(|_| failure.is_none()) else {
+                return;
+            };
+            Python::with_gil(|py| {
+                let d = PyDict::new_bound(py);
+                let res = d
+                    .set_item("records", p.records)
+                    .and_then(|_| d.set_item("bytes_read", p.bytes_read))
+                    .and_then(|_| d.set_item("bytes_total", p.bytes_total))
+                    .and_then(|_| d.set_item("elapsed", p.elapsed.as_secs_f64()))
+                    .and_then(|_| d.set_item("done", p.done))
+                    .and_then(|_| progress.bind(py).call1((d,)));
+                failure = res.err();
+            });
+        })
+    });
+    let report = res.map_err(|e| map_error(e, "verify_wal"))?;
+    if let Some(err) = failure {
+        return Err(err);
+    }
+    let d = verify_report_dict(py, &report)?;
+    if raise_on_corrupt && report.status() == wal::VerifyStatus::Corrupt {
+        let err = WalCorruptError::new_err(format!(
+            "verify_wal: {} is corrupt: {} corrupt regions, {} seq breaks",
+            path,
+            report.corrupt.len(),
+            report.seq_breaks.len()
+        ));
+        err.value_bound(py).setattr("report", &d)?;
+        return Err(err);
+    }
+    Ok(d)
+}
+
+/// =======================
+/// Нагрузочный прогон
+/// =======================
+#[pyfunction(signature = (addr, ops=100_000, value_size=256, workers=8, mix="80get/20set"))]
+fn benchmark<'py>(
+    py: Python<'py>,
+    addr: String,
+    ops: u64,
+    value_size: usize,
+    workers: usize,
+    mix: &str,
+) -> PyResult<Bound<'py, PyDict>> {
+    let opts = crate::BenchOptions {
+        ops,
+        value_size,
+        workers,
+        get_percent: crate::parse_mix(mix).map_err(|e| PyValueError::new_err(e.to_string()))?,
+        ..Default::default()
+    };
+    let report = py
+        .allow_threads(|| crate::benchmark(&addr, &opts))
+        .map_err(|e| map_error(e, "benchmark"))?;
+    let d = PyDict::new_bound(py);
+    d.set_item("ops", report.ops)?;
+    d.set_item("gets", report.gets)?;
+    d.set_item("sets", report.sets)?;
+    d.set_item("seconds", report.elapsed.as_secs_f64())?;
+    d.set_item("ops_per_sec", report.ops_per_sec)?;
+    d.set_item("p50_us", report.p50_us)?;
+    d.set_item("p90_us", report.p90_us)?;
+    d.set_item("p99_us", report.p99_us)?;
+    d.set_item("p999_us", report.p999_us)?;
+    d.set_item("max_us", report.max_us)?;
+    d.set_item("verified", report.verified)?;
+    Ok(d)
+}
+
+/// =======================
+/// Python-модуль
+/// =======================
+
+#[pymodule]
+fn tiny_mp_cache(py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("TinyCacheError", py.get_type_bound::<TinyCacheError>())?;
+    m.add("BindError", py.get_type_bound::<BindError>())?;
+    m.add("InvalidKeyError", py.get_type_bound::<InvalidKeyError>())?;
+    m.add("ChecksumError", py.get_type_bound::<ChecksumError>())?;
+    m.add(
+        "ResponseTooLargeError",
+        py.get_type_bound::<ResponseTooLargeError>(),
+    )?;
+    m.add(
+        "LockNotOwnedError",
+        py.get_type_bound::<LockNotOwnedError>(),
+    )?;
+    m.add(
+        "PartialWriteError",
+        py.get_type_bound::<PartialWriteError>(),
+    )?;
+    m.add("WalCorruptError", py.get_type_bound::<WalCorruptError>())?;
+    m.add_class::<TinyCache>()?;
+    m.add_class::<cluster::TinyCacheCluster>()?;
+    m.add_class::<local::TinyCacheLocal>()?;
+    m.add_class::<pubsub::Subscription>()?;
+    m.add_class::<scan::ScanIter>()?;
+    m.add_class::<watch::Watch>()?;
+    m.add_class::<spawn::ServerHandle>()?;
+    m.add_class::<bloom::KeyFilter>()?;
+    m.add_class::<meta::ValueMeta>()?;
+    m.add_class::<lock::TinyCacheLock>()?;
+    m.add_class::<ids::IdAllocator>()?;
+    m.add_class::<trace::TraceId>()?;
+    m.add_function(wrap_pyfunction!(serve, m)?)?;
+    m.add_function(wrap_pyfunction!(spawn::spawn_server, m)?)?;
+    m.add_function(wrap_pyfunction!(trace::trace_id, m)?)?;
+    m.add_function(wrap_pyfunction!(inspect_wal, m)?)?;
+    m.add_function(wrap_pyfunction!(repair_wal, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_wal, m)?)?;
+    m.add_function(wrap_pyfunction!(hash_password, m)?)?;
+    m.add_function(wrap_pyfunction!(export_to_file, m)?)?;
+    m.add_function(wrap_pyfunction!(benchmark, m)?)?;
+    #[cfg(unix)]
+    m.add_function(wrap_pyfunction!(serve_unix, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use crate::BloomFilter;
+use pyo3::prelude::*;
+
+/// Фильтр Блума от `TinyCache.export_bloom()`: проверка «ключ, наверное,
+/// есть» без обращения к серверу. Это снимок на момент сборки, записанные
+/// потом ключи фильтр не видит.
+#[pyclass]
+pub struct KeyFilter {
+    filter: BloomFilter,
+    // префикс пространства имён клиента, дописывается к проверяемым ключам
+    ns: String,
+}
+
+impl KeyFilter {
+    pub(crate) fn new(filter: BloomFilter, ns: String) -> Self {
+        Self { filter, ns }
+    }
+}
+
+#[pymethods]
+impl KeyFilter {
+    /// False — ключа точно не было; True — был или это ложное срабатывание.
+    fn maybe_contains(&self, key: &str) -> bool {
+        self.filter.maybe_contains(&format!("{}{}", self.ns, key))
+    }
+
+    fn __contains__(&self, key: &str) -> bool {
+        self.maybe_contains(key)
+    }
+
+    #[getter]
+    fn hashes(&self) -> u32 {
+        self.filter.hashes()
+    }
+
+    /// Размер битового массива в битах.
+    #[getter]
+    fn bits(&self) -> u64 {
+        self.filter.bit_len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "KeyFilter(bits={}, hashes={})",
+            self.filter.bit_len(),
+            self.filter.hashes()
+        )
+    }
+}
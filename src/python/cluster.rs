@@ -0,0 +1,487 @@
+use super::{map_error, KEYS_PAGE};
+use crate::error::CacheError;
+use crate::{send_cmd_sync, CacheCommand, CacheResponse, TransportAddr};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Шардирование на стороне клиента: ключи раскладываются по серверам через
+/// кольцо consistent hashing с виртуальными узлами, так что добавление или
+/// удаление сервера переносит только ~1/N ключей. Узел, на котором подряд
+/// `max_failures` раз случилась сетевая ошибка, на `eject_seconds` выводится
+/// из кольца: его ключи временно уходят на следующий по кольцу узел.
+struct Node {
+    name: String,
+    addr: TransportAddr,
+    // сетевые ошибки подряд
+    failures: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Node {
+    fn is_available(&self) -> bool {
+        match *self.ejected_until.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    fn ejected_for(&self) -> Option<Duration> {
+        let until = (*self.ejected_until.lock().unwrap_or_else(|e| e.into_inner()))?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    fn record(&self, res: &Result<CacheResponse, CacheError>, max_failures: u32, eject: Duration) {
+        let mut ejected = self.ejected_until.lock().unwrap_or_else(|e| e.into_inner());
+        match res {
+            // ошибки самого сервера (read-only, busy, ...) про здоровье узла не говорят
+            Err(CacheError::Network(e)) => {
+                if self.failures.fetch_add(1, Ordering::Relaxed) + 1 >= max_failures {
+                    eprintln!(
+                        "cluster node {} ejected for {:?} after {} failures: {}",
+                        self.name, eject, max_failures, e
+                    );
+                    *ejected = Some(Instant::now() + eject);
+                    self.failures.store(0, Ordering::Relaxed);
+                }
+            }
+            _ => {
+                self.failures.store(0, Ordering::Relaxed);
+                *ejected = None;
+            }
+        }
+    }
+}
+
+/// FNV-1a: одинаковый во всех процессах и версиях, в отличие от DefaultHasher.
+fn ring_hash(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for &b in data {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    // FNV плохо перемешивает хвост, а ключи часто отличаются только им
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^ (h >> 33)
+}
+
+// (курсор следующей страницы, пары)
+type ScanPage<'py> = (u64, Vec<(String, Bound<'py, PyBytes>)>);
+
+fn unexpected(op: &str, resp: CacheResponse) -> PyErr {
+    PyRuntimeError::new_err(format!("Unexpected response from {}: {:?}", op, resp))
+}
+
+#[pyclass]
+pub struct TinyCacheCluster {
+    nodes: Vec<Node>,
+    // (точка на кольце, индекс узла), по возрастанию точки
+    ring: Vec<(u64, usize)>,
+    max_failures: u32,
+    eject: Duration,
+}
+
+impl TinyCacheCluster {
+    /// Узел-хозяин ключа без учёта здоровья — то, что видит `rebalance_report`.
+    fn owner(&self, key: &str) -> usize {
+        let h = ring_hash(key.as_bytes());
+        let i = self.ring.partition_point(|&(point, _)| point < h);
+        self.ring[i % self.ring.len()].1
+    }
+
+    /// Первый доступный узел по кольцу начиная с хозяина ключа. Если выброшены
+    /// все, остаётся хозяин: пусть запрос покажет, ожил ли он.
+    fn route(&self, key: &str) -> usize {
+        let h = ring_hash(key.as_bytes());
+        let start = self.ring.partition_point(|&(point, _)| point < h);
+        (0..self.ring.len())
+            .map(|i| self.ring[(start + i) % self.ring.len()].1)
+            .find(|&n| self.nodes[n].is_available())
+            .unwrap_or_else(|| self.owner(key))
+    }
+
+    fn call(&self, node: usize, cmd: CacheCommand) -> Result<CacheResponse, CacheError> {
+        let node = &self.nodes[node];
+        let res = send_cmd_sync(&node.addr, cmd);
+        node.record(&res, self.max_failures, self.eject);
+        res
+    }
+
+    fn call_key(&self, op: &str, key: &str, cmd: CacheCommand) -> PyResult<CacheResponse> {
+        let node = self.route(key);
+        self.call(node, cmd)
+            .map_err(|e| map_error(e, &format!("{} on {}", op, self.nodes[node].name)))
+    }
+
+    /// Выполняет `f` на всех доступных узлах параллельно.
+    fn fan_out<T: Send>(
+        &self,
+        py: Python<'_>,
+        op: &str,
+        f: impl Fn(usize) -> Result<T, CacheError> + Sync,
+    ) -> PyResult<Vec<T>> {
+        let live: Vec<usize> = (0..self.nodes.len())
+            .filter(|&n| self.nodes[n].is_available())
+            .collect();
+        let results = py.allow_threads(|| {
+            thread::scope(|s| {
+                let f = &f;
+                let handles: Vec<_> = live.iter().map(|&n| (n, s.spawn(move || f(n)))).collect();
+                handles
+                    .into_iter()
+                    .map(|(n, h)| (n, h.join().expect("cluster worker panicked")))
+                    .collect::<Vec<_>>()
+            })
+        });
+        results
+            .into_iter()
+            .map(|(n, res)| {
+                res.map_err(|e| map_error(e, &format!("{} on {}", op, self.nodes[n].name)))
+            })
+            .collect()
+    }
+
+    /// Len каждого узла; недоступный или выброшенный узел — None.
+    fn node_lens(&self, py: Python<'_>) -> PyResult<Vec<Option<i64>>> {
+        let mut lens = vec![None; self.nodes.len()];
+        let live = self.fan_out(py, "len", |n| {
+            // ошибку узла отдаём как None: отчёт нужен и при неполном кластере
+            Ok((
+                n,
+                match self.call(n, CacheCommand::Len) {
+                    Ok(CacheResponse::Int(len)) => Some(len),
+                    _ => None,
+                },
+            ))
+        })?;
+        for (n, len) in live {
+            lens[n] = len;
+        }
+        Ok(lens)
+    }
+
+    /// Раскладывает ключи по узлам: узел -> (позиции в исходном списке, ключи).
+    fn split<T>(&self, items: Vec<(String, T)>) -> HashMap<usize, Vec<(usize, String, T)>> {
+        let mut groups: HashMap<usize, Vec<(usize, String, T)>> = HashMap::new();
+        for (i, (key, v)) in items.into_iter().enumerate() {
+            groups
+                .entry(self.route(&key))
+                .or_default()
+                .push((i, key, v));
+        }
+        groups
+    }
+
+    /// Ключи каждого узла выполняются по одному, но узлы — параллельно.
+    fn per_node<T: Send, R: Send>(
+        &self,
+        py: Python<'_>,
+        op: &str,
+        items: Vec<(String, T)>,
+        f: impl Fn(usize, String, T) -> Result<R, CacheError> + Sync,
+    ) -> PyResult<Vec<(usize, R)>> {
+        let groups = self.split(items);
+        let results = py.allow_threads(|| {
+            thread::scope(|s| {
+                let handles: Vec<_> = groups
+                    .into_iter()
+                    .map(|(node, group)| {
+                        let f = &f;
+                        let h = s.spawn(move || {
+                            group
+                                .into_iter()
+                                .map(|(i, key, v)| f(node, key, v).map(|r| (i, r)))
+                                .collect::<Result<Vec<_>, _>>()
+                        });
+                        (node, h)
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|(n, h)| (n, h.join().expect("cluster worker panicked")))
+                    .collect::<Vec<_>>()
+            })
+        });
+        let mut out = Vec::new();
+        for (n, res) in results {
+            out.extend(
+                res.map_err(|e| map_error(e, &format!("{} on {}", op, self.nodes[n].name)))?,
+            );
+        }
+        out.sort_unstable_by_key(|&(i, _)| i);
+        Ok(out)
+    }
+}
+
+fn expect_value(op: &str, resp: CacheResponse) -> Result<Option<Vec<u8>>, CacheError> {
+    match resp {
+        CacheResponse::Value(v) => Ok(Some(v)),
+        CacheResponse::Nil => Ok(None),
+        other => Err(CacheError::Internal(format!(
+            "Unexpected response from {}: {:?}",
+            op, other
+        ))),
+    }
+}
+
+#[pymethods]
+impl TinyCacheCluster {
+    #[new]
+    #[pyo3(signature = (addrs, vnodes=160, max_failures=3, eject_seconds=5.0))]
+    fn new(
+        addrs: Vec<String>,
+        vnodes: u32,
+        max_failures: u32,
+        eject_seconds: f64,
+    ) -> PyResult<Self> {
+        if addrs.is_empty() {
+            return Err(PyValueError::new_err("cluster needs at least one address"));
+        }
+        if vnodes == 0 || max_failures == 0 || !eject_seconds.is_finite() || eject_seconds < 0.0 {
+            return Err(PyValueError::new_err(
+                "vnodes and max_failures must be positive, eject_seconds non-negative",
+            ));
+        }
+        let mut ring = Vec::with_capacity(addrs.len() * vnodes as usize);
+        for (n, addr) in addrs.iter().enumerate() {
+            for v in 0..vnodes {
+                ring.push((ring_hash(format!("{}#{}", addr, v).as_bytes()), n));
+            }
+        }
+        ring.sort_unstable();
+        let nodes = addrs
+            .into_iter()
+            .map(|name| Node {
+                addr: TransportAddr::parse(&name),
+                name,
+                failures: AtomicU32::new(0),
+                ejected_until: Mutex::new(None),
+            })
+            .collect();
+        Ok(Self {
+            nodes,
+            ring,
+            max_failures,
+            eject: Duration::from_secs_f64(eject_seconds),
+        })
+    }
+
+    /// Адрес узла, который сейчас обслуживает ключ.
+    fn node_for(&self, key: &str) -> String {
+        self.nodes[self.route(key)].name.clone()
+    }
+
+    fn set(&self, key: String, value: &[u8]) -> PyResult<()> {
+        let cmd = CacheCommand::Set(key.clone(), value.to_vec());
+        match self.call_key("set", &key, cmd)? {
+            CacheResponse::Ok => Ok(()),
+            resp => Err(unexpected("set", resp)),
+        }
+    }
+
+    fn get<'py>(&self, py: Python<'py>, key: String) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        let resp = self.call_key("get", &key, CacheCommand::Get(key.clone()))?;
+        let v = expect_value("get", resp).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok(v.map(|v| PyBytes::new_bound(py, &v)))
+    }
+
+    fn pop<'py>(&self, py: Python<'py>, key: String) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        let resp = self.call_key("pop", &key, CacheCommand::Pop(key.clone()))?;
+        let v = expect_value("pop", resp).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok(v.map(|v| PyBytes::new_bound(py, &v)))
+    }
+
+    fn delete(&self, key: String) -> PyResult<i64> {
+        match self.call_key("delete", &key, CacheCommand::Del(key.clone()))? {
+            CacheResponse::Int(n) => Ok(n),
+            resp => Err(unexpected("delete", resp)),
+        }
+    }
+
+    fn get_many<'py>(
+        &self,
+        py: Python<'py>,
+        keys: Vec<String>,
+    ) -> PyResult<Vec<Option<Bound<'py, PyBytes>>>> {
+        let items = keys.into_iter().map(|k| (k, ())).collect();
+        let values = self.per_node(py, "get_many", items, |node, key, ()| {
+            expect_value("get", self.call(node, CacheCommand::Get(key))?)
+        })?;
+        Ok(values
+            .into_iter()
+            .map(|(_, v)| v.map(|v| PyBytes::new_bound(py, &v)))
+            .collect())
+    }
+
+    fn set_many(&self, py: Python<'_>, items: HashMap<String, Vec<u8>>) -> PyResult<()> {
+        self.per_node(
+            py,
+            "set_many",
+            items.into_iter().collect(),
+            |node, key, value| match self.call(node, CacheCommand::Set(key, value))? {
+                CacheResponse::Ok => Ok(()),
+                other => Err(CacheError::Internal(format!(
+                    "Unexpected response from set: {:?}",
+                    other
+                ))),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn delete_many(&self, py: Python<'_>, keys: Vec<String>) -> PyResult<i64> {
+        let items = keys.into_iter().map(|k| (k, ())).collect();
+        let deleted = self.per_node(py, "delete_many", items, |node, key, ()| {
+            match self.call(node, CacheCommand::Del(key))? {
+                CacheResponse::Int(n) => Ok(n),
+                other => Err(CacheError::Internal(format!(
+                    "Unexpected response from delete: {:?}",
+                    other
+                ))),
+            }
+        })?;
+        Ok(deleted.into_iter().map(|(_, n)| n).sum())
+    }
+
+    /// Ключи со всех доступных узлов, без определённого порядка. С
+    /// `sort=True` каждый узел отдаёт первые `offset + limit` своих ключей по
+    /// возрастанию, а страница вырезается из их слияния.
+    #[pyo3(signature = (pattern, sort=false, offset=0, limit=None))]
+    fn keys(
+        &self,
+        py: Python<'_>,
+        pattern: String,
+        sort: bool,
+        offset: u64,
+        limit: Option<u64>,
+    ) -> PyResult<Vec<String>> {
+        let cmd = if sort {
+            let Some(prefix) = pattern.strip_suffix('*') else {
+                return Ok(Vec::new());
+            };
+            let end = offset.saturating_add(limit.unwrap_or(KEYS_PAGE));
+            CacheCommand::KeysSorted(prefix.to_string(), 0, end)
+        } else if offset != 0 || limit.is_some() {
+            return Err(PyValueError::new_err("offset and limit require sort=True"));
+        } else {
+            CacheCommand::Keys(pattern)
+        };
+        let parts = self.fan_out(py, "keys", |n| match self.call(n, cmd.clone())? {
+            CacheResponse::Keys(keys) => Ok(keys),
+            other => Err(CacheError::Internal(format!(
+                "Unexpected response from keys: {:?}",
+                other
+            ))),
+        })?;
+        let mut keys: Vec<String> = parts.into_iter().flatten().collect();
+        if sort {
+            keys.sort_unstable();
+            let limit = limit.unwrap_or(KEYS_PAGE).try_into().unwrap_or(usize::MAX);
+            keys = keys.into_iter().skip(offset as usize).take(limit).collect();
+        }
+        Ok(keys)
+    }
+
+    fn len(&self, py: Python<'_>) -> PyResult<i64> {
+        let lens = self.fan_out(py, "len", |n| match self.call(n, CacheCommand::Len)? {
+            CacheResponse::Int(len) => Ok(len),
+            other => Err(CacheError::Internal(format!(
+                "Unexpected response from len: {:?}",
+                other
+            ))),
+        })?;
+        Ok(lens.into_iter().sum())
+    }
+
+    /// Страница обхода всего кластера: узлы обходятся по очереди, в курсоре
+    /// закодированы номер узла и курсор внутри него (0 — начало и конец обхода).
+    #[pyo3(signature = (cursor=0, prefix=String::new(), count=1000))]
+    fn scan_items<'py>(
+        &self,
+        py: Python<'py>,
+        cursor: u64,
+        prefix: String,
+        count: u32,
+    ) -> PyResult<ScanPage<'py>> {
+        let n = self.nodes.len() as u64;
+        let (mut node, mut node_cursor) = (cursor % n, cursor / n);
+        let mut items = Vec::new();
+        while node < n && items.len() < count as usize {
+            let idx = node as usize;
+            let want = count - items.len() as u32;
+            // выброшенный узел пропускаем: его ключи сейчас недоступны
+            let next = if self.nodes[idx].is_available() {
+                let cmd = CacheCommand::ScanItems(node_cursor, prefix.clone(), want);
+                match py.allow_threads(|| self.call(idx, cmd)) {
+                    Ok(CacheResponse::Items(next, page)) => {
+                        items.extend(page);
+                        next
+                    }
+                    Ok(resp) => return Err(unexpected("scan_items", resp)),
+                    Err(e) => {
+                        return Err(map_error(
+                            e,
+                            &format!("scan_items on {}", self.nodes[idx].name),
+                        ))
+                    }
+                }
+            } else {
+                0
+            };
+            if next == 0 {
+                node += 1;
+                node_cursor = 0;
+            } else {
+                node_cursor = next;
+            }
+        }
+        let next = if node >= n { 0 } else { node_cursor * n + node };
+        let items = items
+            .into_iter()
+            .map(|(k, v)| (k, PyBytes::new_bound(py, &v)))
+            .collect();
+        Ok((next, items))
+    }
+
+    /// Распределение ключей по узлам: реальное число ключей (None — узел
+    /// недоступен), доля кольца, которой узел владеет, и состояние узла.
+    fn rebalance_report<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let lens = self.node_lens(py)?;
+        let mut share = vec![0u128; self.nodes.len()];
+        for (i, &(point, n)) in self.ring.iter().enumerate() {
+            // узел владеет дугой от предыдущей точки до своей
+            let prev = if i == 0 {
+                self.ring.last().expect("non-empty ring").0
+            } else {
+                self.ring[i - 1].0
+            };
+            share[n] += point.wrapping_sub(prev) as u128;
+        }
+        let total: i64 = lens.iter().flatten().sum();
+        let report = PyDict::new_bound(py);
+        for (n, node) in self.nodes.iter().enumerate() {
+            let entry = PyDict::new_bound(py);
+            entry.set_item("keys", lens[n])?;
+            entry.set_item(
+                "key_share",
+                lens[n].map(|k| {
+                    if total == 0 {
+                        0.0
+                    } else {
+                        k as f64 / total as f64
+                    }
+                }),
+            )?;
+            entry.set_item("ring_share", share[n] as f64 / 2f64.powi(64))?;
+            entry.set_item("ejected_for", node.ejected_for().map(|d| d.as_secs_f64()))?;
+            report.set_item(&node.name, entry)?;
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,125 @@
+//! Callback'и `serve(on_ready=..., on_error=..., on_connection=...)`. Потоки
+//! сервера только кладут событие в очередь, а callback'и под GIL вызывает
+//! отдельный поток-уведомитель: медленный callback не задерживает ни приём,
+//! ни обслуживание соединений.
+
+use crate::{EventHook, ServerEvent};
+use pyo3::exceptions::{PyRuntimeError, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::thread;
+
+/// Сколько событий ждёт уведомителя; не поместившиеся выбрасываются.
+const QUEUE: usize = 1024;
+
+#[derive(Default)]
+pub(crate) struct Hooks {
+    on_ready: Option<PyObject>,
+    on_error: Option<PyObject>,
+    on_connection: Option<PyObject>,
+}
+
+impl Hooks {
+    pub(crate) fn new(
+        py: Python<'_>,
+        on_ready: Option<PyObject>,
+        on_error: Option<PyObject>,
+        on_connection: Option<PyObject>,
+    ) -> PyResult<Self> {
+        for (name, cb) in [
+            ("on_ready", &on_ready),
+            ("on_error", &on_error),
+            ("on_connection", &on_connection),
+        ] {
+            if cb.as_ref().is_some_and(|cb| !cb.bind(py).is_callable()) {
+                return Err(PyTypeError::new_err(format!("{} must be callable", name)));
+            }
+        }
+        Ok(Self {
+            on_ready,
+            on_error,
+            on_connection,
+        })
+    }
+
+    fn callback(&self, event: &ServerEvent) -> Option<&PyObject> {
+        match event {
+            ServerEvent::Ready { .. } => self.on_ready.as_ref(),
+            ServerEvent::Connection { .. } => self.on_connection.as_ref(),
+            ServerEvent::ConnectionError { .. } => self.on_error.as_ref(),
+        }
+    }
+
+    /// Запускает уведомитель и возвращает hook для
+    /// `PersistentCore::set_event_hook`; без callback'ов — None.
+    pub(crate) fn into_event_hook(self) -> PyResult<Option<EventHook>> {
+        let wanted = [
+            self.on_ready.is_some(),
+            self.on_connection.is_some(),
+            self.on_error.is_some(),
+        ];
+        if wanted == [false; 3] {
+            return Ok(None);
+        }
+        let (tx, rx) = mpsc::sync_channel(QUEUE);
+        thread::Builder::new()
+            .name("tiny-mp-cache-notifier".into())
+            .spawn(move || notify(rx, self))
+            .map_err(|e| PyRuntimeError::new_err(format!("spawn notifier thread: {}", e)))?;
+        let warned = AtomicBool::new(false);
+        Ok(Some(Box::new(move |event: ServerEvent| {
+            let kind = match event {
+                ServerEvent::Ready { .. } => 0,
+                ServerEvent::Connection { .. } => 1,
+                ServerEvent::ConnectionError { .. } => 2,
+            };
+            if !wanted[kind] {
+                return;
+            }
+            if let Err(TrySendError::Full(_)) = tx.try_send(event) {
+                if !warned.swap(true, Ordering::Relaxed) {
+                    eprintln!("serve callbacks fall behind, some server events are dropped");
+                }
+            }
+        })))
+    }
+}
+
+fn notify(rx: Receiver<ServerEvent>, hooks: Hooks) {
+    for event in rx {
+        Python::with_gil(|py| {
+            let Some(callback) = hooks.callback(&event) else {
+                return;
+            };
+            let res = event_dict(py, event).and_then(|d| callback.call1(py, (d,)));
+            // исключение в callback не должно останавливать сервер
+            if let Err(e) = res {
+                e.print(py);
+            }
+        });
+    }
+}
+
+fn event_dict(py: Python<'_>, event: ServerEvent) -> PyResult<Bound<'_, PyDict>> {
+    let d = PyDict::new_bound(py);
+    match event {
+        ServerEvent::Ready { addr } => {
+            d.set_item("event", "ready")?;
+            d.set_item("addr", addr)?;
+        }
+        ServerEvent::Connection { id, peer } => {
+            d.set_item("event", "connection")?;
+            d.set_item("id", id)?;
+            d.set_item("peer", peer)?;
+        }
+        ServerEvent::ConnectionError { id, peer, error } => {
+            d.set_item("event", "error")?;
+            d.set_item("id", id)?;
+            d.set_item("peer", peer)?;
+            d.set_item("error", error)?;
+        }
+    }
+    Ok(d)
+}
@@ -0,0 +1,112 @@
+//! `IdAllocator`: возрастающие номера для многих процессов. Номера берутся у
+//! сервера блоками через `IncrRange` и раздаются из блока локально, так что
+//! запрос к серверу — один на `block_size` номеров. Номера уникальны среди
+//! всех процессов и перезапусков сервера; неиспользованный остаток блока
+//! (процесс завершился) пропадает, поэтому номера идут с дырами и между
+//! процессами не упорядочены.
+
+use super::{map_error, TinyCache};
+use crate::{CacheCommand, CacheResponse};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::Mutex;
+
+// выданный блок: следующий номер и последний номер блока
+struct Block {
+    next: i64,
+    last: i64,
+}
+
+/// Раздаёт номера из блоков `block_size` штук со счётчика `key`. Потоки
+/// одного объекта берут номера по очереди; блок запрашивает тот, кому не
+/// хватило.
+#[pyclass(frozen)]
+pub struct IdAllocator {
+    cache: TinyCache,
+    // ключ с префиксом пространства имён
+    key: String,
+    block_size: u64,
+    block: Mutex<Block>,
+}
+
+#[pymethods]
+impl IdAllocator {
+    #[new]
+    #[pyo3(signature = (cache, key, block_size=100))]
+    fn new(cache: PyRef<'_, TinyCache>, key: String, block_size: u64) -> PyResult<Self> {
+        if block_size == 0 {
+            return Err(PyValueError::new_err(
+                "IdAllocator(): block_size must be positive",
+            ));
+        }
+        Ok(Self {
+            key: cache.key(&key),
+            cache: cache.clone(),
+            block_size,
+            block: Mutex::new(Block { next: 1, last: 0 }),
+        })
+    }
+
+    /// Следующий номер; при пустом блоке — запрос к серверу за новым.
+    fn next_id(&self, py: Python<'_>) -> PyResult<i64> {
+        // блокировка и запрос без GIL: поток, ждущий блок, не держит остальных
+        let res = py.allow_threads(|| {
+            let mut block = self.block.lock().unwrap_or_else(|e| e.into_inner());
+            if block.next > block.last {
+                let cmd = CacheCommand::IncrRange(self.key.clone(), self.block_size);
+                match self.cache.pool.call(&cmd)? {
+                    CacheResponse::Int(prev) => {
+                        block.next = prev + 1;
+                        block.last = prev + self.block_size as i64;
+                    }
+                    resp => return Ok(Err(resp)),
+                }
+            }
+            block.next += 1;
+            Ok(Ok(block.next - 1))
+        });
+        self.cache.invalidate(&self.key);
+        match res {
+            Ok(Ok(id)) => Ok(id),
+            Ok(Err(resp)) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from next_id: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "next_id")),
+        }
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<i64> {
+        self.next_id(py)
+    }
+
+    #[getter]
+    fn key(&self) -> &str {
+        &self.key[self.cache.ns.len()..]
+    }
+
+    #[getter]
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Сколько номеров осталось в текущем блоке.
+    #[getter]
+    fn remaining(&self) -> u64 {
+        let block = self.block.lock().unwrap_or_else(|e| e.into_inner());
+        (block.last - block.next + 1).max(0) as u64
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "IdAllocator(key={:?}, block_size={}, remaining={})",
+            self.key(),
+            self.block_size,
+            self.remaining()
+        )
+    }
+}
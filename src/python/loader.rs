@@ -0,0 +1,62 @@
+//! `serve(loader=...)`: Python-функция для промахов Get, см. `crate::loader`.
+//! Загрузчик зовётся под GIL с потоков пула, его исключение становится
+//! ошибкой Get у клиента, а сервер работает дальше.
+
+use super::lock::millis;
+use crate::{Loaded, Loader};
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyTuple};
+use std::time::Duration;
+
+/// Оборачивает `loader(key)`; `ttl` — срок загруженных значений в секундах,
+/// если функция не вернула свой.
+pub(crate) fn wrap(
+    py: Python<'_>,
+    loader: Option<PyObject>,
+    ttl: Option<f64>,
+) -> PyResult<Option<Loader>> {
+    let Some(loader) = loader else {
+        return Ok(None);
+    };
+    if !loader.bind(py).is_callable() {
+        return Err(PyTypeError::new_err("loader must be callable"));
+    }
+    let ttl = ttl
+        .map(|t| millis("serve", "loader_ttl_secs", t).map(Duration::from_millis))
+        .transpose()?;
+    Ok(Some(Box::new(move |key: &str| {
+        Python::with_gil(|py| {
+            let res = loader.call1(py, (key,))?;
+            loaded(res.bind(py), ttl)
+        })
+        .map_err(|e| e.to_string())
+    })))
+}
+
+// None, bytes или (bytes, ttl в секундах; None — бессрочно)
+fn loaded(res: &Bound<'_, PyAny>, ttl: Option<Duration>) -> PyResult<Option<Loaded>> {
+    if res.is_none() {
+        return Ok(None);
+    }
+    let (value, ttl) = match res.downcast::<PyTuple>() {
+        Ok(pair) if pair.len() == 2 => {
+            let ttl = match pair.get_item(1)?.extract::<Option<f64>>()? {
+                Some(t) => Some(Duration::from_millis(millis("loader", "ttl", t)?)),
+                None => None,
+            };
+            (pair.get_item(0)?, ttl)
+        }
+        _ => (res.clone(), ttl),
+    };
+    match value.downcast::<PyBytes>() {
+        Ok(b) => Ok(Some(Loaded {
+            value: b.as_bytes().to_vec(),
+            ttl,
+        })),
+        Err(_) => Err(PyTypeError::new_err(format!(
+            "loader must return bytes, (bytes, ttl) or None, got {}",
+            value.get_type().name()?
+        ))),
+    }
+}
@@ -0,0 +1,271 @@
+use super::hooks::Hooks;
+use super::{config_value, map_error, map_to_dict, open_core, ServeArgs};
+use crate::core::Deadline;
+use crate::encoding::decode_response;
+use crate::error::CacheError;
+use crate::{
+    execute, execute_admin, CacheCommand, CacheResponse, PeerAccess, PersistentCore,
+    DEFAULT_STATS_PREFIXES,
+};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::sync::Arc;
+
+/// Встроенный режим: тот же `PersistentCore`, что у сервера, но прямо в
+/// процессе Python, без сокетов и bincode. Команды идут через тот же `execute`,
+/// поэтому поведение и WAL совпадают с сетевым сервером: каталог, записанный
+/// `TinyCacheLocal`, потом открывает `serve()`, и наоборот. Один журнал
+/// одновременно открывает на запись только один процесс, см. `wal::lock_path`.
+#[pyclass]
+pub struct TinyCacheLocal {
+    core: Arc<PersistentCore>,
+}
+
+impl TinyCacheLocal {
+    /// Вид на уже открытое ядро, например для `serve(preload=...)`.
+    pub(super) fn from_core(core: Arc<PersistentCore>) -> Self {
+        Self { core }
+    }
+
+    /// Выполняет команду без GIL: вызовы из разных потоков Python идут параллельно.
+    fn run(&self, py: Python<'_>, cmd: CacheCommand) -> Result<CacheResponse, CacheError> {
+        // значения сжатых клиентов с того же ядра отдаются распакованными
+        py.allow_threads(|| execute(cmd, &self.core).and_then(decode_response))
+    }
+}
+
+fn unexpected(op: &str, resp: CacheResponse) -> PyErr {
+    PyRuntimeError::new_err(format!("Unexpected response from {}: {:?}", op, resp))
+}
+
+#[pymethods]
+impl TinyCacheLocal {
+    #[new]
+    #[pyo3(signature = (
+        wal_dir=None,
+        compact_after=None,
+        fsync="everysec",
+        wal_segment_size=None,
+        persistence=true,
+        wal_key=None,
+        read_only=false,
+        case_insensitive_keys=false,
+        name="tiny-mp-cache"
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        wal_dir: Option<String>,
+        compact_after: Option<u64>,
+        fsync: &str,
+        wal_segment_size: Option<u64>,
+        persistence: bool,
+        wal_key: Option<&[u8]>,
+        read_only: bool,
+        case_insensitive_keys: bool,
+        name: &str,
+    ) -> PyResult<Self> {
+        let core = open_core(ServeArgs {
+            wal_dir,
+            name,
+            compact_after,
+            fsync,
+            wal_segment_size,
+            persistence,
+            wal_key,
+            replicate_from: None,
+            read_only,
+            admin_token: None,
+            max_bit_offset: None,
+            max_response_size: None,
+            slowlog_ms: None,
+            idle_timeout_secs: None,
+            tombstone_ttl_secs: None,
+            dedup_min_size: None,
+            history_depth: 0,
+            stats_prefix_depth: 0,
+            stats_prefix_sep: ":",
+            stats_max_prefixes: DEFAULT_STATS_PREFIXES,
+            rate_limit_per_conn: None,
+            rate_limit_per_ip: None,
+            key_normalization: None,
+            case_insensitive_keys,
+            replay_threads: None,
+            verify_values: false,
+            loader: None,
+            loader_threads: 0,
+            write_feed: None,
+            peer_access: PeerAccess::default(),
+            hooks: Hooks::default(),
+            acl_file: None,
+            chaos: None,
+        })?;
+        Ok(Self { core })
+    }
+
+    fn set(&self, py: Python<'_>, key: String, value: &[u8]) -> PyResult<()> {
+        match self.run(py, CacheCommand::Set(key, value.to_vec())) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(unexpected("set", resp)),
+            Err(e) => Err(map_error(e, "set")),
+        }
+    }
+
+    fn get<'py>(&self, py: Python<'py>, key: String) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        match self.run(py, CacheCommand::Get(key)) {
+            Ok(CacheResponse::Value(v)) => Ok(Some(PyBytes::new_bound(py, &v))),
+            Ok(CacheResponse::Nil) => Ok(None),
+            Ok(resp) => Err(unexpected("get", resp)),
+            Err(e) => Err(map_error(e, "get")),
+        }
+    }
+
+    #[pyo3(signature = (key, timeout=5.0))]
+    fn get_blocking<'py>(
+        &self,
+        py: Python<'py>,
+        key: String,
+        timeout: f64,
+    ) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        if !(0.0..=crate::blocking::MAX_BLOCK.as_secs_f64()).contains(&timeout) {
+            return Err(PyValueError::new_err(format!(
+                "timeout must be between 0 and {} seconds",
+                crate::blocking::MAX_BLOCK.as_secs()
+            )));
+        }
+        match self.run(py, CacheCommand::BGet(key, (timeout * 1000.0) as u64)) {
+            Ok(CacheResponse::Value(v)) => Ok(Some(PyBytes::new_bound(py, &v))),
+            Ok(CacheResponse::Nil) => Ok(None),
+            Ok(resp) => Err(unexpected("get_blocking", resp)),
+            Err(e) => Err(map_error(e, "get_blocking")),
+        }
+    }
+
+    fn pop<'py>(&self, py: Python<'py>, key: String) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        match self.run(py, CacheCommand::Pop(key)) {
+            Ok(CacheResponse::Value(v)) => Ok(Some(PyBytes::new_bound(py, &v))),
+            Ok(CacheResponse::Nil) => Ok(None),
+            Ok(resp) => Err(unexpected("pop", resp)),
+            Err(e) => Err(map_error(e, "pop")),
+        }
+    }
+
+    fn delete(&self, py: Python<'_>, key: String) -> PyResult<i64> {
+        match self.run(py, CacheCommand::Del(key)) {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(unexpected("delete", resp)),
+            Err(e) => Err(map_error(e, "delete")),
+        }
+    }
+
+    fn keys(&self, py: Python<'_>, pattern: String) -> PyResult<Vec<String>> {
+        match self.run(py, CacheCommand::Keys(pattern)) {
+            Ok(CacheResponse::Keys(keys)) => Ok(keys),
+            Ok(resp) => Err(unexpected("keys", resp)),
+            Err(e) => Err(map_error(e, "keys")),
+        }
+    }
+
+    fn len(&self, py: Python<'_>) -> PyResult<i64> {
+        match self.run(py, CacheCommand::Len) {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(unexpected("len", resp)),
+            Err(e) => Err(map_error(e, "len")),
+        }
+    }
+
+    fn save(&self, py: Python<'_>) -> PyResult<()> {
+        match self.run(py, CacheCommand::Save) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(unexpected("save", resp)),
+            Err(e) => Err(map_error(e, "save")),
+        }
+    }
+
+    fn bgsave(&self, py: Python<'_>) -> PyResult<()> {
+        match self.run(py, CacheCommand::BgSave) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(unexpected("bgsave", resp)),
+            Err(e) => Err(map_error(e, "bgsave")),
+        }
+    }
+
+    #[pyo3(signature = (path, format="binary"))]
+    fn export(&self, py: Python<'_>, path: String, format: &str) -> PyResult<i64> {
+        match self.run(py, CacheCommand::Export(format.to_string(), path)) {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(unexpected("export", resp)),
+            Err(e) => Err(map_error(e, "export")),
+        }
+    }
+
+    #[pyo3(signature = (path, replace=false))]
+    fn import_dump(&self, py: Python<'_>, path: String, replace: bool) -> PyResult<i64> {
+        match self.run(py, CacheCommand::Import(path, replace)) {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(unexpected("import_dump", resp)),
+            Err(e) => Err(map_error(e, "import_dump")),
+        }
+    }
+
+    // процесс сам владеет ядром, так что админ-токен не нужен
+    fn set_read_only(&self, py: Python<'_>, read_only: bool) -> PyResult<()> {
+        let cmd = CacheCommand::SetReadOnly(read_only);
+        match py.allow_threads(|| execute_admin(cmd, &self.core, Deadline::NONE)) {
+            Ok(CacheResponse::Ok) => Ok(()),
+            Ok(resp) => Err(unexpected("set_read_only", resp)),
+            Err(e) => Err(map_error(e, "set_read_only")),
+        }
+    }
+
+    fn config_set(&self, py: Python<'_>, name: String, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let value = config_value(value)?;
+        py.allow_threads(|| self.core.config_set(&name, &value))
+            .map_err(|e| map_error(e, "config_set"))
+    }
+
+    #[pyo3(signature = (name="*".to_string()))]
+    fn config_get<'py>(&self, py: Python<'py>, name: String) -> PyResult<Bound<'py, PyDict>> {
+        match self.core.config_get(&name) {
+            Ok(map) => map_to_dict(py, map),
+            Err(e) => Err(map_error(e, "config_get")),
+        }
+    }
+
+    fn config<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        match self.core.config_dump() {
+            Ok(map) => map_to_dict(py, map),
+            Err(e) => Err(map_error(e, "config")),
+        }
+    }
+
+    fn publish(&self, py: Python<'_>, channel: String, data: &[u8]) -> PyResult<i64> {
+        match self.run(py, CacheCommand::Publish(channel, data.to_vec())) {
+            Ok(CacheResponse::Int(n)) => Ok(n),
+            Ok(resp) => Err(unexpected("publish", resp)),
+            Err(e) => Err(map_error(e, "publish")),
+        }
+    }
+
+    fn wal_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        match self
+            .run(py, CacheCommand::WalStats)
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => map_to_dict(py, map),
+            Ok(Err(resp)) => Err(unexpected("wal_stats", resp)),
+            Err(e) => Err(map_error(e, "wal_stats")),
+        }
+    }
+
+    fn info<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        match self
+            .run(py, CacheCommand::Info)
+            .map(CacheResponse::into_map)
+        {
+            Ok(Ok(map)) => map_to_dict(py, map),
+            Ok(Err(resp)) => Err(unexpected("info", resp)),
+            Err(e) => Err(map_error(e, "info")),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! `TinyCacheLock`: блокировка между процессами на одном ключе. Захват —
+//! `set_nx` ключа со случайным токеном и сроком аренды, продление —
+//! `PExtendIfEquals`, освобождение — `delete_if` с тем же токеном, так что
+//! владелец, чья аренда истекла, не снимет и не продлит чужую блокировку.
+//! Ожидание — опрос с растущей паузой до `MAX_POLL_INTERVAL`; очереди нет,
+//! порядок захвата не гарантирован.
+
+use super::{map_error, LockNotOwnedError, TinyCache};
+use crate::{CacheCommand, CacheResponse};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyTuple};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const TOKEN_LEN: usize = 16;
+
+// секунды в мс, не меньше одной
+pub(super) fn millis(op: &str, what: &str, seconds: f64) -> PyResult<u64> {
+    let ms = (seconds * 1000.0).round();
+    if !ms.is_finite() || ms < 1.0 {
+        return Err(PyValueError::new_err(format!(
+            "{}(): {} must be at least 1 ms, got {} s",
+            op, what, seconds
+        )));
+    }
+    Ok(ms as u64)
+}
+
+/// Блокировка на ключе `name` с арендой `ttl` секунд: если владелец умер,
+/// не освободив её, ключ истекает сам. Долгую работу под блокировкой
+/// продлевают `extend()`. Поддерживает `with`.
+#[pyclass]
+pub struct TinyCacheLock {
+    cache: TinyCache,
+    name: String,
+    ttl: f64,
+    // токен текущего захвата; None — блокировка не взята этим объектом
+    token: Option<Vec<u8>>,
+}
+
+impl TinyCacheLock {
+    pub(super) fn create(cache: TinyCache, name: String, ttl: f64) -> PyResult<Self> {
+        millis("TinyCacheLock", "ttl", ttl)?;
+        Ok(Self {
+            cache,
+            name,
+            ttl,
+            token: None,
+        })
+    }
+
+    fn not_owned(&self, op: &str, why: &str) -> PyErr {
+        LockNotOwnedError::new_err(format!("{}(): lock {:?} {}", op, self.name, why))
+    }
+}
+
+#[pymethods]
+impl TinyCacheLock {
+    #[new]
+    #[pyo3(signature = (cache, name, ttl=30.0))]
+    fn new(cache: PyRef<'_, TinyCache>, name: String, ttl: f64) -> PyResult<Self> {
+        Self::create(cache.clone(), name, ttl)
+    }
+
+    /// Берёт блокировку и возвращает, удалось ли. `blocking=False` — одна
+    /// попытка; иначе ждёт до `timeout` секунд (None — сколько угодно).
+    #[pyo3(signature = (blocking=true, timeout=None))]
+    fn acquire(&mut self, py: Python<'_>, blocking: bool, timeout: Option<f64>) -> PyResult<bool> {
+        if self.token.is_some() {
+            return Err(PyRuntimeError::new_err(format!(
+                "acquire(): lock {:?} is already held by this TinyCacheLock",
+                self.name
+            )));
+        }
+        let deadline = match timeout {
+            Some(_) if !blocking => {
+                return Err(PyValueError::new_err(
+                    "acquire(): can't specify a timeout for a non-blocking call",
+                ))
+            }
+            Some(t) if t.is_nan() || t < 0.0 => {
+                return Err(PyValueError::new_err(format!(
+                    "acquire(): timeout must be non-negative, got {}",
+                    t
+                )))
+            }
+            // необозримо долгий срок — то же, что None
+            Some(t) => Duration::try_from_secs_f64(t)
+                .ok()
+                .and_then(|t| Instant::now().checked_add(t)),
+            None if blocking => None,
+            None => Some(Instant::now()),
+        };
+        let mut token = vec![0; TOKEN_LEN];
+        OsRng.fill_bytes(&mut token);
+        let mut pause = MIN_POLL_INTERVAL;
+        loop {
+            if self
+                .cache
+                .set_nx(py, self.name.clone(), &token, Some(self.ttl))?
+            {
+                self.token = Some(token);
+                return Ok(true);
+            }
+            let wait = match deadline {
+                Some(at) => match at.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => pause.min(left),
+                    _ => return Ok(false),
+                },
+                None => pause,
+            };
+            py.allow_threads(|| thread::sleep(wait));
+            py.check_signals()?;
+            pause = (pause * 2).min(MAX_POLL_INTERVAL);
+        }
+    }
+
+    /// Снимает блокировку. `LockNotOwnedError`, если этот объект её не брал
+    /// или аренда истекла раньше (ключ мог взять другой процесс, и работа под
+    /// блокировкой шла без защиты).
+    fn release(&mut self, py: Python<'_>) -> PyResult<()> {
+        let Some(token) = self.token.take() else {
+            return Err(self.not_owned("release", "is not held by this TinyCacheLock"));
+        };
+        if !self.cache.delete_if(py, self.name.clone(), &token)? {
+            return Err(self.not_owned("release", "expired before release"));
+        }
+        Ok(())
+    }
+
+    /// Продлевает аренду на `additional` секунд сверх оставшейся, только если
+    /// ключ всё ещё хранит наш токен; иначе `LockNotOwnedError`.
+    fn extend(&mut self, py: Python<'_>, additional: f64) -> PyResult<()> {
+        let add_ms = millis("extend", "additional", additional)?;
+        let Some(token) = &self.token else {
+            return Err(self.not_owned("extend", "is not held by this TinyCacheLock"));
+        };
+        let key = self.cache.key(&self.name);
+        let cmd = CacheCommand::PExtendIfEquals(key.clone(), token.clone(), add_ms);
+        let res = py.allow_threads(|| self.cache.pool.call(&cmd));
+        self.cache.invalidate(&key);
+        match res {
+            Ok(CacheResponse::Int(0)) => {
+                self.token = None;
+                Err(self.not_owned("extend", "expired before extend"))
+            }
+            Ok(CacheResponse::Int(_)) => Ok(()),
+            Ok(resp) => Err(PyRuntimeError::new_err(format!(
+                "Unexpected response from extend: {:?}",
+                resp
+            ))),
+            Err(e) => Err(map_error(e, "extend")),
+        }
+    }
+
+    /// Взят ли ключ этим объектом; сервер не спрашивается, так что аренда
+    /// при этом могла и истечь.
+    #[getter]
+    fn held(&self) -> bool {
+        self.token.is_some()
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[getter]
+    fn ttl(&self) -> f64 {
+        self.ttl
+    }
+
+    /// Значение ключа при захвате, 16 случайных байт; None — блокировка не взята.
+    #[getter]
+    fn token<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        self.token.as_ref().map(|t| PyBytes::new_bound(py, t))
+    }
+
+    fn __enter__(mut slf: PyRefMut<'_, Self>) -> PyResult<PyRefMut<'_, Self>> {
+        let py = slf.py();
+        slf.acquire(py, true, None)?;
+        Ok(slf)
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&mut self, py: Python<'_>, _args: &Bound<'_, PyTuple>) -> PyResult<bool> {
+        self.release(py)?;
+        Ok(false)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "TinyCacheLock(name={:?}, ttl={}, held={})",
+            self.name,
+            self.ttl,
+            self.token.is_some()
+        )
+    }
+}
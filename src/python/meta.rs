@@ -0,0 +1,65 @@
+use crate::ValueMeta as Meta;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+/// Ответ `TinyCache.get_with_meta()`: значение и его свежесть по часам
+/// сервера, так что часы клиента не нужны.
+#[pyclass(frozen)]
+pub struct ValueMeta {
+    meta: Meta,
+}
+
+impl ValueMeta {
+    pub(crate) fn new(meta: Meta) -> Self {
+        Self { meta }
+    }
+}
+
+#[pymethods]
+impl ValueMeta {
+    #[getter]
+    fn value<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.meta.value)
+    }
+
+    /// Unix-время в мс последней записи; None — неизвестно (ключ из старого
+    /// снапшота или WAL).
+    #[getter]
+    fn written_at_ms(&self) -> Option<u64> {
+        Some(self.meta.written_at).filter(|&at| at > 0)
+    }
+
+    /// Оставшийся срок в мс; None — ключ бессрочный.
+    #[getter]
+    fn ttl_ms(&self) -> Option<u64> {
+        self.meta.ttl_ms
+    }
+
+    #[getter]
+    fn version(&self) -> u64 {
+        self.meta.version
+    }
+
+    /// Часы сервера в момент ответа, unix-время в мс.
+    #[getter]
+    fn server_time_ms(&self) -> u64 {
+        self.meta.now
+    }
+
+    /// Сколько мс прошло с последней записи по часам сервера.
+    #[getter]
+    fn age_ms(&self) -> Option<u64> {
+        self.written_at_ms()
+            .map(|at| self.meta.now.saturating_sub(at))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ValueMeta(len={}, written_at_ms={:?}, ttl_ms={:?}, version={})",
+            self.meta.value.len(),
+            self.written_at_ms(),
+            self.meta.ttl_ms,
+            self.meta.version
+        )
+    }
+}
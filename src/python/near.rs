@@ -0,0 +1,164 @@
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Ближний кэш клиента для `get()`: LRU на `capacity` ключей, запись живёт
+/// не дольше `ttl`. Ключи полные, с префиксом пространства имён, поэтому
+/// виды `namespace()` делят один кэш. Отсутствие ключа не кэшируется.
+pub(crate) struct NearCache {
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<State>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Slot>,
+    // тик последнего обращения -> ключ; первым вытесняется самый старый
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+struct Slot {
+    // bytes неизменяемы: попадание отдаёт тот же объект без копии
+    value: Py<PyBytes>,
+    expires: Instant,
+    tick: u64,
+}
+
+impl State {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(slot) = self.entries.get_mut(key) {
+            let key = self
+                .order
+                .remove(&slot.tick)
+                .expect("order tracks every entry");
+            slot.tick = tick;
+            self.order.insert(tick, key);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(slot) = self.entries.remove(key) {
+            self.order.remove(&slot.tick);
+        }
+    }
+}
+
+impl NearCache {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            state: Mutex::new(State::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Значение из кэша; просроченная запись удаляется и считается промахом.
+    pub(crate) fn get(&self, py: Python<'_>, key: &str) -> Option<Py<PyBytes>> {
+        let mut state = self.state();
+        let value = match state.entries.get(key) {
+            Some(slot) if slot.expires > Instant::now() => Some(slot.value.clone_ref(py)),
+            Some(_) => {
+                state.remove(key);
+                None
+            }
+            None => None,
+        };
+        match value {
+            Some(v) => {
+                state.touch(key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(v)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(&self, key: String, value: Py<PyBytes>) {
+        let mut state = self.state();
+        state.remove(&key);
+        state.tick += 1;
+        let tick = state.tick;
+        state.order.insert(tick, key.clone());
+        let expires = Instant::now() + self.ttl;
+        state.entries.insert(
+            key,
+            Slot {
+                value,
+                expires,
+                tick,
+            },
+        );
+        while state.entries.len() > self.capacity {
+            let (_, oldest) = state.order.pop_first().expect("entries are not empty");
+            state.entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn invalidate(&self, key: &str) {
+        self.state().remove(key);
+    }
+
+    pub(crate) fn invalidate_prefix(&self, prefix: &str) {
+        let mut state = self.state();
+        if prefix.is_empty() {
+            state.entries.clear();
+            state.order.clear();
+            return;
+        }
+        let keys: Vec<String> = state
+            .entries
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in keys {
+            state.remove(&key);
+        }
+    }
+
+    pub(crate) fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new_bound(py);
+        d.set_item("hits", self.hits.load(Ordering::Relaxed))?;
+        d.set_item("misses", self.misses.load(Ordering::Relaxed))?;
+        d.set_item("evictions", self.evictions.load(Ordering::Relaxed))?;
+        d.set_item("size", self.state().entries.len())?;
+        d.set_item("capacity", self.capacity)?;
+        Ok(d)
+    }
+}
+
+/// Callback для `watch()`: событие по ключу сбрасывает его из ближнего кэша,
+/// так что записи других клиентов видны раньше истечения TTL.
+#[pyclass]
+pub(crate) struct Invalidator(pub(crate) Arc<NearCache>);
+
+#[pymethods]
+impl Invalidator {
+    fn __call__(&self, event: &Bound<'_, PyDict>) -> PyResult<()> {
+        if let Some(key) = event.get_item("key")? {
+            self.0.invalidate(&key.extract::<String>()?);
+        }
+        Ok(())
+    }
+}
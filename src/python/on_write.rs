@@ -0,0 +1,111 @@
+//! `serve(on_write=...)`: мутации ключей с `on_write_prefix` уходят в
+//! `on_write(key, op, value)` с отдельного потока под GIL, см. `crate::feed`.
+//! Путь записи только кладёт событие в очередь. При выходе из `serve()` и
+//! из интерпретатора очередь дочитывается; процесс, убитый сигналом, теряет
+//! то, что callback не успел разобрать.
+
+use super::map_error;
+use crate::{decode_value, Drain, Overflow, WriteFeed};
+use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyCFunction};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Сколько при остановке ждать, пока callback разберёт очередь.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Запущенный поток callback'а.
+pub(crate) struct OnWrite {
+    feed: Arc<WriteFeed>,
+    drain: Mutex<Drain>,
+}
+
+impl OnWrite {
+    pub(crate) fn feed(&self) -> Arc<WriteFeed> {
+        Arc::clone(&self.feed)
+    }
+
+    /// Закрывает поток и ждёт, пока callback дочитает очередь; вызывать без GIL.
+    pub(crate) fn drain(&self) {
+        self.feed.close();
+        let drained = self
+            .drain
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .wait(DRAIN_TIMEOUT);
+        if !drained {
+            eprintln!(
+                "on_write did not drain its queue within {}s, the rest of the events are lost",
+                DRAIN_TIMEOUT.as_secs()
+            );
+        }
+    }
+}
+
+/// `drain` у запущенного callback'а, с отпущенным GIL.
+pub(crate) fn drain(py: Python<'_>, hook: Option<&OnWrite>) {
+    if let Some(hook) = hook {
+        py.allow_threads(|| hook.drain());
+    }
+}
+
+/// Запускает поток callback'а и регистрирует дочитывание очереди в
+/// `atexit`; без callback'а — None.
+pub(crate) fn start(
+    py: Python<'_>,
+    callback: Option<PyObject>,
+    prefix: String,
+    queue: usize,
+    overflow: &str,
+) -> PyResult<Option<Arc<OnWrite>>> {
+    let Some(callback) = callback else {
+        return Ok(None);
+    };
+    if !callback.bind(py).is_callable() {
+        return Err(PyTypeError::new_err("on_write must be callable"));
+    }
+    if queue == 0 {
+        return Err(PyValueError::new_err("on_write_queue must be positive"));
+    }
+    let overflow: Overflow = overflow
+        .parse()
+        .map_err(|e: crate::error::CacheError| PyValueError::new_err(e.to_string()))?;
+    let (feed, events, done) = WriteFeed::new(prefix, queue, overflow);
+    thread::Builder::new()
+        .name("tiny-mp-cache-on-write".into())
+        .spawn(move || {
+            for event in events {
+                Python::with_gil(|py| {
+                    // сжатое клиентом значение callback получает распакованным
+                    let value = event.value.map(decode_value).transpose();
+                    let value = match value {
+                        Ok(value) => value.map(|v| PyBytes::new_bound(py, &v)),
+                        Err(e) => {
+                            map_error(e, "on_write").print(py);
+                            return;
+                        }
+                    };
+                    let res = callback.call1(py, (event.key, event.op.as_str(), value));
+                    // исключение в callback не должно останавливать поток
+                    if let Err(e) = res {
+                        e.print(py);
+                    }
+                });
+            }
+        })
+        .map_err(|e| PyRuntimeError::new_err(format!("spawn on_write thread: {}", e)))?;
+    let hook = Arc::new(OnWrite {
+        feed,
+        drain: Mutex::new(done),
+    });
+    // serve() в фоновом потоке не возвращается: очередь дочитывается при выходе
+    let at_exit = Arc::clone(&hook);
+    let drain_at_exit = PyCFunction::new_closure_bound(py, None, None, move |args, _| {
+        drain(args.py(), Some(&at_exit));
+    })?;
+    py.import_bound("atexit")?
+        .call_method1("register", (drain_at_exit,))?;
+    Ok(Some(hook))
+}
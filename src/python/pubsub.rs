@@ -0,0 +1,170 @@
+use super::map_error;
+use crate::acl::Credentials;
+use crate::error::CacheError;
+use crate::{read_response, write_frame, CacheCommand, CacheResponse, Conn, TransportAddr};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// сервер шлёт heartbeat раз в секунду, так что тишина дольше — обрыв
+pub(super) const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Подписка на стороне клиента. С callback сообщения разбирает фоновый поток,
+/// без него подписку читают как итератор пар `(channel, payload)`.
+#[pyclass]
+pub struct Subscription {
+    channels: Vec<String>,
+    // None в режиме callback: соединение забрал поток
+    conn: Mutex<Option<Conn>>,
+    // копия сокета: close() закрывает её и будит ждущее чтение
+    control: Conn,
+    closed: Arc<AtomicBool>,
+    listener: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Subscription {
+    pub(crate) fn open(
+        addr: &TransportAddr,
+        channels: Vec<String>,
+        callback: Option<PyObject>,
+        auth: Option<&Credentials>,
+    ) -> Result<Self, CacheError> {
+        let mut conn = Conn::connect(addr)?;
+        conn.set_read_timeout(Some(READ_TIMEOUT))?;
+        if let Some(auth) = auth {
+            auth.login(&mut conn)?;
+        }
+        write_frame(&mut conn, &CacheCommand::Subscribe(channels.clone()))?;
+        match read_response(&mut conn)? {
+            CacheResponse::Ok => {}
+            CacheResponse::Error(msg) => return Err(CacheError::Server(msg)),
+            other => {
+                return Err(CacheError::Internal(format!(
+                    "Unexpected response from subscribe: {:?}",
+                    other
+                )))
+            }
+        }
+        let control = conn.try_clone()?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let (conn, listener) = match callback {
+            None => (Some(conn), None),
+            Some(callback) => {
+                let closed = closed.clone();
+                let name = format!("{:?}", channels);
+                let handle = thread::Builder::new()
+                    .name("tiny-mp-cache-subscriber".into())
+                    .spawn(move || listen(conn, callback, &closed, &name))
+                    .map_err(|e| CacheError::Internal(format!("spawn subscriber thread: {}", e)))?;
+                (None, Some(handle))
+            }
+        };
+        Ok(Self {
+            channels,
+            conn: Mutex::new(conn),
+            control,
+            closed,
+            listener: Mutex::new(listener),
+        })
+    }
+
+    fn shutdown(&self) {
+        if !self.closed.swap(true, Ordering::SeqCst) {
+            self.control.shutdown();
+        }
+    }
+}
+
+/// Следующее сообщение; heartbeat-кадры пропускаются.
+fn next_message(conn: &mut Conn) -> Result<(String, Vec<u8>), CacheError> {
+    loop {
+        match read_response(conn)? {
+            CacheResponse::Message(ch, payload) => return Ok((ch, payload)),
+            CacheResponse::Ok => continue,
+            other => {
+                return Err(CacheError::Internal(format!(
+                    "unexpected subscription response: {:?}",
+                    other
+                )))
+            }
+        }
+    }
+}
+
+fn listen(mut conn: Conn, callback: PyObject, closed: &AtomicBool, channels: &str) {
+    loop {
+        let (ch, payload) = match next_message(&mut conn) {
+            Ok(msg) => msg,
+            Err(e) => {
+                if !closed.load(Ordering::SeqCst) {
+                    eprintln!("subscription to {} lost: {}", channels, e);
+                }
+                return;
+            }
+        };
+        Python::with_gil(|py| {
+            // исключение в callback не должно останавливать подписку
+            if let Err(e) = callback.call1(py, (ch, PyBytes::new_bound(py, &payload))) {
+                e.print(py);
+            }
+        });
+    }
+}
+
+#[pymethods]
+impl Subscription {
+    #[getter]
+    fn channels(&self) -> Vec<String> {
+        self.channels.clone()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<(String, Bound<'py, PyBytes>)>> {
+        let res = py.allow_threads(|| {
+            let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+            match conn.as_mut() {
+                Some(conn) => next_message(conn).map(Some),
+                None => Ok(None),
+            }
+        });
+        if self.closed.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        match res {
+            Ok(Some((ch, payload))) => Ok(Some((ch, PyBytes::new_bound(py, &payload)))),
+            Ok(None) => Err(PyRuntimeError::new_err(
+                "subscription with a callback can't be iterated",
+            )),
+            Err(e) => Err(map_error(e, "subscribe")),
+        }
+    }
+
+    fn close(&self, py: Python<'_>) {
+        self.shutdown();
+        let listener = self
+            .listener
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        // close() из самого callback: поток и так завершится после возврата
+        if let Some(handle) = listener.filter(|h| h.thread().id() != thread::current().id()) {
+            // поток может ждать GIL ради callback
+            py.allow_threads(|| {
+                let _ = handle.join();
+            });
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,146 @@
+use super::map_error;
+use crate::pool::Pool;
+use crate::{CacheCommand, CacheResponse};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+pub(super) const SCAN_PAGE_SIZE: u32 = 1000;
+
+#[derive(Clone, Copy)]
+pub(crate) enum ScanMode {
+    Keys,
+    Values,
+    Items,
+    // пары (ключ, длина значения) с длиной в min..=max, см. ScanSizes
+    Sizes(u64, u64),
+    // пары (ключ, срок в секундах, -1 — бессрочный), см. ScanTtl
+    Ttls,
+    // ключи без срока: страницы ScanTtl, фильтр на клиенте
+    NoTtl,
+}
+
+// что страница принесла к ключу
+enum Found {
+    Key,
+    Value(Vec<u8>),
+    Size(u64),
+    // срок в мс, -1 — бессрочный
+    Ttl(i64),
+}
+
+/// Ленивый обход ключей страницами Scan/ScanItems/ScanSizes/ScanTtl. Страницы идут через пул
+/// клиента, соединение между страницами за обходом не закреплено. Курсор позиционный,
+/// поэтому при записи во время обхода отдельные ключи могут пропасть из него
+/// или встретиться дважды; ошибок это не вызывает.
+#[pyclass]
+pub struct ScanIter {
+    pool: Arc<Pool>,
+    prefix: String,
+    // длина префикса пространства имён, срезается с ключей
+    strip: usize,
+    mode: ScanMode,
+    cursor: u64,
+    done: bool,
+    page: VecDeque<(String, Found)>,
+}
+
+impl ScanIter {
+    pub(crate) fn new(pool: &Arc<Pool>, prefix: String, strip: usize, mode: ScanMode) -> Self {
+        Self {
+            pool: Arc::clone(pool),
+            prefix,
+            strip,
+            mode,
+            cursor: 0,
+            done: false,
+            page: VecDeque::new(),
+        }
+    }
+
+    fn fetch(&mut self, py: Python<'_>) -> PyResult<()> {
+        let cmd = match self.mode {
+            ScanMode::Keys => CacheCommand::Scan(self.cursor, self.prefix.clone(), SCAN_PAGE_SIZE),
+            ScanMode::Values | ScanMode::Items => {
+                CacheCommand::ScanItems(self.cursor, self.prefix.clone(), SCAN_PAGE_SIZE)
+            }
+            ScanMode::Sizes(min, max) => {
+                CacheCommand::ScanSizes(self.cursor, self.prefix.clone(), SCAN_PAGE_SIZE, min, max)
+            }
+            ScanMode::Ttls | ScanMode::NoTtl => {
+                CacheCommand::ScanTtl(self.cursor, self.prefix.clone(), SCAN_PAGE_SIZE)
+            }
+        };
+        let (next, page) = match py.allow_threads(|| self.pool.call(&cmd)) {
+            Ok(CacheResponse::ScanKeys(next, keys)) => {
+                (next, keys.into_iter().map(|k| (k, Found::Key)).collect())
+            }
+            Ok(CacheResponse::Items(next, items)) => (
+                next,
+                items
+                    .into_iter()
+                    .map(|(k, v)| (k, Found::Value(v)))
+                    .collect(),
+            ),
+            Ok(CacheResponse::Sizes(next, keys)) => (
+                next,
+                keys.into_iter().map(|(k, n)| (k, Found::Size(n))).collect(),
+            ),
+            Ok(CacheResponse::Ttls(next, keys)) => (
+                next,
+                keys.into_iter()
+                    .filter(|&(_, ttl)| !matches!(self.mode, ScanMode::NoTtl) || ttl < 0)
+                    .map(|(k, ttl)| (k, Found::Ttl(ttl)))
+                    .collect(),
+            ),
+            Ok(resp) => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "Unexpected response from scan: {:?}",
+                    resp
+                )))
+            }
+            Err(e) => return Err(map_error(e, "scan")),
+        };
+        self.cursor = next;
+        self.done = next == 0;
+        self.page = page;
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl ScanIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        // если под префикс ничего не попало, последняя страница пустая
+        while self.page.is_empty() {
+            if self.done {
+                return Ok(None);
+            }
+            self.fetch(py)?;
+        }
+        let Some((mut key, found)) = self.page.pop_front() else {
+            return Ok(None);
+        };
+        key.drain(..self.strip.min(key.len()));
+        Ok(Some(match (self.mode, found) {
+            (ScanMode::Keys, _) => key.into_py(py),
+            (ScanMode::Values, Found::Value(v)) => PyBytes::new_bound(py, &v).into_py(py),
+            (ScanMode::Items, Found::Value(v)) => (key, PyBytes::new_bound(py, &v)).into_py(py),
+            (ScanMode::Sizes(..), Found::Size(n)) => (key, n).into_py(py),
+            // секунды с округлением, как у ttl()
+            (ScanMode::Ttls, Found::Ttl(ms)) => {
+                (key, if ms < 0 { ms } else { (ms + 500) / 1000 }).into_py(py)
+            }
+            (ScanMode::NoTtl, _) => key.into_py(py),
+            _ => {
+                return Err(PyRuntimeError::new_err("scan page has no values"));
+            }
+        }))
+    }
+}
@@ -0,0 +1,365 @@
+use super::{resolve_cache_paths, DEFAULT_NAME};
+use crate::wal::ReplayProgress;
+use crate::{send_cmd_sync, CacheCommand, CacheResponse, TransportAddr};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple};
+use std::io::{BufRead, BufReader};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, ChildStderr, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// дочерний процесс получает аргументы serve() одним pickle в hex
+const CHILD_SCRIPT: &str = "import pickle, sys\n\
+import tiny_mp_cache\n\
+func, kwargs = pickle.loads(bytes.fromhex(sys.argv[1]))\n\
+getattr(tiny_mp_cache, func)(**kwargs)\n";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// stderr дочернего процесса: до готовности строки копятся для сообщения
+/// об ошибке, потом пересылаются в stderr родителя. Строки хода replay
+/// разбираются по пути.
+#[derive(Default)]
+struct ChildOutput {
+    lines: Vec<String>,
+    forward: bool,
+    replay: Option<ReplayProgress>,
+    // когда пришла последняя строка хода replay
+    replay_at: Option<Instant>,
+}
+
+impl ChildOutput {
+    fn start_forwarding(&mut self) {
+        for line in self.lines.drain(..) {
+            eprintln!("{}", line);
+        }
+        self.forward = true;
+    }
+}
+
+fn read_stderr(stderr: ChildStderr, output: Arc<Mutex<ChildOutput>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            let mut out = output.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(progress) = ReplayProgress::parse_log_line(&line) {
+                out.replay = Some(progress);
+                out.replay_at = Some(Instant::now());
+            }
+            if out.forward {
+                eprintln!("{}", line);
+            } else {
+                out.lines.push(line);
+            }
+        }
+    })
+}
+
+/// Сервер в отдельном процессе Python, запущенный `spawn_server()`.
+/// Процесс убивается в `terminate()`, на выходе из `with` и при сборке
+/// объекта; файл UDS-сокета при этом удаляется.
+#[pyclass]
+pub struct ServerHandle {
+    addr: String,
+    target: TransportAddr,
+    pid: u32,
+    child: Mutex<Option<Child>>,
+    socket: Option<PathBuf>,
+    output: Arc<Mutex<ChildOutput>>,
+    // сервер уже отвечал на Ping
+    ready: AtomicBool,
+    // WAL и снапшот сервера; None при persistence=False
+    paths: Option<(PathBuf, PathBuf)>,
+}
+
+impl ServerHandle {
+    fn output(&self) -> MutexGuard<'_, ChildOutput> {
+        self.output.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Some(статус), если процесс завершился или его уже убили
+    fn exit_status(&self) -> Option<String> {
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        match child.as_mut().map(|c| c.try_wait()) {
+            Some(Ok(None)) => None,
+            Some(Ok(Some(status))) => Some(status.to_string()),
+            Some(Err(e)) => Some(format!("unknown status ({})", e)),
+            None => Some("terminated".into()),
+        }
+    }
+
+    fn ping(&self) -> bool {
+        if self.ready.load(Ordering::Relaxed) {
+            return true;
+        }
+        let ok = matches!(
+            send_cmd_sync(&self.target, CacheCommand::Ping),
+            Ok(CacheResponse::Ok)
+        );
+        if ok {
+            self.ready.store(true, Ordering::Relaxed);
+        }
+        ok
+    }
+
+    fn shutdown(&self) {
+        let child = self.child.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(mut child) = child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        if let Some(path) = &self.socket {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[pymethods]
+impl ServerHandle {
+    #[getter]
+    fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    #[getter]
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Полные пути файлов сервера из `wal_dir` и `name`; None без
+    /// персистентности.
+    #[getter]
+    fn wal_path(&self) -> Option<String> {
+        let (wal, _) = self.paths.as_ref()?;
+        Some(wal.display().to_string())
+    }
+
+    #[getter]
+    fn snapshot_path(&self) -> Option<String> {
+        let (_, snapshot) = self.paths.as_ref()?;
+        Some(snapshot.display().to_string())
+    }
+
+    #[getter]
+    fn lock_path(&self) -> Option<String> {
+        let (wal, _) = self.paths.as_ref()?;
+        Some(crate::wal::lock_path(wal).display().to_string())
+    }
+
+    fn is_alive(&self) -> bool {
+        self.exit_status().is_none()
+    }
+
+    /// "starting", "replaying", "ready" или "exited".
+    fn ready_state(&self, py: Python<'_>) -> &'static str {
+        py.allow_threads(|| {
+            if self.exit_status().is_some() {
+                "exited"
+            } else if self.ping() {
+                "ready"
+            } else if self.output().replay.as_ref().is_some_and(|p| !p.done) {
+                "replaying"
+            } else {
+                "starting"
+            }
+        })
+    }
+
+    /// Последний известный ход replay в дочернем процессе или None, если
+    /// сервер о нём ещё не сообщал.
+    #[getter]
+    fn replay_progress<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let Some(p) = self.output().replay.clone() else {
+            return Ok(None);
+        };
+        let d = PyDict::new_bound(py);
+        d.set_item("records", p.records)?;
+        d.set_item("bytes_read", p.bytes_read)?;
+        d.set_item("bytes_total", p.bytes_total)?;
+        d.set_item("percent", p.percent())?;
+        d.set_item("keys", p.keys)?;
+        d.set_item("elapsed", p.elapsed.as_secs_f64())?;
+        let ops = PyDict::new_bound(py);
+        for (op, n) in &p.ops {
+            ops.set_item(op, n)?;
+        }
+        d.set_item("ops", ops)?;
+        d.set_item("done", p.done)?;
+        Ok(Some(d))
+    }
+
+    fn terminate(&self, py: Python<'_>) {
+        py.allow_threads(|| self.shutdown());
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, py: Python<'_>, _args: &Bound<'_, PyTuple>) -> bool {
+        self.terminate(py);
+        false
+    }
+}
+
+fn free_port() -> PyResult<u16> {
+    // порт может занять кто-то ещё до старта сервера, но при 0 сервер
+    // не смог бы сообщить выбранный порт родителю
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .map(|a| a.port())
+        .map_err(|e| PyRuntimeError::new_err(format!("pick free port: {}", e)))
+}
+
+/// Запускает `serve()`/`serve_unix()` в новом процессе того же
+/// интерпретатора и ждёт, пока сервер ответит на Ping; с `wait=False`
+/// возвращается сразу. `timeout` отсчитывается заново с каждого сообщения
+/// о ходе replay, так что долгий replay не считается зависанием.
+#[pyfunction(signature = (port=0, wal_dir=None, unix_path=None, timeout=10.0, wait=true, **opts))]
+pub fn spawn_server(
+    py: Python<'_>,
+    port: u16,
+    wal_dir: Option<String>,
+    unix_path: Option<String>,
+    timeout: f64,
+    wait: bool,
+    opts: Option<&Bound<'_, PyDict>>,
+) -> PyResult<ServerHandle> {
+    if !(timeout.is_finite() && timeout > 0.0) {
+        return Err(PyValueError::new_err("timeout must be positive"));
+    }
+    let kwargs = match opts {
+        Some(opts) => opts.copy()?,
+        None => PyDict::new_bound(py),
+    };
+    // пути те же, что получит сервер: он стартует в том же текущем каталоге
+    let persistence = match kwargs.get_item("persistence")? {
+        Some(v) => v.is_truthy()?,
+        None => true,
+    };
+    let name: String = match kwargs.get_item("name")? {
+        Some(v) => v.extract()?,
+        None => DEFAULT_NAME.to_string(),
+    };
+    let paths = if persistence {
+        Some(resolve_cache_paths(wal_dir.as_deref(), &name, false)?)
+    } else {
+        None
+    };
+    kwargs.set_item("wal_dir", wal_dir)?;
+    if unix_path.is_some() && cfg!(not(unix)) {
+        return Err(PyValueError::new_err(
+            "unix_path needs Unix domain sockets; on this platform the server listens on TCP loopback",
+        ));
+    }
+    let (func, addr, socket) = match unix_path {
+        Some(path) => {
+            kwargs.set_item("path", &path)?;
+            (
+                "serve_unix",
+                format!("unix://{}", path),
+                Some(PathBuf::from(path)),
+            )
+        }
+        None => {
+            let port = if port == 0 { free_port()? } else { port };
+            kwargs.set_item("port", port)?;
+            ("serve", format!("127.0.0.1:{}", port), None)
+        }
+    };
+    let payload: Vec<u8> = py
+        .import_bound("pickle")?
+        .call_method1("dumps", ((func, kwargs),))?
+        .extract()?;
+    let hex: String = payload.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let sys = py.import_bound("sys")?;
+    let python: String = sys.getattr("executable")?.extract()?;
+    // ребёнок должен импортировать тот же пакет, что и родитель
+    let path: Vec<String> = sys.getattr("path")?.extract()?;
+    let python_path = std::env::join_paths(path.iter().filter(|p| !p.is_empty()))
+        .map_err(|e| PyRuntimeError::new_err(format!("PYTHONPATH: {}", e)))?;
+
+    let mut child = Command::new(python)
+        .arg("-c")
+        .arg(CHILD_SCRIPT)
+        .arg(hex)
+        .env("PYTHONPATH", python_path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| PyRuntimeError::new_err(format!("spawn server: {}", e)))?;
+    let pid = child.id();
+    // stderr читается с самого начала, иначе полный pipe остановит сервер
+    let output = Arc::new(Mutex::new(ChildOutput::default()));
+    let reader = child
+        .stderr
+        .take()
+        .map(|stderr| read_stderr(stderr, Arc::clone(&output)));
+
+    let handle = ServerHandle {
+        target: TransportAddr::parse(&addr),
+        addr,
+        pid,
+        child: Mutex::new(Some(child)),
+        socket,
+        output,
+        ready: AtomicBool::new(false),
+        paths,
+    };
+    if !wait {
+        handle.output().start_forwarding();
+        return Ok(handle);
+    }
+
+    let started = Instant::now();
+    let timeout = Duration::from_secs_f64(timeout);
+    let ready = py.allow_threads(|| loop {
+        if handle.ping() {
+            return Ok(());
+        }
+        if let Some(status) = handle.exit_status() {
+            return Err(format!(
+                "server exited with {} before becoming ready",
+                status
+            ));
+        }
+        let since = handle.output().replay_at.unwrap_or(started);
+        if since.elapsed() >= timeout {
+            handle.shutdown();
+            let replaying = if since > started {
+                " (no replay progress since the last report)"
+            } else {
+                ""
+            };
+            return Err(format!(
+                "server on {} not ready after {:.1}s{}",
+                handle.addr,
+                started.elapsed().as_secs_f64(),
+                replaying
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    });
+    if let Err(msg) = ready {
+        // процесс завершился или убит: stderr дочитывается до конца
+        if let Some(reader) = reader {
+            let _ = py.allow_threads(|| reader.join());
+        }
+        let stderr = handle.output().lines.join("\n");
+        return Err(PyRuntimeError::new_err(format!("{}:\n{}", msg, stderr)));
+    }
+    handle.output().start_forwarding();
+    Ok(handle)
+}
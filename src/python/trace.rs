@@ -0,0 +1,54 @@
+//! `with trace_id("req-42"): ...`: trace id для всех команд `TinyCache`
+//! этого потока внутри блока, см. `crate::with_trace_id`. Id хранится на
+//! поток, а не в contextvar: команды выполняются без GIL, и читать
+//! contextvar на каждой из них пришлось бы под ним.
+
+use crate::trace::TraceScope;
+use pyo3::prelude::*;
+use std::sync::Mutex;
+
+/// Контекстный менеджер `trace_id()`; вложенный блок восстанавливает
+/// внешний id при выходе.
+#[pyclass(frozen)]
+pub struct TraceId {
+    id: String,
+    scope: Mutex<Vec<TraceScope>>,
+}
+
+#[pymethods]
+impl TraceId {
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        let scope = TraceScope::enter(&slf.id);
+        slf.scope
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(scope);
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, pyo3::types::PyTuple>) -> bool {
+        // снятие области восстанавливает прежний id
+        self.scope.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        false
+    }
+
+    #[getter]
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn __repr__(&self) -> String {
+        format!("trace_id({:?})", self.id)
+    }
+}
+
+/// Trace id для команд этого потока внутри `with`: сервер пишет его в
+/// `info()["slowlog"]` и в `client_list()`. Длиннее 255 байт обрезается.
+#[pyfunction]
+pub fn trace_id(id: String) -> TraceId {
+    TraceId {
+        id,
+        scope: Mutex::new(Vec::new()),
+    }
+}
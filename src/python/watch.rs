@@ -0,0 +1,152 @@
+use super::pubsub::READ_TIMEOUT;
+use crate::acl::Credentials;
+use crate::error::CacheError;
+use crate::watch::WatchEvent;
+use crate::{read_response, write_frame, CacheCommand, CacheResponse, Conn, TransportAddr};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Наблюдение на стороне клиента: события разбирает фоновый поток и передаёт
+/// в callback словарём `{"key", "op", "value_size", "value"}`.
+#[pyclass]
+pub struct Watch {
+    prefix: String,
+    // копия сокета: stop() закрывает её и будит поток
+    control: Conn,
+    stopped: Arc<AtomicBool>,
+    // почему поток остановился сам (обрыв, отключение сервером)
+    error: Arc<Mutex<Option<String>>>,
+    listener: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Watch {
+    pub(crate) fn open(
+        addr: &TransportAddr,
+        prefix: String,
+        strip: usize,
+        with_values: bool,
+        callback: PyObject,
+        auth: Option<&Credentials>,
+    ) -> Result<Self, CacheError> {
+        let mut conn = Conn::connect(addr)?;
+        conn.set_read_timeout(Some(READ_TIMEOUT))?;
+        if let Some(auth) = auth {
+            auth.login(&mut conn)?;
+        }
+        write_frame(&mut conn, &CacheCommand::Watch(prefix.clone(), with_values))?;
+        match read_response(&mut conn)? {
+            CacheResponse::Ok => {}
+            CacheResponse::Error(msg) => return Err(CacheError::Server(msg)),
+            other => {
+                return Err(CacheError::Internal(format!(
+                    "Unexpected response from watch: {:?}",
+                    other
+                )))
+            }
+        }
+        let control = conn.try_clone()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let error = Arc::new(Mutex::new(None));
+        let handle = {
+            let (stopped, error) = (stopped.clone(), error.clone());
+            thread::Builder::new()
+                .name("tiny-mp-cache-watch".into())
+                .spawn(move || {
+                    let e = listen(conn, strip, callback);
+                    if !stopped.load(Ordering::SeqCst) {
+                        *error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e.to_string());
+                    }
+                })
+                .map_err(|e| CacheError::Internal(format!("spawn watch thread: {}", e)))?
+        };
+        Ok(Self {
+            prefix: prefix[strip..].to_string(),
+            control,
+            stopped,
+            error,
+            listener: Mutex::new(Some(handle)),
+        })
+    }
+
+    pub(crate) fn shutdown(&self) {
+        if !self.stopped.swap(true, Ordering::SeqCst) {
+            self.control.shutdown();
+        }
+    }
+}
+
+/// Разбирает события до ошибки соединения и возвращает её. `strip` — длина
+/// префикса пространства имён, который срезается с ключей событий.
+fn listen(mut conn: Conn, strip: usize, callback: PyObject) -> CacheError {
+    loop {
+        let mut event = match read_response(&mut conn) {
+            Ok(CacheResponse::Event(event)) => event,
+            Ok(CacheResponse::Ok) => continue,
+            Ok(CacheResponse::Error(msg)) => return CacheError::Server(msg),
+            Ok(other) => {
+                return CacheError::Internal(format!("unexpected watch response: {:?}", other))
+            }
+            Err(e) => return e,
+        };
+        event.key.drain(..strip.min(event.key.len()));
+        Python::with_gil(|py| {
+            let res = event_dict(py, event).and_then(|d| callback.call1(py, (d,)));
+            // исключение в callback не должно останавливать наблюдение
+            if let Err(e) = res {
+                e.print(py);
+            }
+        });
+    }
+}
+
+fn event_dict(py: Python<'_>, event: WatchEvent) -> PyResult<Bound<'_, PyDict>> {
+    let d = PyDict::new_bound(py);
+    d.set_item("key", event.key)?;
+    d.set_item("op", event.op.as_str())?;
+    d.set_item("value_size", event.value_size)?;
+    d.set_item("value", event.value.map(|v| PyBytes::new_bound(py, &v)))?;
+    Ok(d)
+}
+
+#[pymethods]
+impl Watch {
+    #[getter]
+    fn prefix(&self) -> String {
+        self.prefix.clone()
+    }
+
+    #[getter]
+    fn error(&self) -> Option<String> {
+        self.error.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn is_alive(&self) -> bool {
+        let listener = self.listener.lock().unwrap_or_else(|e| e.into_inner());
+        listener.as_ref().is_some_and(|h| !h.is_finished())
+    }
+
+    fn stop(&self, py: Python<'_>) {
+        self.shutdown();
+        let listener = self
+            .listener
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        // stop() из самого callback: поток и так завершится после возврата
+        if let Some(handle) = listener.filter(|h| h.thread().id() != thread::current().id()) {
+            // поток может ждать GIL ради callback
+            py.allow_threads(|| {
+                let _ = handle.join();
+            });
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,237 @@
+use crate::core::{Deadline, Tombstone};
+use crate::error::CacheError;
+use crate::wal::WalRecord;
+use crate::{
+    read_response, write_frame, CacheCommand, CacheResponse, Conn, PersistentCore, TransportAddr,
+};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Репликация: реплика шлёт `ReplSync(seq)` — seq последней применённой записи
+/// основного сервера — и держит соединение открытым. Основной сервер отвечает
+/// потоком `CacheResponse::Repl`:
+///   - записи WAL после seq, если они ещё лежат на диске, иначе полный снимок
+///     (SnapshotStart, SnapshotItems..., SnapshotEnd);
+///   - затем новые записи по мере их попадания в WAL и Ping, пока записей нет.
+///
+/// seq реплики совпадают с seq основного сервера, поэтому после рестарта
+/// реплика продолжает с последней записи своего WAL.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ReplFrame {
+    // реплика очищает данные, дальше идёт снимок
+    SnapshotStart,
+    SnapshotItems(Vec<(String, Vec<u8>)>),
+    // снимок закончен и соответствует seq
+    SnapshotEnd(u64),
+    Records(Vec<(u64, WalRecord)>),
+    Ping,
+    // сроки ключей (unix-время в мс) из предыдущего SnapshotItems
+    SnapshotExpiries(Vec<(String, u64)>),
+    // теги ключей из предыдущего SnapshotItems
+    SnapshotTags(Vec<(String, Vec<String>)>),
+    // надгробия, см. PersistentCore::set_tombstone_ttl; идут после всех пар
+    SnapshotTombstones(Vec<(String, Tombstone)>),
+}
+
+// предел одного кадра: пары и записи упаковываются в кадры примерно до такого
+// размера, но одна пара может быть и больше
+const FRAME_TARGET_BYTES: usize = 4 << 20;
+const FRAME_MAX_ITEMS: usize = 1000;
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+// без кадров (хотя бы Ping) столько времени — соединение считается мёртвым
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+fn send(w: &mut impl Write, frame: ReplFrame) -> Result<(), CacheError> {
+    write_frame(w, &CacheResponse::Repl(frame))
+}
+
+/// Копит записи и отправляет их кадрами `Records`.
+struct RecordBatcher<'a, W: Write> {
+    w: &'a mut W,
+    recs: Vec<(u64, WalRecord)>,
+    bytes: usize,
+}
+
+impl<'a, W: Write> RecordBatcher<'a, W> {
+    fn new(w: &'a mut W) -> Self {
+        Self {
+            w,
+            recs: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    fn push(&mut self, seq: u64, rec: WalRecord) -> Result<(), CacheError> {
+        self.bytes += rec.key().len() + rec.value_len();
+        self.recs.push((seq, rec));
+        if self.recs.len() >= FRAME_MAX_ITEMS || self.bytes >= FRAME_TARGET_BYTES {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), CacheError> {
+        if self.recs.is_empty() {
+            return Ok(());
+        }
+        self.bytes = 0;
+        send(self.w, ReplFrame::Records(std::mem::take(&mut self.recs)))
+    }
+}
+
+/// Сторона основного сервера: обслуживает `ReplSync(after)` до разрыва соединения.
+pub fn serve_replica<S: Write>(
+    stream: &mut S,
+    core: &PersistentCore,
+    after: u64,
+) -> Result<(), CacheError> {
+    let (seq, rx) = match core.repl_subscribe() {
+        Ok(sub) => sub,
+        Err(e) => return write_frame(stream, &CacheResponse::Error(e.to_string())),
+    };
+    let _replica = core.track_replica();
+    let (wal, _) = core.wal()?;
+
+    // реплика впереди нас (основной сервер потерял данные) или нужных записей
+    // уже нет на диске — только полная синхронизация
+    let caught_up = after <= seq && {
+        let mut batcher = RecordBatcher::new(stream);
+        let found = wal.read_range(after, seq, |seq, rec| batcher.push(seq, rec))?;
+        batcher.flush()?;
+        found
+    };
+    if !caught_up {
+        send_snapshot(stream, core, seq)?;
+    }
+
+    loop {
+        match rx.recv_timeout(PING_INTERVAL) {
+            Ok(batch) => send(stream, ReplFrame::Records(wal.decode_batch(&batch)?))?,
+            Err(RecvTimeoutError::Timeout) => send(stream, ReplFrame::Ping)?,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(CacheError::Busy(
+                    "replica fell behind the WAL stream, dropping it".into(),
+                ))
+            }
+        }
+    }
+}
+
+/// Снимок без блокировок: пары читаются из живой карты после подписки на seq,
+/// так что он «размыт», но записи после seq, которые пойдут следом, его выравнивают
+/// (как WAL после снапшота bgsave).
+fn send_snapshot(
+    stream: &mut impl Write,
+    core: &PersistentCore,
+    seq: u64,
+) -> Result<(), CacheError> {
+    send(stream, ReplFrame::SnapshotStart)?;
+    let mut items = Vec::new();
+    let mut expiries = Vec::new();
+    let mut tags = Vec::new();
+    let mut bytes = 0;
+    let mut flush = |items: &mut Vec<_>, expiries: &mut Vec<_>, tags: &mut Vec<_>| {
+        send(stream, ReplFrame::SnapshotItems(std::mem::take(items)))?;
+        if !expiries.is_empty() {
+            send(
+                stream,
+                ReplFrame::SnapshotExpiries(std::mem::take(expiries)),
+            )?;
+        }
+        if !tags.is_empty() {
+            send(stream, ReplFrame::SnapshotTags(std::mem::take(tags)))?;
+        }
+        Ok::<_, CacheError>(())
+    };
+    for key in core.keys_prefix("", Deadline::NONE)? {
+        // ключ могли удалить, пока шёл обход
+        let Some((value, expires_at, key_tags)) = core.get_entry(&key) else {
+            continue;
+        };
+        bytes += key.len() + value.len();
+        if let Some(at) = expires_at {
+            expiries.push((key.clone(), at));
+        }
+        if !key_tags.is_empty() {
+            bytes += key_tags.iter().map(String::len).sum::<usize>();
+            tags.push((key.clone(), key_tags));
+        }
+        items.push((key, value));
+        if items.len() >= FRAME_MAX_ITEMS || bytes >= FRAME_TARGET_BYTES {
+            bytes = 0;
+            flush(&mut items, &mut expiries, &mut tags)?;
+        }
+    }
+    if !items.is_empty() {
+        flush(&mut items, &mut expiries, &mut tags)?;
+    }
+    let mut tombs = Vec::new();
+    let mut bytes = 0;
+    core.for_each_tombstone(|k, t| {
+        bytes += k.len() + t.value.len();
+        tombs.push((k.to_string(), t.clone()));
+        if tombs.len() >= FRAME_MAX_ITEMS || bytes >= FRAME_TARGET_BYTES {
+            bytes = 0;
+            send(
+                stream,
+                ReplFrame::SnapshotTombstones(std::mem::take(&mut tombs)),
+            )?;
+        }
+        Ok(())
+    })?;
+    if !tombs.is_empty() {
+        send(stream, ReplFrame::SnapshotTombstones(tombs))?;
+    }
+    send(stream, ReplFrame::SnapshotEnd(seq))
+}
+
+/// Сторона реплики: фоновый поток, который держит подписку на `primary` и
+/// переподключается после любой ошибки.
+pub fn spawn_replica(core: Arc<PersistentCore>, primary: String) -> Result<(), CacheError> {
+    let addr = TransportAddr::parse(&primary);
+    thread::Builder::new()
+        .name("tiny-mp-cache-replica".into())
+        .spawn(move || loop {
+            if let Err(e) = follow(&core, &addr) {
+                eprintln!("replication from {} interrupted: {}", primary, e);
+                core.repl_disconnected(&e);
+            }
+            thread::sleep(RECONNECT_DELAY);
+        })
+        .map(|_| ())
+        .map_err(|e| CacheError::Internal(format!("spawn replica thread: {}", e)))
+}
+
+fn follow(core: &PersistentCore, addr: &TransportAddr) -> Result<(), CacheError> {
+    let mut conn = Conn::connect(addr)?;
+    conn.set_read_timeout(Some(READ_TIMEOUT))?;
+    write_frame(&mut conn, &CacheCommand::ReplSync(core.repl_position()))?;
+    loop {
+        let frame = match read_response(&mut conn)? {
+            CacheResponse::Repl(frame) => frame,
+            CacheResponse::Error(msg) => return Err(CacheError::Server(msg)),
+            other => {
+                return Err(CacheError::Internal(format!(
+                    "unexpected replication response: {:?}",
+                    other
+                )))
+            }
+        };
+        core.repl_connected();
+        match frame {
+            ReplFrame::SnapshotStart => core.begin_resync()?,
+            ReplFrame::SnapshotItems(items) => core.load_resync(items),
+            ReplFrame::SnapshotExpiries(expiries) => core.load_resync_expiries(expiries),
+            ReplFrame::SnapshotTags(tags) => core.load_resync_tags(tags),
+            ReplFrame::SnapshotTombstones(tombs) => core.load_resync_tombstones(tombs),
+            ReplFrame::SnapshotEnd(seq) => core.finish_resync(seq)?,
+            ReplFrame::Records(recs) => core.apply_replicated(recs)?,
+            ReplFrame::Ping => {}
+        }
+    }
+}
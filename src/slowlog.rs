@@ -0,0 +1,125 @@
+//! Журнал медленных команд, см. `PersistentCore::set_slowlog`: команда
+//! соединения, выполнявшаяся дольше порога, пишется в stderr и в кольцо
+//! последних `SLOWLOG_LEN` записей (`info()["slowlog"]`) вместе с trace id,
+//! который прислал клиент. Время — выполнение команды без чтения кадра и
+//! отправки ответа; `BGet` ждёт по определению и в журнал не попадает.
+
+use crate::clients::Session;
+use crate::core::now_unix_ms;
+use crate::ResponseValue;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Сколько последних медленных команд хранится.
+pub const SLOWLOG_LEN: usize = 128;
+
+/// Запись журнала.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlowEntry {
+    // номер записи с запуска сервера, с 1
+    pub seq: u64,
+    pub at_ms: u64,
+    pub micros: u64,
+    pub command: &'static str,
+    // id соединения, как в ClientList
+    pub client: u64,
+    pub peer: String,
+    pub trace_id: Option<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct SlowLog {
+    // порог в мкс, 0 — журнал выключен
+    threshold_us: AtomicU64,
+    total: AtomicU64,
+    entries: Mutex<VecDeque<SlowEntry>>,
+}
+
+impl SlowLog {
+    pub(crate) fn set_threshold(&self, threshold: Option<Duration>) {
+        let us = threshold.map_or(0, |t| t.as_micros().clamp(1, u64::MAX as u128) as u64);
+        self.threshold_us.store(us, Ordering::Relaxed);
+    }
+
+    pub(crate) fn threshold(&self) -> Option<Duration> {
+        match self.threshold_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
+        }
+    }
+
+    /// Учитывает команду `command`, начатую в `started`, если она медленнее порога.
+    pub(crate) fn record(
+        &self,
+        started: Instant,
+        command: &'static str,
+        session: &Session,
+        trace_id: Option<&str>,
+    ) {
+        let threshold = self.threshold_us.load(Ordering::Relaxed);
+        if threshold == 0 || command == "BGet" {
+            return;
+        }
+        let micros = started.elapsed().as_micros().min(u64::MAX as u128) as u64;
+        if micros < threshold {
+            return;
+        }
+        let entry = SlowEntry {
+            seq: self.total.fetch_add(1, Ordering::Relaxed) + 1,
+            at_ms: now_unix_ms(),
+            micros,
+            command,
+            client: session.id(),
+            peer: session.peer().to_string(),
+            trace_id: trace_id.map(str::to_string),
+        };
+        eprintln!(
+            "slow command {} took {} us, connection {} ({}), trace id {}",
+            entry.command,
+            entry.micros,
+            entry.client,
+            entry.peer,
+            entry.trace_id.as_deref().unwrap_or("-")
+        );
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == SLOWLOG_LEN {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Записи от старых к новым.
+    pub(crate) fn entries(&self) -> Vec<SlowEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().cloned().collect()
+    }
+
+    /// Поля `info()`: `slow_commands` за всё время и `slowlog` — записи по
+    /// номеру.
+    pub(crate) fn info_fields(&self) -> Vec<(&'static str, ResponseValue)> {
+        let log = self
+            .entries()
+            .into_iter()
+            .map(|e| {
+                let fields = vec![
+                    ("at_ms".to_string(), e.at_ms.into()),
+                    ("micros".to_string(), e.micros.into()),
+                    ("command".to_string(), e.command.to_string().into()),
+                    ("client".to_string(), e.client.into()),
+                    ("peer".to_string(), e.peer.into()),
+                    (
+                        "trace_id".to_string(),
+                        e.trace_id.unwrap_or_default().into(),
+                    ),
+                ];
+                (e.seq.to_string(), ResponseValue::Nested(fields))
+            })
+            .collect();
+        vec![
+            ("slow_commands", self.total.load(Ordering::Relaxed).into()),
+            ("slowlog", ResponseValue::Nested(log)),
+        ]
+    }
+}
@@ -0,0 +1,279 @@
+use crate::core::Tombstone;
+use crate::crypto::{self, WalKey, KEY_CHECK_LEN};
+use crate::error::CacheError;
+use crate::wal::replace_file;
+use crate::CacheCore;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Формат снапшота:
+///   header: magic "TMCS" | version u32 | seq u64
+///   records: (len u32 | bincode (key, value)) * count
+///   footer: count u64 | crc32 u32 (по header + records + count)
+/// seq — последняя запись WAL, состояние после которой лежит в снапшоте.
+/// Версия 2 — зашифрованный снапшот: за seq идёт проверочное значение ключа,
+/// а каждая запись — nonce + шифротекст, как в WAL. Версии 3 и 4 — то же, что
+/// 1 и 2, но запись — (key, value, expires_at): срок ключа в unix-мс или None.
+/// Версии 5 и 6 добавляют к записи теги ключа: (key, value, expires_at, tags).
+/// Версии 7 и 8 — ещё и purge_at: Some — запись не ключ, а надгробие до этого
+/// unix-времени в мс (см. `core::Tombstone`). Версии 9 и 10 добавляют время
+/// последней записи в unix-мс и версию ключа (у надгробий — нули). Пишутся
+/// только 9 и 10.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"TMCS";
+const SNAPSHOT_VERSION: u32 = 1;
+const SNAPSHOT_VERSION_ENCRYPTED: u32 = 2;
+const SNAPSHOT_VERSION_EXPIRY: u32 = 3;
+const SNAPSHOT_VERSION_EXPIRY_ENCRYPTED: u32 = 4;
+const SNAPSHOT_VERSION_TAGS: u32 = 5;
+const SNAPSHOT_VERSION_TAGS_ENCRYPTED: u32 = 6;
+const SNAPSHOT_VERSION_TOMBSTONES: u32 = 7;
+const SNAPSHOT_VERSION_TOMBSTONES_ENCRYPTED: u32 = 8;
+const SNAPSHOT_VERSION_META: u32 = 9;
+const SNAPSHOT_VERSION_META_ENCRYPTED: u32 = 10;
+
+// запись версий 9 и 10: ключ, значение, срок, теги, purge_at надгробия,
+// время последней записи и версия
+type Record<'a> = (
+    &'a str,
+    &'a [u8],
+    Option<u64>,
+    &'a [String],
+    Option<u64>,
+    u64,
+    u64,
+);
+
+/// Обёртка, считающая crc32 по всему, что через неё прочитано или записано.
+pub(crate) struct Crc<W> {
+    pub(crate) inner: W,
+    pub(crate) hasher: crc32fast::Hasher,
+}
+
+impl<W> Crc<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for Crc<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Crc<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Атомарно записывает содержимое `core` в `path`, возвращает число записей.
+/// Запись идёт по шардам DashMap без глобальной блокировки: если параллельно
+/// идут записи, снапшот «размыт» между seq и моментом окончания, что безопасно,
+/// т.к. доигрывание WAL после seq перекрывает любое промежуточное значение ключа.
+pub fn write(
+    path: &Path,
+    core: &CacheCore,
+    seq: u64,
+    key: Option<&WalKey>,
+    mut on_record: impl FnMut(),
+) -> Result<u64, CacheError> {
+    let to_io = |e: CacheError| std::io::Error::other(e.to_string());
+    let mut count = 0u64;
+    replace_file(path, |f| {
+        let mut w = Crc::new(BufWriter::new(f));
+        w.write_all(SNAPSHOT_MAGIC)?;
+        match key {
+            Some(key) => {
+                w.write_all(&SNAPSHOT_VERSION_META_ENCRYPTED.to_le_bytes())?;
+                w.write_all(&seq.to_le_bytes())?;
+                w.write_all(&key.check_value().map_err(to_io)?)?;
+            }
+            None => {
+                w.write_all(&SNAPSHOT_VERSION_META.to_le_bytes())?;
+                w.write_all(&seq.to_le_bytes())?;
+            }
+        }
+        let mut record = |w: &mut Crc<BufWriter<&mut File>>, rec: Record<'_>| {
+            let mut data = bincode::serialize(&rec)
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+            if let Some(key) = key {
+                data = key.seal(&data).map_err(to_io)?;
+            }
+            w.write_all(&(data.len() as u32).to_le_bytes())?;
+            w.write_all(&data)?;
+            count += 1;
+            on_record();
+            Ok::<(), std::io::Error>(())
+        };
+        core.try_for_each_with_meta(|k, v, expires_at, tags, (written_at, version)| {
+            record(&mut w, (k, v, expires_at, tags, None, written_at, version))
+        })?;
+        core.try_for_each_tombstone(|k, t| {
+            record(
+                &mut w,
+                (k, &t.value, t.expires_at, &t.tags, Some(t.purge_at), 0, 0),
+            )
+        })?;
+        w.write_all(&count.to_le_bytes())?;
+        let crc = w.hasher.clone().finalize();
+        let mut inner = w.inner;
+        inner.write_all(&crc.to_le_bytes())?;
+        inner.flush()
+    })
+    .map_err(|e| CacheError::Internal(format!("write snapshot: {}", e)))?;
+    Ok(count)
+}
+
+/// Загружает снапшот в `core`. Возвращает его seq или `None`, если файла нет.
+/// Зашифрованный снапшот без ключа или с чужим ключом — ошибка.
+pub fn load(
+    path: &Path,
+    core: &CacheCore,
+    key: Option<&WalKey>,
+) -> Result<Option<u64>, CacheError> {
+    let f = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(CacheError::Internal(format!("open snapshot: {}", e))),
+    };
+    let map_io = |e: std::io::Error| CacheError::Internal(format!("read snapshot: {}", e));
+    let mut r = Crc::new(BufReader::new(f));
+
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic).map_err(map_io)?;
+    if &magic != SNAPSHOT_MAGIC {
+        return Err(CacheError::Internal("not a snapshot file".into()));
+    }
+    let version = read_u32(&mut r).map_err(map_io)?;
+    // шифрование и номер формата записи: 1 — (k, v), 3 — со сроком, 5 — с
+    // тегами, 7 — с надгробиями, 9 — со временем записи и версией
+    let (encrypted, format) = match version {
+        SNAPSHOT_VERSION => (false, 1),
+        SNAPSHOT_VERSION_ENCRYPTED => (true, 1),
+        SNAPSHOT_VERSION_EXPIRY => (false, 3),
+        SNAPSHOT_VERSION_EXPIRY_ENCRYPTED => (true, 3),
+        SNAPSHOT_VERSION_TAGS => (false, 5),
+        SNAPSHOT_VERSION_TAGS_ENCRYPTED => (true, 5),
+        SNAPSHOT_VERSION_TOMBSTONES => (false, 7),
+        SNAPSHOT_VERSION_TOMBSTONES_ENCRYPTED => (true, 7),
+        SNAPSHOT_VERSION_META => (false, 9),
+        SNAPSHOT_VERSION_META_ENCRYPTED => (true, 9),
+        _ => {
+            return Err(CacheError::Internal(format!(
+                "unsupported snapshot version {}",
+                version
+            )))
+        }
+    };
+    let seq = read_u64(&mut r).map_err(map_io)?;
+    let mut pos = 16u64;
+    if encrypted {
+        let mut check = [0u8; KEY_CHECK_LEN];
+        r.read_exact(&mut check).map_err(map_io)?;
+        crypto::verify_key(key, &check, path)?;
+        pos += KEY_CHECK_LEN as u64;
+    }
+
+    // Записи и footer идут подряд, а длина записи — u32: читаем по 4 байта,
+    // и когда до конца файла остаётся только footer, выходим.
+    let file_len = r.inner.get_ref().metadata().map_err(map_io)?.len();
+    let footer_start = file_len.saturating_sub(12);
+    let mut count = 0u64;
+    while pos < footer_start {
+        let len = read_u32(&mut r).map_err(map_io)? as usize;
+        let mut buf = vec![0u8; len];
+        r.read_exact(&mut buf).map_err(map_io)?;
+        if encrypted {
+            buf = key.and_then(|k| k.open(&buf)).ok_or_else(|| {
+                CacheError::Internal(format!(
+                    "snapshot {} record at offset {} failed authentication",
+                    path.display(),
+                    pos
+                ))
+            })?;
+        }
+        let to_err = |e: bincode::Error| CacheError::Serialization(e.to_string());
+        // время записи ключей старых форматов неизвестно (0), версия — 1
+        let (k, v, expires_at, tags, purge_at, written_at, version): (
+            String,
+            Vec<u8>,
+            Option<u64>,
+            Vec<String>,
+            _,
+            u64,
+            u64,
+        ) = match format {
+            9 => bincode::deserialize(&buf).map_err(to_err)?,
+            7 => {
+                let (k, v, at, tags, purge_at) = bincode::deserialize(&buf).map_err(to_err)?;
+                (k, v, at, tags, purge_at, 0, 1)
+            }
+            5 => {
+                let (k, v, at, tags) = bincode::deserialize(&buf).map_err(to_err)?;
+                (k, v, at, tags, None, 0, 1)
+            }
+            3 => {
+                let (k, v, at) = bincode::deserialize(&buf).map_err(to_err)?;
+                (k, v, at, Vec::new(), None, 0, 1)
+            }
+            _ => {
+                let (k, v) = bincode::deserialize(&buf).map_err(to_err)?;
+                (k, v, None, Vec::new(), None, 0, 1)
+            }
+        };
+        match purge_at {
+            Some(purge_at) => core.put_tombstone(
+                k,
+                Tombstone {
+                    value: v,
+                    expires_at,
+                    tags,
+                    purge_at,
+                },
+            ),
+            None => {
+                core.set_tagged(k.clone(), v, tags, expires_at);
+                core.set_write_meta(&k, written_at, version);
+            }
+        }
+        pos += 4 + len as u64;
+        count += 1;
+    }
+
+    let stored_count = read_u64(&mut r).map_err(map_io)?;
+    let crc = r.hasher.clone().finalize();
+    let mut crc_buf = [0u8; 4];
+    r.inner.read_exact(&mut crc_buf).map_err(map_io)?;
+    if stored_count != count || u32::from_le_bytes(crc_buf) != crc {
+        return Err(CacheError::Internal(format!(
+            "snapshot {} is corrupted (checksum or record count mismatch)",
+            path.display()
+        )));
+    }
+    Ok(Some(seq))
+}
+
+fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(r: &mut impl Read) -> std::io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
@@ -0,0 +1,60 @@
+//! Trace id запроса: строка, которую клиент передаёт серверу вместе с
+//! командой, чтобы медленную команду в `slowlog` можно было сопоставить с
+//! запросом приложения. Id задаётся на поток вызовом `with_trace_id` и
+//! уходит со всеми командами `Client` и `MuxConn` этого потока внутри него.
+//! В кадре id идёт после заголовка протокола 4 (см. `PROTOCOL_VERSION`);
+//! без id кадр не длиннее прежнего.
+
+use std::cell::RefCell;
+
+/// Предел длины id в байтах: длиннее обрезается по границе символа.
+pub const MAX_TRACE_ID: usize = 255;
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Выполняет `f` с trace id `id` у команд этого потока; пустой id снимает
+/// внешний. После `f`, в том числе по панике, восстанавливается прежний id.
+pub fn with_trace_id<R>(id: &str, f: impl FnOnce() -> R) -> R {
+    let _scope = TraceScope::enter(id);
+    f()
+}
+
+/// Id команд этого потока сейчас.
+pub fn current_trace_id() -> Option<String> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Id, заданный на поток до конца области; вложенные области восстанавливают
+/// внешний id.
+pub(crate) struct TraceScope {
+    prev: Option<String>,
+}
+
+impl TraceScope {
+    pub(crate) fn enter(id: &str) -> Self {
+        let id = Some(truncate(id).to_string()).filter(|id| !id.is_empty());
+        Self {
+            prev: CURRENT.with(|c| c.replace(id)),
+        }
+    }
+}
+
+impl Drop for TraceScope {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT.with(|c| *c.borrow_mut() = prev);
+    }
+}
+
+fn truncate(id: &str) -> &str {
+    if id.len() <= MAX_TRACE_ID {
+        return id;
+    }
+    let mut end = MAX_TRACE_ID;
+    while !id.is_char_boundary(end) {
+        end -= 1;
+    }
+    &id[..end]
+}
@@ -0,0 +1,72 @@
+use crate::encoding::check_raw;
+use crate::error::CacheError;
+use serde::{Deserialize, Serialize};
+
+/// Преобразование значения в команде `Update`: сервер применяет его под
+/// блокировкой ключа и пишет в WAL обычный Set с результатом, так что
+/// реплики и восстановление ничего о нём не знают. Целые (`*I64`) хранятся
+/// десятичным текстом, как `b"42"`; отсутствующий ключ для них — 0. Сжатое
+/// клиентом значение (см. `ValueEncoding`) не меняется, это ошибка.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum UpdateOp {
+    AppendBytes(Vec<u8>),
+    PrependBytes(Vec<u8>),
+    // отсутствующий ключ так и остаётся отсутствующим
+    TruncateTo(u64),
+    // записать, если ключа нет или новое значение длиннее текущего
+    SetIfLonger(Vec<u8>),
+    AddI64(i64),
+    MinI64(i64),
+    MaxI64(i64),
+}
+
+pub(crate) fn parse_i64(key: &str, v: Option<&[u8]>) -> Result<i64, CacheError> {
+    let Some(v) = v else {
+        return Ok(0);
+    };
+    std::str::from_utf8(v)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| CacheError::Unsupported(format!("value of key {:?} is not an i64", key)))
+}
+
+impl UpdateOp {
+    /// Новое значение ключа или `None`, если значение не меняется.
+    pub(crate) fn apply(
+        &self,
+        key: &str,
+        current: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>, CacheError> {
+        let cur = current.unwrap_or_default();
+        check_raw(key, cur)?;
+        let new = match self {
+            UpdateOp::AppendBytes(arg) => [cur, arg.as_slice()].concat(),
+            UpdateOp::PrependBytes(arg) => [arg.as_slice(), cur].concat(),
+            UpdateOp::TruncateTo(len) => match current {
+                Some(cur) if cur.len() as u64 > *len => cur[..*len as usize].to_vec(),
+                _ => return Ok(None),
+            },
+            UpdateOp::SetIfLonger(arg) => match current {
+                Some(cur) if cur.len() >= arg.len() => return Ok(None),
+                _ => arg.clone(),
+            },
+            UpdateOp::AddI64(n) => {
+                let sum = parse_i64(key, current)?.checked_add(*n).ok_or_else(|| {
+                    CacheError::Unsupported(format!("add_i64 on key {:?} overflows i64", key))
+                })?;
+                sum.to_string().into_bytes()
+            }
+            UpdateOp::MinI64(n) => match current {
+                None => n.to_string().into_bytes(),
+                Some(_) if parse_i64(key, current)? <= *n => return Ok(None),
+                Some(_) => n.to_string().into_bytes(),
+            },
+            UpdateOp::MaxI64(n) => match current {
+                None => n.to_string().into_bytes(),
+                Some(_) if parse_i64(key, current)? >= *n => return Ok(None),
+                Some(_) => n.to_string().into_bytes(),
+            },
+        };
+        Ok(Some(new))
+    }
+}
@@ -0,0 +1,139 @@
+//! Сверка памяти с тем, что дают снапшот и WAL (`CacheCommand::Verify`).
+
+use crate::core::{now_unix_ms, CacheCore};
+use crate::error::CacheError;
+use crate::ResponseValue;
+use std::collections::HashSet;
+
+/// Итог `Verify`. Расхождения по ключам, изменённым во время проверки, не
+/// считаются: они попадают в `skipped`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    // seq WAL, на котором началась проверка
+    pub seq: u64,
+    pub keys_checked: u64,
+    pub skipped: u64,
+    pub mismatches: u64,
+    // не больше `max_examples` пар (ключ, причина): "missing_in_memory",
+    // "missing_in_wal", "value_differs", "expiry_differs"
+    pub examples: Vec<(String, String)>,
+}
+
+impl VerifyReport {
+    pub(crate) fn into_map(self) -> Vec<(String, ResponseValue)> {
+        let examples = self
+            .examples
+            .into_iter()
+            .map(|(key, reason)| (key, reason.into()))
+            .collect();
+        vec![
+            ("seq".into(), self.seq.into()),
+            ("keys_checked".into(), self.keys_checked.into()),
+            ("skipped".into(), self.skipped.into()),
+            ("mismatches".into(), self.mismatches.into()),
+            ("examples".into(), ResponseValue::Nested(examples)),
+        ]
+    }
+
+    /// Отчёт из `Map`, а от соединения версии 1 — из Info, где вложенные
+    /// примеры развёрнуты в поля "examples:<ключ>", а числа пришли строками.
+    pub(crate) fn from_map(map: Vec<(String, ResponseValue)>) -> Result<Self, CacheError> {
+        let mut report = VerifyReport::default();
+        for (name, value) in map {
+            let bad = || CacheError::Internal(format!("bad verify field {}: {:?}", name, value));
+            if let Some(key) = name.strip_prefix("examples:") {
+                let reason = value.as_str().ok_or_else(bad)?;
+                report.examples.push((key.to_string(), reason.to_string()));
+                continue;
+            }
+            let slot = match name.as_str() {
+                "seq" => &mut report.seq,
+                "keys_checked" => &mut report.keys_checked,
+                "skipped" => &mut report.skipped,
+                "mismatches" => &mut report.mismatches,
+                "examples" => {
+                    let ResponseValue::Nested(examples) = &value else {
+                        return Err(bad());
+                    };
+                    for (key, reason) in examples {
+                        let reason = reason.as_str().ok_or_else(bad)?;
+                        report.examples.push((key.clone(), reason.to_string()));
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            *slot = match &value {
+                ResponseValue::Int(n) => u64::try_from(*n).ok(),
+                ResponseValue::Str(s) => s.parse().ok(),
+                _ => None,
+            }
+            .ok_or_else(bad)?;
+        }
+        Ok(report)
+    }
+}
+
+/// Сравнивает живую карту с восстановленной `scratch`: число проверенных
+/// ключей и расхождения. Без `values` сравнивается только наличие ключей.
+/// Ключ, истёкший между обходами, расхождением не считается.
+pub(crate) fn diff(
+    live: &CacheCore,
+    scratch: &CacheCore,
+    values: bool,
+) -> (u64, Vec<(String, &'static str)>) {
+    let mut checked = 0;
+    let mut found = Vec::new();
+    scratch
+        .try_for_each(|k, v, at, _| {
+            checked += 1;
+            let reason = match live.peek(k, |lv| lv == v) {
+                None if at.is_some_and(|at| at <= now_unix_ms()) => None,
+                None => Some("missing_in_memory"),
+                Some(_) if !values => None,
+                Some(false) => Some("value_differs"),
+                Some(true) => match live.expires_at(k) {
+                    Some(live_at) if live_at != at => Some("expiry_differs"),
+                    _ => None,
+                },
+            };
+            if let Some(reason) = reason {
+                found.push((k.to_string(), reason));
+            }
+            Ok::<_, CacheError>(())
+        })
+        .expect("diff callback cannot fail");
+    live.try_for_each(|k, _, _, _| {
+        if scratch.peek(k, |_| ()).is_none() {
+            checked += 1;
+            found.push((k.to_string(), "missing_in_wal"));
+        }
+        Ok::<_, CacheError>(())
+    })
+    .expect("diff callback cannot fail");
+    (checked, found)
+}
+
+/// Отчёт из расхождений `diff` без ключей, записанных во время проверки.
+pub(crate) fn report(
+    seq: u64,
+    checked: u64,
+    found: Vec<(String, &'static str)>,
+    changed: &HashSet<String>,
+    max_examples: usize,
+) -> VerifyReport {
+    let total = found.len() as u64;
+    let mut report = VerifyReport {
+        seq,
+        keys_checked: checked,
+        ..Default::default()
+    };
+    for (key, reason) in found.into_iter().filter(|(k, _)| !changed.contains(k)) {
+        report.mismatches += 1;
+        if report.examples.len() < max_examples {
+            report.examples.push((key, reason.to_string()));
+        }
+    }
+    report.skipped = total - report.mismatches;
+    report
+}
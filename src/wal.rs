@@ -1,79 +1,2579 @@
+use crate::core::{now_unix_ms, Tombstone};
+use crate::crypto::{self, WalKey, KEY_CHECK_LEN};
 use crate::error::CacheError;
 use crate::CacheCore;
 use serde::{Deserialize, Serialize};
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod legacy;
+mod verify;
+
+pub use verify::{verify, CorruptRegion, SeqBreak, VerifyProgress, VerifyReport, VerifyStatus};
+
+/// Журнал состоит из сегментов `<name>.000001`, `<name>.000002`, ...
+/// Каждый сегмент начинается с заголовка: magic + версия формата + base_seq
+/// (seq последней записи до этого сегмента). Запись — len u32 | seq u64 | данные,
+/// seq идут подряд: base_seq + 1, base_seq + 2, ...
+/// Зашифрованный сегмент: после base_seq идёт проверочное значение ключа,
+/// а данные записи — это nonce + шифротекст bincode-записи.
+///
+/// Перед bincode-записью может стоять отметка времени (`STAMP_TAG` и
+/// unix-время в мс, когда запись сделали); её пишут все новые записи, а
+/// записи без неё читаются как раньше.
+///
+/// Версии 5 (открытый текст) и 6 (шифрованный) после base_seq хранят номер
+/// схемы записей (`WAL_SCHEMA`), до проверочного значения ключа. Сегмент с
+/// более новой схемой не открывается: его записи разобрались бы не в те
+/// варианты `WalRecord`. Записи старых схем читаются через определения из
+/// `legacy`, а `PersistentCore::new` после replay переписывает такой журнал
+/// компакцией.
+///
+/// Версии 3 и 4 — то же без номера схемы (схема 0). Версии 1 (открытый
+/// текст) и 2 (шифрованный) — старый формат без seq в записи, там seq
+/// неявные. Файл `<name>` без номера — журнал старых версий (сегмент 0), он
+/// может быть и без заголовка: тогда читается как сегмент с base_seq = 0.
+const WAL_MAGIC: &[u8; 4] = b"TMCW";
+const WAL_VERSION_LEGACY: u32 = 1;
+const WAL_VERSION_LEGACY_ENCRYPTED: u32 = 2;
+const WAL_VERSION_UNVERSIONED: u32 = 3;
+const WAL_VERSION_UNVERSIONED_ENCRYPTED: u32 = 4;
+const WAL_VERSION: u32 = 5;
+const WAL_VERSION_ENCRYPTED: u32 = 6;
+// заголовок версий 1-4
+const WAL_HEADER_LEN_UNVERSIONED: u64 = 16;
+const WAL_HEADER_LEN: u64 = 20;
+/// Номер схемы `WalRecord`, с которой пишутся новые сегменты. Растёт при
+/// любом изменении записей, кроме нового варианта в конце: тогда прежнее
+/// определение переезжает в `legacy` вместе с переводом в новое.
+pub const WAL_SCHEMA: u32 = 1;
+// len u32 + seq u64
+const RECORD_FRAME_LEN: usize = 12;
+// сколько батчей может ждать отправки подписчику, прежде чем его отключат
+const SUBSCRIBER_BACKLOG: usize = 4096;
+// старшие 16 бит версии в заголовке — флаги журнала
+const WAL_FLAGS_SHIFT: u32 = 16;
+/// Флаг заголовка: ключи журнала приведены к нижнему регистру
+/// (`PersistOptions::case_insensitive_keys`).
+pub const WAL_FLAG_FOLD_CASE: u32 = 1;
+// в режиме bulk записи уходят на диск кусками не меньше этого
+const BULK_FLUSH_BYTES: usize = 1 << 20;
+// начало отметки времени записи: тега варианта WalRecord с таким номером нет
+const STAMP_TAG: u32 = u32::MAX;
+// STAMP_TAG u32 + unix-время в мс u64
+const STAMP_LEN: usize = 12;
+
+thread_local! {
+    // `append` этого потока откладывает запись на диск, см. `deferring`
+    static DEFERRING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Выполняет `f` так, что `Wal::append` в этом потоке не ждёт записи на
+/// диск: запись получает seq и остаётся в памяти до `Wal::flush_deferred`
+/// или любой записи журнала другим путём. Для пачки команд одного
+/// соединения (см. `mux::serve_multiplexed`): ответы на них уходят только
+/// после `flush_deferred`.
+pub(crate) fn deferring<R>(f: impl FnOnce() -> R) -> R {
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            DEFERRING.with(|d| d.set(self.0));
+        }
+    }
+    let _restore = Restore(DEFERRING.with(|d| d.replace(true)));
+    f()
+}
+
+/// Когда данные WAL доходят до диска (fsync), а не только до page cache ОС.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// fsync на каждую запись: ответ клиенту уходит только после fsync
+    Always,
+    /// фоновый поток делает fsync раз в секунду, теряется не больше секунды
+    #[default]
+    EverySec,
+    /// fsync не вызывается, сбрасывает ОС
+    Never,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "never" => Ok(FsyncPolicy::Never),
+            other => Err(CacheError::Internal(format!(
+                "unknown fsync policy {:?}, expected always/everysec/never",
+                other
+            ))),
+        }
+    }
+}
+
+impl FsyncPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::Never => "never",
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum WalRecord {
     Set(String, Vec<u8>),
     Del(String),
     Pop(String),
+    // ключ, смещение бита, значение бита; см. bits.rs
+    SetBit(String, u64, bool),
+    // Set со сроком: ключ истекает в unix-время в мс
+    SetEx(String, Vec<u8>, u64),
+    // срок существующего ключа, unix-время в мс
+    Expire(String, u64),
+    // Set с тегами и, если есть, сроком
+    SetTagged(String, Vec<u8>, Vec<String>, Option<u64>),
+    // удаление в режиме надгробий: значение уходит в надгробие целиком, так
+    // что replay восстанавливает его без предыдущих записей ключа
+    Tombstone(String, Tombstone),
+    // значение вернулось из надгробия ключа
+    Undelete(String),
+    // обмен слотов двух ключей (значения, сроки, теги), см. CacheCore::swap
+    Swap(String, String),
+}
+
+impl WalRecord {
+    pub fn op(&self) -> &'static str {
+        match self {
+            WalRecord::Set(..) => "set",
+            WalRecord::Del(_) => "del",
+            WalRecord::Pop(_) => "pop",
+            WalRecord::SetBit(..) => "setbit",
+            WalRecord::SetEx(..) => "setex",
+            WalRecord::Expire(..) => "expire",
+            WalRecord::SetTagged(..) => "settagged",
+            WalRecord::Tombstone(..) => "tombstone",
+            WalRecord::Undelete(_) => "undelete",
+            WalRecord::Swap(..) => "swap",
+        }
+    }
+
+    // номер вида записи в `RECORD_OPS`
+    fn op_index(&self) -> usize {
+        RECORD_OPS
+            .iter()
+            .position(|&op| op == self.op())
+            .expect("RECORD_OPS lists every op")
+    }
+
+    /// Ключ записи; у Swap — первый из двух.
+    pub fn key(&self) -> &str {
+        match self {
+            WalRecord::Set(k, _)
+            | WalRecord::Del(k)
+            | WalRecord::Pop(k)
+            | WalRecord::SetBit(k, ..)
+            | WalRecord::SetEx(k, ..)
+            | WalRecord::Expire(k, _)
+            | WalRecord::SetTagged(k, ..)
+            | WalRecord::Tombstone(k, _)
+            | WalRecord::Undelete(k)
+            | WalRecord::Swap(k, _) => k,
+        }
+    }
+
+    pub fn value_len(&self) -> usize {
+        match self {
+            WalRecord::Set(_, v) | WalRecord::SetEx(_, v, _) | WalRecord::SetTagged(_, v, ..) => {
+                v.len()
+            }
+            WalRecord::Tombstone(_, t) => t.value.len(),
+            WalRecord::Del(_)
+            | WalRecord::Pop(_)
+            | WalRecord::SetBit(..)
+            | WalRecord::Expire(..)
+            | WalRecord::Undelete(_)
+            | WalRecord::Swap(..) => 0,
+        }
+    }
+
+    fn into_key(self) -> String {
+        match self {
+            WalRecord::Set(k, _)
+            | WalRecord::Del(k)
+            | WalRecord::Pop(k)
+            | WalRecord::SetBit(k, ..)
+            | WalRecord::SetEx(k, ..)
+            | WalRecord::Expire(k, _)
+            | WalRecord::SetTagged(k, ..)
+            | WalRecord::Tombstone(k, _)
+            | WalRecord::Undelete(k)
+            | WalRecord::Swap(k, _) => k,
+        }
+    }
+}
+
+/// Та же запись без копирования ключа и значения, для быстрого replay.
+/// Порядок вариантов обязан совпадать с `WalRecord`.
+#[derive(Deserialize)]
+enum WalRecordRef<'a> {
+    // значение разбирается только ради проверки формата записи
+    Set(&'a str, #[allow(dead_code)] &'a [u8]),
+    Del(&'a str),
+    Pop(&'a str),
+    SetBit(&'a str, u64, bool),
+    SetEx(&'a str, #[allow(dead_code)] &'a [u8], u64),
+    Expire(&'a str, u64),
+    SetTagged(
+        &'a str,
+        #[allow(dead_code)] &'a [u8],
+        #[allow(dead_code)] Vec<&'a str>,
+        Option<u64>,
+    ),
+    Tombstone(&'a str, #[serde(borrow)] TombstoneRef<'a>),
+    Undelete(&'a str),
+    Swap(&'a str, &'a str),
+}
+
+// виды записей в порядке вариантов `WalRecord`, имена — как у `WalRecord::op`
+const RECORD_OPS: [&str; 10] = [
+    "set",
+    "del",
+    "pop",
+    "setbit",
+    "setex",
+    "expire",
+    "settagged",
+    "tombstone",
+    "undelete",
+    "swap",
+];
+
+impl WalRecordRef<'_> {
+    // номер вида записи в `RECORD_OPS`
+    fn op_index(&self) -> usize {
+        match self {
+            WalRecordRef::Set(..) => 0,
+            WalRecordRef::Del(_) => 1,
+            WalRecordRef::Pop(_) => 2,
+            WalRecordRef::SetBit(..) => 3,
+            WalRecordRef::SetEx(..) => 4,
+            WalRecordRef::Expire(..) => 5,
+            WalRecordRef::SetTagged(..) => 6,
+            WalRecordRef::Tombstone(..) => 7,
+            WalRecordRef::Undelete(_) => 8,
+            WalRecordRef::Swap(..) => 9,
+        }
+    }
+}
+
+/// `Tombstone` без копирования, поля в том же порядке.
+#[derive(Deserialize)]
+struct TombstoneRef<'a> {
+    #[allow(dead_code)]
+    value: &'a [u8],
+    expires_at: Option<u64>,
+    #[allow(dead_code)]
+    #[serde(borrow)]
+    tags: Vec<&'a str>,
+    #[allow(dead_code)]
+    purge_at: u64,
+}
+
+/// Сводка по содержимому журнала на диске.
+#[derive(Clone, Debug, Default)]
+pub struct WalStats {
+    pub segments: u64,
+    // размер всех сегментов вместе с заголовками
+    pub bytes: u64,
+    pub sets: u64,
+    pub dels: u64,
+    pub pops: u64,
+    pub setbits: u64,
+    // Set со сроком считаются в sets, здесь — только Expire
+    pub expires: u64,
+    pub swaps: u64,
+    // 0, если в журнале нет записей
+    pub first_seq: u64,
+    pub last_seq: u64,
+    // оценка числа записей, которые ещё определяют значение ключа (последний
+    // Set по ключу); ключи сравниваются по хешу, поэтому это оценка
+    pub live: u64,
+}
+
+impl WalStats {
+    pub fn records(&self) -> u64 {
+        self.sets + self.dels + self.pops + self.setbits + self.expires + self.swaps
+    }
+}
+
+/// Запись журнала для отладочного просмотра (`inspect_wal`).
+#[derive(Clone, Debug)]
+pub struct InspectedRecord {
+    pub seq: u64,
+    pub op: &'static str,
+    pub key: String,
+    // второй ключ swap
+    pub other_key: Option<String>,
+    // unix-время в мс, когда запись сделали; None у записей старых версий
+    pub written_at: Option<u64>,
+    // None для del/pop
+    pub value_size: Option<usize>,
+    pub offset: u64,
+    pub file: PathBuf,
+}
+
+#[derive(Clone, Debug)]
+struct Segment {
+    num: u64,
+    path: PathBuf,
+    base_seq: u64,
+    // 0 для старого журнала без заголовка
+    header_len: u64,
+    encrypted: bool,
+    // seq записан в каждой записи (версии 3 и старше)
+    explicit_seq: bool,
+    // WAL_FLAG_*; 0 у журналов старого формата
+    flags: u32,
+    // схема записей, см. `WAL_SCHEMA`; 0 у версий 1-4
+    schema: u32,
+}
+
+struct WalState {
+    // активный (последний) сегмент, сюда идёт запись
+    file: File,
+    // все живые сегменты по возрастанию номера, последний — активный
+    segments: Vec<Segment>,
+    // seq последней записи в журнале
+    last_seq: u64,
+    // размер активного сегмента, т.е. смещение, куда ляжет следующая запись
+    len: u64,
+    // есть записанные, но ещё не прошедшие fsync данные
+    dirty: bool,
+    // статистика group commit с момента старта
+    batches: u64,
+    batched_records: u64,
+    // подписчики на новые записи (реплики); получают батчи в порядке seq
+    subscribers: Vec<SyncSender<Arc<Vec<u8>>>>,
+    // Some — режим bulk (`Wal::begin_bulk`): записи с seq, ещё не записанные в файл
+    bulk: Option<Vec<u8>>,
+    // отложенные записи (`deferring`) с seq, ещё не записанные в файл, и их число
+    deferred: Vec<u8>,
+    deferred_records: u64,
+}
+
+impl WalState {
+    fn sync(&mut self) -> std::io::Result<()> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn active(&self) -> &Segment {
+        self.segments
+            .last()
+            .expect("WAL always has an active segment")
+    }
+}
+
+/// Канал подписчика: батчи записей в формате сегмента, с уже проставленными seq.
+pub type Subscription = Receiver<Arc<Vec<u8>>>;
+
+/// Group commit: писатели складывают сериализованные записи в общий буфер,
+/// а один из них (лидер) пишет весь накопленный батч одним write + flush/fsync.
+/// Пока лидер пишет, следующие записи копятся в новый батч.
+#[derive(Default)]
+struct Staging {
+    buf: Vec<u8>,
+    records: u64,
+    // номер батча, который сейчас набирается
+    open_batch: u64,
+    // лидер пишет батч на диск
+    flushing: bool,
+    // результат записанного батча: seq перед первой записью батча
+    // и сколько его участников ещё не забрали результат
+    results: HashMap<u64, (Result<u64, CacheError>, u64)>,
 }
 
 pub struct Wal {
+    // имя журнала без номера сегмента
     path: PathBuf,
-    file: Mutex<File>,
+    state: Arc<Mutex<WalState>>,
+    staging: Mutex<Staging>,
+    batch_done: Condvar,
+    fsync: FsyncPolicy,
+    // ротация сегмента после стольких байт, None — без ротации по размеру
+    segment_size: Option<u64>,
+    // с ключом новые сегменты и записи шифруются
+    key: Option<Arc<WalKey>>,
+    // флаги в заголовке каждого сегмента, см. `Wal::open`
+    flags: u32,
+    // `lock_path(path)` под исключительной блокировкой, пока журнал открыт
+    _lock: File,
 }
 
 impl Wal {
-    pub fn open(path: PathBuf) -> Result<Self, CacheError> {
-        let file = OpenOptions::new()
-            .create(true)
+    /// Открывает журнал с флагами `flags` (`WAL_FLAG_*`): новые сегменты
+    /// пишутся с ними, а журнал, уже записанный с другими, не открывается.
+    /// Журнал, уже открытый на запись другим процессом, тоже не открывается,
+    /// см. `lock_path`.
+    pub fn open(
+        path: PathBuf,
+        fsync: FsyncPolicy,
+        segment_size: Option<u64>,
+        key: Option<Arc<WalKey>>,
+        flags: u32,
+    ) -> Result<Self, CacheError> {
+        let lock = lock_journal(&path)?;
+        let mut segments = discover_segments(&path, key.as_deref())?;
+        if segments.is_empty() {
+            segments.push(create_segment(&path, 1, 0, flags, key.as_deref())?);
+        }
+        let active = segments.last().expect("just ensured non-empty").clone();
+        let mut file = OpenOptions::new()
             .append(true)
             .read(true)
-            .open(&path)
+            .open(&active.path)
             .map_err(|e| CacheError::Internal(format!("open WAL: {}", e)))?;
+        let mut len = file
+            .metadata()
+            .map_err(|e| CacheError::Internal(format!("stat WAL: {}", e)))?
+            .len();
+        if len == 0 {
+            // пустой файл старого формата
+            len = write_header(&mut file, 0, flags, WAL_SCHEMA, key.as_deref())
+                .map_err(|e| CacheError::Internal(format!("write WAL header: {}", e)))?;
+            let active = segments.last_mut().expect("just ensured non-empty");
+            active.header_len = len;
+            active.encrypted = key.is_some();
+            active.explicit_seq = true;
+            active.flags = flags;
+            active.schema = WAL_SCHEMA;
+        }
+        if let Some(seg) = segments.iter().find(|s| s.flags != flags) {
+            return Err(CacheError::Unsupported(format!(
+                "WAL segment {} was written with case_insensitive_keys={}, \
+                 open it with the same setting",
+                seg.path.display(),
+                seg.flags & WAL_FLAG_FOLD_CASE != 0
+            )));
+        }
+        let last_seq = active.base_seq;
+        let state = Arc::new(Mutex::new(WalState {
+            file,
+            segments,
+            last_seq,
+            len,
+            dirty: false,
+            batches: 0,
+            batched_records: 0,
+            subscribers: Vec::new(),
+            bulk: None,
+            deferred: Vec::new(),
+            deferred_records: 0,
+        }));
+        if fsync == FsyncPolicy::EverySec {
+            spawn_syncer(Arc::downgrade(&state))?;
+        }
         Ok(Self {
             path,
-            file: Mutex::new(file),
+            state,
+            staging: Mutex::new(Staging::default()),
+            batch_done: Condvar::new(),
+            fsync,
+            segment_size,
+            key,
+            flags,
+            _lock: lock,
         })
     }
 
-    pub fn append(&self, rec: &WalRecord) -> Result<(), CacheError> {
-        let mut f = self
-            .file
+    fn lock_state(&self) -> Result<MutexGuard<'_, WalState>, CacheError> {
+        self.state
             .lock()
-            .map_err(|_| CacheError::Internal("WAL mutex poisoned".into()))?;
-        let data =
-            bincode::serialize(rec).map_err(|e| CacheError::Serialization(e.to_string()))?;
-        let len = (data.len() as u32).to_le_bytes();
-        f.write_all(&len)
-            .and_then(|_| f.write_all(&data))
-            .and_then(|_| f.flush())
-            .map_err(|e| CacheError::Internal(format!("write WAL: {}", e)))
-    }
-
-    pub fn replay(&self, core: &CacheCore) -> Result<(), CacheError> {
-        let mut f = File::open(&self.path)
-            .map_err(|e| CacheError::Internal(format!("open WAL for replay: {}", e)))?;
+            .map_err(|_| CacheError::Internal("WAL mutex poisoned".into()))
+    }
+
+    // всё, кроме самой записи батча, видит журнал с уже дописанными буфером
+    // bulk и отложенными записями
+    fn lock(&self) -> Result<MutexGuard<'_, WalState>, CacheError> {
+        let mut st = self.lock_state()?;
+        self.write_deferred(&mut st, &[], 0)?;
+        self.flush_bulk(&mut st)?;
+        Ok(st)
+    }
+
+    /// Дописывает запись и возвращает присвоенный ей seq. Возвращается только
+    /// после того, как батч с записью записан (и прошёл fsync для `Always`).
+    pub fn append(&self, rec: &WalRecord) -> Result<u64, CacheError> {
+        let mut data = encode_record(rec)?;
+        if let Some(key) = &self.key {
+            // после replay активный сегмент всегда в том же режиме, что и ключ
+            data = key.seal(&data)?;
+        }
+        if DEFERRING.with(Cell::get) {
+            return self.append_deferred(&data);
+        }
+
+        let mut stg = self.lock_staging()?;
+        let batch = stg.open_batch;
+        let index = stg.records;
+        stg.buf
+            .extend_from_slice(&(data.len() as u32).to_le_bytes());
+        // seq проставит лидер, когда батч получит номера под мьютексом журнала
+        stg.buf.extend_from_slice(&0u64.to_le_bytes());
+        stg.buf.extend_from_slice(&data);
+        stg.records += 1;
+
         loop {
-            let mut len_buf = [0u8; 4];
-            if let Err(e) = f.read_exact(&mut len_buf) {
-                use std::io::ErrorKind;
-                if e.kind() == ErrorKind::UnexpectedEof {
-                    break;
+            if let Some(base) = take_result(&mut stg, batch) {
+                return base.map(|base| base + index + 1);
+            }
+            if !stg.flushing {
+                // никто не пишет: становимся лидером и забираем весь открытый батч,
+                // в котором заведомо лежит и наша запись
+                stg.flushing = true;
+                let id = stg.open_batch;
+                stg.open_batch += 1;
+                let mut buf = std::mem::take(&mut stg.buf);
+                let records = std::mem::replace(&mut stg.records, 0);
+                drop(stg);
+
+                let res = self.write_batch(&mut buf, records);
+
+                stg = self.lock_staging()?;
+                stg.flushing = false;
+                stg.results.insert(id, (res, records));
+                self.batch_done.notify_all();
+                continue;
+            }
+            stg = self
+                .batch_done
+                .wait(stg)
+                .map_err(|_| CacheError::Internal("WAL staging mutex poisoned".into()))?;
+        }
+    }
+
+    // seq сразу, под мьютексом журнала: запись ляжет в файл раньше любой
+    // следующей
+    fn append_deferred(&self, data: &[u8]) -> Result<u64, CacheError> {
+        let mut st = self.lock_state()?;
+        let seq = st.last_seq + 1;
+        let st = &mut *st;
+        let buf = match &mut st.bulk {
+            Some(bulk) => bulk,
+            None => {
+                st.deferred_records += 1;
+                &mut st.deferred
+            }
+        };
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&seq.to_le_bytes());
+        buf.extend_from_slice(data);
+        st.last_seq = seq;
+        Ok(seq)
+    }
+
+    /// Пишет отложенные записи (`deferring`) одним write (и fsync для
+    /// `Always`); без них ничего не делает.
+    pub fn flush_deferred(&self) -> Result<(), CacheError> {
+        self.lock().map(drop)
+    }
+
+    // отложенные записи и за ними батч `buf` из `records` новых записей —
+    // одним write; seq отложенных уже учтены в last_seq
+    fn write_deferred(
+        &self,
+        st: &mut WalState,
+        buf: &[u8],
+        records: u64,
+    ) -> Result<(), CacheError> {
+        if st.deferred.is_empty() {
+            return match records {
+                0 => Ok(()),
+                _ => self.write_locked(st, buf, records),
+            };
+        }
+        let mut all = std::mem::take(&mut st.deferred);
+        all.extend_from_slice(buf);
+        if records == 0 {
+            // отложенные записи сами по себе — отдельный батч
+            st.batches += 1;
+        }
+        st.batched_records += std::mem::replace(&mut st.deferred_records, 0);
+        self.write_locked(st, &all, records)
+    }
+
+    /// Пишет `recs` одним батчем мимо group commit (один write и максимум один
+    /// fsync на весь батч) и возвращает seq последней записи.
+    pub fn append_batch(&self, recs: &[WalRecord]) -> Result<u64, CacheError> {
+        let mut buf = Vec::new();
+        for rec in recs {
+            let mut data = encode_record(rec)?;
+            if let Some(key) = &self.key {
+                data = key.seal(&data)?;
+            }
+            buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&0u64.to_le_bytes());
+            buf.extend_from_slice(&data);
+        }
+        let records = recs.len() as u64;
+        Ok(self.write_batch(&mut buf, records)? + records)
+    }
+
+    fn lock_staging(&self) -> Result<MutexGuard<'_, Staging>, CacheError> {
+        self.staging
+            .lock()
+            .map_err(|_| CacheError::Internal("WAL staging mutex poisoned".into()))
+    }
+
+    /// Пишет батч из `records` записей, возвращает seq перед первой из них.
+    fn write_batch(&self, buf: &mut [u8], records: u64) -> Result<u64, CacheError> {
+        let mut st = self.lock_state()?;
+        let base = st.last_seq;
+        let mut pos = 0;
+        for seq in base + 1..=base + records {
+            let len = u32::from_le_bytes(buf[pos..pos + 4].try_into().expect("4 bytes")) as usize;
+            buf[pos + 4..pos + RECORD_FRAME_LEN].copy_from_slice(&seq.to_le_bytes());
+            pos += RECORD_FRAME_LEN + len;
+        }
+        st.batches += 1;
+        st.batched_records += records;
+        if let Some(bulk) = &mut st.bulk {
+            bulk.extend_from_slice(buf);
+            let full = bulk.len() >= BULK_FLUSH_BYTES;
+            st.last_seq += records;
+            if full {
+                self.flush_bulk(&mut st)?;
+            }
+            return Ok(base);
+        }
+        self.write_deferred(&mut st, buf, records)?;
+        Ok(base)
+    }
+
+    fn flush_bulk(&self, st: &mut WalState) -> Result<(), CacheError> {
+        let Some(buf) = st.bulk.as_mut().filter(|b| !b.is_empty()) else {
+            return Ok(());
+        };
+        let buf = std::mem::take(buf);
+        // seq этих записей уже учтены в last_seq
+        self.write_locked(st, &buf, 0)
+    }
+
+    fn write_locked(&self, st: &mut WalState, buf: &[u8], records: u64) -> Result<(), CacheError> {
+        st.file
+            .write_all(buf)
+            .and_then(|_| st.file.flush())
+            .map_err(|e| CacheError::Internal(format!("write WAL: {}", e)))?;
+        st.last_seq += records;
+        st.len += buf.len() as u64;
+        st.dirty = true;
+        if !st.subscribers.is_empty() {
+            // под мьютексом журнала, так что батчи уходят строго по seq; медленного
+            // подписчика не ждём, а отключаем — он переподключится и догонит по журналу
+            let batch = Arc::new(buf.to_vec());
+            st.subscribers
+                .retain(|s| s.try_send(Arc::clone(&batch)).is_ok());
+        }
+        if self.fsync == FsyncPolicy::Always {
+            st.sync()
+                .map_err(|e| CacheError::Internal(format!("fsync WAL: {}", e)))?;
+        }
+        if let Some(limit) = self.segment_size {
+            if st.len >= limit {
+                // батч уже записан: неудачная ротация не должна ронять запись,
+                // попробуем снова на следующем батче
+                if let Err(e) = self.rotate_locked(st) {
+                    eprintln!("WAL segment rotation error: {:?}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Режим массовой загрузки: `append` только ставит записи в буфер, а на
+    /// диск они уходят кусками по `BULK_FLUSH_BYTES` (fsync при `Always` —
+    /// один на кусок). До `end_bulk` последние записи теряются при сбое.
+    pub fn begin_bulk(&self) -> Result<(), CacheError> {
+        self.lock()?.bulk.get_or_insert_with(Vec::new);
+        Ok(())
+    }
+
+    /// Дописывает буфер bulk, делает fsync и возвращает обычный режим.
+    pub fn end_bulk(&self) -> Result<(), CacheError> {
+        let mut st = self.lock()?;
+        st.bulk = None;
+        st.sync()
+            .map_err(|e| CacheError::Internal(format!("fsync WAL: {}", e)))
+    }
+
+    /// Принудительный fsync накопленных записей (независимо от политики).
+    pub fn sync(&self) -> Result<(), CacheError> {
+        self.lock()?
+            .sync()
+            .map_err(|e| CacheError::Internal(format!("fsync WAL: {}", e)))
+    }
+
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        self.fsync
+    }
+
+    /// Размер сегмента, после которого начинается новый; `None` — один файл.
+    pub fn segment_size(&self) -> Option<u64> {
+        self.segment_size
+    }
+
+    /// Имя журнала без номера сегмента.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Ключ шифрования журнала; им же шифруются снапшоты.
+    pub fn key(&self) -> Option<&WalKey> {
+        self.key.as_deref()
+    }
+
+    pub fn last_seq(&self) -> Result<u64, CacheError> {
+        Ok(self.lock()?.last_seq)
+    }
+
+    pub fn segment_count(&self) -> Result<usize, CacheError> {
+        Ok(self.lock()?.segments.len())
+    }
+
+    /// Число батчей и записей в них с момента старта.
+    pub fn batch_stats(&self) -> Result<(u64, u64), CacheError> {
+        let st = self.lock()?;
+        Ok((st.batches, st.batched_records))
+    }
+
+    /// Подписка на новые записи: возвращает seq, после которого пойдут батчи,
+    /// и канал с батчами в формате записей сегмента (см. `decode_batch`).
+    pub fn subscribe(&self) -> Result<(u64, Subscription), CacheError> {
+        let mut st = self.lock()?;
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_BACKLOG);
+        st.subscribers.push(tx);
+        Ok((st.last_seq, rx))
+    }
+
+    /// Разбирает батч из подписки в записи с их seq.
+    pub fn decode_batch(&self, buf: &[u8]) -> Result<Vec<(u64, WalRecord)>, CacheError> {
+        let mut recs = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let len = u32::from_le_bytes(buf[pos..pos + 4].try_into().expect("4 bytes")) as usize;
+            let seq = u64::from_le_bytes(
+                buf[pos + 4..pos + RECORD_FRAME_LEN]
+                    .try_into()
+                    .expect("8 bytes"),
+            );
+            let data = &buf[pos + RECORD_FRAME_LEN..pos + RECORD_FRAME_LEN + len];
+            let data = match &self.key {
+                Some(key) => Cow::Owned(key.open(data).ok_or_else(|| {
+                    CacheError::Internal(format!("WAL batch record {} failed authentication", seq))
+                })?),
+                None => Cow::Borrowed(data),
+            };
+            let rec = bincode::deserialize(split_stamp(&data).1).map_err(|e| {
+                CacheError::Serialization(format!("WAL batch record {}: {}", seq, e))
+            })?;
+            recs.push((seq, rec));
+            pos += RECORD_FRAME_LEN + len;
+        }
+        Ok(recs)
+    }
+
+    /// Читает с диска записи с seq в (`after`, `upto`] по порядку. Возвращает
+    /// `false`, если часть из них уже удалена компакцией.
+    pub fn read_range(
+        &self,
+        after: u64,
+        upto: u64,
+        mut on_record: impl FnMut(u64, WalRecord) -> Result<(), CacheError>,
+    ) -> Result<bool, CacheError> {
+        let segments = self.lock()?.segments.clone();
+        if segments[0].base_seq > after {
+            return Ok(false);
+        }
+        for (i, seg) in segments.iter().enumerate() {
+            if segments
+                .get(i + 1)
+                .is_some_and(|next| next.base_seq <= after)
+            {
+                continue;
+            }
+            if seg.base_seq >= upto {
+                break;
+            }
+            scan_segment(seg, |raw| {
+                if raw.seq > upto {
+                    return Ok(false);
+                }
+                if raw.seq > after {
+                    let seq = raw.seq;
+                    on_record(seq, decode_record(seg, self.key(), raw)?)?;
+                }
+                Ok(true)
+            })?;
+        }
+        Ok(true)
+    }
+
+    /// Начинает журнал заново с `seq`: новый пустой сегмент, все старые удаляются.
+    /// Вызывается, когда снапшот на `seq` уже записан (полная синхронизация реплики).
+    pub fn reset(&self, seq: u64) -> Result<(), CacheError> {
+        let mut st = self.lock()?;
+        st.last_seq = seq;
+        self.start_segment_locked(&mut st)?;
+        let active = st.segments.pop().expect("just started a segment");
+        for seg in std::mem::take(&mut st.segments) {
+            fs::remove_file(&seg.path)
+                .map_err(|e| CacheError::Internal(format!("remove WAL segment: {}", e)))?;
+        }
+        st.segments.push(active);
+        Ok(())
+    }
+
+    /// Сколько записей накопилось с момента последней компакции.
+    pub fn pending(&self) -> Result<u64, CacheError> {
+        let st = self.lock()?;
+        Ok(st.last_seq - st.segments[0].base_seq)
+    }
+
+    /// Читает все сегменты с диска и считает записи по типам. Запись в журнал
+    /// при этом не блокируется; сегмент, удалённый компакцией посреди чтения,
+    /// пропускается.
+    pub fn stats(&self) -> Result<WalStats, CacheError> {
+        let segments = self.lock()?.segments.clone();
+        let mut stats = WalStats::default();
+        // хеш ключа -> последняя операция по нему была Set
+        let mut last_op: HashMap<u64, bool> = HashMap::new();
+        for seg in &segments {
+            let bytes = match fs::metadata(&seg.path) {
+                Ok(m) => m.len(),
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(CacheError::Internal(format!("stat WAL segment: {}", e))),
+            };
+            stats.segments += 1;
+            stats.bytes += bytes;
+            scan_segment(seg, |raw| {
+                let seq = raw.seq;
+                let rec = decode_record(seg, self.key(), raw)?;
+                match rec {
+                    WalRecord::Set(..)
+                    | WalRecord::SetEx(..)
+                    | WalRecord::SetTagged(..)
+                    | WalRecord::Undelete(_) => stats.sets += 1,
+                    WalRecord::Del(_) | WalRecord::Tombstone(..) => stats.dels += 1,
+                    WalRecord::Pop(_) => stats.pops += 1,
+                    WalRecord::SetBit(..) => stats.setbits += 1,
+                    WalRecord::Expire(..) => stats.expires += 1,
+                    WalRecord::Swap(..) => stats.swaps += 1,
+                }
+                let hash = |k: &str| {
+                    let mut h = DefaultHasher::new();
+                    k.hash(&mut h);
+                    h.finish()
+                };
+                if let WalRecord::Swap(a, b) = &rec {
+                    // ключи меняются и тем, жива ли их последняя запись
+                    let (a, b) = (hash(a), hash(b));
+                    let (live_a, live_b) = (last_op.remove(&a), last_op.remove(&b));
+                    last_op.extend(live_b.map(|live| (a, live)));
+                    last_op.extend(live_a.map(|live| (b, live)));
+                } else if !matches!(rec, WalRecord::Expire(..)) {
+                    // Expire не меняет, живо ли значение
+                    let live = !matches!(
+                        rec,
+                        WalRecord::Del(_) | WalRecord::Pop(_) | WalRecord::Tombstone(..)
+                    );
+                    last_op.insert(hash(rec.key()), live);
+                }
+                if stats.first_seq == 0 {
+                    stats.first_seq = seq;
+                }
+                stats.last_seq = seq;
+                Ok(true)
+            })?;
+        }
+        stats.live = last_op.values().filter(|&&set| set).count() as u64;
+        Ok(stats)
+    }
+
+    /// Доигрывает в `core` записи с seq > `after_seq` (seq снапшота, 0 — снапшота нет).
+    /// Первый проход находит последнюю запись по каждому ключу, второй читает
+    /// и применяет только их, так что значения перезаписанных ключей не
+    /// копируются; результат тот же, что у последовательного применения записей.
+    /// Сегменты, целиком покрытые снапшотом, не читаются. Оборванная запись в конце
+    /// сегмента (падение посреди write) отбрасывается, а активный сегмент
+    /// обрезается по последней целой записи. О ходе сообщает в `report`, см.
+    /// `ReplayProgress`; `threads` — как `PersistOptions::replay_threads`.
+    /// Возвращает seq последней известной записи.
+    pub fn replay(
+        &self,
+        core: &CacheCore,
+        after_seq: u64,
+        threads: Option<usize>,
+        report: &mut dyn FnMut(&ReplayProgress),
+    ) -> Result<u64, CacheError> {
+        let segments = self.lock()?.segments.clone();
+        let (seq, active_len) = replay_segments(
+            &segments,
+            core,
+            after_seq,
+            self.key.as_deref(),
+            threads,
+            report,
+        )?;
+
+        let mut st = self.lock()?;
+        if let Some((good_len, torn)) = active_len {
+            if torn {
+                st.file
+                    .set_len(good_len)
+                    .map_err(|e| CacheError::Internal(format!("truncate torn WAL tail: {}", e)))?;
+            }
+            st.len = good_len;
+        }
+        st.last_seq = seq.max(after_seq);
+        let active = st.active();
+        if active.encrypted != self.key.is_some()
+            || !active.explicit_seq
+            || active.schema != WAL_SCHEMA
+        {
+            // ключ только что включили или сегмент старого формата: дописывать
+            // в него нельзя, старые сегменты уйдут при следующей компакции
+            self.start_segment_locked(&mut st)?;
+        }
+        Ok(st.last_seq)
+    }
+
+    /// Закрывает активный сегмент и начинает новый с текущего seq, который и
+    /// возвращает. Всё до этого seq лежит в старых сегментах, и их можно удалить
+    /// через `remove_covered`, когда снапшот на этот seq записан.
+    pub fn rotate(&self) -> Result<u64, CacheError> {
+        let mut st = self.lock()?;
+        self.rotate_locked(&mut st)?;
+        Ok(st.last_seq)
+    }
+
+    fn rotate_locked(&self, st: &mut WalState) -> Result<(), CacheError> {
+        if st.last_seq == st.active().base_seq {
+            // активный сегмент пуст, новый не нужен
+            return Ok(());
+        }
+        self.start_segment_locked(st)
+    }
+
+    fn start_segment_locked(&self, st: &mut WalState) -> Result<(), CacheError> {
+        // сначала старый сегмент целиком на диск, потом новый: после падения
+        // между шагами на диске либо только старый, либо оба полностью записаны
+        st.sync()
+            .map_err(|e| CacheError::Internal(format!("fsync WAL segment: {}", e)))?;
+        let seg = create_segment(
+            &self.path,
+            st.active().num + 1,
+            st.last_seq,
+            self.flags,
+            self.key.as_deref(),
+        )?;
+        st.file = OpenOptions::new()
+            .append(true)
+            .read(true)
+            .open(&seg.path)
+            .map_err(|e| CacheError::Internal(format!("open WAL segment: {}", e)))?;
+        st.len = seg.header_len;
+        st.segments.push(seg);
+        Ok(())
+    }
+
+    /// Самая старая схема записей среди сегментов, если она старше
+    /// `WAL_SCHEMA`; такой журнал стоит переписать компакцией.
+    pub fn legacy_schema(&self) -> Result<Option<u32>, CacheError> {
+        let st = self.lock()?;
+        Ok(st
+            .segments
+            .iter()
+            .map(|s| s.schema)
+            .filter(|&schema| schema < WAL_SCHEMA)
+            .min())
+    }
+
+    /// Удаляет сегменты, все записи которых имеют seq <= `seq` (снапшот их покрывает).
+    /// Активный сегмент не удаляется никогда. Сегмент, который ещё читает
+    /// реплика, удаляется и на Windows: std открывает файлы с FILE_SHARE_DELETE,
+    /// а номера сегментов не повторяются, так что имя не понадобится снова.
+    pub fn remove_covered(&self, seq: u64) -> Result<(), CacheError> {
+        let mut st = self.lock()?;
+        while st.segments.len() > 1 && st.segments[1].base_seq <= seq {
+            let seg = st.segments.remove(0);
+            fs::remove_file(&seg.path)
+                .map_err(|e| CacheError::Internal(format!("remove WAL segment: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Wal {
+    // при штатной остановке дописываем на диск всё, что не успел фоновый fsync
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            eprintln!("WAL fsync on shutdown: {:?}", e);
+        }
+    }
+}
+
+/// Поток для политики everysec: живёт, пока жив сам журнал.
+fn spawn_syncer(state: Weak<Mutex<WalState>>) -> Result<(), CacheError> {
+    thread::Builder::new()
+        .name("tiny-mp-cache-fsync".into())
+        .spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            let Some(state) = state.upgrade() else {
+                break;
+            };
+            let res = match state.lock() {
+                Ok(mut st) => st.sync(),
+                Err(_) => break,
+            };
+            if let Err(e) = res {
+                eprintln!("WAL background fsync error: {}", e);
+            }
+        })
+        .map(|_| ())
+        .map_err(|e| CacheError::Internal(format!("spawn WAL fsync thread: {}", e)))
+}
+
+/// Забирает результат батча; последний участник удаляет его из таблицы.
+fn take_result(stg: &mut Staging, batch: u64) -> Option<Result<u64, CacheError>> {
+    let (res, waiters) = stg.results.get_mut(&batch)?;
+    let res = res.clone();
+    *waiters -= 1;
+    if *waiters == 0 {
+        stg.results.remove(&batch);
+    }
+    Some(res)
+}
+
+/// Целая запись сегмента: seq, смещение от начала файла и данные как на
+/// диске (для зашифрованного сегмента — nonce + шифротекст).
+struct RawRecord<'a> {
+    seq: u64,
+    offset: u64,
+    data: &'a [u8],
+}
+
+/// Чем закончилось чтение сегмента.
+struct ScanEnd {
+    // seq последней целой записи
+    seq: u64,
+    // длина целой части файла
+    good_len: u64,
+    // в конце оборванная запись
+    torn: bool,
+}
+
+const SCAN_BUF_SIZE: usize = 1 << 20;
+
+fn frame_len(seg: &Segment) -> usize {
+    if seg.explicit_seq {
+        RECORD_FRAME_LEN
+    } else {
+        4
+    }
+}
+
+/// Читает записи сегмента по порядку и проверяет их seq. Колбэк может
+/// остановить чтение, вернув `false`.
+fn scan_segment(
+    seg: &Segment,
+    mut on_record: impl FnMut(RawRecord<'_>) -> Result<bool, CacheError>,
+) -> Result<ScanEnd, CacheError> {
+    let f = File::open(&seg.path)
+        .map_err(|e| CacheError::Internal(format!("open WAL for replay: {}", e)))?;
+    let mut f = BufReader::with_capacity(SCAN_BUF_SIZE, f);
+    f.seek(SeekFrom::Start(seg.header_len))
+        .map_err(|e| CacheError::Internal(format!("read WAL header: {}", e)))?;
+
+    let frame_len = frame_len(seg);
+    let mut seq = seg.base_seq;
+    let mut pos = seg.header_len;
+    // один буфер на все записи сегмента
+    let mut data = Vec::new();
+    let end = |seq, good_len, torn| {
+        Ok(ScanEnd {
+            seq,
+            good_len,
+            torn,
+        })
+    };
+    loop {
+        let mut frame = [0u8; RECORD_FRAME_LEN];
+        let n = read_full(&mut f, &mut frame[..frame_len])
+            .map_err(|e| CacheError::Internal(format!("read WAL len: {}", e)))?;
+        if n == 0 {
+            return end(seq, pos, false);
+        }
+        if n < frame_len {
+            return end(seq, pos, true);
+        }
+        let len = u32::from_le_bytes(frame[..4].try_into().expect("4 bytes")) as usize;
+        data.resize(len, 0);
+        if read_full(&mut f, &mut data)
+            .map_err(|e| CacheError::Internal(format!("read WAL rec: {}", e)))?
+            < len
+        {
+            return end(seq, pos, true);
+        }
+        let offset = pos;
+        if seg.explicit_seq {
+            let stored = u64::from_le_bytes(frame[4..].try_into().expect("8 bytes"));
+            if stored != seq + 1 {
+                // seq строго растут на единицу; разрыв или повтор — порча или ручная правка
+                return Err(CacheError::Internal(format!(
+                    "WAL record at offset {} in {} has seq {}, expected {}",
+                    offset,
+                    seg.path.display(),
+                    stored,
+                    seq + 1
+                )));
+            }
+        }
+        seq += 1;
+        pos += (frame_len + len) as u64;
+        let raw = RawRecord {
+            seq,
+            offset,
+            data: &data,
+        };
+        if !on_record(raw)? {
+            return end(seq, pos, false);
+        }
+    }
+}
+
+/// Данные новой записи: отметка времени и bincode.
+fn encode_record(rec: &WalRecord) -> Result<Vec<u8>, CacheError> {
+    let mut data = Vec::with_capacity(STAMP_LEN + 32);
+    data.extend_from_slice(&STAMP_TAG.to_le_bytes());
+    data.extend_from_slice(&now_unix_ms().to_le_bytes());
+    bincode::serialize_into(&mut data, rec)
+        .map_err(|e| CacheError::Serialization(e.to_string()))?;
+    Ok(data)
+}
+
+/// Отметка времени записи, если есть, и bincode самой записи.
+fn split_stamp(data: &[u8]) -> (Option<u64>, &[u8]) {
+    match data.get(..STAMP_LEN) {
+        Some(head) if head[..4] == STAMP_TAG.to_le_bytes() => {
+            let at = u64::from_le_bytes(head[4..].try_into().expect("8 bytes"));
+            (Some(at), &data[STAMP_LEN..])
+        }
+        _ => (None, data),
+    }
+}
+
+/// Открытые данные записи (для зашифрованного сегмента — расшифрованные).
+fn open_record<'a>(
+    seg: &Segment,
+    key: Option<&WalKey>,
+    raw: RawRecord<'a>,
+) -> Result<Cow<'a, [u8]>, CacheError> {
+    if !seg.encrypted {
+        return Ok(Cow::Borrowed(raw.data));
+    }
+    // ключ уже сверен с заголовком, так что это порча, а не чужой ключ
+    key.and_then(|k| k.open(raw.data))
+        .map(Cow::Owned)
+        .ok_or_else(|| {
+            CacheError::Internal(format!(
+                "WAL record at offset {} in {} failed authentication",
+                raw.offset,
+                seg.path.display()
+            ))
+        })
+}
+
+fn decode_record(
+    seg: &Segment,
+    key: Option<&WalKey>,
+    raw: RawRecord<'_>,
+) -> Result<WalRecord, CacheError> {
+    decode_stamped(seg, key, raw).map(|(_, rec)| rec)
+}
+
+/// Запись и её отметка времени (`None` у записей старых версий).
+fn decode_stamped(
+    seg: &Segment,
+    key: Option<&WalKey>,
+    raw: RawRecord<'_>,
+) -> Result<(Option<u64>, WalRecord), CacheError> {
+    let offset = raw.offset;
+    let data = open_record(seg, key, raw)?;
+    let (at, body) = split_stamp(&data);
+    let rec = decode_body(seg.schema, body).map_err(|e| {
+        CacheError::Serialization(format!("WAL record at offset {}: {}", offset, e))
+    })?;
+    Ok((at, rec))
+}
+
+/// bincode записи схемы `schema`, переведённый в нынешний `WalRecord`.
+fn decode_body(schema: u32, body: &[u8]) -> bincode::Result<WalRecord> {
+    if schema == WAL_SCHEMA {
+        bincode::deserialize(body)
+    } else {
+        legacy::decode(schema, body)
+    }
+}
+
+/// Как часто replay сообщает о своём ходе.
+pub const REPLAY_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Ход replay при старте, см. `PersistOptions::on_replay`: не чаще раза в
+/// `REPLAY_REPORT_INTERVAL` и ещё раз в конце, с `done`. Байты считаются по
+/// первому проходу; сегменты, целиком покрытые снапшотом, не читаются и в
+/// `bytes_total` не входят. После 100% ещё применяются значения последних
+/// записей, так что до `done` может пройти время.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayProgress {
+    // записи после снапшота
+    pub records: u64,
+    pub bytes_read: u64,
+    pub bytes_total: u64,
+    // ключей в памяти вместе с восстановленными из снапшота; истёкшие за время
+    // остановки убираются уже после replay
+    pub keys: u64,
+    pub elapsed: Duration,
+    // (вид записи, сколько их), только встреченные виды
+    pub ops: Vec<(&'static str, u64)>,
+    pub done: bool,
+}
+
+impl ReplayProgress {
+    /// Прочитанная доля журнала в процентах; пустой журнал — 100.
+    pub fn percent(&self) -> f64 {
+        if self.bytes_total == 0 {
+            return 100.0;
+        }
+        (self.bytes_read as f64 * 100.0 / self.bytes_total as f64).min(100.0)
+    }
+
+    /// Разбирает строку лога в формате `Display`: так `spawn_server` следит
+    /// за replay в дочернем процессе.
+    #[cfg(feature = "python")]
+    pub(crate) fn parse_log_line(line: &str) -> Option<Self> {
+        let (done, rest) = match line.strip_prefix("WAL replay done: ") {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix("WAL replay: ")?),
+        };
+        let (main, ops) = rest.split_once("; ").unwrap_or((rest, ""));
+        let mut parts = main.split(", ");
+        let (read, total) = parts.next()?.split_once(' ')?.0.split_once('/')?;
+        let records = parts.next()?.strip_suffix(" records")?.parse().ok()?;
+        let keys = parts.next()?.strip_suffix(" keys")?.parse().ok()?;
+        let secs = parts.next()?.strip_suffix('s')?.parse().ok()?;
+        let ops = ops
+            .split(", ")
+            .filter(|op| !op.is_empty())
+            .map(|op| {
+                let (name, n) = op.split_once(' ')?;
+                let name = RECORD_OPS.iter().find(|known| **known == name)?;
+                Some((*name, n.parse().ok()?))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            records,
+            bytes_read: read.parse().ok()?,
+            bytes_total: total.parse().ok()?,
+            keys,
+            elapsed: Duration::try_from_secs_f64(secs).ok()?,
+            ops,
+            done,
+        })
+    }
+}
+
+impl fmt::Display for ReplayProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WAL replay{}: {}/{} bytes ({:.0}%), {} records, {} keys, {:.1}s",
+            if self.done { " done" } else { "" },
+            self.bytes_read,
+            self.bytes_total,
+            self.percent(),
+            self.records,
+            self.keys,
+            self.elapsed.as_secs_f64()
+        )?;
+        for (i, (op, n)) in self.ops.iter().enumerate() {
+            write!(f, "{}{} {}", if i == 0 { "; " } else { ", " }, op, n)?;
+        }
+        Ok(())
+    }
+}
+
+/// Callback хода replay для `PersistOptions::on_replay`.
+#[derive(Clone)]
+pub struct ReplayHook(pub Arc<dyn Fn(&ReplayProgress) + Send + Sync>);
+
+impl fmt::Debug for ReplayHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReplayHook")
+    }
+}
+
+/// Счётчики идущего replay и когда о нём сообщали последний раз.
+struct Progress<'a> {
+    report: &'a mut dyn FnMut(&ReplayProgress),
+    started: Instant,
+    reported: Instant,
+    counts: [u64; RECORD_OPS.len()],
+    records: u64,
+    // записи вместе с покрытыми снапшотом, для редкой проверки часов
+    seen: u64,
+    // байты сегментов до текущего
+    passed: u64,
+    read: u64,
+    total: u64,
+    // `read` при последней проверке часов
+    checked: u64,
+}
+
+impl<'a> Progress<'a> {
+    fn new(total: u64, report: &'a mut dyn FnMut(&ReplayProgress)) -> Self {
+        let now = Instant::now();
+        Self {
+            report,
+            started: now,
+            reported: now,
+            counts: [0; RECORD_OPS.len()],
+            records: 0,
+            seen: 0,
+            passed: 0,
+            read: 0,
+            total,
+            checked: 0,
+        }
+    }
+
+    /// Запись, которая кончается на `end` в текущем сегменте; `op` — вид из
+    /// `RECORD_OPS`, у записи из снапшота None.
+    fn record(&mut self, op: Option<usize>, end: u64, core: &CacheCore) {
+        self.read = self.passed + end;
+        self.seen += 1;
+        if let Some(op) = op {
+            self.counts[op] += 1;
+            self.records += 1;
+        }
+        // на часы смотрим не на каждой записи
+        if self.seen.is_multiple_of(1024) || self.read - self.checked >= 16 << 20 {
+            self.checked = self.read;
+            if self.reported.elapsed() >= REPLAY_REPORT_INTERVAL {
+                self.emit(core, false);
+            }
+        }
+    }
+
+    fn segment_done(&mut self, size: u64) {
+        self.passed += size;
+        self.read = self.passed;
+    }
+
+    fn emit(&mut self, core: &CacheCore, done: bool) {
+        let progress = ReplayProgress {
+            records: self.records,
+            bytes_read: self.read,
+            bytes_total: self.total,
+            keys: core.len() as u64,
+            elapsed: self.started.elapsed(),
+            ops: RECORD_OPS
+                .iter()
+                .zip(self.counts)
+                .filter(|&(_, n)| n > 0)
+                .map(|(op, n)| (*op, n))
+                .collect(),
+            done,
+        };
+        (self.report)(&progress);
+        self.reported = Instant::now();
+    }
+}
+
+/// Итог доигрывания по ключу.
+#[derive(Default)]
+struct KeyReplay {
+    // где лежит последний Set (номер сегмента в списке и смещение записи),
+    // `Some(None)` — последним было удаление, `None` — ни того ни другого,
+    // значение из снапшота
+    base: Option<Option<(usize, u64)>>,
+    // SetBit после base по порядку журнала
+    bits: Vec<(u64, bool)>,
+    // срок из последнего SetEx или Expire после base
+    expires_at: Option<u64>,
+    // последний Tombstone, ещё не возвращённый Undelete: сегмент, смещение и
+    // срок ключа из него
+    tombstone: Option<(usize, u64, Option<u64>)>,
+    // Undelete вернул надгробие из снапшота; `restore` — и значение из него
+    // до сих пор последнее
+    snapshot_tomb_used: bool,
+    restore: bool,
+    // отметка времени последней записи значения; None — запись без отметки
+    written_at: Option<u64>,
+    // сколько раз значение записали в этом проходе: после удаления, если
+    // `fresh`, иначе поверх версии из снапшота
+    writes: u64,
+    fresh: bool,
+}
+
+impl KeyReplay {
+    fn written(&mut self, at: Option<u64>) {
+        self.written_at = at;
+        self.writes += 1;
+    }
+
+    fn removed(&mut self) {
+        self.written_at = None;
+        self.writes = 0;
+        self.fresh = true;
+    }
+}
+
+type Replayed = HashMap<String, KeyReplay>;
+
+/// Что первый проход помнит о записи, кроме ключа и значения.
+#[derive(Clone, Copy)]
+enum Op {
+    // Set, SetEx и SetTagged; срок записи, если есть
+    Write(Option<u64>),
+    // Del и Pop
+    Remove,
+    SetBit(u64, bool),
+    Expire(u64),
+    // срок ключа из надгробия
+    Tombstone(Option<u64>),
+    Undelete,
+}
+
+/// Запись для первого прохода: Swap трогает два ключа и идёт отдельно.
+enum Decoded<'a> {
+    One(&'a str, Op),
+    Swap(&'a str, &'a str),
+}
+
+// номер сегмента в списке и смещение записи
+type RecordPos = (usize, u64);
+
+/// Куда первый проход отдаёт записи, с отметкой времени и местом каждой.
+type OnRecord<'a> = dyn FnMut(Decoded<'_>, Option<u64>, RecordPos) -> Result<(), CacheError> + 'a;
+
+impl<'a> From<WalRecordRef<'a>> for Decoded<'a> {
+    fn from(rec: WalRecordRef<'a>) -> Self {
+        let (k, op) = match rec {
+            WalRecordRef::Set(k, _) => (k, Op::Write(None)),
+            WalRecordRef::SetEx(k, _, at) => (k, Op::Write(Some(at))),
+            WalRecordRef::SetTagged(k, _, _, at) => (k, Op::Write(at)),
+            WalRecordRef::Del(k) | WalRecordRef::Pop(k) => (k, Op::Remove),
+            WalRecordRef::SetBit(k, offset, bit) => (k, Op::SetBit(offset, bit)),
+            WalRecordRef::Expire(k, at) => (k, Op::Expire(at)),
+            WalRecordRef::Tombstone(k, t) => (k, Op::Tombstone(t.expires_at)),
+            WalRecordRef::Undelete(k) => (k, Op::Undelete),
+            WalRecordRef::Swap(a, b) => return Decoded::Swap(a, b),
+        };
+        Decoded::One(k, op)
+    }
+}
+
+impl<'a> From<&'a WalRecord> for Decoded<'a> {
+    fn from(rec: &'a WalRecord) -> Self {
+        let (k, op) = match rec {
+            WalRecord::Set(k, _) => (k, Op::Write(None)),
+            WalRecord::SetEx(k, _, at) => (k, Op::Write(Some(*at))),
+            WalRecord::SetTagged(k, _, _, at) => (k, Op::Write(*at)),
+            WalRecord::Del(k) | WalRecord::Pop(k) => (k, Op::Remove),
+            WalRecord::SetBit(k, offset, bit) => (k, Op::SetBit(*offset, *bit)),
+            WalRecord::Expire(k, at) => (k, Op::Expire(*at)),
+            WalRecord::Tombstone(k, t) => (k, Op::Tombstone(t.expires_at)),
+            WalRecord::Undelete(k) => (k, Op::Undelete),
+            WalRecord::Swap(a, b) => return Decoded::Swap(a, b),
+        };
+        Decoded::One(k, op)
+    }
+}
+
+/// Первый проход для одной записи ключа `k`: запоминает только место
+/// последней записи, не копируя значения. `pos` — номер сегмента в списке и
+/// смещение записи, `at` — её отметка времени.
+fn note(last: &mut Replayed, k: &str, op: Op, at: Option<u64>, pos: RecordPos) {
+    // ключ уже встречался — обходимся без новой строки
+    let slot = match last.get_mut(k) {
+        Some(slot) => slot,
+        None => last.entry(k.to_owned()).or_default(),
+    };
+    if let Op::Tombstone(_) | Op::Undelete = op {
+        slot.bits.clear();
+        slot.restore = false;
+        // версия возвращённого из надгробия ключа тоже начинается заново
+        slot.removed();
+        match op {
+            Op::Tombstone(t) => {
+                slot.base = Some(None);
+                slot.expires_at = None;
+                slot.tombstone = Some((pos.0, pos.1, t));
+            }
+            // значение — из Tombstone в журнале, а если его там нет, из
+            // надгробия снапшота
+            _ => {
+                slot.written(at);
+                match slot.tombstone.take() {
+                    Some((seg, offset, t)) => {
+                        slot.base = Some(Some((seg, offset)));
+                        slot.expires_at = t;
+                    }
+                    None => {
+                        slot.expires_at = None;
+                        slot.snapshot_tomb_used = true;
+                        slot.restore = true;
+                    }
+                }
+            }
+        }
+        return;
+    }
+    let (base, bit, expires_at) = match op {
+        Op::Write(t) => (Some(Some(pos)), None, t),
+        Op::Remove => (Some(None), None, None),
+        Op::SetBit(offset, bit) => (None, Some((offset, bit)), None),
+        Op::Expire(t) => (None, None, Some(t)),
+        Op::Tombstone(_) | Op::Undelete => unreachable!("handled above"),
+    };
+    if base.is_some() {
+        slot.base = base;
+        slot.bits.clear();
+        slot.expires_at = None;
+        slot.restore = false;
+    }
+    match base {
+        Some(None) => slot.removed(),
+        Some(Some(_)) => slot.written(at),
+        None if bit.is_some() => slot.written(at),
+        None => {}
+    }
+    slot.bits.extend(bit);
+    slot.expires_at = expires_at.or(slot.expires_at);
+}
+
+/// Первый проход по сегменту: разбирает записи после снапшота и отдаёт их
+/// `on_record` вместе с отметкой времени и местом записи.
+fn replay_segment(
+    segments: &[Segment],
+    seg_index: usize,
+    after_seq: u64,
+    key: Option<&WalKey>,
+    progress: &mut Progress<'_>,
+    core: &CacheCore,
+    on_record: &mut OnRecord<'_>,
+) -> Result<ScanEnd, CacheError> {
+    let seg = &segments[seg_index];
+    let frame_len = frame_len(seg) as u64;
+    scan_segment(seg, |raw| {
+        let end = raw.offset + frame_len + raw.data.len() as u64;
+        if raw.seq <= after_seq {
+            // запись уже есть в снапшоте
+            progress.record(None, end, core);
+            return Ok(true);
+        }
+        let offset = raw.offset;
+        let data = open_record(seg, key, raw)?;
+        let (at, body) = split_stamp(&data);
+        let to_err = |e: bincode::Error| {
+            CacheError::Serialization(format!("WAL record at offset {}: {}", offset, e))
+        };
+        if seg.schema != WAL_SCHEMA {
+            // старая схема: без быстрого разбора, записей таких немного
+            let rec = legacy::decode(seg.schema, body).map_err(to_err)?;
+            progress.record(Some(rec.op_index()), end, core);
+            on_record((&rec).into(), at, (seg_index, offset))?;
+            return Ok(true);
+        }
+        let rec: WalRecordRef<'_> = bincode::deserialize(body).map_err(to_err)?;
+        progress.record(Some(rec.op_index()), end, core);
+        on_record(rec.into(), at, (seg_index, offset))?;
+        Ok(true)
+    })
+}
+
+/// Второй проход replay: читает из сегмента записи по смещениям (по
+/// возрастанию) и применяет их к `core`. Второй элемент пары — лечь ли
+/// записи Tombstone надгробием; иначе её значение становится значением ключа.
+fn apply_records(
+    seg: &Segment,
+    offsets: &[(u64, bool)],
+    core: &CacheCore,
+    key: Option<&WalKey>,
+) -> Result<(), CacheError> {
+    let map_io = |e: std::io::Error| CacheError::Internal(format!("read WAL rec: {}", e));
+    let f = File::open(&seg.path).map_err(map_io)?;
+    let mut f = BufReader::with_capacity(SCAN_BUF_SIZE, f);
+    let frame_len = frame_len(seg);
+    let mut pos = 0u64;
+    let mut data = Vec::new();
+    for &(offset, as_tombstone) in offsets {
+        // вперёд внутри буфера — без сброса буфера и лишнего чтения
+        f.seek_relative((offset - pos) as i64).map_err(map_io)?;
+        let mut frame = [0u8; RECORD_FRAME_LEN];
+        f.read_exact(&mut frame[..frame_len]).map_err(map_io)?;
+        let len = u32::from_le_bytes(frame[..4].try_into().expect("4 bytes")) as usize;
+        data.resize(len, 0);
+        f.read_exact(&mut data).map_err(map_io)?;
+        pos = offset + (frame_len + len) as u64;
+        let raw = RawRecord {
+            seq: 0,
+            offset,
+            data: &data,
+        };
+        match decode_record(seg, key, raw)? {
+            // срок SetEx ставит replay_segments после всех записей
+            WalRecord::Set(k, v) | WalRecord::SetEx(k, v, _) => core.set(k, v),
+            WalRecord::SetTagged(k, v, tags, _) => core.set_tagged(k, v, tags, None),
+            WalRecord::Tombstone(k, t) if as_tombstone => core.put_tombstone(k, t),
+            WalRecord::Tombstone(k, t) => core.set_tagged(k, t.value, t.tags, None),
+            other => {
+                return Err(CacheError::Internal(format!(
+                    "WAL record at offset {} in {} changed during replay: expected set, found {}",
+                    offset,
+                    seg.path.display(),
+                    other.op()
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Журнал меньше этого доигрывается в одном потоке, если число потоков не
+/// задано явно: на малом журнале потоки не окупаются.
+pub const PARALLEL_REPLAY_MIN_BYTES: u64 = 64 << 20;
+
+/// Наибольшее число потоков replay по умолчанию.
+pub const MAX_REPLAY_THREADS: usize = 16;
+
+// записей в пачке, которую читатель отдаёт потоку replay
+const REPLAY_BATCH: usize = 4096;
+
+/// Записи для одного потока replay: ключи подряд в одной строке, чтобы не
+/// выделять строку на каждую запись.
+#[derive(Default)]
+struct Batch {
+    keys: String,
+    // (конец ключа в `keys`, запись, отметка времени, место записи)
+    notes: Vec<(usize, Op, Option<u64>, RecordPos)>,
+}
+
+enum Job {
+    Notes(Batch),
+    // применить накопленное к core и ответить
+    Apply(mpsc::Sender<Result<(), CacheError>>),
+}
+
+fn replay_worker_stopped() -> CacheError {
+    CacheError::Internal("WAL replay worker stopped".into())
+}
+
+/// Потоки параллельного replay. Ключ по хешу всегда попадает в один поток,
+/// а каналы сохраняют порядок, так что записи каждого ключа идут в порядке
+/// журнала. Каждый поток ведёт свою часть `Replayed` и сам применяет её к
+/// `core`: второй проход тоже идёт параллельно.
+struct Workers {
+    txs: Vec<SyncSender<Job>>,
+    batches: Vec<Batch>,
+}
+
+impl Workers {
+    fn spawn<'scope, 'env>(
+        scope: &'scope thread::Scope<'scope, 'env>,
+        threads: usize,
+        segments: &'env [Segment],
+        core: &'env CacheCore,
+        key: Option<&'env WalKey>,
+    ) -> Result<Self, CacheError> {
+        let mut txs = Vec::with_capacity(threads);
+        for i in 0..threads {
+            // пара пачек в очереди: читатель не уходит далеко вперёд
+            let (tx, rx) = mpsc::sync_channel(2);
+            thread::Builder::new()
+                .name(format!("tiny-mp-cache-replay-{}", i))
+                .spawn_scoped(scope, move || replay_worker(rx, segments, core, key))
+                .map_err(|e| CacheError::Internal(format!("spawn WAL replay thread: {}", e)))?;
+            txs.push(tx);
+        }
+        Ok(Self {
+            batches: (0..threads).map(|_| Batch::default()).collect(),
+            txs,
+        })
+    }
+
+    fn note(&mut self, k: &str, op: Op, at: Option<u64>, pos: RecordPos) -> Result<(), CacheError> {
+        let mut h = DefaultHasher::new();
+        k.hash(&mut h);
+        let i = (h.finish() % self.txs.len() as u64) as usize;
+        let batch = &mut self.batches[i];
+        batch.keys.push_str(k);
+        batch.notes.push((batch.keys.len(), op, at, pos));
+        if batch.notes.len() >= REPLAY_BATCH {
+            self.send(i)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, i: usize) -> Result<(), CacheError> {
+        let batch = std::mem::take(&mut self.batches[i]);
+        self.txs[i]
+            .send(Job::Notes(batch))
+            .map_err(|_| replay_worker_stopped())
+    }
+
+    /// Ждёт, пока все потоки применят к `core` всё, что им отдано: перед
+    /// Swap и в конце журнала.
+    fn apply(&mut self) -> Result<(), CacheError> {
+        let (tx, rx) = mpsc::channel();
+        for i in 0..self.txs.len() {
+            if !self.batches[i].notes.is_empty() {
+                self.send(i)?;
+            }
+            self.txs[i]
+                .send(Job::Apply(tx.clone()))
+                .map_err(|_| replay_worker_stopped())?;
+        }
+        drop(tx);
+        for _ in 0..self.txs.len() {
+            rx.recv().map_err(|_| replay_worker_stopped())??;
+        }
+        Ok(())
+    }
+}
+
+fn replay_worker(rx: Receiver<Job>, segments: &[Segment], core: &CacheCore, key: Option<&WalKey>) {
+    let mut last = Replayed::new();
+    for job in rx {
+        match job {
+            Job::Notes(batch) => {
+                let mut start = 0;
+                for (end, op, at, pos) in batch.notes {
+                    note(&mut last, &batch.keys[start..end], op, at, pos);
+                    start = end;
                 }
-                return Err(CacheError::Internal(format!("read WAL len: {}", e)));
-            }
-            let len = u32::from_le_bytes(len_buf) as usize;
-            let mut buf = vec![0u8; len];
-            f.read_exact(&mut buf)
-                .map_err(|e| CacheError::Internal(format!("read WAL rec: {}", e)))?;
-            let rec: WalRecord =
-                bincode::deserialize(&buf).map_err(|e| CacheError::Serialization(e.to_string()))?;
-            match rec {
-                WalRecord::Set(k, v) => core.set(k, v),
-                WalRecord::Del(k) => {
-                    core.delete(&k);
+            }
+            Job::Apply(done) => {
+                let _ = done.send(apply_replayed(
+                    segments,
+                    std::mem::take(&mut last),
+                    core,
+                    key,
+                ));
+            }
+        }
+    }
+    // канал закрыт без Apply: читатель бросил replay из-за ошибки
+}
+
+/// Сколько потоков доигрывает журнал из `total` байт, см.
+/// `PersistOptions::replay_threads`.
+fn replay_threads(threads: Option<usize>, total: u64) -> usize {
+    match threads {
+        Some(n) => n.max(1),
+        None if total >= PARALLEL_REPLAY_MIN_BYTES => thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_REPLAY_THREADS),
+        None => 1,
+    }
+}
+
+/// Общая часть `Wal::replay` и `replay_read_only`: доигрывает сегменты в `core`
+/// и возвращает seq последней записи и (длину целой части, оборван ли хвост)
+/// последнего сегмента. Файлы не меняет. О ходе сообщает в `report`; сколько
+/// потоков — см. `replay_threads`.
+fn replay_segments(
+    segments: &[Segment],
+    core: &CacheCore,
+    after_seq: u64,
+    key: Option<&WalKey>,
+    threads: Option<usize>,
+    report: &mut dyn FnMut(&ReplayProgress),
+) -> Result<(u64, Option<(u64, bool)>), CacheError> {
+    if segments[0].base_seq > after_seq {
+        return Err(CacheError::Internal(format!(
+            "WAL starts after seq {} but snapshot covers only up to {}",
+            segments[0].base_seq, after_seq
+        )));
+    }
+
+    // размеры читаемых сегментов; 0 — весь сегмент уже в снапшоте
+    let sizes = segments
+        .iter()
+        .enumerate()
+        .map(|(i, seg)| match segments.get(i + 1) {
+            Some(next) if next.base_seq <= after_seq => Ok(0),
+            _ => fs::metadata(&seg.path)
+                .map(|m| m.len())
+                .map_err(|e| CacheError::Internal(format!("stat WAL segment: {}", e))),
+        })
+        .collect::<Result<Vec<u64>, CacheError>>()?;
+    let total = sizes.iter().sum();
+    let threads = replay_threads(threads, total);
+    let mut progress = Progress::new(total, report);
+
+    // Swap зависит от того, что было у обоих ключей к его моменту, поэтому на
+    // нём накопленное применяется к `core` и обмен делается прямо в памяти
+    let end = if threads == 1 {
+        let mut last = Replayed::new();
+        let end = scan_segments(
+            segments,
+            &sizes,
+            after_seq,
+            key,
+            &mut progress,
+            core,
+            &mut |rec, at, pos| {
+                match rec {
+                    Decoded::One(k, op) => note(&mut last, k, op, at, pos),
+                    Decoded::Swap(a, b) => {
+                        apply_replayed(segments, std::mem::take(&mut last), core, key)?;
+                        core.swap(a, b, |_, _| Ok::<_, CacheError>(()))?;
+                    }
                 }
-                WalRecord::Pop(k) => {
-                    core.pop(&k);
+                Ok(())
+            },
+        )?;
+        apply_replayed(segments, last, core, key)?;
+        end
+    } else {
+        thread::scope(|scope| {
+            let mut workers = Workers::spawn(scope, threads, segments, core, key)?;
+            let end = scan_segments(
+                segments,
+                &sizes,
+                after_seq,
+                key,
+                &mut progress,
+                core,
+                &mut |rec, at, pos| match rec {
+                    Decoded::One(k, op) => workers.note(k, op, at, pos),
+                    Decoded::Swap(a, b) => {
+                        workers.apply()?;
+                        core.swap(a, b, |_, _| Ok::<_, CacheError>(()))?;
+                        Ok(())
+                    }
+                },
+            )?;
+            workers.apply()?;
+            Ok::<_, CacheError>(end)
+        })?
+    };
+    progress.emit(core, true);
+    Ok(end)
+}
+
+/// Первый проход по всем сегментам после снапшота, по порядку; итог — как у
+/// `replay_segments`.
+#[allow(clippy::too_many_arguments)]
+fn scan_segments(
+    segments: &[Segment],
+    sizes: &[u64],
+    after_seq: u64,
+    key: Option<&WalKey>,
+    progress: &mut Progress<'_>,
+    core: &CacheCore,
+    on_record: &mut OnRecord<'_>,
+) -> Result<(u64, Option<(u64, bool)>), CacheError> {
+    let mut seq = segments[0].base_seq;
+    let mut active_len = None;
+    for (i, seg) in segments.iter().enumerate() {
+        let next = segments.get(i + 1);
+        if let Some(next) = next {
+            if next.base_seq <= after_seq {
+                // весь сегмент уже в снапшоте
+                seq = next.base_seq;
+                continue;
+            }
+        }
+        if seg.base_seq != seq {
+            return Err(CacheError::Internal(format!(
+                "WAL segment {} starts after seq {} but previous records end at {}",
+                seg.path.display(),
+                seg.base_seq,
+                seq
+            )));
+        }
+        let ScanEnd {
+            seq: end_seq,
+            good_len,
+            torn,
+        } = replay_segment(segments, i, after_seq, key, progress, core, on_record)?;
+        progress.segment_done(sizes[i]);
+        seq = end_seq;
+        if torn {
+            eprintln!(
+                "WAL segment {} has a torn record at offset {}, ignoring the tail",
+                seg.path.display(),
+                good_len
+            );
+        }
+        if next.is_none() {
+            active_len = Some((good_len, torn));
+        }
+    }
+    Ok((seq, active_len))
+}
+
+/// Второй проход replay по итогам первого: значения из последних записей,
+/// удаления, надгробия, биты и сроки.
+fn apply_replayed(
+    segments: &[Segment],
+    last: Replayed,
+    core: &CacheCore,
+    key: Option<&WalKey>,
+) -> Result<(), CacheError> {
+    let mut sets: Vec<Vec<(u64, bool)>> = vec![Vec::new(); segments.len()];
+    let mut bits = Vec::new();
+    let mut expiries = Vec::new();
+    let mut restores = Vec::new();
+    let mut metas = Vec::new();
+    for (k, state) in last {
+        if state.writes > 0 {
+            // версию до этого прохода читаем, пока значения не применены
+            let before = if state.fresh {
+                0
+            } else {
+                core.write_meta(&k).map_or(0, |(_, version)| version)
+            };
+            metas.push((
+                k.clone(),
+                state.written_at.unwrap_or(0),
+                before + state.writes,
+            ));
+        }
+        if let Some((seg_index, offset, _)) = state.tombstone {
+            sets[seg_index].push((offset, true));
+        } else if state.snapshot_tomb_used && !state.restore {
+            // надгробие снапшота вернули, а значение потом перезаписали
+            core.take_tombstone(&k);
+        }
+        match state.base {
+            Some(Some((seg_index, offset))) => sets[seg_index].push((offset, false)),
+            // ключ мог прийти из снапшота; Pop и Del здесь равнозначны
+            Some(None) => {
+                core.delete(&k);
+            }
+            None => {}
+        }
+        if let Some(at) = state.expires_at {
+            expiries.push((k.clone(), at));
+        }
+        if state.restore {
+            restores.push(k.clone());
+        }
+        if !state.bits.is_empty() {
+            bits.push((k, state.bits));
+        }
+    }
+    for (seg, offsets) in segments.iter().zip(&mut sets) {
+        if offsets.is_empty() {
+            continue;
+        }
+        offsets.sort_unstable();
+        apply_records(seg, offsets, core, key)?;
+    }
+    for k in restores {
+        core.restore(k);
+    }
+    // биты ложатся поверх значения из последнего Set или снапшота
+    for (k, ops) in bits {
+        for (offset, bit) in ops {
+            core.set_bit(k.clone(), offset, bit, |_, _| Ok::<_, CacheError>(()))?;
+        }
+    }
+    // сроки — поверх итогового значения; уже истёкшие ключи убирает вызывающий
+    for (k, at) in expiries {
+        core.set_expiry(k, at, |_| Ok::<_, CacheError>(()))?;
+    }
+    for (k, written_at, version) in metas {
+        core.set_write_meta(&k, written_at, version);
+    }
+    Ok(())
+}
+
+/// Доигрывание для сервера в режиме только чтения: журнал не открывается на
+/// запись, оборванный хвост не обрезается, новые сегменты не создаются, так что
+/// WAL может лежать на носителе только для чтения.
+pub fn replay_read_only(
+    path: &Path,
+    core: &CacheCore,
+    after_seq: u64,
+    key: Option<&WalKey>,
+    threads: Option<usize>,
+    report: &mut dyn FnMut(&ReplayProgress),
+) -> Result<u64, CacheError> {
+    if path
+        .parent()
+        .is_some_and(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+    {
+        // нет каталога — нет и журнала, создавать его не будем
+        return Ok(after_seq);
+    }
+    let segments = discover_segments(path, key)?;
+    if segments.is_empty() {
+        return Ok(after_seq);
+    }
+    let (seq, _) = replay_segments(&segments, core, after_seq, key, threads, report)?;
+    Ok(seq.max(after_seq))
+}
+
+/// Ключи записей с seq больше `after_seq`, для `Verify`: по ним проверка
+/// узнаёт ключи, изменённые во время неё. Запись, которую сервер ещё
+/// дописывает, выглядит как оборванный хвост и в результат не попадает.
+pub(crate) fn keys_after(
+    path: &Path,
+    key: Option<&WalKey>,
+    after_seq: u64,
+) -> Result<HashSet<String>, CacheError> {
+    let segments = discover_segments(path, key)?;
+    let mut keys = HashSet::new();
+    for (i, seg) in segments.iter().enumerate() {
+        if segments
+            .get(i + 1)
+            .is_some_and(|next| next.base_seq <= after_seq)
+        {
+            continue;
+        }
+        scan_segment(seg, |raw| {
+            if raw.seq > after_seq {
+                match decode_record(seg, key, raw)? {
+                    WalRecord::Swap(a, b) => keys.extend([a, b]),
+                    rec => {
+                        keys.insert(rec.into_key());
+                    }
                 }
             }
+            Ok(true)
+        })?;
+    }
+    Ok(keys)
+}
+
+/// Разбирает журнал `path` без сервера: все сегменты по порядку, не больше
+/// `limit` записей. Оборванный хвост просто не попадает в результат.
+pub fn inspect(
+    path: &Path,
+    key: Option<&WalKey>,
+    limit: usize,
+) -> Result<Vec<InspectedRecord>, CacheError> {
+    let segments = discover_segments(path, key)?;
+    if segments.is_empty() {
+        return Err(CacheError::Internal(format!(
+            "no WAL found at {}",
+            path.display()
+        )));
+    }
+    let mut out = Vec::new();
+    for seg in &segments {
+        if out.len() >= limit {
+            break;
+        }
+        scan_segment(seg, |raw| {
+            let (seq, offset) = (raw.seq, raw.offset);
+            let (written_at, rec) = decode_stamped(seg, key, raw)?;
+            out.push(InspectedRecord {
+                seq,
+                written_at,
+                op: rec.op(),
+                value_size: match &rec {
+                    WalRecord::Set(_, v)
+                    | WalRecord::SetEx(_, v, _)
+                    | WalRecord::SetTagged(_, v, ..) => Some(v.len()),
+                    WalRecord::Tombstone(_, t) => Some(t.value.len()),
+                    _ => None,
+                },
+                other_key: match &rec {
+                    WalRecord::Swap(_, b) => Some(b.clone()),
+                    _ => None,
+                },
+                key: rec.into_key(),
+                offset,
+                file: seg.path.clone(),
+            });
+            Ok(out.len() < limit)
+        })?;
+    }
+    Ok(out)
+}
+
+/// Итог `repair`.
+#[derive(Clone, Debug, Default)]
+pub struct RepairReport {
+    pub kept: u64,
+    // по разрывам seq там, где они известны; иначе по одной записи на
+    // повреждённый участок, т.е. оценка снизу
+    pub dropped: u64,
+    // пропущенные диапазоны байт входного файла, [start, end)
+    pub skipped: Vec<(u64, u64)>,
+}
+
+// записи длиннее не бывают: команда ограничена мегабайтом
+const MAX_RECORD_LEN: usize = 16 << 20;
+
+/// Восстанавливает повреждённый сегмент `input` в новый файл `output`.
+/// Запись считается целой, если её длина правдоподобна, seq продолжает
+/// предыдущие, а данные разбираются в `WalRecord` ровно по длине (для
+/// зашифрованного сегмента ещё и проходят проверку тега). После порчи чтение
+/// смещается на байт вперёд, пока не найдётся следующая целая запись.
+/// Уцелевшие записи перенумеровываются подряд от base_seq входа, чтобы
+/// результат проходил проверку seq при replay. `input` не меняется, `output`
+/// записывается через fsync + rename.
+pub fn repair(
+    input: &Path,
+    output: &Path,
+    key: Option<&WalKey>,
+) -> Result<RepairReport, CacheError> {
+    let map_io = |e: std::io::Error| CacheError::Internal(format!("repair WAL: {}", e));
+    if output.exists()
+        && fs::canonicalize(input).map_err(map_io)? == fs::canonicalize(output).map_err(map_io)?
+    {
+        return Err(CacheError::Internal(
+            "repair output must not be the input file".into(),
+        ));
+    }
+    let data = fs::read(input).map_err(map_io)?;
+    let header = read_header(&mut std::io::Cursor::new(&data[..]), input, key)?;
+    let frame_len = if header.explicit_seq {
+        RECORD_FRAME_LEN
+    } else {
+        4
+    };
+
+    let mut report = RepairReport::default();
+    let mut out = Vec::with_capacity(data.len());
+    let mut out_seq = header.base_seq;
+    let mut prev_seq = header.base_seq;
+    let mut pos = header.len as usize;
+    let mut skip_start: Option<usize> = None;
+    while pos < data.len() {
+        // после пропуска seq может перескочить, но не больше, чем записей
+        // влезло бы в пропущенные байты
+        let max_gap = skip_start.map_or(0, |start| (pos - start) as u64);
+        let Some((seq, payload, _)) =
+            parse_record(&data[pos..], frame_len, &header, key, prev_seq, max_gap)
+        else {
+            skip_start.get_or_insert(pos);
+            pos += 1;
+            continue;
+        };
+        if let Some(start) = skip_start.take() {
+            report.skipped.push((start as u64, pos as u64));
+            report.dropped += match seq {
+                Some(seq) => seq - prev_seq - 1,
+                None => 1,
+            };
+        }
+        if let Some(seq) = seq {
+            prev_seq = seq;
+        }
+        out_seq += 1;
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&out_seq.to_le_bytes());
+        out.extend_from_slice(payload);
+        report.kept += 1;
+        pos += frame_len + payload.len();
+    }
+    if let Some(start) = skip_start {
+        report.skipped.push((start as u64, data.len() as u64));
+        report.dropped += 1;
+    }
+
+    replace_file(output, |f| {
+        // записи остаются в схеме входа
+        write_header(f, header.base_seq, header.flags, header.schema, key)?;
+        f.write_all(&out)
+    })
+    .map_err(map_io)?;
+    Ok(report)
+}
+
+/// Пробует прочитать целую запись в начале `buf`. Возвращает seq из записи
+/// (`None` для старого формата), данные записи и номер её вида в
+/// `RECORD_OPS`.
+fn parse_record<'a>(
+    buf: &'a [u8],
+    frame_len: usize,
+    header: &Header,
+    key: Option<&WalKey>,
+    prev_seq: u64,
+    max_gap: u64,
+) -> Option<(Option<u64>, &'a [u8], usize)> {
+    let len = u32::from_le_bytes(buf.get(..4)?.try_into().ok()?) as usize;
+    if len == 0 || len > MAX_RECORD_LEN {
+        return None;
+    }
+    let payload = buf.get(frame_len..frame_len + len)?;
+    let seq = if header.explicit_seq {
+        let seq = u64::from_le_bytes(buf[4..RECORD_FRAME_LEN].try_into().ok()?);
+        if seq <= prev_seq || seq - prev_seq - 1 > max_gap {
+            return None;
+        }
+        Some(seq)
+    } else {
+        None
+    };
+    let plain = if header.encrypted {
+        std::borrow::Cow::Owned(key?.open(payload)?)
+    } else {
+        std::borrow::Cow::Borrowed(payload)
+    };
+    let body = split_stamp(&plain).1;
+    if header.schema != WAL_SCHEMA {
+        if !legacy::is_exact(header.schema, body) {
+            return None;
+        }
+        let op = legacy::decode(header.schema, body).ok()?.op_index();
+        return Some((seq, payload, op));
+    }
+    // данные разбираются ровно по длине, без копий ключа и значения
+    use bincode::Options;
+    let rec: WalRecordRef<'_> = bincode::options()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(body)
+        .ok()?;
+    Some((seq, payload, rec.op_index()))
+}
+
+/// Как read_exact, но на EOF возвращает, сколько успели прочитать.
+fn read_full(r: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+fn segment_path(path: &Path, num: u64) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{:06}", num));
+    PathBuf::from(name)
+}
+
+/// Файл блокировки журнала `path`: `<name>.lock` рядом с `<name>.wal`. Пока
+/// журнал открыт на запись, файл под исключительной блокировкой ОС, так что
+/// два сервера с одним журналом не пишут в него вперемешку: второй сразу
+/// получает ошибку. Блокировка снимается с закрытием журнала или смертью
+/// процесса, а сам файл остаётся.
+pub fn lock_path(path: &Path) -> PathBuf {
+    path.with_extension("lock")
+}
+
+fn lock_journal(path: &Path) -> Result<File, CacheError> {
+    let lock = lock_path(path);
+    let f = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock)
+        .map_err(|e| {
+            CacheError::Internal(format!("open WAL lock file {}: {}", lock.display(), e))
+        })?;
+    match f.try_lock() {
+        Ok(()) => Ok(f),
+        Err(fs::TryLockError::WouldBlock) => Err(CacheError::Busy(format!(
+            "WAL {} is already open by another server (lock file {})",
+            path.display(),
+            lock.display()
+        ))),
+        Err(fs::TryLockError::Error(e)) => Err(CacheError::Internal(format!(
+            "lock WAL {}: {}",
+            lock.display(),
+            e
+        ))),
+    }
+}
+
+/// Находит сегменты журнала `path` (включая файл старого формата) по порядку.
+fn discover_segments(path: &Path, key: Option<&WalKey>) -> Result<Vec<Segment>, CacheError> {
+    let map_io = |e: std::io::Error| CacheError::Internal(format!("list WAL segments: {}", e));
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let prefix = format!(
+        "{}.",
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| CacheError::Internal("WAL path has no file name".into()))?
+    );
+
+    let mut nums = Vec::new();
+    if path.exists() {
+        nums.push(0);
+    }
+    for entry in fs::read_dir(&dir).map_err(map_io)? {
+        let name = entry.map_err(map_io)?.file_name();
+        let Some(suffix) = name.to_str().and_then(|n| n.strip_prefix(&prefix)) else {
+            continue;
+        };
+        // `<name>.000001.tmp` и прочие посторонние файлы пропускаем
+        if suffix.len() >= 6 && suffix.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(num) = suffix.parse::<u64>() {
+                nums.push(num);
+            }
         }
+    }
+    nums.sort_unstable();
+
+    let mut segments = Vec::with_capacity(nums.len());
+    for num in nums {
+        let seg_path = if num == 0 {
+            path.to_path_buf()
+        } else {
+            segment_path(path, num)
+        };
+        let mut f = File::open(&seg_path).map_err(map_io)?;
+        let header = read_header(&mut f, &seg_path, key)?;
+        segments.push(Segment {
+            num,
+            path: seg_path,
+            base_seq: header.base_seq,
+            header_len: header.len,
+            encrypted: header.encrypted,
+            explicit_seq: header.explicit_seq,
+            flags: header.flags,
+            schema: header.schema,
+        });
+    }
+    Ok(segments)
+}
+
+/// Флаги журнала по его последнему сегменту; 0, если журнала нет.
+pub(crate) fn journal_flags(path: &Path, key: Option<&WalKey>) -> Result<u32, CacheError> {
+    if path
+        .parent()
+        .is_some_and(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+    {
+        return Ok(0);
+    }
+    Ok(discover_segments(path, key)?.last().map_or(0, |s| s.flags))
+}
+
+/// Создаёт пустой сегмент атомарно: заголовок пишется во временный файл,
+/// который после fsync переименовывается в `<name>.<num>`.
+fn create_segment(
+    path: &Path,
+    num: u64,
+    base_seq: u64,
+    flags: u32,
+    key: Option<&WalKey>,
+) -> Result<Segment, CacheError> {
+    let seg_path = segment_path(path, num);
+    let mut header_len = 0;
+    replace_file(&seg_path, |f| {
+        header_len = write_header(f, base_seq, flags, WAL_SCHEMA, key)?;
         Ok(())
+    })
+    .map_err(|e| CacheError::Internal(format!("create WAL segment: {}", e)))?;
+    Ok(Segment {
+        num,
+        path: seg_path,
+        base_seq,
+        header_len,
+        encrypted: key.is_some(),
+        explicit_seq: true,
+        flags,
+        schema: WAL_SCHEMA,
+    })
+}
+
+/// Пишет заголовок сегмента, возвращает его длину.
+fn write_header(
+    w: &mut impl Write,
+    base_seq: u64,
+    flags: u32,
+    schema: u32,
+    key: Option<&WalKey>,
+) -> std::io::Result<u64> {
+    w.write_all(WAL_MAGIC)?;
+    let check = match key {
+        Some(key) => Some(
+            key.check_value()
+                .map_err(|e| std::io::Error::other(e.to_string()))?,
+        ),
+        None => None,
+    };
+    let version = if check.is_some() {
+        WAL_VERSION_ENCRYPTED
+    } else {
+        WAL_VERSION
+    };
+    // без флагов заголовок тот же, что до их появления
+    w.write_all(&(version | flags << WAL_FLAGS_SHIFT).to_le_bytes())?;
+    w.write_all(&base_seq.to_le_bytes())?;
+    w.write_all(&schema.to_le_bytes())?;
+    if let Some(check) = &check {
+        w.write_all(check)?;
+    }
+    w.flush()?;
+    Ok(WAL_HEADER_LEN + check.map_or(0, |c| c.len() as u64))
+}
+
+struct Header {
+    base_seq: u64,
+    // 0 для старого формата без заголовка
+    len: u64,
+    encrypted: bool,
+    explicit_seq: bool,
+    flags: u32,
+    schema: u32,
+}
+
+/// Читает заголовок сегмента; для зашифрованного сегмента сверяет ключ.
+/// Сегмент со схемой записей новее `WAL_SCHEMA` не читается.
+fn read_header<R: Read + Seek>(
+    r: &mut R,
+    path: &Path,
+    key: Option<&WalKey>,
+) -> Result<Header, CacheError> {
+    let map_io = |e: std::io::Error| CacheError::Internal(format!("read WAL header: {}", e));
+    let mut magic = [0u8; 4];
+    let has_magic = match r.read_exact(&mut magic) {
+        Ok(()) => &magic == WAL_MAGIC,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(map_io(e)),
+    };
+    if !has_magic {
+        // старый журнал без заголовка
+        r.seek(SeekFrom::Start(0)).map_err(map_io)?;
+        return Ok(Header {
+            base_seq: 0,
+            len: 0,
+            encrypted: false,
+            explicit_seq: false,
+            flags: 0,
+            schema: 0,
+        });
     }
+    let mut version = [0u8; 4];
+    r.read_exact(&mut version).map_err(map_io)?;
+    let version = u32::from_le_bytes(version);
+    let flags = version >> WAL_FLAGS_SHIFT;
+    if flags & !WAL_FLAG_FOLD_CASE != 0 {
+        return Err(CacheError::Internal(format!(
+            "unsupported WAL flags {:#x} in {}",
+            flags,
+            path.display()
+        )));
+    }
+    let version = version & ((1 << WAL_FLAGS_SHIFT) - 1);
+    let (encrypted, explicit_seq, has_schema) = match version {
+        WAL_VERSION_LEGACY => (false, false, false),
+        WAL_VERSION_LEGACY_ENCRYPTED => (true, false, false),
+        WAL_VERSION_UNVERSIONED => (false, true, false),
+        WAL_VERSION_UNVERSIONED_ENCRYPTED => (true, true, false),
+        WAL_VERSION => (false, true, true),
+        WAL_VERSION_ENCRYPTED => (true, true, true),
+        _ => {
+            return Err(CacheError::Internal(format!(
+                "unsupported WAL version {}",
+                version
+            )))
+        }
+    };
+    let mut base = [0u8; 8];
+    r.read_exact(&mut base).map_err(map_io)?;
+    let mut header = Header {
+        base_seq: u64::from_le_bytes(base),
+        len: WAL_HEADER_LEN_UNVERSIONED,
+        encrypted,
+        explicit_seq,
+        flags,
+        schema: 0,
+    };
+    if has_schema {
+        let mut schema = [0u8; 4];
+        r.read_exact(&mut schema).map_err(map_io)?;
+        header.schema = u32::from_le_bytes(schema);
+        header.len = WAL_HEADER_LEN;
+        if header.schema > WAL_SCHEMA {
+            return Err(CacheError::Unsupported(format!(
+                "WAL segment {} uses record schema {}, this build reads schemas up to {}; \
+                 open it with a newer tiny-mp-cache",
+                path.display(),
+                header.schema,
+                WAL_SCHEMA
+            )));
+        }
+    }
+    if encrypted {
+        let mut check = [0u8; KEY_CHECK_LEN];
+        r.read_exact(&mut check).map_err(map_io)?;
+        crypto::verify_key(key, &check, path)?;
+        header.len += KEY_CHECK_LEN as u64;
+    }
+    Ok(header)
+}
+
+/// Пишет файл через `<path>.tmp` в том же каталоге: fsync файла, rename
+/// поверх `path`, fsync каталога. На диске всегда лежит либо старая, либо
+/// полностью записанная новая версия; если процесс умер посреди записи,
+/// остаётся только `.tmp`, который следующая запись перезапишет. Так пишутся
+/// снапшоты, сегменты WAL, `repair_wal` и файлы `export`.
+pub fn replace_file(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut f = File::create(&tmp_path)?;
+    let res = write(&mut f).and_then(|()| f.sync_all());
+    drop(f);
+    if let Err(e) = res.and_then(|()| fs::rename(&tmp_path, path)) {
+        // недописанный файл никому не нужен
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    sync_dir(path)
+}
+
+/// fsync директории, чтобы создание/переименование файла пережило сбой питания.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
 }
@@ -0,0 +1,87 @@
+//! Записи журнала прежних схем (см. `WAL_SCHEMA`). Определения здесь
+//! заморожены: `WalRecord` меняется, а сегменты, записанные до изменения,
+//! читаются этими типами и переводятся в нынешние записи. Схема, которой
+//! здесь нет, — новее сборки; такой сегмент отклоняет ещё `read_header`.
+
+use super::WalRecord;
+use crate::core::Tombstone;
+use serde::{Deserialize, Serialize};
+
+/// Схема 0: сегменты версий 1-4, до номера схемы в заголовке. Варианты только
+/// дописывались в конец, так что старые сегменты любой из этих версий
+/// читаются этим определением.
+#[derive(Serialize, Deserialize)]
+enum RecordV0 {
+    Set(String, Vec<u8>),
+    Del(String),
+    Pop(String),
+    SetBit(String, u64, bool),
+    SetEx(String, Vec<u8>, u64),
+    Expire(String, u64),
+    SetTagged(String, Vec<u8>, Vec<String>, Option<u64>),
+    Tombstone(String, TombstoneV0),
+    Undelete(String),
+    Swap(String, String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct TombstoneV0 {
+    value: Vec<u8>,
+    expires_at: Option<u64>,
+    tags: Vec<String>,
+    purge_at: u64,
+}
+
+impl From<RecordV0> for WalRecord {
+    fn from(rec: RecordV0) -> Self {
+        match rec {
+            RecordV0::Set(k, v) => WalRecord::Set(k, v),
+            RecordV0::Del(k) => WalRecord::Del(k),
+            RecordV0::Pop(k) => WalRecord::Pop(k),
+            RecordV0::SetBit(k, offset, bit) => WalRecord::SetBit(k, offset, bit),
+            RecordV0::SetEx(k, v, at) => WalRecord::SetEx(k, v, at),
+            RecordV0::Expire(k, at) => WalRecord::Expire(k, at),
+            RecordV0::SetTagged(k, v, tags, at) => WalRecord::SetTagged(k, v, tags, at),
+            RecordV0::Tombstone(k, t) => WalRecord::Tombstone(
+                k,
+                Tombstone {
+                    value: t.value,
+                    expires_at: t.expires_at,
+                    tags: t.tags,
+                    purge_at: t.purge_at,
+                },
+            ),
+            RecordV0::Undelete(k) => WalRecord::Undelete(k),
+            RecordV0::Swap(a, b) => WalRecord::Swap(a, b),
+        }
+    }
+}
+
+fn unknown(schema: u32) -> bincode::Error {
+    Box::new(bincode::ErrorKind::Custom(format!(
+        "unknown WAL record schema {}",
+        schema
+    )))
+}
+
+/// bincode записи старой схемы `schema` в виде нынешней записи.
+pub(super) fn decode(schema: u32, body: &[u8]) -> bincode::Result<WalRecord> {
+    match schema {
+        0 => bincode::deserialize::<RecordV0>(body).map(WalRecord::from),
+        _ => Err(unknown(schema)),
+    }
+}
+
+/// `body` — ровно одна запись схемы `schema`, без лишних байт в конце; для
+/// `repair`.
+pub(super) fn is_exact(schema: u32, body: &[u8]) -> bool {
+    match schema {
+        0 => {
+            bincode::deserialize::<RecordV0>(body)
+                .ok()
+                .and_then(|rec| bincode::serialized_size(&rec).ok())
+                == Some(body.len() as u64)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,330 @@
+//! Проверка журнала на диске без сервера и без `CacheCore`: все сегменты
+//! читаются по порядку окном не больше записи с кадром и куска чтения, так
+//! что память не растёт с размером журнала. Запись проверяется так же, как
+//! её ищет `repair`: правдоподобная длина, seq продолжает предыдущие, данные
+//! расшифровываются и разбираются ровно по длине. Значения при этом не
+//! копируются.
+
+use super::{
+    discover_segments, frame_len, parse_record, read_full, Header, Segment, MAX_RECORD_LEN,
+    RECORD_OPS, REPLAY_REPORT_INTERVAL, SCAN_BUF_SIZE,
+};
+use crate::crypto::WalKey;
+use crate::error::CacheError;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Итог `verify` одним словом.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyStatus {
+    Clean,
+    // только оборванные хвосты: сервер отбросит их при старте, как после
+    // падения посреди записи
+    Recoverable,
+    // сервер с этим журналом не поднимется; спасти уцелевшее — `repair`
+    Corrupt,
+}
+
+impl VerifyStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VerifyStatus::Clean => "clean",
+            VerifyStatus::Recoverable => "recoverable",
+            VerifyStatus::Corrupt => "corrupt",
+        }
+    }
+}
+
+/// Испорченный участок сегмента, `[start, end)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorruptRegion {
+    pub file: PathBuf,
+    pub start: u64,
+    pub end: u64,
+    // по разрыву seq вокруг участка; None — участок до конца файла или
+    // журнал старого формата без seq
+    pub records_lost: Option<u64>,
+}
+
+/// Сегмент начинается не с того seq, на котором кончился предыдущий.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeqBreak {
+    pub file: PathBuf,
+    pub expected: u64,
+    pub found: u64,
+}
+
+/// Итог `verify`.
+#[derive(Clone, Debug, Default)]
+pub struct VerifyReport {
+    pub segments: u64,
+    // размер всех сегментов вместе с заголовками
+    pub bytes: u64,
+    // целые записи
+    pub records: u64,
+    // (вид записи, сколько их), только встреченные виды
+    pub ops: Vec<(&'static str, u64)>,
+    // 0, если целых записей нет
+    pub first_seq: u64,
+    pub last_seq: u64,
+    // (файл, смещение) оборванной последней записи
+    pub torn: Vec<(PathBuf, u64)>,
+    pub corrupt: Vec<CorruptRegion>,
+    pub seq_breaks: Vec<SeqBreak>,
+}
+
+impl VerifyReport {
+    pub fn status(&self) -> VerifyStatus {
+        if !self.corrupt.is_empty() || !self.seq_breaks.is_empty() {
+            VerifyStatus::Corrupt
+        } else if !self.torn.is_empty() {
+            VerifyStatus::Recoverable
+        } else {
+            VerifyStatus::Clean
+        }
+    }
+}
+
+/// Ход `verify`: не чаще раза в `REPLAY_REPORT_INTERVAL` и ещё раз в конце,
+/// с `done`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyProgress {
+    pub records: u64,
+    pub bytes_read: u64,
+    pub bytes_total: u64,
+    pub elapsed: Duration,
+    pub done: bool,
+}
+
+/// Окно чтения сегмента: в памяти не больше записи с кадром и куска чтения.
+struct Window {
+    f: File,
+    buf: Vec<u8>,
+    // начало непрочитанного в `buf`
+    start: usize,
+    // смещение `buf[start]` в файле
+    pos: u64,
+    eof: bool,
+}
+
+impl Window {
+    fn open(path: &Path, at: u64) -> std::io::Result<Self> {
+        let mut f = File::open(path)?;
+        f.seek(SeekFrom::Start(at))?;
+        Ok(Self {
+            f,
+            buf: Vec::new(),
+            start: 0,
+            pos: at,
+            eof: false,
+        })
+    }
+
+    /// Не меньше `n` байт от `pos`, если файл не кончится раньше.
+    fn fill(&mut self, n: usize) -> std::io::Result<&[u8]> {
+        while self.buf.len() - self.start < n && !self.eof {
+            self.buf.drain(..self.start);
+            self.start = 0;
+            let have = self.buf.len();
+            let want = (n - have).max(SCAN_BUF_SIZE);
+            self.buf.resize(have + want, 0);
+            let got = read_full(&mut self.f, &mut self.buf[have..])?;
+            self.buf.truncate(have + got);
+            self.eof = got < want;
+        }
+        Ok(&self.buf[self.start..])
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.start += n;
+        self.pos += n as u64;
+    }
+}
+
+struct Progress<'a> {
+    report: &'a mut dyn FnMut(&VerifyProgress),
+    started: Instant,
+    reported: Instant,
+    records: u64,
+    // байты сегментов до текущего
+    passed: u64,
+    total: u64,
+    calls: u64,
+}
+
+impl Progress<'_> {
+    fn at(&mut self, read: u64) {
+        self.calls += 1;
+        // на часы смотрим не на каждой записи
+        if self.calls.is_multiple_of(1024) && self.reported.elapsed() >= REPLAY_REPORT_INTERVAL {
+            self.emit(self.passed + read, false);
+        }
+    }
+
+    fn emit(&mut self, read: u64, done: bool) {
+        (self.report)(&VerifyProgress {
+            records: self.records,
+            bytes_read: read,
+            bytes_total: self.total,
+            elapsed: self.started.elapsed(),
+            done,
+        });
+        self.reported = Instant::now();
+    }
+}
+
+/// Проверяет журнал `path` (все сегменты или отдельный файл сегмента), ничего
+/// не меняя. Неверный ключ или нечитаемый заголовок — ошибка, а не отчёт:
+/// записи тогда проверить нечем.
+pub fn verify(
+    path: &Path,
+    key: Option<&WalKey>,
+    report: &mut dyn FnMut(&VerifyProgress),
+) -> Result<VerifyReport, CacheError> {
+    let segments = discover_segments(path, key)?;
+    if segments.is_empty() {
+        return Err(CacheError::Internal(format!(
+            "no WAL found at {}",
+            path.display()
+        )));
+    }
+    let sizes = segments
+        .iter()
+        .map(|seg| {
+            std::fs::metadata(&seg.path)
+                .map(|m| m.len())
+                .map_err(|e| CacheError::Internal(format!("stat WAL segment: {}", e)))
+        })
+        .collect::<Result<Vec<u64>, CacheError>>()?;
+    let now = Instant::now();
+    let mut progress = Progress {
+        report,
+        started: now,
+        reported: now,
+        records: 0,
+        passed: 0,
+        total: sizes.iter().sum(),
+        calls: 0,
+    };
+    let mut out = VerifyReport {
+        segments: segments.len() as u64,
+        bytes: progress.total,
+        ..VerifyReport::default()
+    };
+    let mut counts = [0u64; RECORD_OPS.len()];
+    let mut end_seq = None;
+    for (seg, size) in segments.iter().zip(sizes) {
+        if let Some(expected) = end_seq.filter(|&seq| seq != seg.base_seq) {
+            out.seq_breaks.push(SeqBreak {
+                file: seg.path.clone(),
+                expected,
+                found: seg.base_seq,
+            });
+        }
+        end_seq = Some(verify_segment(
+            seg,
+            key,
+            &mut out,
+            &mut counts,
+            &mut progress,
+        )?);
+        progress.passed += size;
+    }
+    out.ops = RECORD_OPS
+        .iter()
+        .zip(counts)
+        .filter(|&(_, n)| n > 0)
+        .map(|(op, n)| (*op, n))
+        .collect();
+    progress.emit(progress.total, true);
+    Ok(out)
+}
+
+/// Проверяет один сегмент, возвращает seq его последней целой записи.
+fn verify_segment(
+    seg: &Segment,
+    key: Option<&WalKey>,
+    out: &mut VerifyReport,
+    counts: &mut [u64; RECORD_OPS.len()],
+    progress: &mut Progress<'_>,
+) -> Result<u64, CacheError> {
+    let map_io =
+        |e: std::io::Error| CacheError::Internal(format!("read {}: {}", seg.path.display(), e));
+    let header = Header {
+        base_seq: seg.base_seq,
+        len: seg.header_len,
+        encrypted: seg.encrypted,
+        explicit_seq: seg.explicit_seq,
+        flags: seg.flags,
+        schema: seg.schema,
+    };
+    let frame = frame_len(seg);
+    let mut win = Window::open(&seg.path, seg.header_len).map_err(map_io)?;
+    let mut prev_seq = seg.base_seq;
+    // начало участка, где целой записи не нашлось
+    let mut skip_start: Option<u64> = None;
+    loop {
+        let pos = win.pos;
+        let buf = win.fill(frame).map_err(map_io)?;
+        if buf.is_empty() {
+            break;
+        }
+        let len = buf.get(..4).map_or(0, |b| {
+            u32::from_le_bytes(b.try_into().expect("4 bytes")) as usize
+        });
+        let need = match len {
+            1..=MAX_RECORD_LEN => frame + len,
+            _ => frame,
+        };
+        let buf = win.fill(need).map_err(map_io)?;
+        if buf.len() < need && skip_start.is_none() {
+            // файл кончился посреди записи: так выглядит падение во время записи
+            out.torn.push((seg.path.clone(), pos));
+            break;
+        }
+        // после пропуска seq может перескочить, но не больше, чем записей
+        // влезло бы в пропущенные байты
+        let max_gap = skip_start.map_or(0, |start| pos - start);
+        match parse_record(buf, frame, &header, key, prev_seq, max_gap) {
+            Some((seq, _, op)) => {
+                let seq = seq.unwrap_or(prev_seq + 1);
+                if let Some(start) = skip_start.take() {
+                    out.corrupt.push(CorruptRegion {
+                        file: seg.path.clone(),
+                        start,
+                        end: pos,
+                        records_lost: seg.explicit_seq.then(|| seq - prev_seq - 1),
+                    });
+                }
+                counts[op] += 1;
+                out.records += 1;
+                if out.first_seq == 0 {
+                    out.first_seq = seq;
+                }
+                out.last_seq = seq;
+                prev_seq = seq;
+                progress.records += 1;
+                win.advance(need);
+                progress.at(win.pos);
+            }
+            None => {
+                skip_start.get_or_insert(pos);
+                win.advance(1);
+                if win.pos.is_multiple_of(64 << 10) {
+                    progress.at(win.pos);
+                }
+            }
+        }
+    }
+    if let Some(start) = skip_start {
+        out.corrupt.push(CorruptRegion {
+            file: seg.path.clone(),
+            start,
+            end: win.pos,
+            records_lost: None,
+        });
+    }
+    Ok(prev_seq)
+}
@@ -0,0 +1,195 @@
+use crate::encoding::decode_value;
+use crate::error::CacheError;
+use crate::feed::WriteFeed;
+use crate::pubsub::HEARTBEAT_INTERVAL;
+use crate::{write_all, write_frame, CacheResponse, ResponseValue};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, RwLock};
+
+/// Уведомления об изменениях ключей: соединение с `Watch(prefix, with_values)`
+/// получает кадр `CacheResponse::Event` на каждую мутацию ключа с этим префиксом.
+/// События рассылаются после успешной записи в WAL. Наблюдатель, который не
+/// успевает разбирать свою очередь, отключается: путь записи его не ждёт.
+const WATCHER_QUEUE: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WatchOp {
+    Set,
+    Del,
+    // срок ключа прошёл, см. PersistentCore::expire_due
+    Expired,
+}
+
+impl WatchOp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WatchOp::Set => "set",
+            WatchOp::Del => "del",
+            WatchOp::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WatchEvent {
+    pub key: String,
+    pub op: WatchOp,
+    pub value_size: u64,
+    // только для наблюдателей с with_values и только для Set
+    pub value: Option<Vec<u8>>,
+}
+
+// готовый кадр `CacheResponse::Event`
+type Frame = Arc<Vec<u8>>;
+
+struct Watcher {
+    id: u64,
+    prefix: String,
+    with_values: bool,
+    tx: SyncSender<Frame>,
+}
+
+#[derive(Default)]
+pub struct Watchers {
+    list: RwLock<Vec<Watcher>>,
+    // len(list) без блокировки: без наблюдателей запись ничего не платит
+    count: AtomicUsize,
+    next_id: AtomicU64,
+    // отключено за медленность
+    evicted: AtomicU64,
+    // поток записей внутри процесса, см. PersistentCore::set_write_feed
+    pub(crate) feed: Option<Arc<WriteFeed>>,
+}
+
+fn encode(event: WatchEvent) -> Option<Frame> {
+    bincode::serialize(&CacheResponse::Event(event))
+        .ok()
+        .map(Arc::new)
+}
+
+impl Watchers {
+    pub fn active(&self) -> bool {
+        self.feed.is_some() || self.count.load(Ordering::Acquire) > 0
+    }
+
+    /// Рассылает событие по ключу; вызывается после успешной записи в WAL.
+    pub fn notify(&self, key: &str, op: WatchOp, value: Option<&[u8]>) {
+        if let Some(feed) = &self.feed {
+            feed.push(key, op, value);
+        }
+        if self.count.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let value_size = value.map_or(0, |v| v.len() as u64);
+        // кадр с значением и без кодируются не больше одного раза
+        let (mut plain, mut full) = (None, None);
+        let mut slow = Vec::new();
+        {
+            let list = self.list.read().unwrap_or_else(|e| e.into_inner());
+            for w in list.iter().filter(|w| key.starts_with(&w.prefix)) {
+                let slot = if w.with_values { &mut full } else { &mut plain };
+                if slot.is_none() {
+                    *slot = encode(WatchEvent {
+                        key: key.to_string(),
+                        op,
+                        value_size,
+                        // наблюдатели говорят на версии 1: сжатое отдаётся
+                        // распакованным, а битое — без значения
+                        value: value
+                            .filter(|_| w.with_values)
+                            .and_then(|v| decode_value(v.to_vec()).ok()),
+                    });
+                }
+                let Some(frame) = slot.clone() else {
+                    continue;
+                };
+                if let Err(TrySendError::Full(_)) = w.tx.try_send(frame) {
+                    slow.push(w.id);
+                }
+            }
+        }
+        if !slow.is_empty() {
+            // без отправителя поток наблюдателя дочитает очередь и закроет соединение
+            // соседний писатель мог отключить того же наблюдателя раньше
+            let removed = self.remove(|w| slow.contains(&w.id));
+            self.evicted.fetch_add(removed as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Возвращает, сколько наблюдателей убрано.
+    fn remove(&self, pred: impl Fn(&Watcher) -> bool) -> usize {
+        let mut list = self.list.write().unwrap_or_else(|e| e.into_inner());
+        let before = list.len();
+        list.retain(|w| !pred(w));
+        self.count.store(list.len(), Ordering::Release);
+        before - list.len()
+    }
+
+    fn register(&self, prefix: String, with_values: bool) -> (u64, Receiver<Frame>) {
+        let (tx, rx) = mpsc::sync_channel(WATCHER_QUEUE);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut list = self.list.write().unwrap_or_else(|e| e.into_inner());
+        list.push(Watcher {
+            id,
+            prefix,
+            with_values,
+            tx,
+        });
+        self.count.store(list.len(), Ordering::Release);
+        (id, rx)
+    }
+
+    pub fn info_fields(&self) -> Vec<(&'static str, ResponseValue)> {
+        let mut fields = vec![
+            ("watchers", self.count.load(Ordering::Acquire).into()),
+            (
+                "watchers_evicted",
+                self.evicted.load(Ordering::Relaxed).into(),
+            ),
+        ];
+        if let Some(feed) = &self.feed {
+            fields.extend(feed.info_fields());
+        }
+        fields
+    }
+}
+
+/// Сторона сервера: держит соединение наблюдателя до обрыва или отключения.
+pub fn serve_watcher<S: Write>(
+    stream: &mut S,
+    watchers: &Watchers,
+    prefix: String,
+    with_values: bool,
+) -> Result<(), CacheError> {
+    let (id, rx) = watchers.register(prefix, with_values);
+    let res = stream_events(stream, &rx);
+    watchers.remove(|w| w.id == id);
+    match res {
+        // наблюдатель закрыл соединение — обычное завершение
+        Err(CacheError::Network(_)) => Ok(()),
+        other => other,
+    }
+}
+
+fn stream_events<S: Write>(stream: &mut S, rx: &Receiver<Frame>) -> Result<(), CacheError> {
+    // подтверждение: после него события уже доходят
+    write_frame(stream, &CacheResponse::Ok)?;
+    loop {
+        match rx.recv_timeout(HEARTBEAT_INTERVAL) {
+            Ok(frame) => {
+                write_all(stream, &(frame.len() as u32).to_le_bytes())?;
+                write_all(stream, &frame)?;
+            }
+            Err(RecvTimeoutError::Timeout) => write_frame(stream, &CacheResponse::Ok)?,
+            Err(RecvTimeoutError::Disconnected) => {
+                return write_frame(
+                    stream,
+                    &CacheResponse::Error("watcher fell behind and was disconnected".into()),
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+#!/usr/bin/env python3
+import os
+import tempfile
+import time
+from tiny_mp_cache import spawn_server, hash_password, TinyCache, TinyCacheError
+
+USERS = f"""
+[[users]]
+name = "analytics"
+password_hash = "{hash_password("a-pass")}"
+allow_read = ["events:"]
+
+[[users]]
+name = "ingest"
+password_hash = "{hash_password("i-pass")}"
+allow_read = ["events:"]
+allow_write = ["events:", "staging:"]
+
+[[users]]
+name = "root"
+password_hash = "{hash_password("r-pass")}"
+admin = true
+"""
+
+
+def wait_for(cond, what, timeout=10):
+    deadline = time.time() + timeout
+    while not cond():
+        if time.time() > deadline:
+            raise AssertionError(f"timed out waiting for {what}")
+        time.sleep(0.05)
+
+
+def expect_denied(call, message):
+    try:
+        call()
+        raise AssertionError(f"expected error containing {message!r}")
+    except TinyCacheError as e:
+        assert "permission denied" in str(e) and message in str(e), e
+
+
+def test_acl(acl_file):
+    with spawn_server(persistence=False, acl_file=acl_file) as srv:
+        anon = TinyCache(srv.addr)
+        expect_denied(lambda: anon.get("events:1"), "requires Auth")
+        try:
+            TinyCache(srv.addr, user="analytics", password="wrong", wait_ready=True)
+            raise AssertionError("wrong password accepted")
+        except ConnectionError as e:
+            assert "invalid user or password" in str(e), e
+
+        ingest = TinyCache(srv.addr, user="ingest", password="i-pass")
+        analytics = TinyCache(srv.addr, user="analytics", password="a-pass")
+        seen = []
+        sub = analytics.watch("events:", seen.append)
+        ingest.set("events:1", b"click")
+        assert analytics.get("events:1") == b"click"
+        expect_denied(lambda: analytics.set("events:2", b"x"), "allow_write")
+        expect_denied(lambda: analytics.get("staging:1"), "allow_read")
+        expect_denied(lambda: analytics.save(), "admin = false")
+        expect_denied(lambda: analytics.watch("staging:", seen.append), "allow_read")
+
+        root = TinyCache(srv.addr, user="root", password="r-pass")
+        users = {c["user"] for c in root.client_list()}
+        assert {"", "ingest", "analytics", "root"} <= users, users
+        root.acl_set_user("ops", "o-pass", allow_read=["staging:"])
+        assert [u["name"] for u in root.acl_list()] == ["analytics", "ingest", "ops", "root"]
+        assert root.acl_list()[2] == {
+            "name": "ops",
+            "allow_read": ["staging:"],
+            "allow_write": [],
+            "admin": False,
+        }
+        ops = TinyCache(srv.addr, user="ops", password="o-pass")
+        assert ops.get("staging:1") is None
+        assert root.acl_del_user("analytics") and not root.acl_del_user("analytics")
+        expect_denied(lambda: analytics.get("events:1"), "no longer exists")
+        assert root.config()["acl_file"] == acl_file
+        # watch вошёл тем же пользователем
+        wait_for(lambda: seen, "watch event")
+        assert seen[0]["key"] == "events:1" and sub.error is None, (seen, sub.error)
+        sub.stop()
+
+    with open(acl_file) as f:
+        text = f.read()
+    assert '"ops"' in text and "analytics" not in text, text
+    # переписанный файл читается при следующем старте
+    with spawn_server(persistence=False, acl_file=acl_file) as srv:
+        assert TinyCache(srv.addr, user="ops", password="o-pass").get("staging:1") is None
+    print("acl OK")
+
+
+def test_bad_file(acl_file):
+    with open(acl_file, "w") as f:
+        f.write('[[users]]\nname = "x"\nadmin = maybe\n')
+    try:
+        with spawn_server(persistence=False, acl_file=acl_file):
+            pass
+        raise AssertionError("bad acl_file accepted")
+    except Exception as e:
+        assert "line 3" in str(e), e
+    try:
+        TinyCache("127.0.0.1:1", user="x")
+        raise AssertionError("user without password accepted")
+    except ValueError:
+        pass
+    print("acl bad file OK")
+
+
+def main():
+    with tempfile.TemporaryDirectory() as d:
+        path = os.path.join(d, "users.toml")
+        with open(path, "w") as f:
+            f.write(USERS)
+        test_acl(path)
+        test_bad_file(path)
+    print("ACL TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
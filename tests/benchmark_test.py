@@ -0,0 +1,43 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import time
+from tiny_mp_cache import benchmark, serve, TinyCache
+
+PORT = 5031
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    TinyCache(ADDR, wait_ready=True, ready_timeout=10.0).set("user:1", b"kept")
+
+    r = benchmark(ADDR, ops=5000, value_size=64, workers=4, mix="70get/30set")
+    print(r)
+    assert r["ops"] == 5000 and r["gets"] + r["sets"] == 5000
+    assert r["ops_per_sec"] > 0 and r["p50_us"] <= r["p99_us"] <= r["max_us"]
+    assert r["verified"] >= r["gets"]
+    # чужие ключи целы, ключи прогона удалены
+    c = TinyCache(ADDR)
+    assert c.keys("*") == ["user:1"]
+
+    for mix in ("80get", "get/set", "50get/60set"):
+        try:
+            benchmark(ADDR, ops=10, mix=mix)
+        except ValueError:
+            pass
+        else:
+            raise AssertionError(f"mix {mix!r} must be rejected")
+
+    p.terminate()
+    p.join()
+    print("BENCHMARK TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,98 @@
+#!/usr/bin/env python3
+import errno
+import os
+import shutil
+import socket
+import tempfile
+from tiny_mp_cache import BindError, TinyCache, TinyCacheError, serve, serve_unix, spawn_server
+
+PORT = 5036
+
+
+def expect_bind_error(call):
+    try:
+        call()
+    except BindError as e:
+        return e
+    raise AssertionError("expected BindError")
+
+
+def main():
+    assert issubclass(BindError, TinyCacheError) and issubclass(TinyCacheError, RuntimeError)
+
+    taken = socket.socket()
+    taken.bind(("127.0.0.1", PORT))
+    taken.listen()
+    e = expect_bind_error(lambda: serve(PORT, persistence=False))
+    assert (e.reason, e.errno, e.addr) == ("address_in_use", errno.EADDRINUSE, f"127.0.0.1:{PORT}"), vars(e)
+    assert str(e).startswith(f"cannot bind 127.0.0.1:{PORT}: "), e
+    taken.close()
+
+    tmp = tempfile.mkdtemp()
+    try:
+        path = os.path.join(tmp, "missing", "cache.sock")
+        e = expect_bind_error(lambda: serve_unix(path, persistence=False))
+        assert (e.reason, e.errno, e.addr) == ("invalid_address", errno.ENOENT, path), vars(e)
+
+        # на месте сокета каталог: старый файл не удалить
+        path = os.path.join(tmp, "dir.sock")
+        os.mkdir(path)
+        e = expect_bind_error(lambda: serve_unix(path, persistence=False))
+        assert e.errno == errno.EISDIR and e.addr == path, vars(e)
+        assert str(e).startswith(f"cannot remove old socket {path}: "), e
+
+        # файл сокета упавшего сервера: соединение отклоняется, файл заменяется
+        path = os.path.join(tmp, "stale.sock")
+        stale = socket.socket(socket.AF_UNIX)
+        stale.bind(path)
+        stale.close()
+        with spawn_server(unix_path=path, persistence=False) as srv:
+            TinyCache(srv.addr).set("k", b"v")
+
+            # второй сервер на живом сокете не запускается, первый не задет
+            e = expect_bind_error(lambda: serve_unix(path, persistence=False))
+            assert (e.reason, e.errno, e.addr) == ("address_in_use", None, path), vars(e)
+            assert str(e) == f"cannot bind {path}: another server is already listening on this path", e
+            assert TinyCache(srv.addr).get("k") == b"v"
+
+        # чужой процесс слушает, но на Ping не отвечает: файл тоже не трогаем
+        path = os.path.join(tmp, "foreign.sock")
+        foreign = socket.socket(socket.AF_UNIX)
+        foreign.bind(path)
+        foreign.listen()
+        e = expect_bind_error(lambda: serve_unix(path, persistence=False))
+        assert e.reason == "address_in_use", vars(e)
+        assert "another process is listening on this path" in str(e), e
+        assert os.path.exists(path)
+        foreign.close()
+
+        # старый сокет в каталоге без права записи; root удалит и так
+        if os.geteuid() != 0:
+            locked = os.path.join(tmp, "locked")
+            os.mkdir(locked)
+            path = os.path.join(locked, "cache.sock")
+            stale = socket.socket(socket.AF_UNIX)
+            stale.bind(path)
+            stale.close()
+            os.chmod(locked, 0o555)
+            try:
+                e = expect_bind_error(lambda: serve_unix(path, persistence=False))
+                assert (e.reason, e.errno) == ("permission_denied", errno.EACCES), vars(e)
+                assert str(e).startswith(f"cannot remove old socket {path}: "), e
+            finally:
+                os.chmod(locked, 0o755)
+    finally:
+        shutil.rmtree(tmp)
+
+    # ошибки клиента — тоже TinyCacheError
+    try:
+        TinyCache(f"127.0.0.1:{PORT}").get("k")
+        raise AssertionError("nothing listens there")
+    except TinyCacheError:
+        pass
+
+    print("BIND ERROR TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,93 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import threading
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5020
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def start_server():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def test_immediate_and_timeout(c):
+    c.set("result:ready", b"done")
+    start = time.time()
+    assert c.get_blocking("result:ready", timeout=5.0) == b"done"
+    assert time.time() - start < 1
+
+    start = time.time()
+    assert c.get_blocking("result:never", timeout=0.3) is None
+    elapsed = time.time() - start
+    assert 0.25 < elapsed < 2, elapsed
+    assert c.info()["blocked_clients"] == 0
+
+    for bad in [-1.0, 61.0]:
+        try:
+            c.get_blocking("x", timeout=bad)
+            raise AssertionError(f"timeout={bad} must be rejected")
+        except ValueError:
+            pass
+    print("immediate and timeout OK")
+
+
+def waiter(results, key):
+    results[key] = TinyCache(ADDR).get_blocking("result:42", timeout=10.0)
+
+
+def test_wake_all(c):
+    results = {}
+    # потоки ждут параллельно: get_blocking отпускает GIL
+    threads = [threading.Thread(target=waiter, args=(results, i)) for i in range(5)]
+    for t in threads:
+        t.start()
+    deadline = time.time() + 5
+    while c.info()["blocked_clients"] != 5:
+        assert time.time() < deadline, c.info()["blocked_clients"]
+        time.sleep(0.02)
+
+    # pop и delete ключа никого не будят
+    c.delete("result:42")
+    assert c.pop("result:42", None) is None
+    time.sleep(0.2)
+    assert results == {}
+
+    start = time.time()
+    c.set("result:42", b"answer")
+    for t in threads:
+        t.join()
+    assert time.time() - start < 2
+    assert results == {i: b"answer" for i in range(5)}, results
+    assert c.info()["blocked_clients"] == 0
+    print("wake all OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = start_server()
+    c = TinyCache(ADDR)
+    test_immediate_and_timeout(c)
+    test_wake_all(c)
+    p.terminate()
+    p.join()
+    print("BLOCKING GET TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
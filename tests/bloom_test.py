@@ -0,0 +1,46 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+from tiny_mp_cache import serve, KeyFilter, TinyCache
+
+PORT = 5040
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+
+    c.update({f"img:{i}": b"" for i in range(5000)})
+    c.set("other", b"")
+    f = c.export_bloom("img:")
+    assert isinstance(f, KeyFilter) and f.hashes == 7 and f.bits >= 50000, f
+    assert all(f.maybe_contains(f"img:{i}") for i in range(5000))
+    false_hits = sum(f"img:x{i}" in f for i in range(10000))
+    assert false_hits < 300, false_hits  # ожидается около 1%
+
+    # фильтр пространства имён проверяет ключи без префикса
+    ns = c.namespace("img")
+    nf = ns.export_bloom(bits_per_key=16)
+    assert "1" in nf and nf.hashes == 11
+    assert not c.export_bloom("missing:").maybe_contains("img:1")
+
+    try:
+        c.export_bloom(bits_per_key=0)
+    except Exception as e:
+        assert "bits_per_key" in str(e), e
+    else:
+        raise AssertionError("bits_per_key=0 must be rejected")
+
+    p.terminate()
+    p.join()
+    print("BLOOM TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
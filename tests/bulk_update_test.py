@@ -0,0 +1,84 @@
+#!/usr/bin/env python3
+from tiny_mp_cache import spawn_server, PartialWriteError, TinyCache, TinyCacheError
+
+
+def main():
+    with spawn_server(persistence=False, admin_token="t") as srv:
+        c = TinyCache(srv.addr)
+        admin = TinyCache(srv.addr, admin_token="t")
+        items = {f"bulk:{i:03}": bytes([i]) * 1000 for i in range(100)}
+
+        calls = []
+        c.update(items, batch_bytes=10_000, progress=lambda b, k: calls.append((b, k)))
+        # около 1 КБ на пару: девять пар в пакете
+        assert len(calls) == 12, calls
+        assert calls[-1][1] == 100 and calls[-1][0] > 100_000, calls
+        assert all(a < b for a, b in zip(calls, calls[1:])), calls
+        assert c.to_dict("bulk:") == items
+
+        # пара больше batch_bytes уходит отдельной командой
+        c.update({"big:a": b"x" * 5000, "big:b": b"y"}, batch_bytes=100)
+        assert c.get("big:a") == b"x" * 5000
+        for bad in (0, 2_000_000):
+            try:
+                c.update(items, batch_bytes=bad)
+            except ValueError as e:
+                assert "batch_bytes" in str(e), e
+            else:
+                raise AssertionError(f"batch_bytes={bad} must fail")
+        try:
+            c.update("bulk:n", op="add_i64", arg=1, batch_bytes=10)
+        except TypeError:
+            pass
+        else:
+            raise AssertionError("batch_bytes with op must raise TypeError")
+
+        # сервер отказал посреди записи: в remaining — отказанный пакет и дальше
+        for k in c.keys("bulk:*"):
+            c.delete(k)
+
+        def go_read_only(b, k):
+            if k >= 18:
+                admin.set_read_only(True)
+
+        try:
+            c.update(items, batch_bytes=10_000, progress=go_read_only)
+        except PartialWriteError as e:
+            assert (e.batches_written, e.keys_written) == (2, 18), e
+            assert sorted(e.remaining) == sorted(items)[18:], sorted(e.remaining)[:3]
+            assert isinstance(e.__cause__, TinyCacheError), e.__cause__
+            assert "read-only" in str(e) or "read only" in str(e), e
+            remaining = e.remaining
+        else:
+            raise AssertionError("update on a read-only server must fail")
+        assert len(c.keys("bulk:*")) == 18
+        admin.set_read_only(False)
+        c.update(remaining)
+        assert c.to_dict("bulk:") == items
+
+        # исключение из progress: записанный пакет в remaining не попадает
+        def stop(b, k):
+            if k >= 27:
+                raise LookupError("stop")
+
+        try:
+            c.update({f"p:{i:03}": b"v" * 1000 for i in range(50)}, batch_bytes=10_000, progress=stop)
+        except PartialWriteError as e:
+            assert (e.batches_written, e.keys_written) == (3, 27), e
+            assert len(e.remaining) == 23 and min(e.remaining) == "p:027", e
+            assert isinstance(e.__cause__, LookupError), e.__cause__
+        else:
+            raise AssertionError("an exception from progress must stop update")
+        assert len(c.keys("p:*")) == 27
+        # KeyboardInterrupt не заворачивается
+        try:
+            c.update({"q": b"v"}, progress=lambda b, k: (_ for _ in ()).throw(KeyboardInterrupt()))
+        except KeyboardInterrupt:
+            pass
+        else:
+            raise AssertionError("KeyboardInterrupt from progress must propagate")
+    print("BULK UPDATE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -1,7 +1,12 @@
 #!/usr/bin/env python3
+import array
+import ctypes
+import json
 import multiprocessing as mp
 import os
 import time
+import tracemalloc
+from datetime import datetime, timedelta, timezone
 from tiny_mp_cache import serve, serve_unix, TinyCache  # serve_unix доступен только на Unix
 
 
@@ -16,7 +21,8 @@ def uds_server(stop_event: mp.Event, wal_path: str | None = None):
         try:
             if os.path.exists(UDS_PATH):
                 os.remove(UDS_PATH)
-            serve_unix(UDS_PATH)
+            # TCP-сервер ещё жив и держит журнал в том же каталоге
+            serve_unix(UDS_PATH, name="tiny-mp-cache-uds")
             # нормальное завершение — выходим из цикла
             break
         except Exception as e:
@@ -75,6 +81,22 @@ def run_api_tests(addr: str):
     print("keys:", keys)
     assert "test:a" in keys and "test:b" in keys
 
+    print(f"== [{addr}] keys(sort=True) ==")
+    for i in (3, 1, 4, 0, 2):
+        c.set(f"sorted:{i}", b"x")
+    assert c.keys("sorted:*", sort=True) == [f"sorted:{i}" for i in range(5)]
+    assert c.keys("sorted:*", sort=True, offset=1, limit=2) == ["sorted:1", "sorted:2"]
+    assert c.keys("sorted:*", sort=True, offset=10) == []
+    assert c.keys("sorted:1", sort=True) == []
+    try:
+        c.keys("sorted:*", limit=2)
+    except ValueError:
+        pass
+    else:
+        raise AssertionError("limit without sort must fail")
+    for k in c.keys("sorted:*"):
+        c.delete(k)
+
     print(f"== [{addr}] delete ==")
     n1 = c.delete("test:a")
     n2 = c.delete("test:a")
@@ -86,11 +108,341 @@ def run_api_tests(addr: str):
     print(f"== [{addr}] pop ==")
     c.set("test:pop", b"payload")
     v1 = c.pop("test:pop")
-    v2 = c.pop("test:pop")
+    v2 = c.pop("test:pop", None)
     print("pop1:", v1, "pop2:", v2)
     assert v1 == b"payload"
     assert v2 is None
 
+    print(f"== [{addr}] dict-style access ==")
+    c["test:d"] = b"value-d"
+    assert c["test:d"] == b"value-d"
+    assert "test:d" in c and "test:nope" not in c
+    assert len(c) == c.len()
+    # в отличие от get(), отсутствующий ключ — KeyError, а не None
+    assert c.get("test:nope") is None
+    try:
+        c["test:nope"]
+        raise AssertionError("cache[missing] must raise KeyError")
+    except KeyError as e:
+        assert e.args == ("test:nope",), e
+    del c["test:d"]
+    assert "test:d" not in c
+    try:
+        del c["test:d"]
+        raise AssertionError("del cache[missing] must raise KeyError")
+    except KeyError:
+        pass
+    # пустое значение — это значение, а не отсутствие ключа
+    c["test:empty"] = b""
+    assert "test:empty" in c and c["test:empty"] == b""
+    del c["test:empty"]
+
+    print(f"== [{addr}] get/pop with default ==")
+    sentinel = object()
+    assert c.get("test:nope", b"dflt") == b"dflt"
+    # default не приводится к bytes и возвращается тем же объектом
+    assert c.get("test:nope", sentinel) is sentinel
+    assert c.get("test:nope", 0) == 0
+    assert c.get("test:nope", default=[1]) == [1]
+    assert c.get("test:nope", None) is None
+    c["test:def"] = b"v"
+    assert c.get("test:def", sentinel) == b"v"
+    c["test:empty"] = b""
+    # b"" — значение, default не подставляется
+    assert c.get("test:empty", sentinel) == b""
+    assert c.pop("test:empty", sentinel) == b""
+    assert c.pop("test:def", sentinel) == b"v"
+    assert c.pop("test:def", sentinel) is sentinel
+    assert c.pop("test:def", None) is None
+    # как dict.pop: без default отсутствие ключа — KeyError
+    try:
+        c.pop("test:def")
+        raise AssertionError("pop() of a missing key without default must raise KeyError")
+    except KeyError:
+        pass
+
+    print(f"== [{addr}] setdefault/get_or_set ==")
+    assert c.setdefault("test:sd", b"first") == b"first"
+    assert c.setdefault("test:sd", b"second") == b"first"
+    assert c.get_or_set("test:sd", b"third") == (b"first", False)
+    assert c.get_or_set("test:gos", b"") == (b"", True)
+    assert c.get_or_set("test:gos", b"x") == (b"", False)
+    assert c.delete("test:sd") == 1 and c.delete("test:gos") == 1
+
+    print(f"== [{addr}] update/to_dict ==")
+    c.update({"bulk:a": b"1", "bulk:b": b"2"})
+    c.update([("bulk:c", b"3")])
+    c.update((f"bulk:g{i}", b"g") for i in range(3))
+    assert c.to_dict("bulk:") == {
+        "bulk:a": b"1", "bulk:b": b"2", "bulk:c": b"3",
+        "bulk:g0": b"g", "bulk:g1": b"g", "bulk:g2": b"g",
+    }
+    for bad in ({"bulk:ok": b"x", "bulk:bad": "str"}, [("bulk:ok", b"x"), ("bulk:bad", 1)]):
+        try:
+            c.update(bad)
+        except TypeError as e:
+            assert "bulk:bad" in str(e), e
+        else:
+            raise AssertionError("update with a non-bytes value must raise TypeError")
+    # проверка до отправки: ни одна пара не записана
+    assert c.get("bulk:ok") is None
+    # больше предела кадра: уходит несколькими командами
+    big = {f"bulk:big:{i:04}": bytes([i % 256]) * 1024 for i in range(3000)}
+    c.update(big)
+    assert c.to_dict("bulk:big:") == big
+    assert len(c.to_dict("bulk:big:", max_items=3000)) == 3000
+    try:
+        c.to_dict("bulk:big:", max_items=10)
+    except ValueError as e:
+        assert "more than 10" in str(e), e
+    else:
+        raise AssertionError("to_dict over max_items must raise ValueError")
+    try:
+        c.update({"bulk:huge": b"x" * 2_000_000})
+    except ValueError as e:
+        assert "bulk:huge" in str(e), e
+    else:
+        raise AssertionError("update with an oversized value must raise ValueError")
+    for k in c.keys("bulk:*"):
+        c.delete(k)
+
+    print(f"== [{addr}] update(key, op=...) ==")
+    assert c.update("rmw:n", op="add_i64", arg=5) == 5
+    assert c.update("rmw:n", op="add_i64", arg=-8) == -3
+    assert c.get("rmw:n") == b"-3"
+    assert c.update("rmw:n", op="max_i64", arg=10) == 10
+    assert c.update("rmw:n", op="min_i64", arg=20) == 10
+    assert c.update("rmw:log", op="append_bytes", arg=b"b") == b"b"
+    assert c.update("rmw:log", op="prepend_bytes", arg=b"a") == b"ab"
+    assert c.update("rmw:log", op="set_if_longer", arg=b"x") == b"ab"
+    assert c.update("rmw:log", op="set_if_longer", arg=b"abcd") == b"abcd"
+    assert c.update("rmw:log", op="truncate_to", arg=1) == b"a"
+    assert c.update("rmw:missing", op="truncate_to", arg=0) is None
+    for args, kwargs, exc in [
+        (("rmw:n",), {"op": "mul_i64", "arg": 2}, ValueError),
+        (("rmw:n",), {"op": "add_i64", "arg": b"1"}, TypeError),
+        (("rmw:log",), {"op": "append_bytes", "arg": 1}, TypeError),
+        (("rmw:n",), {"op": "add_i64"}, TypeError),
+        (("rmw:n",), {"op": "truncate_to", "arg": -1}, ValueError),
+        ((1,), {"op": "add_i64", "arg": 1}, TypeError),
+        # значение не целое: ошибку возвращает сервер
+        (("rmw:log",), {"op": "add_i64", "arg": 1}, RuntimeError),
+    ]:
+        try:
+            c.update(*args, **kwargs)
+        except exc:
+            pass
+        else:
+            raise AssertionError(f"update{args}{kwargs} must raise {exc.__name__}")
+    assert c.get("rmw:log") == b"a"
+    for k in c.keys("rmw:*"):
+        c.delete(k)
+
+    print(f"== [{addr}] aggregate(prefix, op) ==")
+    for i, n in enumerate([5, -2, 40]):
+        c.set(f"agg:h{i}", str(n).encode())
+    c.set("agg:bad", b"n/a")
+    assert c.aggregate("agg:", "sum", skip_invalid=True) == 43
+    assert c.aggregate("agg:", "min", skip_invalid=True) == -2
+    assert c.aggregate("agg:", "max", skip_invalid=True) == 40
+    assert c.aggregate("agg:", "count", skip_invalid=True) == 3
+    assert c.aggregate("agg:none:", "max") is None
+    assert c.aggregate("agg:none:", "sum") == 0
+    for op, exc in [("sum", RuntimeError), ("avg", ValueError)]:
+        try:
+            c.aggregate("agg:", op)
+        except exc:
+            pass
+        else:
+            raise AssertionError(f"aggregate(agg:, {op}) must raise {exc.__name__}")
+    for k in c.keys("agg:*"):
+        c.delete(k)
+
+    print(f"== [{addr}] setbit/getbit/bitcount ==")
+    assert c.setbit("bits:b", 9, True) is False
+    assert c.get("bits:b") == b"\x00\x40"
+    assert c.setbit("bits:b", 9, True) is True
+    assert c.getbit("bits:b", 9) and not c.getbit("bits:b", 8)
+    assert not c.getbit("bits:b", 10_000) and not c.getbit("bits:none", 0)
+    c.setbit("bits:b", 0, True)
+    assert c.bitcount("bits:b") == 2 and c.bitcount("bits:none") == 0
+    assert c.bitcount("bits:b", 1) == 1 and c.bitcount("bits:b", -1, -1) == 1
+    assert c.bitcount("bits:b", 0, 0) == 1 and c.bitcount("bits:b", 5, 9) == 0
+    assert c.setbit("bits:b", 0, False) is True and c.bitcount("bits:b") == 1
+    # setbit работает с обычным значением
+    c.set("bits:raw", b"a")  # 0b01100001
+    assert c.bitcount("bits:raw") == 3 and c.getbit("bits:raw", 1)
+    try:
+        c.setbit("bits:huge", 2**32, True)
+    except RuntimeError as e:
+        assert "limit" in str(e), e
+    else:
+        raise AssertionError("setbit over the offset limit must fail")
+    assert c.get("bits:huge") is None
+    for k in c.keys("bits:*"):
+        c.delete(k)
+
+    print(f"== [{addr}] touch/idle_time ==")
+    c.set("idle:a", b"1")
+    c.set("idle:b", b"2")
+    assert c.idle_time("idle:a") == 0 and c.idle_time("idle:none") is None
+    assert c.touch("idle:a", "idle:b", "idle:none") == 2
+    assert c.touch() == 0
+    for k in c.keys("idle:*"):
+        c.delete(k)
+
+    print(f"== [{addr}] type ==")
+    c.set("type:raw", b"abc")
+    c.set_obj("type:obj", {"a": 1})
+    assert c.type("type:raw") == "bytes" and c.type("type:none") == "none"
+    assert c.type("type:raw", detail=True) == {"type": "bytes", "encoding": "raw", "length": 3}
+    assert c.type("type:obj", detail=True)["encoding"] == "pickle"
+    assert c.type("type:none", detail=True) == {"type": "none"}
+    for k in c.keys("type:*"):
+        c.delete(k)
+
+    print(f"== [{addr}] setex/expire/expire_at/ttl ==")
+    c.setex("ttl:a", 0.2, b"1")
+    assert 0 < c.pttl("ttl:a") <= 200 and c.ttl("ttl:a") in (0, 1)
+    c.psetex("ttl:b", 10_000, bytearray(b"2"))
+    assert c.get("ttl:b") == b"2" and 9 <= c.ttl("ttl:b") <= 10
+    c.set("ttl:c", b"3")
+    assert c.ttl("ttl:c") == -1 and c.ttl("ttl:none") == -2 and c.pttl("ttl:none") == -2
+    assert c.expire("ttl:c", 60) and 59_000 < c.pttl("ttl:c") <= 60_000
+    assert c.expire("ttl:none", 60) is False
+    # абсолютный срок: datetime или unix-время в секундах
+    assert c.expire_at("ttl:c", datetime.now(timezone.utc) + timedelta(hours=1))
+    assert 3590 <= c.ttl("ttl:c") <= 3600
+    assert c.expire_at("ttl:c", time.time() + 30) and 29 <= c.ttl("ttl:c") <= 30
+    # set сбрасывает срок
+    c.set("ttl:c", b"3")
+    assert c.ttl("ttl:c") == -1
+    # момент в прошлом и неположительный срок удаляют ключ сразу
+    assert c.expire_at("ttl:c", datetime(2000, 1, 1, tzinfo=timezone.utc)) is True
+    assert c.get("ttl:c") is None and c.expire_at("ttl:c", time.time() + 30) is False
+    assert c.expire("ttl:b", 0) is True and "ttl:b" not in c
+    time.sleep(0.3)
+    assert c.get("ttl:a") is None and "ttl:a" not in c and c.ttl("ttl:a") == -2
+    assert c.keys("ttl:*") == []
+    for bad in (0, -1, 0.0001, float("nan")):
+        try:
+            c.setex("ttl:bad", bad, b"x")
+        except ValueError:
+            pass
+        else:
+            raise AssertionError(f"setex with ttl {bad} must fail")
+    try:
+        c.expire_at("ttl:bad", "tomorrow")
+    except TypeError:
+        pass
+    else:
+        raise AssertionError("expire_at with a str must fail")
+    assert c.get("ttl:bad") is None
+
+    print(f"== [{addr}] set_json/get_json ==")
+    doc = {"name": "Ёжик", "tags": ["a", "б"], "nested": {"n": 1, "f": 1.5, "ok": True, "none": None}}
+    c.set_json("json:doc", doc)
+    assert c.get_json("json:doc") == doc
+    # те же байты, что при ручной сериализации
+    assert c.get("json:doc") == json.dumps(doc).encode()
+    c.set("json:manual", json.dumps([1, "два"]).encode())
+    assert c.get_json("json:manual") == [1, "два"]
+    # сохранённый null — это None, default только для отсутствующего ключа
+    c.set_json("json:null", None)
+    assert c.get("json:null") == b"null"
+    assert c.get_json("json:null", "dflt") is None
+    assert c.get_json("json:nope") is None
+    assert c.get_json("json:nope", {"d": 1}) == {"d": 1}
+    for raw in (b"{not json", b"\xff\xfe"):
+        c.set("json:bad", raw)
+        try:
+            c.get_json("json:bad")
+        except ValueError as e:
+            assert "json:bad" in str(e), e
+        else:
+            raise AssertionError("get_json on invalid JSON must raise ValueError")
+    try:
+        c.set_json("json:obj", object())
+    except TypeError:
+        pass
+    else:
+        raise AssertionError("set_json on a non-serializable object must raise TypeError")
+    for k in c.keys("json:*"):
+        c.delete(k)
+
+    print(f"== [{addr}] set() with buffer objects ==")
+    c.set("buf:ba", bytearray(b"from-bytearray"))
+    assert c.get("buf:ba") == b"from-bytearray"
+    c["buf:mv"] = memoryview(b"xxfrom-viewxx")[2:-2]
+    assert c.get("buf:mv") == b"from-view"
+    nums = array.array("i", [1, 2, 3])
+    c.set("buf:arr", nums)
+    assert c.get("buf:arr") == nums.tobytes()
+    # 2D-буфер уходит плоско, в порядке C
+    grid = memoryview(bytes(range(6))).cast("B", (2, 3))
+    c.set("buf:2d", grid)
+    assert c.get("buf:2d") == bytes(range(6))
+    c.set("buf:empty", bytearray())
+    assert c.get("buf:empty") == b""
+    try:
+        c.set("buf:strided", memoryview(b"abcdef")[::2])
+    except BufferError as e:
+        assert "C-contiguous" in str(e), e
+    else:
+        raise AssertionError("set() of a non-contiguous buffer must raise BufferError")
+    for bad in ("text", 42, None):
+        try:
+            c.set("buf:bad", bad)
+        except TypeError as e:
+            assert "bytes-like" in str(e), e
+        else:
+            raise AssertionError(f"set() of {bad!r} must raise TypeError")
+    try:
+        c.set("buf:huge", bytearray(2_000_000))
+    except ValueError as e:
+        assert "buf:huge" in str(e) and "limit" in str(e), e
+    else:
+        raise AssertionError("set() over the frame limit must raise ValueError")
+    assert c.get("buf:strided") is None and c.get("buf:bad") is None and c.get("buf:huge") is None
+    # значение копируется прямо в кадр: Python-аллокаций размера значения нет
+    big = bytearray(os.urandom(900_000))
+    tracemalloc.start()
+    c.set("buf:big", big)
+    c.set("buf:big-view", memoryview(big))
+    _, peak = tracemalloc.get_traced_memory()
+    tracemalloc.stop()
+    assert peak < 100_000, peak
+    assert c.get("buf:big") == big and c.get("buf:big-view") == big
+    # формат с явным порядком байтов ('<d' у ctypes) тоже идёт в кадр без копии
+    doubles = (ctypes.c_double * 100_000)(*range(100_000))
+    le = memoryview(doubles)
+    assert le.format == "<d", le.format
+    tracemalloc.start()
+    c.set("buf:le", le)
+    _, peak = tracemalloc.get_traced_memory()
+    tracemalloc.stop()
+    assert peak < 100_000, peak
+    assert c.get("buf:le") == bytes(doubles)
+    # пустой многомерный буфер cast('B') не берёт, но это просто b""
+    c.set("buf:le-empty", memoryview(((ctypes.c_double * 0) * 3)()))
+    assert c.get("buf:le-empty") == b""
+    try:
+        import numpy as np
+    except ImportError:
+        pass
+    else:
+        m = np.arange(100_000, dtype=np.float64)
+        c.set("buf:np", m)
+        assert np.array_equal(np.frombuffer(c.get("buf:np"), dtype=np.float64), m)
+        try:
+            c.set("buf:np-t", np.arange(12.0).reshape(3, 4).T)
+        except BufferError:
+            pass
+        else:
+            raise AssertionError("set() of a transposed array must raise BufferError")
+    for k in c.keys("buf:*"):
+        c.delete(k)
+
     print(f"ALL API TESTS PASSED for {addr}\n")
 
 
@@ -0,0 +1,53 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import shutil
+from tiny_mp_cache import serve, TinyCache, TinyCacheLocal
+
+PORT = 5044
+ADDR = f"127.0.0.1:{PORT}"
+WAL_DIR = "/tmp/tiny_mp_cache_case_insensitive_test"
+
+
+def server():
+    serve(PORT, wal_dir=WAL_DIR, case_insensitive_keys=True)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    c.set("Session:ABC", b"1")
+    c.set("SESSION:Été", b"2")
+    assert c.get("session:abc") == b"1"
+    assert c.get("session:été") == b"2"
+    assert sorted(c.keys("SESSION:*")) == ["session:abc", "session:été"]
+    c.delete("SeSsIoN:aBc")
+    assert c.get("session:abc") is None
+    assert c.info()["case_insensitive_keys"] == 1
+    print("fold OK")
+    p.terminate()
+    p.join()
+
+    # WAL помнит настройку: без неё каталог не открывается
+    for open_plain in [
+        lambda: serve(PORT, wal_dir=WAL_DIR),
+        lambda: TinyCacheLocal(wal_dir=WAL_DIR),
+    ]:
+        try:
+            open_plain()
+            raise AssertionError("must refuse")
+        except Exception as e:
+            assert "case_insensitive_keys" in str(e), e
+    local = TinyCacheLocal(wal_dir=WAL_DIR, case_insensitive_keys=True)
+    assert local.get("SESSION:ÉTÉ") == b"2"
+    print("refuse OK")
+
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+    print("CASE INSENSITIVE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
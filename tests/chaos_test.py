@@ -0,0 +1,101 @@
+#!/usr/bin/env python3
+import time
+from tiny_mp_cache import spawn_server, TinyCache, TinyCacheError
+
+INJECTED = "chaos: injected error"
+
+
+def outcome(call):
+    try:
+        call()
+        return "ok"
+    except TinyCacheError as e:
+        return "error" if INJECTED in str(e) else "dropped"
+
+
+def until_ok(call, tries=50):
+    for _ in range(tries):
+        try:
+            return call()
+        except TinyCacheError:
+            pass
+    raise AssertionError("every attempt failed")
+
+
+def run(seed):
+    chaos = {"error_rate": 0.2, "disconnect_rate": 0.2, "seed": seed}
+    with spawn_server(persistence=False, admin_token="t", chaos=chaos) as srv:
+        c = TinyCache(srv.addr)
+        seen = [outcome(lambda: c.set(f"k:{i}", b"v")) for i in range(200)]
+        assert {"ok", "error", "dropped"} <= set(seen), seen
+        # обрыв переиспользованного соединения set переживает повтором, так
+        # что новых соединений больше, чем отказов наружу
+        reconnects = c.pool_stats()["reconnects"]
+        assert reconnects > seen.count("dropped"), (reconnects, seen.count("dropped"))
+        cfg = until_ok(TinyCache(srv.addr, admin_token="t").config)
+        assert cfg["chaos"] == 1 and cfg["chaos_seed"] == seed, cfg
+        info = until_ok(c.info)
+        assert info["chaos_disconnects"] >= reconnects, info
+    return seen
+
+
+def test_seeded_faults():
+    # тот же seed — те же сбои тех же команд
+    assert run(42) == run(42)
+    print("seeded faults OK")
+
+
+def test_pop_not_retried():
+    with spawn_server(persistence=False, chaos={"disconnect_rate": 0.5, "seed": 3}) as srv:
+        c = TinyCache(srv.addr)
+        for i in range(50):
+            until_ok(lambda: c.set(f"p:{i}", b"v"))
+        popped = dropped = 0
+        for i in range(50):
+            if outcome(lambda: c.pop(f"p:{i}")) == "ok":
+                popped += 1
+            else:
+                dropped += 1
+        assert popped and dropped, (popped, dropped)
+        # pop не повторяется: оборванный не выполнился, ключ на месте
+        assert until_ok(lambda: len(c)) == dropped
+    print("pop not retried OK")
+
+
+def test_latency():
+    with spawn_server(persistence=False, chaos={"latency_ms": (100, 150)}) as srv:
+        c = TinyCache(srv.addr)
+        started = time.monotonic()
+        c.set("k", b"v")
+        assert c.get("k") == b"v"
+        elapsed = time.monotonic() - started
+        assert 0.2 <= elapsed < 2.0, elapsed
+        assert c.info()["chaos_delays"] >= 2
+    print("latency OK")
+
+
+def test_bad_options():
+    for chaos, message in [
+        ({"error_rate": 2}, "between 0 and 1"),
+        ({"latency": 5}, "unknown chaos setting"),
+        ({"latency_ms": "slow"}, "latency_ms expects"),
+    ]:
+        try:
+            with spawn_server(persistence=False, chaos=chaos):
+                pass
+            raise AssertionError(f"chaos={chaos} accepted")
+        except (ValueError, RuntimeError) as e:
+            assert message in str(e), e
+    print("bad options OK")
+
+
+def main():
+    test_seeded_faults()
+    test_pop_not_retried()
+    test_latency()
+    test_bad_options()
+    print("CHAOS TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,4001 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tiny_mp_cache::core::Deadline;
+use tiny_mp_cache::crypto::WalKey;
+use tiny_mp_cache::error::{BindReason, CacheError};
+use tiny_mp_cache::wal::{
+    self, replace_file, FsyncPolicy, ReplayHook, ReplayProgress, VerifyStatus, WAL_SCHEMA,
+};
+use tiny_mp_cache::{current_trace_id, with_trace_id, MAX_TRACE_ID};
+use tiny_mp_cache::{decode_value, encode_value, AggOp, ValueEncoding};
+use tiny_mp_cache::{hash_password, AclUser};
+use tiny_mp_cache::{
+    parse_mix, serve_listener, serve_tcp, BenchOptions, CacheCommand, CacheResponse, ChaosOptions,
+    Client, ClientOptions, DumpFormat, KeyNormalization, Loaded, MuxConn, Overflow, PersistOptions,
+    PersistentCore, ResponseValue, ServerEvent, UpdateOp, WatchOp, WriteFeed, INJECTED_ERROR,
+    MAX_RESPONSE_SIZE, PROTOCOL_VERSION,
+};
+
+fn start_server(core: PersistentCore) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || serve_listener(listener, Arc::new(core)));
+    addr
+}
+
+#[test]
+fn every_method() {
+    let mut core = PersistentCore::ephemeral();
+    core.set_admin_token("secret".into());
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+
+    c.ping().unwrap();
+    assert!(c.is_empty().unwrap());
+    c.set("job:1", b"one").unwrap();
+    c.set("job:2", b"two").unwrap();
+    c.set("other", b"x").unwrap();
+    assert_eq!(c.get("job:1").unwrap(), Some(b"one".to_vec()));
+    assert_eq!(c.get("missing").unwrap(), None);
+    assert!(c.exists("job:1").unwrap());
+    assert!(!c.exists("missing").unwrap());
+    assert_eq!(c.len().unwrap(), 3);
+    assert_eq!(
+        c.get_or_set("job:1", b"new").unwrap(),
+        (b"one".to_vec(), false)
+    );
+    assert_eq!(
+        c.get_or_set("fresh", b"new").unwrap(),
+        (b"new".to_vec(), true)
+    );
+    assert_eq!(
+        c.get_or_set("fresh", b"newer").unwrap(),
+        (b"new".to_vec(), false)
+    );
+    assert_eq!(c.delete("fresh").unwrap(), 1);
+
+    let mut keys = c.keys("job:*").unwrap();
+    keys.sort();
+    assert_eq!(keys, ["job:1", "job:2"]);
+
+    let (next, mut items) = c.scan_items(0, "job:", 100).unwrap();
+    items.sort();
+    assert_eq!(next, 0);
+    assert_eq!(
+        items,
+        [
+            ("job:1".to_string(), b"one".to_vec()),
+            ("job:2".to_string(), b"two".to_vec())
+        ]
+    );
+
+    let (next, mut keys) = c.scan_keys(0, "job:", 1).unwrap();
+    assert!(next != 0 && keys.len() == 1);
+    let (next, rest) = c.scan_keys(next, "job:", 100).unwrap();
+    keys.extend(rest);
+    keys.sort();
+    assert_eq!(
+        (next, keys),
+        (0, vec!["job:1".to_string(), "job:2".to_string()])
+    );
+
+    c.set("job:big", &[0; 5000]).unwrap();
+    let sorted = |(next, mut keys): (u64, Vec<(String, u64)>)| {
+        assert_eq!(next, 0);
+        keys.sort();
+        keys
+    };
+    assert_eq!(
+        sorted(c.scan_sizes(0, "job:", 100, 1000..).unwrap()),
+        [("job:big".to_string(), 5000)]
+    );
+    assert_eq!(
+        sorted(c.scan_sizes(0, "job:", 100, 0..=3).unwrap()),
+        [("job:1".to_string(), 3), ("job:2".to_string(), 3)]
+    );
+    assert_eq!(sorted(c.scan_sizes(0, "job:", 100, 0..3).unwrap()), []);
+    assert_eq!(c.scan_sizes(0, "job:", 100, ..0).unwrap(), (0, vec![]));
+    let (next, first) = c.scan_sizes(0, "job:", 1, ..).unwrap();
+    assert!(next != 0 && first.len() == 1);
+    c.set_ex("job:big", b"x", Duration::from_secs(60)).unwrap();
+    let (next, mut ttls) = c.scan_ttl(0, "job:", 100).unwrap();
+    ttls.sort();
+    assert_eq!(next, 0);
+    assert!(matches!(&ttls[..], [(a, -1), (b, -1), (big, ms)]
+        if a == "job:1" && b == "job:2" && big == "job:big" && (59_000..=60_000).contains(ms)));
+    assert_eq!(c.delete("job:big").unwrap(), 1);
+
+    assert_eq!(c.pop("job:2").unwrap(), Some(b"two".to_vec()));
+    assert_eq!(c.pop("job:2").unwrap(), None);
+    assert_eq!(c.delete("other").unwrap(), 1);
+    assert_eq!(c.delete("other").unwrap(), 0);
+    c.set("tmp:a", b"1").unwrap();
+    c.set("tmp:b", b"2").unwrap();
+    c.set("tmpx", b"3").unwrap();
+    assert_eq!(c.delete_prefix("tmp:").unwrap(), 2);
+    assert_eq!(c.delete_prefix("tmp:").unwrap(), 0);
+    assert_eq!(c.delete("tmpx").unwrap(), 1);
+
+    assert_eq!(
+        c.get_blocking("job:1", Duration::from_secs(1)).unwrap(),
+        Some(b"one".to_vec())
+    );
+    let start = Instant::now();
+    assert_eq!(
+        c.get_blocking("never", Duration::from_millis(200)).unwrap(),
+        None
+    );
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert!(c.get_blocking("never", Duration::from_secs(61)).is_err());
+
+    assert_eq!(c.publish("news", b"hi").unwrap(), 0);
+
+    let info = c.info().unwrap();
+    assert!(info
+        .iter()
+        .any(|(k, v)| k == "persistence" && v.as_str() == Some("none")));
+    assert!(info
+        .iter()
+        .any(|(k, v)| k == "keys" && *v == ResponseValue::Int(1)));
+    // без персистентности WAL нет, но соединение остаётся рабочим
+    assert!(matches!(c.wal_stats(), Err(CacheError::Server(_))));
+    assert!(matches!(c.save(), Err(CacheError::Server(_))));
+    assert!(matches!(c.bgsave(), Err(CacheError::Server(_))));
+    assert_eq!(c.len().unwrap(), 1);
+
+    let dir = std::env::temp_dir().join(format!("tiny-mp-cache-client-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dump = dir.join("dump.bin");
+    let dump = dump.to_str().unwrap();
+    assert_eq!(c.export(dump, "binary").unwrap(), 1);
+    c.set("job:1", b"changed").unwrap();
+    assert_eq!(c.import_dump(dump, true).unwrap(), 1);
+    assert_eq!(c.get("job:1").unwrap(), Some(b"one".to_vec()));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(matches!(c.set_read_only(true), Err(CacheError::Server(_))));
+    let admin = ClientOptions {
+        admin_token: Some("secret".into()),
+        ..Default::default()
+    };
+    let admin = Client::connect_with(&addr, admin).unwrap();
+    admin.set_read_only(true).unwrap();
+    assert!(matches!(c.set("k", b"v"), Err(CacheError::Server(_))));
+    admin.set_read_only(false).unwrap();
+    c.set("k", b"v").unwrap();
+}
+
+#[test]
+fn get_or_set_has_one_winner() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Arc::new(Client::connect(&addr).unwrap());
+    for round in 0..20 {
+        let key = format!("race:{}", round);
+        let workers: Vec<_> = (0..8u8)
+            .map(|n| {
+                let (c, key) = (Arc::clone(&c), key.clone());
+                thread::spawn(move || c.get_or_set(&key, &[n]).unwrap())
+            })
+            .collect();
+        let results: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        // ровно один записал, и все видят его значение
+        assert_eq!(results.iter().filter(|(_, inserted)| *inserted).count(), 1);
+        let winner = c.get(&key).unwrap().unwrap();
+        assert!(results.iter().all(|(v, _)| *v == winner));
+    }
+}
+
+#[test]
+fn conditional_delete() {
+    let dir = std::env::temp_dir().join(format!("tmc-delete-if-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+    let wal_field = |core: &PersistentCore, name: &str| info_field(core.wal_stats().unwrap(), name);
+
+    let core = open();
+    assert!(core
+        .set_nx("lock".into(), b"mine".to_vec(), 60_000)
+        .unwrap());
+    assert!(!core
+        .set_nx("lock".into(), b"theirs".to_vec(), 60_000)
+        .unwrap());
+    assert_eq!(wal_field(&core, "sets"), ResponseValue::Int(1));
+    assert!(!core.delete_if_equals("lock", b"theirs").unwrap());
+    assert!(!core.delete_if_equals("missing", b"mine").unwrap());
+    // несовпадение в WAL не пишется
+    assert_eq!(wal_field(&core, "dels"), ResponseValue::Int(0));
+    drop(core);
+
+    let core = open();
+    assert_eq!(core.get("lock"), Some(b"mine".to_vec()));
+    assert!(core.pttl("lock") > 50_000);
+    assert!(core.delete_if_equals("lock", b"mine").unwrap());
+    assert_eq!(wal_field(&core, "dels"), ResponseValue::Int(1));
+    drop(core);
+
+    let core = open();
+    assert_eq!(core.get("lock"), None);
+    drop(core);
+    let _ = fs::remove_dir_all(&dir);
+
+    // через клиента, в том числе в режиме надгробий
+    let core = PersistentCore::ephemeral();
+    core.set_tombstone_ttl(Some(Duration::from_secs(60)));
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    assert!(c
+        .set_nx("lock", b"a", Some(Duration::from_millis(300)))
+        .unwrap());
+    assert!(!c.set_nx("lock", b"b", None).unwrap());
+    assert!(!c.delete_if("lock", b"b").unwrap());
+    assert!(c.delete_if("lock", b"a").unwrap());
+    assert!(c.undelete("lock").unwrap());
+    assert!(!c.extend_if("lock", b"b", Duration::from_secs(60)).unwrap());
+    assert!(c.pttl("lock").unwrap() <= 300);
+    assert!(c
+        .extend_if("lock", b"a", Duration::from_millis(100))
+        .unwrap());
+    assert!(c.pttl("lock").unwrap() > 300);
+    // аренда истекла: ключ берёт другой, прежний владелец его не снимет
+    thread::sleep(Duration::from_millis(500));
+    assert!(c.set_nx("lock", b"b", None).unwrap());
+    assert!(!c.delete_if("lock", b"a").unwrap());
+    assert_eq!(c.get("lock").unwrap(), Some(b"b".to_vec()));
+    assert_eq!(c.pttl("lock").unwrap(), -1);
+    // бессрочному ключу продление ставит срок
+    assert!(c.extend_if("lock", b"b", Duration::from_secs(60)).unwrap());
+    assert!(c.pttl("lock").unwrap() > 50_000);
+}
+
+#[test]
+fn pop_miss_is_not_logged() {
+    let dir = std::env::temp_dir().join(format!("tmc-pop-miss-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+    let wal_field = |core: &PersistentCore, name: &str| info_field(core.wal_stats().unwrap(), name);
+
+    let core = open();
+    for i in 0..10 {
+        core.set(format!("job:{}", i), b"x".to_vec()).unwrap();
+    }
+    let bytes = wal_field(&core, "bytes");
+    // очередь: pop до пустой, промахов в десятки раз больше попаданий
+    let mut popped = 0;
+    for i in 0..1000 {
+        if core.pop(&format!("job:{}", i % 100)).unwrap().is_some() {
+            popped += 1;
+        }
+        assert_eq!(core.delete(&format!("gone:{}", i)).unwrap(), 0);
+    }
+    assert_eq!(popped, 10);
+    assert_eq!(wal_field(&core, "pops"), ResponseValue::Int(10));
+    assert_eq!(wal_field(&core, "dels"), ResponseValue::Int(0));
+    assert_eq!(wal_field(&core, "records"), ResponseValue::Int(20));
+    let ResponseValue::Int(after_hits) = wal_field(&core, "bytes") else {
+        panic!("bytes is not an int");
+    };
+    let ResponseValue::Int(before) = bytes else {
+        panic!("bytes is not an int");
+    };
+    assert!(after_hits > before);
+    // одни промахи WAL не растят
+    for i in 0..1000 {
+        assert_eq!(core.pop(&format!("job:{}", i)).unwrap(), None);
+        assert_eq!(core.delete(&format!("job:{}", i)).unwrap(), 0);
+    }
+    assert_eq!(wal_field(&core, "bytes"), ResponseValue::Int(after_hits));
+    core.set("kept".into(), b"v".to_vec()).unwrap();
+    assert_eq!(core.delete("kept").unwrap(), 1);
+    assert_eq!(wal_field(&core, "dels"), ResponseValue::Int(1));
+    core.set("kept".into(), b"v".to_vec()).unwrap();
+    drop(core);
+
+    let core = open();
+    assert_eq!(core.get("job:3"), None);
+    assert_eq!(core.get("kept"), Some(b"v".to_vec()));
+    drop(core);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn incr_range() {
+    let dir = std::env::temp_dir().join(format!("tmc-incr-range-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+
+    let core = open();
+    assert_eq!(core.incr_range("ids".into(), 100).unwrap(), 0);
+    assert_eq!(core.incr_range("ids".into(), 1).unwrap(), 100);
+    assert!(core.incr_range("ids".into(), 0).is_err());
+    core.set("text".into(), b"abc".to_vec()).unwrap();
+    assert!(core.incr_range("text".into(), 1).is_err());
+    drop(core);
+    // выданные до перезапуска номера не повторяются, и после компакции тоже
+    let core = open();
+    assert_eq!(core.incr_range("ids".into(), 10).unwrap(), 101);
+    core.compact().unwrap();
+    drop(core);
+    let core = open();
+    assert_eq!(core.get("ids"), Some(b"111".to_vec()));
+    drop(core);
+    let _ = fs::remove_dir_all(&dir);
+
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Arc::new(Client::connect(&addr).unwrap());
+    let workers: Vec<_> = (0..8)
+        .map(|_| {
+            let c = Arc::clone(&c);
+            thread::spawn(move || {
+                (0..50)
+                    .map(|_| c.incr_range("ids", 7).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let mut starts: Vec<i64> = workers
+        .into_iter()
+        .flat_map(|w| w.join().unwrap())
+        .collect();
+    starts.sort_unstable();
+    // блоки идут встык, без пересечений
+    assert_eq!(starts, (0..400).map(|i| i * 7).collect::<Vec<_>>());
+}
+
+#[test]
+fn read_through_loader() {
+    let calls = Arc::new(Mutex::new(Vec::<String>::new()));
+    let seen = Arc::clone(&calls);
+    let mut core = PersistentCore::ephemeral();
+    core.set_loader(
+        Box::new(move |key: &str| {
+            seen.lock().unwrap().push(key.to_string());
+            // медленный источник: одновременные промахи застают вызов
+            thread::sleep(Duration::from_millis(100));
+            match key {
+                "missing" => Ok(None),
+                "broken" => Err("source is down".into()),
+                "panics" => panic!("loader bug"),
+                "short" => Ok(Some(Loaded {
+                    value: b"tmp".to_vec(),
+                    ttl: Some(Duration::from_millis(300)),
+                })),
+                _ => Ok(Some(Loaded {
+                    value: format!("v:{}", key).into_bytes(),
+                    ttl: None,
+                })),
+            }
+        }),
+        2,
+    )
+    .unwrap();
+    core.set("cached".into(), b"own".to_vec()).unwrap();
+    let addr = start_server(core).to_string();
+    let c = Arc::new(Client::connect(&addr).unwrap());
+
+    // ключ в кэше загрузчик не зовёт
+    assert_eq!(c.get("cached").unwrap(), Some(b"own".to_vec()));
+    let workers: Vec<_> = (0..8)
+        .map(|_| {
+            let c = Arc::clone(&c);
+            thread::spawn(move || c.get("user:1").unwrap())
+        })
+        .collect();
+    for w in workers {
+        assert_eq!(w.join().unwrap(), Some(b"v:user:1".to_vec()));
+    }
+    // один вызов на все промахи, дальше значение из кэша
+    assert_eq!(*calls.lock().unwrap(), ["user:1"]);
+    assert_eq!(c.get("user:1").unwrap(), Some(b"v:user:1".to_vec()));
+    assert_eq!(c.pttl("user:1").unwrap(), -1);
+    assert_eq!(calls.lock().unwrap().len(), 1);
+
+    assert_eq!(c.get("missing").unwrap(), None);
+    assert!(!c.exists("missing").unwrap());
+    match c.get("broken") {
+        Err(CacheError::Server(msg)) => assert!(msg.contains("source is down"), "{}", msg),
+        other => panic!("unexpected {:?}", other),
+    }
+    match c.get("panics") {
+        Err(CacheError::Server(msg)) => assert!(msg.contains("panicked"), "{}", msg),
+        other => panic!("unexpected {:?}", other),
+    }
+    // ошибка не закрывает соединение, и загрузчик живёт дальше
+    assert_eq!(c.get("user:2").unwrap(), Some(b"v:user:2".to_vec()));
+
+    assert_eq!(c.get("short").unwrap(), Some(b"tmp".to_vec()));
+    let ttl = c.pttl("short").unwrap();
+    assert!(0 < ttl && ttl <= 300, "{}", ttl);
+    thread::sleep(Duration::from_millis(400));
+    let before = calls.lock().unwrap().len();
+    assert_eq!(c.get("short").unwrap(), Some(b"tmp".to_vec()));
+    assert_eq!(calls.lock().unwrap().len(), before + 1);
+}
+
+#[test]
+fn write_feed() {
+    let (feed, events, drain) = WriteFeed::new("persist:".into(), 1000, Overflow::Drop);
+    let mut core = PersistentCore::ephemeral();
+    core.set_write_feed(Arc::clone(&feed));
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    let consumer =
+        thread::spawn(move || events.map(|e| (e.key, e.op, e.value)).collect::<Vec<_>>());
+
+    c.set("persist:a", b"1").unwrap();
+    c.set("other", b"x").unwrap();
+    c.update("persist:n", UpdateOp::AddI64(5)).unwrap();
+    c.set_ex("persist:t", b"tmp", Duration::from_millis(50))
+        .unwrap();
+    c.delete("persist:a").unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        info_field(c.info().unwrap(), "write_feed_sent"),
+        ResponseValue::Int(5)
+    );
+    // после закрытия записи в поток не идут, а очередь дочитывается
+    feed.close();
+    c.set("persist:late", b"1").unwrap();
+    assert!(drain.wait(Duration::from_secs(5)));
+    let set = |k: &str, v: &[u8]| (k.to_string(), WatchOp::Set, Some(v.to_vec()));
+    assert_eq!(
+        consumer.join().unwrap(),
+        [
+            set("persist:a", b"1"),
+            set("persist:n", b"5"),
+            set("persist:t", b"tmp"),
+            ("persist:a".into(), WatchOp::Del, None),
+            ("persist:t".into(), WatchOp::Expired, None),
+        ]
+    );
+    assert!("block".parse::<Overflow>().is_ok() && "later".parse::<Overflow>().is_err());
+
+    // drop: без потребителя лишнее выбрасывается и считается, block ждёт места
+    for overflow in [Overflow::Drop, Overflow::Block] {
+        let (feed, events, _drain) = WriteFeed::new(String::new(), 2, overflow);
+        let mut core = PersistentCore::ephemeral();
+        core.set_write_feed(Arc::clone(&feed));
+        let core = Arc::new(core);
+        let writer = {
+            let core = Arc::clone(&core);
+            thread::spawn(move || {
+                for i in 0..5 {
+                    core.set(format!("k{}", i), b"v".to_vec()).unwrap();
+                }
+            })
+        };
+        thread::sleep(Duration::from_millis(200));
+        let dropped = info_field(core.info().unwrap(), "write_feed_dropped");
+        let received = match overflow {
+            Overflow::Drop => {
+                feed.close();
+                events.count()
+            }
+            Overflow::Block => events.take(5).count(),
+        };
+        writer.join().unwrap();
+        let expected = match overflow {
+            Overflow::Drop => (3, 2),
+            Overflow::Block => (0, 5),
+        };
+        assert_eq!(
+            (dropped, received),
+            (ResponseValue::Int(expected.0), expected.1)
+        );
+    }
+}
+
+#[test]
+fn update_ops() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+    let b = |s: &str| Some(s.as_bytes().to_vec());
+
+    assert_eq!(
+        c.update("log", UpdateOp::AppendBytes(b"b".to_vec()))
+            .unwrap(),
+        b("b")
+    );
+    assert_eq!(
+        c.update("log", UpdateOp::AppendBytes(b"c".to_vec()))
+            .unwrap(),
+        b("bc")
+    );
+    assert_eq!(
+        c.update("log", UpdateOp::PrependBytes(b"a".to_vec()))
+            .unwrap(),
+        b("abc")
+    );
+    assert_eq!(c.update("log", UpdateOp::TruncateTo(2)).unwrap(), b("ab"));
+    assert_eq!(c.update("log", UpdateOp::TruncateTo(5)).unwrap(), b("ab"));
+    assert_eq!(c.update("nothing", UpdateOp::TruncateTo(0)).unwrap(), None);
+    assert!(!c.exists("nothing").unwrap());
+    assert_eq!(
+        c.update("log", UpdateOp::SetIfLonger(b"x".to_vec()))
+            .unwrap(),
+        b("ab")
+    );
+    assert_eq!(
+        c.update("log", UpdateOp::SetIfLonger(b"xyz".to_vec()))
+            .unwrap(),
+        b("xyz")
+    );
+
+    assert_eq!(c.update("n", UpdateOp::AddI64(5)).unwrap(), b("5"));
+    assert_eq!(c.update("n", UpdateOp::AddI64(-7)).unwrap(), b("-2"));
+    assert_eq!(c.update("n", UpdateOp::MinI64(3)).unwrap(), b("-2"));
+    assert_eq!(c.update("n", UpdateOp::MaxI64(3)).unwrap(), b("3"));
+    assert_eq!(c.update("lo", UpdateOp::MinI64(9)).unwrap(), b("9"));
+    c.set("max", i64::MAX.to_string().as_bytes()).unwrap();
+    assert!(matches!(
+        c.update("max", UpdateOp::AddI64(1)),
+        Err(CacheError::Server(_))
+    ));
+    assert!(matches!(
+        c.update("log", UpdateOp::AddI64(1)),
+        Err(CacheError::Server(_))
+    ));
+    assert_eq!(c.get("log").unwrap(), b("xyz"));
+}
+
+#[test]
+fn aggregate() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+    for (key, value) in [("m:a", "7"), ("m:b", "-3"), ("m:c", "12"), ("other", "100")] {
+        c.set(key, value.as_bytes()).unwrap();
+    }
+    let agg = |op, skip| c.aggregate("m:", op, skip);
+    assert_eq!(agg(AggOp::Sum, false).unwrap(), Some(16));
+    assert_eq!(agg(AggOp::Min, false).unwrap(), Some(-3));
+    assert_eq!(agg(AggOp::Max, false).unwrap(), Some(12));
+    assert_eq!(agg(AggOp::Count, false).unwrap(), Some(3));
+    assert_eq!(c.aggregate("none:", AggOp::Min, false).unwrap(), None);
+    assert_eq!(c.aggregate("none:", AggOp::Sum, false).unwrap(), Some(0));
+
+    // нецелое: ошибка или пропуск по флагу
+    c.set("m:text", b"12abc").unwrap();
+    assert!(
+        matches!(agg(AggOp::Sum, false), Err(CacheError::Server(msg)) if msg.contains("not an i64"))
+    );
+    assert_eq!(agg(AggOp::Count, true).unwrap(), Some(3));
+
+    // переполнение суммы — ошибка, а не перенос
+    c.set("m:huge", i64::MAX.to_string().as_bytes()).unwrap();
+    assert!(
+        matches!(agg(AggOp::Sum, true), Err(CacheError::Server(msg)) if msg.contains("overflows"))
+    );
+    assert_eq!(agg(AggOp::Max, true).unwrap(), Some(i64::MAX));
+    assert_eq!(AggOp::parse("count").unwrap(), AggOp::Count);
+    assert!(AggOp::parse("avg").is_err());
+}
+
+#[test]
+fn bit_ops() {
+    let core = PersistentCore::ephemeral();
+    core.set_max_bit_offset(63);
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+
+    assert!(!c.setbit("flags", 10, true).unwrap());
+    assert!(c.setbit("flags", 10, true).unwrap());
+    assert_eq!(c.get("flags").unwrap(), Some(vec![0x00, 0x20]));
+    assert!(c.getbit("flags", 10).unwrap());
+    assert!(!c.getbit("flags", 11).unwrap());
+    assert!(!c.getbit("flags", 1000).unwrap());
+    c.setbit("flags", 63, true).unwrap();
+    assert_eq!(c.bitcount("flags", None).unwrap(), 2);
+    assert_eq!(c.bitcount("flags", Some((0, 1))).unwrap(), 1);
+    assert_eq!(c.bitcount("flags", Some((-1, -1))).unwrap(), 1);
+    assert_eq!(c.bitcount("missing", None).unwrap(), 0);
+    assert!(matches!(
+        c.setbit("flags", 64, true),
+        Err(CacheError::Server(_))
+    ));
+    assert_eq!(c.get("flags").unwrap().unwrap().len(), 8);
+}
+
+#[test]
+fn touch_and_idle_time() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+    c.set("a", b"1").unwrap();
+    c.set("b", b"2").unwrap();
+    assert_eq!(c.idle_time("a").unwrap(), Some(0));
+    assert_eq!(c.idle_time("missing").unwrap(), None);
+
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(c.idle_time("a").unwrap(), Some(1));
+    assert_eq!(c.touch(&["a", "missing", "a"]).unwrap(), 2);
+    assert_eq!(c.idle_time("a").unwrap(), Some(0));
+    // чтение тоже считается обращением
+    c.get("b").unwrap();
+    assert_eq!(c.idle_time("b").unwrap(), Some(0));
+}
+
+#[test]
+fn keys_sorted() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+    for k in ["k:b", "k:d", "k:a", "k:c", "other"] {
+        c.set(k, b"1").unwrap();
+    }
+    assert_eq!(
+        c.keys_sorted("k:", 0, 10).unwrap(),
+        ["k:a", "k:b", "k:c", "k:d"]
+    );
+    assert_eq!(c.keys_sorted("k:", 1, 2).unwrap(), ["k:b", "k:c"]);
+    assert_eq!(c.keys_sorted("k:", 3, 5).unwrap(), ["k:d"]);
+    assert!(c.keys_sorted("k:", 4, 1).unwrap().is_empty());
+}
+
+#[test]
+fn value_compression() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let zstd = Client::connect_with(
+        &addr,
+        ClientOptions {
+            compress: ValueEncoding::Zstd,
+            compress_min: Some(1024),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let lz4 = Client::connect_with(
+        &addr,
+        ClientOptions {
+            compress: ValueEncoding::Lz4,
+            compress_min: Some(1024),
+            multiplex: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let plain = Client::connect(&addr).unwrap();
+    let big = b"abcdefgh".repeat(1000);
+    let encoding = |key: &str| {
+        let fields = plain.key_type(key).unwrap();
+        let get = |name: &str| fields.iter().find(|(k, _)| k == name).unwrap().1.clone();
+        (get("encoding"), get("length"))
+    };
+
+    zstd.set("z", &big).unwrap();
+    lz4.set_ex("l", &big, Duration::from_secs(60)).unwrap();
+    for key in ["z", "l"] {
+        // каждый клиент читает исходные байты
+        for c in [&zstd, &lz4, &plain] {
+            assert_eq!(c.get(key).unwrap(), Some(big.clone()));
+        }
+    }
+    for (key, name) in [("z", "zstd"), ("l", "lz4")] {
+        assert!(
+            matches!(encoding(key), (e, ResponseValue::Int(n)) if e == name.into() && n < 1000)
+        );
+    }
+    // короткое и несжимаемое хранятся как есть
+    zstd.set("short", b"abc").unwrap();
+    // xorshift: сжатие такие байты не укорачивает
+    let mut x = 0x9E37_79B9_7F4A_7C15u64;
+    let noise: Vec<u8> = (0..4096)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        })
+        .collect();
+    zstd.set("noise", &noise).unwrap();
+    assert_eq!(encoding("short").0, "raw".into());
+    assert_eq!(encoding("noise").0, "raw".into());
+
+    // протокол 1 получает значение распакованным, протокол 5 — как хранится
+    let mut v1 = TcpStream::connect(&addr).unwrap();
+    assert!(matches!(
+        request(&mut v1, &CacheCommand::Get("z".into())),
+        CacheResponse::Value(v) if v == big
+    ));
+    let mut v5 = TcpStream::connect(&addr).unwrap();
+    request(&mut v5, &CacheCommand::Hello(PROTOCOL_VERSION));
+    let (_, resp) = request_tagged(&mut v5, 1, &CacheCommand::Get("z".into()));
+    let CacheResponse::Value(stored) = resp else {
+        panic!("{:?}", resp)
+    };
+    assert_eq!(ValueEncoding::of(&stored), ValueEncoding::Zstd);
+    assert_eq!(decode_value(stored).unwrap(), big);
+
+    // сырые байты, похожие на заголовок, не распаковываются
+    let mut tricky = b"\x00TMCENC\x01".to_vec();
+    tricky.extend(&big[..10]);
+    zstd.set("tricky", &tricky).unwrap();
+    assert_eq!(plain.get("tricky").unwrap(), Some(tricky.clone()));
+    // и у клиента старше 5, который о заголовках не знает: протокол 1 и 4
+    let legacy = [
+        b"\x00TMCENC\x00abc".to_vec(),
+        b"\x00TMCENC\x01junk".to_vec(),
+    ];
+    let mut v4 = TcpStream::connect(&addr).unwrap();
+    request(&mut v4, &CacheCommand::Hello(4));
+    for (i, value) in legacy.iter().enumerate() {
+        let set = CacheCommand::Set(format!("legacy:{}", i), value.clone());
+        assert!(matches!(request(&mut v1, &set), CacheResponse::Ok));
+        let get = CacheCommand::Get(format!("legacy:{}", i));
+        assert!(matches!(request(&mut v1, &get), CacheResponse::Value(v) if v == *value));
+        assert!(matches!(
+            request_tagged(&mut v4, 2, &get).1,
+            CacheResponse::Value(v) if v == *value
+        ));
+    }
+    let mset = CacheCommand::MSet(vec![("legacy:2".into(), legacy[1].clone())]);
+    assert!(matches!(
+        request_tagged(&mut v4, 3, &mset).1,
+        CacheResponse::Ok
+    ));
+    let keys = ["legacy:0", "legacy:1", "legacy:2"];
+    let mget = CacheCommand::MGetConsistent(keys.map(String::from).to_vec());
+    let want = vec![
+        Some(legacy[0].clone()),
+        Some(legacy[1].clone()),
+        Some(legacy[1].clone()),
+    ];
+    assert!(matches!(request(&mut v1, &mget), CacheResponse::Values(v) if v == want));
+    assert_eq!(plain.get_consistent(&keys).unwrap(), want);
+    assert_eq!(encode_value(b"abc", ValueEncoding::Raw), None);
+    let packed = encode_value(&big, ValueEncoding::Lz4).unwrap();
+    assert_eq!(decode_value(packed).unwrap(), big);
+
+    // сравнение — с исходными байтами; на месте сжатое не меняется
+    assert!(zstd.extend_if("z", &big, Duration::from_secs(60)).unwrap());
+    assert!(matches!(
+        plain.setbit("z", 0, true),
+        Err(CacheError::Server(msg)) if msg.contains("zstd-compressed")
+    ));
+    // чтение битов видит исходные байты
+    assert_eq!(
+        plain.bitcount("z", None).unwrap(),
+        big.iter().map(|b| b.count_ones() as i64).sum::<i64>()
+    );
+    assert!(plain
+        .update("z", UpdateOp::AppendBytes(b"!".to_vec()))
+        .is_err());
+    assert!(plain.delete_if("z", &big).unwrap());
+    assert_eq!(plain.get("z").unwrap(), None);
+}
+
+#[test]
+fn key_type() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+    c.set("a", b"abc").unwrap();
+    let fields = |key: &str| c.key_type(key).unwrap();
+    let pair = |k: &str, v: ResponseValue| (k.to_string(), v);
+    assert_eq!(
+        fields("a"),
+        vec![
+            pair("type", "bytes".into()),
+            pair("encoding", "raw".into()),
+            pair("length", ResponseValue::Int(3))
+        ]
+    );
+    assert_eq!(fields("missing"), vec![pair("type", "none".into())]);
+}
+
+#[test]
+fn expiry() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+
+    c.set_ex("short", b"1", Duration::from_millis(300)).unwrap();
+    let left = c.pttl("short").unwrap();
+    assert!(left > 0 && left <= 300, "{}", left);
+    assert_eq!(c.get("short").unwrap(), Some(b"1".to_vec()));
+    // срок переживает изменение значения
+    c.update("short", UpdateOp::AppendBytes(b"2".to_vec()))
+        .unwrap();
+    assert!(c.pttl("short").unwrap() > 0);
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(c.get("short").unwrap(), None);
+    assert!(!c.exists("short").unwrap());
+    assert_eq!(c.pttl("short").unwrap(), -2);
+    assert!(c.keys("short*").unwrap().is_empty());
+    // истёкший ключ — как отсутствующий
+    assert!(c.get_or_set("short", b"3").unwrap().1);
+    assert_eq!(c.pttl("short").unwrap(), -1);
+
+    c.set("plain", b"v").unwrap();
+    assert_eq!(c.pttl("plain").unwrap(), -1);
+    assert!(c.expire("plain", Duration::from_secs(10)).unwrap());
+    assert!(c.pttl("plain").unwrap() > 9_000);
+    // Set сбрасывает срок
+    c.set("plain", b"v2").unwrap();
+    assert_eq!(c.pttl("plain").unwrap(), -1);
+    assert!(!c.expire("missing", Duration::from_secs(1)).unwrap());
+
+    let at = SystemTime::now() + Duration::from_secs(60);
+    assert!(c.expire_at("plain", at).unwrap());
+    let left = c.pttl("plain").unwrap();
+    assert!(left > 58_000 && left <= 60_000, "{}", left);
+    // прошедший момент удаляет ключ сразу
+    assert!(c
+        .expire_at("plain", SystemTime::now() - Duration::from_secs(1))
+        .unwrap());
+    assert_eq!(c.get("plain").unwrap(), None);
+    assert!(!c.expire_at("plain", at).unwrap());
+
+    assert!(matches!(
+        c.set_ex("zero", b"v", Duration::ZERO),
+        Err(CacheError::Server(_))
+    ));
+}
+
+#[test]
+fn expiry_survives_restart() {
+    let dir = std::env::temp_dir().join(format!("tmc-expiry-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+
+    let core = open();
+    core.set_ex("soon".into(), b"1".to_vec(), 200).unwrap();
+    core.set_ex("later".into(), b"2".to_vec(), 60_000).unwrap();
+    core.set("moved".into(), b"3".to_vec()).unwrap();
+    assert!(core.expire("moved", 60_000).unwrap());
+    core.compact().unwrap();
+    core.set("plain".into(), b"4".to_vec()).unwrap();
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    assert!(core.expire_at("plain", now_ms + 60_000).unwrap());
+    drop(core);
+    thread::sleep(Duration::from_millis(300));
+
+    // сроки из снапшота и из хвоста WAL
+    let core = open();
+    assert_eq!(core.get("soon"), None);
+    assert_eq!(core.len(), 3);
+    for key in ["later", "moved", "plain"] {
+        let left = core.pttl(key);
+        assert!(left > 50_000 && left <= 60_000, "{}: {}", key, left);
+    }
+    drop(core);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn tags() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+
+    c.set_with_tags("user:1", b"a", &["users", "team:x"])
+        .unwrap();
+    c.set_with_tags("user:2", b"b", &["users", "users"])
+        .unwrap();
+    c.set_with_tags("user:3", b"c", &["users"]).unwrap();
+    c.set("other", b"d").unwrap();
+    // удалённый, перезаписанный без тегов и истёкший ключи из тега выпадают
+    c.delete("user:2").unwrap();
+    c.set("user:3", b"c2").unwrap();
+    c.set_with_tags("short", b"e", &["users"]).unwrap();
+    assert!(c.expire("short", Duration::from_millis(50)).unwrap());
+    thread::sleep(Duration::from_millis(100));
+    // Update сохраняет теги
+    c.update("user:1", UpdateOp::AppendBytes(b"!".to_vec()))
+        .unwrap();
+    assert_eq!(c.delete_by_tag("users").unwrap(), 1);
+    assert_eq!(c.get("user:1").unwrap(), None);
+    assert_eq!(c.get("user:3").unwrap(), Some(b"c2".to_vec()));
+    assert_eq!(c.delete_by_tag("team:x").unwrap(), 0);
+    assert_eq!(c.delete_by_tag("missing").unwrap(), 0);
+    let mut keys = c.keys("*").unwrap();
+    keys.sort();
+    assert_eq!(keys, ["other", "user:3"]);
+}
+
+#[test]
+fn tags_survive_restart() {
+    let dir = std::env::temp_dir().join(format!("tmc-tags-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+
+    let core = open();
+    core.set_tagged("snap".into(), b"1".to_vec(), vec!["t".into()])
+        .unwrap();
+    core.compact().unwrap();
+    core.set_tagged("wal".into(), b"2".to_vec(), vec!["t".into(), "u".into()])
+        .unwrap();
+    core.set_tagged("gone".into(), b"3".to_vec(), vec!["u".into()])
+        .unwrap();
+    core.set("gone".into(), b"4".to_vec()).unwrap();
+    drop(core);
+
+    // теги из снапшота и из хвоста WAL
+    let core = open();
+    assert_eq!(core.delete_by_tag("u").unwrap(), 1);
+    assert_eq!(core.get("gone"), Some(b"4".to_vec()));
+    core.compact().unwrap();
+    drop(core);
+    let core = open();
+    // удаление по тегу тоже пережило рестарт
+    assert_eq!(core.get("wal"), None);
+    assert_eq!(core.delete_by_tag("t").unwrap(), 1);
+    assert_eq!(core.len(), 1);
+    drop(core);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+fn info_field(info: Vec<(String, ResponseValue)>, name: &str) -> ResponseValue {
+    info.into_iter().find(|(k, _)| k == name).unwrap().1
+}
+
+#[test]
+fn tombstones() {
+    let core = PersistentCore::ephemeral();
+    core.set_tombstone_ttl(Some(Duration::from_millis(500)));
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+
+    c.set_with_tags("a", b"1", &["t"]).unwrap();
+    c.set_ex("b", b"2", Duration::from_secs(60)).unwrap();
+    c.set("dir:1", b"3").unwrap();
+    c.set("dir:2", b"4").unwrap();
+    assert_eq!(c.delete("a").unwrap(), 1);
+    assert_eq!(c.pop("b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(c.delete_prefix("dir:").unwrap(), 2);
+    // надгробий не видно ни get, ни keys, ни len
+    assert_eq!(c.get("a").unwrap(), None);
+    assert!(c.keys("*").unwrap().is_empty());
+    assert_eq!(c.len().unwrap(), 0);
+    assert_eq!(
+        info_field(c.info().unwrap(), "tombstones"),
+        ResponseValue::Int(4)
+    );
+
+    // ключ возвращается со сроком и тегами
+    assert!(c.undelete("a").unwrap());
+    assert!(c.undelete("b").unwrap());
+    assert_eq!(c.get("a").unwrap(), Some(b"1".to_vec()));
+    assert!(c.pttl("b").unwrap() > 50_000);
+    assert!(!c.undelete("a").unwrap());
+    assert!(!c.undelete("missing").unwrap());
+    // записанный заново ключ надгробием не перетирается
+    c.set("dir:1", b"new").unwrap();
+    assert!(!c.undelete("dir:1").unwrap());
+    assert_eq!(c.delete_by_tag("t").unwrap(), 1);
+    assert!(c.undelete("a").unwrap());
+
+    thread::sleep(Duration::from_millis(700));
+    assert!(!c.undelete("dir:2").unwrap());
+    assert_eq!(
+        info_field(c.info().unwrap(), "tombstones"),
+        ResponseValue::Int(0)
+    );
+}
+
+#[test]
+fn tombstones_survive_restart() {
+    let dir = std::env::temp_dir().join(format!("tmc-tombstones-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        let core = PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap();
+        core.set_tombstone_ttl(Some(Duration::from_secs(60)));
+        core
+    };
+
+    let core = open();
+    for key in ["snap", "wal", "back", "again"] {
+        core.set(key.into(), key.as_bytes().to_vec()).unwrap();
+    }
+    core.delete("snap").unwrap();
+    core.delete("again").unwrap();
+    core.compact().unwrap();
+    core.delete("wal").unwrap();
+    core.delete("back").unwrap();
+    // надгробия из снапшота и из WAL, возвращённые после компакции
+    assert!(core.undelete("back").unwrap());
+    assert!(core.undelete("again").unwrap());
+    core.set("again".into(), b"over".to_vec()).unwrap();
+    drop(core);
+
+    let core = open();
+    assert_eq!(core.len(), 2);
+    assert_eq!(core.get("back"), Some(b"back".to_vec()));
+    assert_eq!(core.get("again"), Some(b"over".to_vec()));
+    assert_eq!(
+        info_field(core.info().unwrap(), "tombstones"),
+        ResponseValue::Int(2)
+    );
+    assert!(!core.undelete("again").unwrap());
+    assert!(core.undelete("snap").unwrap());
+    assert!(core.undelete("wal").unwrap());
+    drop(core);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn preload_survives_restart() {
+    let dir = std::env::temp_dir().join(format!("tmc-preload-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        let opts = PersistOptions {
+            compact_after: Some(3000),
+            segment_size: Some(64 << 10),
+            ..PersistOptions::default()
+        };
+        PersistentCore::new(dir.join("cache.wal"), dir.join("cache.snapshot"), opts).unwrap()
+    };
+
+    let core = open();
+    core.set("before".into(), b"1".to_vec()).unwrap();
+    // компакция и ротация сегментов посреди прогрева видят буфер bulk
+    let n = core
+        .preload(|| {
+            for i in 0..5000 {
+                core.set(format!("warm:{}", i), vec![b'x'; 100]).unwrap();
+            }
+            core.delete("before").unwrap()
+        })
+        .unwrap();
+    assert_eq!(n, 1);
+    core.set("after".into(), b"2".to_vec()).unwrap();
+    drop(core);
+
+    let core = open();
+    assert_eq!(core.len(), 5001);
+    assert_eq!(core.get("warm:4999"), Some(vec![b'x'; 100]));
+    assert_eq!(core.get("before"), None);
+    assert_eq!(core.get("after"), Some(b"2".to_vec()));
+    core.set_read_only(true).unwrap();
+    assert!(matches!(
+        core.preload(|| ()),
+        Err(CacheError::Unsupported(_))
+    ));
+    drop(core);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn key_normalization() {
+    let nfc = "r\u{e9}sum\u{e9}";
+    let nfd = "re\u{301}sume\u{301}";
+    let dir = std::env::temp_dir().join(format!("tmc-keynorm-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = |dir: &Path| {
+        fs::create_dir_all(dir).unwrap();
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+
+    // записано без политики: два разных ключа
+    let core = open(&dir);
+    core.set(nfd.into(), b"old".to_vec()).unwrap();
+    core.set(nfc.into(), b"old".to_vec()).unwrap();
+    assert_eq!(core.len(), 2);
+    core.export("keys.dump", DumpFormat::Binary, Deadline::NONE)
+        .unwrap();
+    drop(core);
+
+    // восстановленные из WAL ключи не подходят политике: открытие отказывает
+    let mut core = open(&dir);
+    match core.set_key_normalization(Some(KeyNormalization::Nfc)) {
+        Err(CacheError::InvalidKey(msg)) => {
+            assert!(msg.contains("1 stored keys"), "{}", msg)
+        }
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+    core.set_key_normalization(Some(KeyNormalization::AsIs))
+        .unwrap();
+    drop(core);
+
+    // путь в новом каталоге: дамп импортируется с политикой, ключи сливаются
+    let mut core = open(&dir.join("fresh"));
+    core.set_key_normalization(Some(KeyNormalization::Nfc))
+        .unwrap();
+    assert_eq!(core.import("../keys.dump", true).unwrap(), 2);
+    assert_eq!(core.len(), 1);
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    c.set(nfd, b"v").unwrap();
+    assert_eq!(c.get(nfc).unwrap(), Some(b"v".to_vec()));
+    assert_eq!(c.keys("re\u{301}*").unwrap(), vec![nfc.to_string()]);
+    assert_eq!(c.len().unwrap(), 1);
+    match c.set("bad\u{0}key", b"v") {
+        Err(CacheError::InvalidKey(msg)) => assert!(msg.contains("control character"), "{}", msg),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(c.get("tab\tkey"), Err(CacheError::InvalidKey(_))));
+    assert_eq!(
+        info_field(c.info().unwrap(), "key_normalization"),
+        ResponseValue::Str("nfc".into())
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn dedup() {
+    let config = vec![7u8; 1000];
+    let core = PersistentCore::ephemeral();
+    // уже записанные значения объединяются при включении
+    for i in 0..2 {
+        core.set(format!("task:{}", i), config.clone()).unwrap();
+    }
+    core.set_dedup_min_size(Some(64));
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    let stats = |c: &Client| {
+        let info = c.info().unwrap();
+        [
+            "dedup_values",
+            "dedup_refs",
+            "dedup_logical_bytes",
+            "dedup_physical_bytes",
+        ]
+        .map(|name| info_field(info.clone(), name))
+        .map(|v| match v {
+            ResponseValue::Int(n) => n,
+            other => panic!("{:?}", other),
+        })
+    };
+
+    for i in 2..10 {
+        c.set(&format!("task:{}", i), &config).unwrap();
+    }
+    c.set("small", b"short").unwrap();
+    assert_eq!(stats(&c), [1, 10, 10_000, 1000]);
+    assert_eq!(c.get("task:3").unwrap(), Some(config.clone()));
+
+    // изменение одной копии не задевает остальные
+    c.update("task:0", UpdateOp::AppendBytes(b"!".to_vec()))
+        .unwrap();
+    c.setbit("task:1", 0, true).unwrap();
+    assert_eq!(c.get("task:2").unwrap(), Some(config.clone()));
+    assert_eq!(c.get("task:1").unwrap().unwrap()[0], 0x87);
+    assert_eq!(stats(&c), [2, 9, 9001, 2001]);
+
+    c.pop("task:3").unwrap();
+    c.delete_prefix("task:").unwrap();
+    assert_eq!(stats(&c), [0, 0, 0, 0]);
+}
+
+#[test]
+fn small_values() {
+    let dir = std::env::temp_dir().join(format!("tmc-small-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        let core = PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap();
+        core.set_dedup_min_size(Some(1));
+        core
+    };
+    let id = vec![b'i'; 30];
+    let wide = vec![b'w'; 31];
+
+    let core = open();
+    for key in ["id:1", "id:2"] {
+        core.set(key.into(), id.clone()).unwrap();
+    }
+    for key in ["wide:1", "wide:2"] {
+        core.set(key.into(), wide.clone()).unwrap();
+    }
+    core.set("flag".into(), b"1".to_vec()).unwrap();
+    core.set("empty".into(), Vec::new()).unwrap();
+    // маленькие значения не делятся даже при пороге в байт
+    assert_eq!(
+        info_field(core.info().unwrap(), "dedup_refs"),
+        ResponseValue::Int(2)
+    );
+    assert_eq!(core.get("id:1"), Some(id.clone()));
+    assert_eq!(core.get("empty"), Some(Vec::new()));
+
+    // рост за порог и обратно
+    let grown = [id.clone(), b"!".to_vec()].concat();
+    core.update("id:1".into(), &UpdateOp::AppendBytes(b"!".to_vec()))
+        .unwrap();
+    assert_eq!(core.get("id:1"), Some(grown.clone()));
+    assert!(!core.set_bit("flag".into(), 300, true).unwrap());
+    assert_eq!(core.get("flag").unwrap().len(), 38);
+    core.set_bit("id:2".into(), 0, true).unwrap();
+    assert!(core.get_bit("id:2", 0));
+    core.set("wide:1".into(), b"short".to_vec()).unwrap();
+    assert_eq!(core.get("wide:2"), Some(wide.clone()));
+    drop(core);
+
+    // WAL и снапшот видят обычные значения
+    let core = open();
+    assert_eq!(core.get("id:1"), Some(grown.clone()));
+    assert_eq!(core.get("wide:1"), Some(b"short".to_vec()));
+    assert_eq!(core.get("flag").unwrap().len(), 38);
+    core.compact().unwrap();
+    drop(core);
+    let core = open();
+    assert_eq!(core.get("id:1"), Some(grown));
+    assert_eq!(core.get("id:2").unwrap()[0], b'i' | 0x80);
+    assert_eq!(core.get("empty"), Some(Vec::new()));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn history() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+    let values = |key: &str| -> Vec<Vec<u8>> {
+        c.history(key)
+            .unwrap()
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect()
+    };
+
+    c.set_history_depth("cfg:", 2).unwrap();
+    c.set_history_depth("cfg:big:", 5).unwrap();
+    let before = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    for v in [b"v1", b"v2", b"v3"] {
+        c.set("cfg:a", v).unwrap();
+    }
+    c.update("cfg:a", UpdateOp::AppendBytes(b"!".to_vec()))
+        .unwrap();
+    assert_eq!(values("cfg:a"), [b"v2".to_vec(), b"v3".to_vec()]);
+    let history = c.history("cfg:a").unwrap();
+    assert!(history[0].0 >= before && history[0].0 <= history[1].0);
+    // удалённое значение тоже прежнее
+    c.delete("cfg:a").unwrap();
+    assert_eq!(values("cfg:a"), [b"v3".to_vec(), b"v3!".to_vec()]);
+
+    for i in 0..4u8 {
+        c.set("cfg:big:1", &[i]).unwrap();
+        c.set("other", &[i]).unwrap();
+    }
+    assert_eq!(values("cfg:big:1").len(), 3);
+    assert!(values("other").is_empty());
+    assert!(values("missing").is_empty());
+    assert_eq!(
+        info_field(c.info().unwrap(), "history_versions"),
+        ResponseValue::Int(5)
+    );
+
+    // новая глубина укорачивает накопленное
+    c.set_history_depth("cfg:big:", 1).unwrap();
+    assert_eq!(values("cfg:big:1").len(), 1);
+    c.set_history_depth("cfg:big:", 0).unwrap();
+    assert!(values("cfg:big:1").is_empty());
+    c.set("cfg:big:1", b"x").unwrap();
+    assert!(values("cfg:big:1").is_empty());
+    assert_eq!(values("cfg:a").len(), 2);
+    assert!(matches!(
+        c.set_history_depth("", 1_000_000),
+        Err(CacheError::Server(_))
+    ));
+}
+
+#[test]
+fn export_bloom() {
+    let core = PersistentCore::ephemeral();
+    for i in 0..20_000 {
+        core.set(format!("user:{}", i), Vec::new()).unwrap();
+    }
+    core.set("other:1".into(), Vec::new()).unwrap();
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+
+    // ложных «нет» не бывает, а доля ложных «есть» близка к расчётной
+    // (1 - e^(-k/b))^k; 20000 проб дают её с запасом в разы
+    for (bits_per_key, expected) in [(4, 0.147), (10, 0.0082)] {
+        let filter = c.export_bloom("user:", bits_per_key).unwrap();
+        assert!((0..20_000).all(|i| filter.maybe_contains(&format!("user:{}", i))));
+        let false_hits = (0..20_000)
+            .filter(|i| filter.maybe_contains(&format!("absent:{}", i)))
+            .count();
+        let rate = false_hits as f64 / 20_000.0;
+        assert!(
+            rate > expected / 2.0 && rate < expected * 2.0,
+            "bits_per_key {}: rate {}",
+            bits_per_key,
+            rate
+        );
+    }
+    let filter = c.export_bloom("none:", 10).unwrap();
+    assert!(!filter.maybe_contains("user:1"));
+    assert!(c.export_bloom("", 0).is_err());
+}
+
+#[test]
+fn rate_limits() {
+    let limited = |res: Result<Option<Vec<u8>>, CacheError>| match res {
+        Ok(_) => false,
+        Err(CacheError::Server(msg)) => {
+            assert!(msg.starts_with("rate limited, retry after "), "{}", msg);
+            true
+        }
+        Err(e) => panic!("{:?}", e),
+    };
+
+    let core = PersistentCore::ephemeral();
+    core.set_rate_limits(Some(20), None);
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    let passed = (0..40).filter(|_| !limited(c.get("k"))).count();
+    assert!((20..25).contains(&passed), "{}", passed);
+    // лимит соединения не трогает другое соединение
+    let other = Client::connect(&addr).unwrap();
+    assert!(!limited(other.get("k")));
+    thread::sleep(Duration::from_millis(300));
+    assert!(!limited(c.get("k")));
+    assert!(
+        info_field(other.info().unwrap(), "rate_limited_commands")
+            .as_int()
+            .unwrap()
+            >= 15
+    );
+
+    // лимит IP общий для всех соединений с него
+    let core = PersistentCore::ephemeral();
+    core.set_rate_limits(None, Some(10));
+    let addr = start_server(core).to_string();
+    let (a, b) = (
+        Client::connect(&addr).unwrap(),
+        Client::connect(&addr).unwrap(),
+    );
+    for _ in 0..6 {
+        assert!(!limited(a.get("k")));
+    }
+    let passed = (0..10).filter(|_| !limited(b.get("k"))).count();
+    assert!((4..7).contains(&passed), "{}", passed);
+}
+
+#[test]
+fn runtime_config() {
+    let mut core = PersistentCore::ephemeral();
+    core.set_admin_token("secret".into());
+    let addr = start_server(core).to_string();
+    let admin = ClientOptions {
+        admin_token: Some("secret".into()),
+        ..Default::default()
+    };
+    let admin = Client::connect_with(&addr, admin).unwrap();
+    let c = Client::connect(&addr).unwrap();
+    assert!(matches!(
+        c.config_set("rate_limit_per_conn", "5"),
+        Err(CacheError::Server(_))
+    ));
+    assert!(matches!(c.config_get("*"), Err(CacheError::Server(_))));
+
+    let all = admin.config_get("*").unwrap();
+    let names: Vec<&str> = all.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "max_bit_offset",
+            "idle_timeout_secs",
+            "tombstone_ttl_secs",
+            "dedup_min_size",
+            "rate_limit_per_conn",
+            "rate_limit_per_ip",
+            "verify_values",
+            "max_response_size",
+            "slowlog_ms"
+        ]
+    );
+    assert_eq!(info_field(all, "rate_limit_per_conn").as_int(), Some(0));
+
+    // лимит включается на уже открытом соединении
+    assert!((0..30).all(|_| c.get("k").is_ok()));
+    admin.config_set("rate_limit_per_conn", "10").unwrap();
+    let passed = (0..30).filter(|_| c.get("k").is_ok()).count();
+    assert!((10..15).contains(&passed), "{}", passed);
+    admin.config_set("rate_limit_per_conn", "none").unwrap();
+    assert!((0..30).all(|_| c.get("k").is_ok()));
+
+    admin.config_set("max_bit_offset", "100").unwrap();
+    assert!(c.setbit("b", 100, true).is_ok());
+    assert!(c.setbit("b", 101, true).is_err());
+
+    admin.config_set("tombstone_ttl_secs", "60").unwrap();
+    c.set("t", b"v").unwrap();
+    c.delete("t").unwrap();
+    assert!(c.undelete("t").unwrap());
+
+    admin.config_set("verify_values", "true").unwrap();
+    let got = admin.config_get("verify_values").unwrap();
+    assert_eq!(got, [("verify_values".to_string(), ResponseValue::Int(1))]);
+    assert_eq!(
+        info_field(c.info().unwrap(), "verify_values").as_int(),
+        Some(1)
+    );
+
+    let unsupported = |res: Result<(), CacheError>| match res {
+        Err(CacheError::Server(msg)) => msg,
+        other => panic!("{:?}", other),
+    };
+    for name in ["wal_dir", "port", "fsync"] {
+        let msg = unsupported(admin.config_set(name, "x"));
+        assert!(msg.contains("cannot change at runtime"), "{}", msg);
+        assert!(msg.contains(name), "{}", msg);
+    }
+    let msg = unsupported(admin.config_set("read_only", "true"));
+    assert!(msg.contains("SetReadOnly"), "{}", msg);
+    let msg = unsupported(admin.config_set("max_value_size", "1"));
+    assert!(msg.contains("unknown setting"), "{}", msg);
+    assert!(msg.contains("rate_limit_per_ip"), "{}", msg);
+    let msg = unsupported(admin.config_set("rate_limit_per_ip", "-1"));
+    assert!(msg.contains("rate_limit_per_ip expects"), "{}", msg);
+    assert!(admin.config_get("wal_dir").is_err());
+}
+
+#[test]
+fn config_dump() {
+    let dir = std::env::temp_dir().join(format!("tmc-config-dump-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let opts = PersistOptions {
+        fsync: FsyncPolicy::Always,
+        segment_size: Some(1 << 20),
+        ..PersistOptions::default()
+    };
+    let mut core =
+        PersistentCore::new(dir.join("cache.wal"), dir.join("cache.snapshot"), opts).unwrap();
+    core.set_admin_token("secret".into());
+    core.set_hit_stats_prefix(1, ":", 16).unwrap();
+    let addr = start_server(core).to_string();
+    let admin = ClientOptions {
+        admin_token: Some("secret".into()),
+        ..Default::default()
+    };
+    let admin = Client::connect_with(&addr, admin).unwrap();
+    let c = Client::connect(&addr).unwrap();
+    assert!(matches!(c.config_dump(), Err(CacheError::Server(_))));
+
+    admin.config_set("slowlog_ms", "25").unwrap();
+    let dump = admin.config_dump().unwrap();
+    let field = |name: &str| info_field(dump.clone(), name);
+    assert_eq!(field("version").as_str(), Some(env!("CARGO_PKG_VERSION")));
+    assert_eq!(field("max_command_size").as_int(), Some(1_000_000));
+    // настройки на ходу — те же, что у ConfigGet("*")
+    for (name, value) in admin.config_get("*").unwrap() {
+        assert_eq!(field(&name), value, "{}", name);
+    }
+    assert_eq!(field("slowlog_ms").as_int(), Some(25));
+    assert_eq!(field("persistence").as_str(), Some("wal"));
+    assert_eq!(field("fsync").as_str(), Some("always"));
+    assert_eq!(field("wal_segment_size").as_int(), Some(1 << 20));
+    assert_eq!(field("stats_prefix_depth").as_int(), Some(1));
+    // секреты не выдаются
+    assert_eq!(field("admin_token").as_str(), Some("set"));
+    assert_eq!(field("wal_key").as_str(), Some("unset"));
+    assert!(!dump.iter().any(|(_, v)| v.as_str() == Some("secret")));
+    let wal_path = field("wal_path");
+    assert!(
+        wal_path.as_str().unwrap().ends_with("cache.wal"),
+        "{:?}",
+        wal_path
+    );
+    drop((admin, c));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn sample() {
+    let core = PersistentCore::ephemeral();
+    assert!(core.sample(10).is_empty());
+    // четверть ключей на "a:", с размером значения по номеру
+    for i in 0..20_000 {
+        let key = format!("{}:{}", if i % 4 == 0 { "a" } else { "b" }, i);
+        core.set(key, vec![0; i % 50]).unwrap();
+    }
+    core.set_ex("ttl".into(), b"v".to_vec(), 60_000).unwrap();
+    core.set_ex("gone".into(), b"v".to_vec(), 1).unwrap();
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    thread::sleep(Duration::from_millis(5));
+
+    let (mut a, mut total) = (0, 0);
+    for _ in 0..20 {
+        let keys = c.sample(100).unwrap();
+        assert_eq!(keys.len(), 100);
+        let mut names: Vec<&str> = keys.iter().map(|k| k.key.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), 100, "keys repeat within a sample");
+        for k in &keys {
+            assert_ne!(k.key, "gone");
+            if k.key == "ttl" {
+                assert!(k.ttl_ms.is_some_and(|t| t <= 60_000));
+                continue;
+            }
+            let i: u64 = k.key[2..].parse().unwrap();
+            assert_eq!((k.size, k.ttl_ms), (i % 50, None), "{:?}", k);
+            assert!(k.age_ms.is_some());
+            a += k.key.starts_with("a:") as u32;
+            total += 1;
+        }
+    }
+    // доля "a:" — 0.25; 2000 выборок дают стандартное отклонение около 0.01
+    let share = a as f64 / total as f64;
+    assert!((0.2..0.3).contains(&share), "{}", share);
+    assert_eq!(c.sample(1_000_000).unwrap().len(), 10_000);
+    assert!(c.sample(0).unwrap().is_empty());
+
+    // карта почти пуста после удалений, бакеты таблиц редко заняты
+    c.delete_prefix("b:").unwrap();
+    for i in (0..20_000).step_by(4).skip(50) {
+        c.delete(&format!("a:{}", i)).unwrap();
+    }
+    let keys = c.sample(20).unwrap();
+    assert_eq!(keys.len(), 20);
+    assert!(keys
+        .iter()
+        .all(|k| k.key == "ttl" || k.key["a:".len()..].parse::<u32>().unwrap() < 200));
+    // ключей меньше, чем просят: все по разу
+    let mut all: Vec<String> = c.sample(100).unwrap().into_iter().map(|k| k.key).collect();
+    all.sort_unstable();
+    assert_eq!(all.len(), 51);
+    all.dedup();
+    assert_eq!(all.len(), 51);
+}
+
+#[test]
+fn prefix_stats() {
+    let core = PersistentCore::ephemeral();
+    for i in 0..30 {
+        core.set(format!("img:{}", i), vec![0; 1000]).unwrap();
+    }
+    for i in 0..10 {
+        core.set(format!("user:{}:name", i), b"bob".to_vec())
+            .unwrap();
+    }
+    core.set("flat".into(), b"x".to_vec()).unwrap();
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+
+    let counts = |keys: i64, bytes: i64| {
+        ResponseValue::Nested(vec![
+            ("keys".into(), ResponseValue::Int(keys)),
+            ("bytes".into(), ResponseValue::Int(bytes)),
+        ])
+    };
+    assert_eq!(
+        c.prefix_stats(":", 1).unwrap(),
+        vec![
+            (
+                "groups".to_string(),
+                ResponseValue::Nested(vec![
+                    ("img:".into(), counts(30, 30_000)),
+                    ("user:".into(), counts(10, 30)),
+                    ("".into(), counts(1, 1)),
+                ])
+            ),
+            ("truncated".to_string(), ResponseValue::Int(0)),
+        ]
+    );
+    let deep = c.prefix_stats(":", 2).unwrap();
+    let Some((_, ResponseValue::Nested(groups))) = deep.iter().find(|(k, _)| k == "groups") else {
+        panic!("{:?}", deep);
+    };
+    // у ключей img: второго разделителя нет — группа та же
+    assert_eq!(groups.len(), 12);
+    assert!(groups.contains(&("img:".into(), counts(30, 30_000))));
+    assert!(groups.contains(&("user:7:".into(), counts(1, 3))));
+    assert!(matches!(
+        c.prefix_stats(":", 0),
+        Err(CacheError::Server(msg)) if msg.contains("depth")
+    ));
+}
+
+#[test]
+fn prefix_scans_under_heavy_writes() {
+    const USERS: usize = 20_000;
+    const BIG: usize = 900_000;
+    let core = PersistentCore::ephemeral();
+    for i in 0..USERS {
+        core.set(format!("user:{}", i), b"v".to_vec()).unwrap();
+    }
+    let addr = start_server(core).to_string();
+    let until = Instant::now() + Duration::from_millis(1500);
+
+    // писатели перезаписывают большие значения, ключи user: только меняют значение
+    let writers: Vec<_> = (0..4)
+        .map(|w| {
+            let c = Client::connect(&addr).unwrap();
+            thread::spawn(move || {
+                let mut n = 0;
+                while Instant::now() < until {
+                    c.set(&format!("big:{}", n % 16), &vec![w as u8; BIG])
+                        .unwrap();
+                    c.set(&format!("user:{}", n * 7 % USERS), b"w").unwrap();
+                    n += 1;
+                }
+                n
+            })
+        })
+        .collect();
+    let scanners: Vec<_> = (0..4)
+        .map(|_| {
+            let c = Client::connect(&addr).unwrap();
+            thread::spawn(move || {
+                let mut slowest = Duration::ZERO;
+                let mut scans = 0;
+                while Instant::now() < until {
+                    let t = Instant::now();
+                    // ни один ключ user: не удаляется, поэтому все видны каждому обходу
+                    assert_eq!(c.keys("user:*").unwrap().len(), USERS);
+                    let (_, page) = c.scan_items(0, "user:", 500).unwrap();
+                    assert_eq!(page.len(), 500);
+                    assert!(page
+                        .iter()
+                        .all(|(k, v)| k.starts_with("user:") && v.len() == 1));
+                    let (_, big) = c.scan_sizes(0, "big:", 100, BIG as u64..).unwrap();
+                    assert!(big.iter().all(|(_, len)| *len == BIG as u64));
+                    c.prefix_stats(":", 1).unwrap();
+                    slowest = slowest.max(t.elapsed());
+                    scans += 1;
+                }
+                (scans, slowest)
+            })
+        })
+        .collect();
+    for w in writers {
+        assert!(w.join().unwrap() > 0);
+    }
+    for s in scanners {
+        let (scans, slowest) = s.join().unwrap();
+        assert!(scans > 0);
+        assert!(slowest < Duration::from_secs(2), "scan took {:?}", slowest);
+    }
+}
+
+#[test]
+fn response_size_limit() {
+    // предел — ровно ответ Get на значение в 1000 байт
+    let limit = bincode::serialized_size(&CacheResponse::Value(vec![7; 1000])).unwrap();
+    let mut core = PersistentCore::ephemeral();
+    core.set_admin_token("secret".into());
+    core.set_max_response_size(limit as usize);
+    let addr = start_server(core);
+    let c = Client::connect(&addr.to_string()).unwrap();
+
+    c.set("fits", &[7; 1000]).unwrap();
+    c.set("over", &[7; 1001]).unwrap();
+    assert_eq!(c.get("fits").unwrap(), Some(vec![7; 1000]));
+    match c.get("over") {
+        Err(CacheError::ResponseTooLarge(size, max)) => assert_eq!((size, max), (limit + 1, limit)),
+        other => panic!("expected ResponseTooLarge, got {:?}", other),
+    }
+    // соединение после отказа в строю
+    c.ping().unwrap();
+    assert_eq!(c.get("fits").unwrap(), Some(vec![7; 1000]));
+
+    // Keys и Items больше предела уходят кусками под него
+    for i in 0..200 {
+        c.set(&format!("many:{:03}", i), &[1; 100]).unwrap();
+    }
+    assert_eq!(c.keys("many:*").unwrap().len(), 200);
+    assert_eq!(c.keys_sorted("many:", 0, 1000).unwrap().len(), 200);
+    let (next, items) = c.scan_items(0, "many:", 1000).unwrap();
+    assert_eq!((next, items.len()), (0, 200));
+
+    // ключ, который не помещается и один, обрывает ответ ошибкой
+    c.set(&format!("wide:b{}", "k".repeat(1000)), b"1").unwrap();
+    c.set("wide:a", b"1").unwrap();
+    assert!(matches!(
+        c.keys_sorted("wide:", 0, 10),
+        Err(CacheError::ResponseTooLarge(..))
+    ));
+    assert!(matches!(
+        c.scan_items(0, "fits", 10),
+        Err(CacheError::ResponseTooLarge(..))
+    ));
+    assert_eq!(c.keys_sorted("many:", 0, 1).unwrap(), ["many:000"]);
+
+    // протоколу 1 Keys уходит одним кадром и целиком упирается в предел
+    let mut raw = TcpStream::connect(addr).unwrap();
+    match request(&mut raw, &CacheCommand::Keys("many:*".into())) {
+        CacheResponse::Error(msg) => assert!(msg.starts_with("response too large: "), "{}", msg),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    assert!(matches!(
+        request(&mut raw, &CacheCommand::Get("fits".into())),
+        CacheResponse::Value(_)
+    ));
+
+    // предел меняется на ходу и не больше MAX_RESPONSE_SIZE
+    let admin = ClientOptions {
+        admin_token: Some("secret".into()),
+        ..Default::default()
+    };
+    let admin = Client::connect_with(&addr.to_string(), admin).unwrap();
+    assert!(admin.config_set("max_response_size", "0").is_err());
+    admin.config_set("max_response_size", "1000000000").unwrap();
+    let all = admin.config_get("max_response_size").unwrap();
+    assert_eq!(
+        info_field(all, "max_response_size").as_int(),
+        Some(MAX_RESPONSE_SIZE as i64)
+    );
+    assert!(c.get("over").unwrap().is_some());
+}
+
+#[test]
+fn oversized_response_frame() {
+    // сервер, заявляющий кадр больше MAX_RESPONSE_SIZE: клиент не выделяет под него память
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for mut s in listener.incoming().flatten() {
+            while read_command(&mut s).is_some() {
+                let head = (MAX_RESPONSE_SIZE as u32 + 1).to_le_bytes();
+                if s.write_all(&head).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    match Client::connect(&addr.to_string()).map(|c| c.ping()) {
+        Err(CacheError::Serialization(msg)) | Ok(Err(CacheError::Serialization(msg))) => {
+            assert!(msg.contains("byte limit"), "{}", msg)
+        }
+        other => panic!(
+            "expected a serialization error, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+}
+
+#[test]
+fn verify_values() {
+    let core = Arc::new(PersistentCore::ephemeral());
+    core.set("before".into(), b"loaded".to_vec()).unwrap();
+    // суммы уже записанных значений считаются при включении
+    core.set_verify_values(true);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Arc::clone(&core);
+    thread::spawn(move || serve_listener(listener, server));
+    let c = Client::connect(&addr).unwrap();
+
+    c.set("a", b"alpha").unwrap();
+    // изменения на месте пересчитывают сумму
+    c.update("a", UpdateOp::AppendBytes(b"!".to_vec())).unwrap();
+    assert_eq!(c.get("a").unwrap(), Some(b"alpha!".to_vec()));
+    c.setbit("bits", 9, true).unwrap();
+    assert_eq!(c.get("bits").unwrap(), Some(vec![0, 0x40]));
+
+    assert!(core.corrupt_value("before"));
+    assert!(core.corrupt_value("a"));
+    assert!(matches!(c.get("before"), Err(CacheError::Checksum(k)) if k == "before"));
+    // Pop не удаляет испорченное значение
+    assert!(matches!(c.pop("a"), Err(CacheError::Checksum(k)) if k == "a"));
+    assert_eq!(core.get("a"), Some(b"`lpha!".to_vec()));
+    let info = c.info().unwrap();
+    assert_eq!(
+        info_field(info.clone(), "verify_values"),
+        ResponseValue::Int(1)
+    );
+    assert_eq!(info_field(info, "checksum_failures"), ResponseValue::Int(2));
+    // перезапись даёт новую сумму
+    c.set("a", b"fresh").unwrap();
+    assert_eq!(c.pop("a").unwrap(), Some(b"fresh".to_vec()));
+
+    // без режима порча не видна
+    core.set_verify_values(false);
+    assert_eq!(c.get("before").unwrap(), Some(b"moaded".to_vec()));
+}
+
+#[test]
+fn verify() {
+    let dir = std::env::temp_dir().join(format!("tmc-verify-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let open = |name: &str| {
+        fs::create_dir_all(dir.join(name)).unwrap();
+        PersistentCore::new(
+            dir.join(name).join("cache.wal"),
+            dir.join(name).join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+
+    let mut core = open("a");
+    core.set("same".into(), b"1".to_vec()).unwrap();
+    core.set("changed".into(), b"2".to_vec()).unwrap();
+    core.set("only_memory".into(), b"3".to_vec()).unwrap();
+    core.compact().unwrap();
+    core.set("tail".into(), b"4".to_vec()).unwrap();
+    let report = core.verify(true, 10).unwrap();
+    assert_eq!((report.keys_checked, report.mismatches), (4, 0));
+
+    // чужой снапшот того же seq: память больше не сходится с файлами
+    let other = open("b");
+    other.set("same".into(), b"1".to_vec()).unwrap();
+    other.set("changed".into(), b"other".to_vec()).unwrap();
+    other.set("only_disk".into(), b"5".to_vec()).unwrap();
+    other.compact().unwrap();
+    fs::copy(dir.join("b/cache.snapshot"), dir.join("a/cache.snapshot")).unwrap();
+
+    let mut report = core.verify(true, 10).unwrap();
+    report.examples.sort();
+    assert_eq!(report.mismatches, 3);
+    assert_eq!(
+        report.examples,
+        [
+            ("changed".to_string(), "value_differs".to_string()),
+            ("only_disk".to_string(), "missing_in_memory".to_string()),
+            ("only_memory".to_string(), "missing_in_wal".to_string()),
+        ]
+    );
+    let report = core.verify(false, 1).unwrap();
+    assert_eq!((report.mismatches, report.examples.len()), (2, 1));
+
+    // по сети — только с админ-токеном
+    core.set_admin_token("secret".into());
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    assert!(matches!(c.verify(true, 10), Err(CacheError::Server(_))));
+    let admin = ClientOptions {
+        admin_token: Some("secret".into()),
+        ..Default::default()
+    };
+    let admin = Client::connect_with(&addr, admin).unwrap();
+    assert_eq!(admin.verify(true, 10).unwrap().mismatches, 3);
+    drop(other);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn client_list_and_kill() {
+    let mut core = PersistentCore::ephemeral();
+    core.set_admin_token("secret".into());
+    let addr = start_server(core);
+    let admin = ClientOptions {
+        admin_token: Some("secret".into()),
+        ..Default::default()
+    };
+    let admin = Client::connect_with(&addr.to_string(), admin).unwrap();
+    let c = Client::connect(&addr.to_string()).unwrap();
+    assert!(matches!(c.client_list(), Err(CacheError::Server(_))));
+    assert!(matches!(c.client_kill(1), Err(CacheError::Server(_))));
+
+    let mut raw = TcpStream::connect(addr).unwrap();
+    assert!(matches!(
+        request(&mut raw, &CacheCommand::Set("k".into(), b"v".to_vec())),
+        CacheResponse::Ok
+    ));
+    let list = admin.client_list().unwrap();
+    let peer = raw.local_addr().unwrap().to_string();
+    let entry = list.iter().find(|e| e.peer == peer).unwrap().clone();
+    assert_eq!((entry.commands, entry.last_command.as_str()), (1, "Set"));
+    // ответ мог дойти раньше, чем поток соединения учёл его байты
+    assert!(entry.bytes_in > 0);
+    // у админ-команды видно имя вложенной
+    assert!(list.iter().any(|e| e.last_command == "ClientList"));
+    let info = admin.info().unwrap();
+    assert!(info
+        .iter()
+        .any(|(k, v)| k == "connected_clients" && *v == ResponseValue::Int(list.len() as i64)));
+
+    assert!(admin.client_kill(entry.id).unwrap());
+    let mut buf = [0u8; 1];
+    assert_eq!(raw.read(&mut buf).unwrap(), 0);
+    // запись уходит из реестра, когда поток соединения завершится
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while admin
+        .client_list()
+        .unwrap()
+        .iter()
+        .any(|e| e.id == entry.id)
+    {
+        assert!(
+            Instant::now() < deadline,
+            "killed connection is still listed"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!admin.client_kill(entry.id).unwrap());
+}
+
+#[test]
+fn slowlog_trace_ids() {
+    let mut core = PersistentCore::ephemeral();
+    core.set_admin_token("secret".into());
+    // медленный источник: промах Get дольше порога журнала
+    core.set_loader(
+        Box::new(|_key: &str| {
+            thread::sleep(Duration::from_millis(50));
+            Ok(None)
+        }),
+        2,
+    )
+    .unwrap();
+    core.set_slowlog(Some(Duration::from_millis(20)));
+    let core = Arc::new(core);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Arc::clone(&core);
+    thread::spawn(move || serve_listener(listener, server));
+
+    let admin = Client::connect_with(
+        &addr,
+        ClientOptions {
+            admin_token: Some("secret".into()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let c = Client::connect(&addr).unwrap();
+    with_trace_id("req-42", || {
+        assert_eq!(current_trace_id().as_deref(), Some("req-42"));
+        // вложенная область действует до выхода из неё
+        with_trace_id("inner", || c.set("fast", b"v")).unwrap();
+        assert_eq!(c.get("missing").unwrap(), None);
+    });
+    assert_eq!(current_trace_id(), None);
+    // без id и быстрее порога — не в журнале
+    assert_eq!(c.get("fast").unwrap(), Some(b"v".to_vec()));
+    assert_eq!(c.get("untraced").unwrap(), None);
+
+    let log = core.slowlog();
+    assert_eq!(log.len(), 2, "{:?}", log);
+    assert_eq!(
+        (log[0].command, log[0].trace_id.as_deref()),
+        ("Get", Some("req-42"))
+    );
+    assert!(log[0].micros >= 50_000);
+    assert_eq!(log[1].trace_id, None);
+    assert!(c
+        .info()
+        .unwrap()
+        .iter()
+        .any(|(k, v)| k == "slow_commands" && *v == ResponseValue::Int(2)));
+    // команда без id сбрасывает id соединения
+    let list = admin.client_list().unwrap();
+    assert!(list
+        .iter()
+        .any(|e| e.last_command == "Info" && e.trace_id.is_empty()));
+
+    // тот же id по мультиплексированному соединению, обрезанный до предела
+    let mux = MuxConn::connect(&addr).unwrap();
+    let long = "x".repeat(MAX_TRACE_ID + 10);
+    with_trace_id(&long, || mux.call(&CacheCommand::Get("mux".into()))).unwrap();
+    let last = core.slowlog().pop().unwrap();
+    assert_eq!(last.trace_id.as_deref(), Some(&long[..MAX_TRACE_ID]));
+    with_trace_id("listed", || {
+        mux.call(&CacheCommand::Set("k".into(), b"v".to_vec()))
+    })
+    .unwrap();
+    let list = admin.client_list().unwrap();
+    assert!(list.iter().any(|e| e.trace_id == "listed"));
+
+    // без id кадр протокола 4 не длиннее, чем у версии 2
+    let mut raw = TcpStream::connect(&addr).unwrap();
+    assert!(matches!(
+        request(&mut raw, &CacheCommand::Hello(PROTOCOL_VERSION)),
+        CacheResponse::Int(v) if v == PROTOCOL_VERSION as i64
+    ));
+    assert!(matches!(
+        request_tagged(&mut raw, 7, &CacheCommand::Get("fast".into())),
+        (7, CacheResponse::Value(v)) if v == b"v"
+    ));
+    // флаг в старшем бите бюджета, затем длина и байты id
+    let body = bincode::serialize(&CacheCommand::Get("raw".into())).unwrap();
+    let mut frame = (body.len() as u32).to_le_bytes().to_vec();
+    frame.extend(8u64.to_le_bytes());
+    frame.extend((1u32 << 31).to_le_bytes());
+    frame.push(3);
+    frame.extend(b"abc");
+    frame.extend(&body);
+    raw.write_all(&frame).unwrap();
+    assert!(matches!(read_tagged(&mut raw), (8, _, CacheResponse::Nil)));
+    assert_eq!(
+        core.slowlog().pop().unwrap().trace_id.as_deref(),
+        Some("abc")
+    );
+}
+
+#[test]
+fn idle_connections_are_reaped() {
+    let core = PersistentCore::ephemeral();
+    core.set_idle_timeout(Some(Duration::from_secs(1)));
+    let addr = start_server(core);
+    let c = Client::connect(&addr.to_string()).unwrap();
+    let mux = ClientOptions {
+        multiplex: true,
+        ..Default::default()
+    };
+    let mux = Client::connect_with(&addr.to_string(), mux).unwrap();
+    c.set("n", b"1").unwrap();
+    mux.set("m", b"1").unwrap();
+
+    let mut idle = TcpStream::connect(addr).unwrap();
+    idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    // соединение, ждущее BGet дольше таймаута, не простаивает
+    let mut waiting = TcpStream::connect(addr).unwrap();
+    let bget =
+        thread::spawn(move || request(&mut waiting, &CacheCommand::BGet("late".into(), 5000)));
+    thread::sleep(Duration::from_millis(1800));
+    assert_eq!(idle.read(&mut [0u8; 1]).unwrap(), 0);
+
+    // клиенты переподключаются сами, в том числе для непереповторяемых команд
+    assert_eq!(
+        c.update("n", UpdateOp::AddI64(1)).unwrap(),
+        Some(b"2".to_vec())
+    );
+    assert_eq!(mux.get("m").unwrap(), Some(b"1".to_vec()));
+    c.set("late", b"v").unwrap();
+    assert!(matches!(bget.join().unwrap(), CacheResponse::Value(v) if v == b"v"));
+    let reaped = c
+        .info()
+        .unwrap()
+        .into_iter()
+        .find(|(k, _)| k == "reaped_connections")
+        .unwrap()
+        .1;
+    assert!(
+        matches!(reaped, ResponseValue::Int(n) if n >= 3),
+        "{:?}",
+        reaped
+    );
+}
+
+#[test]
+fn server_events() {
+    let (tx, rx) = mpsc::channel();
+    let mut core = PersistentCore::ephemeral();
+    core.set_event_hook(Box::new(move |event| {
+        let _ = tx.send(event);
+    }));
+    let addr = start_server(core);
+    let next = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(
+        next(),
+        ServerEvent::Ready {
+            addr: addr.to_string()
+        }
+    );
+
+    let mut raw = TcpStream::connect(addr).unwrap();
+    let ServerEvent::Connection { id, peer } = next() else {
+        panic!("expected a connection event");
+    };
+    assert_eq!(peer, raw.local_addr().unwrap().to_string());
+    // оборванный кадр — соединение закрывается с ошибкой
+    raw.write_all(&u32::MAX.to_le_bytes()).unwrap();
+    raw.shutdown(Shutdown::Write).unwrap();
+    match next() {
+        ServerEvent::ConnectionError {
+            id: failed, error, ..
+        } => {
+            assert_eq!(
+                (failed, error.as_str()),
+                (
+                    id,
+                    "network error: connection closed 0 bytes into a 4294967295 byte frame"
+                )
+            );
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    // обычное закрытие ошибкой не считается
+    Client::connect(&addr.to_string()).unwrap().close();
+    assert!(matches!(next(), ServerEvent::Connection { .. }));
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn update_add_is_atomic() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Arc::new(Client::connect(&addr).unwrap());
+    let workers: Vec<_> = (0..8)
+        .map(|_| {
+            let c = Arc::clone(&c);
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    c.update("hits", UpdateOp::AddI64(1)).unwrap();
+                }
+            })
+        })
+        .collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+    assert_eq!(c.get("hits").unwrap(), Some(b"800".to_vec()));
+}
+
+#[test]
+fn shared_between_threads() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Arc::new(Client::connect(&addr).unwrap());
+    let workers: Vec<_> = (0..4)
+        .map(|n| {
+            let c = Arc::clone(&c);
+            thread::spawn(move || {
+                for i in 0..200 {
+                    c.set(&format!("t:{}:{}", n, i), b"v").unwrap();
+                }
+            })
+        })
+        .collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+    assert_eq!(c.len().unwrap(), 800);
+    assert_eq!(c.clone().len().unwrap(), 800);
+}
+
+/// Сервер в режиме сбоев; `core` — чтобы смотреть счётчики и ключи мимо
+/// сбоев.
+fn start_chaos_server(opts: ChaosOptions) -> (Arc<PersistentCore>, String) {
+    let mut core = PersistentCore::ephemeral();
+    core.set_chaos(Some(opts)).unwrap();
+    let core = Arc::new(core);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Arc::clone(&core);
+    thread::spawn(move || serve_listener(listener, server));
+    (core, addr)
+}
+
+// кадр протокола руками: для поддельных серверов и проверки без Client
+fn read_command(stream: &mut impl Read) -> Option<CacheCommand> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).ok()?;
+    let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+    stream.read_exact(&mut buf).ok()?;
+    bincode::deserialize(&buf).ok()
+}
+
+fn write_response(stream: &mut impl Write, resp: &CacheResponse) {
+    let body = bincode::serialize(resp).unwrap();
+    let mut frame = (body.len() as u32).to_le_bytes().to_vec();
+    frame.extend(body);
+    // одной записью: иначе Nagle и отложенный ACK добавляют ~40 мс на вызов
+    stream.write_all(&frame).unwrap();
+}
+
+fn request(stream: &mut TcpStream, cmd: &CacheCommand) -> CacheResponse {
+    let body = bincode::serialize(cmd).unwrap();
+    let mut frame = (body.len() as u32).to_le_bytes().to_vec();
+    frame.extend(body);
+    stream.write_all(&frame).unwrap();
+    read_plain(stream)
+}
+
+// кадр ответа протокола 1: длина и тело
+fn read_plain(stream: &mut TcpStream) -> CacheResponse {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+    stream.read_exact(&mut buf).unwrap();
+    bincode::deserialize(&buf).unwrap()
+}
+
+// то же в формате протокола 2: после длины — id запроса и бюджет (0 — без срока)
+fn request_tagged(stream: &mut TcpStream, id: u64, cmd: &CacheCommand) -> (u64, CacheResponse) {
+    request_within(stream, id, 0, cmd, Duration::ZERO)
+}
+
+// тело кадра уходит через `delay` после заголовка: бюджет сервер отсчитывает
+// от заголовка, так что к началу выполнения он уже может истечь
+fn request_within(
+    stream: &mut TcpStream,
+    id: u64,
+    budget_ms: u32,
+    cmd: &CacheCommand,
+    delay: Duration,
+) -> (u64, CacheResponse) {
+    let body = bincode::serialize(cmd).unwrap();
+    let mut head = (body.len() as u32).to_le_bytes().to_vec();
+    head.extend(id.to_le_bytes());
+    head.extend(budget_ms.to_le_bytes());
+    stream.write_all(&head).unwrap();
+    thread::sleep(delay);
+    stream.write_all(&body).unwrap();
+    let (id, _, resp) = read_tagged(stream);
+    (id, resp)
+}
+
+// кадр ответа протокола 2: id, длина тела и тело
+fn read_tagged(stream: &mut TcpStream) -> (u64, usize, CacheResponse) {
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head).unwrap();
+    let mut buf = vec![0u8; u32::from_le_bytes(head[..4].try_into().unwrap()) as usize];
+    stream.read_exact(&mut buf).unwrap();
+    let id = u64::from_le_bytes(head[4..].try_into().unwrap());
+    (id, buf.len(), bincode::deserialize(&buf).unwrap())
+}
+
+#[test]
+fn pipelined_sets_share_wal_writes() {
+    let dir = std::env::temp_dir().join(format!("tmc-pipeline-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let opts = PersistOptions {
+        fsync: FsyncPolicy::Always,
+        ..PersistOptions::default()
+    };
+    let core =
+        PersistentCore::new(dir.join("cache.wal"), dir.join("cache.snapshot"), opts).unwrap();
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    let batches = || match info_field(c.info().unwrap(), "wal_batches") {
+        ResponseValue::Int(n) => n,
+        other => panic!("wal_batches is {:?}", other),
+    };
+
+    let mut raw = TcpStream::connect(&addr).unwrap();
+    assert!(matches!(
+        request(&mut raw, &CacheCommand::Hello(PROTOCOL_VERSION)),
+        CacheResponse::Int(_)
+    ));
+    // по одной команде с ожиданием ответа: запись WAL на каждую
+    let before = batches();
+    for i in 0..20u64 {
+        let set = CacheCommand::Set(format!("one:{}", i), b"v".to_vec());
+        assert!(matches!(
+            request_tagged(&mut raw, i, &set),
+            (_, CacheResponse::Ok)
+        ));
+    }
+    assert_eq!(batches() - before, 20);
+
+    // 500 Set одним куском, ответы — после: записи WAL идут пачками
+    let before = batches();
+    let mut frames = Vec::new();
+    for i in 0..500u64 {
+        let body =
+            bincode::serialize(&CacheCommand::Set(format!("pipe:{}", i), vec![7; 32])).unwrap();
+        frames.extend((body.len() as u32).to_le_bytes());
+        frames.extend(i.to_le_bytes());
+        frames.extend(0u32.to_le_bytes());
+        frames.extend(body);
+    }
+    raw.write_all(&frames).unwrap();
+    let mut ids: Vec<u64> = (0..500)
+        .map(|_| match read_tagged(&mut raw) {
+            (id, _, CacheResponse::Ok) => id,
+            other => panic!("unexpected response {:?}", other),
+        })
+        .collect();
+    ids.sort_unstable();
+    assert_eq!(ids, (0..500).collect::<Vec<_>>());
+    let writes = batches() - before;
+    assert!(
+        writes < 100,
+        "500 pipelined sets took {} WAL writes",
+        writes
+    );
+    assert_eq!(
+        info_field(c.info().unwrap(), "wal_batched_records"),
+        info_field(c.info().unwrap(), "wal_last_seq")
+    );
+    assert_eq!(c.len().unwrap(), 520);
+    assert_eq!(c.get("pipe:499").unwrap(), Some(vec![7; 32]));
+}
+
+fn assert_protocol_error(resp: CacheResponse, prefix: &str) {
+    match resp {
+        CacheResponse::Error(msg) => assert!(
+            msg.starts_with(&format!("protocol error: {}", prefix)),
+            "{}",
+            msg
+        ),
+        resp => panic!("{:?}", resp),
+    }
+}
+
+#[test]
+fn hostile_frames() {
+    let mut core = PersistentCore::ephemeral();
+    core.set_admin_token("secret".into());
+    let addr = start_server(core);
+    let oversized = 1_000_001usize;
+
+    // версия 1: кадр сверх предела пропускается целиком, битый — разбирается
+    // до ошибки; соединение живо
+    let mut s = TcpStream::connect(addr).unwrap();
+    s.write_all(&(oversized as u32).to_le_bytes()).unwrap();
+    s.write_all(&vec![0xab; oversized]).unwrap();
+    assert_protocol_error(
+        read_plain(&mut s),
+        "command frame of 1000001 bytes is over the 1000000 byte limit",
+    );
+    s.write_all(&[8, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4])
+        .unwrap();
+    assert_protocol_error(read_plain(&mut s), "malformed command frame: ");
+    assert!(matches!(
+        request(&mut s, &CacheCommand::Ping),
+        CacheResponse::Ok
+    ));
+
+    // протокол 2: ошибка приходит с id кадра
+    assert!(matches!(
+        request(&mut s, &CacheCommand::Hello(2)),
+        CacheResponse::Int(2)
+    ));
+    let mut head = (oversized as u32).to_le_bytes().to_vec();
+    head.extend(7u64.to_le_bytes());
+    head.extend(0u32.to_le_bytes());
+    s.write_all(&head).unwrap();
+    s.write_all(&vec![0; oversized]).unwrap();
+    let (id, _, resp) = read_tagged(&mut s);
+    assert_eq!(id, 7);
+    assert_protocol_error(resp, "command frame of 1000001 bytes");
+    let mut frame = 3u32.to_le_bytes().to_vec();
+    frame.extend(8u64.to_le_bytes());
+    frame.extend(0u32.to_le_bytes());
+    frame.extend([0xff; 3]);
+    s.write_all(&frame).unwrap();
+    let (id, _, resp) = read_tagged(&mut s);
+    assert_eq!(id, 8);
+    assert_protocol_error(resp, "malformed command frame: ");
+    assert!(matches!(
+        request_tagged(&mut s, 9, &CacheCommand::Ping),
+        (9, CacheResponse::Ok)
+    ));
+
+    // оборванный кадр закрывает соединение
+    let mut s = TcpStream::connect(addr).unwrap();
+    s.write_all(&[100, 0, 0, 0, 1, 2, 3]).unwrap();
+    s.shutdown(Shutdown::Write).unwrap();
+    assert_eq!(s.read(&mut [0; 16]).unwrap(), 0);
+
+    // случайные байты, в том числе длины под 4 ГБ: сервер не падает и не
+    // держит память и соединения
+    let rss = || {
+        fs::read_to_string("/proc/self/statm")
+            .ok()
+            .and_then(|s| s.split_whitespace().nth(1)?.parse::<u64>().ok())
+            .map_or(0, |pages| pages * 4096)
+    };
+    let before = rss();
+    let mut x: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut junk = |len: usize| -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    };
+    for i in 0..300 {
+        let len = 1 + (i * 37) % 4096;
+        // кадры с верной длиной и мусором внутри, затем обычная команда
+        let mut s = TcpStream::connect(addr).unwrap();
+        for _ in 0..3 {
+            let mut frame = (len as u32).to_le_bytes().to_vec();
+            frame.extend(junk(len));
+            s.write_all(&frame).unwrap();
+            assert_protocol_error(read_plain(&mut s), "");
+        }
+        assert!(matches!(
+            request(&mut s, &CacheCommand::Ping),
+            CacheResponse::Ok
+        ));
+        // просто мусор
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.write_all(&junk(len)).unwrap();
+        s.shutdown(Shutdown::Write).unwrap();
+        let _ = s.read_to_end(&mut Vec::new());
+    }
+    assert!(
+        rss().saturating_sub(before) < 64 << 20,
+        "{} -> {}",
+        before,
+        rss()
+    );
+    let admin = ClientOptions {
+        admin_token: Some("secret".into()),
+        ..Default::default()
+    };
+    let c = Client::connect_with(&addr.to_string(), admin).unwrap();
+    c.set("k", b"v").unwrap();
+    assert_eq!(c.get("k").unwrap(), Some(b"v".to_vec()));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    // все соединения, кроме этого и первого, закрыты
+    while c.client_list().unwrap().len() > 2 {
+        assert!(Instant::now() < deadline, "{:?}", c.client_list());
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn protocol_versions() {
+    let addr = start_server(PersistentCore::ephemeral());
+    let mut s = TcpStream::connect(addr).unwrap();
+    assert!(matches!(
+        request(&mut s, &CacheCommand::Set("k".into(), b"v".to_vec())),
+        CacheResponse::Ok
+    ));
+    // без Hello — версия 1: словари приходят как Info со строками
+    match request(&mut s, &CacheCommand::Info) {
+        CacheResponse::Info(fields) => {
+            assert!(fields.contains(&("keys".to_string(), "1".to_string())))
+        }
+        resp => panic!("{:?}", resp),
+    }
+    match request(&mut s, &CacheCommand::Hello(1)) {
+        CacheResponse::Int(1) => {}
+        resp => panic!("{:?}", resp),
+    }
+    assert!(matches!(
+        request(&mut s, &CacheCommand::Type("k".into())),
+        CacheResponse::Info(_)
+    ));
+    // клиент новее сервера получает версию сервера; дальше кадры с id
+    assert!(matches!(
+        request(&mut s, &CacheCommand::Hello(PROTOCOL_VERSION + 5)),
+        CacheResponse::Int(v) if v == PROTOCOL_VERSION as i64
+    ));
+    match request_tagged(&mut s, 41, &CacheCommand::Info) {
+        (41, CacheResponse::Map(map)) => {
+            assert!(map.contains(&("keys".to_string(), ResponseValue::Int(1))))
+        }
+        resp => panic!("{:?}", resp),
+    }
+    assert!(matches!(
+        request_tagged(&mut s, 42, &CacheCommand::Hello(1)),
+        (42, CacheResponse::Error(_))
+    ));
+    assert!(matches!(
+        request_tagged(&mut s, 43, &CacheCommand::Subscribe(vec!["c".into()])),
+        (43, CacheResponse::Error(_))
+    ));
+    assert!(matches!(
+        request_tagged(&mut s, 44, &CacheCommand::Get("k".into())),
+        (44, CacheResponse::Value(v)) if v == b"v"
+    ));
+}
+
+#[test]
+fn chunked_responses() {
+    let core = PersistentCore::ephemeral();
+    for i in 0..60_000 {
+        core.set(format!("chunk:{:06}:{}", i, "k".repeat(40)), Vec::new())
+            .unwrap();
+    }
+    for i in 0..12 {
+        core.set(format!("big:{:02}", i), vec![i as u8; 200_000])
+            .unwrap();
+    }
+    let addr = start_server(core);
+    let limit = 1 << 20;
+
+    // протокол 3: куски с одним id и не больше мегабайта, последний — без more
+    let mut s = TcpStream::connect(addr).unwrap();
+    assert!(matches!(
+        request(&mut s, &CacheCommand::Hello(3)),
+        CacheResponse::Int(3)
+    ));
+    let keys = CacheCommand::Keys("chunk:*".into());
+    let mut frame = request_tagged(&mut s, 7, &keys);
+    let (mut all, mut frames) = (Vec::new(), 0);
+    loop {
+        frames += 1;
+        match frame {
+            (7, CacheResponse::KeysChunk(part, more)) => {
+                assert!(!part.is_empty());
+                all.extend(part);
+                if !more {
+                    break;
+                }
+            }
+            resp => panic!("{:?}", resp),
+        }
+        let (id, size, resp) = read_tagged(&mut s);
+        assert!(size <= limit, "{} byte chunk", size);
+        frame = (id, resp);
+    }
+    assert!(frames >= 3, "{} frames", frames);
+    all.sort();
+    all.dedup();
+    assert_eq!(all.len(), 60_000);
+    // пустой ответ — один пустой кусок
+    assert!(matches!(
+        request_tagged(&mut s, 8, &CacheCommand::Keys("none:*".into())),
+        (8, CacheResponse::KeysChunk(part, false)) if part.is_empty()
+    ));
+    let scan = CacheCommand::ScanItems(0, "big:".into(), 100);
+    let mut frame = request_tagged(&mut s, 9, &scan);
+    let (mut items, mut frames) = (0, 0);
+    loop {
+        frames += 1;
+        match frame {
+            (9, CacheResponse::ItemsChunk(0, part, more)) => {
+                assert!(part.iter().all(|(_, v)| v.len() == 200_000));
+                items += part.len();
+                if !more {
+                    break;
+                }
+            }
+            resp => panic!("{:?}", resp),
+        }
+        let (id, size, resp) = read_tagged(&mut s);
+        assert!(size <= limit, "{} byte chunk", size);
+        frame = (id, resp);
+    }
+    assert!(frames >= 3, "{} frames", frames);
+    assert_eq!(items, 12);
+
+    // клиенты собирают куски сами
+    for multiplex in [false, true] {
+        let c = Client::connect_with(
+            &addr.to_string(),
+            ClientOptions {
+                multiplex,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(c.keys("chunk:*").unwrap().len(), 60_000);
+        let sorted = c.keys_sorted("chunk:", 100, 30_000).unwrap();
+        assert_eq!(sorted.len(), 30_000);
+        assert!(sorted[0].starts_with("chunk:000100:"));
+        let (next, page) = c.scan_items(0, "big:", 100).unwrap();
+        assert_eq!((next, page.len()), (0, 12));
+    }
+
+    // протоколам 1 и 2 — по-прежнему одним кадром
+    let mut v2 = TcpStream::connect(addr).unwrap();
+    assert!(matches!(
+        request(&mut v2, &CacheCommand::Hello(2)),
+        CacheResponse::Int(2)
+    ));
+    assert!(matches!(
+        request_tagged(&mut v2, 1, &keys),
+        (1, CacheResponse::Keys(k)) if k.len() == 60_000
+    ));
+    let mut v1 = TcpStream::connect(addr).unwrap();
+    assert!(matches!(
+        request(&mut v1, &scan),
+        CacheResponse::Items(0, items) if items.len() == 12
+    ));
+}
+
+#[test]
+fn multiplexed_ordering() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let mux = MuxConn::connect(&addr).unwrap();
+
+    // команды одного ключа выполняются в порядке отправки, даже не дожидаясь ответов
+    let mut pending = vec![mux
+        .send(&CacheCommand::Set("seq".into(), Vec::new()))
+        .unwrap()];
+    for i in 0..200u8 {
+        let op = UpdateOp::AppendBytes(vec![i]);
+        pending.push(mux.send(&CacheCommand::Update("seq".into(), op)).unwrap());
+    }
+    let get = mux.send(&CacheCommand::Get("seq".into())).unwrap();
+    for p in pending {
+        p.wait().unwrap();
+    }
+    match get.wait().unwrap() {
+        CacheResponse::Value(v) => assert_eq!(v, (0..200u8).collect::<Vec<_>>()),
+        resp => panic!("{:?}", resp),
+    }
+
+    // команда не с одним ключом видит все отправленные до неё
+    let sets: Vec<_> = (0..100)
+        .map(|i| {
+            mux.send(&CacheCommand::Set(format!("b:{}", i), b"x".to_vec()))
+                .unwrap()
+        })
+        .collect();
+    let del = mux.send(&CacheCommand::DelPrefix("b:".into())).unwrap();
+    let after = mux.send(&CacheCommand::Exists("b:7".into())).unwrap();
+    assert!(matches!(del.wait().unwrap(), CacheResponse::Int(100)));
+    assert!(matches!(after.wait().unwrap(), CacheResponse::Int(0)));
+    for p in sets {
+        p.wait().unwrap();
+    }
+
+    // BGet не задерживает остальные: ответ на Get приходит раньше
+    let bget = mux.send(&CacheCommand::BGet("late".into(), 5000)).unwrap();
+    assert!(matches!(
+        mux.call(&CacheCommand::Get("other".into())).unwrap(),
+        CacheResponse::Nil
+    ));
+    assert!(bget.try_take().is_none());
+    mux.call(&CacheCommand::Set("late".into(), b"v".to_vec()))
+        .unwrap();
+    assert!(matches!(bget.wait().unwrap(), CacheResponse::Value(v) if v == b"v"));
+
+    // ошибка сервера приходит своему запросу и соединение не ломает
+    assert!(matches!(
+        mux.call(&CacheCommand::Save),
+        Err(CacheError::Server(_))
+    ));
+    assert!(matches!(
+        mux.call(&CacheCommand::Len).unwrap(),
+        CacheResponse::Int(2)
+    ));
+    mux.close();
+    assert!(matches!(
+        mux.call(&CacheCommand::Len),
+        Err(CacheError::Closed)
+    ));
+}
+
+#[test]
+fn multiplexed_client() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect_with(
+        &addr,
+        ClientOptions {
+            multiplex: true,
+            read_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            // клон делит то же соединение
+            let c = c.clone();
+            thread::spawn(move || {
+                for i in 0..200 {
+                    let key = format!("t{}:{}", t, i % 10);
+                    let value = format!("{}", i).into_bytes();
+                    c.set(&key, &value).unwrap();
+                    assert_eq!(c.get(&key).unwrap(), Some(value));
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(c.len().unwrap(), 80);
+    assert_eq!(
+        c.get_blocking("never", Duration::from_millis(100)).unwrap(),
+        None
+    );
+    let info = c.info().unwrap();
+    assert!(info
+        .iter()
+        .any(|(k, v)| k == "keys" && *v == ResponseValue::Int(80)));
+}
+
+#[test]
+fn deadline_exceeded() {
+    let addr = start_server(PersistentCore::ephemeral());
+    let c = Client::connect(&addr.to_string()).unwrap();
+    for i in 0..3000 {
+        c.set(&format!("d:{}", i), b"x").unwrap();
+    }
+    let mut s = TcpStream::connect(addr).unwrap();
+    s.set_nodelay(true).unwrap();
+    // на протоколе 2 Keys — один кадр
+    assert!(matches!(
+        request(&mut s, &CacheCommand::Hello(2)),
+        CacheResponse::Int(2)
+    ));
+    let dump = std::env::temp_dir().join(format!("tmc-deadline-{}.bin", std::process::id()));
+    let _ = fs::remove_file(&dump);
+    let late = Duration::from_millis(50);
+    for (id, cmd) in [
+        (1, CacheCommand::Keys("d:*".into())),
+        (2, CacheCommand::Scan(0, "d:".into(), 10_000)),
+        (3, CacheCommand::DelPrefix("d:".into())),
+        (
+            4,
+            CacheCommand::Export("binary".into(), dump.to_str().unwrap().into()),
+        ),
+    ] {
+        match request_within(&mut s, id, 5, &cmd, late) {
+            (got, CacheResponse::Error(msg)) if got == id => assert_eq!(msg, "deadline exceeded"),
+            resp => panic!("{:?}: {:?}", cmd, resp),
+        }
+    }
+    // отменённые команды ничего не сделали
+    assert_eq!(c.len().unwrap(), 3000);
+    assert!(!dump.exists());
+
+    // бюджета хватает — команда выполняется как обычно
+    match request_within(&mut s, 5, 10_000, &CacheCommand::Keys("d:*".into()), late) {
+        (5, CacheResponse::Keys(keys)) => assert_eq!(keys.len(), 3000),
+        resp => panic!("{:?}", resp),
+    }
+    // клиенты шлют бюджет из таймаута чтения
+    let mux = Client::connect_with(
+        &addr.to_string(),
+        ClientOptions {
+            multiplex: true,
+            read_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(mux.delete_prefix("d:").unwrap(), 3000);
+}
+
+#[test]
+fn falls_back_to_protocol_v1() {
+    // сервер версии 1: на незнакомую команду (Hello) закрывает соединение
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                while let Some(cmd) = read_command(&mut stream) {
+                    let resp = match cmd {
+                        CacheCommand::Hello(_) => return,
+                        CacheCommand::Info => {
+                            CacheResponse::Info(vec![("keys".into(), "7".into())])
+                        }
+                        _ => CacheResponse::Ok,
+                    };
+                    write_response(&mut stream, &resp);
+                }
+            });
+        }
+    });
+
+    let c = Client::connect(&addr).unwrap();
+    assert_eq!(
+        c.info().unwrap(),
+        vec![("keys".to_string(), ResponseValue::Str("7".into()))]
+    );
+    c.set("k", b"v").unwrap();
+}
+
+#[test]
+fn benchmark() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let opts = BenchOptions {
+        ops: 2000,
+        workers: 3,
+        keys_per_worker: 50,
+        ..Default::default()
+    };
+    let report = tiny_mp_cache::benchmark(&addr, &opts).unwrap();
+    assert_eq!(report.gets + report.sets, 2000);
+    assert!(report.gets > report.sets && report.sets > 0);
+    assert!(report.verified >= report.gets);
+    assert!(report.p50_us <= report.p99_us && report.p99_us <= report.max_us);
+    // ключи прогона удалены
+    assert!(Client::connect(&addr).unwrap().is_empty().unwrap());
+
+    assert_eq!(parse_mix("80get/20set").unwrap(), 80);
+    assert_eq!(parse_mix("100set").unwrap(), 0);
+    assert!(parse_mix("80get/30set").is_err());
+}
+
+#[test]
+fn benchmark_rejects_a_server_that_loses_writes() {
+    // отвечает Ok на любую запись и ничего не хранит
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                while let Some(cmd) = read_command(&mut stream) {
+                    let resp = match cmd {
+                        CacheCommand::Get(_) => CacheResponse::Nil,
+                        CacheCommand::DelPrefix(_) => CacheResponse::Int(0),
+                        _ => CacheResponse::Ok,
+                    };
+                    write_response(&mut stream, &resp);
+                }
+            });
+        }
+    });
+
+    let opts = BenchOptions {
+        ops: 100,
+        workers: 2,
+        keys_per_worker: 10,
+        ..Default::default()
+    };
+    let err = tiny_mp_cache::benchmark(&addr, &opts).unwrap_err();
+    assert!(err.to_string().contains("sanity check failed"), "{}", err);
+}
+
+#[test]
+fn reconnects_after_dropped_connection() {
+    // с этим seed обрывается первая команда, а следующие две проходят
+    let opts = ChaosOptions {
+        disconnect_rate: 0.5,
+        seed: Some(14),
+        ..Default::default()
+    };
+    let (_, addr) = start_chaos_server(opts.clone());
+    let c = Client::connect(&addr).unwrap();
+    // set повторяется на новом соединении незаметно для вызывающего
+    c.set("k", b"v").unwrap();
+    assert_eq!(c.get("k").unwrap(), Some(b"v".to_vec()));
+
+    let (core, addr) = start_chaos_server(opts);
+    core.set("k".into(), b"v".to_vec()).unwrap();
+    let c = Client::connect(&addr).unwrap();
+    // pop не повторяется: ошибка наружу, но следующий вызов переподключается
+    assert!(matches!(c.pop("k"), Err(CacheError::Network(_))));
+    assert_eq!(c.pop("k").unwrap(), Some(b"v".to_vec()));
+}
+
+#[test]
+fn read_timeout() {
+    // принимает соединения и молчит
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let mut buf = [0u8; 1024];
+                while matches!(stream.read(&mut buf), Ok(n) if n > 0) {}
+            });
+        }
+    });
+
+    let options = ClientOptions {
+        connect_timeout: Some(Duration::from_secs(1)),
+        read_timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let c = Client::connect_with(&addr, options).unwrap();
+    let start = Instant::now();
+    assert!(matches!(c.get("k"), Err(CacheError::Network(_))));
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn connect_error() {
+    let addr = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    assert!(matches!(
+        Client::connect(&addr),
+        Err(CacheError::Network(_))
+    ));
+}
+
+#[test]
+fn bind_error() {
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = taken.local_addr().unwrap().to_string();
+    let bind = |addr: &str| match serve_tcp(addr, Arc::new(PersistentCore::ephemeral())) {
+        Err(CacheError::Bind(failure)) => failure,
+        res => panic!("expected a bind error, got {:?}", res),
+    };
+    let failure = bind(&addr);
+    assert_eq!(
+        (failure.addr.as_str(), failure.reason),
+        (addr.as_str(), BindReason::AddressInUse)
+    );
+    assert!(failure.errno.is_some());
+    assert!(failure
+        .to_string()
+        .starts_with(&format!("cannot bind {}: ", addr)));
+    assert_eq!(bind("no port").reason, BindReason::InvalidAddress);
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_in_use() {
+    use std::os::unix::net::UnixListener;
+    use tiny_mp_cache::serve_unix_socket;
+
+    let dir = std::env::temp_dir().join(format!("tmc-uds-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("cache.sock");
+    // файл сокета остался от упавшего сервера
+    drop(UnixListener::bind(&path).unwrap());
+    let serve = |path: &Path| serve_unix_socket(path, Arc::new(PersistentCore::ephemeral()));
+    let first = path.clone();
+    thread::spawn(move || serve(&first));
+    let addr = format!("unix://{}", path.display());
+    let c = (0..200)
+        .find_map(|_| {
+            thread::sleep(Duration::from_millis(10));
+            Client::connect(&addr).ok()
+        })
+        .unwrap();
+    c.set("k", b"v").unwrap();
+
+    match serve(&path) {
+        Err(CacheError::Bind(failure)) => {
+            assert_eq!(failure.reason, BindReason::AddressInUse);
+            assert_eq!(
+                failure.to_string(),
+                format!(
+                    "cannot bind {}: another server is already listening on this path",
+                    path.display()
+                )
+            );
+        }
+        res => panic!("expected a bind error, got {:?}", res),
+    }
+    assert_eq!(c.get("k").unwrap().as_deref(), Some(&b"v"[..]));
+    assert_eq!(
+        Client::connect(&addr).unwrap().get("k").unwrap().as_deref(),
+        Some(&b"v"[..])
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn peer_access() {
+    use std::os::unix::net::UnixStream;
+    use tiny_mp_cache::{peer_cred, PeerAccess, PeerCred, PeerRole};
+
+    let (a, _b) = UnixStream::pair().unwrap();
+    let me = peer_cred(&a).unwrap();
+    // SAFETY: geteuid/getegid не трогают память
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    assert_eq!(me, PeerCred { uid, gid });
+
+    let own = 500;
+    let peer = |uid, gid| PeerCred { uid, gid };
+    assert_eq!(
+        PeerAccess::default().role(peer(1, 1), own),
+        Some(PeerRole::Full)
+    );
+    let access = PeerAccess {
+        allow_uids: vec![1000],
+        allow_gids: vec![100],
+        readonly_gids: vec![200],
+        ..Default::default()
+    };
+    assert_eq!(access.role(peer(1000, 1), own), Some(PeerRole::Full));
+    assert_eq!(access.role(peer(1, 100), own), Some(PeerRole::Full));
+    assert_eq!(access.role(peer(1, 200), own), Some(PeerRole::ReadOnly));
+    // полный доступ сильнее роли только для чтения
+    assert_eq!(access.role(peer(1000, 200), own), Some(PeerRole::Full));
+    assert_eq!(access.role(peer(1, 1), own), None);
+    // uid самого сервера пускается всегда
+    assert_eq!(access.role(peer(own, 1), own), Some(PeerRole::Full));
+
+    // свой uid проходит и непустую политику
+    let mut core = PersistentCore::ephemeral();
+    core.set_peer_access(PeerAccess {
+        allow_uids: vec![uid.wrapping_add(1)],
+        ..Default::default()
+    })
+    .unwrap();
+    let dir = std::env::temp_dir().join(format!("tmc-peer-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("cache.sock");
+    let serve_path = path.clone();
+    thread::spawn(move || tiny_mp_cache::serve_unix_socket(&serve_path, Arc::new(core)));
+    let addr = format!("unix://{}", path.display());
+    let c = (0..200)
+        .find_map(|_| {
+            thread::sleep(Duration::from_millis(10));
+            Client::connect(&addr).ok()
+        })
+        .unwrap();
+    c.set("k", b"v").unwrap();
+    assert_eq!(c.get("k").unwrap(), Some(b"v".to_vec()));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// новая версия файла в replace_file_crash, 64 КБ
+fn crash_contents() -> Vec<u8> {
+    (0..64 * 1024).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn replace_file_crash() {
+    // дочерний процесс: пишет n байт новой версии и умирает, не вернувшись
+    // из записи (exit, а не abort — без core-файлов)
+    if let Ok(spec) = std::env::var("TMC_CRASH_AT") {
+        let (n, path) = spec.split_once('=').unwrap();
+        let n: usize = n.parse().unwrap();
+        let _ = replace_file(Path::new(path), |f| {
+            f.write_all(&crash_contents()[..n])?;
+            std::process::exit(3)
+        });
+        unreachable!("the writer exits");
+    }
+    let dir = std::env::temp_dir().join(format!("tmc-crash-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("data");
+    let new = crash_contents();
+    fs::write(&path, b"old contents").unwrap();
+    for n in [0, 1, 4096, new.len() - 1, new.len()] {
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "replace_file_crash", "--test-threads=1"])
+            .env("TMC_CRASH_AT", format!("{}={}", n, path.display()))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success());
+        // процесс умер до rename: файл целиком старый, недописанное — в .tmp
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            b"old contents",
+            "crash after {}",
+            n
+        );
+        assert_eq!(fs::metadata(dir.join("data.tmp")).unwrap().len(), n as u64);
+    }
+    // следующая запись проходит поверх оставшегося .tmp
+    replace_file(&path, |f| f.write_all(&new)).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), new);
+    assert!(!dir.join("data.tmp").exists());
+
+    // компакция пишет снапшот поверх мусора, оставшегося от упавшей записи
+    let open = || {
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+    let core = open();
+    core.set("k".into(), b"v".to_vec()).unwrap();
+    fs::write(dir.join("cache.snapshot.tmp"), b"half a snapshot").unwrap();
+    core.compact().unwrap();
+    drop(core);
+    assert!(!dir.join("cache.snapshot.tmp").exists());
+    assert_eq!(open().get("k"), Some(b"v".to_vec()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn case_insensitive_keys() {
+    let dir = std::env::temp_dir().join(format!("tmc-fold-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = |dir: &Path, fold: bool| {
+        let opts = PersistOptions {
+            case_insensitive_keys: fold,
+            ..PersistOptions::default()
+        };
+        PersistentCore::new(dir.join("cache.wal"), dir.join("cache.snapshot"), opts)
+    };
+
+    let core = open(&dir, true).unwrap();
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    c.set("User:Alice", b"1").unwrap();
+    c.set("USER:ÉVA", b"2").unwrap();
+    assert_eq!(c.get("user:alice").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(c.get("user:éva").unwrap(), Some(b"2".to_vec()));
+    let mut keys = c.keys("USER:*").unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["user:alice".to_string(), "user:éva".to_string()]);
+    assert!(c.exists("USER:ALICE").unwrap());
+    assert_eq!(c.len().unwrap(), 2);
+    assert_eq!(
+        info_field(c.info().unwrap(), "case_insensitive_keys"),
+        ResponseValue::Int(1)
+    );
+    drop(c);
+
+    // сервер держит журнал открытым, так что дальше — его копия
+    let copy = dir.join("copy");
+    fs::create_dir_all(&copy).unwrap();
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() {
+            fs::copy(&path, copy.join(path.file_name().unwrap())).unwrap();
+        }
+    }
+    // настройка записана в заголовке WAL: открытие без неё отказывает
+    match open(&copy, false) {
+        Err(CacheError::Unsupported(msg)) => {
+            assert!(msg.contains("case_insensitive_keys=true"), "{}", msg)
+        }
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+    let core = open(&copy, true).unwrap();
+    assert_eq!(core.get("user:alice"), Some(b"1".to_vec()));
+    assert_eq!(core.len(), 2);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn consistent_reads_never_see_half_a_swap() {
+    let addr = start_server(PersistentCore::ephemeral()).to_string();
+    let c = Client::connect(&addr).unwrap();
+    c.set("pair:a", b"x").unwrap();
+    c.set("pair:b", b"y").unwrap();
+    assert_eq!(
+        c.get_consistent(&["pair:b", "missing", "pair:b"]).unwrap(),
+        vec![Some(b"y".to_vec()), None, Some(b"y".to_vec())]
+    );
+    assert_eq!(
+        c.get_consistent(&[]).unwrap(),
+        Vec::<Option<Vec<u8>>>::new()
+    );
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let (c, stop) = (c.clone(), Arc::clone(&stop));
+        thread::spawn(move || {
+            let mut swaps = 0;
+            while !stop.load(Ordering::Relaxed) {
+                assert!(c.swap("pair:a", "pair:b").unwrap());
+                swaps += 1;
+            }
+            swaps
+        })
+    };
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let c = c.clone();
+            thread::spawn(move || {
+                let deadline = Instant::now() + Duration::from_millis(300);
+                let mut reads = 0;
+                while Instant::now() < deadline {
+                    let pair = c.get_consistent(&["pair:a", "pair:b"]).unwrap();
+                    let pair: Vec<&[u8]> = pair.iter().map(|v| v.as_deref().unwrap()).collect();
+                    assert!(pair == [b"x", b"y"] || pair == [b"y", b"x"], "{:?}", pair);
+                    reads += 1;
+                }
+                reads
+            })
+        })
+        .collect();
+    let reads: u32 = readers.into_iter().map(|r| r.join().unwrap()).sum();
+    stop.store(true, Ordering::Relaxed);
+    let swaps: u32 = writer.join().unwrap();
+    assert!(
+        reads > 100 && swaps > 100,
+        "{} reads, {} swaps",
+        reads,
+        swaps
+    );
+}
+
+#[test]
+fn swap_survives_replay() {
+    let dir = std::env::temp_dir().join(format!("tmc-swap-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+
+    let core = open();
+    core.set_ex("config:active".into(), b"blue".to_vec(), 600_000)
+        .unwrap();
+    core.set_tagged(
+        "config:staged".into(),
+        b"green".to_vec(),
+        vec!["cfg".into()],
+    )
+    .unwrap();
+    core.set("old".into(), b"x".to_vec()).unwrap();
+    // часть ключей — только в снапшоте, обмен — только в WAL
+    core.compact().unwrap();
+    assert!(core.swap("config:active", "config:staged").unwrap());
+    assert!(core.swap("old", "new").unwrap());
+    assert!(!core.swap("none1", "none2").unwrap());
+    core.set("fresh".into(), b"1".to_vec()).unwrap();
+    assert!(core.swap("fresh", "old").unwrap());
+    core.set("fresh".into(), b"2".to_vec()).unwrap();
+    drop(core);
+
+    let core = open();
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    assert_eq!(c.get("config:active").unwrap(), Some(b"green".to_vec()));
+    assert_eq!(c.get("config:staged").unwrap(), Some(b"blue".to_vec()));
+    // срок и теги переехали вместе со значениями
+    assert_eq!(c.pttl("config:active").unwrap(), -1);
+    assert!(c.pttl("config:staged").unwrap() > 0);
+    assert_eq!(c.get("new").unwrap(), Some(b"x".to_vec()));
+    assert_eq!(c.get("old").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(c.get("fresh").unwrap(), Some(b"2".to_vec()));
+    assert!(c.swap("config:active", "config:staged").unwrap());
+    assert_eq!(c.get("config:active").unwrap(), Some(b"blue".to_vec()));
+    assert!(c.swap("same", "new").unwrap());
+    assert_eq!(c.get("new").unwrap(), None);
+    assert!(!c.swap("none1", "none2").unwrap());
+    assert!(c.swap("same", "same").unwrap());
+    assert!(!c.swap("none1", "none1").unwrap());
+    assert!(c.swap("config:active", "config:staged").unwrap());
+    assert_eq!(c.delete_by_tag("cfg").unwrap(), 1);
+    assert_eq!(c.get("config:active").unwrap(), None);
+    assert_eq!(c.get("config:staged").unwrap(), Some(b"blue".to_vec()));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn write_meta_survives_restart() {
+    let dir = std::env::temp_dir().join(format!("tmc-meta-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = || {
+        PersistentCore::new(
+            dir.join("cache.wal"),
+            dir.join("cache.snapshot"),
+            PersistOptions::default(),
+        )
+        .unwrap()
+    };
+    let unix_ms = || {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    };
+
+    let core = open();
+    let before = unix_ms();
+    core.set("snap".into(), b"1".to_vec()).unwrap();
+    core.set("snap".into(), b"2".to_vec()).unwrap();
+    // часть истории — в снапшоте, часть — только в WAL
+    core.compact().unwrap();
+    thread::sleep(Duration::from_millis(20));
+    let mid = unix_ms();
+    core.set("snap".into(), b"3".to_vec()).unwrap();
+    core.set_ex("wal".into(), b"a".to_vec(), 600_000).unwrap();
+    core.set_bit("wal".into(), 3, true).unwrap();
+    core.set("gone".into(), b"x".to_vec()).unwrap();
+    core.delete("gone").unwrap();
+    core.set("gone".into(), b"y".to_vec()).unwrap();
+    let after = unix_ms();
+    drop(core);
+
+    let core = open();
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+    let snap = c.get_with_meta("snap").unwrap().unwrap();
+    assert_eq!(snap.value, b"3");
+    assert_eq!(snap.version, 3);
+    assert!((mid..=after).contains(&snap.written_at), "{:?}", snap);
+    assert_eq!(snap.ttl_ms, None);
+    let wal = c.get_with_meta("wal").unwrap().unwrap();
+    assert_eq!(wal.version, 2);
+    assert!(wal.ttl_ms.is_some_and(|ttl| ttl > 500_000));
+    assert!((before..=after).contains(&wal.written_at));
+    assert_eq!(c.get_with_meta("gone").unwrap().unwrap().version, 1);
+    assert_eq!(c.get_with_meta("missing").unwrap(), None);
+    let now = c.time().unwrap();
+    assert!(now >= after && now <= unix_ms());
+    assert!(wal.now >= after);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn replay_reports_progress() {
+    let dir = std::env::temp_dir().join(format!("tmc-replay-progress-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let reports = Arc::new(Mutex::new(Vec::<ReplayProgress>::new()));
+    let open = || {
+        let reports = Arc::clone(&reports);
+        let opts = PersistOptions {
+            segment_size: Some(64 << 10),
+            on_replay: Some(ReplayHook(Arc::new(move |p: &ReplayProgress| {
+                reports.lock().unwrap().push(p.clone())
+            }))),
+            ..PersistOptions::default()
+        };
+        PersistentCore::new(dir.join("cache.wal"), dir.join("cache.snapshot"), opts).unwrap()
+    };
+
+    let core = open();
+    for i in 0..2000 {
+        core.set(format!("k:{}", i), vec![b'x'; 100]).unwrap();
+    }
+    for i in 0..500 {
+        core.delete(&format!("k:{}", i)).unwrap();
+    }
+    core.swap("k:1000", "moved").unwrap();
+    drop(core);
+    reports.lock().unwrap().clear();
+
+    let core = open();
+    let reports = reports.lock().unwrap();
+    let done = reports.last().unwrap();
+    assert!(done.done);
+    assert!(reports[..reports.len() - 1].iter().all(|p| !p.done));
+    assert_eq!(done.records, 2501);
+    assert_eq!(done.ops, vec![("set", 2000), ("del", 500), ("swap", 1)]);
+    assert_eq!(done.keys, 1500);
+    assert_eq!(done.keys, core.len() as u64);
+    assert!(done.bytes_total > 2000 * 100);
+    assert_eq!(done.bytes_read, done.bytes_total);
+    assert_eq!(done.percent(), 100.0);
+    let line = done.to_string();
+    assert!(line.starts_with("WAL replay done: "), "{}", line);
+    assert!(line.ends_with("; set 2000, del 500, swap 1"), "{}", line);
+    drop(reports);
+    drop(core);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn parallel_replay_matches_sequential() {
+    let base = std::env::temp_dir().join(format!("tmc-par-replay-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+    let open = |dir: &Path, threads| {
+        fs::create_dir_all(dir).unwrap();
+        let opts = PersistOptions {
+            segment_size: Some(256 << 10),
+            replay_threads: Some(threads),
+            ..PersistOptions::default()
+        };
+        let core =
+            PersistentCore::new(dir.join("cache.wal"), dir.join("cache.snapshot"), opts).unwrap();
+        core.set_tombstone_ttl(Some(Duration::from_secs(600)));
+        core
+    };
+    let far = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+        + 3_600_000;
+
+    let src = base.join("src");
+    let core = open(&src, 1);
+    for i in 0..20_000u64 {
+        let k = format!("k:{}", i % 3000);
+        let v = i.to_le_bytes().to_vec();
+        match i % 11 {
+            0 => drop(core.delete(&k).unwrap()),
+            1 => drop(core.pop(&k).unwrap()),
+            2 => core.set_ex(k, v, 3_600_000).unwrap(),
+            3 => drop(core.expire_at(&k, far + i).unwrap()),
+            4 => drop(core.set_bit(k, i % 64, i % 3 == 0).unwrap()),
+            5 => core.set_tagged(k, v, vec![format!("t:{}", i % 7)]).unwrap(),
+            6 => drop(core.undelete(&k).unwrap()),
+            // обмены — точки синхронизации потоков replay
+            7 => drop(core.swap(&k, &format!("k:{}", i * 7 % 3000)).unwrap()),
+            _ => core.set(k, v).unwrap(),
+        }
+        if i == 8000 {
+            // часть журнала покрыта снапшотом
+            core.compact().unwrap();
+        }
+    }
+    drop(core);
+
+    let state = |threads| {
+        let dir = base.join(format!("t{}", threads));
+        fs::create_dir_all(&dir).unwrap();
+        for entry in fs::read_dir(&src).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+        let core = open(&dir, threads);
+        let mut keys = core.keys_prefix("", Deadline::NONE).unwrap();
+        keys.sort();
+        let values: Vec<_> = keys
+            .iter()
+            .map(|k| {
+                let meta = core.get_with_meta(k).unwrap();
+                let expires_at = meta.ttl_ms.map(|ttl| meta.now + ttl);
+                (meta.value, meta.written_at, meta.version, expires_at)
+            })
+            .collect();
+        let tombstones = info_field(core.info().unwrap(), "tombstones");
+        let tagged: Vec<i64> = (0..7)
+            .map(|t| core.delete_by_tag(&format!("t:{}", t)).unwrap())
+            .collect();
+        let restored = (0..3000)
+            .filter(|i| core.undelete(&format!("k:{}", i)).unwrap())
+            .count();
+        (keys, values, tombstones, tagged, restored)
+    };
+    let sequential = state(1);
+    assert!(sequential.0.len() > 1000);
+    assert_eq!(state(4), sequential);
+    assert_eq!(state(3), sequential);
+    let _ = fs::remove_dir_all(&base);
+}
+
+// Журналы всех версий формата из tests/fixtures/wal: v1/v2 записаны сборкой с
+// шифрованием WAL, v3/v4 — последней до отметок времени записей,
+// v3-stamped/v4-stamped — последней до номера схемы в заголовке, v5/v6 —
+// нынешней. Чётные версии зашифрованы ключом 00 01 .. 1f.
+#[test]
+fn wal_fixtures_of_every_version() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/wal");
+    let base = std::env::temp_dir().join(format!("tmc-wal-fixtures-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+    let key: Vec<u8> = (0u8..32).collect();
+    let key = Arc::new(WalKey::from_bytes(&key).unwrap());
+    let open = |dir: &Path, encrypted: bool| {
+        let opts = PersistOptions {
+            key: encrypted.then(|| Arc::clone(&key)),
+            ..PersistOptions::default()
+        };
+        PersistentCore::new(dir.join("cache.wal"), dir.join("cache.snapshot"), opts)
+    };
+    let copy = |version: &str| {
+        let dir = base.join(version);
+        fs::create_dir_all(&dir).unwrap();
+        for entry in fs::read_dir(fixtures.join(version)).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+        dir
+    };
+    let check = |core: &PersistentCore, rich: bool| {
+        assert_eq!(core.get("fx:a"), Some(b"alpha2".to_vec()));
+        assert_eq!(core.get("fx:b"), Some(b"beta".to_vec()));
+        assert_eq!(core.get("fx:gone"), None);
+        assert_eq!(core.get("fx:popped"), None);
+        if !rich {
+            assert_eq!(core.len(), 2);
+            return;
+        }
+        assert!(core.get_bit("fx:bits", 3));
+        assert!(core.get_with_meta("fx:ttl").unwrap().ttl_ms.is_some());
+        assert!(core.get_with_meta("fx:b").unwrap().ttl_ms.is_some());
+        assert_eq!(core.get("fx:tagged"), Some(b"red".to_vec()));
+        assert_eq!(core.get("fx:dead"), None);
+        assert_eq!(core.get("fx:s1"), Some(b"two".to_vec()));
+        assert_eq!(core.get("fx:s2"), Some(b"one".to_vec()));
+        assert_eq!(core.len(), 7);
+    };
+    // (версия заголовка, схема записей) каждого сегмента
+    let headers = |dir: &Path| {
+        let mut headers = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext.len() == 6) {
+                let data = fs::read(&path).unwrap();
+                let version = u32::from_le_bytes(data[4..8].try_into().unwrap()) & 0xffff;
+                let schema = u32::from_le_bytes(data[16..20].try_into().unwrap());
+                headers.push((version, schema));
+            }
+        }
+        headers
+    };
+
+    for version in [
+        "v1",
+        "v2",
+        "v3",
+        "v4",
+        "v3-stamped",
+        "v4-stamped",
+        "v5",
+        "v6",
+    ] {
+        let dir = copy(version);
+        let encrypted = matches!(version, "v2" | "v4" | "v4-stamped" | "v6");
+        let rich = !matches!(version, "v1" | "v2");
+        let core = open(&dir, encrypted).unwrap();
+        check(&core, rich);
+        drop(core);
+        // старые журналы переписаны: снапшот и пустой сегмент нынешней схемы
+        let current = if encrypted { 6 } else { 5 };
+        assert_eq!(headers(&dir), vec![(current, WAL_SCHEMA)], "{}", version);
+        let upgraded = !matches!(version, "v5" | "v6");
+        assert_eq!(dir.join("cache.snapshot").exists(), upgraded, "{}", version);
+        let core = open(&dir, encrypted).unwrap();
+        check(&core, rich);
+    }
+
+    // схема новее сборки: журнал не открывается, файл не трогается
+    let dir = copy("v5");
+    let seg = dir.join("cache.wal.000001");
+    let mut data = fs::read(&seg).unwrap();
+    data[16..20].copy_from_slice(&(WAL_SCHEMA + 1).to_le_bytes());
+    fs::write(&seg, &data).unwrap();
+    match open(&dir, false) {
+        Err(CacheError::Unsupported(msg)) => assert!(msg.contains("schema"), "{}", msg),
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("WAL with a newer schema opened"),
+    }
+    assert_eq!(fs::read(&seg).unwrap(), data);
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn wal_lock_per_name() {
+    let dir = std::env::temp_dir().join(format!("tmc-wal-lock-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let open = |name: &str| {
+        PersistentCore::new(
+            dir.join(format!("{}.wal", name)),
+            dir.join(format!("{}.snapshot", name)),
+            PersistOptions::default(),
+        )
+    };
+
+    let sessions = open("sessions").unwrap();
+    // тот же журнал второй раз не открывается, пока открыт первый
+    match open("sessions") {
+        Err(CacheError::Busy(msg)) => assert!(msg.contains("sessions.lock"), "{}", msg),
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+    // другое имя в том же каталоге — независимый кэш
+    let jobs = open("jobs").unwrap();
+    sessions.set("k".into(), b"s".to_vec()).unwrap();
+    jobs.set("k".into(), b"j".to_vec()).unwrap();
+    let info = sessions.info().unwrap();
+    let path = |name: &str| ResponseValue::Str(dir.join(name).display().to_string());
+    assert_eq!(info_field(info.clone(), "wal_path"), path("sessions.wal"));
+    assert_eq!(
+        info_field(info.clone(), "snapshot_path"),
+        path("sessions.snapshot")
+    );
+    assert_eq!(info_field(info, "lock_path"), path("sessions.lock"));
+
+    // блокировка уходит вместе с журналом
+    drop(sessions);
+    let sessions = open("sessions").unwrap();
+    assert_eq!(sessions.get("k"), Some(b"s".to_vec()));
+    assert_eq!(jobs.get("k"), Some(b"j".to_vec()));
+    drop((sessions, jobs));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn hit_stats_by_prefix() {
+    let core = PersistentCore::ephemeral();
+    match core.set_hit_stats_prefix(1, "", 8) {
+        Err(CacheError::Unsupported(msg)) => assert!(msg.contains("stats_prefix_sep"), "{}", msg),
+        other => panic!("unexpected {:?}", other),
+    }
+    core.set_hit_stats_prefix(1, ":", 2).unwrap();
+    let addr = start_server(core).to_string();
+    let c = Client::connect(&addr).unwrap();
+
+    c.set("sessions:1", b"a").unwrap();
+    c.set("users:1", b"b").unwrap();
+    assert!(c.get("sessions:1").unwrap().is_some());
+    assert!(c.get("sessions:2").unwrap().is_none());
+    assert!(c.get_with_meta("sessions:1").unwrap().is_some());
+    // GetOrSet: вставка — промах, найденное значение — попадание
+    c.get_or_set("users:2", b"c").unwrap();
+    c.get_or_set("users:2", b"d").unwrap();
+    c.get_consistent(&["users:1", "users:3"]).unwrap();
+    // третий префикс сверх предела — в other
+    assert!(c.get("orders:1").unwrap().is_none());
+    assert!(c.get("orders:2").unwrap().is_none());
+
+    let stats = c.stats().unwrap();
+    assert_eq!(info_field(stats.clone(), "hits"), ResponseValue::Int(4));
+    assert_eq!(info_field(stats.clone(), "misses"), ResponseValue::Int(5));
+    let by_prefix = match info_field(stats.clone(), "by_prefix") {
+        ResponseValue::Nested(by_prefix) => by_prefix,
+        other => panic!("by_prefix is {:?}", other),
+    };
+    let names: Vec<&str> = by_prefix.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(names, ["users", "sessions"]);
+    let prefix = |name: &str| match info_field(by_prefix.clone(), name) {
+        ResponseValue::Nested(fields) => fields,
+        other => panic!("{} is {:?}", name, other),
+    };
+    let sessions = prefix("sessions");
+    assert_eq!(info_field(sessions.clone(), "hits"), ResponseValue::Int(2));
+    assert_eq!(
+        info_field(sessions.clone(), "misses"),
+        ResponseValue::Int(1)
+    );
+    assert_eq!(
+        info_field(sessions, "hit_rate"),
+        ResponseValue::Float(2.0 / 3.0)
+    );
+    let users = prefix("users");
+    assert_eq!(info_field(users.clone(), "hits"), ResponseValue::Int(2));
+    assert_eq!(info_field(users, "misses"), ResponseValue::Int(2));
+    match info_field(stats, "other") {
+        ResponseValue::Nested(other) => {
+            assert_eq!(info_field(other.clone(), "misses"), ResponseValue::Int(2));
+            assert_eq!(info_field(other, "hit_rate"), ResponseValue::Float(0.0));
+        }
+        other => panic!("other is {:?}", other),
+    }
+}
+
+#[test]
+fn verify_wal_offline() {
+    let dir = std::env::temp_dir().join(format!("tmc-verify-wal-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let core = PersistentCore::new(
+        dir.join("cache.wal"),
+        dir.join("cache.snapshot"),
+        PersistOptions::default(),
+    )
+    .unwrap();
+    for i in 0..100 {
+        core.set(format!("k:{}", i), vec![b'v'; 64]).unwrap();
+    }
+    core.delete("k:0").unwrap();
+    core.swap("k:1", "k:2").unwrap();
+    drop(core);
+
+    let wal = dir.join("cache.wal");
+    let mut reports = Vec::new();
+    let report = wal::verify(&wal, None, &mut |p| reports.push(p.clone())).unwrap();
+    assert_eq!(report.status(), VerifyStatus::Clean);
+    assert_eq!(
+        (report.records, report.first_seq, report.last_seq),
+        (102, 1, 102)
+    );
+    assert_eq!(report.ops, [("set", 100), ("del", 1), ("swap", 1)]);
+    let last = reports.last().unwrap();
+    assert!(last.done && last.bytes_read == report.bytes, "{:?}", last);
+
+    // оборванная последняя запись: сервер её отбросит
+    let seg = dir.join("cache.wal.000001");
+    let data = fs::read(&seg).unwrap();
+    let mut torn = data.clone();
+    torn.extend_from_slice(&[80, 0, 0, 0, 103, 0]);
+    fs::write(&seg, &torn).unwrap();
+    let report = wal::verify(&wal, None, &mut |_| {}).unwrap();
+    assert_eq!(report.status(), VerifyStatus::Recoverable);
+    assert_eq!(report.torn, [(seg.clone(), data.len() as u64)]);
+    assert_eq!(report.records, 102);
+
+    // порча посередине: участок найден, записи после него целы
+    let mut bad = data.clone();
+    let at = data.len() / 2;
+    bad[at..at + 8].fill(0xff);
+    fs::write(&seg, &bad).unwrap();
+    let report = wal::verify(&wal, None, &mut |_| {}).unwrap();
+    assert_eq!(report.status(), VerifyStatus::Corrupt);
+    let [region] = &report.corrupt[..] else {
+        panic!("{:?}", report.corrupt)
+    };
+    assert!(
+        region.start <= at as u64 && at as u64 + 8 <= region.end,
+        "{:?}",
+        region
+    );
+    let lost = region.records_lost.unwrap();
+    assert!((1..=2).contains(&lost), "{:?}", region);
+    assert_eq!(report.records + lost, 102);
+    assert_eq!(report.last_seq, 102);
+    // файл verify не меняет
+    assert_eq!(fs::read(&seg).unwrap(), bad);
+
+    // журналы всех версий формата из tests/fixtures/wal целы
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/wal");
+    let key = WalKey::from_bytes(&(0u8..32).collect::<Vec<_>>()).unwrap();
+    for version in [
+        "v1",
+        "v2",
+        "v3",
+        "v3-stamped",
+        "v4",
+        "v4-stamped",
+        "v5",
+        "v6",
+    ] {
+        let encrypted = matches!(version, "v2" | "v4" | "v4-stamped" | "v6");
+        let wal = fixtures.join(version).join("cache.wal");
+        let report = wal::verify(&wal, encrypted.then_some(&key), &mut |_| {}).unwrap();
+        assert_eq!(
+            report.status(),
+            VerifyStatus::Clean,
+            "{}: {:?}",
+            version,
+            report
+        );
+        assert!(report.records > 0, "{}", version);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn acl_users_by_prefix() {
+    let dir = std::env::temp_dir().join(format!("tmc-acl-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("users.toml");
+    let toml = format!(
+        r#"# пользователи тестового сервера
+[[users]]
+name = "analytics"
+password_hash = "{}"
+allow_read = ["events:"]
+
+[[users]]
+name = "ingest"
+password_hash = "{}"
+allow_read = ["events:*"]
+allow_write = [
+    "events:",
+    "staging:",
+]
+
+[[users]]
+name = "root"
+password_hash = "{}"
+admin = true
+"#,
+        hash_password("a-pass"),
+        hash_password("i-pass"),
+        hash_password("r-pass"),
+    );
+    fs::write(&file, toml).unwrap();
+    let mut core = PersistentCore::ephemeral();
+    core.set_acl_file(&file).unwrap();
+    let addr = start_server(core).to_string();
+    let login = |user: &str, password: &str, multiplex: bool| {
+        let opts = ClientOptions {
+            user: Some(user.into()),
+            password: Some(password.into()),
+            multiplex,
+            ..Default::default()
+        };
+        Client::connect_with(&addr, opts)
+    };
+
+    // без входа — только Ping и Hello
+    let anon = Client::connect(&addr).unwrap();
+    anon.ping().unwrap();
+    assert!(matches!(
+        anon.get("events:1"),
+        Err(CacheError::Server(m)) if m.contains("requires Auth")
+    ));
+    assert!(matches!(
+        login("analytics", "wrong", false),
+        Err(CacheError::Server(_))
+    ));
+
+    for multiplex in [false, true] {
+        let ingest = login("ingest", "i-pass", multiplex).unwrap();
+        ingest.set("events:1", b"click").unwrap();
+        ingest.set("staging:1", b"x").unwrap();
+        assert!(matches!(
+            ingest.get("staging:1"),
+            Err(CacheError::Server(_))
+        ));
+        let analytics = login("analytics", "a-pass", multiplex).unwrap();
+        assert_eq!(analytics.get("events:1").unwrap().unwrap(), b"click");
+        match analytics.set("events:2", b"v") {
+            Err(CacheError::Server(m)) => {
+                assert!(m.contains("allow_write"), "{}", m)
+            }
+            other => panic!("{:?}", other),
+        }
+        // команды над всем кэшем — только у admin
+        assert!(matches!(
+            analytics.config_dump(),
+            Err(CacheError::Server(_))
+        ));
+    }
+
+    // admin-пользователь управляет таблицей без токена
+    let root = login("root", "r-pass", false).unwrap();
+    let analytics = login("analytics", "a-pass", false).unwrap();
+    analytics.get("events:1").unwrap();
+    let clients = root.client_list().unwrap();
+    assert!(clients.iter().any(|c| c.user == "analytics"));
+    root.acl_set_user(&AclUser {
+        name: "ops".into(),
+        password_hash: hash_password("o-pass"),
+        allow_read: vec!["".into()],
+        ..Default::default()
+    })
+    .unwrap();
+    let names: Vec<_> = root
+        .acl_list()
+        .unwrap()
+        .into_iter()
+        .map(|u| {
+            assert!(u.password_hash.is_empty());
+            u.name
+        })
+        .collect();
+    assert_eq!(names, ["analytics", "ingest", "ops", "root"]);
+    let ops = login("ops", "o-pass", false).unwrap();
+    assert!(ops.get("staging:1").is_ok());
+    // глубина истории — не чтение: читателю всех ключей её не поменять
+    for prefix in ["staging:", ""] {
+        assert!(matches!(
+            ops.set_history_depth(prefix, 3),
+            Err(CacheError::Server(_))
+        ));
+    }
+    let ingest = login("ingest", "i-pass", false).unwrap();
+    ingest.set_history_depth("events:", 3).unwrap();
+    match ingest.set_history_depth("", 3) {
+        Err(CacheError::Server(m)) => assert!(m.contains("admin = false"), "{}", m),
+        other => panic!("{:?}", other),
+    }
+    root.set_history_depth("", 3).unwrap();
+    assert!(root.acl_del_user("analytics").unwrap());
+    assert!(!root.acl_del_user("analytics").unwrap());
+    // открытое соединение удалённого пользователя получает отказ
+    assert!(matches!(
+        analytics.get("events:1"),
+        Err(CacheError::Server(m)) if m.contains("no longer exists")
+    ));
+
+    // файл переписан и читается сервером снова
+    let mut core = PersistentCore::ephemeral();
+    core.set_acl_file(&file).unwrap();
+    let text = fs::read_to_string(&file).unwrap();
+    assert!(
+        text.contains("\"ops\"") && !text.contains("analytics"),
+        "{}",
+        text
+    );
+    let dump = root.config_dump().unwrap();
+    assert!(dump
+        .iter()
+        .any(|(k, v)| k == "acl_users" && *v == ResponseValue::Int(3)));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn chaos_faults() {
+    let bad = |opts: ChaosOptions| {
+        PersistentCore::ephemeral()
+            .set_chaos(Some(opts))
+            .unwrap_err()
+    };
+    for (opts, msg) in [
+        (
+            ChaosOptions {
+                error_rate: 1.5,
+                ..Default::default()
+            },
+            "error_rate must be between 0 and 1",
+        ),
+        (
+            ChaosOptions {
+                error_rate: 0.6,
+                disconnect_rate: 0.6,
+                ..Default::default()
+            },
+            "must not exceed 1",
+        ),
+        (
+            ChaosOptions {
+                latency_ms: Some((50, 10)),
+                ..Default::default()
+            },
+            "min above max",
+        ),
+    ] {
+        let e = bad(opts).to_string();
+        assert!(e.contains(msg), "{}", e);
+    }
+
+    let counter = |core: &PersistentCore, name: &str| match core
+        .info()
+        .unwrap()
+        .into_iter()
+        .find(|(k, _)| k == name)
+    {
+        Some((_, ResponseValue::Int(n))) => n,
+        other => panic!("{} is {:?}", name, other),
+    };
+
+    // обрыв на каждой команде: set повторяется один раз на новом соединении,
+    // pop — нет, и ни одна не выполняется
+    let (core, addr) = start_chaos_server(ChaosOptions {
+        disconnect_rate: 1.0,
+        ..Default::default()
+    });
+    let c = Client::connect(&addr).unwrap();
+    assert!(matches!(c.set("k", b"v"), Err(CacheError::Network(_))));
+    assert_eq!(counter(&core, "chaos_disconnects"), 2);
+    assert!(matches!(c.pop("k"), Err(CacheError::Network(_))));
+    assert_eq!(counter(&core, "chaos_disconnects"), 3);
+    assert_eq!(core.get("k"), None);
+
+    // ошибка — ответ сервера: не повторяется и соединение не рвёт
+    let (core, addr) = start_chaos_server(ChaosOptions {
+        error_rate: 1.0,
+        ..Default::default()
+    });
+    let c = Client::connect(&addr).unwrap();
+    match c.set("k", b"v") {
+        Err(CacheError::Server(m)) => assert_eq!(m, INJECTED_ERROR),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(counter(&core, "chaos_errors"), 1);
+    assert_eq!(core.get("k"), None);
+
+    // с seed клиент видит те же сбои в том же порядке; успешные set
+    // выполнены, остальные — нет
+    let outcomes = |multiplex: bool| {
+        let (core, addr) = start_chaos_server(ChaosOptions {
+            error_rate: 0.2,
+            disconnect_rate: 0.1,
+            seed: Some(7),
+            latency_ms: Some((0, 2)),
+        });
+        let opts = ClientOptions {
+            multiplex,
+            ..Default::default()
+        };
+        let c = Client::connect_with(&addr, opts).unwrap();
+        let seen: Vec<&str> = (0..200)
+            .map(|i| {
+                let key = format!("k:{}", i);
+                let seen = match c.set(&key, b"v") {
+                    Ok(()) => "ok",
+                    Err(CacheError::Server(m)) if m == INJECTED_ERROR => "error",
+                    Err(CacheError::Network(_)) => "dropped",
+                    Err(e) => panic!("{:?}", e),
+                };
+                assert_eq!(core.get(&key).is_some(), seen == "ok", "{}", key);
+                seen
+            })
+            .collect();
+        let errors = seen.iter().filter(|s| **s == "error").count() as i64;
+        assert_eq!(counter(&core, "chaos_errors"), errors);
+        let dump = core.config_dump().unwrap();
+        assert!(dump.contains(&("chaos_seed".into(), ResponseValue::Int(7))));
+        seen
+    };
+    let first = outcomes(false);
+    assert_eq!(outcomes(false), first);
+    for kind in ["ok", "error", "dropped"] {
+        assert!(first.contains(&kind), "{:?}", first);
+    }
+    // мультиплексированный клиент тоже переподключается после обрыва
+    let mux = outcomes(true);
+    let after_drop = mux.iter().skip_while(|s| **s != "dropped").skip(1);
+    assert!(after_drop.clone().any(|s| *s == "ok"), "{:?}", mux);
+}
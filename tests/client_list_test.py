@@ -0,0 +1,69 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import socket
+import struct
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5032
+ADDR = f"127.0.0.1:{PORT}"
+TOKEN = "s3cret"
+
+
+def server():
+    serve(PORT, persistence=False, admin_token=TOKEN)
+
+
+def expect_error(call, message):
+    try:
+        call()
+        raise AssertionError(f"expected error containing {message!r}")
+    except RuntimeError as e:
+        assert message in str(e), e
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    admin = TinyCache(ADDR, admin_token=TOKEN)
+    expect_error(lambda: c.client_list(), "require an admin token")
+    expect_error(lambda: c.client_kill(1), "require an admin token")
+    expect_error(lambda: TinyCache(ADDR, admin_token="wrong").client_list(), "invalid admin token")
+
+    # соединение, которое держит кто-то посторонний: Ping по протоколу 1
+    idle = socket.create_connection(("127.0.0.1", PORT))
+    body = bytes([22, 0, 0, 0])  # bincode: CacheCommand::Ping
+    idle.sendall(struct.pack("<I", len(body)) + body)
+    assert idle.recv(64)
+    peer = "%s:%d" % idle.getsockname()
+
+    clients = admin.client_list()
+    mine = [x for x in clients if x["peer"] == peer]
+    assert len(mine) == 1, clients
+    entry = mine[0]
+    assert entry["commands"] == 1 and entry["last_command"] == "Ping", entry
+    assert entry["bytes_in"] == 8 and entry["idle_ms"] >= 0, entry
+    assert [x["id"] for x in clients] == sorted(x["id"] for x in clients)
+    assert admin.info()["connected_clients"] == len(clients)
+
+    assert admin.client_kill(entry["id"]) is True
+    idle.settimeout(5)
+    assert idle.recv(64) == b""
+    deadline = time.time() + 5
+    while any(x["id"] == entry["id"] for x in admin.client_list()):
+        assert time.time() < deadline, "killed connection is still listed"
+        time.sleep(0.01)
+    assert admin.client_kill(entry["id"]) is False
+    # остальные соединения живы
+    c.set("k", b"v")
+    assert c.get("k") == b"v"
+
+    p.terminate()
+    p.join()
+    print("CLIENT LIST TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
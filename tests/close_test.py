@@ -0,0 +1,120 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import subprocess
+import sys
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5024
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def start_server():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def expect_closed(fn, *args):
+    try:
+        fn(*args)
+    except RuntimeError as e:
+        assert "client is closed" in str(e), e
+    else:
+        raise AssertionError(f"{fn.__name__} must fail after close()")
+
+
+def test_close():
+    with TinyCache(ADDR) as c:
+        c.set("c:k", b"v")
+        assert c.get("c:k") == b"v" and not c.closed
+    assert c.closed
+    # без молчаливого переподключения
+    expect_closed(c.get, "c:k")
+    expect_closed(c.set, "c:k", b"w")
+    expect_closed(c.len)
+    expect_closed(lambda: list(c))
+    expect_closed(c.subscribe, ["news"])
+    c.close()  # повторный close безвреден
+
+    c = TinyCache(ADDR)
+    it = c.keys_iter("c:")
+    c.close()
+    expect_closed(next, it)
+    assert TinyCache(ADDR).get("c:k") == b"v"
+    print("close OK")
+
+
+def child(c, n, results):
+    for i in range(50):
+        c.set(f"f:{n}:{i}", f"{n}-{i}".encode())
+    results.put(all(c.get(f"f:{n}:{i}") == f"{n}-{i}".encode() for i in range(50)))
+
+
+def test_fork_and_restart(p):
+    c = TinyCache(ADDR)
+    c.set("f:parent", b"p")
+    # дочерние процессы не трогают соединения родителя из пула
+    results = mp.Queue()
+    procs = [mp.Process(target=child, args=(c, n, results)) for n in range(4)]
+    for proc in procs:
+        proc.start()
+    for proc in procs:
+        proc.join()
+        assert proc.exitcode == 0
+    assert all(results.get() for _ in procs)
+    assert c.get("f:parent") == b"p"
+
+    # перезапуск сервера: простаивающее соединение мёртвое, get повторяется на новом
+    p.terminate()
+    p.join()
+    p = start_server()
+    assert c.get("f:parent") is None
+    c.set("f:parent", b"again")
+    assert c.get("f:parent") == b"again"
+    print("fork and restart OK")
+    return p
+
+
+SHUTDOWN_SCRIPT = f"""
+from tiny_mp_cache import TinyCache
+c = TinyCache("{ADDR}")
+c.set("s:k", b"v")
+for _ in range(10):
+    c.get("s:k")
+holder = [c, c.keys_iter()]
+"""
+
+
+def test_interpreter_shutdown():
+    # клиент без close() на выходе интерпретатора не шумит
+    res = subprocess.run([sys.executable, "-c", SHUTDOWN_SCRIPT], capture_output=True, text=True, timeout=30)
+    assert res.returncode == 0 and res.stderr == "", res.stderr
+    print("interpreter shutdown OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = start_server()
+    test_close()
+    p = test_fork_and_restart(p)
+    test_interpreter_shutdown()
+    p.terminate()
+    p.join()
+    print("CLOSE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
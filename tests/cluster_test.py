@@ -0,0 +1,138 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import time
+from tiny_mp_cache import serve, TinyCache, TinyCacheCluster
+
+PORTS = [5015, 5016, 5017]
+ADDRS = [f"127.0.0.1:{port}" for port in PORTS]
+
+DATA = {f"c:{i:04d}": f"v{i}".encode() for i in range(3000)}
+
+
+def server(port):
+    serve(port, persistence=False)
+
+
+def start_server(port):
+    p = mp.Process(target=server, args=(port,), daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(f"127.0.0.1:{port}").len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def test_routing_and_fan_out():
+    cluster = TinyCacheCluster(ADDRS)
+    cluster.set_many(DATA)
+    assert cluster.len() == len(DATA)
+    assert sorted(cluster.keys("c:*")) == sorted(DATA)
+    # страница сортированного списка собирается из голов всех узлов
+    assert cluster.keys("c:*", sort=True, offset=5, limit=3) == sorted(DATA)[5:8]
+    assert cluster.get_many(["c:0001", "missing", "c:2999"]) == [b"v1", None, b"v2999"]
+
+    # каждый ключ лежит ровно на своём узле
+    servers = {addr: TinyCache(addr) for addr in ADDRS}
+    for key in list(DATA)[:300]:
+        owner = cluster.node_for(key)
+        for addr, c in servers.items():
+            assert (c.get(key) is not None) == (addr == owner), (key, addr, owner)
+
+    # другой клиент с тем же списком узлов видит то же кольцо
+    other = TinyCacheCluster(list(ADDRS))
+    assert all(other.node_for(k) == cluster.node_for(k) for k in DATA)
+
+    report = cluster.rebalance_report()
+    assert sum(r["keys"] for r in report.values()) == len(DATA)
+    assert abs(sum(r["ring_share"] for r in report.values()) - 1.0) < 1e-9
+    for addr, r in report.items():
+        # 160 виртуальных узлов дают разброс в пределах нескольких процентов
+        assert 0.2 < r["key_share"] < 0.47, report
+        assert r["ejected_for"] is None
+        assert servers[addr].len() == r["keys"]
+
+    assert cluster.pop("c:0000") == b"v0"
+    assert cluster.delete("c:0000") == 0
+    assert cluster.delete_many(["c:0001", "c:0002", "missing"]) == 2
+    print("routing and fan-out OK")
+
+
+def test_scan():
+    cluster = TinyCacheCluster(ADDRS)
+    expected = {k: v for k, v in DATA.items() if k.startswith("c:1")}
+    seen = {}
+    cursor, pages = 0, 0
+    while True:
+        cursor, items = cluster.scan_items(cursor, prefix="c:1", count=100)
+        assert len(items) <= 100
+        for k, v in items:
+            assert k not in seen, k
+            seen[k] = v
+        pages += 1
+        if cursor == 0:
+            break
+    assert seen == expected
+    assert pages >= len(expected) // 100
+    print("scan OK")
+
+
+def test_ejection(procs):
+    cluster = TinyCacheCluster(ADDRS, max_failures=2, eject_seconds=1.0)
+    victim = ADDRS[1]
+    key = next(k for k in DATA if cluster.node_for(k) == victim)
+    stop_server(procs[1])
+
+    for _ in range(2):
+        try:
+            cluster.get(key)
+            raise AssertionError("dead node must fail")
+        except RuntimeError as e:
+            assert victim in str(e), e
+
+    # узел выброшен: его ключи уходят на живые узлы, fan-out его пропускает
+    assert cluster.node_for(key) != victim
+    cluster.set(key, b"moved")
+    assert cluster.get(key) == b"moved"
+    assert len(cluster.keys("c:*")) == cluster.len() < len(DATA)
+    report = cluster.rebalance_report()
+    assert report[victim]["keys"] is None and report[victim]["ejected_for"] > 0, report
+
+    # после перезапуска и истечения срока узел возвращается в кольцо
+    procs[1] = start_server(PORTS[1])
+    time.sleep(1.1)
+    assert cluster.get(key) is None
+    assert cluster.node_for(key) == victim
+    assert cluster.rebalance_report()[victim]["keys"] == 0
+    print("ejection OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    procs = [start_server(port) for port in PORTS]
+    try:
+        try:
+            TinyCacheCluster([])
+            raise AssertionError("empty cluster must be rejected")
+        except ValueError:
+            pass
+        test_routing_and_fan_out()
+        test_scan()
+        test_ejection(procs)
+    finally:
+        for p in procs:
+            stop_server(p)
+    print("CLUSTER TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
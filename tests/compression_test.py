@@ -0,0 +1,63 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5049
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    srv = mp.Process(target=server, daemon=True)
+    srv.start()
+    try:
+        plain = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0, allow_pickle=True)
+        z = TinyCache(ADDR, compress="zstd", compress_min=1024, allow_pickle=True)
+        l4 = TinyCache(ADDR, compress="lz4", compress_min=1024)
+        big = b"0123456789abcdef" * 1000
+
+        z.set("z", big)
+        l4.set("l", memoryview(bytearray(big)))
+        z.setex("ttl", 60, bytearray(big))
+        for key in ("z", "l", "ttl"):
+            # клиент без compress читает те же байты
+            assert plain.get(key) == big
+            assert z.get(key) == big and l4.get(key) == big
+        assert plain.type("z", detail=True)["encoding"] == "zstd"
+        assert plain.type("l", detail=True)["encoding"] == "lz4"
+        assert plain.type("z", detail=True)["length"] < len(big)
+
+        # короче compress_min — как есть
+        z.set("small", b"abc" * 10)
+        assert plain.type("small", detail=True)["encoding"] == "raw"
+
+        z.update({"m1": big, "m2": b"x"})
+        assert plain.type("m1", detail=True)["encoding"] == "zstd"
+        assert plain.get("m1") == big and plain.get("m2") == b"x"
+        z.set_json("j", {"text": "a" * 5000})
+        assert plain.get_json("j") == {"text": "a" * 5000}
+        z.set_obj("o", list(range(2000)))
+        assert plain.get_obj("o") == list(range(2000))
+
+        # namespace наследует compress
+        z.namespace("ns").set("k", big)
+        assert plain.type("ns:k", detail=True)["encoding"] == "zstd"
+
+        try:
+            TinyCache(ADDR, compress="gzip")
+        except ValueError as e:
+            assert "gzip" in str(e), e
+        else:
+            raise AssertionError("compress='gzip' accepted")
+    finally:
+        srv.terminate()
+        srv.join()
+    print("COMPRESSION TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,80 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+from tiny_mp_cache import serve, TinyCache, TinyCacheLocal
+
+PORT = 5047
+ADDR = f"127.0.0.1:{PORT}"
+TOKEN = "s3cret"
+
+
+def server():
+    serve(PORT, persistence=False, admin_token=TOKEN, max_bit_offset=64)
+
+
+def expect_error(call, message):
+    try:
+        call()
+        raise AssertionError(f"expected error containing {message!r}")
+    except RuntimeError as e:
+        assert message in str(e), e
+
+
+def test_server():
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    admin = TinyCache(ADDR, admin_token=TOKEN)
+    expect_error(lambda: c.config_set("rate_limit_per_conn", 5), "require an admin token")
+    expect_error(lambda: c.config_get(), "require an admin token")
+    expect_error(lambda: c.config(), "ConfigDump requires an admin token")
+
+    cfg = admin.config_get()
+    assert cfg["max_bit_offset"] == 64 and cfg["rate_limit_per_conn"] == 0, cfg
+    expect_error(lambda: c.setbit("b", 100, True), "over the server limit of 64")
+    admin.config_set("max_bit_offset", 1000)
+    c.setbit("b", 100, True)
+    assert admin.config_get("max_bit_offset") == {"max_bit_offset": 1000}
+
+    admin.config_set("verify_values", True)
+    assert c.info()["verify_values"] == 1
+    admin.config_set("verify_values", False)
+    admin.config_set("tombstone_ttl_secs", 30)
+    admin.config_set("idle_timeout_secs", None)
+    assert admin.config_get("tombstone_ttl_secs") == {"tombstone_ttl_secs": 30}
+
+    for name in ["wal_dir", "port", "fsync"]:
+        expect_error(lambda: admin.config_set(name, "x"), f"{name} cannot change at runtime")
+    expect_error(lambda: admin.config_set("read_only", True), "through SetReadOnly")
+    expect_error(lambda: admin.config_set("maxmemory", 5), "unknown setting")
+    expect_error(lambda: admin.config_set("rate_limit_per_ip", "fast"), "rate_limit_per_ip expects")
+
+    dump = admin.config()
+    assert dump["max_bit_offset"] == 1000 and dump["tombstone_ttl_secs"] == 30, dump
+    assert dump["persistence"] == "none" and dump["role"] == "primary", dump
+    assert dump["max_command_size"] == 1_000_000, dump
+    # токен не выдаётся
+    assert dump["admin_token"] == "set", dump
+    assert TOKEN not in dump.values(), dump
+    print("server config OK")
+
+
+def test_local():
+    local = TinyCacheLocal(persistence=False)
+    local.config_set("dedup_min_size", 64)
+    assert local.config_get("dedup_min_size") == {"dedup_min_size": 64}
+    expect_error(lambda: local.config_set("wal_dir", "/tmp"), "cannot change at runtime")
+    assert local.config()["dedup_min_size"] == 64
+    print("local config OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    test_server()
+    p.terminate()
+    p.join()
+    test_local()
+    print("CONFIG TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
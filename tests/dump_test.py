@@ -0,0 +1,192 @@
+#!/usr/bin/env python3
+import base64
+import glob
+import json
+import multiprocessing as mp
+import os
+import struct
+import time
+import zlib
+from tiny_mp_cache import serve, export_to_file, TinyCache
+
+PORT = 5011
+ADDR = f"127.0.0.1:{PORT}"
+WAL_FILE = "tiny-mp-cache.wal"
+SNAPSHOT_FILE = "tiny-mp-cache.snapshot"
+DUMPS = ["server.dump", "server.jsonl", "client.dump", "client.jsonl", "broken.dump", "broken.jsonl"]
+
+DATA = {f"d:{i:04d}": os.urandom(i % 50) for i in range(2500)}
+
+
+def cleanup():
+    for f in [WAL_FILE, SNAPSHOT_FILE] + DUMPS + glob.glob(WAL_FILE + ".[0-9]*"):
+        if os.path.exists(f):
+            os.remove(f)
+
+
+def server(kwargs):
+    serve(PORT, **kwargs)
+
+
+def start_server(**kwargs):
+    p = mp.Process(target=server, args=(kwargs,), daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def read_binary(path):
+    with open(path, "rb") as f:
+        data = f.read()
+    assert data[:4] == b"TMCD" and struct.unpack_from("<I", data, 4)[0] == 1
+    (crc,) = struct.unpack_from("<I", data, len(data) - 4)
+    assert zlib.crc32(data[:-4]) == crc, "bad dump checksum"
+    (count,) = struct.unpack_from("<Q", data, len(data) - 12)
+    pairs = []
+    pos, end = 8, len(data) - 12
+    while pos < end:
+        (klen,) = struct.unpack_from("<I", data, pos)
+        key = data[pos + 4:pos + 4 + klen].decode()
+        pos += 4 + klen
+        (vlen,) = struct.unpack_from("<I", data, pos)
+        pairs.append((key, data[pos + 4:pos + 4 + vlen]))
+        pos += 4 + vlen
+    assert pos == end and len(pairs) == count
+    return pairs
+
+
+def read_json(path):
+    with open(path) as f:
+        return [(e["key"], base64.b64decode(e["value"])) for e in map(json.loads, f)]
+
+
+def test_server_export():
+    cleanup()
+    p = start_server()
+    c = TinyCache(ADDR)
+    for k, v in DATA.items():
+        c.set(k, v)
+
+    assert c.export("server.dump") == len(DATA)
+    pairs = read_binary("server.dump")
+    assert [k for k, _ in pairs] == sorted(DATA), "server dump must be sorted by key"
+    assert dict(pairs) == DATA
+
+    assert c.export("server.jsonl", format="json") == len(DATA)
+    assert read_json("server.jsonl") == pairs
+
+    try:
+        c.export("server.xml", format="xml")
+        raise AssertionError("unknown format must be rejected")
+    except RuntimeError as e:
+        assert "unknown dump format" in str(e), e
+    stop_server(p)
+    print("server export OK")
+
+
+def test_client_export():
+    cleanup()
+    p = start_server(persistence=False)
+    c = TinyCache(ADDR)
+    for k, v in DATA.items():
+        c.set(k, v)
+
+    # больше одной страницы ScanItems
+    assert export_to_file(ADDR, "client.dump") == len(DATA)
+    assert dict(read_binary("client.dump")) == DATA
+    assert export_to_file(ADDR, "client.jsonl", format="json") == len(DATA)
+    assert dict(read_json("client.jsonl")) == DATA
+    stop_server(p)
+
+    try:
+        export_to_file(ADDR, "client.dump", format="xml")
+        raise AssertionError("unknown format must be rejected")
+    except ValueError as e:
+        assert "unknown dump format" in str(e), e
+    print("client export OK")
+
+
+def clear(c):
+    for k in c.keys("d:*"):
+        c.delete(k)
+
+
+def test_import():
+    cleanup()
+    p = start_server()
+    c = TinyCache(ADDR)
+    for k, v in DATA.items():
+        c.set(k, v)
+    c.export("server.dump")
+    c.export("server.jsonl", format="json")
+    clear(c)
+
+    # replace=False оставляет существующие ключи
+    c.set("d:0001", b"mine")
+    batches = int(c.info()["wal_batches"])
+    assert c.import_dump("server.dump") == len(DATA) - 1
+    assert c.get("d:0001") == b"mine"
+    info = c.info()
+    assert info["last_import_status"] == "ok", info
+    assert info["import_keys_loaded"] == len(DATA) - 1, info
+    assert info["import_keys_skipped"] == 1, info
+    assert info["import_bytes_loaded"] == info["import_bytes_total"] == os.path.getsize("server.dump")
+    # пары пишутся в WAL батчами, а не по одной
+    assert int(info["wal_batches"]) - batches <= 3, info
+
+    assert c.import_dump("server.jsonl", replace=True) == len(DATA)
+    assert c.get("d:0001") == DATA["d:0001"]
+    stop_server(p)
+
+    # импорт пережил рестарт
+    p = start_server()
+    c = TinyCache(ADDR)
+    assert {k: c.get(k) for k in c.keys("d:*")} == DATA
+    clear(c)
+
+    with open("server.dump", "rb") as f:
+        data = bytearray(f.read())
+    data[100] ^= 0xFF
+    with open("broken.dump", "wb") as f:
+        f.write(data)
+    with open("server.jsonl") as f:
+        lines = f.readlines()
+    with open("broken.jsonl", "w") as f:
+        f.writelines(lines[:10] + ["{not json\n"] + lines[10:])
+
+    for path, message in [("broken.dump", "checksum mismatch"), ("broken.jsonl", "line 11")]:
+        try:
+            c.import_dump(path)
+            raise AssertionError(f"{path} must be rejected")
+        except RuntimeError as e:
+            assert message in str(e), e
+        # битый файл не применяется даже частично
+        assert c.len() == 0
+        assert c.info()["last_import_status"].startswith("err after 0 keys"), c.info()
+    stop_server(p)
+    print("import OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    test_server_export()
+    test_client_export()
+    test_import()
+    cleanup()
+    print("DUMP TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
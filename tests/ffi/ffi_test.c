@@ -0,0 +1,63 @@
+// Проверка C ABI (фича `ffi`) против запущенного сервера:
+//   cargo build --no-default-features --features ffi
+//   cargo run --example serve --no-default-features -- 127.0.0.1:5022 &
+//   cc -Iinclude tests/ffi/ffi_test.c -Ltarget/debug -ltiny_mp_cache -o ffi_test
+//   LD_LIBRARY_PATH=target/debug ./ffi_test 127.0.0.1:5022
+#include <stdio.h>
+#include <string.h>
+
+#include "tiny_mp_cache.h"
+
+#define CHECK(cond)                                                     \
+    do {                                                                \
+        if (!(cond)) {                                                  \
+            const char *err = tmc_last_error();                         \
+            fprintf(stderr, "%s:%d: check failed: %s (last error: %s)\n", \
+                    __FILE__, __LINE__, #cond, err ? err : "none");     \
+            return 1;                                                   \
+        }                                                               \
+    } while (0)
+
+int main(int argc, char **argv) {
+    const char *addr = argc > 1 ? argv[1] : "127.0.0.1:5022";
+
+    // до сервера не достучаться: NULL и текст ошибки
+    CHECK(tmc_client_new("127.0.0.1:1") == NULL);
+    CHECK(tmc_last_error() != NULL);
+
+    TmcClient *c = tmc_client_new(addr);
+    CHECK(c != NULL);
+    CHECK(tmc_last_error() == NULL);
+
+    const char value[] = "payload\0with-nul";
+    CHECK(tmc_set(c, "ffi:key", (const uint8_t *)value, sizeof(value)) == 0);
+
+    uint8_t *buf = NULL;
+    size_t len = 0;
+    CHECK(tmc_get(c, "ffi:key", &buf, &len) == 1);
+    CHECK(len == sizeof(value) && memcmp(buf, value, len) == 0);
+    tmc_free(buf, len);
+
+    // пустое значение: NULL допустим при нулевой длине
+    CHECK(tmc_set(c, "ffi:empty", NULL, 0) == 0);
+    CHECK(tmc_get(c, "ffi:empty", &buf, &len) == 1);
+    CHECK(len == 0);
+    tmc_free(buf, len);
+
+    CHECK(tmc_delete(c, "ffi:key") == 1);
+    CHECK(tmc_delete(c, "ffi:key") == 0);
+    CHECK(tmc_get(c, "ffi:key", &buf, &len) == 0);
+    CHECK(buf == NULL && len == 0);
+
+    // ошибки аргументов не валят процесс
+    CHECK(tmc_set(c, NULL, NULL, 0) == -1);
+    CHECK(strstr(tmc_last_error(), "key is NULL") != NULL);
+    CHECK(tmc_set(c, "ffi:bad", NULL, 3) == -1);
+    CHECK(tmc_delete(NULL, "ffi:key") == -1);
+
+    tmc_client_free(c);
+    tmc_client_free(NULL);
+    tmc_free(NULL, 0);
+    printf("FFI TEST PASSED\n");
+    return 0;
+}
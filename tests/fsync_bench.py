@@ -0,0 +1,55 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import os
+import tempfile
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5004
+ADDR = f"127.0.0.1:{PORT}"
+
+N_OPS = 2000
+N_WORKERS = 4
+VALUE = b"v" * 16  # маленькие значения: упираемся в запись WAL, а не в сеть
+
+
+def server(wal_dir: str, fsync: str):
+    serve(PORT, wal_dir=wal_dir, fsync=fsync)
+
+
+def writer(worker_id: int):
+    cache = TinyCache(ADDR)
+    for i in range(N_OPS // N_WORKERS):
+        cache.set(f"bench:{worker_id}:{i}", VALUE)
+
+
+def run_bench(fsync: str):
+    with tempfile.TemporaryDirectory() as wal_dir:
+        srv = mp.Process(target=server, args=(wal_dir, fsync), daemon=True)
+        srv.start()
+        time.sleep(0.5)
+
+        t0 = time.time()
+        workers = [mp.Process(target=writer, args=(wid,)) for wid in range(N_WORKERS)]
+        for p in workers:
+            p.start()
+        for p in workers:
+            p.join()
+        elapsed = time.time() - t0
+
+        assert TinyCache(ADDR).info()["wal_fsync"] == fsync
+        srv.terminate()
+        srv.join()
+
+    print(f"fsync={fsync:<9} sets={N_OPS} workers={N_WORKERS} "
+          f"elapsed={elapsed:.3f}s ops/s={N_OPS / elapsed:,.0f}")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    for fsync in ("never", "everysec", "always"):
+        run_bench(fsync)
+
+
+if __name__ == "__main__":
+    main()
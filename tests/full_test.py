@@ -20,7 +20,8 @@ def tcp_server():
 
 
 def uds_server():
-    serve_unix(UDS_PATH)
+    # TCP-сервер ещё держит WAL с именем по умолчанию
+    serve_unix(UDS_PATH, name="tiny-mp-cache-uds")
 
 
 def make_job_key(i: int) -> str:
@@ -47,7 +48,7 @@ def worker(worker_id: int, addr: str, processed_ids):
         if not keys:
             break
         for key in keys:
-            raw = cache.pop(key)
+            raw = cache.pop(key, None)
             if raw is None:
                 continue
             job = json.loads(raw.decode("utf-8"))
@@ -0,0 +1,62 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import sys
+import tempfile
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5005
+ADDR = f"127.0.0.1:{PORT}"
+
+N_OPS = 4000
+VALUE = b"v" * 16
+
+
+def server(wal_dir: str, fsync: str):
+    serve(PORT, wal_dir=wal_dir, fsync=fsync)
+
+
+def writer(worker_id: int, n_ops: int):
+    cache = TinyCache(ADDR)
+    for i in range(n_ops):
+        cache.set(f"gc:{worker_id}:{i}", VALUE)
+
+
+def run_bench(fsync: str, n_workers: int):
+    with tempfile.TemporaryDirectory() as wal_dir:
+        srv = mp.Process(target=server, args=(wal_dir, fsync), daemon=True)
+        srv.start()
+        time.sleep(0.5)
+
+        per_worker = N_OPS // n_workers
+        t0 = time.time()
+        workers = [mp.Process(target=writer, args=(wid, per_worker)) for wid in range(n_workers)]
+        for p in workers:
+            p.start()
+        for p in workers:
+            p.join()
+        elapsed = time.time() - t0
+
+        cache = TinyCache(ADDR)
+        total = per_worker * n_workers
+        assert cache.len() == total, (cache.len(), total)
+        info = cache.info()
+        per_batch = int(info["wal_batched_records"]) / max(int(info["wal_batches"]), 1)
+        srv.terminate()
+        srv.join()
+
+    print(f"fsync={fsync:<6} writers={n_workers:<3} sets={total} "
+          f"elapsed={elapsed:.3f}s ops/s={total / elapsed:,.0f} records/write={per_batch:.2f}")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    # конкурентные писатели: с group commit несколько Set делят один write + fsync
+    workers = [int(w) for w in sys.argv[1:]] or [1, 4, 16, 64]
+    for fsync in ("always", "never"):
+        for n in workers:
+            run_bench(fsync, n)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,51 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5039
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False, history_depth=2)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+
+    start = time.time()
+    for v in (b"good", b"also good", b"garbage"):
+        c.set("conf", v)
+    h = c.history("conf")
+    assert [v for _, v in h] == [b"good", b"also good"], h
+    assert all(start - 1 <= at <= time.time() + 1 for at, _ in h), h
+    assert c.history("missing") == []
+
+    # правило пространства имён длиннее глобального
+    ns = c.namespace("job")
+    ns.set_history_depth("", 0)
+    ns.set("k", b"1")
+    ns.set("k", b"2")
+    assert ns.history("k") == []
+    c.set("conf", b"fixed")
+    assert [v for _, v in c.history("conf")] == [b"also good", b"garbage"]
+    assert c.info()["history_keys"] == 1
+
+    try:
+        c.set_history_depth("", 10**6)
+    except Exception as e:
+        assert "history depth" in str(e), e
+    else:
+        raise AssertionError("too deep history must be rejected")
+
+    p.terminate()
+    p.join()
+    print("HISTORY TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
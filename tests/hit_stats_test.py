@@ -0,0 +1,44 @@
+#!/usr/bin/env python3
+from tiny_mp_cache import spawn_server, TinyCache
+
+
+def main():
+    with spawn_server(persistence=False) as srv:
+        c = TinyCache(srv.addr)
+        c.set("sessions:1", b"a")
+        assert c.get("sessions:1") == b"a"
+        assert c.get("sessions:2") is None
+        # без stats_prefix_depth — только общие счётчики
+        assert c.stats() == {"hits": 1, "misses": 1, "hit_rate": 0.5, "by_prefix": {}}
+
+    with spawn_server(
+        persistence=False, stats_prefix_depth=1, stats_prefix_sep=":", stats_max_prefixes=2
+    ) as srv:
+        c = TinyCache(srv.addr)
+        c.update({"sessions:1": b"a", "users:1": b"b"})
+        for _ in range(3):
+            assert c.get("sessions:1") == b"a"
+        assert c.get("sessions:404") is None
+        assert c.get_consistent(["users:1", "users:2"]) == [b"b", None]
+        # префиксы сверх предела — под "*"
+        assert c.get("orders:1") is None
+
+        stats = c.stats()
+        assert stats["by_prefix"]["sessions"]["hit_rate"] == 0.75, stats
+        assert stats["by_prefix"]["users"] == {"hits": 1, "misses": 1, "hit_rate": 0.5}, stats
+        assert list(stats["by_prefix"]) == ["sessions", "users", "*"], stats
+        assert stats["by_prefix"]["*"]["misses"] == 1, stats
+        assert (stats["hits"], stats["misses"]) == (4, 3), stats
+
+    try:
+        with spawn_server(persistence=False, stats_prefix_depth=1, stats_prefix_sep=""):
+            pass
+    except Exception as e:
+        assert "stats_prefix_sep" in str(e), e
+    else:
+        raise AssertionError("empty stats_prefix_sep must fail")
+    print("HIT STATS TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,110 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import tempfile
+import threading
+from tiny_mp_cache import spawn_server, TinyCache, IdAllocator
+
+
+def test_incr_range(c):
+    assert c.incr_range("ids", 10) == 0
+    assert c.incr_range("ids", 5) == 10
+    assert c.get("ids") == b"15"
+    for n in (0, -1):
+        try:
+            c.incr_range("ids", n)
+        except (ValueError, OverflowError):
+            pass
+        else:
+            raise AssertionError(f"n={n} must be rejected")
+    c.set("text", b"abc")
+    try:
+        c.incr_range("text", 1)
+    except RuntimeError as e:
+        assert "not an i64" in str(e), e
+    else:
+        raise AssertionError("incr_range on a non-integer must fail")
+    print("incr_range OK")
+
+
+def test_allocator(c):
+    ids = IdAllocator(c, "orders", block_size=10)
+    assert ids.remaining == 0 and ids.block_size == 10 and ids.key == "orders"
+    assert [ids.next_id() for _ in range(3)] == [1, 2, 3]
+    assert ids.remaining == 7 and c.get("orders") == b"10"
+    other = IdAllocator(c, "orders", block_size=10)
+    assert next(other) == 11
+    assert [ids.next_id() for _ in range(8)] == [4, 5, 6, 7, 8, 9, 10, 21]
+    assert c.get("orders") == b"30"
+    # в пространстве имён — счётчик вида
+    ns = IdAllocator(c.namespace("app"), "orders", block_size=2)
+    assert next(ns) == 1 and c.get("app:orders") == b"2" and ns.key == "orders"
+    try:
+        IdAllocator(c, "x", block_size=0)
+    except ValueError as e:
+        assert "block_size" in str(e), e
+    else:
+        raise AssertionError("block_size=0 must be rejected")
+    print("allocator OK")
+
+
+def test_threads(c):
+    ids = IdAllocator(c, "threads", block_size=7)
+    got = []
+
+    def run():
+        got.extend(ids.next_id() for _ in range(500))
+
+    workers = [threading.Thread(target=run) for _ in range(4)]
+    for w in workers:
+        w.start()
+    for w in workers:
+        w.join()
+    assert sorted(got) == list(range(1, 2001)), "ids repeat or skip within one allocator"
+    print("threads OK")
+
+
+def worker(addr, out):
+    ids = IdAllocator(TinyCache(addr), "procs", block_size=50)
+    out.put([ids.next_id() for _ in range(333)])
+
+
+def test_processes(addr):
+    out = mp.Queue()
+    procs = [mp.Process(target=worker, args=(addr, out)) for _ in range(4)]
+    for p in procs:
+        p.start()
+    got = [i for _ in procs for i in out.get(timeout=60)]
+    for p in procs:
+        p.join(60)
+        assert p.exitcode == 0, p.exitcode
+    assert len(set(got)) == len(got) == 4 * 333
+    # каждый процесс берёт 7 блоков и оставляет остаток последнего
+    assert max(got) <= 4 * 7 * 50
+    print("processes OK")
+
+
+def test_restart():
+    with tempfile.TemporaryDirectory() as d:
+        with spawn_server(wal_dir=d) as srv:
+            ids = IdAllocator(TinyCache(srv.addr), "ids", block_size=100)
+            first = [ids.next_id() for _ in range(5)]
+        # сервер убит, 95 номеров блока не использованы, но и не вернутся
+        with spawn_server(wal_dir=d) as srv:
+            ids = IdAllocator(TinyCache(srv.addr), "ids", block_size=100)
+            assert ids.next_id() == 101, first
+    print("restart OK")
+
+
+def main():
+    with spawn_server(persistence=False) as srv:
+        c = TinyCache(srv.addr)
+        test_incr_range(c)
+        test_allocator(c)
+        test_threads(c)
+        test_processes(srv.addr)
+    test_restart()
+    print("ID ALLOCATOR TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,53 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import socket
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5033
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False, idle_timeout_secs=1)
+
+
+def reaped(c):
+    return c.info()["reaped_connections"]
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    c.set("n", b"1")
+    before = reaped(c)
+
+    raw = socket.create_connection(("127.0.0.1", PORT))
+    raw.settimeout(5.0)
+    time.sleep(1.8)
+    # сервер закрыл простаивающее соединение сам
+    assert raw.recv(1) == b""
+
+    # соединение пула тоже закрыто, но команда доходит через новое
+    assert c.pop("n") == b"1"
+    assert reaped(c) >= before + 2
+
+    # ожидание get_blocking дольше таймаута соединение не теряет
+    def later():
+        time.sleep(1.8)
+        TinyCache(ADDR).set("late", b"v")
+
+    w = mp.Process(target=later)
+    w.start()
+    assert c.get_blocking("late", timeout=5.0) == b"v"
+    w.join()
+
+    p.terminate()
+    p.join()
+    print("IDLE REAPER TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,61 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import shutil
+from tiny_mp_cache import serve, TinyCache, TinyCacheLocal, InvalidKeyError
+
+PORT = 5043
+ADDR = f"127.0.0.1:{PORT}"
+WAL_DIR = "/tmp/tiny_mp_cache_key_normalization_test"
+
+NFC = "r\u00e9sum\u00e9"
+NFD = "re\u0301sume\u0301"
+
+
+def server():
+    serve(PORT, wal_dir=WAL_DIR, key_normalization="nfc")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    c.set(NFD, b"v")
+    assert c.get(NFC) == b"v"
+    assert c.keys("re\u0301*") == [NFC]
+    assert c.info()["key_normalization"] == "nfc"
+    for bad in ["nul\x00key", "line\nkey", "del\x7fkey"]:
+        try:
+            c.set(bad, b"v")
+        except InvalidKeyError as e:
+            assert "control character" in str(e), e
+        else:
+            raise AssertionError(f"{bad!r} must be rejected")
+    print("nfc OK")
+    p.terminate()
+    p.join()
+
+    # ключ, записанный без политики, не даёт поднять сервер с ней
+    local = TinyCacheLocal(wal_dir=WAL_DIR)
+    local.set(NFD, b"raw")
+    del local
+    try:
+        serve(PORT, wal_dir=WAL_DIR, key_normalization="nfc")
+        raise AssertionError("serve() must refuse")
+    except ValueError as e:
+        assert "do not fit key_normalization=nfc" in str(e), e
+    try:
+        serve(PORT, persistence=False, key_normalization="nfkc")
+        raise AssertionError("serve() must refuse")
+    except ValueError as e:
+        assert "expected none/nfc" in str(e), e
+    print("refuse OK")
+
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+    print("KEY NORMALIZATION TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
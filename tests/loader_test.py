@@ -0,0 +1,96 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import threading
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5050
+ADDR = f"127.0.0.1:{PORT}"
+
+CALLS = None
+
+
+def load(key):
+    with CALLS.get_lock():
+        CALLS.value += 1
+    # медленная база: одновременные промахи застают вызов
+    time.sleep(0.2)
+    if key == "missing":
+        return None
+    if key == "broken":
+        raise ConnectionError("database is down")
+    if key == "wrong":
+        return "text"
+    if key == "short":
+        return b"tmp", 0.3
+    if key == "forever":
+        return b"kept", None
+    return f"row:{key}".encode()
+
+
+def server():
+    serve(PORT, persistence=False, loader=load, loader_ttl_secs=60, loader_threads=2)
+
+
+def main():
+    global CALLS
+    mp.set_start_method("fork", force=True)
+    CALLS = mp.Value("i", 0)
+
+    for kwargs, err, text in [
+        (dict(loader=42), TypeError, "loader must be callable"),
+        (dict(loader=load, loader_ttl_secs=0), ValueError, "at least 1 ms"),
+        (dict(loader=load, replicate_from="127.0.0.1:1"), ValueError, "replicate_from"),
+    ]:
+        try:
+            serve(PORT, persistence=False, **kwargs)
+        except err as e:
+            assert text in str(e), e
+        else:
+            raise AssertionError(f"{kwargs} must raise {err.__name__}")
+
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+
+    c.set("cached", b"own")
+    assert c.get("cached") == b"own"
+    assert CALLS.value == 0
+
+    # восемь потоков промахиваются одновременно — один вызов загрузчика
+    got = []
+
+    def miss():
+        got.append(TinyCache(ADDR).get("user:1"))
+
+    threads = [threading.Thread(target=miss) for _ in range(8)]
+    for t in threads:
+        t.start()
+    for t in threads:
+        t.join()
+    assert got == [b"row:user:1"] * 8, got
+    assert CALLS.value == 1
+    assert c.get("user:1") == b"row:user:1" and CALLS.value == 1
+    assert 59_000 < c.pttl("user:1") <= 60_000
+
+    assert c.get("missing") is None and "missing" not in c
+    for key, text in [("broken", "database is down"), ("wrong", "must return bytes")]:
+        try:
+            c.get(key)
+        except Exception as e:
+            assert text in str(e), e
+        else:
+            raise AssertionError(f"get({key!r}) must raise")
+    # после ошибки сервер и соединение работают
+    assert c.get("user:2") == b"row:user:2"
+
+    assert c.get("short") == b"tmp" and 0 < c.pttl("short") <= 300
+    assert c.get("forever") == b"kept" and c.pttl("forever") == -1
+
+    p.terminate()
+    p.join()
+    print("LOADER TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,123 @@
+#!/usr/bin/env python3
+import gc
+import multiprocessing as mp
+import os
+import shutil
+import threading
+import time
+from tiny_mp_cache import serve, TinyCache, TinyCacheLocal
+
+PORT = 5021
+ADDR = f"127.0.0.1:{PORT}"
+DATA_DIR = "local-data"
+
+
+def cleanup():
+    if os.path.exists(DATA_DIR):
+        shutil.rmtree(DATA_DIR)
+
+
+def server():
+    serve(PORT, wal_dir=DATA_DIR)
+
+
+def start_server():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def test_threads_and_wal():
+    cleanup()
+    c = TinyCacheLocal(wal_dir=DATA_DIR)
+
+    def worker(n):
+        for i in range(500):
+            c.set(f"t:{n}:{i}", f"{n}-{i}".encode())
+        for i in range(0, 500, 5):
+            c.delete(f"t:{n}:{i}")
+
+    threads = [threading.Thread(target=worker, args=(n,)) for n in range(8)]
+    for t in threads:
+        t.start()
+    for t in threads:
+        t.join()
+    assert c.len() == 8 * 400
+    assert c.pop("t:0:1") == b"0-1"
+    assert c.get("t:0:0") is None
+    assert c.info()["persistence"] == "wal"
+
+    # ядро отпускает GIL: пока один поток ждёт ключ, другой успевает его записать
+    got = []
+    waiter = threading.Thread(target=lambda: got.append(c.get_blocking("t:late", timeout=5.0)))
+    waiter.start()
+    time.sleep(0.2)
+    start = time.time()
+    c.set("t:late", b"here")
+    waiter.join()
+    assert got == [b"here"] and time.time() - start < 2, got
+
+    expected = {k: c.get(k) for k in c.keys("t:*")}
+    assert len(expected) == 8 * 400
+    del c
+    gc.collect()
+
+    # WAL встроенного режима читает обычный сервер
+    p = start_server()
+    remote = TinyCache(ADDR)
+    assert {k: remote.get(k) for k in remote.keys("t:*")} == expected
+    remote.set("t:from-server", b"1")
+    stop_server(p)
+
+    # и наоборот
+    c = TinyCacheLocal(wal_dir=DATA_DIR)
+    assert c.get("t:from-server") == b"1"
+    assert c.len() == len(expected) + 1
+    print("threads and WAL OK")
+
+
+def test_ephemeral():
+    c = TinyCacheLocal(persistence=False)
+    c.set("k", b"v")
+    assert c.keys("*") == ["k"] and c.len() == 1
+    assert c.info()["persistence"] == "none"
+    try:
+        c.save()
+        raise AssertionError("save without persistence must fail")
+    except RuntimeError as e:
+        assert "persistence is disabled" in str(e), e
+    c.set_read_only(True)
+    try:
+        c.set("k", b"w")
+        raise AssertionError("read-only cache must reject writes")
+    except RuntimeError as e:
+        assert "read-only server" in str(e), e
+    c.set_read_only(False)
+    c.set("k", b"w")
+    assert c.get("k") == b"w"
+    print("ephemeral OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    test_threads_and_wal()
+    test_ephemeral()
+    cleanup()
+    print("LOCAL TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
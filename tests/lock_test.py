@@ -0,0 +1,154 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import time
+from tiny_mp_cache import spawn_server, TinyCache, TinyCacheLock, LockNotOwnedError
+
+
+def test_delete_if(c):
+    assert c.set_nx("k", b"mine", ttl=60)
+    assert not c.set_nx("k", b"theirs")
+    assert 0 < c.pttl("k") <= 60_000
+    assert not c.delete_if("k", b"theirs")
+    assert c.get("k") == b"mine"
+    assert c.delete_if("k", b"mine")
+    assert c.get("k") is None
+    assert not c.delete_if("k", b"mine")
+    # в пространстве имён — его ключ
+    ns = c.namespace("app")
+    assert ns.set_nx("k", b"v")
+    assert c.get("app:k") == b"v"
+    assert ns.delete_if("k", b"v") and c.get("app:k") is None
+    try:
+        c.set_nx("k", b"v", ttl=0)
+    except ValueError as e:
+        assert "at least 1 ms" in str(e), e
+    else:
+        raise AssertionError("ttl=0 must be rejected")
+    print("delete_if OK")
+
+
+def test_lock(c):
+    lock = TinyCacheLock(c, "job", ttl=10)
+    assert not lock.held and lock.token is None
+    assert lock.acquire(blocking=False)
+    assert lock.held and c.get("job") == lock.token and len(lock.token) == 16
+    assert 0 < c.pttl("job") <= 10_000
+    other = c.lock("job")
+    assert isinstance(other, TinyCacheLock)
+    assert other.token is None
+    t = time.monotonic()
+    assert not other.acquire(timeout=0.2)
+    assert time.monotonic() - t >= 0.2
+    assert not other.acquire(blocking=False)
+    for call, err, text in [
+        (lambda: lock.acquire(), RuntimeError, "already held"),
+        (lambda: other.acquire(blocking=False, timeout=1), ValueError, "non-blocking"),
+        (lambda: other.release(), LockNotOwnedError, "not held"),
+        (lambda: other.extend(1), LockNotOwnedError, "not held"),
+        (lambda: lock.extend(0), ValueError, "at least 1 ms"),
+        (lambda: TinyCacheLock(c, "x", ttl=0), ValueError, "at least 1 ms"),
+    ]:
+        try:
+            call()
+        except err as e:
+            assert text in str(e), e
+        else:
+            raise AssertionError(f"{text!r} must raise {err.__name__}")
+    lock.release()
+    assert not lock.held and c.get("job") is None
+    with c.lock("job") as held:
+        assert held.held
+        assert not other.acquire(blocking=False)
+    assert c.get("job") is None
+    # в пространстве имён — ключ вида
+    with TinyCacheLock(c.namespace("app"), "job"):
+        assert "app:job" in c and "job" not in c
+    # токены не повторяются
+    tokens = set()
+    for _ in range(100):
+        lock.acquire()
+        tokens.add(lock.token)
+        lock.release()
+    assert len(tokens) == 100
+    print("lock OK")
+
+
+def test_extend(c):
+    lock = c.lock("lease", ttl=0.3)
+    assert lock.acquire()
+    lock.extend(60)
+    assert 59_000 < c.pttl("lease") <= 60_300
+    lock.release()
+
+    # аренда истекла: блокировку берёт другой, прежний владелец её не продлит и не снимет
+    slow = c.lock("lease", ttl=0.2)
+    assert slow.acquire()
+    time.sleep(0.3)
+    fast = c.lock("lease", ttl=10)
+    assert fast.acquire(timeout=1)
+    try:
+        slow.extend(10)
+    except LockNotOwnedError as e:
+        assert "expired" in str(e), e
+    else:
+        raise AssertionError("extend() of an expired lease must raise")
+    assert not slow.held and c.pttl("lease") <= 10_000
+    assert slow.acquire(blocking=False) is False
+    slow = c.lock("lease", ttl=0.2)
+    fast.release()
+    assert slow.acquire()
+    time.sleep(0.3)
+    assert fast.acquire()
+    try:
+        slow.release()
+    except LockNotOwnedError as e:
+        assert "expired" in str(e), e
+    else:
+        raise AssertionError("release() of an expired lease must raise")
+    assert c.get("lease") == fast.token
+    fast.release()
+    print("extend OK")
+
+
+def worker(addr, n, out):
+    c = TinyCache(addr)
+    lock = TinyCacheLock(c, "counter-lock", ttl=10)
+    overlaps = 0
+    for _ in range(n):
+        with lock:
+            # внутри может быть только один процесс
+            if not c.set_nx("inside", b"1"):
+                overlaps += 1
+            # чтение и запись без атомарной команды: без блокировки приращения теряются
+            v = int(c.get("counter") or b"0")
+            c.set("counter", str(v + 1).encode())
+            c.delete("inside")
+    out.put(overlaps)
+
+
+def test_processes(srv):
+    out = mp.Queue()
+    procs = [mp.Process(target=worker, args=(srv.addr, 50, out)) for _ in range(4)]
+    for p in procs:
+        p.start()
+    overlaps = [out.get(timeout=60) for _ in procs]
+    for p in procs:
+        p.join(60)
+        assert p.exitcode == 0, p.exitcode
+    assert overlaps == [0] * 4, overlaps
+    assert TinyCache(srv.addr).get("counter") == b"200"
+    print("processes OK")
+
+
+def main():
+    with spawn_server(persistence=False) as srv:
+        c = TinyCache(srv.addr)
+        test_delete_if(c)
+        test_lock(c)
+        test_extend(c)
+        test_processes(srv)
+    print("LOCK TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
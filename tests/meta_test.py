@@ -0,0 +1,65 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import shutil
+import time
+from tiny_mp_cache import serve, TinyCache, ValueMeta
+
+PORT = 5046
+ADDR = f"127.0.0.1:{PORT}"
+WAL_DIR = "/tmp/tiny_mp_cache_meta_test"
+
+
+def server():
+    serve(PORT, wal_dir=WAL_DIR)
+
+
+def start():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    return p, TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+
+
+def now_ms():
+    return int(time.time() * 1000)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+
+    p, c = start()
+    before = now_ms()
+    c.set("cfg", b"v1")
+    c.set("cfg", b"v2")
+    c.setex("ttl", 60, b"x")
+    after = now_ms()
+    m = c.get_with_meta("cfg")
+    assert isinstance(m, ValueMeta)
+    assert m.value == b"v2" and m.version == 2 and m.ttl_ms is None
+    assert before <= m.written_at_ms <= after, (m, before, after)
+    assert 0 < c.get_with_meta("ttl").ttl_ms <= 60_000
+    assert c.get_with_meta("missing") is None
+    assert abs(c.time_ms() - now_ms()) < 1000
+    time.sleep(0.05)
+    assert c.get_with_meta("cfg").age_ms >= 50
+    print("meta OK")
+    p.terminate()
+    p.join()
+
+    # время записи и версия доигрываются из WAL
+    p, c = start()
+    m2 = c.get_with_meta("cfg")
+    assert m2.version == 2
+    assert before <= m2.written_at_ms <= after, m2
+    c.set("cfg", b"v3")
+    assert c.get_with_meta("cfg").version == 3
+    print("restart OK")
+    p.terminate()
+    p.join()
+
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+    print("META TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,132 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import threading
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5028
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def expect(exc, fn, *args, match=""):
+    try:
+        fn(*args)
+    except exc as e:
+        assert match in str(e), e
+    else:
+        raise AssertionError(f"{fn.__name__}{args!r} must raise {exc.__name__}")
+
+
+def test_prefixing(c):
+    users = c.namespace("users")
+    assert users.prefix == "users:" and c.prefix == ""
+    users.set("1", b"alice")
+    users["2"] = b"bob"
+    assert c.get("users:1") == b"alice" and c.get("users:2") == b"bob"
+    assert users.get("1") == b"alice" and users["2"] == b"bob"
+    assert "1" in users and "users:1" not in users
+    assert users.get("users:1") is None
+    # KeyError — с ключом вида, без префикса
+    expect(KeyError, users.__getitem__, "404", match="'404'")
+    assert users.get_or_set("3", b"carol") == (b"carol", True)
+    assert c.get("users:3") == b"carol"
+    assert users.pop("3") == b"carol" and c.get("users:3") is None
+    assert users.delete("2") == 1 and c.get("users:2") is None
+    del users["1"]
+    assert len(users) == 0
+    print("prefixing OK")
+
+
+def test_listing(c):
+    jobs = c.namespace("jobs")
+    jobs.update({"a": b"1", "b": b"2"})
+    jobs.set_json("c", {"n": 3})
+    c.set("jobsx", b"outside")
+    assert sorted(jobs.keys("*")) == ["a", "b", "c"]
+    assert jobs.keys("*", sort=True, offset=1) == ["b", "c"]
+    assert sorted(jobs) == ["a", "b", "c"]
+    assert sorted(jobs.keys_iter("a")) == ["a"]
+    assert dict(jobs.items_iter()) == {"a": b"1", "b": b"2", "c": b'{"n": 3}'}
+    assert jobs.to_dict() == {"a": b"1", "b": b"2", "c": b'{"n": 3}'}
+    assert jobs.get_json("c") == {"n": 3}
+    assert len(jobs) == 3 and len(c) >= 4
+    print("listing OK")
+
+
+def test_nesting(c):
+    b = c.namespace("a").namespace("b")
+    assert b.prefix == "a:b:"
+    b.set("k", b"v")
+    assert c.get("a:b:k") == b"v"
+    assert c.namespace("a").get("b:k") == b"v"
+    slash = c.namespace("x", sep="/").namespace("y", sep="/")
+    assert slash.prefix == "x/y/"
+    slash.set("k", b"v")
+    assert c.get("x/y/k") == b"v"
+    expect(ValueError, c.namespace, "")
+    print("nesting OK")
+
+
+def test_clear(c):
+    t = c.namespace("t")
+    t.update({"1": b"", "2": b"", "sub:3": b""})
+    c.set("t2", b"keep")
+    c.set("other", b"keep")
+    assert t.delete_prefix("sub:") == 1
+    assert sorted(t.keys("*")) == ["1", "2"]
+    assert t.clear() == 2
+    assert t.keys("*") == [] and t.clear() == 0
+    assert c.get("t2") == b"keep" and c.get("other") == b"keep"
+    print("clear OK")
+
+
+def test_watch(c):
+    w = c.namespace("w")
+    events = []
+    got = threading.Event()
+
+    def on_event(e):
+        events.append(e)
+        got.set()
+
+    watch = w.watch("", on_event)
+    assert watch.prefix == ""
+    c.set("wx", b"not in namespace")
+    w.set("k", b"v")
+    assert got.wait(5)
+    watch.stop()
+    assert [(e["key"], e["op"]) for e in events] == [("k", "set")], events
+    print("watch OK")
+
+
+def test_shared_pool(c):
+    ns = c.namespace("p")
+    ns.set("k", b"v")
+    # вид — тот же пул: закрытие одного закрывает оба
+    ns.close()
+    assert c.closed
+    expect(RuntimeError, c.get, "p:k")
+    print("shared pool OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True)
+    test_prefixing(c)
+    test_listing(c)
+    test_nesting(c)
+    test_clear(c)
+    test_watch(c)
+    test_shared_pool(c)
+    p.terminate()
+    p.join()
+    print("NAMESPACE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
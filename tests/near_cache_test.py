@@ -0,0 +1,142 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5029
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def expect(exc, fn, *args, match="", **kwargs):
+    try:
+        fn(*args, **kwargs)
+    except exc as e:
+        assert match in str(e), e
+    else:
+        raise AssertionError(f"{fn.__name__}{args!r} must raise {exc.__name__}")
+
+
+def stats(c):
+    s = c.local_cache_stats()
+    return s["hits"], s["misses"]
+
+
+def test_hits_and_ttl(other):
+    c = TinyCache(ADDR, local_cache_size=16, local_cache_ttl=0.3)
+    other.set("n:k", b"v1")
+    assert c.get("n:k") == b"v1"
+    for _ in range(1000):
+        assert c.get("n:k") == b"v1"
+    assert stats(c) == (1000, 1)
+    # чужая запись не видна до истечения TTL, bypass_local идёт на сервер
+    other.set("n:k", b"v2")
+    assert c.get("n:k") == b"v1"
+    assert c.get("n:k", bypass_local=True) == b"v2"
+    assert c.get("n:k") == b"v2"
+    other.set("n:k", b"v3")
+    time.sleep(0.35)
+    assert c.get("n:k") == b"v3"
+    # отсутствие ключа не кэшируется
+    assert c.get("n:missing") is None
+    other.set("n:missing", b"now")
+    assert c.get("n:missing") == b"now"
+    print("hits and ttl OK")
+
+
+def test_local_writes_invalidate(other):
+    c = TinyCache(ADDR, local_cache_size=16, local_cache_ttl=60)
+    for key in ["w:set", "w:del", "w:pop", "w:upd", "w:pre:1"]:
+        other.set(key, b"old")
+        assert c.get(key) == b"old"
+    c.set("w:set", b"new")
+    assert c.get("w:set") == b"new"
+    c.delete("w:del")
+    assert c.get("w:del") is None
+    c.pop("w:pop")
+    assert c.get("w:pop") is None
+    c.update({"w:upd": b"new"})
+    assert c.get("w:upd") == b"new"
+    c.delete_prefix("w:pre:")
+    assert c.get("w:pre:1") is None
+    # виды namespace() делят ближний кэш клиента
+    ns = c.namespace("w")
+    ns["set"] = b"via namespace"
+    assert c.get("w:set") == b"via namespace"
+    # явный сброс без записи на сервер
+    other.set("w:set", b"external")
+    assert c.get("w:set") == b"via namespace"
+    c.invalidate_local("w:set")
+    assert c.get("w:set") == b"external"
+    other.set("w:set", b"external2")
+    ns.invalidate_local()
+    assert c.get("w:set") == b"external2"
+    print("local writes invalidate OK")
+
+
+def test_lru():
+    c = TinyCache(ADDR, local_cache_size=2, local_cache_ttl=60)
+    for key in ["l:a", "l:b", "l:c"]:
+        c.set(key, key.encode())
+    c.get("l:a")
+    c.get("l:b")
+    c.get("l:a")  # l:b теперь самый старый
+    c.get("l:c")
+    s = c.local_cache_stats()
+    assert s["size"] == 2 and s["capacity"] == 2 and s["evictions"] == 1, s
+    hits, misses = stats(c)
+    c.get("l:a")
+    assert stats(c) == (hits + 1, misses)
+    c.get("l:b")
+    assert stats(c) == (hits + 1, misses + 1)
+    print("lru OK")
+
+
+def test_watch_invalidation(other):
+    c = TinyCache(ADDR, local_cache_size=16, local_cache_ttl=60, local_cache_watch=True)
+    other.set("x:k", b"v1")
+    assert c.get("x:k") == b"v1"
+    other.set("x:k", b"v2")
+    deadline = time.time() + 5
+    while c.get("x:k") != b"v2":
+        assert time.time() < deadline, "watch must invalidate the near cache"
+        time.sleep(0.01)
+    other.delete("x:k")
+    deadline = time.time() + 5
+    while c.get("x:k") is not None:
+        assert time.time() < deadline, "watch must invalidate deleted keys"
+        time.sleep(0.01)
+    c.close()
+    print("watch invalidation OK")
+
+
+def test_disabled_and_errors(other):
+    c = TinyCache(ADDR)
+    other.set("d:k", b"v")
+    c.get("d:k")
+    assert c.local_cache_stats() == {"hits": 0, "misses": 0, "evictions": 0, "size": 0, "capacity": 0}
+    expect(ValueError, TinyCache, ADDR, local_cache_size=8, local_cache_ttl=0)
+    expect(ValueError, TinyCache, ADDR, local_cache_watch=True, match="local_cache_size")
+    print("disabled and errors OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    other = TinyCache(ADDR, wait_ready=True)
+    test_hits_and_ttl(other)
+    test_local_writes_invalidate(other)
+    test_lru()
+    test_watch_invalidation(other)
+    test_disabled_and_errors(other)
+    p.terminate()
+    p.join()
+    print("NEAR CACHE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
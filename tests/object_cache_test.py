@@ -0,0 +1,107 @@
+#!/usr/bin/env python3
+import array
+import dataclasses
+import multiprocessing as mp
+import pickle
+import threading
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5027
+ADDR = f"127.0.0.1:{PORT}"
+
+
+@dataclasses.dataclass
+class Job:
+    id: int
+    tags: list
+    payload: bytes = b""
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def expect(exc, fn, *args, match=""):
+    try:
+        fn(*args)
+    except exc as e:
+        assert match in str(e), e
+    else:
+        raise AssertionError(f"{fn.__name__}{args!r} must raise {exc.__name__}")
+
+
+def test_round_trip(c):
+    job = Job(7, ["a", "б"], b"\x00\xff")
+    c.set_obj("o:job", job)
+    assert c.get_obj("o:job") == job
+    c.set_obj("o:none", None)
+    # сохранённый None — значение, default только для отсутствующего ключа
+    assert c.get_obj("o:none", "dflt") is None
+    assert c.get_obj("o:missing", "dflt") == "dflt"
+    for protocol in range(pickle.HIGHEST_PROTOCOL + 1):
+        c.set_obj("o:proto", {"p": protocol}, protocol=protocol)
+        assert c.get_obj("o:proto") == {"p": protocol}
+    print("round trip OK")
+
+
+def test_large(c):
+    # почти под предел кадра
+    big = array.array("d", range(100_000))
+    c.set_obj("o:array", big)
+    assert c.get_obj("o:array") == big
+    try:
+        import numpy as np
+    except ImportError:
+        print("numpy not installed, skipping numpy arrays")
+    else:
+        arr = np.random.rand(300, 300)
+        c.set_obj("o:np", arr, protocol=5)
+        assert np.array_equal(c.get_obj("o:np"), arr)
+    expect(ValueError, c.set_obj, "o:huge", b"x" * 2_000_000, match="o:huge")
+    assert c.get("o:huge") is None
+    print("large objects OK")
+
+
+def test_namespacing(c):
+    # get по pickle-ключу и get_obj по сырым байтам падают, а не отдают мусор
+    expect(TypeError, c.get, "o:job", match="get_obj")
+    expect(TypeError, c.__getitem__, "o:job", match="get_obj")
+    expect(TypeError, c.get_blocking, "o:job", match="get_obj")
+    c.set("o:raw", pickle.dumps({"looks": "pickled"}))
+    expect(TypeError, c.get_obj, "o:raw", match="raw bytes")
+    print("namespacing OK")
+
+
+def test_unpicklable(c):
+    expect(TypeError, c.set_obj, "o:lock", threading.Lock())
+    expect(Exception, c.set_obj, "o:lambda", lambda: 1)
+    assert c.get("o:lock") is None and c.get("o:lambda") is None
+    print("unpicklable OK")
+
+
+def test_allow_pickle():
+    c = TinyCache(ADDR)
+    # запись разрешена всегда, чтение — только с allow_pickle=True
+    c.set_obj("o:safe", [1, 2])
+    expect(RuntimeError, c.get_obj, "o:safe", match="allow_pickle")
+    assert TinyCache(ADDR, allow_pickle=True).get_obj("o:safe") == [1, 2]
+    print("allow_pickle OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, allow_pickle=True)
+    test_round_trip(c)
+    test_large(c)
+    test_namespacing(c)
+    test_unpicklable(c)
+    test_allow_pickle()
+    p.terminate()
+    p.join()
+    print("OBJECT CACHE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,120 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import subprocess
+import sys
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5051
+ADDR = f"127.0.0.1:{PORT}"
+
+EVENTS = None
+
+
+def mirror(key, op, value):
+    # медленная база: запись в кэш её не ждёт
+    time.sleep(0.05)
+    if key == "persist:bad":
+        raise ValueError("mirror failed")
+    EVENTS.put((key, op, value))
+
+
+def server(**kwargs):
+    serve(PORT, persistence=False, on_write=mirror, on_write_prefix="persist:", **kwargs)
+
+
+def test_events():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    c.set("persist:a", b"1")
+    c.set("other", b"x")
+    c.set("persist:bad", b"?")
+    c.setex("persist:t", 0.05, b"tmp")
+    c.delete("persist:a")
+    got = [EVENTS.get(timeout=5) for _ in range(4)]
+    assert got == [
+        ("persist:a", "set", b"1"),
+        ("persist:t", "set", b"tmp"),
+        ("persist:a", "del", None),
+        ("persist:t", "expired", None),
+    ], got
+    # исключение callback'а не останавливает поток
+    c.set("persist:b", b"2")
+    assert EVENTS.get(timeout=5) == ("persist:b", "set", b"2")
+    assert c.info()["write_feed_sent"] == 6
+    p.terminate()
+    p.join()
+    print("events OK")
+
+
+def test_drop():
+    p = mp.Process(target=server, kwargs=dict(on_write_queue=10), daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    # callback по 50 мс на событие, а запись от этого не замедляется
+    t = time.monotonic()
+    for i in range(200):
+        c.set(f"persist:{i}", b"v")
+    assert time.monotonic() - t < 2.0
+    info = c.info()
+    assert info["write_feed_dropped"] > 0, info
+    assert info["write_feed_sent"] + info["write_feed_dropped"] == 200, info
+    p.terminate()
+    p.join()
+    while not EVENTS.empty():
+        EVENTS.get()
+    print("drop OK")
+
+
+# serve() в фоновом потоке: очередь дочитывается при выходе из интерпретатора
+EXITS_AFTER_WRITES = f"""
+import threading, time
+from tiny_mp_cache import serve, TinyCache
+
+def mirror(key, op, value):
+    time.sleep(0.02)
+    print("event", key, flush=True)
+
+kwargs = dict(persistence=False, on_write=mirror, on_write_overflow="block")
+threading.Thread(target=serve, args=({PORT},), kwargs=kwargs, daemon=True).start()
+c = TinyCache("{ADDR}", wait_ready=True, ready_timeout=10.0)
+for i in range(20):
+    c.set(f"k{{i}}", b"v")
+"""
+
+
+def test_drain():
+    # у процессов multiprocessing atexit не вызывается, поэтому отдельный интерпретатор
+    out = subprocess.run(
+        [sys.executable, "-c", EXITS_AFTER_WRITES], capture_output=True, text=True, timeout=60
+    )
+    assert out.returncode == 0, out.stderr
+    got = [line.split()[1] for line in out.stdout.splitlines() if line.startswith("event ")]
+    assert got == [f"k{i}" for i in range(20)], out.stdout
+    print("drain OK")
+
+
+def main():
+    global EVENTS
+    mp.set_start_method("fork", force=True)
+    EVENTS = mp.Queue()
+    for kwargs, err, text in [
+        (dict(on_write=42), TypeError, "on_write must be callable"),
+        (dict(on_write=mirror, on_write_overflow="later"), ValueError, "block/drop"),
+        (dict(on_write=mirror, on_write_queue=0), ValueError, "must be positive"),
+    ]:
+        try:
+            serve(PORT, persistence=False, **kwargs)
+        except err as e:
+            assert text in str(e), e
+        else:
+            raise AssertionError(f"{kwargs} must raise {err.__name__}")
+    test_events()
+    test_drop()
+    test_drain()
+    print("ON_WRITE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,78 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import os
+import shutil
+import sys
+import tempfile
+from tiny_mp_cache import serve_unix, TinyCache, TinyCacheError
+
+NOBODY = 65534
+READERS = 65533
+
+
+def server(path):
+    serve_unix(path, persistence=False, allow_uids=[12345], readonly_gids=[READERS])
+
+
+# клиент под чужой учёткой: результат — текст ошибки или значение
+def as_peer(path, gid, op, out):
+    os.setgroups([])
+    os.setgid(gid)
+    os.setuid(NOBODY)
+    c = TinyCache(f"unix://{path}")
+    try:
+        out.put(("ok", op(c)))
+    except TinyCacheError as e:
+        out.put(("error", str(e)))
+
+
+def get_k(c):
+    return c.get("k")
+
+
+def set_k(c):
+    c.set("k", b"theirs")
+
+
+def peer(path, gid, op):
+    out = mp.Queue()
+    p = mp.Process(target=as_peer, args=(path, gid, op, out))
+    p.start()
+    res = out.get(timeout=10)
+    p.join(10)
+    return res
+
+
+def main():
+    if sys.platform != "linux" or os.geteuid() != 0:
+        print("PEER ACCESS TEST SKIPPED (needs root on Linux)")
+        return
+    d = tempfile.mkdtemp()
+    os.chmod(d, 0o777)
+    path = os.path.join(d, "cache.sock")
+    srv = mp.Process(target=server, args=(path,), daemon=True)
+    srv.start()
+    try:
+        # свой uid пускается всегда
+        c = TinyCache(f"unix://{path}", wait_ready=True, ready_timeout=10.0)
+        c.set("k", b"mine")
+        os.chmod(path, 0o777)
+
+        kind, msg = peer(path, NOBODY, get_k)
+        assert kind == "error" and "permission denied" in msg, (kind, msg)
+        assert f"uid {NOBODY}" in msg, msg
+
+        # группа только для чтения: чтение проходит, запись — нет
+        assert peer(path, READERS, get_k) == ("ok", b"mine")
+        kind, msg = peer(path, READERS, set_k)
+        assert kind == "error" and "read-only connection" in msg, (kind, msg)
+        assert c.get("k") == b"mine"
+    finally:
+        srv.terminate()
+        srv.join()
+        shutil.rmtree(d, ignore_errors=True)
+    print("PEER ACCESS TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
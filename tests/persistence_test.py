@@ -1,4 +1,5 @@
 #!/usr/bin/env python3
+import glob
 import multiprocessing as mp
 import os
 import time
@@ -6,25 +7,70 @@ from tiny_mp_cache import serve, TinyCache
 
 PORT = 5003
 ADDR = f"127.0.0.1:{PORT}"
-WAL_FILE = "tiny-mp-cache.wal"  # должен совпадать с путём в serve()
+WAL_FILE = "tiny-mp-cache.wal"  # должен совпадать с путём в serve(), сегменты — WAL_FILE.000001, ...
+SNAPSHOT_FILE = "tiny-mp-cache.snapshot"
+
+
+def wal_segments():
+    return sorted(glob.glob(WAL_FILE + ".[0-9]*"))
+
+
+def wal_size():
+    return sum(os.path.getsize(f) for f in wal_segments())
 
 
 def server():
     serve(PORT)
 
 
+def wait_ready(timeout: float = 10.0):
+    # восстановление большого WAL/снапшота может занять больше пары сотен мс
+    deadline = time.time() + timeout
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
 def start_server():
     mp.set_start_method("fork", force=True)
     p = mp.Process(target=server, daemon=True)
     p.start()
-    time.sleep(0.5)
+    wait_ready()
+    return p
+
+
+def compacting_server():
+    serve(PORT, compact_after=10)
+
+
+def start_compacting_server():
+    p = mp.Process(target=compacting_server, daemon=True)
+    p.start()
+    wait_ready()
+    return p
+
+
+def ephemeral_server():
+    serve(PORT, persistence=False)
+
+
+def start_ephemeral_server():
+    p = mp.Process(target=ephemeral_server, daemon=True)
+    p.start()
+    wait_ready()
     return p
 
 
 def main():
     # чистый старт
-    if os.path.exists(WAL_FILE):
-        os.remove(WAL_FILE)
+    for f in [WAL_FILE, SNAPSHOT_FILE] + wal_segments():
+        if os.path.exists(f):
+            os.remove(f)
 
     # первый запуск: пишем данные
     p1 = start_server()
@@ -33,6 +79,25 @@ def main():
     c1.set("p:keep", b"v1")
     c1.set("p:delete", b"to-delete")
     c1.set("p:pop", b"to-pop")
+    c1.update({"p:bulk1": b"b1", "p:bulk2": b"b2"})
+    assert c1.get_or_set("p:gos", b"g") == (b"g", True)
+    c1.update({"p:tmp:1": b"t1", "p:tmp:2": b"t2"})
+    c1.update("p:counter", op="add_i64", arg=41)
+    c1.update("p:counter", op="add_i64", arg=1)
+    # SetBit поверх Set, после удаления и без Set вовсе
+    c1.set("p:bits", b"\x00")
+    c1.setbit("p:bits", 1, True)
+    c1.setbit("p:bits", 12, True)
+    c1.set("p:bits:del", b"\xff")
+    c1.delete("p:bits:del")
+    c1.setbit("p:bits:del", 7, True)
+    c1.setbit("p:bits:new", 0, True)
+    c1.setbit("p:bits:new", 0, False)
+    # сроки в WAL абсолютные: истёкший за время простоя ключ не вернётся
+    c1.psetex("p:ttl:short", 100, b"s")
+    c1.setex("p:ttl:long", 600, b"l")
+    c1.set("p:ttl:exp", b"e")
+    assert c1.expire("p:ttl:exp", 600)
 
     assert c1.get("p:keep") == b"v1"
     assert c1.get("p:delete") == b"to-delete"
@@ -41,6 +106,7 @@ def main():
     # операции, которые должны отразиться в WAL
     c1.delete("p:delete")
     v_pop = c1.pop("p:pop")
+    assert c1.delete_prefix("p:tmp:") == 2
     assert v_pop == b"to-pop"
     assert c1.get("p:delete") is None
     assert c1.get("p:pop") is None
@@ -48,8 +114,9 @@ def main():
     # завершаем первый сервер
     p1.terminate()
     p1.join()
+    time.sleep(0.2)
 
-    assert os.path.exists(WAL_FILE), "WAL file must exist after first run"
+    assert wal_segments(), "WAL segment must exist after first run"
 
     # второй запуск: восстановление из WAL
     p2 = start_server()
@@ -59,12 +126,123 @@ def main():
     assert c2.get("p:keep") == b"v1"
     assert c2.get("p:delete") is None
     assert c2.get("p:pop") is None
-
-    print("PERSISTENCE TEST PASSED")
+    assert c2.get("p:bulk1") == b"b1" and c2.get("p:bulk2") == b"b2"
+    assert c2.get_or_set("p:gos", b"other") == (b"g", False)
+    assert c2.keys("p:tmp:*") == []
+    assert c2.get("p:counter") == b"42"
+    assert c2.get("p:bits") == b"\x40\x08"
+    assert c2.get("p:bits:del") == b"\x01"
+    assert c2.get("p:bits:new") == b"\x00"
+    assert int(c2.wal_stats()["setbits"]) >= 5
+    assert c2.get("p:ttl:short") is None
+    assert 590 <= c2.ttl("p:ttl:long") <= 600 and c2.get("p:ttl:long") == b"l"
+    assert 590 <= c2.ttl("p:ttl:exp") <= 600
+    assert int(c2.wal_stats()["expires"]) == 1
+
+    # save(): снапшот + усечённый WAL
+    wal_before = wal_size()
+    c2.save()
+    assert os.path.exists(SNAPSHOT_FILE), "snapshot must exist after save()"
+    assert wal_size() < wal_before, "WAL must be truncated after save()"
+    assert len(wal_segments()) == 1, wal_segments()
+
+    # записи после снапшота должны доиграться из WAL
+    c2.set("p:after", b"v2")
+    c2.delete("p:keep")
+    c2.setbit("p:bits", 15, True)
 
     p2.terminate()
     p2.join()
 
+    # третий запуск: снапшот + хвост WAL
+    p3 = start_server()
+    c3 = TinyCache(ADDR)
+    assert c3.get("p:keep") is None
+    assert c3.get("p:after") == b"v2"
+    # бит из WAL поверх значения из снапшота
+    assert c3.get("p:bits") == b"\x40\x09"
+    # срок из снапшота
+    assert 0 < c3.ttl("p:ttl:long") <= 600
+    p3.terminate()
+    p3.join()
+
+    # автокомпакция каждые 10 записей
+    p4 = start_compacting_server()
+    c4 = TinyCache(ADDR)
+    for i in range(25):
+        c4.set(f"p:auto:{i}", str(i).encode())
+    p4.terminate()
+    p4.join()
+
+    p5 = start_server()
+    c5 = TinyCache(ADDR)
+    assert len(c5.keys("p:auto:*")) == 25
+    assert c5.get("p:auto:24") == b"24"
+    assert c5.get("p:after") == b"v2"
+
+    # bgsave: снапшот в фоне, запись продолжается
+    big = b"x" * 100_000
+    for i in range(500):
+        c5.set(f"p:big:{i}", big)
+    c5.bgsave()
+    try:
+        c5.bgsave()
+        raise AssertionError("second bgsave must fail while the first one runs")
+    except RuntimeError as e:
+        assert "already in progress" in str(e), e
+    c5.set("p:during", b"v3")
+    for _ in range(100):
+        info = c5.info()
+        if info["save_in_progress"] == 0:
+            break
+        time.sleep(0.05)
+    assert info["last_save_status"] == "ok", info
+    # снапшот «размыт»: может захватить и ключи, записанные во время сохранения
+    assert int(info["save_keys_saved"]) >= int(info["save_keys_total"]), info
+    c5.set("p:later", b"v4")
+
+    p5.terminate()
+    p5.join()
+
+    p6 = start_server()
+    c6 = TinyCache(ADDR)
+    assert len(c6.keys("p:big:*")) == 500
+    assert c6.get("p:during") == b"v3"
+    assert c6.get("p:later") == b"v4"
+
+    p6.terminate()
+    p6.join()
+
+    # persistence=False: на диск ничего не пишется, после рестарта кэш пуст
+    for f in [WAL_FILE, SNAPSHOT_FILE] + wal_segments():
+        if os.path.exists(f):
+            os.remove(f)
+    p7 = start_ephemeral_server()
+    c7 = TinyCache(ADDR)
+    c7.set("p:eph", b"v5")
+    c7.delete("p:eph")
+    c7.set("p:eph", b"v6")
+    assert c7.pop("p:eph") == b"v6"
+    c7.set("p:eph", b"v7")
+    info = c7.info()
+    assert info["persistence"] == "none", info
+    assert "wal_last_seq" not in info, info
+    try:
+        c7.save()
+        raise AssertionError("save must fail when persistence is disabled")
+    except RuntimeError as e:
+        assert "persistence is disabled" in str(e), e
+    assert not wal_segments() and not os.path.exists(SNAPSHOT_FILE)
+    p7.terminate()
+    p7.join()
+
+    p8 = start_ephemeral_server()
+    assert TinyCache(ADDR).get("p:eph") is None
+    p8.terminate()
+    p8.join()
+
+    print("PERSISTENCE TEST PASSED")
+
 
 if __name__ == "__main__":
     main()
@@ -0,0 +1,46 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5034
+ADDR = f"127.0.0.1:{PORT}"
+
+# с этим seed рвётся только третья команда сервера (Hello не в счёт):
+# ping из wait_ready, set, get — обрыв, get повтором, set, pop, info
+CHAOS = {"disconnect_rate": 0.3, "seed": 739}
+
+
+def server():
+    # простаивающие соединения закрывает сам сервер, как после перезапуска
+    serve(PORT, persistence=False, idle_timeout_secs=1, chaos=CHAOS)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    c.set("k", b"1")
+    s = c.pool_stats()
+    assert s == {"idle": 1, "reconnects": 0, "validation_failures": 0}, s
+
+    # оборванная посреди команды get повторяется на новом соединении
+    assert c.get("k") == b"1"
+    assert c.pool_stats()["reconnects"] == 1, c.pool_stats()
+
+    # простоявшее соединение проверяется до команды, которую нельзя повторить
+    c.set("k", b"2")
+    time.sleep(1.8)
+    assert c.pop("k", None) == b"2"
+    s = c.pool_stats()
+    assert s == {"idle": 1, "reconnects": 2, "validation_failures": 1}, s
+    assert c.info()["chaos_disconnects"] == 1
+
+    p.terminate()
+    p.join()
+    print("POOL RECONNECT TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
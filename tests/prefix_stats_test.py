@@ -0,0 +1,51 @@
+#!/usr/bin/env python3
+from tiny_mp_cache import spawn_server, TinyCache
+
+
+def main():
+    with spawn_server(persistence=False) as srv:
+        c = TinyCache(srv.addr)
+        c.update({f"thumbnails:{i}": b"x" * 100 for i in range(50)})
+        c.update({f"sessions:eu:{i}": b"s" * 10 for i in range(20)})
+        c.update({f"sessions:us:{i}": b"s" * 10 for i in range(5)})
+        c.set("plain", b"12345")
+
+        stats = c.prefix_stats()
+        assert stats == {
+            "thumbnails:": {"keys": 50, "bytes": 5000},
+            "sessions:": {"keys": 25, "bytes": 250},
+            "": {"keys": 1, "bytes": 5},
+        }, stats
+        # порядок — от самых больших по байтам
+        assert list(stats) == ["thumbnails:", "sessions:", ""], stats
+
+        # ключ с меньшим числом сегментов остаётся в группе своего префикса
+        deep = c.prefix_stats(depth=2)
+        assert deep["sessions:eu:"] == {"keys": 20, "bytes": 200}, deep
+        assert deep["sessions:us:"] == {"keys": 5, "bytes": 50}, deep
+        assert deep["thumbnails:"] == {"keys": 50, "bytes": 5000}, deep
+        assert c.prefix_stats(sep="/") == {"": {"keys": 76, "bytes": 5255}}
+
+        # вид пространства имён считает весь сервер, как info()
+        assert c.namespace("sessions").prefix_stats() == stats
+
+        for sep, depth in [("", 1), (":", 0)]:
+            try:
+                c.prefix_stats(sep=sep, depth=depth)
+            except ValueError as e:
+                assert "prefix_stats()" in str(e), e
+            else:
+                raise AssertionError(f"prefix_stats({sep!r}, {depth}) must fail")
+
+        # сверх предела групп остальные ключи попадают в "*"
+        c.update({f"g{i}:k": b"v" for i in range(10_050)})
+        wide = c.prefix_stats()
+        assert len(wide) == 10_001, len(wide)
+        total = sum(g["keys"] for g in wide.values())
+        assert total == 76 + 10_050, total
+        assert wide["*"]["keys"] >= 50, wide["*"]
+    print("PREFIX STATS TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,81 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import os
+import shutil
+import traceback
+from tiny_mp_cache import serve, TinyCache, TinyCacheLocal
+
+PORT = 5042
+ADDR = f"127.0.0.1:{PORT}"
+WAL_DIR = "/tmp/tiny_mp_cache_preload_test"
+
+
+def fill(local):
+    for i in range(5000):
+        local.set(f"user:{i}", str(i).encode())
+    # дамп уже загружен: callable может его поправить
+    local.set("from_dump", b"patched")
+
+
+def server(**kwargs):
+    serve(PORT, wal_dir=WAL_DIR, **kwargs)
+
+
+def run(**kwargs):
+    p = mp.Process(target=server, kwargs=kwargs, daemon=True)
+    p.start()
+    return p, TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+
+
+def broken(local):
+    local.set("half", b"done")
+    raise KeyError("no source")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+    os.makedirs(WAL_DIR)
+
+    # дамп для preload_file пишется через встроенный режим в отдельном каталоге
+    src = os.path.join(WAL_DIR, "src")
+    local = TinyCacheLocal(wal_dir=src)
+    local.set("from_dump", b"dump")
+    local.set("only_dump", b"yes")
+    assert local.export("../warm.dump") == 2
+    del local
+
+    # исключение прерывает запуск до bind и приходит как есть
+    try:
+        serve(PORT, persistence=False, preload=broken)
+        raise AssertionError("serve() must fail")
+    except KeyError as e:
+        assert "broken" in "".join(traceback.format_tb(e.__traceback__)), e
+
+    try:
+        serve(PORT, persistence=False, read_only=True, preload=fill)
+        raise AssertionError("serve() must fail")
+    except RuntimeError as e:
+        assert "preload needs a writable server" in str(e), e
+
+    p, c = run(preload=fill, preload_file="warm.dump", fsync="always")
+    assert c.get("user:4999") == b"4999"
+    assert c.get("only_dump") == b"yes"
+    assert c.get("from_dump") == b"patched"
+    assert len(c) == 5002
+    p.terminate()
+    p.join()
+
+    # прогретое записано в WAL и переживает перезапуск без preload
+    p, c = run()
+    assert len(c) == 5002
+    assert c.get("user:0") == b"0"
+    p.terminate()
+    p.join()
+
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+    print("PRELOAD TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,128 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import threading
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5018
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def start_server():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def wait_info(c, field, value, timeout=5):
+    deadline = time.time() + timeout
+    while c.info()[field] != value:
+        if time.time() > deadline:
+            raise AssertionError(f"{field} = {c.info()[field]}, expected {value}")
+        time.sleep(0.05)
+
+
+def test_iterator(c):
+    sub = c.subscribe(["news", "alerts"])
+    assert sub.channels == ["news", "alerts"]
+    info = c.info()
+    assert info["pubsub_subscribers"] == 1 and info["pubsub_channels"] == 2, info
+
+    assert c.publish("news", b"n1") == 1
+    assert c.publish("nobody", b"x") == 0
+    assert c.publish("alerts", b"a1") == 1
+    assert next(sub) == ("news", b"n1")
+    assert next(sub) == ("alerts", b"a1")
+
+    # сердцебиения в тишине не мешают ждать следующее сообщение
+    threading.Timer(1.5, lambda: c.publish("news", b"late")).start()
+    assert next(sub) == ("news", b"late")
+
+    sub.close()
+    assert list(sub) == []
+    wait_info(c, "pubsub_subscribers", 0)
+    assert c.info()["pubsub_channels"] == 0
+    assert c.publish("news", b"n2") == 0
+    print("iterator OK")
+
+
+def test_callback(c):
+    got = []
+    done = threading.Event()
+
+    def on_message(channel, payload):
+        if payload == b"boom":
+            raise ValueError("callback failure must not stop the subscription")
+        got.append((channel, payload))
+        if len(got) == 100:
+            done.set()
+
+    sub = c.subscribe(["jobs"], on_message)
+    other = c.subscribe(["jobs"])
+    assert c.publish("jobs", b"boom") == 2
+    for i in range(100):
+        assert c.publish("jobs", str(i).encode()) == 2
+    assert done.wait(5), len(got)
+    assert got == [("jobs", str(i).encode()) for i in range(100)]
+    assert next(other) == ("jobs", b"boom")
+
+    try:
+        next(sub)
+        raise AssertionError("callback subscription must not be iterable")
+    except RuntimeError as e:
+        assert "callback" in str(e), e
+    sub.close()
+    other.close()
+    wait_info(c, "pubsub_subscribers", 0)
+    print("callback OK")
+
+
+def test_slow_subscriber(c):
+    slow = c.subscribe(["firehose"])
+    payload = b"x" * 16 * 1024
+    start = time.time()
+    received = sum(c.publish("firehose", payload) for _ in range(2500))
+    elapsed = time.time() - start
+    # публикующий не ждёт подписчика, который ничего не читает
+    assert received < 2500, received
+    assert int(c.info()["pubsub_dropped"]) == 2500 - received
+    assert elapsed < 30, elapsed
+
+    # то, что дошло до очереди, приходит по порядку и целиком
+    first = [next(slow) for _ in range(10)]
+    assert first == [("firehose", payload)] * 10
+    slow.close()
+    print(f"slow subscriber OK: {2500 - received} dropped in {elapsed:.2f}s")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = start_server()
+    c = TinyCache(ADDR)
+    try:
+        c.subscribe([])
+        raise AssertionError("empty channel list must be rejected")
+    except RuntimeError as e:
+        assert "at least one channel" in str(e), e
+    test_iterator(c)
+    test_callback(c)
+    test_slow_subscriber(c)
+    p.terminate()
+    p.join()
+    print("PUBSUB TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,44 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import time
+from tiny_mp_cache import serve, TinyCache, TinyCacheError
+
+PORT = 5041
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False, rate_limit_per_ip=50, admin_token="secret")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+    time.sleep(1.1)
+
+    passed, limited = 0, 0
+    for _ in range(200):
+        try:
+            c.get("k")
+            passed += 1
+        except TinyCacheError as e:
+            assert "rate limited, retry after" in str(e), e
+            limited += 1
+    assert 50 <= passed < 60 and passed + limited == 200, (passed, limited)
+
+    time.sleep(1.1)
+    info = c.info()
+    assert info["rate_limited_commands"] == limited, info
+    admin = TinyCache(ADDR, admin_token="secret")
+    # отказы пришлись на открытое соединение пула `c`
+    assert sum(x["rate_limited"] for x in admin.client_list()) == limited
+
+    p.terminate()
+    p.join()
+    print("RATE LIMIT TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
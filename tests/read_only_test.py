@@ -0,0 +1,130 @@
+#!/usr/bin/env python3
+import hashlib
+import multiprocessing as mp
+import os
+import shutil
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5014
+ADDR = f"127.0.0.1:{PORT}"
+WAL_DIR = "ro-data"
+TOKEN = "s3cret"
+
+
+def cleanup():
+    if os.path.exists(WAL_DIR):
+        shutil.rmtree(WAL_DIR)
+
+
+def server(kwargs):
+    serve(PORT, **kwargs)
+
+
+def start_server(**kwargs):
+    p = mp.Process(target=server, args=(kwargs,), daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def expect_error(call, message):
+    try:
+        call()
+        raise AssertionError(f"expected error containing {message!r}")
+    except RuntimeError as e:
+        assert message in str(e), e
+
+
+def dir_state():
+    state = {}
+    for name in sorted(os.listdir(WAL_DIR)):
+        with open(os.path.join(WAL_DIR, name), "rb") as f:
+            state[name] = hashlib.sha256(f.read()).hexdigest()
+    return state
+
+
+def test_runtime_toggle():
+    cleanup()
+    p = start_server(wal_dir=WAL_DIR, admin_token=TOKEN)
+    c = TinyCache(ADDR)
+    admin = TinyCache(ADDR, admin_token=TOKEN)
+    c.set("k", b"1")
+
+    expect_error(lambda: c.set_read_only(True), "requires an admin token")
+    expect_error(lambda: TinyCache(ADDR, admin_token="wrong").set_read_only(True), "invalid admin token")
+    admin.set_read_only(True)
+    assert c.info()["read_only"] == 1
+
+    for write in [lambda: c.set("k", b"2"), lambda: c.delete("k"), lambda: c.pop("k"), c.save, c.bgsave]:
+        expect_error(write, "read-only server")
+    assert c.get("k") == b"1"
+    assert c.keys("*") == ["k"] and c.len() == 1
+
+    admin.set_read_only(False)
+    c.set("k", b"3")
+    assert c.get("k") == b"3" and c.info()["read_only"] == 0
+    stop_server(p)
+
+    p = start_server(wal_dir=WAL_DIR)
+    expect_error(lambda: TinyCache(ADDR, admin_token=TOKEN).set_read_only(True), "admin commands are disabled")
+    stop_server(p)
+    print("runtime toggle OK")
+
+
+def test_read_only_start():
+    cleanup()
+    p = start_server(wal_dir=WAL_DIR, wal_segment_size=128)
+    c = TinyCache(ADDR)
+    for i in range(20):
+        c.set(f"k:{i}", f"v{i}".encode())
+    c.save()
+    c.set("k:tail", b"after snapshot")
+    stop_server(p)
+    # оборванная запись в хвосте должна остаться как есть
+    active = sorted(n for n in os.listdir(WAL_DIR) if n.startswith("tiny-mp-cache.wal."))[-1]
+    with open(os.path.join(WAL_DIR, active), "ab") as f:
+        f.write(b"\x30\x00\x00")
+    before = dir_state()
+
+    p = start_server(wal_dir=WAL_DIR, read_only=True, admin_token=TOKEN)
+    c = TinyCache(ADDR)
+    assert c.len() == 21 and c.get("k:tail") == b"after snapshot"
+    info = c.info()
+    assert info["read_only"] == 1 and info["persistence"] == "read-only", info
+    expect_error(lambda: c.set("x", b"1"), "read-only server")
+    expect_error(lambda: TinyCache(ADDR, admin_token=TOKEN).set_read_only(False), "restart it without read_only")
+    stop_server(p)
+    assert dir_state() == before, "read-only server must not touch the WAL directory"
+
+    # каталога нет — он и не создаётся
+    cleanup()
+    p = start_server(wal_dir=WAL_DIR, read_only=True)
+    assert TinyCache(ADDR).len() == 0
+    stop_server(p)
+    assert not os.path.exists(WAL_DIR)
+    print("read-only start OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    test_runtime_toggle()
+    test_read_only_start()
+    cleanup()
+    print("READ-ONLY TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
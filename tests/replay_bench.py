@@ -0,0 +1,91 @@
+#!/usr/bin/env python3
+# Время старта сервера на большом WAL: N записей по K ключам, часть из них — удаления.
+# Старт меряется дважды: replay в одном потоке и в потоке на ядро (не больше 16).
+# Пример: python tests/replay_bench.py 5000000 1000000
+import glob
+import multiprocessing as mp
+import os
+import random
+import struct
+import sys
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5010
+ADDR = f"127.0.0.1:{PORT}"
+WAL_FILE = "tiny-mp-cache.wal"
+SNAPSHOT_FILE = "tiny-mp-cache.snapshot"
+
+
+def cleanup():
+    for f in [WAL_FILE, SNAPSHOT_FILE] + glob.glob(WAL_FILE + ".[0-9]*"):
+        if os.path.exists(f):
+            os.remove(f)
+
+
+def write_wal(records: int, keys: int):
+    # сегмент формата 5: magic | version | base_seq | схема записей, записи
+    # len | seq | bincode WalRecord
+    rnd = random.Random(42)
+    names = [f"bench:{i:08d}".encode() for i in range(keys)]
+    value = b"v" * 32
+    with open(WAL_FILE + ".000001", "wb") as f:
+        f.write(b"TMCW" + struct.pack("<IQI", 5, 0, 1))
+        buf = []
+        for seq in range(1, records + 1):
+            key = names[rnd.randrange(keys)]
+            op = rnd.random()
+            if op < 0.9:
+                body = struct.pack("<IQ", 0, len(key)) + key + struct.pack("<Q", len(value)) + value
+            else:
+                # Del или Pop
+                body = struct.pack("<IQ", 1 if op < 0.95 else 2, len(key)) + key
+            buf.append(struct.pack("<IQ", len(body), seq) + body)
+            if len(buf) == 100_000:
+                f.write(b"".join(buf))
+                buf.clear()
+        f.write(b"".join(buf))
+
+
+def server(threads):
+    serve(PORT, replay_threads=threads)
+
+
+def startup(threads):
+    t0 = time.time()
+    p = mp.Process(target=server, args=(threads,), daemon=True)
+    p.start()
+    while True:
+        try:
+            n = TinyCache(ADDR).len()
+            break
+        except RuntimeError:
+            time.sleep(0.01)
+    elapsed = time.time() - t0
+    p.terminate()
+    p.join()
+    return elapsed, n
+
+
+def main():
+    records = int(sys.argv[1]) if len(sys.argv) > 1 else 1_000_000
+    keys = int(sys.argv[2]) if len(sys.argv) > 2 else 10_000
+    cleanup()
+    t0 = time.time()
+    write_wal(records, keys)
+    size = os.path.getsize(WAL_FILE + ".000001")
+    print(f"wrote {records} records over {keys} keys, {size / 1e6:.0f} MB in {time.time() - t0:.1f}s")
+
+    mp.set_start_method("fork", force=True)
+    # сервер не пишет снапшот при остановке, так что оба старта доигрывают тот же журнал
+    sequential, n = startup(1)
+    print(f"startup, 1 thread: {sequential:.2f}s, {n} live keys")
+    threads = min(os.cpu_count() or 1, 16)
+    parallel, m = startup(threads)
+    assert m == n, (m, n)
+    print(f"startup, {threads} threads: {parallel:.2f}s, speedup {sequential / parallel:.1f}x")
+    cleanup()
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,183 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import os
+import shutil
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PRIMARY_PORT = 5012
+REPLICA_PORT = 5013
+PRIMARY = f"127.0.0.1:{PRIMARY_PORT}"
+REPLICA = f"127.0.0.1:{REPLICA_PORT}"
+PRIMARY_DIR = "repl-primary"
+REPLICA_DIR = "repl-replica"
+
+
+def cleanup():
+    for d in [PRIMARY_DIR, REPLICA_DIR]:
+        if os.path.exists(d):
+            shutil.rmtree(d)
+
+
+def server(port, kwargs):
+    serve(port, **kwargs)
+
+
+def start_server(port, **kwargs):
+    p = mp.Process(target=server, args=(port, kwargs), daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(f"127.0.0.1:{port}").len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def start_primary(**kwargs):
+    return start_server(PRIMARY_PORT, wal_dir=PRIMARY_DIR, **kwargs)
+
+
+def start_replica(**kwargs):
+    kwargs.setdefault("wal_dir", REPLICA_DIR)
+    return start_server(REPLICA_PORT, replicate_from=PRIMARY, **kwargs)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def contents(c):
+    return {k: c.get(k) for k in c.keys("r:*")}
+
+
+def wait_synced(primary, replica, timeout=10):
+    deadline = time.time() + timeout
+    while True:
+        expected = contents(primary)
+        got = contents(replica)
+        if got == expected:
+            return got
+        if time.time() > deadline:
+            raise AssertionError(f"replica did not catch up: {len(got)} of {len(expected)} keys")
+        time.sleep(0.05)
+
+
+def test_stream_and_read_only():
+    cleanup()
+    p = start_primary()
+    primary = TinyCache(PRIMARY)
+    for i in range(100):
+        primary.set(f"r:{i}", f"v{i}".encode())
+    primary.delete("r:0")
+
+    # реплика догоняет по WAL основного, затем получает новые записи потоком
+    r = start_replica()
+    replica = TinyCache(REPLICA)
+    wait_synced(primary, replica)
+    for i in range(100, 200):
+        primary.set(f"r:{i}", f"v{i}".encode())
+    assert primary.pop("r:1") == b"v1"
+    # сроки приходят потоком вместе с записями
+    primary.setex("r:ttl", 60, b"t")
+    assert len(wait_synced(primary, replica)) == 199
+    assert 0 < replica.ttl("r:ttl") <= 60
+    primary.delete("r:ttl")
+    assert len(wait_synced(primary, replica)) == 198
+
+    info = replica.info()
+    assert info["role"] == "replica" and info["repl_primary"] == PRIMARY, info
+    assert info["repl_connected"] == 1, info
+    assert info["last_seq"] == primary.info()["last_seq"], info
+    assert primary.info()["repl_replicas"] == 1
+    assert primary.info()["role"] == "primary"
+
+    for write in [lambda: replica.set("r:x", b"1"), lambda: replica.delete("r:2"), lambda: replica.pop("r:2")]:
+        try:
+            write()
+            raise AssertionError("replica must reject writes")
+        except RuntimeError as e:
+            assert "read-only replica" in str(e), e
+    stop_server(r)
+    stop_server(p)
+    print("stream and read-only OK")
+
+
+def test_resume_and_reconnect():
+    cleanup()
+    p = start_primary()
+    primary = TinyCache(PRIMARY)
+    r = start_replica()
+    replica = TinyCache(REPLICA)
+    for i in range(50):
+        primary.set(f"r:{i}", b"a")
+    wait_synced(primary, replica)
+
+    # реплика перезапускается и продолжает с seq своего WAL
+    stop_server(r)
+    for i in range(50, 80):
+        primary.set(f"r:{i}", b"b")
+    r = start_replica()
+    wait_synced(primary, replica)
+
+    # основной сервер перезапускается, реплика переподключается сама
+    stop_server(p)
+    p = start_primary()
+    for i in range(80, 90):
+        primary.set(f"r:{i}", b"c")
+    assert len(wait_synced(primary, replica)) == 90
+    stop_server(r)
+    stop_server(p)
+    print("resume and reconnect OK")
+
+
+def test_full_sync_after_compaction():
+    cleanup()
+    p = start_primary()
+    primary = TinyCache(PRIMARY)
+    for i in range(3000):
+        primary.set(f"r:{i}", os.urandom(16))
+    # записи до снапшота уходят из WAL — реплике остаётся только полная синхронизация
+    primary.expire("r:7", 600)
+    primary.save()
+    primary.delete("r:5")
+
+    # и для реплики без персистентности, и для реплики с WAL
+    r = start_replica(persistence=False)
+    replica = TinyCache(REPLICA)
+    assert len(wait_synced(primary, replica)) == 2999
+    # срок ключа — и в полной синхронизации
+    assert 0 < replica.ttl("r:7") <= 600
+    stop_server(r)
+
+    r = start_replica()
+    wait_synced(primary, replica)
+    primary.set("r:new", b"1")
+    wait_synced(primary, replica)
+    stop_server(r)
+
+    # после полной синхронизации WAL реплики продолжает seq основного
+    primary.set("r:offline", b"2")
+    r = start_replica()
+    assert len(wait_synced(primary, replica)) == 3001
+    assert replica.info()["last_seq"] == primary.info()["last_seq"]
+    stop_server(r)
+    stop_server(p)
+    print("full sync OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    test_stream_and_read_only()
+    test_resume_and_reconnect()
+    test_full_sync_after_compaction()
+    cleanup()
+    print("REPLICATION TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,37 @@
+#!/usr/bin/env python3
+from tiny_mp_cache import spawn_server, TinyCache, ResponseTooLargeError, TinyCacheError
+
+
+def main():
+    with spawn_server(persistence=False, max_response_size=10_000) as srv:
+        c = TinyCache(srv.addr)
+        c.set("small", b"x" * 9_000)
+        c.set("big", b"x" * 10_000)
+        assert c.get("small") == b"x" * 9_000
+
+        try:
+            c.get("big")
+        except ResponseTooLargeError as e:
+            assert isinstance(e, TinyCacheError)
+            assert "response too large: " in str(e) and "limit 10000" in str(e), e
+        else:
+            raise AssertionError("get() over max_response_size must fail")
+        # соединение и сервер в строю
+        assert c.get("small") == b"x" * 9_000
+
+        # Keys и пары сверх предела приходят кусками
+        c.update({f"many:{i:04}": b"v" * 100 for i in range(1_000)})
+        assert len(c.keys("many:*")) == 1_000
+        assert len(dict(c.items_iter("many:"))) == 1_000
+
+        try:
+            spawn_server(persistence=False, max_response_size=0)
+        except (ValueError, RuntimeError):
+            pass
+        else:
+            raise AssertionError("max_response_size=0 must be rejected")
+    print("RESPONSE SIZE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,40 @@
+#!/usr/bin/env python3
+import time
+from tiny_mp_cache import spawn_server, TinyCache
+
+
+def main():
+    with spawn_server(persistence=False) as srv:
+        c = TinyCache(srv.addr)
+        assert c.sample() == []
+        c.update({f"img:{i}": b"x" * (i % 10) for i in range(2000)})
+        c.update({f"user:{i}": b"u" for i in range(6000)})
+        c.setex("session", 60, b"s")
+
+        rows = c.sample(200)
+        assert len(rows) == 200 and len({r["key"] for r in rows}) == 200
+        assert set(rows[0]) == {"key", "size", "idle_ms", "ttl_ms", "age_ms"}, rows[0]
+        for r in rows:
+            if r["key"].startswith("img:"):
+                assert r["size"] == int(r["key"][4:]) % 10, r
+            if r["key"] == "session":
+                assert 0 < r["ttl_ms"] <= 60_000, r
+            else:
+                assert r["ttl_ms"] is None, r
+            assert r["idle_ms"] >= 0 and r["age_ms"] >= 0, r
+
+        # четверть ключей — img:
+        share = sum(r["key"].startswith("img:") for _ in range(10) for r in c.sample(200)) / 2000
+        assert 0.19 < share < 0.31, share
+
+        # выборка не отмечает обращения
+        c.set("cold", b"v")
+        time.sleep(0.3)
+        c.sample(8001)
+        assert next(r for r in c.sample(10_000) if r["key"] == "cold")["idle_ms"] >= 300
+        assert len(c.sample(100_000)) == 8002
+    print("SAMPLE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,138 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import os
+import shutil
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5023
+ADDR = f"127.0.0.1:{PORT}"
+DATA_DIR = "scan-iter-data"
+
+
+def server():
+    serve(PORT, wal_dir=DATA_DIR)
+
+
+def start_server():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def test_iterators(c):
+    for i in range(2500):
+        c.set(f"it:{i}", str(i).encode())
+    c.set("other", b"x")
+
+    # больше одной страницы (1000), и всё без единого keys()
+    assert sorted(c) == sorted([f"it:{i}" for i in range(2500)] + ["other"])
+    assert sorted(c.keys_iter("it:")) == sorted(f"it:{i}" for i in range(2500))
+    assert sorted(c.values_iter("it:")) == sorted(str(i).encode() for i in range(2500))
+    items = dict(c.items_iter("it:"))
+    assert len(items) == 2500 and items["it:42"] == b"42"
+    assert dict(c.items()) == {**items, "other": b"x"}
+    assert list(c.keys_iter("missing:")) == []
+    print("iterators OK")
+
+
+def test_size_filter(c):
+    for i in range(1500):
+        c.set(f"img:{i}", b"x" * (2_000 if i % 100 == 0 else 10))
+    c.set("big", b"x" * 5_000)
+
+    # больше одной страницы обхода, но назад приходят только 15 больших
+    big = list(c.scan(prefix="img:", min_size=1_000))
+    assert sorted(big) == sorted((f"img:{i}", 2_000) for i in range(0, 1500, 100)), big
+    assert sorted(c.scan(min_size=1_000)) == sorted(big + [("big", 5_000)])
+    small = dict(c.scan("img:", max_size=10))
+    assert len(small) == 1485 and set(small.values()) == {10}
+    assert list(c.scan("img:", min_size=11, max_size=1_999)) == []
+    # вид пространства имён срезает префикс, как keys_iter
+    assert sorted(c.namespace("img").scan(min_size=1_000))[0] == ("0", 2_000)
+    try:
+        c.scan(min_size=10, max_size=1)
+    except ValueError as e:
+        assert "scan()" in str(e), e
+    else:
+        raise AssertionError("min_size over max_size must fail")
+    for i in range(1500):
+        c.delete(f"img:{i}")
+    c.delete("big")
+    print("size filter OK")
+
+
+def test_ttl_scan(c):
+    for i in range(1500):
+        if i % 100 == 0:
+            c.setex(f"exp:{i}", 60, b"x")
+        else:
+            c.set(f"exp:{i}", b"x")
+    ttls = dict(c.scan_ttl("exp:"))
+    assert len(ttls) == 1500
+    assert {k for k, t in ttls.items() if t != -1} == {f"exp:{i}" for i in range(0, 1500, 100)}
+    assert all(t in (-1, 59, 60) for t in ttls.values()), set(ttls.values())
+    # фильтр на клиенте, страницы те же
+    assert sorted(c.keys_without_ttl("exp:")) == sorted(k for k, t in ttls.items() if t == -1)
+    assert sorted(c.namespace("exp").keys_without_ttl())[:2] == ["1", "10"]
+    for i in range(1500):
+        c.delete(f"exp:{i}")
+    print("ttl scan OK")
+
+
+def test_mutation_during_iteration(c):
+    seen = []
+    for i, key in enumerate(c.keys_iter("it:")):
+        seen.append(key)
+        if i % 100 == 0:
+            c.delete(f"it:{2499 - i}")
+            c.set(f"it:new:{i}", b"n")
+    # обход не падает; ключи, которых не трогали, почти все на месте
+    untouched = {f"it:{i}" for i in range(2500)} - {f"it:{2499 - i}" for i in range(0, 2500, 100)}
+    assert len(untouched - set(seen)) < 100, len(untouched - set(seen))
+    print(f"mutation during iteration OK: {len(seen)} keys seen")
+
+
+def test_survives_restart(p):
+    # между страницами соединение не держится: перезапуск сервера посреди обхода не мешает
+    it = TinyCache(ADDR).keys_iter("it:")
+    first = next(it)
+    stop_server(p)
+    p = start_server()
+    rest = list(it)
+    assert first.startswith("it:") and len(rest) > 1000, len(rest)
+    print("restart mid-iteration OK")
+    return p
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    if os.path.exists(DATA_DIR):
+        shutil.rmtree(DATA_DIR)
+    p = start_server()
+    c = TinyCache(ADDR)
+    test_iterators(c)
+    test_size_filter(c)
+    test_ttl_scan(c)
+    test_mutation_during_iteration(c)
+    p = test_survives_restart(p)
+    stop_server(p)
+    shutil.rmtree(DATA_DIR)
+    print("SCAN ITER TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,72 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import queue
+import socket
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5035
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server(events):
+    seen = {"connections": 0}
+
+    def on_connection(ev):
+        seen["connections"] += 1
+        if seen["connections"] == 1:
+            raise ValueError("boom from on_connection")
+        if seen["connections"] == 2:
+            time.sleep(1.5)
+        events.put(ev)
+
+    serve(PORT, persistence=False, on_ready=events.put, on_error=events.put,
+          on_connection=on_connection)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    try:
+        serve(PORT, persistence=False, on_ready=5)
+        raise AssertionError("non-callable on_ready must be rejected")
+    except TypeError as e:
+        assert "on_ready must be callable" in str(e), e
+
+    events = mp.Queue()
+    p = mp.Process(target=server, args=(events,), daemon=True)
+    p.start()
+    assert events.get(timeout=10) == {"event": "ready", "addr": ADDR}
+
+    # исключение в callback сервер не роняет
+    c = TinyCache(ADDR)
+    c.set("k", b"v")
+    # медленный callback не задерживает следующие соединения
+    t = time.monotonic()
+    for i in range(20):
+        assert TinyCache(ADDR).get("k") == b"v"
+    assert time.monotonic() - t < 1.0
+
+    raw = socket.create_connection(("127.0.0.1", PORT))
+    # кадр обрывается на заголовке
+    raw.sendall(b"\xff\xff\xff\xff")
+    raw.shutdown(socket.SHUT_WR)
+    raw.settimeout(5.0)
+    assert raw.recv(1) == b""
+
+    got = []
+    deadline = time.monotonic() + 10
+    while not any(ev["event"] == "error" for ev in got):
+        got.append(events.get(timeout=max(0.1, deadline - time.monotonic())))
+    conns = [ev for ev in got if ev["event"] == "connection"]
+    assert len(conns) == 21, got
+    err = got[-1]
+    assert err["error"] == "network error: connection closed 0 bytes into a 4294967295 byte frame", err
+    assert err["peer"] == "%s:%d" % raw.getsockname() and err["id"] > 0, err
+
+    p.terminate()
+    p.join()
+    print("SERVER HOOKS TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,119 @@
+#!/usr/bin/env python3
+import os
+import tempfile
+import time
+from tiny_mp_cache import spawn_server, TinyCache
+
+PORT = 5025
+
+
+def test_tcp():
+    # port=0: свободный порт выбирается сам
+    with spawn_server(persistence=False) as srv:
+        assert srv.addr.startswith("127.0.0.1:") and srv.is_alive()
+        c = TinyCache(srv.addr)
+        c.set("s:k", b"v")
+        assert c.get("s:k") == b"v"
+    assert not srv.is_alive()
+
+    srv = spawn_server(PORT, persistence=False)
+    assert srv.addr == f"127.0.0.1:{PORT}"
+    TinyCache(srv.addr).set("s:k", b"v")
+    srv.terminate()
+    srv.terminate()  # повторный вызов безвреден
+    try:
+        TinyCache(srv.addr).get("s:k")
+    except RuntimeError:
+        pass
+    else:
+        raise AssertionError("server must be gone after terminate()")
+    print("tcp OK")
+
+
+def test_wal_dir():
+    with tempfile.TemporaryDirectory() as d:
+        with spawn_server(wal_dir=d, fsync="always") as srv:
+            TinyCache(srv.addr).set("w:k", b"durable")
+        with spawn_server(wal_dir=d) as srv:
+            assert TinyCache(srv.addr).get("w:k") == b"durable"
+    print("wal_dir OK")
+
+
+def test_replay_progress():
+    with tempfile.TemporaryDirectory() as d:
+        with spawn_server(wal_dir=d) as srv:
+            c = TinyCache(srv.addr)
+            c.update({f"r:{i}": b"v" * 100 for i in range(2000)})
+            c.delete("r:0")
+        srv = spawn_server(wal_dir=d, wait=False)
+        deadline = time.time() + 10
+        while (state := srv.ready_state()) != "ready":
+            assert state in ("starting", "replaying"), state
+            assert time.time() < deadline, srv.replay_progress
+            time.sleep(0.02)
+        # итоговая строка replay пишется до bind
+        p = srv.replay_progress
+        assert p["done"] and p["records"] == 2001, p
+        assert p["ops"] == {"set": 2000, "del": 1}, p
+        assert p["keys"] == 1999 and p["percent"] == 100.0, p
+        assert TinyCache(srv.addr).get("r:1999") == b"v" * 100
+        srv.terminate()
+        assert srv.ready_state() == "exited"
+    print("replay progress OK")
+
+
+def test_unix():
+    if os.name != "posix":
+        # без Unix-сокетов — понятная ошибка, а не попытка TCP по пути
+        try:
+            spawn_server(unix_path="cache.sock")
+        except ValueError as e:
+            assert "TCP loopback" in str(e), e
+        else:
+            raise AssertionError("unix_path must be rejected")
+        try:
+            TinyCache("unix://cache.sock").get("k")
+        except RuntimeError as e:
+            assert "needs Unix domain sockets" in str(e), e
+        else:
+            raise AssertionError("unix:// must be rejected")
+        print("unix rejected OK")
+        return
+    with tempfile.TemporaryDirectory() as d:
+        path = os.path.join(d, "cache.sock")
+        with spawn_server(unix_path=path, persistence=False) as srv:
+            assert srv.addr == f"unix://{path}"
+            c = TinyCache(srv.addr)
+            c.set("u:k", b"v")
+            assert c.get("u:k") == b"v"
+            assert os.path.exists(path)
+        assert not os.path.exists(path)
+    print("unix OK")
+
+
+def test_crash_before_ready():
+    start = time.time()
+    try:
+        with tempfile.TemporaryDirectory() as d:
+            spawn_server(wal_dir=d, wal_key=b"short")
+    except RuntimeError as e:
+        # stderr ребёнка попадает в исключение
+        assert "before becoming ready" in str(e), e
+        assert "ValueError" in str(e), e
+    else:
+        raise AssertionError("spawn_server must fail when the child crashes")
+    assert time.time() - start < 10
+    print("crash before ready OK")
+
+
+def main():
+    test_tcp()
+    test_wal_dir()
+    test_replay_progress()
+    test_unix()
+    test_crash_before_ready()
+    print("SPAWN SERVER TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
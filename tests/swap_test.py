@@ -0,0 +1,77 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import shutil
+import threading
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5045
+ADDR = f"127.0.0.1:{PORT}"
+WAL_DIR = "/tmp/tiny_mp_cache_swap_test"
+
+
+def server():
+    serve(PORT, wal_dir=WAL_DIR)
+
+
+def start():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    return p, TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+
+    p, c = start()
+    c.set("config:active", b"blue")
+    c.set("config:staged", b"green")
+    assert c.swap("config:active", "config:staged") is True
+    assert c.get("config:active") == b"green"
+    assert c.get("config:staged") == b"blue"
+    # один ключ — переезд, ни одного — ничего
+    c.set("draft", b"d")
+    assert c.swap("draft", "published") is True
+    assert c.get("draft") is None
+    assert c.get("published") == b"d"
+    assert c.swap("nothing", "here") is False
+    assert c.wal_stats()["swaps"] == 2
+    print("swap OK")
+
+    # get_consistent видит обмен целиком
+    assert c.get_consistent(["config:active", "missing", "config:staged"]) == [b"green", None, b"blue"]
+    def swapper():
+        # чётное число обменов возвращает ключи на место
+        w = TinyCache(ADDR)
+        for _ in range(2000):
+            w.swap("config:active", "config:staged")
+
+    t = threading.Thread(target=swapper)
+    t.start()
+    reads = 0
+    while t.is_alive():
+        pair = c.get_consistent(["config:active", "config:staged"])
+        assert sorted(pair) == [b"blue", b"green"], pair
+        reads += 1
+    t.join()
+    assert reads > 0
+    print(f"consistent reads OK: {reads} reads")
+    p.terminate()
+    p.join()
+
+    # обмен доигрывается из WAL
+    p, c = start()
+    assert c.get("config:active") == b"green"
+    assert c.get("config:staged") == b"blue"
+    assert c.get("published") == b"d"
+    assert c.get("draft") is None
+    print("replay OK")
+    p.terminate()
+    p.join()
+
+    shutil.rmtree(WAL_DIR, ignore_errors=True)
+    print("SWAP TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,53 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5037
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+
+    c.set("page:1", b"a", tags=["pages", "user:7"])
+    c.set("page:2", bytearray(b"b"), tags=["pages"])
+    c.set("page:3", b"c", tags=["pages"])
+    c.set("page:3", b"c2")  # set без тегов снимает теги
+    c.set("plain", b"d", tags=[])
+    assert c.delete_by_tag("user:7") == 1
+    assert c.get("page:1") is None
+    assert c.delete_by_tag("pages") == 1
+    assert sorted(c.keys("*")) == ["page:3", "plain"]
+    assert c.delete_by_tag("pages") == 0
+
+    # теги живут в пространстве имён клиента
+    root = TinyCache(ADDR, local_cache_size=16)
+    a, b = root.namespace("a"), root.namespace("b")
+    a.set("k", b"1", tags=["t"])
+    b.set("k", b"2", tags=["t"])
+    assert a.get("k") == b"1"
+    assert a.delete_by_tag("t") == 1
+    assert a.get("k") is None
+    assert b.get("k") == b"2"
+
+    try:
+        c.set("k", b"v", tags="t")
+    except TypeError:
+        pass
+    else:
+        raise AssertionError("tags must be a list of str")
+
+    p.terminate()
+    p.join()
+    print("TAGS TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,49 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5038
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False, tombstone_ttl_secs=1)
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+
+    c.set("a", b"1")
+    c.set("b", b"2", tags=["t"])
+    assert c.delete("a")
+    assert c.delete_by_tag("t") == 1
+    assert c.get("a") is None and c.keys("*") == []
+    info = c.info()
+    assert info["tombstones"] == 2 and info["tombstone_bytes"] > 0, info
+    assert c.undelete("a") is True
+    assert c.get("a") == b"1"
+    assert c.undelete("a") is False
+    assert c.undelete("missing") is False
+
+    # ключи пространства имён возвращаются через своё имя
+    ns = c.namespace("ns")
+    ns.set("k", b"v")
+    ns.delete("k")
+    assert ns.undelete("k") and ns.get("k") == b"v"
+
+    # после срока хранения надгробие вычищается
+    time.sleep(2.5)
+    assert c.undelete("b") is False
+    assert c.info()["tombstones"] == 0
+
+    p.terminate()
+    p.join()
+    print("TOMBSTONE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,74 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import threading
+import time
+from tiny_mp_cache import serve, trace_id, TinyCache
+
+PORT = 5048
+ADDR = f"127.0.0.1:{PORT}"
+
+
+# медленная база: промах get() дольше порога журнала
+def load(key):
+    time.sleep(0.3)
+    return None
+
+
+def server():
+    serve(PORT, persistence=False, loader=load, slowlog_ms=100, admin_token="secret")
+
+
+def slow_entries(c):
+    return sorted(c.info()["slowlog"].values(), key=lambda e: e["at_ms"])
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    srv = mp.Process(target=server, daemon=True)
+    srv.start()
+    try:
+        c = TinyCache(ADDR, wait_ready=True, ready_timeout=10.0)
+        admin = TinyCache(ADDR, admin_token="secret")
+        c.set("k", b"v")
+        with trace_id("req-42") as t:
+            assert t.id == "req-42"
+            assert c.get("missing") is None
+            # быстрая команда в журнал не попадает
+            assert c.get("k") == b"v"
+        entries = slow_entries(c)
+        assert len(entries) == 1, entries
+        e = entries[0]
+        assert (e["command"], e["trace_id"]) == ("Get", "req-42"), e
+        assert e["micros"] >= 300_000, e
+        assert c.info()["slow_commands"] == 1
+
+        # id — у последней команды соединения
+        with trace_id("listed"):
+            c.set("k", b"w")
+        assert any(x["trace_id"] == "listed" for x in admin.client_list())
+
+        # вне блока и в другом потоке id нет; вложенный блок восстанавливает внешний
+        assert c.get("other") is None
+        with trace_id("outer"):
+            with trace_id("inner"):
+                pass
+            t = threading.Thread(target=lambda: c.get("thread"))
+            t.start()
+            t.join()
+            c.get("after-inner")
+        ids = [x["trace_id"] for x in slow_entries(c)[1:]]
+        assert ids == ["", "", "outer"], ids
+
+        # порог меняется на ходу
+        admin.config_set("slowlog_ms", 0)
+        assert admin.config_get("slowlog_ms") == {"slowlog_ms": 0}
+        c.get("untracked")
+        assert c.info()["slow_commands"] == 4
+    finally:
+        srv.terminate()
+        srv.join()
+    print("TRACE TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
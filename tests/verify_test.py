@@ -0,0 +1,97 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import os
+import shutil
+import threading
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5030
+ADDR = f"127.0.0.1:{PORT}"
+WAL_DIR = "verify-data"
+TOKEN = "s3cret"
+
+
+def cleanup():
+    if os.path.exists(WAL_DIR):
+        shutil.rmtree(WAL_DIR)
+
+
+def server():
+    serve(PORT, wal_dir=WAL_DIR, admin_token=TOKEN)
+
+
+def start_server():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def expect_error(call, message):
+    try:
+        call()
+        raise AssertionError(f"expected error containing {message!r}")
+    except RuntimeError as e:
+        assert message in str(e), e
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    cleanup()
+    p = start_server()
+    c = TinyCache(ADDR)
+    admin = TinyCache(ADDR, admin_token=TOKEN)
+
+    c.update({f"v:{i}": str(i).encode() for i in range(2000)})
+    c.setex("v:ttl", 600, b"t")
+    c.save()
+    c.set("v:tail", b"after save")
+    c.setbit("v:bits", 3, True)
+
+    report = admin.verify()
+    assert report["mismatches"] == 0 and report["examples"] == {}, report
+    assert report["keys_checked"] == 2003, report
+    assert admin.verify(values=False)["mismatches"] == 0
+
+    expect_error(lambda: c.verify(), "Verify requires an admin token")
+    expect_error(lambda: TinyCache(ADDR, admin_token="wrong").verify(), "invalid admin token")
+
+    # запись во время проверки не даёт ложных расхождений
+    stop = threading.Event()
+
+    def writer():
+        w = TinyCache(ADDR)
+        i = 0
+        while not stop.is_set():
+            w.set(f"v:{i % 3000}", b"w%d" % i)
+            if i % 7 == 0:
+                w.delete(f"v:{(i * 13) % 3000}")
+            i += 1
+
+    t = threading.Thread(target=writer)
+    t.start()
+    try:
+        for _ in range(5):
+            report = admin.verify()
+            assert report["mismatches"] == 0, report
+    finally:
+        stop.set()
+        t.join()
+    assert admin.verify()["mismatches"] == 0
+
+    p.terminate()
+    p.join()
+    cleanup()
+    print("VERIFY TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
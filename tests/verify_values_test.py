@@ -0,0 +1,36 @@
+#!/usr/bin/env python3
+import tempfile
+from tiny_mp_cache import spawn_server, TinyCache, ChecksumError, TinyCacheError
+
+
+def main():
+    assert issubclass(ChecksumError, TinyCacheError)
+    with tempfile.TemporaryDirectory() as d:
+        with spawn_server(wal_dir=d) as srv:
+            c = TinyCache(srv.addr)
+            c.set("k", b"before")
+            info = c.info()
+            assert info["verify_values"] == 0 and info["checksum_failures"] == 0, info
+
+        # суммы значений из WAL считаются при старте
+        with spawn_server(wal_dir=d, verify_values=True) as srv:
+            c = TinyCache(srv.addr)
+            assert c.get("k") == b"before"
+            c.set("big", bytes(range(256)) * 2048)
+            assert c.get("big") == bytes(range(256)) * 2048
+            c.set("n", b"1")
+            assert c.update("n", op="add_i64", arg=41) == 42
+            assert c.get("n") == b"42"
+            assert c.update("log", op="append_bytes", arg=b"ab") == b"ab"
+            assert c.get("log") == b"ab"
+            c.setbit("bits", 3, True)
+            assert c.get("bits") == b"\x10"
+            assert c.pop("k") == b"before"
+            info = c.info()
+            assert info["verify_values"] == 1, info
+            assert info["checksum_failures"] == 0, info
+    print("VERIFY VALUES TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,87 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import socket
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5026
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def slow_server():
+    # сервер поднимается не сразу, как после тяжёлой загрузки WAL
+    time.sleep(0.7)
+    serve(PORT, persistence=False)
+
+
+def unused_addr():
+    with socket.socket() as s:
+        s.bind(("127.0.0.1", 0))
+        return f"127.0.0.1:{s.getsockname()[1]}"
+
+
+def test_no_io_without_wait():
+    # конструктор не подключается: ни задержки, ни ошибки на мёртвом адресе
+    start = time.time()
+    for _ in range(100):
+        c = TinyCache(unused_addr())
+    assert time.time() - start < 0.5
+    try:
+        c.len()
+    except RuntimeError:
+        pass
+    else:
+        raise AssertionError("first call must fail on a dead address")
+    print("no I/O without wait_ready OK")
+
+
+def test_timeout():
+    addr = unused_addr()
+    start = time.time()
+    try:
+        TinyCache(addr, wait_ready=True, ready_timeout=0.5)
+    except ConnectionError as e:
+        assert addr in str(e) and "not ready" in str(e), e
+        # последняя причина отказа
+        assert "refused" in str(e).lower(), e
+    else:
+        raise AssertionError("wait_ready must fail on a dead address")
+    elapsed = time.time() - start
+    assert 0.4 < elapsed < 1.5, elapsed
+
+    try:
+        TinyCache(addr, wait_ready=True, ready_timeout=0)
+    except ValueError:
+        pass
+    else:
+        raise AssertionError("ready_timeout must be positive")
+    print("timeout OK")
+
+
+def test_waits_for_server():
+    p = mp.Process(target=slow_server, daemon=True)
+    p.start()
+    start = time.time()
+    c = TinyCache(ADDR, wait_ready=True)
+    assert time.time() - start > 0.5
+    c.set("r:k", b"v")
+    assert c.get("r:k") == b"v"
+    # уже поднятый сервер отвечает с первой попытки
+    start = time.time()
+    TinyCache(ADDR, wait_ready=True, ready_timeout=1.0)
+    assert time.time() - start < 0.2
+    p.terminate()
+    p.join()
+    print("waits for server OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    test_no_io_without_wait()
+    test_timeout()
+    test_waits_for_server()
+    print("WAIT READY TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
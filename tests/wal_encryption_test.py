@@ -0,0 +1,155 @@
+#!/usr/bin/env python3
+import glob
+import multiprocessing as mp
+import os
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5007
+ADDR = f"127.0.0.1:{PORT}"
+WAL_FILE = "tiny-mp-cache.wal"
+SNAPSHOT_FILE = "tiny-mp-cache.snapshot"
+KEY = bytes(range(32))
+OTHER_KEY = bytes(range(1, 33))
+SECRET = b"token-4f1c9e2a-do-not-leak"
+
+
+def wal_segments():
+    return sorted(glob.glob(WAL_FILE + ".[0-9]*"))
+
+
+def cleanup():
+    for f in [WAL_FILE, SNAPSHOT_FILE] + wal_segments():
+        if os.path.exists(f):
+            os.remove(f)
+
+
+def on_disk(files):
+    data = b""
+    for f in files:
+        with open(f, "rb") as fh:
+            data += fh.read()
+    return data
+
+
+def server(kwargs):
+    serve(PORT, **kwargs)
+
+
+def start_server(**kwargs):
+    p = mp.Process(target=server, args=(kwargs,), daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def expect_open_error(fragment, **kwargs):
+    # ошибка возникает при восстановлении, ещё до bind, так что serve() возвращается
+    try:
+        serve(PORT, **kwargs)
+    except (RuntimeError, ValueError) as e:
+        assert fragment in str(e), e
+    else:
+        raise AssertionError("serve() must fail: " + fragment)
+
+
+def test_encrypted_wal_and_snapshot():
+    cleanup()
+    p = start_server(wal_key=KEY)
+    c = TinyCache(ADDR)
+    c.set("enc:token", SECRET)
+    c.set("enc:tmp", SECRET)
+    c.delete("enc:tmp")
+    assert c.info()["wal_encrypted"] == 1
+    stop_server(p)
+    assert SECRET not in on_disk(wal_segments())
+
+    p = start_server(wal_key=KEY)
+    c = TinyCache(ADDR)
+    assert c.get("enc:token") == SECRET
+    assert c.get("enc:tmp") is None
+    c.save()
+    c.set("enc:after", SECRET)
+    stop_server(p)
+    assert SECRET not in on_disk([SNAPSHOT_FILE] + wal_segments())
+
+    p = start_server(wal_key=KEY)
+    c = TinyCache(ADDR)
+    assert c.get("enc:token") == SECRET
+    assert c.get("enc:after") == SECRET
+    stop_server(p)
+
+    expect_open_error("is encrypted, pass wal_key")
+    expect_open_error("different key", wal_key=OTHER_KEY)
+    expect_open_error("32 bytes", wal_key=b"short")
+    print("encrypted WAL OK")
+
+
+def test_key_from_env():
+    cleanup()
+    os.environ["TINY_MP_CACHE_WAL_KEY"] = KEY.hex()
+    try:
+        p = start_server()
+        c = TinyCache(ADDR)
+        c.set("env:a", SECRET)
+        assert c.info()["wal_encrypted"] == 1
+        stop_server(p)
+    finally:
+        del os.environ["TINY_MP_CACHE_WAL_KEY"]
+    assert SECRET not in on_disk(wal_segments())
+
+    p = start_server(wal_key=KEY)
+    assert TinyCache(ADDR).get("env:a") == SECRET
+    stop_server(p)
+    print("key from env OK")
+
+
+def test_enable_on_plaintext_wal():
+    cleanup()
+    p = start_server()
+    c = TinyCache(ADDR)
+    c.set("plain:a", b"v1")
+    stop_server(p)
+
+    # старые сегменты читаются как есть, новые записи идут в зашифрованный сегмент
+    p = start_server(wal_key=KEY)
+    c = TinyCache(ADDR)
+    assert c.get("plain:a") == b"v1"
+    c.set("plain:b", SECRET)
+    assert SECRET not in on_disk(wal_segments())
+    c.save()
+    stop_server(p)
+
+    # после компакции открытого текста не осталось: без ключа не открыть
+    expect_open_error("is encrypted")
+    p = start_server(wal_key=KEY)
+    c = TinyCache(ADDR)
+    assert c.get("plain:a") == b"v1"
+    assert c.get("plain:b") == SECRET
+    stop_server(p)
+    print("enable on plaintext WAL OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    test_encrypted_wal_and_snapshot()
+    test_key_from_env()
+    test_enable_on_plaintext_wal()
+    cleanup()
+    print("WAL ENCRYPTION TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
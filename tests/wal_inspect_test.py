@@ -0,0 +1,139 @@
+#!/usr/bin/env python3
+import glob
+import multiprocessing as mp
+import os
+import time
+from tiny_mp_cache import serve, inspect_wal, TinyCache
+
+PORT = 5008
+ADDR = f"127.0.0.1:{PORT}"
+WAL_FILE = "tiny-mp-cache.wal"
+SNAPSHOT_FILE = "tiny-mp-cache.snapshot"
+KEY = bytes(range(32))
+
+
+def wal_segments():
+    return sorted(glob.glob(WAL_FILE + ".[0-9]*"))
+
+
+def cleanup():
+    for f in [WAL_FILE, SNAPSHOT_FILE] + wal_segments():
+        if os.path.exists(f):
+            os.remove(f)
+
+
+def server(kwargs):
+    serve(PORT, **kwargs)
+
+
+def start_server(**kwargs):
+    p = mp.Process(target=server, args=(kwargs,), daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def fill(c):
+    c.set("i:a", b"1")
+    c.set("i:a", b"22")
+    c.set("i:b", b"333")
+    c.set("i:c", b"4444")
+    c.delete("i:b")
+    assert c.pop("i:c") == b"4444"
+
+
+def test_wal_stats():
+    cleanup()
+    p = start_server(wal_segment_size=64)
+    c = TinyCache(ADDR)
+    fill(c)
+    stats = c.wal_stats()
+    assert stats["records"] == 6, stats
+    assert (stats["sets"], stats["dels"], stats["pops"]) == (4, 1, 1), stats
+    assert (stats["first_seq"], stats["last_seq"]) == (1, 6), stats
+    # живая только последняя запись i:a
+    assert stats["live_records"] == 1, stats
+    assert abs(stats["live_ratio"] - 1 / 6) < 1e-9, stats
+    assert int(stats["segments"]) == len(wal_segments()) > 1, stats
+    assert int(stats["bytes"]) == sum(os.path.getsize(f) for f in wal_segments())
+
+    # после компакции журнал пуст
+    c.save()
+    stats = c.wal_stats()
+    assert stats["records"] == 0, stats
+    assert stats["live_ratio"] == 1.0, stats
+    stop_server(p)
+    print("wal_stats OK")
+
+
+def test_inspect_wal():
+    cleanup()
+    p = start_server(wal_segment_size=64)
+    fill(TinyCache(ADDR))
+    stop_server(p)
+
+    records = inspect_wal(WAL_FILE)
+    assert [(r["seq"], r["op"], r["key"], r["value_size"]) for r in records] == [
+        (1, "set", "i:a", 1),
+        (2, "set", "i:a", 2),
+        (3, "set", "i:b", 3),
+        (4, "set", "i:c", 4),
+        (5, "del", "i:b", None),
+        (6, "pop", "i:c", None),
+    ], records
+    assert all(r["file"].startswith(WAL_FILE + ".") for r in records)
+    assert records[0]["offset"] == 20
+
+    assert len(inspect_wal(WAL_FILE, limit=4)) == 4
+    # отдельный сегмент тоже можно открыть
+    assert inspect_wal(records[-1]["file"])[-1]["seq"] == 6
+
+    try:
+        inspect_wal("no-such.wal")
+        raise AssertionError("inspect_wal must fail on a missing WAL")
+    except RuntimeError as e:
+        assert "no WAL found" in str(e), e
+    print("inspect_wal OK")
+
+
+def test_inspect_encrypted():
+    cleanup()
+    p = start_server(wal_key=KEY)
+    fill(TinyCache(ADDR))
+    assert TinyCache(ADDR).wal_stats()["records"] == 6
+    stop_server(p)
+
+    assert [r["op"] for r in inspect_wal(WAL_FILE, wal_key=KEY)] == [
+        "set", "set", "set", "set", "del", "pop"
+    ]
+    try:
+        inspect_wal(WAL_FILE)
+        raise AssertionError("encrypted WAL must not be readable without the key")
+    except RuntimeError as e:
+        assert "is encrypted" in str(e), e
+    print("inspect encrypted OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    test_wal_stats()
+    test_inspect_wal()
+    test_inspect_encrypted()
+    cleanup()
+    print("WAL INSPECT TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
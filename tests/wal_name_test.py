@@ -0,0 +1,92 @@
+#!/usr/bin/env python3
+import os
+import tempfile
+from tiny_mp_cache import spawn_server, TinyCache, TinyCacheLocal
+
+
+def test_names_coexist():
+    with tempfile.TemporaryDirectory() as d:
+        with spawn_server(wal_dir=d, name="sessions") as s, spawn_server(wal_dir=d, name="jobs") as j:
+            TinyCache(s.addr).set("k", b"session")
+            TinyCache(j.addr).set("k", b"job")
+            assert s.wal_path == os.path.join(d, "sessions.wal")
+            assert s.snapshot_path == os.path.join(d, "sessions.snapshot")
+            assert s.lock_path == os.path.join(d, "sessions.lock")
+            info = TinyCache(j.addr).info()
+            assert info["wal_path"] == os.path.join(d, "jobs.wal"), info
+            assert info["snapshot_path"] == os.path.join(d, "jobs.snapshot"), info
+            assert info["lock_path"] == j.lock_path, info
+            TinyCache(s.addr).save()
+        assert os.path.exists(os.path.join(d, "sessions.snapshot"))
+        assert not os.path.exists(os.path.join(d, "jobs.snapshot"))
+        names = sorted(f for f in os.listdir(d) if ".wal." in f)
+        assert [n.split(".")[0] for n in names] == ["jobs", "sessions"], names
+
+        with spawn_server(wal_dir=d, name="sessions") as s, spawn_server(wal_dir=d, name="jobs") as j:
+            assert TinyCache(s.addr).get("k") == b"session"
+            assert TinyCache(j.addr).get("k") == b"job"
+    print("names coexist OK")
+
+
+def test_default_name():
+    with tempfile.TemporaryDirectory() as d:
+        with spawn_server(wal_dir=d) as srv:
+            TinyCache(srv.addr).set("k", b"v")
+            assert srv.wal_path == os.path.join(d, "tiny-mp-cache.wal")
+            assert srv.lock_path == os.path.join(d, "tiny-mp-cache.lock")
+        # прежнее имя по умолчанию: каталог старого сервера открывается как раньше
+        local = TinyCacheLocal(wal_dir=d)
+        assert local.get("k") == b"v"
+        assert local.info()["wal_path"] == os.path.join(d, "tiny-mp-cache.wal")
+    with spawn_server(persistence=False) as srv:
+        assert srv.wal_path is None and srv.lock_path is None
+    print("default name OK")
+
+
+def test_same_name_fails_fast():
+    with tempfile.TemporaryDirectory() as d:
+        with spawn_server(wal_dir=d, name="shared") as first:
+            TinyCache(first.addr).set("k", b"first")
+            try:
+                spawn_server(wal_dir=d, name="shared", timeout=30)
+            except RuntimeError as e:
+                assert "before becoming ready" in str(e), e
+                assert "shared.lock" in str(e), e
+            else:
+                raise AssertionError("second server on the same WAL must not start")
+            # и в этом же процессе
+            try:
+                TinyCacheLocal(wal_dir=d, name="shared")
+            except RuntimeError as e:
+                assert "already open" in str(e), e
+            else:
+                raise AssertionError("TinyCacheLocal on an open WAL must fail")
+            # первый сервер не пострадал
+            assert TinyCache(first.addr).get("k") == b"first"
+        # после остановки журнал снова свободен
+        local = TinyCacheLocal(wal_dir=d, name="shared")
+        assert local.get("k") == b"first"
+    print("same name fails fast OK")
+
+
+def test_bad_name():
+    for name in ["", "..", "a/b"]:
+        try:
+            TinyCacheLocal(wal_dir=tempfile.gettempdir(), name=name)
+        except ValueError as e:
+            assert "plain file name" in str(e), e
+        else:
+            raise AssertionError(f"name {name!r} must be rejected")
+    print("bad name OK")
+
+
+def main():
+    test_names_coexist()
+    test_default_name()
+    test_same_name_fails_fast()
+    test_bad_name()
+    print("WAL NAME TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,229 @@
+#!/usr/bin/env python3
+import glob
+import multiprocessing as mp
+import os
+import random
+import struct
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5006
+ADDR = f"127.0.0.1:{PORT}"
+WAL_FILE = "tiny-mp-cache.wal"
+SNAPSHOT_FILE = "tiny-mp-cache.snapshot"
+
+
+def wal_segments():
+    return sorted(glob.glob(WAL_FILE + ".[0-9]*"))
+
+
+def cleanup():
+    for f in [WAL_FILE, SNAPSHOT_FILE] + wal_segments():
+        if os.path.exists(f):
+            os.remove(f)
+
+
+def server(kwargs):
+    serve(PORT, **kwargs)
+
+
+def start_server(**kwargs):
+    p = mp.Process(target=server, args=(kwargs,), daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def stop_server(p):
+    p.terminate()
+    p.join()
+
+
+def set_record_body(key: str, value: bytes) -> bytes:
+    # WalRecord::Set в bincode: u32 вариант, u64 длина + байты ключа, u64 длина + значение
+    body = struct.pack("<I", 0)
+    body += struct.pack("<Q", len(key)) + key.encode()
+    body += struct.pack("<Q", len(value)) + value
+    return body
+
+
+def legacy_set_record(key: str, value: bytes) -> bytes:
+    body = set_record_body(key, value)
+    return struct.pack("<I", len(body)) + body
+
+
+def set_record(seq: int, key: str, value: bytes) -> bytes:
+    body = set_record_body(key, value)
+    return struct.pack("<IQ", len(body), seq) + body
+
+
+def test_rotation():
+    cleanup()
+    p = start_server(wal_segment_size=4096)
+    c = TinyCache(ADDR)
+    for i in range(100):
+        c.set(f"seg:{i}", b"x" * 200)
+    segments = wal_segments()
+    assert len(segments) > 3, segments
+    assert int(c.info()["wal_segments"]) == len(segments)
+    stop_server(p)
+
+    # перезапуск доигрывает все сегменты по порядку
+    p = start_server(wal_segment_size=4096)
+    c = TinyCache(ADDR)
+    assert len(c.keys("seg:*")) == 100
+    # компакция: старые сегменты удаляются, остаётся один пустой активный
+    c.save()
+    assert len(wal_segments()) == 1, wal_segments()
+    c.set("seg:after", b"y")
+    stop_server(p)
+
+    p = start_server(wal_segment_size=4096)
+    c = TinyCache(ADDR)
+    assert len(c.keys("seg:*")) == 101
+    assert c.get("seg:after") == b"y"
+    stop_server(p)
+    print("rotation OK")
+
+
+def test_torn_tail():
+    cleanup()
+    p = start_server()
+    c = TinyCache(ADDR)
+    c.set("torn:a", b"1")
+    c.set("torn:b", b"2")
+    stop_server(p)
+
+    # имитируем падение посреди записи: в конце активного сегмента половина записи
+    with open(wal_segments()[-1], "ab") as f:
+        f.write(legacy_set_record("torn:c", b"3")[:7])
+
+    p = start_server()
+    c = TinyCache(ADDR)
+    assert c.get("torn:a") == b"1"
+    assert c.get("torn:b") == b"2"
+    assert c.get("torn:c") is None
+    # после обрезки хвоста журнал снова пригоден для записи
+    c.set("torn:d", b"4")
+    stop_server(p)
+
+    p = start_server()
+    c = TinyCache(ADDR)
+    assert sorted(c.keys("torn:*")) == ["torn:a", "torn:b", "torn:d"]
+    stop_server(p)
+    print("torn tail OK")
+
+
+def test_legacy_file():
+    cleanup()
+    # журнал старых версий: без заголовка и без номера сегмента
+    with open(WAL_FILE, "wb") as f:
+        f.write(legacy_set_record("old:a", b"v1"))
+        f.write(legacy_set_record("old:b", b"v2"))
+
+    p = start_server()
+    c = TinyCache(ADDR)
+    assert c.get("old:a") == b"v1"
+    assert c.get("old:b") == b"v2"
+    c.set("old:c", b"v3")
+    c.save()
+    # после компакции старый файл не нужен
+    assert not os.path.exists(WAL_FILE)
+    stop_server(p)
+
+    p = start_server()
+    c = TinyCache(ADDR)
+    assert sorted(c.keys("old:*")) == ["old:a", "old:b", "old:c"]
+    stop_server(p)
+    print("legacy WAL OK")
+
+
+def test_sequence_numbers():
+    cleanup()
+    p = start_server()
+    c = TinyCache(ADDR)
+    c.set("seq:a", b"1")
+    c.set("seq:b", b"2")
+    c.delete("seq:a")
+    assert c.info()["last_seq"] == 3, c.info()
+    stop_server(p)
+
+    p = start_server()
+    c = TinyCache(ADDR)
+    assert c.info()["last_seq"] == 3, c.info()
+    stop_server(p)
+
+    # запись с правильным следующим seq доигрывается
+    with open(wal_segments()[-1], "ab") as f:
+        f.write(set_record(4, "seq:c", b"3"))
+    p = start_server()
+    c = TinyCache(ADDR)
+    assert c.get("seq:c") == b"3"
+    assert c.info()["last_seq"] == 4
+    stop_server(p)
+
+    # разрыв в seq — это порча журнала, стартовать поверх неё нельзя
+    with open(wal_segments()[-1], "ab") as f:
+        f.write(set_record(7, "seq:d", b"4"))
+    try:
+        serve(PORT)
+        raise AssertionError("serve() must refuse a WAL with a sequence gap")
+    except RuntimeError as e:
+        assert "has seq 7, expected 5" in str(e), e
+    print("sequence numbers OK")
+
+
+def test_replay_matches_sequential():
+    # replay применяет только последнюю запись по ключу; итог должен совпасть
+    # с последовательным применением всех операций
+    cleanup()
+    rnd = random.Random(7)
+    model = {}
+    p = start_server(wal_segment_size=2048)
+    c = TinyCache(ADDR)
+    for i in range(2000):
+        k = f"eq:{rnd.randrange(40)}"
+        op = rnd.random()
+        if op < 0.6:
+            v = str(i).encode()
+            c.set(k, v)
+            model[k] = v
+        elif op < 0.8:
+            c.delete(k)
+            model.pop(k, None)
+        else:
+            assert c.pop(k, None) == model.pop(k, None)
+        if i == 1000:
+            # часть ключей уйдёт в снапшот, а удалять их будет уже хвост WAL
+            c.save()
+    stop_server(p)
+
+    p = start_server(wal_segment_size=2048)
+    c = TinyCache(ADDR)
+    assert sorted(c.keys("eq:*")) == sorted(model), (sorted(c.keys("eq:*")), sorted(model))
+    for k, v in model.items():
+        assert c.get(k) == v, k
+    stop_server(p)
+    print("replay equivalence OK")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    test_rotation()
+    test_torn_tail()
+    test_legacy_file()
+    test_sequence_numbers()
+    test_replay_matches_sequential()
+    cleanup()
+    print("WAL SEGMENTS TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
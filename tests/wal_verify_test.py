@@ -0,0 +1,108 @@
+#!/usr/bin/env python3
+import glob
+import hashlib
+import os
+import tempfile
+from tiny_mp_cache import spawn_server, inspect_wal, verify_wal, TinyCache, WalCorruptError
+
+KEY = bytes(range(32))
+
+
+def write_records(wal_dir, **kwargs):
+    with spawn_server(wal_dir=wal_dir, **kwargs) as srv:
+        c = TinyCache(srv.addr)
+        for i in range(10):
+            c.set(f"r:{i}", f"value-{i}".encode())
+        c.delete("r:0")
+    (segment,) = glob.glob(os.path.join(wal_dir, "*.wal.[0-9]*"))
+    return segment[: segment.rindex(".")], segment
+
+
+def digest(path):
+    with open(path, "rb") as f:
+        return hashlib.sha256(f.read()).hexdigest()
+
+
+def test_clean(wal_dir):
+    wal, _ = write_records(wal_dir)
+    calls = []
+    report = verify_wal(wal, progress=calls.append)
+    assert report["status"] == "clean", report
+    assert report["records"] == 11 and report["segments"] == 1, report
+    assert report["ops"] == {"set": 10, "del": 1}, report
+    assert (report["first_seq"], report["last_seq"]) == (1, 11), report
+    assert report["torn"] == [] and report["corrupt"] == [] and report["seq_breaks"] == []
+    assert calls and calls[-1]["done"], calls
+    assert calls[-1]["bytes_read"] == calls[-1]["bytes_total"] == report["bytes"], calls
+    print("verify clean OK")
+
+
+def test_torn_and_corrupt(wal_dir):
+    wal, segment = write_records(wal_dir)
+    size = os.path.getsize(segment)
+    with open(segment, "ab") as f:
+        f.write(b"\x30\x00\x00\x00garbage")
+    report = verify_wal(wal)
+    assert report["status"] == "recoverable", report
+    assert report["torn"] == [(segment, size)], report
+    assert report["records"] == 11, report
+
+    with open(segment, "r+b") as f:
+        f.truncate(size)
+    bad = inspect_wal(segment)[3]
+    with open(segment, "r+b") as f:
+        f.seek(bad["offset"])
+        f.write(b"\xff" * 10)
+    before = digest(segment)
+    report = verify_wal(segment)
+    assert digest(segment) == before, "verify_wal must not modify the WAL"
+    assert report["status"] == "corrupt", report
+    (region,) = report["corrupt"]
+    assert region["file"] == segment and region["start"] == bad["offset"], region
+    assert region["records_lost"] == 1, region
+    assert report["records"] == 10 and report["last_seq"] == 11, report
+
+    try:
+        verify_wal(wal, raise_on_corrupt=True)
+        raise AssertionError("raise_on_corrupt must raise on a corrupt WAL")
+    except WalCorruptError as e:
+        assert e.report["corrupt"] == report["corrupt"], e.report
+    print("verify torn and corrupt OK")
+
+
+def test_encrypted(wal_dir):
+    wal, _ = write_records(wal_dir, wal_key=KEY)
+    report = verify_wal(wal, wal_key=KEY)
+    assert report["status"] == "clean" and report["records"] == 11, report
+    try:
+        verify_wal(wal)
+        raise AssertionError("encrypted WAL without a key must not verify")
+    except RuntimeError:
+        pass
+    print("verify encrypted OK")
+
+
+def test_progress_error(wal_dir):
+    wal, _ = write_records(wal_dir)
+
+    def fail(p):
+        raise ValueError("stop")
+
+    try:
+        verify_wal(wal, progress=fail)
+        raise AssertionError("progress error must propagate")
+    except ValueError as e:
+        assert str(e) == "stop", e
+    print("verify progress error OK")
+
+
+def main():
+    os.environ.pop("TINY_MP_CACHE_WAL_KEY", None)
+    for test in [test_clean, test_torn_and_corrupt, test_encrypted, test_progress_error]:
+        with tempfile.TemporaryDirectory() as wal_dir:
+            test(wal_dir)
+    print("WAL VERIFY TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
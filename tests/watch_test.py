@@ -0,0 +1,146 @@
+#!/usr/bin/env python3
+import multiprocessing as mp
+import threading
+import time
+from tiny_mp_cache import serve, TinyCache
+
+PORT = 5019
+ADDR = f"127.0.0.1:{PORT}"
+
+
+def server():
+    serve(PORT, persistence=False)
+
+
+def start_server():
+    p = mp.Process(target=server, daemon=True)
+    p.start()
+    deadline = time.time() + 10
+    while True:
+        try:
+            TinyCache(ADDR).len()
+            return p
+        except RuntimeError:
+            if time.time() > deadline:
+                raise
+            time.sleep(0.05)
+
+
+def wait_for(cond, what, timeout=10):
+    deadline = time.time() + timeout
+    while not cond():
+        if time.time() > deadline:
+            raise AssertionError(f"timed out waiting for {what}")
+        time.sleep(0.05)
+
+
+def test_events(c):
+    events, full = [], []
+    w = c.watch("job:42:", events.append)
+    wv = c.watch("job:", full.append, with_values=True)
+    assert c.info()["watchers"] == 2
+
+    c.set("job:42:state", b"running")
+    c.set("job:7:state", b"queued")
+    c.set("other", b"x")
+    assert c.pop("job:42:state") == b"running"
+    c.set("job:42:result", b"ok!")
+    assert c.delete("job:42:result") == 1
+    # удаление отсутствующего ключа ничего не меняет и событий не шлёт
+    assert c.delete("job:42:missing") == 0
+
+    wait_for(lambda: len(events) == 4 and len(full) == 5, "events")
+    assert events == [
+        {"key": "job:42:state", "op": "set", "value_size": 7, "value": None},
+        {"key": "job:42:state", "op": "del", "value_size": 0, "value": None},
+        {"key": "job:42:result", "op": "set", "value_size": 3, "value": None},
+        {"key": "job:42:result", "op": "del", "value_size": 0, "value": None},
+    ], events
+    assert [(e["key"], e["op"], e["value"]) for e in full] == [
+        ("job:42:state", "set", b"running"),
+        ("job:7:state", "set", b"queued"),
+        ("job:42:state", "del", None),
+        ("job:42:result", "set", b"ok!"),
+        ("job:42:result", "del", None),
+    ], full
+
+    assert w.is_alive() and w.error is None
+    w.stop()
+    wv.stop()
+    assert not w.is_alive()
+    wait_for(lambda: c.info()["watchers"] == 0, "watchers to unregister")
+    c.set("job:42:state", b"again")
+    time.sleep(0.2)
+    assert len(events) == 4
+    print("events OK")
+
+
+def test_expired(c):
+    events = []
+    w = c.watch("lease:", events.append)
+    c.psetex("lease:1", 100, b"worker-a")
+    c.set("lease:2", b"worker-b")
+    assert c.expire("lease:2", 0.1)
+    # события expired шлёт фоновый поток истечения, не чтение ключа
+    wait_for(lambda: len(events) == 4, "expired events")
+    assert [(e["key"], e["op"]) for e in events] == [
+        ("lease:1", "set"),
+        ("lease:2", "set"),
+        ("lease:1", "expired"),
+        ("lease:2", "expired"),
+    ], events
+    assert c.keys("lease:*") == []
+    w.stop()
+    print("expired OK")
+
+
+def writer(n, count, size):
+    c = TinyCache(ADDR)
+    value = bytes([n]) * size
+    for i in range(count):
+        c.set(f"bulk:{n}:{i}", value)
+
+
+def test_slow_watcher(c):
+    release = threading.Event()
+    seen = []
+
+    def stuck(event):
+        release.wait()
+        seen.append(event)
+
+    w = c.watch("bulk:", stuck, with_values=True)
+    start = time.time()
+    writers = [mp.Process(target=writer, args=(n, 500, 32 * 1024)) for n in range(6)]
+    for p in writers:
+        p.start()
+    for p in writers:
+        p.join()
+        assert p.exitcode == 0
+    # запись не ждала наблюдателя, а его самого отключили
+    wait_for(lambda: c.info()["watchers_evicted"] == 1, "slow watcher eviction")
+    assert c.info()["watchers"] == 0
+    elapsed = time.time() - start
+
+    release.set()
+    wait_for(lambda: not w.is_alive(), "watch thread to stop", timeout=60)
+    assert "fell behind" in w.error, w.error
+    assert 0 < len(seen) < 3000
+    w.stop()
+    print(f"slow watcher OK: {len(seen)} of 3000 events delivered, writes took {elapsed:.2f}s")
+
+
+def main():
+    mp.set_start_method("fork", force=True)
+    p = start_server()
+    c = TinyCache(ADDR)
+    test_events(c)
+    test_expired(c)
+    test_slow_watcher(c)
+    p.terminate()
+    p.join()
+    print("WATCH TEST PASSED")
+
+
+if __name__ == "__main__":
+    main()
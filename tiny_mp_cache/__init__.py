@@ -1,3 +1,11 @@
-from .tiny_mp_cache import TinyCache, serve, serve_unix
+from .tiny_mp_cache import TinyCache, TinyCacheCluster, TinyCacheLocal, Subscription, Watch, ScanIter, ServerHandle, KeyFilter, ValueMeta, TinyCacheLock, IdAllocator, serve, spawn_server, trace_id, inspect_wal, repair_wal, verify_wal, hash_password, export_to_file, benchmark, TinyCacheError, BindError, InvalidKeyError, ChecksumError, ResponseTooLargeError, LockNotOwnedError, PartialWriteError, WalCorruptError
 
-__all__ = ["TinyCache", "serve", "serve_unix"]
\ No newline at end of file
+__all__ = ["TinyCache", "TinyCacheCluster", "TinyCacheLocal", "Subscription", "Watch", "ScanIter", "ServerHandle", "KeyFilter", "ValueMeta", "TinyCacheLock", "IdAllocator", "serve", "spawn_server", "trace_id", "inspect_wal", "repair_wal", "verify_wal", "hash_password", "export_to_file", "benchmark", "TinyCacheError", "BindError", "InvalidKeyError", "ChecksumError", "ResponseTooLargeError", "LockNotOwnedError", "PartialWriteError", "WalCorruptError"]
+
+# Unix-сокетов на Windows нет, см. README, раздел Windows
+try:
+    from .tiny_mp_cache import serve_unix
+except ImportError:
+    pass
+else:
+    __all__.append("serve_unix")